@@ -20,6 +20,7 @@ docserver-builder -i ./embassy-boot -o webroot/crates/embassy-boot/git.zup
 docserver-builder -i ./embassy-boot-nrf -o webroot/crates/embassy-boot-nrf/git.zup
 docserver-builder -i ./embassy-boot-rp -o webroot/crates/embassy-boot-rp/git.zup
 docserver-builder -i ./embassy-boot-stm32 -o webroot/crates/embassy-boot-stm32/git.zup
+docserver-builder -i ./embassy-cmux -o webroot/crates/embassy-cmux/git.zup
 docserver-builder -i ./embassy-embedded-hal -o webroot/crates/embassy-embedded-hal/git.zup
 docserver-builder -i ./embassy-executor -o webroot/crates/embassy-executor/git.zup
 docserver-builder -i ./embassy-futures -o webroot/crates/embassy-futures/git.zup
@@ -46,6 +47,8 @@ docserver-builder -i ./embassy-net-driver -o webroot/crates/embassy-net-driver/g
 docserver-builder -i ./embassy-net-driver-channel -o webroot/crates/embassy-net-driver-channel/git.zup
 docserver-builder -i ./embassy-net-wiznet -o webroot/crates/embassy-net-wiznet/git.zup
 docserver-builder -i ./embassy-net-ppp -o webroot/crates/embassy-net-ppp/git.zup
+docserver-builder -i ./embassy-net-ieee802154 -o webroot/crates/embassy-net-ieee802154/git.zup
+docserver-builder -i ./embassy-net-sim -o webroot/crates/embassy-net-sim/git.zup
 docserver-builder -i ./embassy-net-tuntap -o webroot/crates/embassy-net-tuntap/git.zup
 docserver-builder -i ./embassy-net-enc28j60 -o webroot/crates/embassy-net-enc28j60/git.zup
 docserver-builder -i ./embassy-net-esp-hosted -o webroot/crates/embassy-net-esp-hosted/git.zup
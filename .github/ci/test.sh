@@ -33,4 +33,11 @@ cargo test --manifest-path ./embassy-stm32/Cargo.toml --no-default-features --fe
 cargo test --manifest-path ./embassy-stm32/Cargo.toml --no-default-features --features stm32f732ze,exti,time-driver-any,exti
 cargo test --manifest-path ./embassy-stm32/Cargo.toml --no-default-features --features stm32f769ni,exti,time-driver-any,exti
 
+cargo test --manifest-path ./embassy-net/Cargo.toml --features tcp,udp,raw,dns,icmp,dhcpv4,dhcpv6,dhcpv4-server,proto-ipv4,proto-ipv6,slaac,medium-ethernet,medium-ip,medium-ieee802154,multicast,forwarding,tls,mdns-responder,sntp,http,mqtt,pcap,statistics,autoip,websocket,coap,dns-cache,dns-server
 cargo test --manifest-path ./embassy-net-adin1110/Cargo.toml
+cargo test --manifest-path ./embassy-net-wiznet/Cargo.toml
+cargo test --manifest-path ./embassy-net-sim/Cargo.toml
+cargo test --manifest-path ./embassy-net-tuntap/Cargo.toml
+cargo test --manifest-path ./embassy-net-ppp/Cargo.toml
+cargo test --manifest-path ./embassy-cmux/Cargo.toml
+cargo test --manifest-path ./embassy-net-ieee802154/Cargo.toml
@@ -0,0 +1,300 @@
+//! Frames of the basic option (3GPP TS 27.010 section 5.2).
+
+use crate::MAX_FRAME_SIZE;
+
+pub(crate) const FLAG: u8 = 0xF9;
+const EA: u8 = 0x01;
+const CR: u8 = 0x02;
+/// Poll/final bit of the control field.
+pub(crate) const PF: u8 = 0x10;
+
+pub(crate) const SABM: u8 = 0x2F;
+pub(crate) const UA: u8 = 0x63;
+pub(crate) const DM: u8 = 0x0F;
+pub(crate) const DISC: u8 = 0x43;
+pub(crate) const UIH: u8 = 0xEF;
+pub(crate) const UI: u8 = 0x03;
+
+const FCS_INIT: u8 = 0xFF;
+const FCS_GOOD: u8 = 0xCF;
+
+/// Length of the longest frame, without flags: address, control, two length bytes, information
+/// and FCS.
+pub(crate) const MAX_FRAME_LEN: usize = 4 + MAX_FRAME_SIZE + 1;
+
+fn fcs8(mut fcs: u8, byte: u8) -> u8 {
+    fcs ^= byte;
+    for _ in 0..8 {
+        fcs = match fcs & 1 {
+            0 => fcs >> 1,
+            _ => (fcs >> 1) ^ 0xE0,
+        };
+    }
+    fcs
+}
+
+/// A received frame.
+pub(crate) struct Frame<'a> {
+    pub dlci: u8,
+    /// Control field, without the poll/final bit.
+    pub control: u8,
+    pub info: &'a [u8],
+}
+
+/// Extracts frames from the received bytes.
+pub(crate) struct FrameReader {
+    buf: [u8; MAX_FRAME_LEN],
+    len: usize,
+    /// Length of the frame without flags, once the length field is received.
+    frame_len: Option<usize>,
+    /// Whether an opening flag was seen. The information field isn't escaped, so after an error
+    /// anything up to the next flag is dropped.
+    synced: bool,
+    frame_size: usize,
+}
+
+impl FrameReader {
+    pub fn new(frame_size: usize) -> Self {
+        Self {
+            buf: [0; MAX_FRAME_LEN],
+            len: 0,
+            frame_len: None,
+            synced: false,
+            frame_size,
+        }
+    }
+
+    /// Consume received bytes, up to the end of a frame.
+    ///
+    /// Returns the number of bytes consumed, and the frame if one was completed.
+    pub fn consume(&mut self, data: &[u8]) -> (usize, Option<Frame<'_>>) {
+        for (i, &byte) in data.iter().enumerate() {
+            if !self.synced {
+                self.synced = byte == FLAG;
+                continue;
+            }
+            if let Some(frame_len) = self.frame_len.filter(|&n| n == self.len) {
+                self.len = 0;
+                self.frame_len = None;
+                if byte != FLAG {
+                    warn!("CMUX: missing closing flag, frame dropped");
+                    self.synced = false;
+                    continue;
+                }
+                if self.check(frame_len) {
+                    return (i + 1, Some(self.frame(frame_len)));
+                }
+                continue;
+            }
+            // Opening flags, or the closing flag of the previous frame.
+            if self.len == 0 && byte == FLAG {
+                continue;
+            }
+
+            self.buf[self.len] = byte;
+            self.len += 1;
+            if self.frame_len.is_none() {
+                let header = match self.buf[..self.len] {
+                    [_, _, l] if l & EA != 0 => Some((3, usize::from(l >> 1))),
+                    [_, _, l0, l1] => Some((4, usize::from(l0 >> 1) | (usize::from(l1) << 7))),
+                    _ => None,
+                };
+                match header {
+                    Some((_, info_len)) if info_len > self.frame_size => {
+                        warn!("CMUX: frame too long, dropped");
+                        self.len = 0;
+                        self.synced = false;
+                    }
+                    Some((header_len, info_len)) => self.frame_len = Some(header_len + info_len + 1),
+                    None => {}
+                }
+            }
+        }
+        (data.len(), None)
+    }
+
+    fn header_len(&self) -> usize {
+        match self.buf[2] & EA {
+            0 => 4,
+            _ => 3,
+        }
+    }
+
+    fn check(&self, frame_len: usize) -> bool {
+        // The FCS covers the information field of UI frames only.
+        let covered = match self.buf[1] & !PF {
+            UI => frame_len - 1,
+            _ => self.header_len(),
+        };
+        let fcs = self.buf[..covered].iter().fold(FCS_INIT, |fcs, &b| fcs8(fcs, b));
+        if fcs8(fcs, self.buf[frame_len - 1]) != FCS_GOOD {
+            warn!("CMUX: bad FCS, frame dropped");
+            return false;
+        }
+        true
+    }
+
+    fn frame(&self, frame_len: usize) -> Frame<'_> {
+        Frame {
+            dlci: self.buf[0] >> 2,
+            control: self.buf[1] & !PF,
+            info: &self.buf[self.header_len()..frame_len - 1],
+        }
+    }
+}
+
+/// Write a frame with its flags to `buf`, returning its length.
+///
+/// `command` sets the C/R bit, as seen from the initiator of the multiplexer.
+pub(crate) fn write_frame(buf: &mut [u8], dlci: u8, command: bool, control: u8, info: &[u8]) -> usize {
+    let cr = match command {
+        true => CR,
+        false => 0,
+    };
+    buf[0] = FLAG;
+    buf[1] = (dlci << 2) | cr | EA;
+    buf[2] = control;
+    let header_len = match info.len() {
+        0..=127 => {
+            buf[3] = ((info.len() as u8) << 1) | EA;
+            3
+        }
+        _ => {
+            buf[3] = (info.len() as u8) << 1;
+            buf[4] = (info.len() >> 7) as u8;
+            4
+        }
+    };
+    let end = 1 + header_len + info.len();
+    buf[1 + header_len..end].copy_from_slice(info);
+    let covered = match control & !PF {
+        UI => &buf[1..end],
+        _ => &buf[1..1 + header_len],
+    };
+    buf[end] = 0xFF - covered.iter().fold(FCS_INIT, |fcs, &b| fcs8(fcs, b));
+    buf[end + 1] = FLAG;
+    end + 2
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec;
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// SABM, UA and DISC on DLCI 0, as sent when opening and closing the multiplexer.
+    const SABM_0: [u8; 6] = [0xF9, 0x03, 0x3F, 0x01, 0x1C, 0xF9];
+    const UA_0: [u8; 6] = [0xF9, 0x03, 0x73, 0x01, 0xD7, 0xF9];
+    const DISC_0: [u8; 6] = [0xF9, 0x03, 0x53, 0x01, 0xFD, 0xF9];
+
+    /// Read all the frames of `data`, as DLCI, control and information.
+    fn read(reader: &mut FrameReader, mut data: &[u8]) -> Vec<(u8, u8, Vec<u8>)> {
+        let mut frames = Vec::new();
+        while !data.is_empty() {
+            let (n, frame) = reader.consume(data);
+            if let Some(frame) = frame {
+                frames.push((frame.dlci, frame.control, frame.info.to_vec()));
+            }
+            data = &data[n..];
+        }
+        frames
+    }
+
+    fn write(dlci: u8, command: bool, control: u8, info: &[u8]) -> Vec<u8> {
+        let mut buf = [0; MAX_FRAME_LEN + 2];
+        let len = write_frame(&mut buf, dlci, command, control, info);
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn known_frames() {
+        assert_eq!(write(0, true, SABM | PF, &[]), SABM_0);
+        assert_eq!(write(0, true, DISC | PF, &[]), DISC_0);
+
+        let mut reader = FrameReader::new(MAX_FRAME_SIZE);
+        assert_eq!(read(&mut reader, &UA_0), [(0, UA, Vec::new())]);
+        assert_eq!(read(&mut reader, &SABM_0), [(0, SABM, Vec::new())]);
+    }
+
+    #[test]
+    fn fcs() {
+        // The FCS is the ones' complement of the CRC, which leaves FCS_GOOD when checked.
+        let crc = [0x03, 0x3F, 0x01].iter().fold(FCS_INIT, |fcs, &b| fcs8(fcs, b));
+        assert_eq!(0xFF - crc, 0x1C);
+        assert_eq!(fcs8(crc, 0x1C), FCS_GOOD);
+
+        let mut reader = FrameReader::new(MAX_FRAME_SIZE);
+        let mut corrupted = UA_0;
+        corrupted[4] ^= 0x01;
+        assert_eq!(read(&mut reader, &corrupted), []);
+        let mut corrupted = UA_0;
+        corrupted[2] ^= 0x20;
+        assert_eq!(read(&mut reader, &corrupted), []);
+        assert_eq!(read(&mut reader, &UA_0).len(), 1);
+
+        // The FCS of UIH frames only covers the header, the one of UI frames also covers the
+        // information.
+        for (control, accepted) in [(UIH, true), (UI, false)] {
+            let mut frame = write(1, true, control, b"AT\r");
+            frame[5] = b'X';
+            assert_eq!(read(&mut reader, &frame).len(), usize::from(accepted));
+        }
+    }
+
+    #[test]
+    fn length() {
+        let info: Vec<u8> = (0..=255).cycle().take(300).collect();
+        let mut reader = FrameReader::new(MAX_FRAME_SIZE);
+
+        // One byte with the EA bit set up to 127 bytes, two bytes for longer frames.
+        let frame = write(2, false, UIH, &info[..127]);
+        assert_eq!(frame[..4], [FLAG, 0x09, UIH, 0xFF]);
+        assert_eq!(frame.len(), 1 + 3 + 127 + 2);
+        let frame = write(2, false, UIH, &info[..128]);
+        assert_eq!(frame[..5], [FLAG, 0x09, UIH, 0x00, 0x01]);
+        let frame = write(2, false, UIH, &info);
+        assert_eq!(frame[..5], [FLAG, 0x09, UIH, 0x58, 0x02]);
+        assert_eq!(frame.len(), 1 + 4 + 300 + 2);
+        assert_eq!(read(&mut reader, &frame), [(2, UIH, info.clone())]);
+
+        // Frames longer than the configured size are dropped.
+        let mut reader = FrameReader::new(200);
+        assert_eq!(read(&mut reader, &frame), []);
+        assert_eq!(read(&mut reader, &UA_0).len(), 1);
+    }
+
+    #[test]
+    fn resync() {
+        let mut reader = FrameReader::new(MAX_FRAME_SIZE);
+        let uih = write(1, true, UIH, b"OK");
+
+        // Bytes before the first flag, and repeated flags, are skipped.
+        let mut data = Vec::from(*b"garbage");
+        data.extend_from_slice(&[FLAG, FLAG]);
+        data.extend_from_slice(&uih[1..]);
+        data.extend_from_slice(&uih);
+        assert_eq!(read(&mut reader, &data), vec![(1, UIH, b"OK".to_vec()); 2]);
+
+        // Without a closing flag, everything up to the next flag is dropped.
+        let mut data = uih.clone();
+        data[uih.len() - 1] = b'x';
+        data.extend_from_slice(b"yz");
+        data.extend_from_slice(&SABM_0);
+        assert_eq!(read(&mut reader, &data), [(0, SABM, Vec::new())]);
+
+        // After a bad FCS, the next frame is read.
+        let mut data = uih.clone();
+        data[uih.len() - 2] ^= 0xFF;
+        data.extend_from_slice(&uih);
+        assert_eq!(read(&mut reader, &data), [(1, UIH, b"OK".to_vec())]);
+
+        // Frames are returned one at a time, with the bytes consumed up to their closing flag.
+        let mut data = uih.clone();
+        data.extend_from_slice(&UA_0);
+        let (n, frame) = reader.consume(&data);
+        assert_eq!((n, frame.map(|f| f.dlci)), (uih.len(), Some(1)));
+    }
+}
@@ -0,0 +1,412 @@
+#![no_std]
+#![warn(missing_docs)]
+#![doc = include_str!("../README.md")]
+
+// must be first
+mod fmt;
+
+mod frame;
+
+use core::convert::Infallible;
+use core::future::pending;
+
+use embassy_futures::select::{select, select_array, Either};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::pipe::{Pipe, Reader, Writer};
+use embassy_time::{with_timeout, Duration};
+use embedded_io_async::{BufRead, ErrorType, Read, Write};
+
+use crate::frame::{write_frame, Frame, FrameReader, DISC, DM, MAX_FRAME_LEN, PF, SABM, UA, UI, UIH};
+
+/// Largest supported frame size, see [`Config::frame_size`].
+pub const MAX_FRAME_SIZE: usize = 1509;
+
+// Multiplexer control messages, sent on DLCI 0 (3GPP TS 27.010 section 5.4.6.3).
+const MSG_EA: u8 = 0x01;
+const MSG_CR: u8 = 0x02;
+const MSG_TYPE: u8 = 0xFC;
+const MSG_PSC: u8 = 0x40;
+const MSG_CLD: u8 = 0xC0;
+const MSG_TEST: u8 = 0x20;
+const MSG_FCON: u8 = 0xA0;
+const MSG_FCOFF: u8 = 0x60;
+const MSG_MSC: u8 = 0xE0;
+const MSG_NSC: u8 = 0x10;
+
+// V.24 signals of the modem status command.
+const V24_FC: u8 = 0x02;
+const V24_RTC: u8 = 0x04;
+const V24_RTR: u8 = 0x08;
+const V24_DV: u8 = 0x80;
+
+/// Error returned by [`Runner::run`].
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RunError<E> {
+    /// Reading from the serial port failed.
+    Read(E),
+    /// Writing to the serial port failed.
+    Write(E),
+    /// Reading from the serial port got EOF.
+    Eof,
+    /// The modem didn't answer when opening a channel.
+    Timeout,
+    /// The modem refused to open a channel.
+    Refused,
+    /// The modem closed the multiplexer.
+    Closed,
+}
+
+/// CMUX configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Config {
+    /// Maximum length of the information field of frames, N1 in the specification.
+    ///
+    /// It must match the value given to the modem with `AT+CMUX`, which defaults to 31, and be at most
+    /// [`MAX_FRAME_SIZE`].
+    pub frame_size: usize,
+    /// Time to wait for the modem to acknowledge opening a channel, T1 in the specification.
+    pub ack_timeout: Duration,
+    /// Retransmissions when the modem doesn't acknowledge opening a channel, N2 in the specification.
+    pub retries: u8,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            frame_size: 31,
+            ack_timeout: Duration::from_secs(1),
+            retries: 3,
+        }
+    }
+}
+
+/// Internal state for the CMUX multiplexer, with `N` channels of `BUF` bytes of buffer in each direction.
+pub struct State<const N: usize, const BUF: usize> {
+    rx: [Pipe<NoopRawMutex, BUF>; N],
+    tx: [Pipe<NoopRawMutex, BUF>; N],
+}
+
+impl<const N: usize, const BUF: usize> State<N, BUF> {
+    /// Create a new `State`.
+    pub const fn new() -> Self {
+        Self {
+            rx: [const { Pipe::new() }; N],
+            tx: [const { Pipe::new() }; N],
+        }
+    }
+}
+
+impl<const N: usize, const BUF: usize> Default for State<N, BUF> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Virtual channel, carried by the multiplexer.
+///
+/// Channel `i` of the array returned by [`new`] uses DLCI `i + 1`.
+pub struct Channel<'d, const BUF: usize> {
+    rx: Reader<'d, NoopRawMutex, BUF>,
+    tx: Writer<'d, NoopRawMutex, BUF>,
+}
+
+impl<'d, const BUF: usize> Channel<'d, BUF> {
+    /// Split the channel in its reading and writing halves, to use them from different tasks.
+    pub fn split(self) -> (Reader<'d, NoopRawMutex, BUF>, Writer<'d, NoopRawMutex, BUF>) {
+        (self.rx, self.tx)
+    }
+}
+
+impl<const BUF: usize> ErrorType for Channel<'_, BUF> {
+    type Error = Infallible;
+}
+
+impl<const BUF: usize> Read for Channel<'_, BUF> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+        Ok(self.rx.read(buf).await)
+    }
+}
+
+impl<const BUF: usize> BufRead for Channel<'_, BUF> {
+    async fn fill_buf(&mut self) -> Result<&[u8], Infallible> {
+        Ok(self.rx.fill_buf().await)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.rx.consume(amt)
+    }
+}
+
+impl<const BUF: usize> Write for Channel<'_, BUF> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+        Ok(self.tx.write(buf).await)
+    }
+}
+
+/// Multiplexer side of a channel.
+struct ChannelState<'d, const BUF: usize> {
+    rx: Writer<'d, NoopRawMutex, BUF>,
+    tx: Reader<'d, NoopRawMutex, BUF>,
+    /// The modem asked us to stop sending, with its modem status.
+    stopped: bool,
+}
+
+/// Background runner for the CMUX multiplexer.
+///
+/// You must call `.run()` in a background task for the channels to operate.
+pub struct Runner<'d, const N: usize, const BUF: usize> {
+    channels: [ChannelState<'d, BUF>; N],
+    /// The modem asked us to stop sending on all channels.
+    stopped: bool,
+}
+
+impl<const N: usize, const BUF: usize> Runner<'_, N, BUF> {
+    /// You must call this in a background task for the channels to operate.
+    ///
+    /// The modem must already be in CMUX mode, with `AT+CMUX=0` sent on the serial port. This opens
+    /// the control channel and the virtual channels, then carries their data until an error occurs.
+    ///
+    /// Received data is written to the channels as it arrives: if a channel isn't read and its buffer
+    /// fills up, the other channels stall too.
+    pub async fn run<RW: BufRead + Write>(
+        &mut self,
+        mut rw: RW,
+        config: Config,
+    ) -> Result<Infallible, RunError<RW::Error>> {
+        assert!(config.frame_size <= MAX_FRAME_SIZE);
+        let mut reader = FrameReader::new(config.frame_size);
+        let mut buf = [0; MAX_FRAME_LEN + 2];
+        self.stopped = false;
+
+        for dlci in 0..=N as u8 {
+            self.open(&mut rw, &mut reader, &config, dlci).await?;
+        }
+        // Modems don't send data before the DTE signals it is ready.
+        for dlci in 1..=N as u8 {
+            let value = [(dlci << 2) | MSG_CR | MSG_EA, V24_DV | V24_RTR | V24_RTC | MSG_EA];
+            send_message(&mut rw, &mut buf, MSG_MSC | MSG_CR, &value).await?;
+        }
+        info!("CMUX: {} channels open", N);
+
+        loop {
+            let rx_fut = async {
+                match rw.fill_buf().await {
+                    Ok([]) => Err(RunError::Eof),
+                    Ok(rx_data) => Ok(rx_data),
+                    Err(e) => Err(RunError::Read(e)),
+                }
+            };
+            let stopped = self.stopped;
+            let tx_fut = select_array(self.channels.each_mut().map(|ch| async move {
+                if stopped || ch.stopped {
+                    pending::<()>().await;
+                }
+                ch.tx.fill_buf().await;
+            }));
+            match select(rx_fut, tx_fut).await {
+                Either::First(rx_data) => {
+                    let (n, frame) = reader.consume(rx_data?);
+                    rw.consume(n);
+                    if let Some(frame) = frame {
+                        self.received(&mut rw, &mut buf, frame).await?;
+                    }
+                }
+                Either::Second(((), i)) => {
+                    let ch = &mut self.channels[i];
+                    let Ok(data) = ch.tx.try_fill_buf() else {
+                        continue;
+                    };
+                    let n = data.len().min(config.frame_size);
+                    let len = write_frame(&mut buf, i as u8 + 1, true, UIH, &data[..n]);
+                    ch.tx.consume(n);
+                    rw.write_all(&buf[..len]).await.map_err(RunError::Write)?;
+                }
+            }
+        }
+    }
+
+    /// Open a channel, waiting for the modem to acknowledge it.
+    async fn open<RW: BufRead + Write>(
+        &mut self,
+        rw: &mut RW,
+        reader: &mut FrameReader,
+        config: &Config,
+        dlci: u8,
+    ) -> Result<(), RunError<RW::Error>> {
+        let mut buf = [0; 8];
+        for _ in 0..=config.retries {
+            let len = write_frame(&mut buf, dlci, true, SABM | PF, &[]);
+            rw.write_all(&buf[..len]).await.map_err(RunError::Write)?;
+
+            let answer = async {
+                loop {
+                    let (n, control) = match rw.fill_buf().await {
+                        Ok([]) => return Err(RunError::Eof),
+                        Ok(rx_data) => match reader.consume(rx_data) {
+                            (n, Some(frame)) if frame.dlci == dlci => (n, Some(frame.control)),
+                            (n, _) => (n, None),
+                        },
+                        Err(e) => return Err(RunError::Read(e)),
+                    };
+                    rw.consume(n);
+                    match control {
+                        Some(UA) => return Ok(()),
+                        Some(DM) => return Err(RunError::Refused),
+                        _ => {}
+                    }
+                }
+            };
+            match with_timeout(config.ack_timeout, answer).await {
+                Ok(Err(RunError::Refused)) => {
+                    warn!("CMUX: DLCI {} refused by the modem", dlci);
+                    return Err(RunError::Refused);
+                }
+                Ok(r) => return r,
+                Err(_) => debug!("CMUX: DLCI {} not acknowledged, retrying", dlci),
+            }
+        }
+        warn!("CMUX: DLCI {} not acknowledged", dlci);
+        Err(RunError::Timeout)
+    }
+
+    /// Handle a frame received from the modem.
+    async fn received<RW: BufRead + Write>(
+        &mut self,
+        rw: &mut RW,
+        buf: &mut [u8],
+        frame: Frame<'_>,
+    ) -> Result<(), RunError<RW::Error>> {
+        match (frame.control, frame.dlci) {
+            (UIH | UI, 0) => self.received_message(rw, buf, frame.info).await,
+            (UIH | UI, dlci) => {
+                let Some(ch) = self.channels.get_mut(usize::from(dlci) - 1) else {
+                    debug!("CMUX: data for unknown DLCI {}", dlci);
+                    return Ok(());
+                };
+                let mut data = frame.info;
+                while !data.is_empty() {
+                    let n = ch.rx.write(data).await;
+                    data = &data[n..];
+                }
+                Ok(())
+            }
+            (SABM, dlci) => {
+                // The modem (re)opening a channel, accept it if it exists.
+                let control = match usize::from(dlci) <= N {
+                    true => UA,
+                    false => DM,
+                };
+                let len = write_frame(buf, dlci, false, control | PF, &[]);
+                rw.write_all(&buf[..len]).await.map_err(RunError::Write)
+            }
+            (DISC, dlci) => {
+                let len = write_frame(buf, dlci, false, UA | PF, &[]);
+                rw.write_all(&buf[..len]).await.map_err(RunError::Write)?;
+                match dlci {
+                    0 => {
+                        info!("CMUX: closed by the modem");
+                        Err(RunError::Closed)
+                    }
+                    _ => {
+                        warn!("CMUX: DLCI {} closed by the modem", dlci);
+                        Ok(())
+                    }
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Handle a control message received on DLCI 0.
+    async fn received_message<RW: BufRead + Write>(
+        &mut self,
+        rw: &mut RW,
+        buf: &mut [u8],
+        data: &[u8],
+    ) -> Result<(), RunError<RW::Error>> {
+        let [kind, len, value @ ..] = data else {
+            return Ok(());
+        };
+        let Some(value) = value.get(..usize::from(len >> 1)) else {
+            return Ok(());
+        };
+        // Answers to our commands need no handling.
+        if kind & MSG_CR == 0 {
+            return Ok(());
+        }
+
+        let response = kind & !MSG_CR;
+        match kind & MSG_TYPE {
+            MSG_MSC => {
+                if let [address, signals, ..] = *value {
+                    let dlci = usize::from(address >> 2);
+                    if let Some(ch) = dlci.checked_sub(1).and_then(|i| self.channels.get_mut(i)) {
+                        ch.stopped = signals & V24_FC != 0;
+                    }
+                }
+                send_message(rw, buf, response, value).await
+            }
+            MSG_FCON | MSG_FCOFF => {
+                self.stopped = kind & MSG_TYPE == MSG_FCOFF;
+                send_message(rw, buf, response, value).await
+            }
+            MSG_TEST | MSG_PSC => send_message(rw, buf, response, value).await,
+            MSG_CLD => {
+                send_message(rw, buf, response, value).await?;
+                info!("CMUX: closed by the modem");
+                Err(RunError::Closed)
+            }
+            _ => {
+                debug!("CMUX: unsupported control message {:02x}", kind);
+                send_message(rw, buf, MSG_NSC, &[*kind]).await
+            }
+        }
+    }
+}
+
+/// Send a control message on DLCI 0.
+async fn send_message<RW: Write>(
+    rw: &mut RW,
+    buf: &mut [u8],
+    kind: u8,
+    value: &[u8],
+) -> Result<(), RunError<RW::Error>> {
+    let mut data = [0; 2 + 8];
+    let value = &value[..value.len().min(8)];
+    data[0] = kind;
+    data[1] = ((value.len() as u8) << 1) | MSG_EA;
+    data[2..2 + value.len()].copy_from_slice(value);
+    let len = write_frame(buf, 0, true, UIH, &data[..2 + value.len()]);
+    rw.write_all(&buf[..len]).await.map_err(RunError::Write)
+}
+
+/// Create a CMUX multiplexer instance.
+///
+/// This returns the `Runner`, on which you must call `.run()` in a background task, and the `N`
+/// virtual channels.
+pub fn new<const N: usize, const BUF: usize>(state: &mut State<N, BUF>) -> (Runner<'_, N, BUF>, [Channel<'_, BUF>; N]) {
+    assert!(N < 64, "CMUX supports at most 63 channels");
+    let rx = state.rx.each_mut().map(Pipe::split);
+    let tx = state.tx.each_mut().map(Pipe::split);
+    let rx_writers = rx.each_ref().map(|(_, w)| *w);
+    let tx_writers = tx.each_ref().map(|(_, w)| *w);
+    let mut rx_readers = rx.into_iter().map(|(r, _)| r);
+    let mut tx_readers = tx.into_iter().map(|(r, _)| r);
+
+    let channels = tx_writers.map(|tx| Channel {
+        rx: unwrap!(rx_readers.next()),
+        tx,
+    });
+    let runner = Runner {
+        channels: rx_writers.map(|rx| ChannelState {
+            rx,
+            tx: unwrap!(tx_readers.next()),
+            stopped: false,
+        }),
+        stopped: false,
+    };
+    (runner, channels)
+}
@@ -0,0 +1,294 @@
+//! Tests against a mock modem, on the other side of a pseudo-terminal.
+#![cfg(target_os = "linux")]
+
+use std::fs::File;
+use std::io::{self, Read as _, Write as _};
+use std::os::fd::{FromRawFd, RawFd};
+use std::thread;
+
+use async_io::Async;
+use embassy_cmux::{Config, RunError, State};
+use embassy_futures::select::{select, Either};
+use embassy_time::Duration;
+use embedded_io_async::{BufRead, ErrorType, Read, Write};
+
+const SABM: u8 = 0x2F;
+const UA: u8 = 0x63;
+const DM: u8 = 0x0F;
+const UIH: u8 = 0xEF;
+const PF: u8 = 0x10;
+
+fn fcs(data: &[u8]) -> u8 {
+    let mut fcs = 0xFFu8;
+    for &b in data {
+        fcs ^= b;
+        for _ in 0..8 {
+            fcs = if fcs & 1 != 0 { (fcs >> 1) ^ 0xE0 } else { fcs >> 1 };
+        }
+    }
+    fcs
+}
+
+/// Frame from the point of view of the modem, which answers commands with C/R = 1.
+fn frame(dlci: u8, cr: bool, control: u8, info: &[u8]) -> Vec<u8> {
+    let mut header = vec![(dlci << 2) | ((cr as u8) << 1) | 1, control];
+    if info.len() < 128 {
+        header.push(((info.len() as u8) << 1) | 1);
+    } else {
+        header.extend([(info.len() as u8) << 1, (info.len() >> 7) as u8]);
+    }
+    let mut f = vec![0xF9];
+    f.extend(&header);
+    f.extend(info);
+    f.push(0xFF - fcs(&header));
+    f.push(0xF9);
+    f
+}
+
+/// Read one frame, returning the DLCI, control field and information.
+fn read_frame(port: &mut File) -> io::Result<(u8, u8, Vec<u8>)> {
+    let mut byte = [0];
+    loop {
+        port.read_exact(&mut byte)?;
+        if byte[0] != 0xF9 {
+            break;
+        }
+    }
+    let mut header = vec![byte[0]];
+    port.read_exact(&mut byte)?;
+    header.push(byte[0]);
+    port.read_exact(&mut byte)?;
+    header.push(byte[0]);
+    let mut len = usize::from(byte[0] >> 1);
+    if byte[0] & 1 == 0 {
+        port.read_exact(&mut byte)?;
+        header.push(byte[0]);
+        len |= usize::from(byte[0]) << 7;
+    }
+    let mut info = vec![0; len];
+    port.read_exact(&mut info)?;
+    let mut tail = [0; 2];
+    port.read_exact(&mut tail)?;
+    assert_eq!(fcs(&header) ^ 0xFF, tail[0], "bad FCS");
+    assert_eq!(tail[1], 0xF9);
+    assert_eq!(header[0] & 0x02, 0x02, "C/R bit of the initiator");
+    Ok((header[0] >> 2, header[1], info))
+}
+
+#[derive(Clone, Copy)]
+enum Behavior {
+    Normal,
+    /// Refuse DLCI 2.
+    Refuse,
+    /// Never answer.
+    Mute,
+}
+
+/// Mock modem: acknowledges channels, answers AT commands on DLCI 2 and echoes data on DLCI 1.
+fn modem(mut port: File, behavior: Behavior, frame_size: usize) {
+    let mut at = Vec::new();
+    while let Ok((dlci, control, info)) = read_frame(&mut port) {
+        let answer = match (control & !PF, dlci, behavior) {
+            (SABM, _, Behavior::Mute) => continue,
+            (SABM, 2, Behavior::Refuse) => frame(dlci, true, DM | PF, &[]),
+            (SABM, _, _) => frame(dlci, true, UA | PF, &[]),
+            // Modem status command: answer it, then allow the DTE to send.
+            (UIH, 0, _) if info[0] == 0xE3 => {
+                let mut answer = frame(0, false, UIH, &[0xE1, info[1], info[2], info[3]]);
+                answer.extend(frame(0, false, UIH, &[0xE3, 0x05, info[2], 0x8D]));
+                answer
+            }
+            (UIH, 0, _) => continue,
+            (UIH, 1, _) => info
+                .chunks(frame_size)
+                .flat_map(|chunk| frame(1, false, UIH, chunk))
+                .collect(),
+            (UIH, 2, _) => {
+                at.extend(info);
+                if !at.ends_with(b"\r") {
+                    continue;
+                }
+                let answer = match &at[..] {
+                    b"AT+CSQ\r" => frame(2, false, UIH, b"\r\n+CSQ: 20,99\r\n\r\nOK\r\n"),
+                    _ => frame(2, false, UIH, b"\r\nERROR\r\n"),
+                };
+                at.clear();
+                answer
+            }
+            _ => continue,
+        };
+        if port.write_all(&answer).is_err() {
+            break;
+        }
+    }
+}
+
+/// Serial port on the terminal side of a pseudo-terminal.
+struct Pty {
+    file: Async<File>,
+    buf: [u8; 256],
+    pos: usize,
+    len: usize,
+}
+
+impl ErrorType for Pty {
+    type Error = io::Error;
+}
+
+impl Read for Pty {
+    async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read_with(|mut f| f.read(buf)).await
+    }
+}
+
+impl BufRead for Pty {
+    async fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.len {
+            let buf = &mut self.buf;
+            self.len = self.file.read_with(|mut f| f.read(buf)).await?;
+            self.pos = 0;
+        }
+        Ok(&self.buf[self.pos..self.len])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+impl Write for Pty {
+    async fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write_with(|mut f| f.write(buf)).await
+    }
+}
+
+fn raw(fd: RawFd) {
+    unsafe {
+        let mut termios = core::mem::zeroed();
+        assert_eq!(libc::tcgetattr(fd, &mut termios), 0);
+        libc::cfmakeraw(&mut termios);
+        assert_eq!(libc::tcsetattr(fd, libc::TCSANOW, &termios), 0);
+    }
+}
+
+/// Start a mock modem, and return the serial port connected to it.
+fn start_modem(behavior: Behavior, frame_size: usize) -> Pty {
+    let (mut master, mut slave) = (0, 0);
+    let r = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            core::ptr::null_mut(),
+            core::ptr::null(),
+            core::ptr::null(),
+        )
+    };
+    assert_eq!(r, 0);
+    raw(master);
+    raw(slave);
+    let master = unsafe { File::from_raw_fd(master) };
+    let slave = unsafe { File::from_raw_fd(slave) };
+    thread::spawn(move || modem(master, behavior, frame_size));
+    Pty {
+        file: Async::new(slave).unwrap(),
+        buf: [0; 256],
+        pos: 0,
+        len: 0,
+    }
+}
+
+async fn read_until<R: BufRead>(r: &mut R, end: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    while !data.ends_with(end) {
+        let buf = r.fill_buf().await.unwrap();
+        data.extend_from_slice(buf);
+        let n = buf.len();
+        r.consume(n);
+    }
+    data
+}
+
+#[test]
+fn at_and_data_channels() {
+    let mut state = State::<2, 512>::new();
+    let (mut runner, [data, mut at]) = embassy_cmux::new(&mut state);
+    let port = start_modem(Behavior::Normal, 31);
+
+    let test = async {
+        at.write_all(b"AT+CSQ\r").await.unwrap();
+        assert_eq!(read_until(&mut at, b"OK\r\n").await, b"\r\n+CSQ: 20,99\r\n\r\nOK\r\n");
+
+        // Bigger than the frame size and the buffers, with flags in the data.
+        let sent: Vec<u8> = (0..2000u32).map(|i| (i * 7) as u8 | 0x01).collect();
+        let (mut rx, mut tx) = data.split();
+        let write = async {
+            tx.write_all(&sent).await.unwrap();
+        };
+        let read = async {
+            let mut received = vec![0; sent.len()];
+            rx.read_exact(&mut received).await.unwrap();
+            received
+        };
+        let (_, received) = embassy_futures::join::join(write, read).await;
+        assert_eq!(received, sent);
+
+        at.write_all(b"AT+FOO\r").await.unwrap();
+        read_until(&mut at, b"ERROR\r\n").await;
+    };
+
+    async_io::block_on(async {
+        match select(runner.run(port, Config::default()), test).await {
+            Either::First(r) => panic!("runner stopped: {:?}", r),
+            Either::Second(()) => {}
+        }
+    });
+}
+
+#[test]
+fn large_frames() {
+    let mut state = State::<1, 4096>::new();
+    let (mut runner, [mut data]) = embassy_cmux::new(&mut state);
+    let port = start_modem(Behavior::Normal, 1509);
+    let mut config = Config::default();
+    config.frame_size = 1509;
+
+    let test = async {
+        let sent: Vec<u8> = (0..3000u32).map(|i| i as u8).collect();
+        data.write_all(&sent).await.unwrap();
+        let mut received = vec![0; sent.len()];
+        data.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, sent);
+    };
+
+    async_io::block_on(async {
+        match select(runner.run(port, config), test).await {
+            Either::First(r) => panic!("runner stopped: {:?}", r),
+            Either::Second(()) => {}
+        }
+    });
+}
+
+#[test]
+fn refused_channel() {
+    let mut state = State::<2, 64>::new();
+    let (mut runner, _channels) = embassy_cmux::new(&mut state);
+    let port = start_modem(Behavior::Refuse, 31);
+
+    let r = async_io::block_on(runner.run(port, Config::default()));
+    assert!(matches!(r, Err(RunError::Refused)));
+}
+
+#[test]
+fn no_answer() {
+    let mut state = State::<1, 64>::new();
+    let (mut runner, _channels) = embassy_cmux::new(&mut state);
+    let port = start_modem(Behavior::Mute, 31);
+    let mut config = Config::default();
+    config.ack_timeout = Duration::from_millis(50);
+    config.retries = 2;
+
+    let start = std::time::Instant::now();
+    let r = async_io::block_on(runner.run(port, config));
+    assert!(matches!(r, Err(RunError::Timeout)));
+    assert!(start.elapsed() >= std::time::Duration::from_millis(150));
+}
@@ -0,0 +1,371 @@
+//! IEEE 802.15.4 MAC frame headers (IEEE 802.15.4-2006 section 7.2).
+//!
+//! Only the 2003 and 2006 frame versions without security are supported, which is what
+//! `embassy-net` sends.
+
+/// Maximum length of a MAC frame, without the FCS.
+pub const MAX_FRAME_LEN: usize = 127 - FCS_LEN;
+const FCS_LEN: usize = 2;
+
+/// Broadcast PAN identifier and short address.
+pub const BROADCAST: u16 = 0xFFFF;
+
+const SECURITY: u16 = 1 << 3;
+const FRAME_PENDING: u16 = 1 << 4;
+const ACK_REQUEST: u16 = 1 << 5;
+const PAN_ID_COMPRESSION: u16 = 1 << 6;
+const DST_MODE_SHIFT: u16 = 10;
+const VERSION_SHIFT: u16 = 12;
+const SRC_MODE_SHIFT: u16 = 14;
+
+const MODE_NONE: u16 = 0;
+const MODE_SHORT: u16 = 2;
+const MODE_EXTENDED: u16 = 3;
+
+/// Frame type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameType {
+    /// Beacon.
+    Beacon,
+    /// Data, carrying 6LoWPAN packets.
+    Data,
+    /// Acknowledgment.
+    Ack,
+    /// MAC command.
+    Command,
+    /// Reserved frame type.
+    Reserved(u8),
+}
+
+impl FrameType {
+    fn from_bits(bits: u16) -> Self {
+        match bits & 0x7 {
+            0 => Self::Beacon,
+            1 => Self::Data,
+            2 => Self::Ack,
+            3 => Self::Command,
+            other => Self::Reserved(other as u8),
+        }
+    }
+
+    fn bits(self) -> u16 {
+        match self {
+            Self::Beacon => 0,
+            Self::Data => 1,
+            Self::Ack => 2,
+            Self::Command => 3,
+            Self::Reserved(bits) => u16::from(bits & 0x7),
+        }
+    }
+}
+
+/// Device address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Address {
+    /// Short address, assigned in the PAN.
+    Short(u16),
+    /// Extended address (EUI-64), most significant byte first like in
+    /// [`HardwareAddress::Ieee802154`](embassy_net_driver_channel::driver::HardwareAddress::Ieee802154).
+    ///
+    /// It is sent least significant byte first.
+    Extended([u8; 8]),
+}
+
+impl Address {
+    /// Whether this is the broadcast short address.
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::Short(BROADCAST)
+    }
+
+    fn mode(address: Option<Self>) -> u16 {
+        match address {
+            None => MODE_NONE,
+            Some(Self::Short(_)) => MODE_SHORT,
+            Some(Self::Extended(_)) => MODE_EXTENDED,
+        }
+    }
+
+    fn len(address: Option<Self>) -> usize {
+        match address {
+            None => 0,
+            Some(Self::Short(_)) => 2,
+            Some(Self::Extended(_)) => 8,
+        }
+    }
+}
+
+/// MAC header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Header {
+    /// Frame type.
+    pub frame_type: FrameType,
+    /// The sender has more data for the recipient.
+    pub frame_pending: bool,
+    /// The recipient must send an acknowledgment.
+    pub ack_request: bool,
+    /// Frame version: 0 for IEEE 802.15.4-2003, 1 for IEEE 802.15.4-2006.
+    pub version: u8,
+    /// Sequence number.
+    pub sequence_number: u8,
+    /// Destination PAN identifier, present along with a destination address.
+    pub dst_pan_id: Option<u16>,
+    /// Destination address.
+    pub dst_addr: Option<Address>,
+    /// Source PAN identifier, present along with a source address.
+    ///
+    /// When it's the same as the destination PAN identifier, it's not sent (PAN ID compression).
+    pub src_pan_id: Option<u16>,
+    /// Source address.
+    pub src_addr: Option<Address>,
+}
+
+impl Header {
+    /// Parse the header of `frame`, returning it along with its length.
+    ///
+    /// Returns `None` for malformed frames, and for frames with security enabled or a frame
+    /// version other than 2003 and 2006.
+    pub fn parse(frame: &[u8]) -> Option<(Self, usize)> {
+        let mut r = Reader { buf: frame, pos: 0 };
+        let fc = r.u16()?;
+        let version = ((fc >> VERSION_SHIFT) & 0x3) as u8;
+        if fc & SECURITY != 0 || version > 1 {
+            return None;
+        }
+        let sequence_number = r.u8()?;
+
+        let dst_mode = (fc >> DST_MODE_SHIFT) & 0x3;
+        let src_mode = (fc >> SRC_MODE_SHIFT) & 0x3;
+        let (dst_pan_id, dst_addr) = match dst_mode {
+            MODE_NONE => (None, None),
+            mode => (Some(r.u16()?), Some(r.address(mode)?)),
+        };
+        let (src_pan_id, src_addr) = match src_mode {
+            MODE_NONE => (None, None),
+            mode => {
+                let pan_id = match (fc & PAN_ID_COMPRESSION != 0, dst_pan_id) {
+                    (true, Some(pan_id)) => pan_id,
+                    (true, None) => return None,
+                    (false, _) => r.u16()?,
+                };
+                (Some(pan_id), Some(r.address(mode)?))
+            }
+        };
+
+        let header = Self {
+            frame_type: FrameType::from_bits(fc),
+            frame_pending: fc & FRAME_PENDING != 0,
+            ack_request: fc & ACK_REQUEST != 0,
+            version,
+            sequence_number,
+            dst_pan_id,
+            dst_addr,
+            src_pan_id,
+            src_addr,
+        };
+        Some((header, r.pos))
+    }
+
+    /// Length of the header, once emitted.
+    pub fn buffer_len(&self) -> usize {
+        let src_pan_id_len = match self.compress_pan_id() || self.src_addr.is_none() {
+            true => 0,
+            false => 2,
+        };
+        let dst_pan_id_len = match self.dst_addr {
+            Some(_) => 2,
+            None => 0,
+        };
+        3 + dst_pan_id_len + Address::len(self.dst_addr) + src_pan_id_len + Address::len(self.src_addr)
+    }
+
+    /// Write the header at the start of `buf`, returning its length.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buf` is shorter than [`buffer_len`](Self::buffer_len), or if an address is given without
+    /// its PAN identifier.
+    pub fn emit(&self, buf: &mut [u8]) -> usize {
+        let compress = self.compress_pan_id();
+        let mut fc = self.frame_type.bits()
+            | (Address::mode(self.dst_addr) << DST_MODE_SHIFT)
+            | (u16::from(self.version & 0x3) << VERSION_SHIFT)
+            | (Address::mode(self.src_addr) << SRC_MODE_SHIFT);
+        if self.frame_pending {
+            fc |= FRAME_PENDING;
+        }
+        if self.ack_request {
+            fc |= ACK_REQUEST;
+        }
+        if compress {
+            fc |= PAN_ID_COMPRESSION;
+        }
+
+        let mut w = Writer { buf, pos: 0 };
+        w.u16(fc);
+        w.u8(self.sequence_number);
+        if let Some(addr) = self.dst_addr {
+            w.u16(unwrap!(self.dst_pan_id));
+            w.address(addr);
+        }
+        if let Some(addr) = self.src_addr {
+            if !compress {
+                w.u16(unwrap!(self.src_pan_id));
+            }
+            w.address(addr);
+        }
+        w.pos
+    }
+
+    fn compress_pan_id(&self) -> bool {
+        self.dst_addr.is_some() && self.src_addr.is_some() && self.dst_pan_id == self.src_pan_id
+    }
+}
+
+/// Build an acknowledgment frame.
+pub fn ack(sequence_number: u8, frame_pending: bool) -> [u8; 3] {
+    let mut fc = FrameType::Ack.bits();
+    if frame_pending {
+        fc |= FRAME_PENDING;
+    }
+    let [a, b] = fc.to_le_bytes();
+    [a, b, sequence_number]
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.buf.get(self.pos..self.pos + N)?;
+        self.pos += N;
+        bytes.try_into().ok()
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take::<1>().map(|[b]| b)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    fn address(&mut self, mode: u16) -> Option<Address> {
+        match mode {
+            MODE_SHORT => self.u16().map(Address::Short),
+            MODE_EXTENDED => self
+                .take()
+                .map(|b| Address::Extended(u64::from_le_bytes(b).to_be_bytes())),
+            _ => None,
+        }
+    }
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Writer<'_> {
+    fn put(&mut self, bytes: &[u8]) {
+        self.buf[self.pos..self.pos + bytes.len()].copy_from_slice(bytes);
+        self.pos += bytes.len();
+    }
+
+    fn u8(&mut self, v: u8) {
+        self.put(&[v]);
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.put(&v.to_le_bytes());
+    }
+
+    fn address(&mut self, addr: Address) {
+        match addr {
+            Address::Short(a) => self.u16(a),
+            Address::Extended(a) => self.put(&u64::from_be_bytes(a).to_le_bytes()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXT_A: [u8; 8] = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77];
+
+    #[test]
+    fn parse_data_frame() {
+        // Data frame from embassy-net: PAN ID compression, zero destination PAN, broadcast
+        // destination, extended source.
+        let frame = [
+            0x41, 0xc8, 0x07, 0x00, 0x00, 0xff, 0xff, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x02, 0xaa,
+        ];
+        let (header, len) = Header::parse(&frame).unwrap();
+        assert_eq!(len, 15);
+        assert_eq!(header.frame_type, FrameType::Data);
+        assert!(!header.ack_request);
+        assert_eq!(header.version, 0);
+        assert_eq!(header.sequence_number, 7);
+        assert_eq!(header.dst_pan_id, Some(0));
+        assert_eq!(header.dst_addr, Some(Address::Short(BROADCAST)));
+        assert_eq!(header.src_pan_id, Some(0));
+        assert_eq!(header.src_addr, Some(Address::Extended(EXT_A)));
+        assert_eq!(header.buffer_len(), 15);
+
+        let mut buf = [0; MAX_FRAME_LEN];
+        assert_eq!(header.emit(&mut buf), 15);
+        assert_eq!(buf[..15], frame[..15]);
+    }
+
+    #[test]
+    fn roundtrip_without_compression() {
+        let header = Header {
+            frame_type: FrameType::Data,
+            frame_pending: true,
+            ack_request: true,
+            version: 1,
+            sequence_number: 200,
+            dst_pan_id: Some(0x1234),
+            dst_addr: Some(Address::Short(0x0001)),
+            src_pan_id: Some(0xabcd),
+            src_addr: Some(Address::Extended(EXT_A)),
+        };
+        let mut buf = [0; MAX_FRAME_LEN];
+        let len = header.emit(&mut buf);
+        assert_eq!(len, 3 + 2 + 2 + 2 + 8);
+        assert_eq!(len, header.buffer_len());
+        assert_eq!(Header::parse(&buf[..len]), Some((header, len)));
+    }
+
+    #[test]
+    fn reject_unsupported() {
+        // Truncated.
+        assert_eq!(Header::parse(&[0x41, 0xc8, 0x07, 0x00]), None);
+        // Security enabled.
+        assert_eq!(
+            Header::parse(&[0x49, 0x88, 0x07, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00]),
+            None
+        );
+        // 2015 frame version.
+        assert_eq!(
+            Header::parse(&[0x41, 0xa8, 0x07, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00]),
+            None
+        );
+    }
+
+    #[test]
+    fn ack_frame() {
+        let frame = ack(42, false);
+        assert_eq!(frame, [0x02, 0x00, 42]);
+        let (header, len) = Header::parse(&frame).unwrap();
+        assert_eq!(len, 3);
+        assert_eq!(header.frame_type, FrameType::Ack);
+        assert_eq!(header.sequence_number, 42);
+        assert_eq!(header.dst_addr, None);
+    }
+}
@@ -0,0 +1,185 @@
+#![no_std]
+#![allow(async_fn_in_trait)]
+#![warn(missing_docs)]
+#![doc = include_str!("../README.md")]
+
+// must be first
+mod fmt;
+
+pub mod frame;
+mod mac;
+
+use embassy_futures::select::{select, Either};
+use embassy_net_driver_channel as ch;
+use embassy_net_driver_channel::driver::{HardwareAddress, LinkState};
+use embassy_time::Duration;
+
+use crate::frame::MAX_FRAME_LEN;
+use crate::mac::{Mac, Rx};
+
+const MTU: usize = MAX_FRAME_LEN;
+
+/// The channel was busy during the clear channel assessment, nothing was sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChannelBusy;
+
+/// IEEE 802.15.4 radio, tuned to the channel of the PAN.
+///
+/// Frames are given without the FCS, which the radio computes and checks.
+pub trait Radio {
+    /// Receive a frame in `buf`, which is [`MAX_FRAME_LEN`] long, and return its length.
+    ///
+    /// Frames with an invalid FCS must be skipped. The returned future is dropped when a frame
+    /// must be sent, so it must be cancel-safe.
+    async fn receive(&mut self, buf: &mut [u8]) -> usize;
+
+    /// Send a frame.
+    ///
+    /// With `cca`, a clear channel assessment is done first, and nothing is sent if the channel
+    /// is busy. Acknowledgments are sent without it.
+    async fn transmit(&mut self, frame: &[u8], cca: bool) -> Result<(), ChannelBusy>;
+}
+
+/// MAC configuration.
+///
+/// The defaults of the MAC parameters are the ones of IEEE 802.15.4-2006, for the 2.4 GHz PHY.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Config {
+    /// PAN identifier.
+    pub pan_id: u16,
+    /// Extended address (EUI-64), most significant byte first. It's also the hardware address of
+    /// the interface, from which `embassy-net` derives the IPv6 link-local address.
+    pub extended_address: [u8; 8],
+    /// Short address, if one is assigned. Frames sent to it are received too.
+    pub short_address: Option<u16>,
+    /// Number of retransmissions of a frame that isn't acknowledged (macMaxFrameRetries).
+    pub max_frame_retries: u8,
+    /// Number of times the channel can be found busy before giving up on a frame
+    /// (macMaxCSMABackoffs).
+    pub max_csma_backoffs: u8,
+    /// Minimum backoff exponent of CSMA-CA (macMinBE).
+    pub min_be: u8,
+    /// Maximum backoff exponent of CSMA-CA (macMaxBE), up to 8.
+    pub max_be: u8,
+    /// Time to wait for an acknowledgment before retransmitting (macAckWaitDuration).
+    pub ack_timeout: Duration,
+}
+
+impl Config {
+    /// Create a configuration with the default MAC parameters.
+    pub const fn new(pan_id: u16, extended_address: [u8; 8]) -> Self {
+        Self {
+            pan_id,
+            extended_address,
+            short_address: None,
+            max_frame_retries: 3,
+            max_csma_backoffs: 4,
+            min_be: 3,
+            max_be: 5,
+            ack_timeout: Duration::from_micros(864),
+        }
+    }
+}
+
+/// Type alias for the embassy-net driver.
+pub type Device<'d> = embassy_net_driver_channel::Device<'d, MTU>;
+
+/// Internal state for the embassy-net integration.
+pub struct State<const N_RX: usize, const N_TX: usize> {
+    ch_state: ch::State<MTU, N_RX, N_TX>,
+}
+
+impl<const N_RX: usize, const N_TX: usize> State<N_RX, N_TX> {
+    /// Create a new `State`.
+    pub const fn new() -> Self {
+        Self {
+            ch_state: ch::State::new(),
+        }
+    }
+}
+
+impl<const N_RX: usize, const N_TX: usize> Default for State<N_RX, N_TX> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Background runner for the driver.
+///
+/// You must call `.run()` in a background task for the driver to operate.
+pub struct Runner<'d> {
+    ch: ch::Runner<'d, MTU>,
+    mac: Mac,
+}
+
+impl Runner<'_> {
+    /// You must call this in a background task for the driver to operate.
+    ///
+    /// The radio listens whenever it's not sending. Frames that can't be sent, because the
+    /// channel is busy or they aren't acknowledged, are dropped.
+    pub async fn run<R: Radio>(&mut self, mut radio: R) -> ! {
+        let (state_chan, mut rx_chan, mut tx_chan) = self.ch.borrow_split();
+        state_chan.set_link_state(LinkState::Up);
+
+        let mut buf = [0; MAX_FRAME_LEN];
+        loop {
+            let event = select(radio.receive(&mut buf), tx_chan.tx_buf()).await;
+            match event {
+                Either::First(len) => {
+                    let frame = &buf[..len];
+                    match self.mac.received(frame) {
+                        Rx::Drop => {}
+                        Rx::Data { ack } => {
+                            // Without an acknowledgment, the sender retransmits the frame later.
+                            let Some(rx_buf) = rx_chan.try_rx_buf() else {
+                                debug!("rx buffer full, dropping frame");
+                                continue;
+                            };
+                            rx_buf[..len].copy_from_slice(frame);
+                            rx_chan.rx_done(len);
+                            if let Some(seq) = ack {
+                                self.mac.send_ack(&mut radio, seq).await;
+                            }
+                        }
+                        Rx::Duplicate { ack } => {
+                            if let Some(seq) = ack {
+                                self.mac.send_ack(&mut radio, seq).await;
+                            }
+                        }
+                    }
+                }
+                Either::Second(frame) => {
+                    if let Err(e) = self.mac.transmit(&mut radio, frame).await {
+                        warn!("tx failed: {:?}", e);
+                    }
+                    tx_chan.tx_done();
+                }
+            }
+        }
+    }
+}
+
+/// Create an IEEE 802.15.4 embassy-net driver instance.
+///
+/// This returns two structs:
+/// - a `Device` that you must pass to the `embassy-net` stack.
+/// - a `Runner`. You must call `.run()` on it in a background task.
+pub fn new<const N_RX: usize, const N_TX: usize>(
+    state: &mut State<N_RX, N_TX>,
+    config: Config,
+) -> (Device<'_>, Runner<'_>) {
+    let (runner, device) = ch::new(
+        &mut state.ch_state,
+        HardwareAddress::Ieee802154(config.extended_address),
+    );
+    (
+        device,
+        Runner {
+            ch: runner,
+            mac: Mac::new(config),
+        },
+    )
+}
@@ -0,0 +1,437 @@
+//! The parts of the MAC of IEEE 802.15.4-2006 needed for 6LoWPAN: unslotted CSMA-CA
+//! (section 7.5.1.4), acknowledgments and retransmissions (section 7.5.6.4), frame filtering
+//! (section 7.5.6.2) and duplicate detection.
+
+use embassy_time::{with_timeout, Duration, Timer};
+
+use crate::frame::{self, Address, FrameType, Header, BROADCAST, MAX_FRAME_LEN};
+use crate::{ChannelBusy, Config, Radio};
+
+/// aUnitBackoffPeriod, 20 symbols of 16 µs.
+const UNIT_BACKOFF_PERIOD: Duration = Duration::from_micros(320);
+
+/// Number of senders remembered for duplicate detection.
+const SEQUENCE_HISTORY: usize = 8;
+
+/// Reason a frame couldn't be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum TxError {
+    /// The frame from the stack couldn't be parsed.
+    Malformed,
+    /// The channel stayed busy for all CSMA-CA backoffs.
+    ChannelAccessFailure,
+    /// No acknowledgment was received, even after all retransmissions.
+    NoAck,
+}
+
+/// What to do with a received frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Rx {
+    /// Not for us, or not a data frame.
+    Drop,
+    /// Data frame, to give to the stack. Acknowledge it with this sequence number, once it's
+    /// accepted.
+    Data { ack: Option<u8> },
+    /// Data frame already received. Acknowledge it again, but don't give it to the stack.
+    Duplicate { ack: Option<u8> },
+}
+
+pub(crate) struct Mac {
+    config: Config,
+    sequence_number: u8,
+    rng: u64,
+    /// Last sequence number received from each sender, round robin.
+    history: [Option<(Address, u8)>; SEQUENCE_HISTORY],
+    history_next: usize,
+}
+
+impl Mac {
+    pub fn new(config: Config) -> Self {
+        // Seed with the extended address, so that neighbors back off differently.
+        let mut rng = 0x2545_f491_4f6c_dd1d;
+        for b in config.extended_address {
+            rng = (rng ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3);
+        }
+        Self {
+            config,
+            sequence_number: rng as u8,
+            rng,
+            history: [None; SEQUENCE_HISTORY],
+            history_next: 0,
+        }
+    }
+
+    /// Send a frame built by the stack, completing its header first.
+    pub async fn transmit<R: Radio>(&mut self, radio: &mut R, frame: &[u8]) -> Result<(), TxError> {
+        let (mut header, header_len) = Header::parse(frame).ok_or(TxError::Malformed)?;
+        let payload = &frame[header_len..];
+
+        // The stack doesn't know the PAN, and never asks for acknowledgments.
+        let pan_id = Some(self.config.pan_id);
+        header.dst_pan_id = header.dst_addr.and(pan_id);
+        header.src_pan_id = header.src_addr.and(pan_id);
+        header.ack_request = header.dst_addr.is_some_and(|a| !a.is_broadcast());
+        header.sequence_number = self.sequence_number;
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+
+        let mut buf = [0; MAX_FRAME_LEN];
+        if header.buffer_len() + payload.len() > buf.len() {
+            return Err(TxError::Malformed);
+        }
+        let len = header.emit(&mut buf);
+        buf[len..][..payload.len()].copy_from_slice(payload);
+        let frame = &buf[..len + payload.len()];
+
+        for attempt in 0..=self.config.max_frame_retries {
+            if attempt > 0 {
+                trace!("retransmitting frame {}", header.sequence_number);
+            }
+            self.csma_ca(radio, frame).await?;
+            if !header.ack_request || self.wait_ack(radio, header.sequence_number).await {
+                return Ok(());
+            }
+        }
+        Err(TxError::NoAck)
+    }
+
+    /// Filter a received frame.
+    pub fn received(&mut self, frame: &[u8]) -> Rx {
+        let Some((header, _)) = Header::parse(frame) else {
+            return Rx::Drop;
+        };
+        if header.frame_type != FrameType::Data {
+            return Rx::Drop;
+        }
+
+        match header.dst_pan_id {
+            Some(pan_id) if pan_id == self.config.pan_id || pan_id == BROADCAST => {}
+            _ => return Rx::Drop,
+        }
+        let broadcast = match header.dst_addr {
+            Some(Address::Short(BROADCAST)) => true,
+            Some(Address::Short(addr)) if Some(addr) == self.config.short_address => false,
+            Some(Address::Extended(addr)) if addr == self.config.extended_address => false,
+            _ => return Rx::Drop,
+        };
+
+        let ack = match header.ack_request && !broadcast {
+            true => Some(header.sequence_number),
+            false => None,
+        };
+        let Some(src) = header.src_addr else {
+            return Rx::Data { ack };
+        };
+        let seen = self.history.iter_mut().flatten().find(|(addr, _)| *addr == src);
+        match seen {
+            Some((_, seq)) if *seq == header.sequence_number => return Rx::Duplicate { ack },
+            Some((_, seq)) => *seq = header.sequence_number,
+            None => {
+                self.history[self.history_next] = Some((src, header.sequence_number));
+                self.history_next = (self.history_next + 1) % SEQUENCE_HISTORY;
+            }
+        }
+        Rx::Data { ack }
+    }
+
+    /// Send an acknowledgment, right away.
+    pub async fn send_ack<R: Radio>(&mut self, radio: &mut R, sequence_number: u8) {
+        // Sent without CCA, the channel is ours during the turnaround time.
+        let _ = radio.transmit(&frame::ack(sequence_number, false), false).await;
+    }
+
+    /// Unslotted CSMA-CA, sending the frame once the channel is clear.
+    async fn csma_ca<R: Radio>(&mut self, radio: &mut R, frame: &[u8]) -> Result<(), TxError> {
+        let mut backoffs = 0;
+        let mut exponent = self.config.min_be.min(self.config.max_be);
+        loop {
+            let periods = self.next_u64() % (1 << exponent);
+            Timer::after(UNIT_BACKOFF_PERIOD * periods as u32).await;
+            match radio.transmit(frame, true).await {
+                Ok(()) => return Ok(()),
+                Err(ChannelBusy) => {
+                    backoffs += 1;
+                    exponent = (exponent + 1).min(self.config.max_be);
+                    if backoffs > self.config.max_csma_backoffs {
+                        debug!("channel access failure");
+                        return Err(TxError::ChannelAccessFailure);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Wait for the acknowledgment of a frame. Other frames received meanwhile are dropped.
+    async fn wait_ack<R: Radio>(&mut self, radio: &mut R, sequence_number: u8) -> bool {
+        let mut buf = [0; MAX_FRAME_LEN];
+        let wait = async {
+            loop {
+                let len = radio.receive(&mut buf).await;
+                match Header::parse(&buf[..len]) {
+                    Some((h, _)) if h.frame_type == FrameType::Ack && h.sequence_number == sequence_number => return,
+                    _ => {}
+                }
+            }
+        };
+        with_timeout(self.config.ack_timeout, wait).await.is_ok()
+    }
+
+    /// xorshift64.
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.rng;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng = x;
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    const OURS: [u8; 8] = [0x02, 0, 0, 0, 0, 0, 0, 0x01];
+    const THEIRS: [u8; 8] = [0x02, 0, 0, 0, 0, 0, 0, 0x02];
+    const PAN_ID: u16 = 0xabcd;
+
+    /// Radio answering the frames sent to it according to a script.
+    #[derive(Default)]
+    struct SimRadio {
+        /// Frames sent, and whether CCA was requested.
+        sent: Vec<(Vec<u8>, bool)>,
+        /// Frames to receive.
+        incoming: VecDeque<Vec<u8>>,
+        /// For each frame sent with CCA, whether the channel is busy. Clear once empty.
+        busy: VecDeque<bool>,
+        /// For each frame sent requesting an acknowledgment, whether it's acknowledged. Yes once
+        /// empty.
+        acks: VecDeque<bool>,
+    }
+
+    impl Radio for SimRadio {
+        async fn receive(&mut self, buf: &mut [u8]) -> usize {
+            loop {
+                if let Some(frame) = self.incoming.pop_front() {
+                    buf[..frame.len()].copy_from_slice(&frame);
+                    return frame.len();
+                }
+                Timer::after_micros(10).await;
+            }
+        }
+
+        async fn transmit(&mut self, frame: &[u8], cca: bool) -> Result<(), ChannelBusy> {
+            if cca && self.busy.pop_front().unwrap_or(false) {
+                return Err(ChannelBusy);
+            }
+            self.sent.push((frame.to_vec(), cca));
+            let (header, _) = Header::parse(frame).unwrap();
+            if header.ack_request && self.acks.pop_front().unwrap_or(true) {
+                self.incoming
+                    .push_back(frame::ack(header.sequence_number, false).to_vec());
+            }
+            Ok(())
+        }
+    }
+
+    fn config() -> Config {
+        let mut config = Config::new(PAN_ID, OURS);
+        config.short_address = Some(0x0001);
+        config
+    }
+
+    /// Frame as built by embassy-net, without PAN identifier nor acknowledgment request.
+    fn stack_frame(dst: Address) -> Vec<u8> {
+        let header = Header {
+            frame_type: FrameType::Data,
+            frame_pending: false,
+            ack_request: false,
+            version: 0,
+            sequence_number: 0,
+            dst_pan_id: Some(0),
+            dst_addr: Some(dst),
+            src_pan_id: Some(0),
+            src_addr: Some(Address::Extended(OURS)),
+        };
+        let mut buf = [0; MAX_FRAME_LEN];
+        let len = header.emit(&mut buf);
+        let mut frame = buf[..len].to_vec();
+        frame.extend_from_slice(b"payload");
+        frame
+    }
+
+    fn peer_frame(pan_id: u16, dst: Address, ack_request: bool, sequence_number: u8) -> Vec<u8> {
+        let header = Header {
+            frame_type: FrameType::Data,
+            frame_pending: false,
+            ack_request,
+            version: 1,
+            sequence_number,
+            dst_pan_id: Some(pan_id),
+            dst_addr: Some(dst),
+            src_pan_id: Some(pan_id),
+            src_addr: Some(Address::Extended(THEIRS)),
+        };
+        let mut buf = [0; MAX_FRAME_LEN];
+        let len = header.emit(&mut buf);
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn unicast_acknowledged() {
+        let mut mac = Mac::new(config());
+        let mut radio = SimRadio::default();
+        let frame = stack_frame(Address::Extended(THEIRS));
+        block_on(mac.transmit(&mut radio, &frame)).unwrap();
+
+        assert_eq!(radio.sent.len(), 1);
+        let (sent, cca) = &radio.sent[0];
+        assert!(cca);
+        let (header, len) = Header::parse(sent).unwrap();
+        assert!(header.ack_request);
+        assert_eq!(header.dst_pan_id, Some(PAN_ID));
+        assert_eq!(header.src_pan_id, Some(PAN_ID));
+        assert_eq!(header.dst_addr, Some(Address::Extended(THEIRS)));
+        assert_eq!(&sent[len..], b"payload");
+
+        // The next frame gets the next sequence number.
+        block_on(mac.transmit(&mut radio, &frame)).unwrap();
+        let (next, _) = Header::parse(&radio.sent[1].0).unwrap();
+        assert_eq!(next.sequence_number, header.sequence_number.wrapping_add(1));
+    }
+
+    #[test]
+    fn retransmitted_until_acknowledged() {
+        let mut mac = Mac::new(config());
+        let mut radio = SimRadio {
+            acks: [false, false, true].into(),
+            ..Default::default()
+        };
+        block_on(mac.transmit(&mut radio, &stack_frame(Address::Short(0x0002)))).unwrap();
+
+        assert_eq!(radio.sent.len(), 3);
+        // Retransmissions keep the sequence number.
+        assert!(radio.sent.iter().all(|(f, _)| *f == radio.sent[0].0));
+    }
+
+    #[test]
+    fn no_ack() {
+        let mut mac = Mac::new(config());
+        let mut radio = SimRadio {
+            acks: [false; 10].into(),
+            ..Default::default()
+        };
+        let r = block_on(mac.transmit(&mut radio, &stack_frame(Address::Short(0x0002))));
+        assert_eq!(r, Err(TxError::NoAck));
+        assert_eq!(radio.sent.len(), 1 + usize::from(config().max_frame_retries));
+    }
+
+    #[test]
+    fn ack_with_other_sequence_number_ignored() {
+        let mut mac = Mac::new(config());
+        let mut radio = SimRadio {
+            acks: [false].into(),
+            ..Default::default()
+        };
+        radio.incoming.push_back(frame::ack(0x55, false).to_vec());
+        block_on(mac.transmit(&mut radio, &stack_frame(Address::Short(0x0002)))).unwrap();
+        assert_eq!(radio.sent.len(), 2);
+    }
+
+    #[test]
+    fn broadcast_not_acknowledged() {
+        let mut mac = Mac::new(config());
+        let mut radio = SimRadio {
+            acks: [false].into(),
+            ..Default::default()
+        };
+        block_on(mac.transmit(&mut radio, &stack_frame(Address::Short(BROADCAST)))).unwrap();
+
+        assert_eq!(radio.sent.len(), 1);
+        let (header, _) = Header::parse(&radio.sent[0].0).unwrap();
+        assert!(!header.ack_request);
+    }
+
+    #[test]
+    fn busy_channel() {
+        let mut mac = Mac::new(config());
+        // Clear after some backoffs.
+        let mut radio = SimRadio {
+            busy: [true, true, true].into(),
+            ..Default::default()
+        };
+        block_on(mac.transmit(&mut radio, &stack_frame(Address::Short(BROADCAST)))).unwrap();
+        assert_eq!(radio.sent.len(), 1);
+        assert!(radio.busy.is_empty());
+
+        // Always busy.
+        radio.busy = [true; 10].into();
+        let r = block_on(mac.transmit(&mut radio, &stack_frame(Address::Short(BROADCAST))));
+        assert_eq!(r, Err(TxError::ChannelAccessFailure));
+        assert_eq!(radio.busy.len(), 10 - 1 - usize::from(config().max_csma_backoffs));
+        assert_eq!(radio.sent.len(), 1);
+    }
+
+    #[test]
+    fn filtering() {
+        let mut mac = Mac::new(config());
+        let ours = Address::Extended(OURS);
+
+        assert_eq!(
+            mac.received(&peer_frame(PAN_ID, ours, true, 1)),
+            Rx::Data { ack: Some(1) }
+        );
+        assert_eq!(
+            mac.received(&peer_frame(PAN_ID, Address::Short(0x0001), true, 2)),
+            Rx::Data { ack: Some(2) }
+        );
+        assert_eq!(
+            mac.received(&peer_frame(PAN_ID, Address::Short(BROADCAST), true, 3)),
+            Rx::Data { ack: None }
+        );
+        assert_eq!(
+            mac.received(&peer_frame(BROADCAST, Address::Short(BROADCAST), false, 4)),
+            Rx::Data { ack: None }
+        );
+
+        // Other PAN, or other destination.
+        assert_eq!(mac.received(&peer_frame(0x1234, ours, true, 5)), Rx::Drop);
+        assert_eq!(
+            mac.received(&peer_frame(PAN_ID, Address::Short(0x0002), true, 6)),
+            Rx::Drop
+        );
+        let other = Address::Extended(THEIRS);
+        assert_eq!(mac.received(&peer_frame(PAN_ID, other, true, 7)), Rx::Drop);
+
+        // Not data.
+        assert_eq!(mac.received(&frame::ack(8, false)), Rx::Drop);
+        assert_eq!(mac.received(&[0x41]), Rx::Drop);
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut mac = Mac::new(config());
+        let ours = Address::Extended(OURS);
+
+        assert_eq!(
+            mac.received(&peer_frame(PAN_ID, ours, true, 1)),
+            Rx::Data { ack: Some(1) }
+        );
+        // Our acknowledgment was lost, and the frame is retransmitted.
+        assert_eq!(
+            mac.received(&peer_frame(PAN_ID, ours, true, 1)),
+            Rx::Duplicate { ack: Some(1) }
+        );
+        assert_eq!(
+            mac.received(&peer_frame(PAN_ID, ours, true, 2)),
+            Rx::Data { ack: Some(2) }
+        );
+    }
+}
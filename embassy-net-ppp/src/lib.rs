@@ -1,22 +1,131 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![warn(missing_docs)]
 #![doc = include_str!("../README.md")]
 
 // must be first
 mod fmt;
 
+mod ppp;
+mod pppos;
+
 use core::convert::Infallible;
 use core::mem::MaybeUninit;
+use core::net::{Ipv4Addr, Ipv6Addr};
 
-use embassy_futures::select::{select, Either};
+use embassy_futures::select::{select3, Either3};
 use embassy_net_driver_channel as ch;
 use embassy_net_driver_channel::driver::LinkState;
+use embassy_time::{Duration, Instant, Timer};
 use embedded_io_async::{BufRead, Write};
-use ppproto::pppos::{BufferFullError, PPPoS, PPPoSAction};
-pub use ppproto::{Config, Ipv4Status};
+use rand_core::RngCore;
+
+use crate::ppp::Ppp;
+use crate::pppos::{FrameReader, TxBuf};
 
 const MTU: usize = 1500;
 
+/// Authentication protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AuthProtocol {
+    /// Password Authentication Protocol, sending the password in clear.
+    Pap,
+    /// Challenge-Handshake Authentication Protocol, with MD5.
+    Chap,
+}
+
+/// User name and password.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Credentials<'a> {
+    /// User name.
+    pub username: &'a [u8],
+    /// Password.
+    pub password: &'a [u8],
+}
+
+/// Addresses handed out in server mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ServerConfig {
+    /// Our address.
+    pub address: Ipv4Addr,
+    /// Address assigned to the peer.
+    pub peer_address: Ipv4Addr,
+    /// DNS servers given to the peer.
+    pub dns_servers: [Option<Ipv4Addr>; 2],
+}
+
+/// PPP configuration.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config<'a> {
+    /// User name sent when the peer requires authentication.
+    pub username: &'a [u8],
+    /// Password sent when the peer requires authentication.
+    pub password: &'a [u8],
+    /// Authentication required from the peer, or `None` to accept unauthenticated peers.
+    pub require_auth: Option<AuthProtocol>,
+    /// Credentials accepted from the peer, when `require_auth` is set.
+    pub secrets: &'a [Credentials<'a>],
+    /// Act as a server, assigning an address and DNS servers to the peer.
+    ///
+    /// Otherwise, act as a client, getting them from the peer.
+    pub server: Option<ServerConfig>,
+    /// Interval of LCP echo requests, or `None` to disable them.
+    pub echo_interval: Option<Duration>,
+    /// Time without receiving anything from the peer after which it is considered dead, and
+    /// [`RunError::DeadPeer`] is returned. Only checked when `echo_interval` is set.
+    pub dead_peer_timeout: Duration,
+    /// Negotiate IPv4, with IPCP.
+    pub ipv4: bool,
+    /// Negotiate IPv6, with IPV6CP.
+    ///
+    /// Off by default: peers without IPv6 support may reject the protocol, or handle it badly.
+    pub ipv6: bool,
+}
+
+impl Default for Config<'_> {
+    fn default() -> Self {
+        Self {
+            username: b"",
+            password: b"",
+            require_auth: None,
+            secrets: &[],
+            server: None,
+            echo_interval: None,
+            dead_peer_timeout: Duration::from_secs(30),
+            ipv4: true,
+            ipv6: false,
+        }
+    }
+}
+
+/// Status of IPv4 on the link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Ipv4Status {
+    /// Our address.
+    pub address: Option<Ipv4Addr>,
+    /// Address of the peer.
+    pub peer_address: Option<Ipv4Addr>,
+    /// DNS servers, given by the peer in client mode, or to it in server mode.
+    pub dns_servers: [Option<Ipv4Addr>; 2],
+}
+
+/// Status of IPv6 on the link.
+///
+/// Only link-local addresses are negotiated, global ones are usually configured with SLAAC,
+/// from the router advertisements of the peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Ipv6Status {
+    /// Our link-local address, from the negotiated interface identifier.
+    pub address: Ipv6Addr,
+    /// Link-local address of the peer.
+    pub peer_address: Option<Ipv6Addr>,
+}
+
 /// Type alias for the embassy-net driver.
 pub type Device<'d> = embassy_net_driver_channel::Device<'d, MTU>;
 
@@ -53,95 +162,108 @@ pub enum RunError<E> {
     Eof,
     /// PPP protocol was terminated by the peer
     Terminated,
+    /// Authentication failed, in either direction.
+    AuthFailed,
+    /// The peer stopped answering LCP echo requests.
+    DeadPeer,
+}
+
+impl<E> From<ppp::Error> for RunError<E> {
+    fn from(error: ppp::Error) -> Self {
+        match error {
+            ppp::Error::Terminated => Self::Terminated,
+            ppp::Error::AuthFailed => Self::AuthFailed,
+            ppp::Error::DeadPeer => Self::DeadPeer,
+        }
+    }
 }
 
 impl<'d> Runner<'d> {
     /// You must call this in a background task for the driver to operate.
     ///
-    /// If reading/writing to the underlying serial port fails, the link state
-    /// is set to Down and the error is returned.
+    /// If reading/writing to the underlying serial port fails, or the PPP connection
+    /// goes down, the link state is set to Down and the error is returned.
     ///
     /// It is allowed to cancel this function's future (i.e. drop it). This will terminate
     /// the PPP connection and set the link state to Down.
     ///
     /// After this function returns or is canceled, you can call it again to establish
     /// a new PPP connection.
+    ///
+    /// `rng` gives the LCP magic numbers, the CHAP challenges and the IPv6 interface identifiers.
+    /// It must be seeded differently on each device, ideally from a hardware random number
+    /// generator, otherwise magic numbers collide and challenges can be replayed.
     pub async fn run<RW: BufRead + Write>(
         &mut self,
         mut rw: RW,
-        config: ppproto::Config<'_>,
+        config: Config<'_>,
+        rng: &mut impl RngCore,
         mut on_ipv4_up: impl FnMut(Ipv4Status),
+        mut on_ipv6_up: impl FnMut(Ipv6Status),
     ) -> Result<Infallible, RunError<RW::Error>> {
-        let mut ppp = PPPoS::new(config);
-        ppp.open().unwrap();
+        let mut ppp = Ppp::new(&config, rng, Instant::now());
+        let mut reader = FrameReader::new();
+        let mut tx = TxBuf::new();
+        ppp.open(Instant::now(), &mut tx);
 
         let (state_chan, mut rx_chan, mut tx_chan) = self.ch.borrow_split();
         state_chan.set_link_state(LinkState::Down);
         let _ondrop = OnDrop::new(|| state_chan.set_link_state(LinkState::Down));
 
-        let mut rx_buf = [0; 2048];
-        let mut tx_buf = [0; 2048];
-
-        let mut needs_poll = true;
-        let mut was_up = false;
+        let mut was_ipv4_up = false;
+        let mut was_ipv6_up = false;
 
         loop {
+            // Send the frames queued by the last event, including the termination on errors.
+            if !tx.data().is_empty() {
+                rw.write_all(tx.data()).await.map_err(RunError::Write)?;
+                tx.clear();
+            }
+            if let Some(e) = ppp.error() {
+                return Err(e.into());
+            }
+
+            let (ipv4_up, ipv6_up) = (ppp.is_ipv4_up(), ppp.is_ipv6_up());
+            if ipv4_up && !was_ipv4_up {
+                on_ipv4_up(ppp.ipv4_status());
+            }
+            if ipv6_up && !was_ipv6_up {
+                on_ipv6_up(ppp.ipv6_status());
+            }
+            (was_ipv4_up, was_ipv6_up) = (ipv4_up, ipv6_up);
+            match ipv4_up || ipv6_up {
+                true => state_chan.set_link_state(LinkState::Up),
+                false => state_chan.set_link_state(LinkState::Down),
+            }
+
             let rx_fut = async {
                 let buf = rx_chan.rx_buf().await;
-                let rx_data = match needs_poll {
-                    true => &[][..],
-                    false => match rw.fill_buf().await {
-                        Ok(rx_data) if rx_data.len() == 0 => return Err(RunError::Eof),
-                        Ok(rx_data) => rx_data,
-                        Err(e) => return Err(RunError::Read(e)),
-                    },
-                };
-                Ok((buf, rx_data))
+                match rw.fill_buf().await {
+                    Ok([]) => Err(RunError::Eof),
+                    Ok(rx_data) => Ok((buf, rx_data)),
+                    Err(e) => Err(RunError::Read(e)),
+                }
             };
             let tx_fut = tx_chan.tx_buf();
-            match select(rx_fut, tx_fut).await {
-                Either::First(r) => {
-                    needs_poll = false;
-
+            let timer_fut = Timer::at(ppp.deadline().unwrap_or(Instant::MAX));
+            match select3(rx_fut, tx_fut, timer_fut).await {
+                Either3::First(r) => {
                     let (buf, rx_data) = r?;
-                    let n = ppp.consume(rx_data, &mut rx_buf);
-                    rw.consume(n);
-
-                    match ppp.poll(&mut tx_buf, &mut rx_buf) {
-                        PPPoSAction::None => {}
-                        PPPoSAction::Received(rg) => {
-                            let pkt = &rx_buf[rg];
+                    let (n, frame) = reader.consume(rx_data);
+                    if let Some(frame) = frame {
+                        if let Some(range) = ppp.received(frame, Instant::now(), &mut tx) {
+                            let pkt = &frame[range];
                             buf[..pkt.len()].copy_from_slice(pkt);
                             rx_chan.rx_done(pkt.len());
                         }
-                        PPPoSAction::Transmit(n) => rw.write_all(&tx_buf[..n]).await.map_err(RunError::Write)?,
-                    }
-
-                    let status = ppp.status();
-                    match status.phase {
-                        ppproto::Phase::Dead => {
-                            return Err(RunError::Terminated);
-                        }
-                        ppproto::Phase::Open => {
-                            if !was_up {
-                                on_ipv4_up(status.ipv4.unwrap());
-                            }
-                            was_up = true;
-                            state_chan.set_link_state(LinkState::Up);
-                        }
-                        _ => {
-                            was_up = false;
-                            state_chan.set_link_state(LinkState::Down);
-                        }
                     }
+                    rw.consume(n);
                 }
-                Either::Second(pkt) => {
-                    match ppp.send(pkt, &mut tx_buf) {
-                        Ok(n) => rw.write_all(&tx_buf[..n]).await.map_err(RunError::Write)?,
-                        Err(BufferFullError) => unreachable!(),
-                    }
+                Either3::Second(pkt) => {
+                    ppp.send_ip(pkt, &mut tx);
                     tx_chan.tx_done();
                 }
+                Either3::Third(()) => ppp.poll_timeout(Instant::now(), &mut tx),
             }
         }
     }
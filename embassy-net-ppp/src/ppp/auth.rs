@@ -0,0 +1,390 @@
+//! Authentication with PAP (RFC 1334) and CHAP with MD5 (RFC 1994), in both directions.
+
+use embassy_time::{Duration, Instant};
+use md5::{Digest, Md5};
+use rand_core::RngCore;
+
+use super::packet::PacketWriter;
+use crate::pppos::TxBuf;
+use crate::{AuthProtocol, Credentials};
+
+pub(crate) const PROTOCOL_PAP: u16 = 0xC023;
+pub(crate) const PROTOCOL_CHAP: u16 = 0xC223;
+pub(crate) const CHAP_MD5: u8 = 5;
+
+const PAP_REQUEST: u8 = 1;
+const PAP_ACK: u8 = 2;
+const PAP_NAK: u8 = 3;
+
+const CHAP_CHALLENGE: u8 = 1;
+const CHAP_RESPONSE: u8 = 2;
+const CHAP_SUCCESS: u8 = 3;
+const CHAP_FAILURE: u8 = 4;
+
+/// Name sent in CHAP challenges.
+const CHAP_NAME: &[u8] = b"embassy-net-ppp";
+
+const RESTART_INTERVAL: Duration = Duration::from_secs(3);
+const MAX_REQUESTS: u8 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum State {
+    Pending,
+    Done,
+    Failed,
+}
+
+/// Authentication in one direction.
+struct Side {
+    protocol: Option<AuthProtocol>,
+    state: State,
+    id: u8,
+    /// Requests left to send, or time left to wait for the peer.
+    restarts: u8,
+    deadline: Option<Instant>,
+}
+
+impl Side {
+    fn new() -> Self {
+        Self {
+            protocol: None,
+            state: State::Done,
+            id: 0,
+            restarts: 0,
+            deadline: None,
+        }
+    }
+
+    fn start(&mut self, protocol: Option<AuthProtocol>, now: Instant) {
+        self.protocol = protocol;
+        self.state = match protocol {
+            Some(_) => State::Pending,
+            None => State::Done,
+        };
+        self.restarts = MAX_REQUESTS;
+        self.deadline = protocol.map(|_| now + RESTART_INTERVAL);
+    }
+
+    fn pending(&self, protocol: AuthProtocol) -> bool {
+        self.protocol == Some(protocol) && self.state == State::Pending
+    }
+
+    fn finish(&mut self, ok: bool) {
+        self.state = match ok {
+            true => State::Done,
+            false => State::Failed,
+        };
+        self.deadline = None;
+    }
+}
+
+pub(crate) struct Auth<'a> {
+    username: &'a [u8],
+    password: &'a [u8],
+    secrets: &'a [Credentials<'a>],
+    /// Authenticating ourselves to the peer.
+    local: Side,
+    /// Authenticating the peer.
+    remote: Side,
+    challenge: [u8; 16],
+}
+
+impl<'a> Auth<'a> {
+    pub fn new(username: &'a [u8], password: &'a [u8], secrets: &'a [Credentials<'a>]) -> Self {
+        Self {
+            username,
+            password,
+            secrets,
+            local: Side::new(),
+            remote: Side::new(),
+            challenge: [0; 16],
+        }
+    }
+
+    /// Start authenticating, with the protocols negotiated by LCP.
+    pub fn start(
+        &mut self,
+        local: Option<AuthProtocol>,
+        remote: Option<AuthProtocol>,
+        rng: &mut impl RngCore,
+        now: Instant,
+        tx: &mut TxBuf,
+    ) {
+        self.local.start(local, now);
+        if local == Some(AuthProtocol::Pap) {
+            self.send_pap_request(tx);
+        }
+
+        self.remote.start(remote, now);
+        if remote == Some(AuthProtocol::Chap) {
+            rng.fill_bytes(&mut self.challenge);
+            self.send_chap_challenge(tx);
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.local.state == State::Done && self.remote.state == State::Done
+    }
+
+    pub fn failed(&self) -> bool {
+        self.local.state == State::Failed || self.remote.state == State::Failed
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        match (self.local.deadline, self.remote.deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    pub fn poll_timeout(&mut self, now: Instant, tx: &mut TxBuf) {
+        if self.local.deadline.is_some_and(|d| d <= now) {
+            if self.local.restarts == 0 {
+                warn!("PPP: authentication to the peer timed out");
+                self.local.finish(false);
+            } else {
+                self.local.restarts -= 1;
+                self.local.deadline = Some(now + RESTART_INTERVAL);
+                // With CHAP, the peer retransmits its challenge.
+                if self.local.protocol == Some(AuthProtocol::Pap) {
+                    self.send_pap_request(tx);
+                }
+            }
+        }
+        if self.remote.deadline.is_some_and(|d| d <= now) {
+            if self.remote.restarts == 0 {
+                warn!("PPP: authentication of the peer timed out");
+                self.remote.finish(false);
+            } else {
+                self.remote.restarts -= 1;
+                self.remote.deadline = Some(now + RESTART_INTERVAL);
+                // With PAP, the peer retransmits its request.
+                if self.remote.protocol == Some(AuthProtocol::Chap) {
+                    self.send_chap_challenge(tx);
+                }
+            }
+        }
+    }
+
+    /// Handle a PAP packet.
+    pub fn handle_pap(&mut self, code: u8, id: u8, data: &[u8], tx: &mut TxBuf) {
+        match code {
+            PAP_REQUEST if self.remote.protocol == Some(AuthProtocol::Pap) => {
+                let ok = match parse_pap_request(data) {
+                    Some((username, password)) => self
+                        .secrets
+                        .iter()
+                        .any(|c| c.username == username && c.password == password),
+                    None => false,
+                };
+                // Requests are retransmitted when the answer is lost, answer them again.
+                if self.remote.state == State::Pending {
+                    match ok {
+                        true => info!("PPP: peer authenticated with PAP"),
+                        false => warn!("PPP: peer failed to authenticate with PAP"),
+                    }
+                    self.remote.finish(ok);
+                }
+                let mut w = PacketWriter::new(if ok { PAP_ACK } else { PAP_NAK }, id);
+                w.push(&[0]);
+                w.send(PROTOCOL_PAP, tx);
+            }
+            PAP_ACK | PAP_NAK if self.local.pending(AuthProtocol::Pap) && id == self.local.id => {
+                match code == PAP_ACK {
+                    true => info!("PPP: authenticated with PAP"),
+                    false => warn!("PPP: PAP authentication refused"),
+                }
+                self.local.finish(code == PAP_ACK);
+            }
+            _ => debug!("PPP: ignoring PAP packet {}", code),
+        }
+    }
+
+    /// Handle a CHAP packet.
+    pub fn handle_chap(&mut self, code: u8, id: u8, data: &[u8], tx: &mut TxBuf) {
+        match code {
+            // The peer may challenge again at any time.
+            CHAP_CHALLENGE if self.local.protocol == Some(AuthProtocol::Chap) => {
+                let Some(challenge) = parse_chap_value(data) else {
+                    return;
+                };
+                self.local.id = id;
+                let mut w = PacketWriter::new(CHAP_RESPONSE, id);
+                w.push(&[16]);
+                w.push(&chap_md5(id, self.password, challenge));
+                w.push(self.username);
+                w.send(PROTOCOL_CHAP, tx);
+            }
+            CHAP_RESPONSE if self.remote.protocol == Some(AuthProtocol::Chap) && id == self.remote.id => {
+                let ok = match parse_chap_value(data) {
+                    Some(response) => {
+                        let name = &data[1 + response.len()..];
+                        self.secrets
+                            .iter()
+                            .find(|c| c.username == name)
+                            .is_some_and(|c| chap_md5(id, c.password, &self.challenge) == response)
+                    }
+                    None => false,
+                };
+                if self.remote.state == State::Pending {
+                    match ok {
+                        true => info!("PPP: peer authenticated with CHAP"),
+                        false => warn!("PPP: peer failed to authenticate with CHAP"),
+                    }
+                    self.remote.finish(ok);
+                }
+                let code = match ok {
+                    true => CHAP_SUCCESS,
+                    false => CHAP_FAILURE,
+                };
+                PacketWriter::new(code, id).send(PROTOCOL_CHAP, tx);
+            }
+            CHAP_SUCCESS | CHAP_FAILURE if self.local.pending(AuthProtocol::Chap) && id == self.local.id => {
+                match code == CHAP_SUCCESS {
+                    true => info!("PPP: authenticated with CHAP"),
+                    false => warn!("PPP: CHAP authentication refused"),
+                }
+                self.local.finish(code == CHAP_SUCCESS);
+            }
+            _ => debug!("PPP: ignoring CHAP packet {}", code),
+        }
+    }
+
+    fn send_pap_request(&mut self, tx: &mut TxBuf) {
+        self.local.id = self.local.id.wrapping_add(1);
+        let mut w = PacketWriter::new(PAP_REQUEST, self.local.id);
+        w.push(&[self.username.len() as u8]);
+        w.push(self.username);
+        w.push(&[self.password.len() as u8]);
+        w.push(self.password);
+        w.send(PROTOCOL_PAP, tx);
+    }
+
+    fn send_chap_challenge(&mut self, tx: &mut TxBuf) {
+        self.remote.id = self.remote.id.wrapping_add(1);
+        let mut w = PacketWriter::new(CHAP_CHALLENGE, self.remote.id);
+        w.push(&[self.challenge.len() as u8]);
+        w.push(&self.challenge);
+        w.push(CHAP_NAME);
+        w.send(PROTOCOL_CHAP, tx);
+    }
+}
+
+fn parse_pap_request(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (&len, data) = data.split_first()?;
+    let (username, data) = data.split_at_checked(usize::from(len))?;
+    let (&len, data) = data.split_first()?;
+    let password = data.get(..usize::from(len))?;
+    Some((username, password))
+}
+
+/// Get the value of a CHAP challenge or response.
+fn parse_chap_value(data: &[u8]) -> Option<&[u8]> {
+    let (&len, data) = data.split_first()?;
+    data.get(..usize::from(len))
+}
+
+fn chap_md5(id: u8, secret: &[u8], challenge: &[u8]) -> [u8; 16] {
+    let mut hash = Md5::new();
+    hash.update([id]);
+    hash.update(secret);
+    hash.update(challenge);
+    hash.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppp::packet::parse;
+    use crate::ppp::tests::{take_frames, TestRng};
+
+    const SECRETS: &[Credentials] = &[Credentials {
+        username: b"user",
+        password: b"pass",
+    }];
+
+    /// Hand the packets queued in `from` to `to`.
+    fn deliver(from: &mut TxBuf, to: &mut Auth, tx: &mut TxBuf) {
+        for (protocol, packet) in take_frames(from) {
+            let (code, id, data) = parse(&packet).unwrap();
+            match protocol {
+                PROTOCOL_PAP => to.handle_pap(code, id, data, tx),
+                _ => to.handle_chap(code, id, data, tx),
+            }
+        }
+    }
+
+    /// Authenticate `client` to `server` with `protocol`.
+    fn authenticate(client: &mut Auth, server: &mut Auth, protocol: AuthProtocol) {
+        let now = Instant::from_secs(0);
+        let (mut tx_client, mut tx_server) = (TxBuf::new(), TxBuf::new());
+        client.start(Some(protocol), None, &mut TestRng(1), now, &mut tx_client);
+        server.start(None, Some(protocol), &mut TestRng(2), now, &mut tx_server);
+        for _ in 0..2 {
+            deliver(&mut tx_client, server, &mut tx_server);
+            deliver(&mut tx_server, client, &mut tx_client);
+        }
+    }
+
+    #[test]
+    fn pap() {
+        let mut client = Auth::new(b"user", b"pass", &[]);
+        let mut server = Auth::new(b"", b"", SECRETS);
+        authenticate(&mut client, &mut server, AuthProtocol::Pap);
+        assert!(client.is_done() && server.is_done());
+
+        let mut client = Auth::new(b"user", b"wrong", &[]);
+        let mut server = Auth::new(b"", b"", SECRETS);
+        authenticate(&mut client, &mut server, AuthProtocol::Pap);
+        assert!(client.failed() && server.failed());
+    }
+
+    #[test]
+    fn chap() {
+        let mut client = Auth::new(b"user", b"pass", &[]);
+        let mut server = Auth::new(b"", b"", SECRETS);
+        authenticate(&mut client, &mut server, AuthProtocol::Chap);
+        assert!(client.is_done() && server.is_done());
+
+        let mut client = Auth::new(b"other", b"pass", &[]);
+        let mut server = Auth::new(b"", b"", SECRETS);
+        authenticate(&mut client, &mut server, AuthProtocol::Chap);
+        assert!(client.failed() && server.failed());
+    }
+
+    #[test]
+    fn chap_challenge_is_random() {
+        let challenges = [1, 2].map(|seed| {
+            let mut auth = Auth::new(b"", b"", SECRETS);
+            let mut tx = TxBuf::new();
+            let now = Instant::from_secs(0);
+            auth.start(None, Some(AuthProtocol::Chap), &mut TestRng(seed), now, &mut tx);
+            let (_, packet) = take_frames(&mut tx).remove(0);
+            let (_, _, data) = parse(&packet).unwrap();
+            parse_chap_value(data).unwrap().to_vec()
+        });
+        assert_eq!(challenges[0].len(), 16);
+        assert_ne!(challenges[0], challenges[1]);
+    }
+
+    #[test]
+    fn timeout() {
+        let mut client = Auth::new(b"user", b"pass", &[]);
+        let mut tx = TxBuf::new();
+        client.start(
+            Some(AuthProtocol::Pap),
+            None,
+            &mut TestRng(1),
+            Instant::from_secs(0),
+            &mut tx,
+        );
+        let mut requests = take_frames(&mut tx).len();
+        while let Some(deadline) = client.deadline() {
+            client.poll_timeout(deadline, &mut tx);
+            requests += take_frames(&mut tx).len();
+        }
+        assert!(client.failed());
+        assert_eq!(requests, 1 + usize::from(MAX_REQUESTS));
+    }
+}
@@ -0,0 +1,188 @@
+//! IP Control Protocol (RFC 1332), with DNS server addresses (RFC 1877).
+
+use core::net::Ipv4Addr;
+
+use heapless::Vec;
+
+use super::option_fsm::{Protocol, Verdict};
+use super::packet::PacketWriter;
+use crate::{Ipv4Status, ServerConfig};
+
+const OPT_ADDRESS: u8 = 3;
+const OPT_DNS: [u8; 2] = [129, 131];
+
+pub(crate) struct Ipv4cp {
+    server: Option<ServerConfig>,
+    address: Ipv4Addr,
+    peer_address: Option<Ipv4Addr>,
+    dns_servers: [Option<Ipv4Addr>; 2],
+    dns_rejected: [bool; 2],
+}
+
+impl Ipv4cp {
+    pub fn new(server: Option<ServerConfig>) -> Self {
+        Self {
+            server,
+            address: server.map_or(Ipv4Addr::UNSPECIFIED, |s| s.address),
+            peer_address: None,
+            dns_servers: server.map_or([None; 2], |s| s.dns_servers),
+            dns_rejected: [false; 2],
+        }
+    }
+
+    pub fn status(&self) -> Ipv4Status {
+        Ipv4Status {
+            address: (!self.address.is_unspecified()).then_some(self.address),
+            peer_address: self.peer_address,
+            dns_servers: self.dns_servers,
+        }
+    }
+}
+
+fn parse_address(data: &[u8]) -> Option<Ipv4Addr> {
+    let octets: [u8; 4] = data.try_into().ok()?;
+    Some(Ipv4Addr::from(octets))
+}
+
+fn nak(address: Ipv4Addr) -> Verdict {
+    Verdict::Nak(unwrap!(Vec::from_slice(&address.octets())))
+}
+
+impl Protocol for Ipv4cp {
+    const PROTOCOL: u16 = 0x8021;
+    const NAME: &'static str = "IPCP";
+
+    fn own_options(&mut self, w: &mut PacketWriter) {
+        w.option(OPT_ADDRESS, &self.address.octets());
+        // Clients ask for DNS servers, with an unspecified address the server naks.
+        if self.server.is_none() {
+            for ((kind, dns), rejected) in OPT_DNS.into_iter().zip(self.dns_servers).zip(self.dns_rejected) {
+                if !rejected {
+                    w.option(kind, &dns.unwrap_or(Ipv4Addr::UNSPECIFIED).octets());
+                }
+            }
+        }
+    }
+
+    fn own_option_nacked(&mut self, kind: u8, data: &[u8], rejected: bool) {
+        if kind == OPT_ADDRESS {
+            if self.server.is_some() || rejected {
+                warn!("PPP: IPCP address refused by peer");
+            } else if let Some(address) = parse_address(data) {
+                self.address = address;
+            }
+        } else if let Some(i) = OPT_DNS.iter().position(|&k| k == kind) {
+            match rejected {
+                true => self.dns_rejected[i] = true,
+                false => self.dns_servers[i] = parse_address(data),
+            }
+        }
+    }
+
+    fn peer_options_start(&mut self) {
+        self.peer_address = None;
+    }
+
+    fn peer_option_received(&mut self, kind: u8, data: &[u8]) -> Verdict {
+        let Some(address) = parse_address(data) else {
+            return Verdict::Rej;
+        };
+        match (kind, &self.server) {
+            (OPT_ADDRESS, Some(server)) if address != server.peer_address => nak(server.peer_address),
+            // We have no address to give to the peer.
+            (OPT_ADDRESS, None) if address.is_unspecified() => Verdict::Rej,
+            (OPT_ADDRESS, _) => {
+                self.peer_address = Some(address);
+                Verdict::Ack
+            }
+            (_, Some(server)) => match OPT_DNS.iter().position(|&k| k == kind) {
+                Some(i) => match server.dns_servers[i] {
+                    Some(dns) if dns == address => Verdict::Ack,
+                    Some(dns) => nak(dns),
+                    None => Verdict::Rej,
+                },
+                None => Verdict::Rej,
+            },
+            _ => Verdict::Rej,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppp::option_fsm::{OptionFsm, State};
+    use crate::ppp::tests::negotiate;
+
+    const SERVER: ServerConfig = ServerConfig {
+        address: Ipv4Addr::new(10, 0, 0, 1),
+        peer_address: Ipv4Addr::new(10, 0, 0, 2),
+        dns_servers: [Some(Ipv4Addr::new(10, 0, 0, 53)), None],
+    };
+
+    #[test]
+    fn client_and_server() {
+        let mut client = OptionFsm::new(Ipv4cp::new(None));
+        let mut server = OptionFsm::new(Ipv4cp::new(Some(SERVER)));
+        negotiate(&mut client, &mut server);
+        assert_eq!((client.state(), server.state()), (State::Opened, State::Opened));
+
+        assert_eq!(
+            client.proto.status(),
+            Ipv4Status {
+                address: Some(SERVER.peer_address),
+                peer_address: Some(SERVER.address),
+                dns_servers: SERVER.dns_servers,
+            }
+        );
+        assert_eq!(
+            server.proto.status(),
+            Ipv4Status {
+                address: Some(SERVER.address),
+                peer_address: Some(SERVER.peer_address),
+                dns_servers: SERVER.dns_servers,
+            }
+        );
+    }
+
+    #[test]
+    fn server_assigns_address_and_dns() {
+        let mut server = Ipv4cp::new(Some(SERVER));
+        server.peer_options_start();
+        match server.peer_option_received(OPT_ADDRESS, &[192, 168, 0, 7]) {
+            Verdict::Nak(value) => assert_eq!(&value, &SERVER.peer_address.octets()),
+            _ => panic!("expected a Nak"),
+        }
+        assert!(matches!(
+            server.peer_option_received(OPT_ADDRESS, &SERVER.peer_address.octets()),
+            Verdict::Ack
+        ));
+        match server.peer_option_received(OPT_DNS[0], &[0; 4]) {
+            Verdict::Nak(value) => assert_eq!(&value, &[10, 0, 0, 53]),
+            _ => panic!("expected a Nak"),
+        }
+        // There's no secondary DNS server to give.
+        assert!(matches!(server.peer_option_received(OPT_DNS[1], &[0; 4]), Verdict::Rej));
+    }
+
+    #[test]
+    fn client() {
+        let mut client = Ipv4cp::new(None);
+        // The peer must know its own address.
+        assert!(matches!(
+            client.peer_option_received(OPT_ADDRESS, &[0; 4]),
+            Verdict::Rej
+        ));
+        assert!(matches!(
+            client.peer_option_received(OPT_DNS[0], &[8, 8, 8, 8]),
+            Verdict::Rej
+        ));
+
+        client.own_option_nacked(OPT_ADDRESS, &[10, 0, 0, 2], false);
+        client.own_option_nacked(OPT_DNS[0], &[10, 0, 0, 53], false);
+        client.own_option_nacked(OPT_DNS[1], &[], true);
+        assert_eq!(client.status().address, Some(Ipv4Addr::new(10, 0, 0, 2)));
+        assert_eq!(client.status().dns_servers, [Some(Ipv4Addr::new(10, 0, 0, 53)), None]);
+        assert_eq!(client.dns_rejected, [false, true]);
+    }
+}
@@ -0,0 +1,155 @@
+//! IPv6 Control Protocol (RFC 5072).
+
+use core::net::Ipv6Addr;
+
+use heapless::Vec;
+
+use super::option_fsm::{Protocol, Verdict};
+use super::packet::PacketWriter;
+use crate::Ipv6Status;
+
+const OPT_INTERFACE_ID: u8 = 1;
+
+pub(crate) struct Ipv6cp {
+    interface_id: [u8; 8],
+    peer_interface_id: Option<[u8; 8]>,
+    /// Interface identifier suggested to the peer, when it has none or the same as ours.
+    pub spare_interface_id: [u8; 8],
+}
+
+impl Ipv6cp {
+    pub fn new(interface_id: [u8; 8], spare_interface_id: [u8; 8]) -> Self {
+        Self {
+            interface_id,
+            peer_interface_id: None,
+            spare_interface_id,
+        }
+    }
+
+    pub fn status(&self) -> Ipv6Status {
+        Ipv6Status {
+            address: link_local(self.interface_id),
+            peer_address: self.peer_interface_id.map(link_local),
+        }
+    }
+}
+
+fn link_local(interface_id: [u8; 8]) -> Ipv6Addr {
+    let mut octets = [0; 16];
+    octets[..2].copy_from_slice(&[0xfe, 0x80]);
+    octets[8..].copy_from_slice(&interface_id);
+    Ipv6Addr::from(octets)
+}
+
+impl Protocol for Ipv6cp {
+    const PROTOCOL: u16 = 0x8057;
+    const NAME: &'static str = "IPV6CP";
+
+    fn own_options(&mut self, w: &mut PacketWriter) {
+        w.option(OPT_INTERFACE_ID, &self.interface_id);
+    }
+
+    fn own_option_nacked(&mut self, kind: u8, data: &[u8], rejected: bool) {
+        if kind != OPT_INTERFACE_ID {
+            return;
+        }
+        match <[u8; 8]>::try_from(data) {
+            Ok(id) if !rejected && id != [0; 8] => self.interface_id = id,
+            _ => warn!("PPP: IPV6CP interface identifier refused by peer"),
+        }
+    }
+
+    fn peer_options_start(&mut self) {
+        self.peer_interface_id = None;
+    }
+
+    fn peer_option_received(&mut self, kind: u8, data: &[u8]) -> Verdict {
+        let Ok(id) = <[u8; 8]>::try_from(data) else {
+            return Verdict::Rej;
+        };
+        if kind != OPT_INTERFACE_ID {
+            return Verdict::Rej;
+        }
+        if id == [0; 8] || id == self.interface_id {
+            if self.spare_interface_id == self.interface_id {
+                self.spare_interface_id[7] ^= 1;
+            }
+            return Verdict::Nak(unwrap!(Vec::from_slice(&self.spare_interface_id)));
+        }
+        self.peer_interface_id = Some(id);
+        Verdict::Ack
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppp::option_fsm::{OptionFsm, State};
+    use crate::ppp::tests::negotiate;
+
+    const ID_A: [u8; 8] = [0x02, 0, 0, 0, 0, 0, 0, 0x0a];
+    const ID_B: [u8; 8] = [0x02, 0, 0, 0, 0, 0, 0, 0x0b];
+
+    fn nak_value(verdict: Verdict) -> [u8; 8] {
+        match verdict {
+            Verdict::Nak(value) => value.as_slice().try_into().unwrap(),
+            _ => panic!("expected a Nak"),
+        }
+    }
+
+    #[test]
+    fn peer_interface_id() {
+        let mut ipv6cp = Ipv6cp::new(ID_A, ID_B);
+        ipv6cp.peer_options_start();
+        assert!(matches!(
+            ipv6cp.peer_option_received(OPT_INTERFACE_ID, &ID_B),
+            Verdict::Ack
+        ));
+        assert_eq!(ipv6cp.status().address, "fe80::200:0:0:a".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(ipv6cp.status().peer_address, Some("fe80::200:0:0:b".parse().unwrap()));
+
+        // Malformed and unknown options are rejected.
+        assert!(matches!(
+            ipv6cp.peer_option_received(OPT_INTERFACE_ID, &[1, 2]),
+            Verdict::Rej
+        ));
+        assert!(matches!(ipv6cp.peer_option_received(2, &ID_B), Verdict::Rej));
+    }
+
+    #[test]
+    fn peer_without_or_with_our_interface_id() {
+        let mut ipv6cp = Ipv6cp::new(ID_A, ID_B);
+        assert_eq!(nak_value(ipv6cp.peer_option_received(OPT_INTERFACE_ID, &[0; 8])), ID_B);
+        assert_eq!(nak_value(ipv6cp.peer_option_received(OPT_INTERFACE_ID, &ID_A)), ID_B);
+        assert_eq!(ipv6cp.status().peer_address, None);
+
+        // The spare identifier is never ours.
+        let mut ipv6cp = Ipv6cp::new(ID_A, ID_A);
+        assert_ne!(nak_value(ipv6cp.peer_option_received(OPT_INTERFACE_ID, &ID_A)), ID_A);
+    }
+
+    #[test]
+    fn own_interface_id_nacked() {
+        let mut ipv6cp = Ipv6cp::new(ID_A, ID_B);
+        ipv6cp.own_option_nacked(OPT_INTERFACE_ID, &ID_B, false);
+        assert_eq!(ipv6cp.interface_id, ID_B);
+
+        // Zero and rejected identifiers are not usable.
+        ipv6cp.own_option_nacked(OPT_INTERFACE_ID, &[0; 8], false);
+        ipv6cp.own_option_nacked(OPT_INTERFACE_ID, &ID_A, true);
+        assert_eq!(ipv6cp.interface_id, ID_B);
+    }
+
+    #[test]
+    fn negotiation_with_same_interface_id() {
+        let mut a = OptionFsm::new(Ipv6cp::new(ID_A, [0x02, 0, 0, 0, 0, 0, 0, 0x0c]));
+        let mut b = OptionFsm::new(Ipv6cp::new(ID_A, [0x02, 0, 0, 0, 0, 0, 0, 0x0d]));
+        negotiate(&mut a, &mut b);
+
+        assert_eq!((a.state(), b.state()), (State::Opened, State::Opened));
+        let (a, b) = (a.proto.status(), b.proto.status());
+        assert_ne!(a.address, b.address);
+        assert_eq!(a.peer_address, Some(b.address));
+        assert_eq!(b.peer_address, Some(a.address));
+    }
+}
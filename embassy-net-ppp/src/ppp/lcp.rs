@@ -0,0 +1,162 @@
+//! Link Control Protocol (RFC 1661).
+
+use heapless::Vec;
+
+use super::auth::{CHAP_MD5, PROTOCOL_CHAP, PROTOCOL_PAP};
+use super::option_fsm::{Protocol, Verdict};
+use super::packet::PacketWriter;
+use crate::AuthProtocol;
+
+const OPT_MRU: u8 = 1;
+const OPT_ACCM: u8 = 2;
+const OPT_AUTH: u8 = 3;
+const OPT_MAGIC: u8 = 5;
+
+pub(crate) struct Lcp {
+    /// Our magic number, or 0 if the peer rejected it.
+    pub magic: u32,
+    /// Random magic number used when the peer naks ours, because it saw its own.
+    pub next_magic: u32,
+    /// Authentication we require from the peer.
+    pub require_auth: Option<AuthProtocol>,
+    /// The peer refused to authenticate the way we require.
+    pub auth_refused: bool,
+    /// Authentication the peer requires from us.
+    pub peer_auth: Option<AuthProtocol>,
+}
+
+impl Lcp {
+    pub fn new(magic: u32, next_magic: u32, require_auth: Option<AuthProtocol>) -> Self {
+        Self {
+            magic,
+            next_magic,
+            require_auth,
+            auth_refused: false,
+            peer_auth: None,
+        }
+    }
+}
+
+fn auth_option(protocol: AuthProtocol) -> &'static [u8] {
+    const PAP: [u8; 2] = PROTOCOL_PAP.to_be_bytes();
+    const CHAP: [u8; 2] = PROTOCOL_CHAP.to_be_bytes();
+    match protocol {
+        AuthProtocol::Pap => &PAP,
+        AuthProtocol::Chap => &[CHAP[0], CHAP[1], CHAP_MD5],
+    }
+}
+
+impl Protocol for Lcp {
+    const PROTOCOL: u16 = 0xC021;
+    const NAME: &'static str = "LCP";
+
+    fn own_options(&mut self, w: &mut PacketWriter) {
+        if self.magic != 0 {
+            w.option(OPT_MAGIC, &self.magic.to_be_bytes());
+        }
+        if let Some(protocol) = self.require_auth {
+            w.option(OPT_AUTH, auth_option(protocol));
+        }
+    }
+
+    fn own_option_nacked(&mut self, kind: u8, _data: &[u8], rejected: bool) {
+        match kind {
+            OPT_MAGIC if rejected => self.magic = 0,
+            // The peer saw its own magic number, so another one is needed.
+            OPT_MAGIC => self.magic = self.next_magic,
+            OPT_AUTH => self.auth_refused = true,
+            _ => {}
+        }
+    }
+
+    fn peer_options_start(&mut self) {
+        self.peer_auth = None;
+    }
+
+    fn peer_option_received(&mut self, kind: u8, data: &[u8]) -> Verdict {
+        match (kind, data.len()) {
+            (OPT_MRU, 2) | (OPT_ACCM, 4) | (OPT_MAGIC, 4) => Verdict::Ack,
+            (OPT_AUTH, _) => {
+                self.peer_auth = [AuthProtocol::Pap, AuthProtocol::Chap]
+                    .into_iter()
+                    .find(|&p| auth_option(p) == data);
+                match self.peer_auth {
+                    Some(_) => Verdict::Ack,
+                    None => Verdict::Nak(unwrap!(Vec::from_slice(auth_option(AuthProtocol::Chap)))),
+                }
+            }
+            _ => Verdict::Rej,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppp::packet::options;
+
+    fn own_options(lcp: &mut Lcp) -> std::vec::Vec<(u8, std::vec::Vec<u8>)> {
+        let mut w = PacketWriter::new(1, 1);
+        lcp.own_options(&mut w);
+        let mut tx = crate::pppos::TxBuf::new();
+        w.send(Lcp::PROTOCOL, &mut tx);
+        let (_, packet) = crate::ppp::tests::take_frames(&mut tx).remove(0);
+        options(&packet[4..])
+            .map(|(kind, data)| (kind, data.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn magic_nacked_or_rejected() {
+        let mut lcp = Lcp::new(1, 2, None);
+        assert_eq!(own_options(&mut lcp), [(OPT_MAGIC, 1u32.to_be_bytes().to_vec())]);
+
+        lcp.own_option_nacked(OPT_MAGIC, &1u32.to_be_bytes(), false);
+        assert_eq!(lcp.magic, 2);
+
+        lcp.own_option_nacked(OPT_MAGIC, &[], true);
+        assert!(own_options(&mut lcp).is_empty());
+    }
+
+    #[test]
+    fn required_auth() {
+        let mut lcp = Lcp::new(1, 2, Some(AuthProtocol::Chap));
+        let options = own_options(&mut lcp);
+        assert_eq!(options[1], (OPT_AUTH, std::vec![0xC2, 0x23, CHAP_MD5]));
+
+        lcp.own_option_nacked(OPT_AUTH, &[0xC0, 0x23], false);
+        assert!(lcp.auth_refused);
+    }
+
+    #[test]
+    fn peer_auth() {
+        let mut lcp = Lcp::new(1, 2, None);
+        lcp.peer_options_start();
+        assert!(matches!(
+            lcp.peer_option_received(OPT_AUTH, &[0xC0, 0x23]),
+            Verdict::Ack
+        ));
+        assert_eq!(lcp.peer_auth, Some(AuthProtocol::Pap));
+
+        // CHAP with another algorithm than MD5 is nak'ed.
+        lcp.peer_options_start();
+        match lcp.peer_option_received(OPT_AUTH, &[0xC2, 0x23, 0x80]) {
+            Verdict::Nak(value) => assert_eq!(&value, &[0xC2, 0x23, CHAP_MD5]),
+            _ => panic!("expected a Nak"),
+        }
+        assert_eq!(lcp.peer_auth, None);
+    }
+
+    #[test]
+    fn peer_options() {
+        let mut lcp = Lcp::new(1, 2, None);
+        assert!(matches!(lcp.peer_option_received(OPT_MRU, &[5, 220]), Verdict::Ack));
+        assert!(matches!(
+            lcp.peer_option_received(OPT_MAGIC, &[1, 2, 3, 4]),
+            Verdict::Ack
+        ));
+        assert!(matches!(lcp.peer_option_received(OPT_MAGIC, &[1, 2]), Verdict::Rej));
+        // Protocol field compression.
+        assert!(matches!(lcp.peer_option_received(7, &[]), Verdict::Rej));
+    }
+}
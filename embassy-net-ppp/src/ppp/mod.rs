@@ -0,0 +1,539 @@
+//! PPP state machines, independent of the serial port (RFC 1661).
+
+use core::ops::Range;
+
+use embassy_time::Instant;
+use rand_core::RngCore;
+
+mod auth;
+mod ipv4cp;
+mod ipv6cp;
+mod lcp;
+mod option_fsm;
+mod packet;
+
+use auth::{Auth, PROTOCOL_CHAP, PROTOCOL_PAP};
+use ipv4cp::Ipv4cp;
+use ipv6cp::Ipv6cp;
+use lcp::Lcp;
+use option_fsm::{OptionFsm, Protocol, State};
+use packet::{parse, PacketWriter};
+
+use crate::pppos::TxBuf;
+use crate::{Config, Ipv4Status, Ipv6Status};
+
+const PROTOCOL_IPV4: u16 = 0x0021;
+const PROTOCOL_IPV6: u16 = 0x0057;
+
+const LCP_PROTOCOL_REJ: u8 = 8;
+const LCP_ECHO_REQ: u8 = 9;
+const LCP_ECHO_REPLY: u8 = 10;
+const LCP_DISCARD_REQ: u8 = 11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum Phase {
+    Dead,
+    Establish,
+    Auth,
+    Network,
+}
+
+/// Reason for the link going down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Error {
+    Terminated,
+    AuthFailed,
+    DeadPeer,
+}
+
+/// A random IPv6 interface identifier, never zero.
+fn interface_id(rng: &mut impl RngCore) -> [u8; 8] {
+    let mut id = [0; 8];
+    while id == [0; 8] {
+        rng.fill_bytes(&mut id);
+    }
+    id
+}
+
+/// A random LCP magic number, never zero.
+fn magic(rng: &mut impl RngCore) -> u32 {
+    loop {
+        match rng.next_u32() {
+            0 => {}
+            magic => return magic,
+        }
+    }
+}
+
+pub(crate) struct Ppp<'a, R> {
+    config: &'a Config<'a>,
+    phase: Phase,
+    rng: R,
+    lcp: OptionFsm<Lcp>,
+    auth: Auth<'a>,
+    ipv4cp: OptionFsm<Ipv4cp>,
+    ipv6cp: OptionFsm<Ipv6cp>,
+    error: Option<Error>,
+    /// When a frame was last received, to detect dead peers.
+    last_rx: Instant,
+    next_echo: Option<Instant>,
+    lcp_id: u8,
+}
+
+impl<'a, R: RngCore> Ppp<'a, R> {
+    pub fn new(config: &'a Config<'a>, mut rng: R, now: Instant) -> Self {
+        let lcp = Lcp::new(magic(&mut rng), magic(&mut rng), config.require_auth);
+        let ipv6cp = Ipv6cp::new(interface_id(&mut rng), interface_id(&mut rng));
+        Self {
+            config,
+            phase: Phase::Dead,
+            rng,
+            lcp: OptionFsm::new(lcp),
+            auth: Auth::new(config.username, config.password, config.secrets),
+            ipv4cp: OptionFsm::new(Ipv4cp::new(config.server)),
+            ipv6cp: OptionFsm::new(ipv6cp),
+            error: None,
+            last_rx: now,
+            next_echo: None,
+            lcp_id: 0,
+        }
+    }
+
+    /// Start establishing the link.
+    pub fn open(&mut self, now: Instant, tx: &mut TxBuf) {
+        self.phase = Phase::Establish;
+        self.lcp.open(now, tx);
+    }
+
+    /// Get the reason why the link went down.
+    pub fn error(&self) -> Option<Error> {
+        self.error
+    }
+
+    pub fn is_ipv4_up(&self) -> bool {
+        self.phase == Phase::Network && self.ipv4cp.state() == State::Opened
+    }
+
+    pub fn is_ipv6_up(&self) -> bool {
+        self.phase == Phase::Network && self.ipv6cp.state() == State::Opened
+    }
+
+    pub fn ipv4_status(&self) -> Ipv4Status {
+        self.ipv4cp.proto.status()
+    }
+
+    pub fn ipv6_status(&self) -> Ipv6Status {
+        self.ipv6cp.proto.status()
+    }
+
+    /// Get when [`poll_timeout`](Self::poll_timeout) must be called next.
+    pub fn deadline(&self) -> Option<Instant> {
+        [
+            self.lcp.deadline(),
+            self.auth.deadline(),
+            self.ipv4cp.deadline(),
+            self.ipv6cp.deadline(),
+            self.next_echo,
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    /// Handle a received frame, starting with the protocol field.
+    ///
+    /// Returns the range of the IP packet in the frame, if it carries one.
+    pub fn received(&mut self, frame: &[u8], now: Instant, tx: &mut TxBuf) -> Option<Range<usize>> {
+        self.last_rx = now;
+
+        // The protocol field may be compressed, if the peer ignored our rejection.
+        let (protocol, start) = match frame[0] & 1 {
+            1 => (u16::from(frame[0]), 1),
+            _ => (u16::from_be_bytes([frame[0], *frame.get(1)?]), 2),
+        };
+        let data = &frame[start..];
+
+        // Packets of protocols not started yet are silently discarded.
+        match protocol {
+            PROTOCOL_IPV4 if self.is_ipv4_up() => return Some(start..frame.len()),
+            PROTOCOL_IPV6 if self.is_ipv6_up() => return Some(start..frame.len()),
+            PROTOCOL_IPV4 | PROTOCOL_IPV6 => {}
+            Lcp::PROTOCOL => self.received_lcp(data, now, tx),
+            PROTOCOL_PAP | PROTOCOL_CHAP => {
+                if let (Phase::Auth | Phase::Network, Some((code, id, data))) = (self.phase, parse(data)) {
+                    match protocol {
+                        PROTOCOL_PAP => self.auth.handle_pap(code, id, data, tx),
+                        _ => self.auth.handle_chap(code, id, data, tx),
+                    }
+                }
+            }
+            Ipv4cp::PROTOCOL if self.config.ipv4 => {
+                if let (Phase::Network, Some((code, id, data))) = (self.phase, parse(data)) {
+                    self.ipv4cp.handle(code, id, data, now, tx);
+                }
+            }
+            Ipv6cp::PROTOCOL if self.config.ipv6 => {
+                if let (Phase::Network, Some((code, id, data))) = (self.phase, parse(data)) {
+                    self.ipv6cp.handle(code, id, data, now, tx);
+                    // Draw another identifier for the next collision.
+                    self.ipv6cp.proto.spare_interface_id = interface_id(&mut self.rng);
+                }
+            }
+            _ if self.lcp.state() == State::Opened => {
+                debug!("PPP: rejecting protocol {:04x}", protocol);
+                let mut w = self.lcp_packet(LCP_PROTOCOL_REJ);
+                w.push(&protocol.to_be_bytes());
+                w.push(data);
+                w.send(Lcp::PROTOCOL, tx);
+            }
+            _ => {}
+        }
+
+        self.update(now, tx);
+        None
+    }
+
+    /// Send an IP packet.
+    pub fn send_ip(&mut self, packet: &[u8], tx: &mut TxBuf) {
+        let protocol = match packet.first().map(|b| b >> 4) {
+            Some(4) if self.is_ipv4_up() => PROTOCOL_IPV4,
+            Some(6) if self.is_ipv6_up() => PROTOCOL_IPV6,
+            _ => return,
+        };
+        if !tx.frame(protocol, packet) {
+            warn!("PPP: tx buffer full, packet dropped");
+        }
+    }
+
+    /// Handle timeouts.
+    pub fn poll_timeout(&mut self, now: Instant, tx: &mut TxBuf) {
+        self.lcp.poll_timeout(now, tx);
+        self.auth.poll_timeout(now, tx);
+        self.ipv4cp.poll_timeout(now, tx);
+        self.ipv6cp.poll_timeout(now, tx);
+
+        if let (Some(next_echo), Some(interval)) = (self.next_echo, self.config.echo_interval) {
+            if next_echo <= now {
+                if now - self.last_rx >= self.config.dead_peer_timeout {
+                    warn!("PPP: peer is dead");
+                    return self.fail(Error::DeadPeer, now, tx);
+                }
+                let mut w = self.lcp_packet(LCP_ECHO_REQ);
+                w.push(&self.lcp.proto.magic.to_be_bytes());
+                w.send(Lcp::PROTOCOL, tx);
+                self.next_echo = Some(now + interval);
+            }
+        }
+
+        self.update(now, tx);
+    }
+
+    fn received_lcp(&mut self, data: &[u8], now: Instant, tx: &mut TxBuf) {
+        let Some((code, id, data)) = parse(data) else {
+            return;
+        };
+        match code {
+            LCP_ECHO_REQ if self.lcp.state() == State::Opened => {
+                let mut w = PacketWriter::new(LCP_ECHO_REPLY, id);
+                w.push(&self.lcp.proto.magic.to_be_bytes());
+                w.push(data.get(4..).unwrap_or_default());
+                w.send(Lcp::PROTOCOL, tx);
+            }
+            // Receiving them is enough to know the peer is alive.
+            LCP_ECHO_REQ | LCP_ECHO_REPLY | LCP_DISCARD_REQ => {}
+            LCP_PROTOCOL_REJ => match data.get(..2).map(|p| u16::from_be_bytes([p[0], p[1]])) {
+                // The peer doesn't do IPv4 or IPv6, carry on with the other one.
+                Some(Ipv4cp::PROTOCOL) => self.ipv4cp.reset(),
+                Some(Ipv6cp::PROTOCOL) => self.ipv6cp.reset(),
+                _ => debug!("PPP: protocol rejected by peer"),
+            },
+            _ => {
+                self.lcp.handle(code, id, data, now, tx);
+                // Draw another magic number for the next Nak (RFC 1661 section 6.4).
+                self.lcp.proto.next_magic = magic(&mut self.rng);
+            }
+        }
+    }
+
+    fn lcp_packet(&mut self, code: u8) -> PacketWriter {
+        self.lcp_id = self.lcp_id.wrapping_add(1);
+        PacketWriter::new(code, self.lcp_id)
+    }
+
+    /// Move between phases, following the state of the protocols.
+    fn update(&mut self, now: Instant, tx: &mut TxBuf) {
+        if self.phase == Phase::Dead {
+            return;
+        }
+        if self.lcp.state() == State::Closed {
+            info!("PPP: link terminated");
+            self.phase = Phase::Dead;
+            self.error.get_or_insert(Error::Terminated);
+            return;
+        }
+        if self.lcp.proto.auth_refused || self.auth.failed() {
+            return self.fail(Error::AuthFailed, now, tx);
+        }
+        if self.lcp.state() != State::Opened {
+            if self.phase > Phase::Establish {
+                debug!("PPP: link renegotiating");
+                self.phase = Phase::Establish;
+                self.ipv4cp.reset();
+                self.ipv6cp.reset();
+                self.next_echo = None;
+            }
+            return;
+        }
+
+        if self.phase == Phase::Establish {
+            debug!("PPP: link established");
+            self.phase = Phase::Auth;
+            self.next_echo = self.config.echo_interval.map(|interval| now + interval);
+            let (local, remote) = (self.lcp.proto.peer_auth, self.lcp.proto.require_auth);
+            self.auth.start(local, remote, &mut self.rng, now, tx);
+        }
+        if self.phase == Phase::Auth && self.auth.is_done() {
+            self.phase = Phase::Network;
+            if self.config.ipv4 {
+                self.ipv4cp.open(now, tx);
+            }
+            if self.config.ipv6 {
+                self.ipv6cp.open(now, tx);
+            }
+        }
+        // The link is useless once all network protocols failed.
+        if self.phase == Phase::Network && self.ipv4cp.state() == State::Closed && self.ipv6cp.state() == State::Closed
+        {
+            self.fail(Error::Terminated, now, tx);
+        }
+    }
+
+    /// Terminate the link because of `error`.
+    fn fail(&mut self, error: Error, now: Instant, tx: &mut TxBuf) {
+        self.error.get_or_insert(error);
+        self.ipv4cp.reset();
+        self.ipv6cp.reset();
+        self.lcp.close(now, tx);
+        self.phase = Phase::Dead;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use core::net::Ipv4Addr;
+
+    use super::*;
+    use crate::pppos::FrameReader;
+    use crate::{AuthProtocol, Credentials, ServerConfig};
+
+    /// SplitMix64, deterministic so that tests are reproducible.
+    pub(crate) struct TestRng(pub u64);
+
+    impl RngCore for TestRng {
+        fn next_u32(&mut self) -> u32 {
+            self.next_u64() as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = self.0;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            rand_core::impls::fill_bytes_via_next(self, dest)
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    /// Decode the frames queued in `tx` into their protocol and content, and clear it.
+    pub(crate) fn take_frames(tx: &mut TxBuf) -> Vec<(u16, Vec<u8>)> {
+        let mut reader = FrameReader::new();
+        let mut data = tx.data();
+        let mut frames = Vec::new();
+        while !data.is_empty() {
+            let (n, frame) = reader.consume(data);
+            if let Some(frame) = frame {
+                frames.push((u16::from_be_bytes([frame[0], frame[1]]), frame[2..].to_vec()));
+            }
+            data = &data[n..];
+        }
+        tx.clear();
+        frames
+    }
+
+    /// Open two automatons and exchange their packets until they are done.
+    pub(crate) fn negotiate<P: Protocol>(a: &mut OptionFsm<P>, b: &mut OptionFsm<P>) {
+        let now = Instant::from_secs(0);
+        let (mut tx_a, mut tx_b) = (TxBuf::new(), TxBuf::new());
+        a.open(now, &mut tx_a);
+        b.open(now, &mut tx_b);
+        for _ in 0..20 {
+            let (frames_a, frames_b) = (take_frames(&mut tx_a), take_frames(&mut tx_b));
+            if frames_a.is_empty() && frames_b.is_empty() {
+                return;
+            }
+            for (_, packet) in frames_a {
+                let (code, id, data) = parse(&packet).unwrap();
+                b.handle(code, id, data, now, &mut tx_b);
+            }
+            for (_, packet) in frames_b {
+                let (code, id, data) = parse(&packet).unwrap();
+                a.handle(code, id, data, now, &mut tx_a);
+            }
+        }
+        panic!("{} negotiation doesn't settle", P::NAME);
+    }
+
+    /// Exchange the frames of two links until they are quiet, at time `now`.
+    fn exchange<R: RngCore>(a: &mut Ppp<'_, R>, b: &mut Ppp<'_, R>, tx_a: &mut TxBuf, tx_b: &mut TxBuf, now: Instant) {
+        for _ in 0..50 {
+            let (frames_a, frames_b) = (take_frames(tx_a), take_frames(tx_b));
+            if frames_a.is_empty() && frames_b.is_empty() {
+                return;
+            }
+            for (protocol, data) in frames_a {
+                b.received(&[&protocol.to_be_bytes()[..], &data].concat(), now, tx_b);
+            }
+            for (protocol, data) in frames_b {
+                a.received(&[&protocol.to_be_bytes()[..], &data].concat(), now, tx_a);
+            }
+        }
+        panic!("PPP negotiation doesn't settle");
+    }
+
+    const SECRETS: &[Credentials] = &[Credentials {
+        username: b"user",
+        password: b"pass",
+    }];
+
+    fn server_config() -> Config<'static> {
+        Config {
+            require_auth: Some(AuthProtocol::Chap),
+            secrets: SECRETS,
+            server: Some(ServerConfig {
+                address: Ipv4Addr::new(10, 0, 0, 1),
+                peer_address: Ipv4Addr::new(10, 0, 0, 2),
+                dns_servers: [Some(Ipv4Addr::new(10, 0, 0, 53)), None],
+            }),
+            ipv6: true,
+            ..Default::default()
+        }
+    }
+
+    fn client_config(password: &[u8]) -> Config<'_> {
+        Config {
+            username: b"user",
+            password,
+            ipv6: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn client_and_server() {
+        let (server_config, client_config) = (server_config(), client_config(b"pass"));
+        let now = Instant::from_secs(0);
+        let mut server = Ppp::new(&server_config, TestRng(1), now);
+        let mut client = Ppp::new(&client_config, TestRng(2), now);
+        let (mut tx_server, mut tx_client) = (TxBuf::new(), TxBuf::new());
+        server.open(now, &mut tx_server);
+        client.open(now, &mut tx_client);
+        exchange(&mut server, &mut client, &mut tx_server, &mut tx_client, now);
+
+        assert!(server.is_ipv4_up() && client.is_ipv4_up());
+        assert!(server.is_ipv6_up() && client.is_ipv6_up());
+        let ipv4 = client.ipv4_status();
+        assert_eq!(ipv4.address, Some(Ipv4Addr::new(10, 0, 0, 2)));
+        assert_eq!(ipv4.peer_address, Some(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(ipv4.dns_servers, [Some(Ipv4Addr::new(10, 0, 0, 53)), None]);
+        assert_eq!(server.ipv4_status().peer_address, ipv4.address);
+        assert_eq!(server.ipv6_status().peer_address, Some(client.ipv6_status().address));
+
+        // IP packets go through once the network protocols are up.
+        let packet = [0x45, 0, 0, 20];
+        client.send_ip(&packet, &mut tx_client);
+        let (protocol, data) = take_frames(&mut tx_client).remove(0);
+        let frame = [&protocol.to_be_bytes()[..], &data].concat();
+        let range = server.received(&frame, now, &mut tx_server).unwrap();
+        assert_eq!(&frame[range], &packet);
+    }
+
+    #[test]
+    fn wrong_password() {
+        let (server_config, client_config) = (server_config(), client_config(b"wrong"));
+        let now = Instant::from_secs(0);
+        let mut server = Ppp::new(&server_config, TestRng(1), now);
+        let mut client = Ppp::new(&client_config, TestRng(2), now);
+        let (mut tx_server, mut tx_client) = (TxBuf::new(), TxBuf::new());
+        server.open(now, &mut tx_server);
+        client.open(now, &mut tx_client);
+        exchange(&mut server, &mut client, &mut tx_server, &mut tx_client, now);
+
+        assert_eq!(server.error(), Some(Error::AuthFailed));
+        assert_eq!(client.error(), Some(Error::AuthFailed));
+        assert!(!client.is_ipv4_up());
+    }
+
+    #[test]
+    fn dead_peer() {
+        let config = Config {
+            echo_interval: Some(embassy_time::Duration::from_secs(10)),
+            ..client_config(b"pass")
+        };
+        let server_config = server_config();
+        let now = Instant::from_secs(0);
+        let mut server = Ppp::new(&server_config, TestRng(1), now);
+        let mut client = Ppp::new(&config, TestRng(2), now);
+        let (mut tx_server, mut tx_client) = (TxBuf::new(), TxBuf::new());
+        server.open(now, &mut tx_server);
+        client.open(now, &mut tx_client);
+        exchange(&mut server, &mut client, &mut tx_server, &mut tx_client, now);
+
+        // Echo requests are sent until the peer is silent for too long.
+        let mut now = now;
+        while client.error().is_none() {
+            now = client.deadline().unwrap();
+            client.poll_timeout(now, &mut tx_client);
+        }
+        assert_eq!(client.error(), Some(Error::DeadPeer));
+        assert!(now >= Instant::from_secs(30));
+    }
+
+    #[test]
+    fn random_values() {
+        let config = Config::default();
+        let now = Instant::from_secs(0);
+        let a = Ppp::new(&config, TestRng(1), now);
+        let b = Ppp::new(&config, TestRng(2), now);
+        assert_ne!(a.lcp.proto.magic, b.lcp.proto.magic);
+        assert_ne!(a.ipv6cp.proto.status().address, b.ipv6cp.proto.status().address);
+    }
+
+    #[test]
+    fn magic_nacked() {
+        let config = Config::default();
+        let now = Instant::from_secs(0);
+        let mut ppp = Ppp::new(&config, TestRng(1), now);
+        let mut tx = TxBuf::new();
+        ppp.open(now, &mut tx);
+        let (_, request) = take_frames(&mut tx).remove(0);
+        let (magic, next_magic) = (ppp.lcp.proto.magic, ppp.lcp.proto.next_magic);
+
+        // The peer naks our magic number when it's the same as its own.
+        let mut nak = request;
+        nak[0] = 3;
+        ppp.received(&[&Lcp::PROTOCOL.to_be_bytes()[..], &nak].concat(), now, &mut tx);
+        assert_eq!(ppp.lcp.proto.magic, next_magic);
+        assert_ne!(ppp.lcp.proto.magic, magic);
+        // Another random number is ready for the next Nak.
+        assert_ne!(ppp.lcp.proto.next_magic, next_magic);
+    }
+}
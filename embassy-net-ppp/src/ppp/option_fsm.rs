@@ -0,0 +1,369 @@
+//! Option negotiation automaton, shared by LCP and the NCPs (RFC 1661 section 4).
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+use super::packet::{options, PacketWriter};
+use crate::pppos::TxBuf;
+
+const CONFIGURE_REQ: u8 = 1;
+const CONFIGURE_ACK: u8 = 2;
+const CONFIGURE_NAK: u8 = 3;
+const CONFIGURE_REJ: u8 = 4;
+const TERMINATE_REQ: u8 = 5;
+const TERMINATE_ACK: u8 = 6;
+const CODE_REJ: u8 = 7;
+
+const RESTART_INTERVAL: Duration = Duration::from_secs(3);
+const MAX_CONFIGURE: u8 = 10;
+const MAX_TERMINATE: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum State {
+    /// Not negotiating, or negotiation failed.
+    Closed,
+    /// Configure-Request sent.
+    ReqSent,
+    /// Configure-Request sent and acknowledged.
+    AckRcvd,
+    /// Configure-Request sent, and the peer's acknowledged.
+    AckSent,
+    /// Configuration agreed on both sides.
+    Opened,
+    /// Terminate-Request sent.
+    Closing,
+}
+
+/// Answer to a configuration option of the peer.
+pub(crate) enum Verdict {
+    Ack,
+    /// The option is acceptable, with this value instead.
+    Nak(Vec<u8, 8>),
+    /// The option is not acceptable at all.
+    Rej,
+}
+
+/// Configuration options of a protocol.
+pub(crate) trait Protocol {
+    const PROTOCOL: u16;
+    const NAME: &'static str;
+
+    /// Write our options to a Configure-Request.
+    fn own_options(&mut self, w: &mut PacketWriter);
+
+    /// Handle one of our options being nak'ed with another value, or rejected.
+    fn own_option_nacked(&mut self, kind: u8, data: &[u8], rejected: bool);
+
+    /// Start handling a Configure-Request of the peer.
+    fn peer_options_start(&mut self);
+
+    /// Check an option of the peer.
+    fn peer_option_received(&mut self, kind: u8, data: &[u8]) -> Verdict;
+}
+
+pub(crate) struct OptionFsm<P> {
+    pub proto: P,
+    state: State,
+    id: u8,
+    restarts: u8,
+    deadline: Option<Instant>,
+}
+
+impl<P: Protocol> OptionFsm<P> {
+    pub fn new(proto: P) -> Self {
+        Self {
+            proto,
+            state: State::Closed,
+            id: 0,
+            restarts: 0,
+            deadline: None,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Get when the restart timer expires.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Start negotiating.
+    pub fn open(&mut self, now: Instant, tx: &mut TxBuf) {
+        self.restarts = MAX_CONFIGURE;
+        self.send_configure_request(now, tx);
+        self.state = State::ReqSent;
+    }
+
+    /// Terminate the connection.
+    pub fn close(&mut self, now: Instant, tx: &mut TxBuf) {
+        if matches!(self.state, State::Closed | State::Closing) {
+            return;
+        }
+        self.restarts = MAX_TERMINATE;
+        self.send_terminate_request(now, tx);
+        self.state = State::Closing;
+    }
+
+    /// Go back to closed without telling the peer, when the layer below goes down.
+    pub fn reset(&mut self) {
+        self.state = State::Closed;
+        self.deadline = None;
+    }
+
+    pub fn poll_timeout(&mut self, now: Instant, tx: &mut TxBuf) {
+        match self.deadline {
+            Some(deadline) if deadline <= now => {}
+            _ => return,
+        }
+        match self.state {
+            State::ReqSent | State::AckRcvd | State::AckSent if self.restarts > 0 => {
+                self.restarts -= 1;
+                self.send_configure_request(now, tx);
+                if self.state == State::AckRcvd {
+                    self.state = State::ReqSent;
+                }
+            }
+            State::Closing if self.restarts > 0 => {
+                self.restarts -= 1;
+                self.send_terminate_request(now, tx);
+            }
+            State::ReqSent | State::AckRcvd | State::AckSent => {
+                warn!("PPP: {} negotiation timed out", P::NAME);
+                self.reset();
+            }
+            _ => self.reset(),
+        }
+    }
+
+    /// Handle a packet of the protocol.
+    pub fn handle(&mut self, code: u8, id: u8, data: &[u8], now: Instant, tx: &mut TxBuf) {
+        match code {
+            CONFIGURE_REQ => self.received_configure_request(id, data, now, tx),
+            CONFIGURE_ACK if id == self.id => match self.state {
+                State::ReqSent => {
+                    self.state = State::AckRcvd;
+                    self.restarts = MAX_CONFIGURE;
+                }
+                State::AckSent => {
+                    debug!("PPP: {} opened", P::NAME);
+                    self.state = State::Opened;
+                    self.deadline = None;
+                }
+                State::AckRcvd | State::Opened => {
+                    // Crossed packets, start over.
+                    self.send_configure_request(now, tx);
+                    self.state = State::ReqSent;
+                }
+                _ => {}
+            },
+            CONFIGURE_NAK | CONFIGURE_REJ if id == self.id => {
+                if matches!(self.state, State::Closed | State::Closing) {
+                    return;
+                }
+                for (kind, data) in options(data) {
+                    self.proto.own_option_nacked(kind, data, code == CONFIGURE_REJ);
+                }
+                self.send_configure_request(now, tx);
+                if self.state != State::AckSent {
+                    self.state = State::ReqSent;
+                }
+            }
+            CONFIGURE_ACK | CONFIGURE_NAK | CONFIGURE_REJ => {
+                debug!("PPP: {} ignoring answer to an old request", P::NAME);
+            }
+            TERMINATE_REQ => {
+                debug!("PPP: {} terminated by peer", P::NAME);
+                PacketWriter::new(TERMINATE_ACK, id).send(P::PROTOCOL, tx);
+                self.reset();
+            }
+            TERMINATE_ACK => {
+                if self.state == State::Closing {
+                    self.reset();
+                }
+            }
+            CODE_REJ => debug!("PPP: {} code rejected by peer", P::NAME),
+            _ => {
+                self.id = self.id.wrapping_add(1);
+                let mut w = PacketWriter::new(CODE_REJ, self.id);
+                w.push(&[code, id]);
+                w.push(&(data.len() as u16 + 4).to_be_bytes());
+                w.push(data);
+                w.send(P::PROTOCOL, tx);
+            }
+        }
+    }
+
+    fn received_configure_request(&mut self, id: u8, data: &[u8], now: Instant, tx: &mut TxBuf) {
+        match self.state {
+            State::Closed => {
+                PacketWriter::new(TERMINATE_ACK, id).send(P::PROTOCOL, tx);
+                return;
+            }
+            State::Closing => return,
+            State::Opened => {
+                debug!("PPP: {} renegotiating", P::NAME);
+                self.restarts = MAX_CONFIGURE;
+                self.send_configure_request(now, tx);
+                self.state = State::ReqSent;
+            }
+            _ => {}
+        }
+
+        self.proto.peer_options_start();
+        let mut nak = PacketWriter::new(CONFIGURE_NAK, id);
+        let mut rej = PacketWriter::new(CONFIGURE_REJ, id);
+        let (mut nacked, mut rejected) = (false, false);
+        for (kind, option) in options(data) {
+            match self.proto.peer_option_received(kind, option) {
+                Verdict::Ack => {}
+                Verdict::Nak(value) => {
+                    nacked = true;
+                    nak.option(kind, &value);
+                }
+                Verdict::Rej => {
+                    rejected = true;
+                    rej.option(kind, option);
+                }
+            }
+        }
+
+        if rejected {
+            rej.send(P::PROTOCOL, tx);
+        } else if nacked {
+            nak.send(P::PROTOCOL, tx);
+        } else {
+            let mut ack = PacketWriter::new(CONFIGURE_ACK, id);
+            ack.push(data);
+            ack.send(P::PROTOCOL, tx);
+            match self.state {
+                State::ReqSent => self.state = State::AckSent,
+                State::AckRcvd => {
+                    debug!("PPP: {} opened", P::NAME);
+                    self.state = State::Opened;
+                    self.deadline = None;
+                }
+                _ => {}
+            }
+            return;
+        }
+        if self.state == State::AckSent {
+            self.state = State::ReqSent;
+        }
+    }
+
+    fn send_configure_request(&mut self, now: Instant, tx: &mut TxBuf) {
+        self.id = self.id.wrapping_add(1);
+        let mut w = PacketWriter::new(CONFIGURE_REQ, self.id);
+        self.proto.own_options(&mut w);
+        w.send(P::PROTOCOL, tx);
+        self.deadline = Some(now + RESTART_INTERVAL);
+    }
+
+    fn send_terminate_request(&mut self, now: Instant, tx: &mut TxBuf) {
+        self.id = self.id.wrapping_add(1);
+        PacketWriter::new(TERMINATE_REQ, self.id).send(P::PROTOCOL, tx);
+        self.deadline = Some(now + RESTART_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppp::lcp::Lcp;
+    use crate::ppp::packet::parse;
+    use crate::ppp::tests::{negotiate, take_frames};
+
+    fn lcp(magic: u32) -> OptionFsm<Lcp> {
+        OptionFsm::new(Lcp::new(magic, magic + 1, None))
+    }
+
+    /// Get the code and identifier of the only packet in `tx`.
+    fn take_packet(tx: &mut TxBuf) -> (u8, u8) {
+        let frames = take_frames(tx);
+        assert_eq!(frames.len(), 1);
+        let (code, id, _) = parse(&frames[0].1).unwrap();
+        (code, id)
+    }
+
+    #[test]
+    fn negotiation() {
+        let (mut a, mut b) = (lcp(1), lcp(2));
+        negotiate(&mut a, &mut b);
+        assert_eq!((a.state(), b.state()), (State::Opened, State::Opened));
+        assert_eq!(a.deadline(), None);
+    }
+
+    #[test]
+    fn retransmit_then_give_up() {
+        let mut fsm = lcp(1);
+        let mut tx = TxBuf::new();
+        let mut now = Instant::from_secs(0);
+        fsm.open(now, &mut tx);
+        assert_eq!(take_packet(&mut tx), (CONFIGURE_REQ, 1));
+
+        // Nothing happens before the restart timer expires.
+        fsm.poll_timeout(now + Duration::from_secs(1), &mut tx);
+        assert!(tx.data().is_empty());
+
+        for id in 2..=MAX_CONFIGURE + 1 {
+            now = fsm.deadline().unwrap();
+            fsm.poll_timeout(now, &mut tx);
+            assert_eq!(take_packet(&mut tx), (CONFIGURE_REQ, id));
+        }
+        fsm.poll_timeout(fsm.deadline().unwrap(), &mut tx);
+        assert!(tx.data().is_empty());
+        assert_eq!(fsm.state(), State::Closed);
+    }
+
+    #[test]
+    fn terminate() {
+        let (mut a, mut b) = (lcp(1), lcp(2));
+        negotiate(&mut a, &mut b);
+        let now = Instant::from_secs(0);
+        let (mut tx_a, mut tx_b) = (TxBuf::new(), TxBuf::new());
+
+        a.close(now, &mut tx_a);
+        assert_eq!(a.state(), State::Closing);
+        let (code, id) = take_packet(&mut tx_a);
+        assert_eq!(code, TERMINATE_REQ);
+        b.handle(code, id, &[], now, &mut tx_b);
+        assert_eq!(b.state(), State::Closed);
+        assert_eq!(take_packet(&mut tx_b), (TERMINATE_ACK, id));
+        a.handle(TERMINATE_ACK, id, &[], now, &mut tx_a);
+        assert_eq!(a.state(), State::Closed);
+
+        // Requests are answered with Terminate-Ack once closed.
+        b.handle(CONFIGURE_REQ, 7, &[], now, &mut tx_b);
+        assert_eq!(take_packet(&mut tx_b), (TERMINATE_ACK, 7));
+    }
+
+    #[test]
+    fn stale_answer_ignored() {
+        let mut fsm = lcp(1);
+        let mut tx = TxBuf::new();
+        let now = Instant::from_secs(0);
+        fsm.open(now, &mut tx);
+        let (_, id) = take_packet(&mut tx);
+
+        fsm.handle(CONFIGURE_ACK, id.wrapping_sub(1), &[], now, &mut tx);
+        assert_eq!(fsm.state(), State::ReqSent);
+        fsm.handle(CONFIGURE_NAK, id.wrapping_sub(1), &[], now, &mut tx);
+        assert!(tx.data().is_empty());
+        fsm.handle(CONFIGURE_ACK, id, &[], now, &mut tx);
+        assert_eq!(fsm.state(), State::AckRcvd);
+    }
+
+    #[test]
+    fn unknown_code_rejected() {
+        let mut fsm = lcp(1);
+        let mut tx = TxBuf::new();
+        fsm.handle(42, 9, &[1, 2], Instant::from_secs(0), &mut tx);
+        let frames = take_frames(&mut tx);
+        let (code, _, data) = parse(&frames[0].1).unwrap();
+        assert_eq!(code, CODE_REJ);
+        assert_eq!(data, &[42, 9, 0, 6, 1, 2]);
+    }
+}
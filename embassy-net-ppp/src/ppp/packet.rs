@@ -0,0 +1,64 @@
+//! Control packets, shared by LCP, the NCPs and the authentication protocols.
+
+use crate::pppos::TxBuf;
+
+/// Builds a control packet.
+pub(crate) struct PacketWriter {
+    buf: [u8; 256],
+    len: usize,
+}
+
+impl PacketWriter {
+    pub fn new(code: u8, id: u8) -> Self {
+        let mut buf = [0; 256];
+        buf[0] = code;
+        buf[1] = id;
+        Self { buf, len: 4 }
+    }
+
+    /// Append data, truncated if the packet is full.
+    pub fn push(&mut self, data: &[u8]) {
+        let n = data.len().min(self.buf.len() - self.len);
+        self.buf[self.len..][..n].copy_from_slice(&data[..n]);
+        self.len += n;
+    }
+
+    pub fn option(&mut self, kind: u8, data: &[u8]) {
+        self.push(&[kind, data.len() as u8 + 2]);
+        self.push(data);
+    }
+
+    pub fn send(mut self, protocol: u16, tx: &mut TxBuf) {
+        let len = self.len as u16;
+        self.buf[2..4].copy_from_slice(&len.to_be_bytes());
+        if !tx.frame(protocol, &self.buf[..self.len]) {
+            warn!("PPP: tx buffer full, packet dropped");
+        }
+    }
+}
+
+/// Parse a control packet into its code, identifier and data.
+pub(crate) fn parse(packet: &[u8]) -> Option<(u8, u8, &[u8])> {
+    if packet.len() < 4 {
+        return None;
+    }
+    let len = usize::from(u16::from_be_bytes([packet[2], packet[3]]));
+    if len < 4 || len > packet.len() {
+        return None;
+    }
+    // Anything past the length is padding.
+    Some((packet[0], packet[1], &packet[4..len]))
+}
+
+/// Iterate over the type and data of configuration options, stopping at a malformed one.
+pub(crate) fn options(mut data: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    core::iter::from_fn(move || {
+        let len = usize::from(*data.get(1)?);
+        if len < 2 || len > data.len() {
+            return None;
+        }
+        let (option, rest) = data.split_at(len);
+        data = rest;
+        Some((option[0], &option[2..]))
+    })
+}
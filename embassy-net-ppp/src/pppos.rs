@@ -0,0 +1,179 @@
+//! PPP in HDLC-like framing, over a serial port (RFC 1662).
+
+use core::ops::Range;
+
+use crate::MTU;
+
+const FLAG: u8 = 0x7E;
+const ESCAPE: u8 = 0x7D;
+const ADDRESS: u8 = 0xFF;
+const CONTROL: u8 = 0x03;
+
+const FCS_INIT: u16 = 0xFFFF;
+const FCS_GOOD: u16 = 0xF0B8;
+
+/// Length of the longest frame, without flags and escaping: address, control, protocol,
+/// information and FCS.
+const MAX_FRAME_LEN: usize = 2 + 2 + MTU + 2;
+
+/// Size of the transmit buffer, fitting one escaped frame of the longest length.
+const TX_BUF_LEN: usize = 2 * MAX_FRAME_LEN + 2;
+
+fn fcs16(mut fcs: u16, byte: u8) -> u16 {
+    fcs ^= u16::from(byte);
+    for _ in 0..8 {
+        fcs = match fcs & 1 {
+            0 => fcs >> 1,
+            _ => (fcs >> 1) ^ 0x8408,
+        };
+    }
+    fcs
+}
+
+/// Extracts frames from the received bytes.
+pub(crate) struct FrameReader {
+    buf: [u8; MAX_FRAME_LEN],
+    len: usize,
+    escape: bool,
+    overflow: bool,
+}
+
+impl FrameReader {
+    pub fn new() -> Self {
+        Self {
+            buf: [0; MAX_FRAME_LEN],
+            len: 0,
+            escape: false,
+            overflow: false,
+        }
+    }
+
+    /// Consume received bytes, up to the end of a frame.
+    ///
+    /// Returns the number of bytes consumed, and the frame if one was completed, starting with the
+    /// protocol field.
+    pub fn consume(&mut self, data: &[u8]) -> (usize, Option<&mut [u8]>) {
+        for (i, &byte) in data.iter().enumerate() {
+            match byte {
+                FLAG => {
+                    if let Some(range) = self.finish() {
+                        return (i + 1, Some(&mut self.buf[range]));
+                    }
+                }
+                ESCAPE => self.escape = true,
+                // We never negotiate the ACCM, so the peer escapes all control characters:
+                // unescaped ones were inserted by the serial link, and are dropped.
+                0..0x20 => {}
+                _ => {
+                    let byte = match self.escape {
+                        true => byte ^ 0x20,
+                        false => byte,
+                    };
+                    self.escape = false;
+                    match self.buf.get_mut(self.len) {
+                        Some(b) => {
+                            *b = byte;
+                            self.len += 1;
+                        }
+                        None => self.overflow = true,
+                    }
+                }
+            }
+        }
+        (data.len(), None)
+    }
+
+    /// Check the frame received before a flag, and get the range of its content.
+    fn finish(&mut self) -> Option<Range<usize>> {
+        let len = core::mem::replace(&mut self.len, 0);
+        let overflow = core::mem::replace(&mut self.overflow, false);
+        self.escape = false;
+
+        if len == 0 {
+            return None;
+        }
+        if overflow {
+            warn!("PPP: frame too long, dropped");
+            return None;
+        }
+        if len < 4 || self.buf[..len].iter().fold(FCS_INIT, |fcs, &b| fcs16(fcs, b)) != FCS_GOOD {
+            warn!("PPP: bad FCS, frame dropped");
+            return None;
+        }
+
+        // The address and control fields may be compressed, if the peer ignored our rejection.
+        let start = match self.buf[..2] {
+            [ADDRESS, CONTROL] => 2,
+            _ => 0,
+        };
+        let end = len - 2;
+        match end > start {
+            true => Some(start..end),
+            false => None,
+        }
+    }
+}
+
+/// Frames waiting to be written to the serial port.
+pub(crate) struct TxBuf {
+    buf: [u8; TX_BUF_LEN],
+    len: usize,
+}
+
+impl TxBuf {
+    pub fn new() -> Self {
+        Self {
+            buf: [0; TX_BUF_LEN],
+            len: 0,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Append a frame with `protocol` and `info`.
+    ///
+    /// Returns false if it doesn't fit, then nothing is appended.
+    pub fn frame(&mut self, protocol: u16, info: &[u8]) -> bool {
+        let start = self.len;
+        let [protocol_hi, protocol_lo] = protocol.to_be_bytes();
+
+        let mut fcs = FCS_INIT;
+        let mut ok = self.raw(FLAG);
+        for &byte in [ADDRESS, CONTROL, protocol_hi, protocol_lo].iter().chain(info) {
+            fcs = fcs16(fcs, byte);
+            ok = ok && self.escaped(byte);
+        }
+        let [fcs_lo, fcs_hi] = (!fcs).to_le_bytes();
+        ok = ok && self.escaped(fcs_lo) && self.escaped(fcs_hi) && self.raw(FLAG);
+
+        if !ok {
+            self.len = start;
+        }
+        ok
+    }
+
+    fn raw(&mut self, byte: u8) -> bool {
+        match self.buf.get_mut(self.len) {
+            Some(b) => {
+                *b = byte;
+                self.len += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn escaped(&mut self, byte: u8) -> bool {
+        // Escaping all control characters works whatever ACCM the peer asked for.
+        match byte {
+            FLAG | ESCAPE | 0..0x20 => self.raw(ESCAPE) && self.raw(byte ^ 0x20),
+            _ => self.raw(byte),
+        }
+    }
+}
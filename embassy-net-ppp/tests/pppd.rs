@@ -0,0 +1,158 @@
+//! Interoperability with pppd, over a pseudoterminal.
+//!
+//! pppd must be installed, and the test run as root so that it can create its interface.
+
+use std::cell::Cell;
+use std::ffi::CStr;
+use std::io;
+use std::net::Ipv4Addr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::process::{Child, Command};
+
+use embassy_futures::block_on;
+use embassy_futures::select::{select, Either};
+use embassy_time::{with_timeout, Duration, Timer};
+use rand_core::OsRng;
+
+/// Master side of a pseudoterminal, as a non-blocking serial port.
+struct Pty {
+    fd: OwnedFd,
+    buf: [u8; 1024],
+    start: usize,
+    end: usize,
+}
+
+impl Pty {
+    /// Open a pseudoterminal in raw mode, returning it and the path of its slave side.
+    fn open() -> (Self, String) {
+        let (mut master, mut slave) = (0, 0);
+        let mut name = [0; 64];
+        let r = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                name.as_mut_ptr(),
+                core::ptr::null(),
+                core::ptr::null(),
+            )
+        };
+        assert_eq!(r, 0, "openpty: {}", io::Error::last_os_error());
+        let slave = unsafe { OwnedFd::from_raw_fd(slave) };
+        let fd = unsafe { OwnedFd::from_raw_fd(master) };
+        unsafe {
+            let mut termios = core::mem::zeroed();
+            libc::tcgetattr(slave.as_raw_fd(), &mut termios);
+            libc::cfmakeraw(&mut termios);
+            libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios);
+            libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK);
+        }
+        let name = unsafe { CStr::from_ptr(name.as_ptr()) }.to_str().unwrap().to_owned();
+        // pppd opens the slave side by its path, ours is closed here.
+        let pty = Self {
+            fd,
+            buf: [0; 1024],
+            start: 0,
+            end: 0,
+        };
+        (pty, name)
+    }
+}
+
+impl embedded_io_async::ErrorType for Pty {
+    type Error = io::Error;
+}
+
+impl embedded_io_async::Read for Pty {
+    async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use embedded_io_async::BufRead;
+        let data = self.fill_buf().await?;
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl embedded_io_async::BufRead for Pty {
+    async fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.start == self.end {
+            let n = unsafe { libc::read(self.fd.as_raw_fd(), self.buf.as_mut_ptr().cast(), self.buf.len()) };
+            match n {
+                0.. => (self.start, self.end) = (0, n as usize),
+                _ if io::Error::last_os_error().kind() == io::ErrorKind::WouldBlock => Timer::after_millis(1).await,
+                // The slave side isn't open yet.
+                _ if io::Error::last_os_error().raw_os_error() == Some(libc::EIO) => Timer::after_millis(10).await,
+                _ => return Err(io::Error::last_os_error()),
+            }
+        }
+        Ok(&self.buf[self.start..self.end])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.start += amt;
+    }
+}
+
+impl embedded_io_async::Write for Pty {
+    async fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            let n = unsafe { libc::write(self.fd.as_raw_fd(), buf.as_ptr().cast(), buf.len()) };
+            match n {
+                0.. => return Ok(n as usize),
+                _ if io::Error::last_os_error().kind() == io::ErrorKind::WouldBlock => Timer::after_millis(1).await,
+                _ => return Err(io::Error::last_os_error()),
+            }
+        }
+    }
+}
+
+/// Kills pppd when the test ends.
+struct Pppd(Child);
+
+impl Drop for Pppd {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+#[ignore = "needs pppd, run as root"]
+fn client_of_pppd() {
+    let (pty, tty) = Pty::open();
+    let _pppd = Pppd(
+        Command::new("pppd")
+            .arg(&tty)
+            .args(["nodetach", "noauth", "local", "nocrtscts", "nodefaultroute", "+ipv6"])
+            .args(["192.168.7.1:192.168.7.2", "ms-dns", "192.168.7.53"])
+            .spawn()
+            .expect("failed to start pppd"),
+    );
+
+    let state = Box::leak(Box::new(embassy_net_ppp::State::<4, 4>::new()));
+    let (_device, mut runner) = embassy_net_ppp::new(state);
+    let config = embassy_net_ppp::Config {
+        ipv6: true,
+        ..Default::default()
+    };
+
+    let (ipv4, ipv6) = (Cell::new(None), Cell::new(None));
+    let mut rng = OsRng;
+    let run = runner.run(pty, config, &mut rng, |s| ipv4.set(Some(s)), |s| ipv6.set(Some(s)));
+    let up = with_timeout(Duration::from_secs(20), async {
+        while ipv4.get().is_none() || ipv6.get().is_none() {
+            Timer::after_millis(10).await;
+        }
+    });
+    match block_on(select(run, up)) {
+        Either::First(r) => panic!("PPP failed: {:?}", r),
+        Either::Second(r) => r.expect("PPP negotiation timed out"),
+    }
+
+    let ipv4 = ipv4.get().unwrap();
+    assert_eq!(ipv4.address, Some(Ipv4Addr::new(192, 168, 7, 2)));
+    assert_eq!(ipv4.peer_address, Some(Ipv4Addr::new(192, 168, 7, 1)));
+    assert_eq!(ipv4.dns_servers[0], Some(Ipv4Addr::new(192, 168, 7, 53)));
+    let ipv6 = ipv6.get().unwrap();
+    assert!(ipv6.peer_address.is_some_and(|a| a != ipv6.address));
+}
@@ -0,0 +1,678 @@
+#![cfg_attr(not(test), no_std)]
+#![doc = include_str!("../README.md")]
+#![warn(missing_docs)]
+
+// must go first!
+mod fmt;
+
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::Context;
+
+pub use embassy_net_driver as driver;
+use embassy_net_driver::{Capabilities, HardwareAddress, LinkState};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::waitqueue::WakerRegistration;
+use embassy_time::{Duration, Instant, Timer};
+
+/// Impairments applied to the packets sent in one direction of the link.
+///
+/// The default configuration is a perfect link, delivering all packets right away.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    /// Probability for a packet to be lost, between 0 and 1.
+    pub loss: f32,
+    /// Probability for a packet to be delivered twice, between 0 and 1.
+    pub duplicate: f32,
+    /// Probability for a packet to be delivered without `latency`, overtaking the packets sent
+    /// before it, between 0 and 1.
+    pub reorder: f32,
+    /// Delay before packets are delivered.
+    pub latency: Duration,
+    /// Maximum random variation of `latency`, either way. It reorders packets too.
+    pub jitter: Duration,
+    /// Bandwidth of the link in bits per second, or `None` for unlimited.
+    ///
+    /// Packets wait in the queue until the ones before them are sent, and are dropped when the
+    /// queue is full.
+    pub bandwidth: Option<u32>,
+}
+
+impl Config {
+    const PERFECT: Self = Self {
+        loss: 0.0,
+        duplicate: 0.0,
+        reorder: 0.0,
+        latency: Duration::from_ticks(0),
+        jitter: Duration::from_ticks(0),
+        bandwidth: None,
+    };
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::PERFECT
+    }
+}
+
+/// Direction of the link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    /// Packets sent by device A to device B.
+    AToB = 0,
+    /// Packets sent by device B to device A.
+    BToA = 1,
+}
+
+/// Packet counters of one direction of the link.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Stats {
+    /// Packets transmitted by the sending device.
+    pub sent: u32,
+    /// Packets received by the receiving device, including duplicates.
+    pub delivered: u32,
+    /// Packets lost, following [`Config::loss`].
+    pub lost: u32,
+    /// Packets duplicated, following [`Config::duplicate`].
+    pub duplicated: u32,
+    /// Packets reordered, following [`Config::reorder`].
+    pub reordered: u32,
+    /// Packets dropped because the queue was full or the link was down.
+    pub dropped: u32,
+}
+
+struct Packet<const MTU: usize> {
+    buf: [u8; MTU],
+    len: usize,
+    /// When the packet is delivered.
+    at: Instant,
+    /// Sequence number, keeping packets delivered at the same time in order.
+    seq: u32,
+    used: bool,
+}
+
+impl<const MTU: usize> Packet<MTU> {
+    const fn new() -> Self {
+        Self {
+            buf: [0; MTU],
+            len: 0,
+            at: Instant::from_ticks(0),
+            seq: 0,
+            used: false,
+        }
+    }
+}
+
+/// Packets in flight in one direction.
+struct Queue<const MTU: usize, const N: usize> {
+    packets: [Packet<MTU>; N],
+    config: Config,
+    rng: u64,
+    seq: u32,
+    /// When the link is done sending the packets before, with a bandwidth limit.
+    busy_until: Instant,
+    stats: Stats,
+    waker: WakerRegistration,
+}
+
+impl<const MTU: usize, const N: usize> Queue<MTU, N> {
+    const fn new(seed: u64) -> Self {
+        Self {
+            packets: [const { Packet::new() }; N],
+            config: Config::PERFECT,
+            rng: seed,
+            seq: 0,
+            busy_until: Instant::from_ticks(0),
+            stats: Stats {
+                sent: 0,
+                delivered: 0,
+                lost: 0,
+                duplicated: 0,
+                reordered: 0,
+                dropped: 0,
+            },
+            waker: WakerRegistration::new(),
+        }
+    }
+
+    /// Get a random number between 0 and 1, with xorshift64*.
+    fn random(&mut self) -> f32 {
+        let mut x = self.rng;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng = x;
+        (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 40) as f32 / (1u32 << 24) as f32
+    }
+
+    /// Return whether an event with the given probability happens.
+    fn chance(&mut self, probability: f32) -> bool {
+        probability > 0.0 && self.random() < probability
+    }
+
+    /// Get a random delay between `-max` and `max`, in microseconds.
+    fn jitter(&mut self, max: Duration) -> i64 {
+        if max.as_ticks() == 0 {
+            return 0;
+        }
+        ((self.random() * 2.0 - 1.0) * max.as_micros() as f32) as i64
+    }
+
+    fn send(&mut self, data: &[u8], link_up: bool) {
+        self.stats.sent += 1;
+        if !link_up {
+            self.stats.dropped += 1;
+            return;
+        }
+        if self.chance(self.config.loss) {
+            trace!("sim: packet lost");
+            self.stats.lost += 1;
+            return;
+        }
+        let copies = match self.chance(self.config.duplicate) {
+            true => {
+                self.stats.duplicated += 1;
+                2
+            }
+            false => 1,
+        };
+
+        for _ in 0..copies {
+            let now = Instant::now();
+            let mut at = now;
+            if let Some(bandwidth) = self.config.bandwidth {
+                let start = self.busy_until.max(now);
+                let micros = data.len() as u64 * 8 * 1_000_000 / u64::from(bandwidth.max(1));
+                self.busy_until = start + Duration::from_micros(micros);
+                at = self.busy_until;
+            }
+            if self.chance(self.config.reorder) {
+                self.stats.reordered += 1;
+            } else {
+                let latency = self.config.latency.as_micros() as i64 + self.jitter(self.config.jitter);
+                at += Duration::from_micros(latency.max(0) as u64);
+            }
+
+            let Some(packet) = self.packets.iter_mut().find(|p| !p.used) else {
+                trace!("sim: queue full, packet dropped");
+                self.stats.dropped += 1;
+                continue;
+            };
+            packet.buf[..data.len()].copy_from_slice(data);
+            packet.len = data.len();
+            packet.at = at;
+            packet.seq = self.seq;
+            packet.used = true;
+            self.seq = self.seq.wrapping_add(1);
+        }
+        self.waker.wake();
+    }
+
+    /// Get the index of the next packet to deliver.
+    fn next(&self) -> Option<usize> {
+        let seq = self.seq;
+        (0..N)
+            .filter(|&i| self.packets[i].used)
+            .min_by_key(|&i| (self.packets[i].at, self.packets[i].seq.wrapping_sub(seq)))
+    }
+
+    fn clear(&mut self) {
+        for packet in self.packets.iter_mut().filter(|p| p.used) {
+            packet.used = false;
+            self.stats.dropped += 1;
+        }
+    }
+}
+
+struct Shared<const MTU: usize, const N: usize> {
+    queues: [Queue<MTU, N>; 2],
+    link_up: bool,
+    link_wakers: [WakerRegistration; 2],
+}
+
+/// State of a simulated link.
+///
+/// Holds up to `N` packets of up to `MTU` bytes in flight in each direction.
+pub struct State<const MTU: usize, const N: usize> {
+    shared: Mutex<NoopRawMutex, RefCell<Shared<MTU, N>>>,
+}
+
+impl<const MTU: usize, const N: usize> State<MTU, N> {
+    /// Create a new link state.
+    pub const fn new() -> Self {
+        Self {
+            shared: Mutex::new(RefCell::new(Shared {
+                queues: [Queue::new(0x853C_49E6_748F_EA9B), Queue::new(0xDA3E_39CB_94B9_5BDB)],
+                link_up: true,
+                link_wakers: [WakerRegistration::new(), WakerRegistration::new()],
+            })),
+        }
+    }
+}
+
+impl<const MTU: usize, const N: usize> Default for State<MTU, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Create a simulated link, returning its two devices, and a handle to control it.
+///
+/// The link starts up and perfect: use the [`Control`] to add impairments.
+pub fn new<const MTU: usize, const N: usize>(
+    state: &mut State<MTU, N>,
+    hardware_address_a: HardwareAddress,
+    hardware_address_b: HardwareAddress,
+) -> (Device<'_, MTU, N>, Device<'_, MTU, N>, Control<'_, MTU, N>) {
+    let shared = &state.shared;
+    let device = |tx: Direction, rx: Direction, hardware_address| Device {
+        shared,
+        tx: tx as usize,
+        rx: rx as usize,
+        hardware_address,
+        rx_buf: [0; MTU],
+        tx_buf: [0; MTU],
+    };
+    (
+        device(Direction::AToB, Direction::BToA, hardware_address_a),
+        device(Direction::BToA, Direction::AToB, hardware_address_b),
+        Control { shared },
+    )
+}
+
+/// Handle to control a simulated link.
+#[derive(Clone, Copy)]
+pub struct Control<'d, const MTU: usize, const N: usize> {
+    shared: &'d Mutex<NoopRawMutex, RefCell<Shared<MTU, N>>>,
+}
+
+impl<const MTU: usize, const N: usize> Control<'_, MTU, N> {
+    fn with<R>(&self, f: impl FnOnce(&mut Shared<MTU, N>) -> R) -> R {
+        self.shared.lock(|s| f(&mut s.borrow_mut()))
+    }
+
+    /// Set the impairments of one direction.
+    ///
+    /// They apply to the packets sent from now on.
+    pub fn set_config(&self, direction: Direction, config: Config) {
+        self.with(|s| s.queues[direction as usize].config = config)
+    }
+
+    /// Get the impairments of one direction.
+    pub fn config(&self, direction: Direction) -> Config {
+        self.with(|s| s.queues[direction as usize].config)
+    }
+
+    /// Seed the random number generators deciding which packets are impaired.
+    ///
+    /// The same seed and traffic give the same impairments.
+    pub fn set_seed(&self, seed: u64) {
+        self.with(|s| {
+            // xorshift needs a non-zero state.
+            s.queues[0].rng = seed | 1;
+            s.queues[1].rng = (seed ^ 0x9E37_79B9_7F4A_7C15) | 1;
+        })
+    }
+
+    /// Bring the link up or down.
+    ///
+    /// While it is down, both devices report it, and all packets are dropped, including the ones
+    /// in flight.
+    pub fn set_link_up(&self, up: bool) {
+        self.with(|s| {
+            s.link_up = up;
+            if !up {
+                s.queues.iter_mut().for_each(Queue::clear);
+            }
+            s.link_wakers.iter_mut().for_each(WakerRegistration::wake);
+        })
+    }
+
+    /// Get whether the link is up.
+    pub fn is_link_up(&self) -> bool {
+        self.with(|s| s.link_up)
+    }
+
+    /// Bring the link down for `duration`, then up again.
+    pub async fn flap(&self, duration: Duration) {
+        self.set_link_up(false);
+        Timer::after(duration).await;
+        self.set_link_up(true);
+    }
+
+    /// Get the packet counters of one direction.
+    pub fn stats(&self, direction: Direction) -> Stats {
+        self.with(|s| s.queues[direction as usize].stats)
+    }
+}
+
+/// One end of a simulated link, implementing the [`Driver`](embassy_net_driver::Driver) trait.
+pub struct Device<'d, const MTU: usize, const N: usize> {
+    shared: &'d Mutex<NoopRawMutex, RefCell<Shared<MTU, N>>>,
+    tx: usize,
+    rx: usize,
+    hardware_address: HardwareAddress,
+    rx_buf: [u8; MTU],
+    tx_buf: [u8; MTU],
+}
+
+impl<const MTU: usize, const N: usize> embassy_net_driver::Driver for Device<'_, MTU, N> {
+    type RxToken<'a>
+        = RxToken<'a>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = TxToken<'a, MTU, N>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, cx: &mut Context) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let now = Instant::now();
+        let (len, next) = self.shared.lock(|s| {
+            let queue = &mut s.borrow_mut().queues[self.rx];
+            match queue.next() {
+                Some(i) if queue.packets[i].at <= now => {
+                    let packet = &mut queue.packets[i];
+                    packet.used = false;
+                    self.rx_buf[..packet.len].copy_from_slice(&packet.buf[..packet.len]);
+                    queue.stats.delivered += 1;
+                    (Some(packet.len), None)
+                }
+                next => {
+                    queue.waker.register(cx.waker());
+                    (None, next.map(|i| queue.packets[i].at))
+                }
+            }
+        });
+
+        if let Some(at) = next {
+            // Wake up when the next packet is due.
+            let mut timer = Timer::at(at);
+            if Pin::new(&mut timer).poll(cx).is_ready() {
+                cx.waker().wake_by_ref();
+            }
+        }
+        let len = len?;
+        Some((
+            RxToken {
+                buf: &mut self.rx_buf[..len],
+            },
+            TxToken {
+                shared: self.shared,
+                queue: self.tx,
+                buf: &mut self.tx_buf,
+            },
+        ))
+    }
+
+    fn transmit(&mut self, _cx: &mut Context) -> Option<Self::TxToken<'_>> {
+        Some(TxToken {
+            shared: self.shared,
+            queue: self.tx,
+            buf: &mut self.tx_buf,
+        })
+    }
+
+    fn link_state(&mut self, cx: &mut Context) -> LinkState {
+        self.shared.lock(|s| {
+            let s = &mut *s.borrow_mut();
+            s.link_wakers[self.rx].register(cx.waker());
+            match s.link_up {
+                true => LinkState::Up,
+                false => LinkState::Down,
+            }
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let mut caps = Capabilities::default();
+        caps.max_transmission_unit = MTU;
+        caps
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        self.hardware_address
+    }
+}
+
+/// Receive token of a [`Device`].
+pub struct RxToken<'a> {
+    buf: &'a mut [u8],
+}
+
+impl embassy_net_driver::RxToken for RxToken<'_> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        f(self.buf)
+    }
+}
+
+/// Transmit token of a [`Device`].
+pub struct TxToken<'a, const MTU: usize, const N: usize> {
+    shared: &'a Mutex<NoopRawMutex, RefCell<Shared<MTU, N>>>,
+    queue: usize,
+    buf: &'a mut [u8; MTU],
+}
+
+impl<const MTU: usize, const N: usize> embassy_net_driver::TxToken for TxToken<'_, MTU, N> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let buf = &mut self.buf[..len];
+        let r = f(buf);
+        self.shared.lock(|s| {
+            let s = &mut *s.borrow_mut();
+            s.queues[self.queue].send(buf, s.link_up);
+        });
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::task::Waker;
+
+    use embassy_net_driver::{Driver, RxToken as _, TxToken as _};
+    use embassy_time::MockDriver;
+    use serial_test::serial;
+
+    use super::*;
+
+    const ADDR_A: HardwareAddress = HardwareAddress::Ethernet([2, 0, 0, 0, 0, 1]);
+    const ADDR_B: HardwareAddress = HardwareAddress::Ethernet([2, 0, 0, 0, 0, 2]);
+
+    fn setup() {
+        MockDriver::get().reset();
+    }
+
+    fn send<const MTU: usize, const N: usize>(device: &mut Device<'_, MTU, N>, data: &[u8]) {
+        let mut cx = Context::from_waker(Waker::noop());
+        let token = device.transmit(&mut cx).unwrap();
+        token.consume(data.len(), |buf| buf.copy_from_slice(data));
+    }
+
+    fn recv<const MTU: usize, const N: usize>(device: &mut Device<'_, MTU, N>) -> Option<Vec<u8>> {
+        let mut cx = Context::from_waker(Waker::noop());
+        let (token, _) = device.receive(&mut cx)?;
+        Some(token.consume(|buf| buf.to_vec()))
+    }
+
+    #[test]
+    #[serial]
+    fn test_perfect() {
+        setup();
+        let mut state = State::<64, 4>::new();
+        let (mut a, mut b, control) = new(&mut state, ADDR_A, ADDR_B);
+
+        send(&mut a, b"hello");
+        send(&mut b, b"world");
+        assert_eq!(recv(&mut b).as_deref(), Some(&b"hello"[..]));
+        assert_eq!(recv(&mut a).as_deref(), Some(&b"world"[..]));
+        assert_eq!(recv(&mut a), None);
+        assert_eq!(
+            control.stats(Direction::AToB),
+            Stats {
+                sent: 1,
+                delivered: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_latency() {
+        setup();
+        let mut state = State::<64, 4>::new();
+        let (mut a, mut b, control) = new(&mut state, ADDR_A, ADDR_B);
+        let config = Config {
+            latency: Duration::from_millis(10),
+            ..Default::default()
+        };
+        control.set_config(Direction::AToB, config);
+
+        send(&mut a, b"1");
+        MockDriver::get().advance(Duration::from_millis(5));
+        send(&mut a, b"2");
+        assert_eq!(recv(&mut b), None);
+        MockDriver::get().advance(Duration::from_millis(5));
+        assert_eq!(recv(&mut b).as_deref(), Some(&b"1"[..]));
+        assert_eq!(recv(&mut b), None);
+        MockDriver::get().advance(Duration::from_millis(5));
+        assert_eq!(recv(&mut b).as_deref(), Some(&b"2"[..]));
+
+        // The other direction is not impaired.
+        send(&mut b, b"3");
+        assert_eq!(recv(&mut a).as_deref(), Some(&b"3"[..]));
+    }
+
+    #[test]
+    #[serial]
+    fn test_loss_and_duplicate() {
+        setup();
+        let mut state = State::<64, 4>::new();
+        let (mut a, mut b, control) = new(&mut state, ADDR_A, ADDR_B);
+        control.set_seed(1234);
+        let config = Config {
+            loss: 0.25,
+            duplicate: 0.25,
+            ..Default::default()
+        };
+        control.set_config(Direction::AToB, config);
+
+        let mut received = 0;
+        for _ in 0..1000 {
+            send(&mut a, b"x");
+            while recv(&mut b).is_some() {
+                received += 1;
+            }
+        }
+        let stats = control.stats(Direction::AToB);
+        assert_eq!(stats.sent, 1000);
+        assert_eq!(stats.delivered, received);
+        assert_eq!(received, stats.sent - stats.lost + stats.duplicated);
+        assert!((200..300).contains(&stats.lost), "{stats:?}");
+        assert!((150..250).contains(&stats.duplicated), "{stats:?}");
+
+        // The same seed gives the same impairments.
+        let mut state2 = State::<64, 4>::new();
+        let (mut a2, _b2, control2) = new(&mut state2, ADDR_A, ADDR_B);
+        control2.set_seed(1234);
+        control2.set_config(Direction::AToB, config);
+        for _ in 0..1000 {
+            send(&mut a2, b"x");
+        }
+        let stats2 = control2.stats(Direction::AToB);
+        assert_eq!((stats2.lost, stats2.duplicated), (stats.lost, stats.duplicated));
+    }
+
+    #[test]
+    #[serial]
+    fn test_reorder() {
+        setup();
+        let mut state = State::<64, 4>::new();
+        let (mut a, mut b, control) = new(&mut state, ADDR_A, ADDR_B);
+        let mut config = Config {
+            latency: Duration::from_millis(10),
+            reorder: 1.0,
+            ..Default::default()
+        };
+        control.set_config(Direction::AToB, config);
+        send(&mut a, b"1");
+        config.reorder = 0.0;
+        control.set_config(Direction::AToB, config);
+        send(&mut a, b"2");
+        config.reorder = 1.0;
+        control.set_config(Direction::AToB, config);
+        send(&mut a, b"3");
+
+        assert_eq!(recv(&mut b).as_deref(), Some(&b"1"[..]));
+        assert_eq!(recv(&mut b).as_deref(), Some(&b"3"[..]));
+        assert_eq!(recv(&mut b), None);
+        MockDriver::get().advance(Duration::from_millis(10));
+        assert_eq!(recv(&mut b).as_deref(), Some(&b"2"[..]));
+        assert_eq!(control.stats(Direction::AToB).reordered, 2);
+    }
+
+    #[test]
+    #[serial]
+    fn test_bandwidth() {
+        setup();
+        let mut state = State::<1500, 3>::new();
+        let (mut a, mut b, control) = new(&mut state, ADDR_A, ADDR_B);
+        // 1000 bytes take 1 ms.
+        let config = Config {
+            bandwidth: Some(8_000_000),
+            ..Default::default()
+        };
+        control.set_config(Direction::AToB, config);
+
+        for _ in 0..4 {
+            send(&mut a, &[0; 1000]);
+        }
+        assert_eq!(control.stats(Direction::AToB).dropped, 1);
+        for _ in 0..3 {
+            assert_eq!(recv(&mut b), None);
+            MockDriver::get().advance(Duration::from_millis(1));
+            assert!(recv(&mut b).is_some());
+        }
+        assert_eq!(recv(&mut b), None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_link_down() {
+        setup();
+        let mut state = State::<64, 4>::new();
+        let (mut a, mut b, control) = new(&mut state, ADDR_A, ADDR_B);
+        let mut cx = Context::from_waker(Waker::noop());
+        let config = Config {
+            latency: Duration::from_millis(10),
+            ..Default::default()
+        };
+        control.set_config(Direction::AToB, config);
+
+        send(&mut a, b"in flight");
+        control.set_link_up(false);
+        assert!(matches!(a.link_state(&mut cx), LinkState::Down));
+        assert!(matches!(b.link_state(&mut cx), LinkState::Down));
+        send(&mut a, b"dropped");
+        MockDriver::get().advance(Duration::from_millis(10));
+        assert_eq!(recv(&mut b), None);
+        assert_eq!(control.stats(Direction::AToB).dropped, 2);
+
+        control.set_link_up(true);
+        assert!(matches!(b.link_state(&mut cx), LinkState::Up));
+        send(&mut a, b"delivered");
+        MockDriver::get().advance(Duration::from_millis(10));
+        assert_eq!(recv(&mut b).as_deref(), Some(&b"delivered"[..]));
+    }
+}
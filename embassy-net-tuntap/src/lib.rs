@@ -1,43 +1,58 @@
 #![warn(missing_docs)]
 #![doc = include_str!("../README.md")]
+use std::future::Future;
 use std::io;
 use std::io::{Read, Write};
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::pin::Pin;
 use std::task::Context;
+use std::time::Duration;
 
-use async_io::Async;
+use async_io::{Async, Timer};
 use embassy_net_driver::{Capabilities, Driver, HardwareAddress, LinkState};
 use log::*;
 
 /// Get the MTU of the given interface.
 pub const SIOCGIFMTU: libc::c_ulong = 0x8921;
+/// Set the MTU of the given interface.
+pub const SIOCSIFMTU: libc::c_ulong = 0x8922;
 /// Get the index of the given interface.
 pub const _SIOCGIFINDEX: libc::c_ulong = 0x8933;
 /// Capture all packages.
 pub const _ETH_P_ALL: libc::c_short = 0x0003;
 /// Set the interface flags.
 pub const TUNSETIFF: libc::c_ulong = 0x400454CA;
+/// Get the interface name and flags.
+pub const TUNGETIFF: libc::c_ulong = 0x800454D2;
 /// TUN device.
-pub const _IFF_TUN: libc::c_int = 0x0001;
+pub const IFF_TUN: libc::c_int = 0x0001;
 /// TAP device.
 pub const IFF_TAP: libc::c_int = 0x0002;
+/// Multi-queue device.
+pub const IFF_MULTI_QUEUE: libc::c_int = 0x0100;
 /// No packet information.
 pub const IFF_NO_PI: libc::c_int = 0x1000;
 
 const ETHERNET_HEADER_LEN: usize = 14;
 
+/// How often the operational state of the interface is checked.
+const LINK_STATE_INTERVAL: Duration = Duration::from_secs(1);
+
 #[repr(C)]
 #[derive(Debug)]
 #[allow(non_camel_case_types)]
 struct ifreq {
     ifr_name: [libc::c_char; libc::IF_NAMESIZE],
-    ifr_data: libc::c_int, /* ifr_ifindex or ifr_mtu */
+    ifr_data: libc::c_int, /* ifr_flags, ifr_ifindex or ifr_mtu */
+    // The kernel reads and writes the whole union.
+    _pad: [u8; 20],
 }
 
 fn ifreq_for(name: &str) -> ifreq {
     let mut ifreq = ifreq {
         ifr_name: [0; libc::IF_NAMESIZE],
         ifr_data: 0,
+        _pad: [0; 20],
     };
     for (i, byte) in name.as_bytes().iter().enumerate() {
         ifreq.ifr_name[i] = *byte as libc::c_char
@@ -56,11 +71,39 @@ fn ifreq_ioctl(lower: libc::c_int, ifreq: &mut ifreq, cmd: libc::c_ulong) -> io:
     Ok(ifreq.ifr_data)
 }
 
+/// Kind of TUN/TAP device.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// TUN device, exchanging IP packets (IP medium).
+    Tun,
+    /// TAP device, exchanging Ethernet frames (Ethernet medium).
+    #[default]
+    Tap,
+}
+
+/// TUN/TAP device configuration.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Config {
+    /// Kind of device.
+    pub mode: Mode,
+    /// IP MTU to set on the interface, or `None` to keep its current one.
+    ///
+    /// Changing it needs the `CAP_NET_ADMIN` capability.
+    pub mtu: Option<usize>,
+    /// Open one queue of a multi-queue interface.
+    ///
+    /// Existing interfaces must be opened with the same setting they were created with.
+    pub multi_queue: bool,
+}
+
 /// A TUN/TAP device.
 #[derive(Debug)]
 pub struct TunTap {
     fd: libc::c_int,
     mtu: usize,
+    mode: Mode,
+    name: String,
 }
 
 impl AsRawFd for TunTap {
@@ -70,8 +113,16 @@ impl AsRawFd for TunTap {
 }
 
 impl TunTap {
-    /// Create a new TUN/TAP device.
+    /// Create a new TAP device, or open the existing one with this name.
     pub fn new(name: &str) -> io::Result<TunTap> {
+        Self::with_config(name, &Config::default())
+    }
+
+    /// Create a new TUN/TAP device, or open the existing one with this name.
+    ///
+    /// Persistent interfaces, created with `ip tuntap add`, can be opened by their owner without
+    /// any capability.
+    pub fn with_config(name: &str, config: &Config) -> io::Result<TunTap> {
         unsafe {
             let fd = libc::open(
                 "/dev/net/tun\0".as_ptr() as *const libc::c_char,
@@ -80,26 +131,120 @@ impl TunTap {
             if fd == -1 {
                 return Err(io::Error::last_os_error());
             }
+            // Owned from now on, to be closed on error.
+            let mut this = TunTap {
+                fd,
+                mtu: 0,
+                mode: config.mode,
+                name: name.to_string(),
+            };
 
             let mut ifreq = ifreq_for(name);
-            ifreq.ifr_data = IFF_TAP | IFF_NO_PI;
+            ifreq.ifr_data = IFF_NO_PI;
+            ifreq.ifr_data |= match config.mode {
+                Mode::Tun => IFF_TUN,
+                Mode::Tap => IFF_TAP,
+            };
+            if config.multi_queue {
+                ifreq.ifr_data |= IFF_MULTI_QUEUE;
+            }
             ifreq_ioctl(fd, &mut ifreq, TUNSETIFF)?;
 
-            let socket = libc::socket(libc::AF_INET, libc::SOCK_DGRAM, libc::IPPROTO_IP);
-            if socket == -1 {
+            if let Some(mtu) = config.mtu {
+                ifreq.ifr_data = mtu as libc::c_int;
+                with_socket(|socket| ifreq_ioctl(socket, &mut ifreq, SIOCSIFMTU))?;
+            }
+            this.mtu = this.query_mtu()?;
+            Ok(this)
+        }
+    }
+
+    /// Use an already open TUN/TAP device, for example a queue of a multi-queue interface opened
+    /// by a privileged process.
+    ///
+    /// The file descriptor is set to non-blocking mode.
+    pub fn from_fd(fd: OwnedFd) -> io::Result<TunTap> {
+        let fd = fd.into_raw_fd();
+        let mut this = TunTap {
+            fd,
+            mtu: 0,
+            mode: Mode::Tap,
+            name: String::new(),
+        };
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if flags == -1 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) == -1 {
                 return Err(io::Error::last_os_error());
             }
+        }
 
-            let ip_mtu = ifreq_ioctl(socket, &mut ifreq, SIOCGIFMTU);
-            libc::close(socket);
-            let ip_mtu = ip_mtu? as usize;
+        let mut ifreq = ifreq_for("");
+        let flags = ifreq_ioctl(fd, &mut ifreq, TUNGETIFF)?;
+        if flags & IFF_NO_PI == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "TUN/TAP device with packet information",
+            ));
+        }
+        if flags & IFF_TUN != 0 {
+            this.mode = Mode::Tun;
+        }
+        this.name = ifreq
+            .ifr_name
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as u8 as char)
+            .collect();
+        this.mtu = this.query_mtu()?;
+        Ok(this)
+    }
+
+    /// Get the kind of device.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Get the name of the interface.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get whether the interface is operationally up, from `/sys/class/net/<name>/operstate`.
+    ///
+    /// TUN/TAP interfaces have no carrier, and report an unknown state when they are up.
+    pub fn is_up(&self) -> io::Result<bool> {
+        let operstate = std::fs::read_to_string(format!("/sys/class/net/{}/operstate", self.name))?;
+        Ok(operstate_is_up(&operstate))
+    }
+
+    /// Get the MTU of the interface, counting the Ethernet header for TAP devices.
+    fn query_mtu(&self) -> io::Result<usize> {
+        let mut ifreq = ifreq_for(&self.name);
+        let ip_mtu = with_socket(|socket| ifreq_ioctl(socket, &mut ifreq, SIOCGIFMTU))? as usize;
+
+        // SIOCGIFMTU returns the IP MTU (typically 1500 bytes.)
+        // smoltcp counts the entire Ethernet packet in the MTU, so add the Ethernet header size to it.
+        Ok(match self.mode {
+            Mode::Tun => ip_mtu,
+            Mode::Tap => ip_mtu + ETHERNET_HEADER_LEN,
+        })
+    }
+}
 
-            // SIOCGIFMTU returns the IP MTU (typically 1500 bytes.)
-            // smoltcp counts the entire Ethernet packet in the MTU, so add the Ethernet header size to it.
-            let mtu = ip_mtu + ETHERNET_HEADER_LEN;
+fn operstate_is_up(operstate: &str) -> bool {
+    matches!(operstate.trim(), "up" | "unknown")
+}
 
-            Ok(TunTap { fd, mtu })
+/// Run an interface ioctl on a temporary socket.
+fn with_socket<R>(f: impl FnOnce(libc::c_int) -> io::Result<R>) -> io::Result<R> {
+    unsafe {
+        let socket = libc::socket(libc::AF_INET, libc::SOCK_DGRAM, libc::IPPROTO_IP);
+        if socket == -1 {
+            return Err(io::Error::last_os_error());
         }
+        let res = f(socket);
+        libc::close(socket);
+        res
     }
 }
 
@@ -140,13 +285,31 @@ impl io::Write for TunTap {
 /// A TUN/TAP device, wrapped in an async interface.
 pub struct TunTapDevice {
     device: Async<TunTap>,
+    link_up: bool,
+    link_timer: Option<Timer>,
 }
 
 impl TunTapDevice {
-    /// Create a new TUN/TAP device.
+    /// Create a new TAP device, or open the existing one with this name.
     pub fn new(name: &str) -> io::Result<TunTapDevice> {
+        Self::from_device(TunTap::new(name)?)
+    }
+
+    /// Create a new TUN/TAP device, or open the existing one with this name.
+    pub fn with_config(name: &str, config: &Config) -> io::Result<TunTapDevice> {
+        Self::from_device(TunTap::with_config(name, config)?)
+    }
+
+    /// Use an already open TUN/TAP device.
+    pub fn from_fd(fd: OwnedFd) -> io::Result<TunTapDevice> {
+        Self::from_device(TunTap::from_fd(fd)?)
+    }
+
+    fn from_device(device: TunTap) -> io::Result<TunTapDevice> {
         Ok(Self {
-            device: Async::new(TunTap::new(name)?)?,
+            device: Async::new(device)?,
+            link_up: false,
+            link_timer: None,
         })
     }
 }
@@ -196,12 +359,40 @@ impl Driver for TunTapDevice {
         caps
     }
 
-    fn link_state(&mut self, _cx: &mut Context) -> LinkState {
-        LinkState::Up
+    fn link_state(&mut self, cx: &mut Context) -> LinkState {
+        // The operational state is polled, there is no notification for it without netlink.
+        let due = match &mut self.link_timer {
+            Some(timer) => Pin::new(timer).poll(cx).is_ready(),
+            None => true,
+        };
+        if due {
+            let link_up = match self.device.get_ref().is_up() {
+                Ok(up) => up,
+                Err(e) => {
+                    warn!("failed to read operstate: {:?}", e);
+                    false
+                }
+            };
+            if link_up != self.link_up {
+                debug!("{}: link up = {}", self.device.get_ref().name(), link_up);
+                self.link_up = link_up;
+            }
+            let mut timer = Timer::after(LINK_STATE_INTERVAL);
+            let _ = Pin::new(&mut timer).poll(cx);
+            self.link_timer = Some(timer);
+        }
+
+        match self.link_up {
+            true => LinkState::Up,
+            false => LinkState::Down,
+        }
     }
 
     fn hardware_address(&self) -> HardwareAddress {
-        HardwareAddress::Ethernet([0x02, 0x03, 0x04, 0x05, 0x06, 0x07])
+        match self.device.get_ref().mode() {
+            Mode::Tun => HardwareAddress::Ip,
+            Mode::Tap => HardwareAddress::Ethernet([0x02, 0x03, 0x04, 0x05, 0x06, 0x07]),
+        }
     }
 }
 
@@ -242,3 +433,54 @@ impl<'a> embassy_net_driver::TxToken for TxToken<'a> {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::os::fd::FromRawFd;
+
+    use super::*;
+
+    #[test]
+    fn operstate() {
+        assert!(operstate_is_up("up\n"));
+        assert!(operstate_is_up("unknown\n"));
+        assert!(!operstate_is_up("down\n"));
+        assert!(!operstate_is_up("lowerlayerdown\n"));
+        assert!(!operstate_is_up("dormant\n"));
+    }
+
+    #[test]
+    fn ifreq_name() {
+        let ifreq = ifreq_for("tun0");
+        assert_eq!(&ifreq.ifr_name[..5], &[b't' as _, b'u' as _, b'n' as _, b'0' as _, 0]);
+    }
+
+    #[test]
+    #[ignore = "needs CAP_NET_ADMIN"]
+    fn tun_mtu_and_from_fd() {
+        let config = Config {
+            mode: Mode::Tun,
+            mtu: Some(1280),
+            multi_queue: false,
+        };
+        let tun = TunTap::with_config("embassytest0", &config).unwrap();
+        assert_eq!(tun.mode(), Mode::Tun);
+        assert_eq!(tun.mtu, 1280);
+        // New interfaces are down until configured.
+        assert!(!tun.is_up().unwrap());
+
+        let fd = unsafe { OwnedFd::from_raw_fd(libc::dup(tun.as_raw_fd())) };
+        let device = TunTapDevice::from_fd(fd).unwrap();
+        assert_eq!(device.device.get_ref().name(), "embassytest0");
+        assert_eq!(device.hardware_address(), HardwareAddress::Ip);
+        assert_eq!(device.capabilities().max_transmission_unit, 1280);
+    }
+
+    #[test]
+    #[ignore = "needs CAP_NET_ADMIN"]
+    fn tap_mtu_counts_ethernet_header() {
+        let tap = TunTap::new("embassytest1").unwrap();
+        assert_eq!(tap.mode(), Mode::Tap);
+        assert_eq!(tap.mtu, 1500 + ETHERNET_HEADER_LEN);
+    }
+}
@@ -1,9 +1,51 @@
-//! Wiznet W5100s and W5500 family driver.
+//! Wiznet W5100s, W5500 and W6100 family driver.
 mod w5500;
 pub use w5500::W5500;
 mod w5100s;
+use core::net::SocketAddrV4;
+
 use embedded_hal_async::spi::SpiDevice;
 pub use w5100s::W5100S;
+mod w6100;
+pub use w6100::W6100;
+
+/// Offsets of the registers in the register block of each socket, used in offload mode.
+pub(crate) struct SocketRegisters {
+    pub mode: u16,
+    pub command: u16,
+    /// Pending interrupts.
+    pub intr: u16,
+    /// Written to clear pending interrupts.
+    pub intr_clear: u16,
+    pub status: u16,
+    pub port: u16,
+    pub dest_ip: u16,
+    pub dest_port: u16,
+    pub rxbuf_size: u16,
+    pub txbuf_size: u16,
+    pub tx_free_size: u16,
+    pub tx_data_write_ptr: u16,
+    pub recvd_size: u16,
+    pub rx_data_read_ptr: u16,
+}
+
+/// Socket registers of the W5100S and W5500.
+pub(crate) const W5X00_SOCKET_REGISTERS: SocketRegisters = SocketRegisters {
+    mode: 0x00,
+    command: 0x01,
+    intr: 0x02,
+    intr_clear: 0x02,
+    status: 0x03,
+    port: 0x04,
+    dest_ip: 0x0C,
+    dest_port: 0x10,
+    rxbuf_size: 0x1E,
+    txbuf_size: 0x1F,
+    tx_free_size: 0x20,
+    tx_data_write_ptr: 0x24,
+    recvd_size: 0x26,
+    rx_data_read_ptr: 0x28,
+};
 
 pub(crate) trait SealedChip {
     type Address;
@@ -14,7 +56,12 @@ pub(crate) trait SealedChip {
     const CHIP_VERSION: u8;
 
     const COMMON_MODE: Self::Address;
+    const COMMON_GATEWAY: Self::Address;
+    const COMMON_SUBNET_MASK: Self::Address;
     const COMMON_MAC: Self::Address;
+    const COMMON_IP: Self::Address;
+    /// Pending interrupts of the sockets, one bit per socket.
+    const COMMON_SOCKET_INTR_STATUS: Self::Address;
     const COMMON_SOCKET_INTR: Self::Address;
     const COMMON_PHY_CFG: Self::Address;
     const COMMON_VERSION: Self::Address;
@@ -28,6 +75,7 @@ pub(crate) trait SealedChip {
     const SOCKET_RECVD_SIZE: Self::Address;
     const SOCKET_RX_DATA_READ_PTR: Self::Address;
     const SOCKET_INTR_MASK: Self::Address;
+    /// Written to clear the pending interrupts of the MACRAW socket.
     const SOCKET_INTR: Self::Address;
 
     const SOCKET_MODE_VALUE: u8;
@@ -35,9 +83,37 @@ pub(crate) trait SealedChip {
     const BUF_SIZE: u16;
     const AUTO_WRAP: bool;
 
+    /// Number of hardware sockets, sharing the buffers in offload mode.
+    const SOCKET_COUNT: u8;
+    const SOCKET_REGISTERS: SocketRegisters;
+
     fn rx_addr(addr: u16) -> Self::Address;
     fn tx_addr(addr: u16) -> Self::Address;
 
+    /// Address of the register at `offset` in the register block of `socket`.
+    fn socket_register(socket: u8, offset: u16) -> Self::Address;
+    /// Address of `addr` in the RX buffer of `socket`, with the buffers split evenly between
+    /// the sockets.
+    fn socket_rx_addr(socket: u8, addr: u16) -> Self::Address;
+    /// Address of `addr` in the TX buffer of `socket`, with the buffers split evenly between
+    /// the sockets.
+    fn socket_tx_addr(socket: u8, addr: u16) -> Self::Address;
+
+    /// Reset the chip, leaving the registers the driver writes unlocked.
+    async fn soft_reset<SPI: SpiDevice>(spi: &mut SPI) -> Result<(), SPI::Error> {
+        Self::bus_write(spi, Self::COMMON_MODE, &[0x80]).await
+    }
+
+    /// Parse the header the chip puts before each received UDP datagram, returning the remote
+    /// address and the length of the datagram.
+    fn udp_header(header: &[u8; 8]) -> (SocketAddrV4, u16) {
+        let address = SocketAddrV4::new(
+            [header[0], header[1], header[2], header[3]].into(),
+            u16::from_be_bytes([header[4], header[5]]),
+        );
+        (address, u16::from_be_bytes([header[6], header[7]]))
+    }
+
     async fn bus_read<SPI: SpiDevice>(spi: &mut SPI, address: Self::Address, data: &mut [u8])
         -> Result<(), SPI::Error>;
     async fn bus_write<SPI: SpiDevice>(spi: &mut SPI, address: Self::Address, data: &[u8]) -> Result<(), SPI::Error>;
@@ -1,6 +1,7 @@
 use embedded_hal_async::spi::{Operation, SpiDevice};
 
 const SOCKET_BASE: u16 = 0x400;
+const SOCKET_STRIDE: u16 = 0x100;
 const TX_BASE: u16 = 0x4000;
 const RX_BASE: u16 = 0x6000;
 
@@ -14,7 +15,11 @@ impl super::SealedChip for W5100S {
     const CHIP_VERSION: u8 = 0x51;
 
     const COMMON_MODE: Self::Address = 0x00;
+    const COMMON_GATEWAY: Self::Address = 0x01;
+    const COMMON_SUBNET_MASK: Self::Address = 0x05;
     const COMMON_MAC: Self::Address = 0x09;
+    const COMMON_IP: Self::Address = 0x0F;
+    const COMMON_SOCKET_INTR_STATUS: Self::Address = 0x15;
     const COMMON_SOCKET_INTR: Self::Address = 0x16;
     const COMMON_PHY_CFG: Self::Address = 0x3c;
     const COMMON_VERSION: Self::Address = 0x80;
@@ -35,6 +40,9 @@ impl super::SealedChip for W5100S {
     const BUF_SIZE: u16 = 0x2000;
     const AUTO_WRAP: bool = false;
 
+    const SOCKET_COUNT: u8 = 4;
+    const SOCKET_REGISTERS: super::SocketRegisters = super::W5X00_SOCKET_REGISTERS;
+
     fn rx_addr(addr: u16) -> Self::Address {
         RX_BASE + addr
     }
@@ -43,6 +51,20 @@ impl super::SealedChip for W5100S {
         TX_BASE + addr
     }
 
+    fn socket_register(socket: u8, offset: u16) -> Self::Address {
+        SOCKET_BASE + socket as u16 * SOCKET_STRIDE + offset
+    }
+
+    fn socket_rx_addr(socket: u8, addr: u16) -> Self::Address {
+        let size = Self::BUF_SIZE / Self::SOCKET_COUNT as u16;
+        RX_BASE + socket as u16 * size + addr % size
+    }
+
+    fn socket_tx_addr(socket: u8, addr: u16) -> Self::Address {
+        let size = Self::BUF_SIZE / Self::SOCKET_COUNT as u16;
+        TX_BASE + socket as u16 * size + addr % size
+    }
+
     async fn bus_read<SPI: SpiDevice>(
         spi: &mut SPI,
         address: Self::Address,
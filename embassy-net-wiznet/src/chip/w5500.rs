@@ -1,11 +1,26 @@
 use embedded_hal_async::spi::{Operation, SpiDevice};
 
-#[repr(u8)]
-pub enum RegisterBlock {
-    Common = 0x00,
-    Socket0 = 0x01,
-    TxBuf = 0x02,
-    RxBuf = 0x03,
+/// Block select bits of an address. Each socket has a register block and buffer blocks.
+#[derive(Clone, Copy)]
+pub struct RegisterBlock(u8);
+
+impl RegisterBlock {
+    pub const COMMON: Self = Self(0x00);
+    pub const SOCKET0: Self = Self::socket(0);
+    pub const TX_BUF0: Self = Self::tx_buf(0);
+    pub const RX_BUF0: Self = Self::rx_buf(0);
+
+    pub const fn socket(n: u8) -> Self {
+        Self(0x01 + (n << 2))
+    }
+
+    pub const fn tx_buf(n: u8) -> Self {
+        Self(0x02 + (n << 2))
+    }
+
+    pub const fn rx_buf(n: u8) -> Self {
+        Self(0x03 + (n << 2))
+    }
 }
 
 /// Wiznet W5500 chip.
@@ -17,34 +32,53 @@ impl super::SealedChip for W5500 {
 
     const CHIP_VERSION: u8 = 0x04;
 
-    const COMMON_MODE: Self::Address = (RegisterBlock::Common, 0x00);
-    const COMMON_MAC: Self::Address = (RegisterBlock::Common, 0x09);
-    const COMMON_SOCKET_INTR: Self::Address = (RegisterBlock::Common, 0x18);
-    const COMMON_PHY_CFG: Self::Address = (RegisterBlock::Common, 0x2E);
-    const COMMON_VERSION: Self::Address = (RegisterBlock::Common, 0x39);
-
-    const SOCKET_MODE: Self::Address = (RegisterBlock::Socket0, 0x00);
-    const SOCKET_COMMAND: Self::Address = (RegisterBlock::Socket0, 0x01);
-    const SOCKET_RXBUF_SIZE: Self::Address = (RegisterBlock::Socket0, 0x1E);
-    const SOCKET_TXBUF_SIZE: Self::Address = (RegisterBlock::Socket0, 0x1F);
-    const SOCKET_TX_FREE_SIZE: Self::Address = (RegisterBlock::Socket0, 0x20);
-    const SOCKET_TX_DATA_WRITE_PTR: Self::Address = (RegisterBlock::Socket0, 0x24);
-    const SOCKET_RECVD_SIZE: Self::Address = (RegisterBlock::Socket0, 0x26);
-    const SOCKET_RX_DATA_READ_PTR: Self::Address = (RegisterBlock::Socket0, 0x28);
-    const SOCKET_INTR_MASK: Self::Address = (RegisterBlock::Socket0, 0x2C);
-    const SOCKET_INTR: Self::Address = (RegisterBlock::Socket0, 0x02);
+    const COMMON_MODE: Self::Address = (RegisterBlock::COMMON, 0x00);
+    const COMMON_GATEWAY: Self::Address = (RegisterBlock::COMMON, 0x01);
+    const COMMON_SUBNET_MASK: Self::Address = (RegisterBlock::COMMON, 0x05);
+    const COMMON_MAC: Self::Address = (RegisterBlock::COMMON, 0x09);
+    const COMMON_IP: Self::Address = (RegisterBlock::COMMON, 0x0F);
+    const COMMON_SOCKET_INTR_STATUS: Self::Address = (RegisterBlock::COMMON, 0x17);
+    const COMMON_SOCKET_INTR: Self::Address = (RegisterBlock::COMMON, 0x18);
+    const COMMON_PHY_CFG: Self::Address = (RegisterBlock::COMMON, 0x2E);
+    const COMMON_VERSION: Self::Address = (RegisterBlock::COMMON, 0x39);
+
+    const SOCKET_MODE: Self::Address = (RegisterBlock::SOCKET0, 0x00);
+    const SOCKET_COMMAND: Self::Address = (RegisterBlock::SOCKET0, 0x01);
+    const SOCKET_RXBUF_SIZE: Self::Address = (RegisterBlock::SOCKET0, 0x1E);
+    const SOCKET_TXBUF_SIZE: Self::Address = (RegisterBlock::SOCKET0, 0x1F);
+    const SOCKET_TX_FREE_SIZE: Self::Address = (RegisterBlock::SOCKET0, 0x20);
+    const SOCKET_TX_DATA_WRITE_PTR: Self::Address = (RegisterBlock::SOCKET0, 0x24);
+    const SOCKET_RECVD_SIZE: Self::Address = (RegisterBlock::SOCKET0, 0x26);
+    const SOCKET_RX_DATA_READ_PTR: Self::Address = (RegisterBlock::SOCKET0, 0x28);
+    const SOCKET_INTR_MASK: Self::Address = (RegisterBlock::SOCKET0, 0x2C);
+    const SOCKET_INTR: Self::Address = (RegisterBlock::SOCKET0, 0x02);
 
     const SOCKET_MODE_VALUE: u8 = (1 << 2) | (1 << 7);
 
     const BUF_SIZE: u16 = 0x4000;
     const AUTO_WRAP: bool = true;
 
+    const SOCKET_COUNT: u8 = 8;
+    const SOCKET_REGISTERS: super::SocketRegisters = super::W5X00_SOCKET_REGISTERS;
+
     fn rx_addr(addr: u16) -> Self::Address {
-        (RegisterBlock::RxBuf, addr)
+        (RegisterBlock::RX_BUF0, addr)
     }
 
     fn tx_addr(addr: u16) -> Self::Address {
-        (RegisterBlock::TxBuf, addr)
+        (RegisterBlock::TX_BUF0, addr)
+    }
+
+    fn socket_register(socket: u8, offset: u16) -> Self::Address {
+        (RegisterBlock::socket(socket), offset)
+    }
+
+    fn socket_rx_addr(socket: u8, addr: u16) -> Self::Address {
+        (RegisterBlock::rx_buf(socket), addr)
+    }
+
+    fn socket_tx_addr(socket: u8, addr: u16) -> Self::Address {
+        (RegisterBlock::tx_buf(socket), addr)
     }
 
     async fn bus_read<SPI: SpiDevice>(
@@ -52,25 +86,43 @@ impl super::SealedChip for W5500 {
         address: Self::Address,
         data: &mut [u8],
     ) -> Result<(), SPI::Error> {
-        let address_phase = address.1.to_be_bytes();
-        let control_phase = [(address.0 as u8) << 3];
-        let operations = &mut [
-            Operation::Write(&address_phase),
-            Operation::Write(&control_phase),
-            Operation::TransferInPlace(data),
-        ];
-        spi.transaction(operations).await
+        bus_read(spi, address, data).await
     }
 
     async fn bus_write<SPI: SpiDevice>(spi: &mut SPI, address: Self::Address, data: &[u8]) -> Result<(), SPI::Error> {
-        let address_phase = address.1.to_be_bytes();
-        let control_phase = [(address.0 as u8) << 3 | 0b0000_0100];
-        let data_phase = data;
-        let operations = &mut [
-            Operation::Write(&address_phase[..]),
-            Operation::Write(&control_phase),
-            Operation::Write(&data_phase),
-        ];
-        spi.transaction(operations).await
+        bus_write(spi, address, data).await
     }
 }
+
+/// Read with the SPI frame of the W5500, also used by the W6100.
+pub(super) async fn bus_read<SPI: SpiDevice>(
+    spi: &mut SPI,
+    address: (RegisterBlock, u16),
+    data: &mut [u8],
+) -> Result<(), SPI::Error> {
+    let address_phase = address.1.to_be_bytes();
+    let control_phase = [address.0 .0 << 3];
+    let operations = &mut [
+        Operation::Write(&address_phase),
+        Operation::Write(&control_phase),
+        Operation::TransferInPlace(data),
+    ];
+    spi.transaction(operations).await
+}
+
+/// Write with the SPI frame of the W5500, also used by the W6100.
+pub(super) async fn bus_write<SPI: SpiDevice>(
+    spi: &mut SPI,
+    address: (RegisterBlock, u16),
+    data: &[u8],
+) -> Result<(), SPI::Error> {
+    let address_phase = address.1.to_be_bytes();
+    let control_phase = [(address.0 .0 << 3) | 0b0000_0100];
+    let data_phase = data;
+    let operations = &mut [
+        Operation::Write(&address_phase[..]),
+        Operation::Write(&control_phase),
+        Operation::Write(data_phase),
+    ];
+    spi.transaction(operations).await
+}
@@ -0,0 +1,252 @@
+use core::net::SocketAddrV4;
+
+use embedded_hal_async::spi::SpiDevice;
+
+use super::w5500::{self, RegisterBlock};
+
+/// Unlocks the system configuration registers, for the reset.
+const CHIP_LOCK: (RegisterBlock, u16) = (RegisterBlock::COMMON, 0x41F4);
+const CHIP_UNLOCK_VALUE: u8 = 0xCE;
+/// Unlocks the network configuration registers: MAC, IP, subnet mask and gateway.
+const NET_LOCK: (RegisterBlock, u16) = (RegisterBlock::COMMON, 0x41F5);
+const NET_UNLOCK_VALUE: u8 = 0x3A;
+
+/// Wiznet W6100 chip.
+pub enum W6100 {}
+
+impl super::Chip for W6100 {}
+impl super::SealedChip for W6100 {
+    type Address = (RegisterBlock, u16);
+
+    /// High byte of the CIDR register.
+    const CHIP_VERSION: u8 = 0x61;
+
+    /// SYCR0, resetting the chip when its top bit is cleared.
+    const COMMON_MODE: Self::Address = (RegisterBlock::COMMON, 0x2004);
+    const COMMON_GATEWAY: Self::Address = (RegisterBlock::COMMON, 0x4130);
+    const COMMON_SUBNET_MASK: Self::Address = (RegisterBlock::COMMON, 0x4134);
+    const COMMON_MAC: Self::Address = (RegisterBlock::COMMON, 0x4120);
+    const COMMON_IP: Self::Address = (RegisterBlock::COMMON, 0x4138);
+    const COMMON_SOCKET_INTR_STATUS: Self::Address = (RegisterBlock::COMMON, 0x2101);
+    const COMMON_SOCKET_INTR: Self::Address = (RegisterBlock::COMMON, 0x2114);
+    const COMMON_PHY_CFG: Self::Address = (RegisterBlock::COMMON, 0x3000);
+    const COMMON_VERSION: Self::Address = (RegisterBlock::COMMON, 0x0000);
+
+    const SOCKET_MODE: Self::Address = (RegisterBlock::SOCKET0, 0x0000);
+    const SOCKET_COMMAND: Self::Address = (RegisterBlock::SOCKET0, 0x0010);
+    const SOCKET_RXBUF_SIZE: Self::Address = (RegisterBlock::SOCKET0, 0x0220);
+    const SOCKET_TXBUF_SIZE: Self::Address = (RegisterBlock::SOCKET0, 0x0200);
+    const SOCKET_TX_FREE_SIZE: Self::Address = (RegisterBlock::SOCKET0, 0x0204);
+    const SOCKET_TX_DATA_WRITE_PTR: Self::Address = (RegisterBlock::SOCKET0, 0x020C);
+    const SOCKET_RECVD_SIZE: Self::Address = (RegisterBlock::SOCKET0, 0x0224);
+    const SOCKET_RX_DATA_READ_PTR: Self::Address = (RegisterBlock::SOCKET0, 0x0228);
+    const SOCKET_INTR_MASK: Self::Address = (RegisterBlock::SOCKET0, 0x0024);
+    const SOCKET_INTR: Self::Address = (RegisterBlock::SOCKET0, 0x0028);
+
+    /// MACRAW mode with MAC filtering. IPv6 and multicast frames aren't blocked.
+    const SOCKET_MODE_VALUE: u8 = 0x07 | (1 << 7);
+
+    const BUF_SIZE: u16 = 0x4000;
+    const AUTO_WRAP: bool = true;
+
+    const SOCKET_COUNT: u8 = 8;
+    const SOCKET_REGISTERS: super::SocketRegisters = super::SocketRegisters {
+        mode: 0x0000,
+        command: 0x0010,
+        intr: 0x0020,
+        intr_clear: 0x0028,
+        status: 0x0030,
+        port: 0x0114,
+        dest_ip: 0x0120,
+        dest_port: 0x0140,
+        rxbuf_size: 0x0220,
+        txbuf_size: 0x0200,
+        tx_free_size: 0x0204,
+        tx_data_write_ptr: 0x020C,
+        recvd_size: 0x0224,
+        rx_data_read_ptr: 0x0228,
+    };
+
+    fn rx_addr(addr: u16) -> Self::Address {
+        (RegisterBlock::RX_BUF0, addr)
+    }
+
+    fn tx_addr(addr: u16) -> Self::Address {
+        (RegisterBlock::TX_BUF0, addr)
+    }
+
+    fn socket_register(socket: u8, offset: u16) -> Self::Address {
+        (RegisterBlock::socket(socket), offset)
+    }
+
+    fn socket_rx_addr(socket: u8, addr: u16) -> Self::Address {
+        (RegisterBlock::rx_buf(socket), addr)
+    }
+
+    fn socket_tx_addr(socket: u8, addr: u16) -> Self::Address {
+        (RegisterBlock::tx_buf(socket), addr)
+    }
+
+    async fn soft_reset<SPI: SpiDevice>(spi: &mut SPI) -> Result<(), SPI::Error> {
+        Self::bus_write(spi, CHIP_LOCK, &[CHIP_UNLOCK_VALUE]).await?;
+        Self::bus_write(spi, Self::COMMON_MODE, &[0x00]).await?;
+        // The reset locks the registers again.
+        Self::bus_write(spi, NET_LOCK, &[NET_UNLOCK_VALUE]).await
+    }
+
+    /// The W6100 puts the length first, with packet info in its top bits.
+    fn udp_header(header: &[u8; 8]) -> (SocketAddrV4, u16) {
+        let address = SocketAddrV4::new(
+            [header[2], header[3], header[4], header[5]].into(),
+            u16::from_be_bytes([header[6], header[7]]),
+        );
+        (address, u16::from_be_bytes([header[0] & 0x07, header[1]]))
+    }
+
+    async fn bus_read<SPI: SpiDevice>(
+        spi: &mut SPI,
+        address: Self::Address,
+        data: &mut [u8],
+    ) -> Result<(), SPI::Error> {
+        w5500::bus_read(spi, address, data).await
+    }
+
+    async fn bus_write<SPI: SpiDevice>(spi: &mut SPI, address: Self::Address, data: &[u8]) -> Result<(), SPI::Error> {
+        w5500::bus_write(spi, address, data).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embedded_hal_mock::eh1::spi::{Mock as SpiMock, Transaction as SpiTransaction};
+
+    use super::*;
+    use crate::chip::SealedChip;
+    use crate::device::WiznetDevice;
+
+    const MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 1];
+
+    fn read(block: u8, address: u16, response: &[u8]) -> Vec<SpiTransaction<u8>> {
+        vec![
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write_vec(address.to_be_bytes().to_vec()),
+            SpiTransaction::write_vec(vec![block << 3]),
+            SpiTransaction::transfer_in_place(vec![0; response.len()], response.to_vec()),
+            SpiTransaction::transaction_end(),
+        ]
+    }
+
+    fn write(block: u8, address: u16, data: &[u8]) -> Vec<SpiTransaction<u8>> {
+        vec![
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write_vec(address.to_be_bytes().to_vec()),
+            SpiTransaction::write_vec(vec![(block << 3) | 0b100]),
+            SpiTransaction::write_vec(data.to_vec()),
+            SpiTransaction::transaction_end(),
+        ]
+    }
+
+    fn macraw_setup() -> Vec<SpiTransaction<u8>> {
+        [
+            // Reset, then unlock the network registers.
+            write(0, 0x41F4, &[0xCE]),
+            write(0, 0x2004, &[0x00]),
+            write(0, 0x41F5, &[0x3A]),
+            // CIDR
+            read(0, 0x0000, &[0x61]),
+            // Interrupts of socket 0, on receive.
+            write(0, 0x2114, &[0x01]),
+            write(1, 0x0024, &[0x04]),
+            write(0, 0x4120, &MAC),
+            // All 16 KiB of buffers for socket 0.
+            write(1, 0x0200, &[16]),
+            write(1, 0x0220, &[16]),
+            // MACRAW with MAC filtering, then OPEN.
+            write(1, 0x0000, &[0x87]),
+            write(1, 0x0010, &[0x01]),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn macraw_setup_and_link() {
+        let expectations = [macraw_setup(), read(0, 0x3000, &[0x01]), read(0, 0x3000, &[0x80])].concat();
+
+        let mut spi = SpiMock::new(&expectations);
+        let mut device = block_on(WiznetDevice::<W6100, _>::new(spi.clone(), MAC)).unwrap();
+        assert!(block_on(device.is_link_up()));
+        // Cable unplugged.
+        assert!(!block_on(device.is_link_up()));
+        spi.done();
+    }
+
+    #[test]
+    fn invalid_chip_version() {
+        let expectations = [
+            write(0, 0x41F4, &[0xCE]),
+            write(0, 0x2004, &[0x00]),
+            write(0, 0x41F5, &[0x3A]),
+            // A W5500 answering at the same address.
+            read(0, 0x0000, &[0x00]),
+        ]
+        .concat();
+
+        let mut spi = SpiMock::new(&expectations);
+        assert!(block_on(WiznetDevice::<W6100, _>::new(spi.clone(), MAC)).is_err());
+        spi.done();
+    }
+
+    #[test]
+    fn read_frame() {
+        let frame: Vec<u8> = (0..60).collect();
+        let expectations = [
+            macraw_setup(),
+            read(1, 0x0224, &[0x00, 0x3E]),
+            read(1, 0x0224, &[0x00, 0x3E]),
+            // Clear the receive interrupt.
+            write(1, 0x0028, &[0x04]),
+            read(1, 0x0228, &[0x01, 0x00]),
+            // Length including itself, with packet info in the top bits.
+            read(3, 0x0100, &[0x40, 0x3E]),
+            read(3, 0x0102, &frame),
+            write(1, 0x0228, &[0x01, 0x3E]),
+            write(1, 0x0010, &[0x40]),
+        ]
+        .concat();
+
+        let mut spi = SpiMock::new(&expectations);
+        let mut device = block_on(WiznetDevice::<W6100, _>::new(spi.clone(), MAC)).unwrap();
+        let mut buf = [0; 1514];
+        let n = block_on(device.read_frame(&mut buf)).unwrap();
+        assert_eq!(&buf[..n], &frame[..]);
+        spi.done();
+    }
+
+    #[test]
+    fn write_frame() {
+        let frame: Vec<u8> = (0..60).collect();
+        let expectations = [
+            macraw_setup(),
+            read(1, 0x0204, &[0x40, 0x00]),
+            read(1, 0x020C, &[0x00, 0x20]),
+            write(2, 0x0020, &frame),
+            write(1, 0x020C, &[0x00, 0x5C]),
+            write(1, 0x0010, &[0x20]),
+        ]
+        .concat();
+
+        let mut spi = SpiMock::new(&expectations);
+        let mut device = block_on(WiznetDevice::<W6100, _>::new(spi.clone(), MAC)).unwrap();
+        assert_eq!(block_on(device.write_frame(&frame)).unwrap(), 60);
+        spi.done();
+    }
+
+    #[test]
+    fn udp_header() {
+        let header = [0x00, 0x05, 192, 168, 1, 10, 0x00, 0x35];
+        let (remote, len) = W6100::udp_header(&header);
+        assert_eq!(remote, SocketAddrV4::new([192, 168, 1, 10].into(), 53));
+        assert_eq!(len, 5);
+    }
+}
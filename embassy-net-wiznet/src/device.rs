@@ -81,7 +81,7 @@ impl<C: Chip, SPI: SpiDevice> WiznetDevice<C, SPI> {
         };
 
         // Reset device
-        this.bus_write(C::COMMON_MODE, &[0x80]).await?;
+        C::soft_reset(&mut this.spi).await?;
 
         // Check the version of the chip
         let mut version = [0];
@@ -205,11 +205,12 @@ impl<C: Chip, SPI: SpiDevice> WiznetDevice<C, SPI> {
 
         let mut read_ptr = self.get_rx_read_ptr().await?;
 
-        // First two bytes gives the size of the received ethernet frame
+        // First two bytes gives the size of the received ethernet frame, in the low 11 bits.
+        // The W6100 keeps packet info in the others.
         let expected_frame_size: usize = {
             let mut frame_bytes = [0u8; 2];
             self.read_bytes(&mut read_ptr, &mut frame_bytes).await?;
-            u16::from_be_bytes(frame_bytes) as usize - 2
+            (u16::from_be_bytes(frame_bytes) & 0x07FF) as usize - 2
         };
 
         // Read the ethernet frame
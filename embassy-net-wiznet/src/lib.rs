@@ -1,10 +1,11 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![allow(async_fn_in_trait)]
 #![doc = include_str!("../README.md")]
 #![warn(missing_docs)]
 
 pub mod chip;
 mod device;
+pub mod offload;
 
 use embassy_futures::select::{select3, Either3};
 use embassy_net_driver_channel as ch;
@@ -107,15 +108,7 @@ pub async fn new<'a, const N_RX: usize, const N_TX: usize, C: Chip, SPI: SpiDevi
     int: INT,
     mut reset: RST,
 ) -> Result<(Device<'a>, Runner<'a, C, SPI, INT, RST>), InitError<SPI::Error>> {
-    // Reset the chip.
-    reset.set_low().ok();
-    // Ensure the reset is registered.
-    Timer::after_millis(1).await;
-    reset.set_high().ok();
-
-    // Wait for PLL lock. Some chips are slower than others.
-    // Slowest is w5100s which is 100ms, so let's just wait that.
-    Timer::after_millis(100).await;
+    hard_reset(&mut reset).await;
 
     let mac = WiznetDevice::new(spi_dev, mac_addr).await?;
 
@@ -131,3 +124,15 @@ pub async fn new<'a, const N_RX: usize, const N_TX: usize, C: Chip, SPI: SpiDevi
         },
     ))
 }
+
+/// Reset the chip with its reset pin.
+async fn hard_reset<RST: OutputPin>(reset: &mut RST) {
+    reset.set_low().ok();
+    // Ensure the reset is registered.
+    Timer::after_millis(1).await;
+    reset.set_high().ok();
+
+    // Wait for PLL lock. Some chips are slower than others.
+    // Slowest is w5100s which is 100ms, so let's just wait that.
+    Timer::after_millis(100).await;
+}
@@ -0,0 +1,605 @@
+//! Hardware socket offload.
+//!
+//! Instead of running the chip in MACRAW mode under `embassy-net`, this drives its own TCP/IP
+//! stack: each connection uses one of the hardware sockets (8 on the W5500 and W6100, 4 on the
+//! W5100S), with its buffers in the chip. The [`Stack`] implements the [`embedded-nal-async`]
+//! traits, so no `smoltcp` or packet buffers are needed on the MCU. Only IPv4 with a static
+//! configuration is supported.
+//!
+//! The chip's interrupt pin signals socket events, which the [`Runner`] dispatches to the
+//! sockets waiting for them.
+//!
+//! ```ignore
+//! use embassy_net_wiznet::offload;
+//! use embedded_nal_async::TcpConnect;
+//!
+//! static STATE: StaticCell<offload::State<W5500, MySpi>> = StaticCell::new();
+//! let config = offload::Config::new(Ipv4Addr::new(192, 168, 1, 2), 24, Some(Ipv4Addr::new(192, 168, 1, 1)));
+//! let (stack, runner) = offload::new(mac_addr, config, STATE.init(offload::State::new()), spi, int, reset).await?;
+//! spawner.spawn(offload_task(runner));
+//!
+//! let mut connection = stack.connect(SocketAddr::new(Ipv4Addr::new(192, 168, 1, 10).into(), 1234)).await?;
+//! connection.write_all(b"Hello world!\n").await?;
+//! ```
+//!
+//! [`embedded-nal-async`]: embedded_nal_async
+
+mod tcp;
+#[cfg(test)]
+mod tests;
+mod udp;
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::net::Ipv4Addr;
+use core::task::Poll;
+
+use embassy_futures::select::select;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_sync::waitqueue::WakerRegistration;
+use embedded_hal::digital::OutputPin;
+use embedded_hal_async::digital::Wait;
+use embedded_hal_async::spi::SpiDevice;
+
+use crate::chip::Chip;
+use crate::InitError;
+pub use tcp::TcpConnection;
+pub use udp::UdpSocket;
+
+/// Most sockets of the supported chips.
+const MAX_SOCKETS: usize = 8;
+/// First port of the IANA ephemeral range, used for local ports.
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+/// Socket mode register values.
+mod mode {
+    pub const TCP: u8 = 0x01;
+    pub const UDP: u8 = 0x02;
+}
+
+/// Socket status register values.
+mod status {
+    pub const CLOSED: u8 = 0x00;
+    pub const INIT: u8 = 0x13;
+    pub const ESTABLISHED: u8 = 0x17;
+    pub const CLOSE_WAIT: u8 = 0x1C;
+    pub const UDP: u8 = 0x22;
+}
+
+/// Socket interrupt register bits.
+mod intr {
+    pub const CONNECTED: u8 = 0x01;
+    pub const DISCONNECTED: u8 = 0x02;
+    pub const RECEIVED: u8 = 0x04;
+    pub const TIMEOUT: u8 = 0x08;
+    pub const SEND_OK: u8 = 0x10;
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy)]
+enum Command {
+    Open = 0x01,
+    Listen = 0x02,
+    Connect = 0x04,
+    Disconnect = 0x08,
+    Close = 0x10,
+    Send = 0x20,
+    Receive = 0x40,
+}
+
+/// IPv4 configuration of the chip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Config {
+    /// Address of the chip.
+    pub address: Ipv4Addr,
+    /// Length of the network prefix, 24 for a `255.255.255.0` subnet mask.
+    pub prefix_len: u8,
+    /// Default gateway, for destinations outside the subnet.
+    pub gateway: Option<Ipv4Addr>,
+}
+
+impl Config {
+    /// Create a configuration.
+    pub const fn new(address: Ipv4Addr, prefix_len: u8, gateway: Option<Ipv4Addr>) -> Self {
+        Self {
+            address,
+            prefix_len,
+            gateway,
+        }
+    }
+
+    fn subnet_mask(&self) -> [u8; 4] {
+        match u32::MAX.checked_shl(32 - self.prefix_len.min(32) as u32) {
+            Some(mask) => mask.to_be_bytes(),
+            None => [0; 4],
+        }
+    }
+}
+
+/// Error of the offloaded sockets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<SE> {
+    /// Error occurred when sending or receiving SPI data
+    Spi(SE),
+    /// All the hardware sockets are in use.
+    NoFreeSocket,
+    /// The address isn't IPv4.
+    AddressNotSupported,
+    /// The remote host refused the connection.
+    ConnectionRefused,
+    /// The connection was closed by the remote host or timed out.
+    ConnectionReset,
+    /// The remote host, or the gateway to it, didn't answer.
+    TimedOut,
+    /// The datagram doesn't fit in the socket buffer.
+    MessageTooLong,
+}
+
+impl<SE> From<SE> for Error<SE> {
+    fn from(e: SE) -> Self {
+        Error::Spi(e)
+    }
+}
+
+impl<SE: core::fmt::Debug> embedded_io_async::Error for Error<SE> {
+    fn kind(&self) -> embedded_io_async::ErrorKind {
+        use embedded_io_async::ErrorKind;
+        match self {
+            Error::Spi(_) => ErrorKind::Other,
+            Error::NoFreeSocket => ErrorKind::OutOfMemory,
+            Error::AddressNotSupported => ErrorKind::Unsupported,
+            Error::ConnectionRefused => ErrorKind::ConnectionRefused,
+            Error::ConnectionReset => ErrorKind::ConnectionReset,
+            Error::TimedOut => ErrorKind::TimedOut,
+            Error::MessageTooLong => ErrorKind::InvalidInput,
+        }
+    }
+}
+
+/// Internal state for the offloaded stack.
+pub struct State<C: Chip, SPI: SpiDevice> {
+    shared: Option<Shared<C, SPI>>,
+}
+
+impl<C: Chip, SPI: SpiDevice> State<C, SPI> {
+    /// Create a new `State`.
+    pub const fn new() -> Self {
+        Self { shared: None }
+    }
+}
+
+impl<C: Chip, SPI: SpiDevice> Default for State<C, SPI> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Handle to the chip's TCP/IP stack.
+///
+/// Connect with [`TcpConnect`](embedded_nal_async::TcpConnect), accept connections with
+/// [`accept`](Self::accept) and bind UDP sockets with [`bind_udp`](Self::bind_udp).
+pub struct Stack<'d, C: Chip, SPI: SpiDevice> {
+    shared: &'d Shared<C, SPI>,
+}
+
+impl<C: Chip, SPI: SpiDevice> Clone for Stack<'_, C, SPI> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C: Chip, SPI: SpiDevice> Copy for Stack<'_, C, SPI> {}
+
+impl<C: Chip, SPI: SpiDevice> Stack<'_, C, SPI> {
+    /// Get whether the link is up.
+    pub async fn is_link_up(&self) -> bool {
+        let mut link = [0];
+        let mut bus = self.shared.bus.lock().await;
+        bus.read(C::COMMON_PHY_CFG, &mut link).await.ok();
+        link[0] & 1 == 1
+    }
+
+    /// Get the IPv4 configuration.
+    pub fn config(&self) -> Config {
+        self.shared.sockets.lock(|s| s.borrow().config)
+    }
+
+    /// Change the IPv4 configuration, for example to apply a DHCP lease.
+    pub async fn set_config(&self, config: Config) -> Result<(), SPI::Error> {
+        self.shared.bus.lock().await.set_config(&config).await?;
+        self.shared.sockets.lock(|s| s.borrow_mut().config = config);
+        Ok(())
+    }
+}
+
+/// Background runner for the offloaded stack.
+///
+/// You must call `.run()` in a background task for the sockets to operate.
+pub struct Runner<'d, C: Chip, SPI: SpiDevice, INT: Wait, RST: OutputPin> {
+    shared: &'d Shared<C, SPI>,
+    int: INT,
+    _reset: RST,
+}
+
+impl<C: Chip, SPI: SpiDevice, INT: Wait, RST: OutputPin> Runner<'_, C, SPI, INT, RST> {
+    /// Run the stack.
+    pub async fn run(mut self) -> ! {
+        loop {
+            self.shared.poll().await.ok();
+            select(self.int.wait_for_low(), self.shared.close.wait()).await;
+        }
+    }
+}
+
+/// Create a Wiznet ethernet chip driver using the chip's TCP/IP stack.
+///
+/// This returns two structs:
+/// - a [`Stack`] to open sockets with.
+/// - a [`Runner`]. You must call `.run()` on it in a background task.
+pub async fn new<C: Chip, SPI: SpiDevice, INT: Wait, RST: OutputPin>(
+    mac_addr: [u8; 6],
+    config: Config,
+    state: &mut State<C, SPI>,
+    spi_dev: SPI,
+    int: INT,
+    mut reset: RST,
+) -> Result<(Stack<'_, C, SPI>, Runner<'_, C, SPI, INT, RST>), InitError<SPI::Error>> {
+    crate::hard_reset(&mut reset).await;
+
+    let mut bus = Bus::new(spi_dev);
+    bus.init(mac_addr, &config).await?;
+
+    let shared = &*state.shared.insert(Shared::new(bus, config));
+    Ok((
+        Stack { shared },
+        Runner {
+            shared,
+            int,
+            _reset: reset,
+        },
+    ))
+}
+
+/// Register access to the chip.
+struct Bus<C, SPI> {
+    spi: SPI,
+    _phantom: PhantomData<C>,
+}
+
+impl<C: Chip, SPI: SpiDevice> Bus<C, SPI> {
+    fn new(spi: SPI) -> Self {
+        Self {
+            spi,
+            _phantom: PhantomData,
+        }
+    }
+
+    async fn init(&mut self, mac_addr: [u8; 6], config: &Config) -> Result<(), InitError<SPI::Error>> {
+        // Reset device
+        C::soft_reset(&mut self.spi).await?;
+
+        // Check the version of the chip
+        let mut version = [0];
+        self.read(C::COMMON_VERSION, &mut version).await?;
+        if version[0] != C::CHIP_VERSION {
+            #[cfg(feature = "defmt")]
+            defmt::error!("invalid chip version: {} (expected {})", version[0], C::CHIP_VERSION);
+            return Err(InitError::InvalidChipVersion {
+                actual: version[0],
+                expected: C::CHIP_VERSION,
+            });
+        }
+
+        self.write(C::COMMON_MAC, &mac_addr).await?;
+        self.set_config(config).await?;
+
+        // Split the buffers evenly between the sockets.
+        let buf_kbs = (C::BUF_SIZE / C::SOCKET_COUNT as u16 / 1024) as u8;
+        for n in 0..C::SOCKET_COUNT {
+            self.socket_write(n, C::SOCKET_REGISTERS.rxbuf_size, &[buf_kbs]).await?;
+            self.socket_write(n, C::SOCKET_REGISTERS.txbuf_size, &[buf_kbs]).await?;
+        }
+
+        // Enable the interrupt pin for all sockets.
+        let mask = ((1u16 << C::SOCKET_COUNT) - 1) as u8;
+        self.write(C::COMMON_SOCKET_INTR, &[mask]).await?;
+
+        Ok(())
+    }
+
+    async fn set_config(&mut self, config: &Config) -> Result<(), SPI::Error> {
+        let gateway = config.gateway.unwrap_or(Ipv4Addr::UNSPECIFIED);
+        self.write(C::COMMON_GATEWAY, &gateway.octets()).await?;
+        self.write(C::COMMON_SUBNET_MASK, &config.subnet_mask()).await?;
+        self.write(C::COMMON_IP, &config.address.octets()).await
+    }
+
+    async fn read(&mut self, address: C::Address, data: &mut [u8]) -> Result<(), SPI::Error> {
+        C::bus_read(&mut self.spi, address, data).await
+    }
+
+    async fn write(&mut self, address: C::Address, data: &[u8]) -> Result<(), SPI::Error> {
+        C::bus_write(&mut self.spi, address, data).await
+    }
+
+    async fn socket_read(&mut self, socket: u8, offset: u16, data: &mut [u8]) -> Result<(), SPI::Error> {
+        self.read(C::socket_register(socket, offset), data).await
+    }
+
+    async fn socket_write(&mut self, socket: u8, offset: u16, data: &[u8]) -> Result<(), SPI::Error> {
+        self.write(C::socket_register(socket, offset), data).await
+    }
+
+    async fn read_u16(&mut self, socket: u8, offset: u16) -> Result<u16, SPI::Error> {
+        let mut data = [0; 2];
+        self.socket_read(socket, offset, &mut data).await?;
+        Ok(u16::from_be_bytes(data))
+    }
+
+    async fn write_u16(&mut self, socket: u8, offset: u16, value: u16) -> Result<(), SPI::Error> {
+        self.socket_write(socket, offset, &value.to_be_bytes()).await
+    }
+
+    /// Read a size register the chip is updating.
+    async fn read_size(&mut self, socket: u8, offset: u16) -> Result<u16, SPI::Error> {
+        loop {
+            // Wait until two sequential reads are equal
+            let size = self.read_u16(socket, offset).await?;
+            if self.read_u16(socket, offset).await? == size {
+                break Ok(size);
+            }
+        }
+    }
+
+    async fn status(&mut self, socket: u8) -> Result<u8, SPI::Error> {
+        let mut status = [0];
+        self.socket_read(socket, C::SOCKET_REGISTERS.status, &mut status)
+            .await?;
+        Ok(status[0])
+    }
+
+    /// Run a socket command, waiting until the chip accepted it.
+    async fn command(&mut self, socket: u8, command: Command) -> Result<(), SPI::Error> {
+        self.socket_write(socket, C::SOCKET_REGISTERS.command, &[command as u8])
+            .await?;
+        let mut pending = [0];
+        loop {
+            self.socket_read(socket, C::SOCKET_REGISTERS.command, &mut pending)
+                .await?;
+            if pending[0] == 0 {
+                break Ok(());
+            }
+        }
+    }
+
+    /// Read bytes from the RX buffer of `socket`, advancing `read_ptr`.
+    async fn read_bytes(&mut self, socket: u8, read_ptr: &mut u16, buffer: &mut [u8]) -> Result<(), SPI::Error> {
+        if C::AUTO_WRAP {
+            self.read(C::socket_rx_addr(socket, *read_ptr), buffer).await?;
+        } else {
+            let n = buffer.len().min(buffer_remaining::<C>(*read_ptr));
+            self.read(C::socket_rx_addr(socket, *read_ptr), &mut buffer[..n])
+                .await?;
+            if n < buffer.len() {
+                self.read(C::socket_rx_addr(socket, 0), &mut buffer[n..]).await?;
+            }
+        }
+        *read_ptr = (*read_ptr).wrapping_add(buffer.len() as u16);
+        Ok(())
+    }
+
+    /// Write bytes to the TX buffer of `socket`, advancing `write_ptr`.
+    async fn write_bytes(&mut self, socket: u8, write_ptr: &mut u16, data: &[u8]) -> Result<(), SPI::Error> {
+        if C::AUTO_WRAP {
+            self.write(C::socket_tx_addr(socket, *write_ptr), data).await?;
+        } else {
+            let n = data.len().min(buffer_remaining::<C>(*write_ptr));
+            self.write(C::socket_tx_addr(socket, *write_ptr), &data[..n]).await?;
+            if n < data.len() {
+                self.write(C::socket_tx_addr(socket, 0), &data[n..]).await?;
+            }
+        }
+        *write_ptr = (*write_ptr).wrapping_add(data.len() as u16);
+        Ok(())
+    }
+}
+
+/// Size of the buffers of each socket.
+const fn socket_buf_size<C: Chip>() -> u16 {
+    C::BUF_SIZE / C::SOCKET_COUNT as u16
+}
+
+/// Bytes from `ptr` to the end of a socket buffer.
+fn buffer_remaining<C: Chip>(ptr: u16) -> usize {
+    (socket_buf_size::<C>() - ptr % socket_buf_size::<C>()) as usize
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SlotState {
+    Free,
+    Open,
+    /// Dropped, waiting for the runner to close it.
+    Closing,
+    /// Dropped while connected, waiting for the remote host to acknowledge the disconnection.
+    Disconnecting,
+}
+
+struct Slot {
+    state: SlotState,
+    /// Interrupts received since the owner last took them.
+    flags: u8,
+    waker: WakerRegistration,
+}
+
+struct Sockets {
+    slots: [Slot; MAX_SOCKETS],
+    next_port: u16,
+    config: Config,
+}
+
+struct Shared<C: Chip, SPI: SpiDevice> {
+    bus: Mutex<NoopRawMutex, Bus<C, SPI>>,
+    sockets: BlockingMutex<NoopRawMutex, RefCell<Sockets>>,
+    /// Wakes the runner to close dropped sockets.
+    close: Signal<NoopRawMutex, ()>,
+}
+
+impl<C: Chip, SPI: SpiDevice> Shared<C, SPI> {
+    fn new(bus: Bus<C, SPI>, config: Config) -> Self {
+        Self {
+            bus: Mutex::new(bus),
+            sockets: BlockingMutex::new(RefCell::new(Sockets {
+                slots: [const {
+                    Slot {
+                        state: SlotState::Free,
+                        flags: 0,
+                        waker: WakerRegistration::new(),
+                    }
+                }; MAX_SOCKETS],
+                next_port: EPHEMERAL_PORT_START,
+                config,
+            })),
+            close: Signal::new(),
+        }
+    }
+
+    /// Reserve a free hardware socket.
+    fn alloc(&self) -> Result<Socket<'_, C, SPI>, Error<SPI::Error>> {
+        self.sockets.lock(|s| {
+            let mut s = s.borrow_mut();
+            let n = s.slots[..C::SOCKET_COUNT as usize]
+                .iter()
+                .position(|slot| slot.state == SlotState::Free)
+                .ok_or(Error::NoFreeSocket)?;
+            s.slots[n].state = SlotState::Open;
+            s.slots[n].flags = 0;
+            Ok(Socket {
+                shared: self,
+                n: n as u8,
+            })
+        })
+    }
+
+    fn ephemeral_port(&self) -> u16 {
+        self.sockets.lock(|s| {
+            let mut s = s.borrow_mut();
+            let port = s.next_port;
+            s.next_port = port.checked_add(1).unwrap_or(EPHEMERAL_PORT_START);
+            port
+        })
+    }
+
+    fn local_address(&self) -> Ipv4Addr {
+        self.sockets.lock(|s| s.borrow().config.address)
+    }
+
+    /// Handle the socket interrupts and close the dropped sockets.
+    async fn poll(&self) -> Result<(), SPI::Error> {
+        let mut bus = self.bus.lock().await;
+        let mut pending = [0];
+        bus.read(C::COMMON_SOCKET_INTR_STATUS, &mut pending).await?;
+        for n in 0..C::SOCKET_COUNT {
+            if pending[0] & (1 << n) != 0 {
+                let mut flags = [0];
+                bus.socket_read(n, C::SOCKET_REGISTERS.intr, &mut flags).await?;
+                bus.socket_write(n, C::SOCKET_REGISTERS.intr_clear, &flags).await?;
+                self.sockets.lock(|s| {
+                    let slot = &mut s.borrow_mut().slots[n as usize];
+                    slot.flags |= flags[0];
+                    slot.waker.wake();
+                });
+            }
+
+            let (state, flags) = self.sockets.lock(|s| {
+                let slot = &s.borrow().slots[n as usize];
+                (slot.state, slot.flags)
+            });
+            let next = match state {
+                SlotState::Closing => match bus.status(n).await? {
+                    status::ESTABLISHED | status::CLOSE_WAIT => {
+                        bus.command(n, Command::Disconnect).await?;
+                        SlotState::Disconnecting
+                    }
+                    _ => {
+                        bus.command(n, Command::Close).await?;
+                        SlotState::Free
+                    }
+                },
+                SlotState::Disconnecting
+                    if flags & (intr::DISCONNECTED | intr::TIMEOUT) != 0 || bus.status(n).await? == status::CLOSED =>
+                {
+                    bus.command(n, Command::Close).await?;
+                    SlotState::Free
+                }
+                state => state,
+            };
+            self.sockets.lock(|s| s.borrow_mut().slots[n as usize].state = next);
+        }
+        Ok(())
+    }
+}
+
+/// A reserved hardware socket, closed in the background when dropped.
+struct Socket<'d, C: Chip, SPI: SpiDevice> {
+    shared: &'d Shared<C, SPI>,
+    n: u8,
+}
+
+impl<C: Chip, SPI: SpiDevice> Socket<'_, C, SPI> {
+    /// Open the socket in `mode` on the local `port`, or an ephemeral one if it's 0.
+    async fn open(&self, mode: u8, port: u16, expected: u8) -> Result<u16, Error<SPI::Error>> {
+        let port = match port {
+            0 => self.shared.ephemeral_port(),
+            port => port,
+        };
+        let mut bus = self.shared.bus.lock().await;
+        bus.socket_write(self.n, C::SOCKET_REGISTERS.mode, &[mode]).await?;
+        bus.write_u16(self.n, C::SOCKET_REGISTERS.port, port).await?;
+        bus.command(self.n, Command::Open).await?;
+        if bus.status(self.n).await? != expected {
+            return Err(Error::ConnectionReset);
+        }
+        Ok(port)
+    }
+
+    /// Clear the interrupts in `mask`, before checking what they signal.
+    fn clear_flags(&self, mask: u8) {
+        self.shared
+            .sockets
+            .lock(|s| s.borrow_mut().slots[self.n as usize].flags &= !mask);
+    }
+
+    /// Wait for an interrupt in `mask`, returning the ones received.
+    ///
+    /// They aren't cleared, so the next waiter sees them too.
+    async fn wait_flags(&self, mask: u8) -> u8 {
+        poll_fn(|cx| {
+            self.shared.sockets.lock(|s| {
+                let slot = &mut s.borrow_mut().slots[self.n as usize];
+                match slot.flags & mask {
+                    0 => {
+                        slot.waker.register(cx.waker());
+                        Poll::Pending
+                    }
+                    flags => Poll::Ready(flags),
+                }
+            })
+        })
+        .await
+    }
+}
+
+impl<C: Chip, SPI: SpiDevice> Drop for Socket<'_, C, SPI> {
+    fn drop(&mut self) {
+        self.shared
+            .sockets
+            .lock(|s| s.borrow_mut().slots[self.n as usize].state = SlotState::Closing);
+        self.shared.close.signal(());
+    }
+}
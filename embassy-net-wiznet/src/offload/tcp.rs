@@ -0,0 +1,141 @@
+use core::net::SocketAddr;
+
+use embassy_time::Timer;
+use embedded_hal_async::spi::SpiDevice;
+use embedded_nal_async::TcpConnect;
+
+use super::{intr, mode, status, Command, Error, Socket, Stack};
+use crate::chip::Chip;
+
+/// A TCP connection on a hardware socket.
+///
+/// Dropping it closes the connection gracefully in the background, then frees the socket.
+pub struct TcpConnection<'d, C: Chip, SPI: SpiDevice> {
+    pub(super) socket: Socket<'d, C, SPI>,
+}
+
+impl<'d, C: Chip, SPI: SpiDevice> Stack<'d, C, SPI> {
+    /// Wait for a connection on the local `port`.
+    ///
+    /// This uses a hardware socket until a connection is accepted, for as long as it's open.
+    pub async fn accept(&self, port: u16) -> Result<TcpConnection<'d, C, SPI>, Error<SPI::Error>> {
+        let socket = self.shared.alloc()?;
+        socket.open(mode::TCP, port, status::INIT).await?;
+
+        let events = intr::CONNECTED | intr::DISCONNECTED | intr::TIMEOUT;
+        socket.clear_flags(events);
+        self.shared.bus.lock().await.command(socket.n, Command::Listen).await?;
+        if socket.wait_flags(events).await & intr::CONNECTED == 0 {
+            return Err(Error::ConnectionReset);
+        }
+        Ok(TcpConnection { socket })
+    }
+}
+
+impl<C: Chip, SPI: SpiDevice> TcpConnect for Stack<'_, C, SPI> {
+    type Error = Error<SPI::Error>;
+    type Connection<'a>
+        = TcpConnection<'a, C, SPI>
+    where
+        Self: 'a;
+
+    async fn connect(&self, remote: SocketAddr) -> Result<Self::Connection<'_>, Self::Error> {
+        let SocketAddr::V4(remote) = remote else {
+            return Err(Error::AddressNotSupported);
+        };
+        let socket = self.shared.alloc()?;
+        socket.open(mode::TCP, 0, status::INIT).await?;
+
+        let events = intr::CONNECTED | intr::DISCONNECTED | intr::TIMEOUT;
+        socket.clear_flags(events);
+        {
+            let mut bus = self.shared.bus.lock().await;
+            bus.socket_write(socket.n, C::SOCKET_REGISTERS.dest_ip, &remote.ip().octets())
+                .await?;
+            bus.write_u16(socket.n, C::SOCKET_REGISTERS.dest_port, remote.port())
+                .await?;
+            bus.command(socket.n, Command::Connect).await?;
+        }
+
+        let flags = socket.wait_flags(events).await;
+        if flags & intr::CONNECTED != 0 {
+            Ok(TcpConnection { socket })
+        } else if flags & intr::DISCONNECTED != 0 {
+            Err(Error::ConnectionRefused)
+        } else {
+            Err(Error::TimedOut)
+        }
+    }
+}
+
+impl<C: Chip, SPI: SpiDevice> embedded_io_async::ErrorType for TcpConnection<'_, C, SPI> {
+    type Error = Error<SPI::Error>;
+}
+
+impl<C: Chip, SPI: SpiDevice> embedded_io_async::Read for TcpConnection<'_, C, SPI> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let n = self.socket.n;
+        let events = intr::RECEIVED | intr::DISCONNECTED | intr::TIMEOUT;
+        loop {
+            self.socket.clear_flags(events);
+            {
+                let mut bus = self.socket.shared.bus.lock().await;
+                let size = bus.read_size(n, C::SOCKET_REGISTERS.recvd_size).await?;
+                if size > 0 {
+                    let len = buf.len().min(size as usize);
+                    let buf = &mut buf[..len];
+                    let mut read_ptr = bus.read_u16(n, C::SOCKET_REGISTERS.rx_data_read_ptr).await?;
+                    bus.read_bytes(n, &mut read_ptr, buf).await?;
+                    bus.write_u16(n, C::SOCKET_REGISTERS.rx_data_read_ptr, read_ptr).await?;
+                    bus.command(n, Command::Receive).await?;
+                    return Ok(buf.len());
+                }
+                match bus.status(n).await? {
+                    status::ESTABLISHED => {}
+                    // The remote host is done sending.
+                    status::CLOSE_WAIT => return Ok(0),
+                    _ => return Err(Error::ConnectionReset),
+                }
+            }
+            self.socket.wait_flags(events).await;
+        }
+    }
+}
+
+impl<C: Chip, SPI: SpiDevice> embedded_io_async::Write for TcpConnection<'_, C, SPI> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let n = self.socket.n;
+        let events = intr::SEND_OK | intr::DISCONNECTED | intr::TIMEOUT;
+        let len = loop {
+            let mut bus = self.socket.shared.bus.lock().await;
+            if !matches!(bus.status(n).await?, status::ESTABLISHED | status::CLOSE_WAIT) {
+                return Err(Error::ConnectionReset);
+            }
+            let free = bus.read_size(n, C::SOCKET_REGISTERS.tx_free_size).await?;
+            if free > 0 {
+                let buf = &buf[..buf.len().min(free as usize)];
+                let mut write_ptr = bus.read_u16(n, C::SOCKET_REGISTERS.tx_data_write_ptr).await?;
+                bus.write_bytes(n, &mut write_ptr, buf).await?;
+                bus.write_u16(n, C::SOCKET_REGISTERS.tx_data_write_ptr, write_ptr)
+                    .await?;
+                self.socket.clear_flags(events);
+                bus.command(n, Command::Send).await?;
+                break buf.len();
+            }
+            drop(bus);
+            // The buffer is freed as the remote host acknowledges the data, without interrupt.
+            Timer::after_millis(1).await;
+        };
+
+        if self.socket.wait_flags(events).await & intr::SEND_OK == 0 {
+            return Err(Error::ConnectionReset);
+        }
+        Ok(len)
+    }
+}
@@ -0,0 +1,337 @@
+use core::net::SocketAddr;
+use core::pin::pin;
+
+use embassy_futures::{block_on, poll_once};
+use embedded_hal_mock::common::Generic;
+use embedded_hal_mock::eh1::spi::{Mock as SpiMock, Transaction as SpiTransaction};
+use embedded_io_async::{Read, Write};
+use embedded_nal_async::{TcpConnect, UnconnectedUdp};
+
+use super::*;
+use crate::chip::{SocketRegisters, W5100S, W5500, W5X00_SOCKET_REGISTERS};
+
+type Spi = Generic<SpiTransaction<u8>>;
+
+const REGS: SocketRegisters = W5X00_SOCKET_REGISTERS;
+
+const CONFIG: Config = Config::new(Ipv4Addr::new(192, 168, 1, 2), 24, Some(Ipv4Addr::new(192, 168, 1, 1)));
+
+fn w5500_read(block: u8, address: u16, response: &[u8]) -> Vec<SpiTransaction<u8>> {
+    vec![
+        SpiTransaction::transaction_start(),
+        SpiTransaction::write_vec(address.to_be_bytes().to_vec()),
+        SpiTransaction::write_vec(vec![block << 3]),
+        SpiTransaction::transfer_in_place(vec![0; response.len()], response.to_vec()),
+        SpiTransaction::transaction_end(),
+    ]
+}
+
+fn w5500_write(block: u8, address: u16, data: &[u8]) -> Vec<SpiTransaction<u8>> {
+    vec![
+        SpiTransaction::transaction_start(),
+        SpiTransaction::write_vec(address.to_be_bytes().to_vec()),
+        SpiTransaction::write_vec(vec![(block << 3) | 0b100]),
+        SpiTransaction::write_vec(data.to_vec()),
+        SpiTransaction::transaction_end(),
+    ]
+}
+
+fn read(address: u16, response: &[u8]) -> Vec<SpiTransaction<u8>> {
+    vec![
+        SpiTransaction::transaction_start(),
+        SpiTransaction::write_vec(vec![0x0F, (address >> 8) as u8, address as u8]),
+        SpiTransaction::read_vec(response.to_vec()),
+        SpiTransaction::transaction_end(),
+    ]
+}
+
+fn write(address: u16, data: &[u8]) -> Vec<SpiTransaction<u8>> {
+    vec![
+        SpiTransaction::transaction_start(),
+        SpiTransaction::write_vec(vec![0xF0, (address >> 8) as u8, address as u8]),
+        SpiTransaction::write_vec(data.to_vec()),
+        SpiTransaction::transaction_end(),
+    ]
+}
+
+/// W5100S socket 0 register.
+const fn s0(offset: u16) -> u16 {
+    0x400 + offset
+}
+
+/// A W5100S command on socket 0, accepted on the first poll.
+fn command(command: Command) -> Vec<SpiTransaction<u8>> {
+    [write(s0(REGS.command), &[command as u8]), read(s0(REGS.command), &[0])].concat()
+}
+
+/// The runner seeing `flags` on socket 0.
+fn interrupt(flags: u8) -> Vec<SpiTransaction<u8>> {
+    [
+        read(0x15, &[0x01]),
+        read(s0(REGS.intr), &[flags]),
+        write(s0(REGS.intr), &[flags]),
+    ]
+    .concat()
+}
+
+fn shared<C: Chip>(expectations: &[SpiTransaction<u8>]) -> (Shared<C, Spi>, Spi) {
+    let spi = SpiMock::new(expectations);
+    (Shared::new(Bus::new(spi.clone()), CONFIG), spi)
+}
+
+fn slot_state<C: Chip>(shared: &Shared<C, Spi>, n: usize) -> SlotState {
+    shared.sockets.lock(|s| s.borrow().slots[n].state)
+}
+
+#[test]
+fn init_w5500() {
+    let mut expectations = [
+        w5500_write(0, 0x00, &[0x80]),
+        w5500_read(0, 0x39, &[0x04]),
+        w5500_write(0, 0x09, &[0x02, 0, 0, 0, 0, 1]),
+        w5500_write(0, 0x01, &[192, 168, 1, 1]),
+        w5500_write(0, 0x05, &[255, 255, 255, 0]),
+        w5500_write(0, 0x0F, &[192, 168, 1, 2]),
+    ]
+    .concat();
+    for n in 0..8 {
+        expectations.extend(w5500_write(1 + (n << 2), 0x1E, &[2]));
+        expectations.extend(w5500_write(1 + (n << 2), 0x1F, &[2]));
+    }
+    expectations.extend(w5500_write(0, 0x18, &[0xFF]));
+
+    let mut spi = SpiMock::new(&expectations);
+    let mut bus = Bus::<W5500, _>::new(spi.clone());
+    block_on(bus.init([0x02, 0, 0, 0, 0, 1], &CONFIG)).unwrap();
+    spi.done();
+}
+
+#[test]
+fn init_invalid_version() {
+    let expectations = [w5500_write(0, 0x00, &[0x80]), w5500_read(0, 0x39, &[0x51])].concat();
+
+    let mut spi = SpiMock::new(&expectations);
+    let mut bus = Bus::<W5500, _>::new(spi.clone());
+    let err = block_on(bus.init([0x02, 0, 0, 0, 0, 1], &CONFIG)).unwrap_err();
+    assert!(matches!(
+        err,
+        InitError::InvalidChipVersion {
+            expected: 0x04,
+            actual: 0x51
+        }
+    ));
+    spi.done();
+}
+
+#[test]
+fn tcp_connect() {
+    let expectations = [
+        write(s0(REGS.mode), &[mode::TCP]),
+        write(s0(REGS.port), &[0xC0, 0x00]),
+        command(Command::Open),
+        read(s0(REGS.status), &[status::INIT]),
+        write(s0(REGS.dest_ip), &[192, 168, 1, 10]),
+        write(s0(REGS.dest_port), &[0x04, 0xD2]),
+        command(Command::Connect),
+        interrupt(intr::CONNECTED),
+    ]
+    .concat();
+    let (shared, mut spi) = shared::<W5100S>(&expectations);
+    let stack = Stack { shared: &shared };
+
+    let remote = SocketAddr::new(Ipv4Addr::new(192, 168, 1, 10).into(), 1234);
+    let mut connect = pin!(stack.connect(remote));
+    assert!(poll_once(connect.as_mut()).is_pending());
+    block_on(shared.poll()).unwrap();
+    let connection = block_on(connect).unwrap();
+    assert_eq!(connection.socket.n, 0);
+
+    spi.done();
+}
+
+#[test]
+fn tcp_connect_refused() {
+    let expectations = [
+        write(s0(REGS.mode), &[mode::TCP]),
+        write(s0(REGS.port), &[0xC0, 0x00]),
+        command(Command::Open),
+        read(s0(REGS.status), &[status::INIT]),
+        write(s0(REGS.dest_ip), &[192, 168, 1, 10]),
+        write(s0(REGS.dest_port), &[0x04, 0xD2]),
+        command(Command::Connect),
+        interrupt(intr::DISCONNECTED),
+        // The socket is closed once the runner sees it was dropped.
+        read(0x15, &[0x00]),
+        read(s0(REGS.status), &[status::CLOSED]),
+        command(Command::Close),
+    ]
+    .concat();
+    let (shared, mut spi) = shared::<W5100S>(&expectations);
+    let stack = Stack { shared: &shared };
+
+    let remote = SocketAddr::new(Ipv4Addr::new(192, 168, 1, 10).into(), 1234);
+    let mut connect = pin!(stack.connect(remote));
+    assert!(poll_once(connect.as_mut()).is_pending());
+    block_on(shared.poll()).unwrap();
+    assert!(matches!(block_on(connect), Err(Error::ConnectionRefused)));
+    assert!(shared.close.signaled());
+    block_on(shared.poll()).unwrap();
+    assert!(slot_state(&shared, 0) == SlotState::Free);
+
+    spi.done();
+}
+
+#[test]
+fn tcp_read_wraps() {
+    let expectations = [
+        read(s0(REGS.recvd_size), &[0x00, 0x0A]),
+        read(s0(REGS.recvd_size), &[0x00, 0x0A]),
+        read(s0(REGS.rx_data_read_ptr), &[0x17, 0xFC]),
+        // 4 bytes until the end of the 2 KiB buffer of socket 0, then from its start.
+        read(0x6000 + 0x7FC, &[1, 2, 3, 4]),
+        read(0x6000, &[5, 6, 7, 8, 9, 10]),
+        write(s0(REGS.rx_data_read_ptr), &[0x18, 0x06]),
+        command(Command::Receive),
+    ]
+    .concat();
+    let (shared, mut spi) = shared::<W5100S>(&expectations);
+    let mut connection = TcpConnection {
+        socket: shared.alloc().unwrap(),
+    };
+
+    let mut buf = [0; 16];
+    let n = block_on(connection.read(&mut buf)).unwrap();
+    assert_eq!(&buf[..n], &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+
+    spi.done();
+}
+
+#[test]
+fn tcp_read_eof() {
+    let expectations = [
+        read(s0(REGS.recvd_size), &[0x00, 0x00]),
+        read(s0(REGS.recvd_size), &[0x00, 0x00]),
+        read(s0(REGS.status), &[status::CLOSE_WAIT]),
+    ]
+    .concat();
+    let (shared, mut spi) = shared::<W5100S>(&expectations);
+    let mut connection = TcpConnection {
+        socket: shared.alloc().unwrap(),
+    };
+
+    let mut buf = [0; 16];
+    assert_eq!(block_on(connection.read(&mut buf)).unwrap(), 0);
+
+    spi.done();
+}
+
+#[test]
+fn tcp_write() {
+    let expectations = [
+        read(s0(REGS.status), &[status::ESTABLISHED]),
+        read(s0(REGS.tx_free_size), &[0x00, 0x03]),
+        read(s0(REGS.tx_free_size), &[0x00, 0x03]),
+        read(s0(REGS.tx_data_write_ptr), &[0x00, 0x10]),
+        write(0x4000 + 0x10, b"Hel"),
+        write(s0(REGS.tx_data_write_ptr), &[0x00, 0x13]),
+        command(Command::Send),
+        interrupt(intr::SEND_OK),
+    ]
+    .concat();
+    let (shared, mut spi) = shared::<W5100S>(&expectations);
+    let mut connection = TcpConnection {
+        socket: shared.alloc().unwrap(),
+    };
+
+    let mut write = pin!(connection.write(b"Hello"));
+    assert!(poll_once(write.as_mut()).is_pending());
+    block_on(shared.poll()).unwrap();
+    assert_eq!(block_on(write).unwrap(), 3);
+
+    spi.done();
+}
+
+#[test]
+fn dropped_connection_disconnects() {
+    let expectations = [
+        read(0x15, &[0x00]),
+        read(s0(REGS.status), &[status::ESTABLISHED]),
+        command(Command::Disconnect),
+        interrupt(intr::DISCONNECTED),
+        command(Command::Close),
+    ]
+    .concat();
+    let (shared, mut spi) = shared::<W5100S>(&expectations);
+    drop(TcpConnection {
+        socket: shared.alloc().unwrap(),
+    });
+
+    block_on(shared.poll()).unwrap();
+    assert!(slot_state(&shared, 0) == SlotState::Disconnecting);
+    block_on(shared.poll()).unwrap();
+    assert!(slot_state(&shared, 0) == SlotState::Free);
+
+    spi.done();
+}
+
+#[test]
+fn udp_receive() {
+    let expectations = [
+        write(s0(REGS.mode), &[mode::UDP]),
+        write(s0(REGS.port), &[0x00, 0x44]),
+        command(Command::Open),
+        read(s0(REGS.status), &[status::UDP]),
+        read(s0(REGS.recvd_size), &[0x00, 0x0E]),
+        read(s0(REGS.recvd_size), &[0x00, 0x0E]),
+        read(s0(REGS.rx_data_read_ptr), &[0x00, 0x00]),
+        read(0x6000, &[192, 168, 1, 10, 0x00, 0x43, 0x00, 0x06]),
+        // The end of the datagram doesn't fit and is skipped.
+        read(0x6008, &[1, 2, 3, 4]),
+        write(s0(REGS.rx_data_read_ptr), &[0x00, 0x0E]),
+        command(Command::Receive),
+    ]
+    .concat();
+    let (shared, mut spi) = shared::<W5100S>(&expectations);
+    let stack = Stack { shared: &shared };
+
+    let mut socket = block_on(stack.bind_udp(68)).unwrap();
+    let mut buf = [0; 4];
+    let (n, local, remote) = block_on(socket.receive_into(&mut buf)).unwrap();
+    assert_eq!(&buf[..n], &[1, 2, 3, 4]);
+    assert_eq!(local, SocketAddr::new(CONFIG.address.into(), 68));
+    assert_eq!(remote, SocketAddr::new(Ipv4Addr::new(192, 168, 1, 10).into(), 67));
+
+    spi.done();
+}
+
+#[test]
+fn udp_send() {
+    let expectations = [
+        write(s0(REGS.mode), &[mode::UDP]),
+        write(s0(REGS.port), &[0xC0, 0x00]),
+        command(Command::Open),
+        read(s0(REGS.status), &[status::UDP]),
+        read(s0(REGS.tx_free_size), &[0x08, 0x00]),
+        read(s0(REGS.tx_free_size), &[0x08, 0x00]),
+        read(s0(REGS.tx_data_write_ptr), &[0x00, 0x00]),
+        write(0x4000, b"ping"),
+        write(s0(REGS.tx_data_write_ptr), &[0x00, 0x04]),
+        write(s0(REGS.dest_ip), &[192, 168, 1, 10]),
+        write(s0(REGS.dest_port), &[0x1F, 0x90]),
+        command(Command::Send),
+        interrupt(intr::TIMEOUT),
+    ]
+    .concat();
+    let (shared, mut spi) = shared::<W5100S>(&expectations);
+    let stack = Stack { shared: &shared };
+
+    let mut socket = block_on(stack.bind_udp(0)).unwrap();
+    let local = socket.local_addr();
+    let remote = SocketAddr::new(Ipv4Addr::new(192, 168, 1, 10).into(), 8080);
+    let mut send = pin!(socket.send(local, remote, b"ping"));
+    assert!(poll_once(send.as_mut()).is_pending());
+    block_on(shared.poll()).unwrap();
+    assert!(matches!(block_on(send), Err(Error::TimedOut)));
+
+    spi.done();
+}
@@ -0,0 +1,98 @@
+use core::net::{SocketAddr, SocketAddrV4};
+
+use embassy_time::Timer;
+use embedded_hal_async::spi::SpiDevice;
+use embedded_nal_async::UnconnectedUdp;
+
+use super::{intr, mode, socket_buf_size, status, Command, Error, Socket, Stack};
+use crate::chip::Chip;
+
+/// Length of the header the chip puts before each received datagram.
+const HEADER_LEN: usize = 8;
+
+/// A UDP socket on a hardware socket.
+pub struct UdpSocket<'d, C: Chip, SPI: SpiDevice> {
+    pub(super) socket: Socket<'d, C, SPI>,
+    pub(super) port: u16,
+}
+
+impl<'d, C: Chip, SPI: SpiDevice> Stack<'d, C, SPI> {
+    /// Open a UDP socket on the local `port`, or an ephemeral one if it's 0.
+    pub async fn bind_udp(&self, port: u16) -> Result<UdpSocket<'d, C, SPI>, Error<SPI::Error>> {
+        let socket = self.shared.alloc()?;
+        let port = socket.open(mode::UDP, port, status::UDP).await?;
+        Ok(UdpSocket { socket, port })
+    }
+}
+
+impl<C: Chip, SPI: SpiDevice> UdpSocket<'_, C, SPI> {
+    /// Get the local address of the socket.
+    pub fn local_addr(&self) -> SocketAddr {
+        SocketAddrV4::new(self.socket.shared.local_address(), self.port).into()
+    }
+}
+
+impl<C: Chip, SPI: SpiDevice> UnconnectedUdp for UdpSocket<'_, C, SPI> {
+    type Error = Error<SPI::Error>;
+
+    async fn send(&mut self, _local: SocketAddr, remote: SocketAddr, data: &[u8]) -> Result<(), Self::Error> {
+        let SocketAddr::V4(remote) = remote else {
+            return Err(Error::AddressNotSupported);
+        };
+        if data.len() > socket_buf_size::<C>() as usize {
+            return Err(Error::MessageTooLong);
+        }
+        let n = self.socket.n;
+        let events = intr::SEND_OK | intr::TIMEOUT;
+        loop {
+            let mut bus = self.socket.shared.bus.lock().await;
+            if bus.read_size(n, C::SOCKET_REGISTERS.tx_free_size).await? as usize >= data.len() {
+                let mut write_ptr = bus.read_u16(n, C::SOCKET_REGISTERS.tx_data_write_ptr).await?;
+                bus.write_bytes(n, &mut write_ptr, data).await?;
+                bus.write_u16(n, C::SOCKET_REGISTERS.tx_data_write_ptr, write_ptr)
+                    .await?;
+                bus.socket_write(n, C::SOCKET_REGISTERS.dest_ip, &remote.ip().octets())
+                    .await?;
+                bus.write_u16(n, C::SOCKET_REGISTERS.dest_port, remote.port()).await?;
+                self.socket.clear_flags(events);
+                bus.command(n, Command::Send).await?;
+                break;
+            }
+            drop(bus);
+            // The previous datagram is still being sent.
+            Timer::after_millis(1).await;
+        }
+
+        // Timeouts come from ARP, when no host has the address.
+        if self.socket.wait_flags(events).await & intr::SEND_OK == 0 {
+            return Err(Error::TimedOut);
+        }
+        Ok(())
+    }
+
+    async fn receive_into(&mut self, buf: &mut [u8]) -> Result<(usize, SocketAddr, SocketAddr), Self::Error> {
+        let n = self.socket.n;
+        loop {
+            self.socket.clear_flags(intr::RECEIVED);
+            {
+                let mut bus = self.socket.shared.bus.lock().await;
+                if bus.read_size(n, C::SOCKET_REGISTERS.recvd_size).await? > 0 {
+                    let mut read_ptr = bus.read_u16(n, C::SOCKET_REGISTERS.rx_data_read_ptr).await?;
+                    let mut header = [0; HEADER_LEN];
+                    bus.read_bytes(n, &mut read_ptr, &mut header).await?;
+                    let (remote, len) = C::udp_header(&header);
+
+                    // Truncate datagrams longer than `buf`.
+                    let copied = buf.len().min(len as usize);
+                    let buf = &mut buf[..copied];
+                    bus.read_bytes(n, &mut read_ptr, buf).await?;
+                    let read_ptr = read_ptr.wrapping_add(len - copied as u16);
+                    bus.write_u16(n, C::SOCKET_REGISTERS.rx_data_read_ptr, read_ptr).await?;
+                    bus.command(n, Command::Receive).await?;
+                    return Ok((buf.len(), self.local_addr(), remote.into()));
+                }
+            }
+            self.socket.wait_flags(intr::RECEIVED).await;
+        }
+    }
+}
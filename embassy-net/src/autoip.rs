@@ -0,0 +1,494 @@
+//! IPv4 link-local address autoconfiguration (RFC 3927).
+//!
+//! An address is picked in 169.254/16, probed for with ARP, announced, then defended. ARP
+//! packets are not visible to smoltcp sockets, so they're snooped from the frames received by
+//! the driver, and probes and announcements are sent to it directly.
+
+use embassy_time::{Duration, Instant};
+use smoltcp::phy::{Device, TxToken};
+use smoltcp::wire::{
+    ArpOperation, ArpPacket, ArpRepr, EthernetAddress, EthernetFrame, EthernetProtocol, EthernetRepr, Ipv4Address,
+    Ipv4Cidr,
+};
+
+use crate::time::instant_to_smoltcp;
+use crate::{LinkLocalConfig, StaticConfigV4};
+
+// Timing constants of RFC 3927 section 9.
+const PROBE_WAIT: Duration = Duration::from_secs(1);
+const PROBE_NUM: u8 = 3;
+const PROBE_MIN: Duration = Duration::from_secs(1);
+const PROBE_MAX: Duration = Duration::from_secs(2);
+const ANNOUNCE_WAIT: Duration = Duration::from_secs(2);
+const ANNOUNCE_NUM: u8 = 2;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(2);
+const MAX_CONFLICTS: u8 = 10;
+const RATE_LIMIT_INTERVAL: Duration = Duration::from_secs(60);
+const DEFEND_INTERVAL: Duration = Duration::from_secs(10);
+
+const PREFIX_LEN: u8 = 16;
+const ARP_FRAME_LEN: usize = 14 + 28;
+
+/// Result of polling the state machine.
+pub(crate) enum Event {
+    /// A link-local address is available.
+    Configured(StaticConfigV4),
+    /// The address was lost to a conflict.
+    Deconfigured,
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    /// Not running, because the link is down or DHCP has a lease.
+    Stopped,
+    /// Waiting before probing.
+    Waiting { until: Instant },
+    /// Probing for the address. Once all probes are sent, `next` is the end of the wait for answers.
+    Probing { sent: u8, next: Instant },
+    /// The address is ours, and is being announced.
+    Announcing { sent: u8, next: Instant },
+    /// The address is ours.
+    Bound,
+}
+
+pub(crate) struct AutoIp {
+    config: LinkLocalConfig,
+    /// Delay before probing starts, to give DHCP a chance.
+    delay: Duration,
+    hardware_address: EthernetAddress,
+    rng: u64,
+    address: Ipv4Address,
+    state: State,
+    conflicts: u8,
+    last_defense: Option<Instant>,
+    /// An announcement must be sent to defend the address.
+    defend: bool,
+    current: Option<StaticConfigV4>,
+}
+
+impl AutoIp {
+    pub(crate) fn new(config: LinkLocalConfig, delay: Duration, hardware_address: EthernetAddress) -> Self {
+        // RFC 3927 section 2.1: seed with the hardware address, so the same address is picked
+        // each time in a stable network.
+        let mut rng = 0x2545_f491_4f6c_dd1d;
+        for b in hardware_address.0 {
+            rng = (rng ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3);
+        }
+        let mut this = Self {
+            address: Ipv4Address::UNSPECIFIED,
+            config,
+            delay,
+            hardware_address,
+            rng,
+            state: State::Stopped,
+            conflicts: 0,
+            last_defense: None,
+            defend: false,
+            current: None,
+        };
+        this.address = match this.config.address {
+            Some(addr) if is_valid(addr) => addr,
+            _ => this.random_address(),
+        };
+        this
+    }
+
+    /// Start the state machine, after the configured delay.
+    pub(crate) fn start(&mut self, now: Instant) {
+        if !matches!(self.state, State::Stopped) {
+            return;
+        }
+        let wait = self.random_duration(PROBE_WAIT);
+        self.state = State::Waiting {
+            until: now + self.delay + wait,
+        };
+    }
+
+    /// Stop the state machine, and give up the address.
+    ///
+    /// Returns [`Event::Deconfigured`] if the address was configured.
+    pub(crate) fn stop(&mut self) -> Option<Event> {
+        self.state = State::Stopped;
+        self.defend = false;
+        self.current.take().map(|_| Event::Deconfigured)
+    }
+
+    /// Look at a frame received on the interface, for ARP packets conflicting with our address.
+    pub(crate) fn received(&mut self, frame: &[u8]) {
+        let Some(ArpRepr::EthernetIpv4 {
+            source_hardware_addr,
+            source_protocol_addr,
+            target_protocol_addr,
+            ..
+        }) = parse_arp(frame)
+        else {
+            return;
+        };
+        if source_hardware_addr == self.hardware_address {
+            return;
+        }
+        let now = Instant::now();
+
+        match self.state {
+            State::Probing { .. } => {
+                // Someone uses the address, or probes for it at the same time.
+                let probe = source_protocol_addr.is_unspecified() && target_protocol_addr == self.address;
+                if source_protocol_addr == self.address || probe {
+                    info!("IPv4 link-local: {:?} is in use", self.address);
+                    self.conflict(now);
+                }
+            }
+            State::Announcing { .. } | State::Bound
+                if source_protocol_addr.is_unspecified() && target_protocol_addr == self.address =>
+            {
+                // smoltcp ignores requests from the unspecified address, answer the probe here.
+                self.defend = true;
+            }
+            State::Announcing { .. } | State::Bound if source_protocol_addr == self.address => {
+                match self.last_defense {
+                    Some(at) if now - at < DEFEND_INTERVAL => {
+                        warn!("IPv4 link-local: lost {:?} to {:?}", self.address, source_hardware_addr);
+                        self.conflict(now);
+                    }
+                    _ => {
+                        info!("IPv4 link-local: defending {:?}", self.address);
+                        self.last_defense = Some(now);
+                        self.defend = true;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Send pending probes and announcements.
+    pub(crate) fn poll<D: Device>(&mut self, device: &mut D, now: Instant) -> Option<Event> {
+        if self.defend && self.send(device, self.address, now) {
+            self.defend = false;
+        }
+
+        match self.state {
+            State::Waiting { until } if until <= now => {
+                debug!("IPv4 link-local: probing {:?}", self.address);
+                self.state = State::Probing { sent: 0, next: now };
+                return self.poll(device, now);
+            }
+            State::Probing { sent, next } if next <= now => {
+                if sent < PROBE_NUM {
+                    if self.send(device, Ipv4Address::UNSPECIFIED, now) {
+                        let next = match sent + 1 {
+                            PROBE_NUM => now + ANNOUNCE_WAIT,
+                            _ => now + PROBE_MIN + self.random_duration(PROBE_MAX - PROBE_MIN),
+                        };
+                        self.state = State::Probing { sent: sent + 1, next };
+                    }
+                } else {
+                    info!("IPv4 link-local: using {:?}", self.address);
+                    self.conflicts = 0;
+                    self.last_defense = None;
+                    self.state = State::Announcing { sent: 0, next: now };
+                    return self.poll(device, now);
+                }
+            }
+            State::Announcing { sent, next } if next <= now => {
+                if self.send(device, self.address, now) {
+                    self.state = match sent + 1 {
+                        ANNOUNCE_NUM => State::Bound,
+                        sent => State::Announcing {
+                            sent,
+                            next: now + ANNOUNCE_INTERVAL,
+                        },
+                    };
+                }
+            }
+            _ => {}
+        }
+
+        self.update()
+    }
+
+    /// Next instant at which [`poll`](Self::poll) has work to do.
+    pub(crate) fn poll_at(&self) -> Option<Instant> {
+        if self.defend {
+            return Some(Instant::from_ticks(0));
+        }
+        match self.state {
+            State::Waiting { until: at } | State::Probing { next: at, .. } | State::Announcing { next: at, .. } => {
+                Some(at)
+            }
+            State::Stopped | State::Bound => None,
+        }
+    }
+
+    fn update(&mut self) -> Option<Event> {
+        let config = match self.state {
+            State::Announcing { .. } | State::Bound => Some(StaticConfigV4 {
+                address: Ipv4Cidr::new(self.address, PREFIX_LEN),
+                gateway: None,
+                dns_servers: Default::default(),
+            }),
+            _ => None,
+        };
+
+        if config == self.current {
+            return None;
+        }
+        self.current = config.clone();
+        match config {
+            Some(config) => Some(Event::Configured(config)),
+            None => Some(Event::Deconfigured),
+        }
+    }
+
+    /// Pick another address after a conflict.
+    fn conflict(&mut self, now: Instant) {
+        self.conflicts = self.conflicts.saturating_add(1);
+        self.address = self.random_address();
+        self.defend = false;
+        // RFC 3927 section 2.2.1: slow down after too many conflicts.
+        let wait = match self.conflicts >= MAX_CONFLICTS {
+            true => RATE_LIMIT_INTERVAL,
+            false => self.random_duration(PROBE_WAIT),
+        };
+        self.state = State::Waiting { until: now + wait };
+    }
+
+    /// Send an ARP probe (from the unspecified address) or announcement (from our address).
+    ///
+    /// Returns `false` if the driver has no transmit buffer available.
+    fn send<D: Device>(&mut self, device: &mut D, sender: Ipv4Address, now: Instant) -> bool {
+        let Some(token) = device.transmit(instant_to_smoltcp(now)) else {
+            return false;
+        };
+        let eth = EthernetRepr {
+            src_addr: self.hardware_address,
+            dst_addr: EthernetAddress::BROADCAST,
+            ethertype: EthernetProtocol::Arp,
+        };
+        let arp = ArpRepr::EthernetIpv4 {
+            operation: ArpOperation::Request,
+            source_hardware_addr: self.hardware_address,
+            source_protocol_addr: sender,
+            target_hardware_addr: EthernetAddress([0; 6]),
+            target_protocol_addr: self.address,
+        };
+        token.consume(ARP_FRAME_LEN, |buf| {
+            let mut frame = EthernetFrame::new_unchecked(buf);
+            eth.emit(&mut frame);
+            arp.emit(&mut ArpPacket::new_unchecked(frame.payload_mut()));
+        });
+        true
+    }
+
+    /// Random address in 169.254.1.0 - 169.254.254.255.
+    fn random_address(&mut self) -> Ipv4Address {
+        let n = (self.next_u64() % (254 * 256)) as u16;
+        Ipv4Address::new(169, 254, (n >> 8) as u8 + 1, n as u8)
+    }
+
+    fn random_duration(&mut self, max: Duration) -> Duration {
+        Duration::from_ticks(self.next_u64() % max.as_ticks().max(1))
+    }
+
+    /// xorshift64.
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.rng;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng = x;
+        x
+    }
+}
+
+/// Check whether `addr` can be used as a link-local address (RFC 3927 section 2.1).
+fn is_valid(addr: Ipv4Address) -> bool {
+    let [a, b, c, _] = addr.octets();
+    a == 169 && b == 254 && (1..=254).contains(&c)
+}
+
+fn parse_arp(frame: &[u8]) -> Option<ArpRepr> {
+    let frame = EthernetFrame::new_checked(frame).ok()?;
+    if frame.ethertype() != EthernetProtocol::Arp {
+        return None;
+    }
+    ArpRepr::parse(&ArpPacket::new_checked(frame.payload()).ok()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use smoltcp::phy::{DeviceCapabilities, RxToken};
+
+    use super::*;
+
+    const MAC: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 1]);
+    const OTHER_MAC: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 2]);
+    const ADDRESS: Ipv4Address = Ipv4Address::new(169, 254, 10, 20);
+
+    /// A device keeping the frames sent, and receiving none.
+    #[derive(Default)]
+    struct Capture {
+        sent: Vec<Vec<u8>>,
+    }
+
+    enum NoRx {}
+
+    impl RxToken for NoRx {
+        fn consume<R, F: FnOnce(&[u8]) -> R>(self, _: F) -> R {
+            match self {}
+        }
+    }
+
+    struct Tx<'a>(&'a mut Vec<Vec<u8>>);
+
+    impl TxToken for Tx<'_> {
+        fn consume<R, F: FnOnce(&mut [u8]) -> R>(self, len: usize, f: F) -> R {
+            let mut frame = vec![0; len];
+            let r = f(&mut frame);
+            self.0.push(frame);
+            r
+        }
+    }
+
+    impl Device for Capture {
+        type RxToken<'a> = NoRx;
+        type TxToken<'a> = Tx<'a>;
+
+        fn receive(&mut self, _: smoltcp::time::Instant) -> Option<(NoRx, Tx<'_>)> {
+            None
+        }
+
+        fn transmit(&mut self, _: smoltcp::time::Instant) -> Option<Tx<'_>> {
+            Some(Tx(&mut self.sent))
+        }
+
+        fn capabilities(&self) -> DeviceCapabilities {
+            DeviceCapabilities::default()
+        }
+    }
+
+    fn autoip() -> AutoIp {
+        let config = LinkLocalConfig {
+            address: Some(ADDRESS),
+            ..Default::default()
+        };
+        AutoIp::new(config, Duration::from_secs(0), MAC)
+    }
+
+    /// Poll every 100 ms from `now`, until an event.
+    fn poll_until_event(autoip: &mut AutoIp, device: &mut Capture, now: &mut Instant) -> Event {
+        for _ in 0..300 {
+            if let Some(event) = autoip.poll(device, *now) {
+                return event;
+            }
+            *now += Duration::from_millis(100);
+        }
+        panic!("no event");
+    }
+
+    fn arp(sender_mac: EthernetAddress, sender: Ipv4Address, target: Ipv4Address) -> Vec<u8> {
+        let mut frame = vec![0; ARP_FRAME_LEN];
+        let mut eth = EthernetFrame::new_unchecked(&mut frame[..]);
+        EthernetRepr {
+            src_addr: sender_mac,
+            dst_addr: EthernetAddress::BROADCAST,
+            ethertype: EthernetProtocol::Arp,
+        }
+        .emit(&mut eth);
+        ArpRepr::EthernetIpv4 {
+            operation: ArpOperation::Request,
+            source_hardware_addr: sender_mac,
+            source_protocol_addr: sender,
+            target_hardware_addr: EthernetAddress([0; 6]),
+            target_protocol_addr: target,
+        }
+        .emit(&mut ArpPacket::new_unchecked(eth.payload_mut()));
+        frame
+    }
+
+    fn configured_address(event: Event) -> Ipv4Address {
+        match event {
+            Event::Configured(config) => {
+                assert_eq!(config.address.prefix_len(), PREFIX_LEN);
+                config.address.address()
+            }
+            Event::Deconfigured => panic!("deconfigured"),
+        }
+    }
+
+    #[test]
+    fn probe_announce_and_stop() {
+        let mut autoip = autoip();
+        let mut device = Capture::default();
+        let mut now = Instant::now();
+        autoip.start(now);
+
+        let event = poll_until_event(&mut autoip, &mut device, &mut now);
+        assert_eq!(configured_address(event), ADDRESS);
+        let senders: Vec<_> = device
+            .sent
+            .iter()
+            .map(|frame| match parse_arp(frame) {
+                Some(ArpRepr::EthernetIpv4 {
+                    source_protocol_addr,
+                    target_protocol_addr,
+                    ..
+                }) => {
+                    assert_eq!(target_protocol_addr, ADDRESS);
+                    source_protocol_addr
+                }
+                _ => panic!("not ARP"),
+            })
+            .collect();
+        // Three probes, then the first announcement.
+        core::assert_eq!(
+            senders,
+            [
+                Ipv4Address::UNSPECIFIED,
+                Ipv4Address::UNSPECIFIED,
+                Ipv4Address::UNSPECIFIED,
+                ADDRESS
+            ]
+        );
+
+        // Link down: the address must be removed.
+        assert!(matches!(autoip.stop(), Some(Event::Deconfigured)));
+        assert!(autoip.stop().is_none());
+        assert!(autoip.poll(&mut device, now).is_none());
+
+        // Link up again: probing starts over, and the address comes back once it's done.
+        autoip.start(now);
+        let event = poll_until_event(&mut autoip, &mut device, &mut now);
+        assert_eq!(configured_address(event), ADDRESS);
+    }
+
+    #[test]
+    fn conflict_while_probing() {
+        let mut autoip = autoip();
+        let mut device = Capture::default();
+        let mut now = Instant::now();
+        autoip.start(now);
+        while device.sent.is_empty() {
+            assert!(autoip.poll(&mut device, now).is_none());
+            now += Duration::from_millis(100);
+        }
+
+        // Another host has the address.
+        autoip.received(&arp(OTHER_MAC, ADDRESS, ADDRESS));
+        let event = poll_until_event(&mut autoip, &mut device, &mut now);
+        let address = configured_address(event);
+        assert_ne!(address, ADDRESS);
+        assert!(is_valid(address));
+
+        // Probes of another host for our address are answered, without giving it up.
+        device.sent.clear();
+        autoip.received(&arp(OTHER_MAC, Ipv4Address::UNSPECIFIED, address));
+        assert!(autoip.poll(&mut device, now).is_none());
+        assert!(matches!(
+            parse_arp(&device.sent[0]),
+            Some(ArpRepr::EthernetIpv4 { source_protocol_addr, .. }) if source_protocol_addr == address
+        ));
+    }
+}
@@ -0,0 +1,489 @@
+//! CoAP client.
+//!
+//! ```ignore
+//! use embassy_net::coap::client::{Client, Request};
+//! use embassy_net::coap::{Config, ContentFormat};
+//!
+//! let mut client = Client::new(socket, Config::new(), &mut rng).unwrap();
+//! let mut buf = [0; 1024];
+//!
+//! let request = Request::put("config/interval", b"{\"s\":10}").content_format(ContentFormat::JSON);
+//! let response = client.send(server, &request, &mut buf).await?;
+//! info!("code: {}.{:02}", response.code().class(), response.code().detail());
+//!
+//! client.download(server, &Request::get("firmware"), &mut buf, |offset, data| write_flash(offset, data)).await?;
+//!
+//! client.observe(server, &Request::get("sensors/temperature"), &mut buf).await?;
+//! loop {
+//!     let notification = client.notification(&mut buf).await?;
+//!     info!("temperature: {}", core::str::from_utf8(notification.payload()).unwrap());
+//! }
+//! ```
+
+use core::ops::Range;
+
+use embassy_time::{with_deadline, Duration, Instant};
+use rand_core::RngCore;
+
+use super::message::{Message, Writer};
+use super::{
+    after, backoff, next_message_id, size_exponent, split_path, Block, Code, Config, ContentFormat, Error, Method,
+    OptionNumber, Type, PAYLOAD_ROOM,
+};
+use crate::udp::{BindError, UdpSocket};
+use crate::IpEndpoint;
+
+/// Length of the tokens of requests.
+const TOKEN_LEN: usize = 4;
+
+/// A notification is newer than the last one after this long, whatever its sequence number
+/// (RFC 7641 section 3.4).
+const NOTIFICATION_REORDERING: Duration = Duration::from_secs(128);
+
+/// A CoAP request to send.
+#[derive(Debug, Clone, Copy)]
+pub struct Request<'r> {
+    method: Method,
+    path: &'r str,
+    payload: &'r [u8],
+    content_format: Option<ContentFormat>,
+    accept: Option<ContentFormat>,
+    confirmable: bool,
+}
+
+impl<'r> Request<'r> {
+    /// Create a request without payload.
+    ///
+    /// `path` may have a query, like `sensors/temperature?unit=C`.
+    pub const fn new(method: Method, path: &'r str) -> Self {
+        Self {
+            method,
+            path,
+            payload: &[],
+            content_format: None,
+            accept: None,
+            confirmable: true,
+        }
+    }
+
+    /// Create a GET request.
+    pub const fn get(path: &'r str) -> Self {
+        Self::new(Method::Get, path)
+    }
+
+    /// Create a POST request with a payload.
+    pub const fn post(path: &'r str, payload: &'r [u8]) -> Self {
+        Self::new(Method::Post, path).payload(payload)
+    }
+
+    /// Create a PUT request with a payload.
+    pub const fn put(path: &'r str, payload: &'r [u8]) -> Self {
+        Self::new(Method::Put, path).payload(payload)
+    }
+
+    /// Create a DELETE request.
+    pub const fn delete(path: &'r str) -> Self {
+        Self::new(Method::Delete, path)
+    }
+
+    /// Set the payload.
+    ///
+    /// Payloads larger than [`Config::block_size`] are sent in blocks.
+    pub const fn payload(mut self, payload: &'r [u8]) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    /// Set the content format of the payload.
+    pub const fn content_format(mut self, content_format: ContentFormat) -> Self {
+        self.content_format = Some(content_format);
+        self
+    }
+
+    /// Set the content format accepted in the response.
+    pub const fn accept(mut self, content_format: ContentFormat) -> Self {
+        self.accept = Some(content_format);
+        self
+    }
+
+    /// Send the request in non-confirmable messages, which aren't retransmitted.
+    pub const fn non_confirmable(mut self) -> Self {
+        self.confirmable = false;
+        self
+    }
+}
+
+/// A resource observed by a [`Client`].
+struct Observation {
+    remote: IpEndpoint,
+    token: [u8; TOKEN_LEN],
+    sequence: u32,
+    received: Instant,
+}
+
+impl Observation {
+    /// Whether a notification with sequence number `sequence` is newer than the last one.
+    fn is_fresh(&self, sequence: u32) -> bool {
+        let (last, sequence) = (self.sequence, sequence & 0xff_ffff);
+        (last < sequence && sequence - last < 1 << 23)
+            || (last > sequence && last - sequence > 1 << 23)
+            || Instant::now() > self.received + NOTIFICATION_REORDERING
+    }
+}
+
+/// CoAP client.
+///
+/// Messages are written and received in a buffer given to each call, which holds both the
+/// request and its response. Block-wise transfers use blocks of at most
+/// [`Config::block_size`] bytes, and smaller ones when the buffer is too small.
+pub struct Client<'a, R: RngCore> {
+    socket: UdpSocket<'a>,
+    config: Config,
+    message_id: u16,
+    rng: R,
+    observation: Option<Observation>,
+}
+
+impl<'a, R: RngCore> Client<'a, R> {
+    /// Create a client, binding `socket` to an ephemeral port.
+    pub fn new(mut socket: UdpSocket<'a>, config: Config, mut rng: R) -> Result<Self, BindError> {
+        socket.bind(0)?;
+        let message_id = rng.next_u32() as u16;
+        Ok(Self {
+            socket,
+            config,
+            message_id,
+            rng,
+            observation: None,
+        })
+    }
+
+    /// Send a request to `remote`, and wait for its response.
+    ///
+    /// Payloads larger than a block are sent in blocks (Block1), and the response to the last
+    /// block is returned. A large response only carries its first block (Block2): use
+    /// [`download`](Self::download) to get all of them.
+    pub async fn send<'b>(
+        &mut self,
+        remote: IpEndpoint,
+        request: &Request<'_>,
+        buf: &'b mut [u8],
+    ) -> Result<Message<'b>, Error> {
+        let payload = request.payload;
+        let mut szx = size_exponent(self.block_size(buf.len()));
+        if payload.len() <= 16 << szx {
+            let len = self.write_request(request, payload, None, None, None, buf)?;
+            let range = self.exchange(remote, len, buf).await?;
+            return Message::parse(&buf[range]);
+        }
+
+        let mut offset = 0;
+        let range = loop {
+            let size = 16 << szx;
+            let end = payload.len().min(offset + size);
+            let block = Block {
+                num: (offset / size) as u32,
+                more: end < payload.len(),
+                szx,
+            };
+            let len = self.write_request(request, &payload[offset..end], None, Some(block), None, buf)?;
+            let range = self.exchange(remote, len, buf).await?;
+            let response = Message::parse(&buf[range.clone()])?;
+            let echoed = response.options().block1();
+
+            if block.more && response.code().is_success() {
+                // The server may ask for smaller blocks (RFC 7959 section 2.5).
+                match echoed {
+                    Some(echoed) if echoed.num == block.num => {
+                        szx = szx.min(echoed.szx);
+                        offset = end;
+                        continue;
+                    }
+                    _ => return Err(Error::UnexpectedBlock),
+                }
+            }
+            if response.code() == Code::REQUEST_ENTITY_TOO_LARGE && offset == 0 {
+                if let Some(echoed) = echoed.filter(|e| e.szx < szx) {
+                    szx = echoed.szx;
+                    continue;
+                }
+            }
+            break range;
+        };
+        Message::parse(&buf[range])
+    }
+
+    /// Send a request to `remote`, and receive its response in blocks (Block2).
+    ///
+    /// The payload of each block of a successful response is passed to `f`, with its offset. The
+    /// response carrying the last block is returned.
+    pub async fn download<'b>(
+        &mut self,
+        remote: IpEndpoint,
+        request: &Request<'_>,
+        buf: &'b mut [u8],
+        mut f: impl FnMut(usize, &[u8]),
+    ) -> Result<Message<'b>, Error> {
+        let mut block = Block {
+            num: 0,
+            more: false,
+            szx: size_exponent(self.block_size(buf.len())),
+        };
+        let range = loop {
+            let len = self.write_request(request, request.payload, None, None, Some(block), buf)?;
+            let range = self.exchange(remote, len, buf).await?;
+            let response = Message::parse(&buf[range.clone()])?;
+            if !response.code().is_success() {
+                break range;
+            }
+            match response.options().block2() {
+                // The whole representation fits in a message.
+                None if block.num == 0 => {
+                    f(0, response.payload());
+                    break range;
+                }
+                Some(received) if received.offset() == block.offset() => {
+                    if received.more && response.payload().len() != received.size() {
+                        return Err(Error::UnexpectedBlock);
+                    }
+                    f(received.offset(), response.payload());
+                    if !received.more {
+                        break range;
+                    }
+                    // The server may send smaller blocks than asked for.
+                    block = Block {
+                        num: received.num + 1,
+                        ..received
+                    };
+                }
+                _ => return Err(Error::UnexpectedBlock),
+            }
+        };
+        Message::parse(&buf[range])
+    }
+
+    /// Send a GET request to `remote` to observe a resource, and wait for its response.
+    ///
+    /// If the response has an Observe option, the client observes the resource until
+    /// [`stop_observing`](Self::stop_observing) or another call to `observe`. The following
+    /// representations are received with [`notification`](Self::notification).
+    pub async fn observe<'b>(
+        &mut self,
+        remote: IpEndpoint,
+        request: &Request<'_>,
+        buf: &'b mut [u8],
+    ) -> Result<Message<'b>, Error> {
+        self.observation = None;
+        let len = self.write_request(request, &[], Some(0), None, None, buf)?;
+        let mut token = [0; TOKEN_LEN];
+        token.copy_from_slice(&buf[4..4 + TOKEN_LEN]);
+        let range = self.exchange(remote, len, buf).await?;
+
+        let response = Message::parse(&buf[range])?;
+        if let (true, Some(sequence)) = (response.code().is_success(), response.options().observe()) {
+            self.observation = Some(Observation {
+                remote,
+                token,
+                sequence,
+                received: Instant::now(),
+            });
+        }
+        Ok(response)
+    }
+
+    /// Wait for the next notification of the resource observed.
+    ///
+    /// Notifications older than the last one are dropped. A notification with an error code ends
+    /// the observation.
+    pub async fn notification<'b>(&mut self, buf: &'b mut [u8]) -> Result<Message<'b>, Error> {
+        let len = loop {
+            let Some(observation) = &self.observation else {
+                return Err(Error::NotObserving);
+            };
+            let Ok((len, meta)) = self.socket.recv_from(buf).await else {
+                continue;
+            };
+            let Ok(message) = Message::parse(&buf[..len]) else {
+                continue;
+            };
+            let remote = meta.endpoint;
+            if remote != observation.remote
+                || message.token() != observation.token
+                || !matches!(message.ty(), Type::Confirmable | Type::NonConfirmable)
+            {
+                self.reject(&message, remote).await;
+                continue;
+            }
+            if message.ty() == Type::Confirmable {
+                self.acknowledge(&message, remote).await;
+            }
+            match (message.code().is_success(), message.options().observe()) {
+                (true, Some(sequence)) => {
+                    if !observation.is_fresh(sequence) {
+                        continue;
+                    }
+                    self.observation = Some(Observation {
+                        sequence,
+                        received: Instant::now(),
+                        ..*observation
+                    });
+                }
+                _ => self.observation = None,
+            }
+            break len;
+        };
+        Message::parse(&buf[..len])
+    }
+
+    /// Stop observing the resource.
+    ///
+    /// The following notifications are rejected with resets, which cancel the observation on the
+    /// server (RFC 7641 section 3.6).
+    pub fn stop_observing(&mut self) {
+        self.observation = None;
+    }
+
+    /// Get the largest block size for messages in `buf`, with room for the request and the
+    /// response.
+    fn block_size(&self, len: usize) -> usize {
+        self.config.block_size.min((len / 2).saturating_sub(PAYLOAD_ROOM))
+    }
+
+    /// Write a request with a new message ID and token into `buf`, returning its length.
+    fn write_request(
+        &mut self,
+        request: &Request<'_>,
+        payload: &[u8],
+        observe: Option<u32>,
+        block1: Option<Block>,
+        block2: Option<Block>,
+        buf: &mut [u8],
+    ) -> Result<usize, Error> {
+        let ty = match request.confirmable {
+            true => Type::Confirmable,
+            false => Type::NonConfirmable,
+        };
+        let message_id = next_message_id(&mut self.message_id);
+        let token = self.rng.next_u64().to_le_bytes();
+        let mut writer = Writer::new(buf, ty, request.method.code(), message_id, &token[..TOKEN_LEN])?;
+
+        let (path, query) = split_path(request.path);
+        if let Some(observe) = observe {
+            writer.uint_option(OptionNumber::OBSERVE, observe)?;
+        }
+        for segment in path {
+            writer.option(OptionNumber::URI_PATH, segment.as_bytes())?;
+        }
+        if let Some(content_format) = request.content_format {
+            writer.uint_option(OptionNumber::CONTENT_FORMAT, content_format.0 as u32)?;
+        }
+        for argument in query {
+            writer.option(OptionNumber::URI_QUERY, argument.as_bytes())?;
+        }
+        if let Some(accept) = request.accept {
+            writer.uint_option(OptionNumber::ACCEPT, accept.0 as u32)?;
+        }
+        if let Some(block2) = block2 {
+            writer.uint_option(OptionNumber::BLOCK2, block2.to_uint())?;
+        }
+        if let Some(block1) = block1 {
+            writer.uint_option(OptionNumber::BLOCK1, block1.to_uint())?;
+        }
+        writer.payload(payload)?;
+        Ok(writer.len())
+    }
+
+    /// Send the request of `len` bytes at the start of `buf`, and wait for its response,
+    /// returning where it is in `buf`.
+    ///
+    /// Confirmable requests are retransmitted until they are acknowledged. The response is either
+    /// piggybacked on the acknowledgement, or sent separately later.
+    async fn exchange(&mut self, remote: IpEndpoint, len: usize, buf: &mut [u8]) -> Result<Range<usize>, Error> {
+        let (request, rx) = buf.split_at_mut(len);
+        let confirmable = Type::from_bits(request[0] >> 4) == Type::Confirmable;
+        let message_id = u16::from_be_bytes([request[2], request[3]]);
+        let token = &request[4..4 + (request[0] & 0x0f) as usize];
+
+        self.socket.send_to(request, remote).await?;
+        let mut timeout = self.config.initial_timeout(&mut self.rng);
+        let mut retransmissions = 0;
+        // Non-confirmable requests are only sent once, but their response may be late.
+        let mut acknowledged = !confirmable;
+        let mut deadline = match confirmable {
+            true => after(Instant::now(), timeout),
+            false => after(Instant::now(), self.config.max_transmit_wait()),
+        };
+
+        let response_len = loop {
+            let (n, meta) = match with_deadline(deadline, self.socket.recv_from(rx)).await {
+                Ok(Ok(received)) => received,
+                Ok(Err(_)) => continue,
+                Err(_) => {
+                    if acknowledged || retransmissions >= self.config.max_retransmit() {
+                        return Err(Error::Timeout);
+                    }
+                    retransmissions += 1;
+                    timeout = backoff(timeout);
+                    deadline = after(Instant::now(), timeout);
+                    debug!("CoAP client: retransmitting {}", message_id);
+                    self.socket.send_to(request, remote).await?;
+                    continue;
+                }
+            };
+            let Ok(response) = Message::parse(&rx[..n]) else {
+                continue;
+            };
+            let from_remote = meta.endpoint == remote;
+            match response.ty() {
+                Type::Acknowledgement | Type::Reset if !from_remote || response.message_id() != message_id => {}
+                Type::Reset => return Err(Error::Reset),
+                Type::Acknowledgement if response.code() == Code::EMPTY => {
+                    // The response will be sent separately.
+                    acknowledged = true;
+                    deadline = after(Instant::now(), self.config.max_transmit_wait());
+                }
+                Type::Acknowledgement if response.token() == token => break n,
+                Type::Acknowledgement => {}
+                _ if from_remote && response.token() == token => {
+                    if response.ty() == Type::Confirmable {
+                        self.acknowledge(&response, remote).await;
+                    }
+                    break n;
+                }
+                _ => self.reject(&response, meta.endpoint).await,
+            }
+        };
+        Ok(len..len + response_len)
+    }
+
+    /// Acknowledge a confirmable message.
+    async fn acknowledge(&self, message: &Message<'_>, remote: IpEndpoint) {
+        self.send_empty(Type::Acknowledgement, message.message_id(), remote)
+            .await;
+    }
+
+    /// Reject an unexpected message with a reset.
+    ///
+    /// Notifications of the resource observed are ignored: they are sent again, and received by
+    /// [`notification`](Self::notification).
+    async fn reject(&self, message: &Message<'_>, remote: IpEndpoint) {
+        if !matches!(message.ty(), Type::Confirmable | Type::NonConfirmable) {
+            return;
+        }
+        if let Some(observation) = &self.observation {
+            if observation.remote == remote && message.token() == observation.token {
+                return;
+            }
+        }
+        self.send_empty(Type::Reset, message.message_id(), remote).await;
+    }
+
+    async fn send_empty(&self, ty: Type, message_id: u16, remote: IpEndpoint) {
+        let mut buf = [0; 4];
+        if Writer::new(&mut buf, ty, Code::EMPTY, message_id, &[]).is_ok() {
+            if let Err(e) = self.socket.send_to(&buf, remote).await {
+                warn!("CoAP client: send error: {:?}", e);
+            }
+        }
+    }
+}
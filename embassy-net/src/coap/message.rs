@@ -0,0 +1,403 @@
+//! Message format (RFC 7252 section 3).
+
+use super::{Block, Code, ContentFormat, Error, OptionNumber, Type, MAX_TOKEN_LEN};
+
+const VERSION: u8 = 1;
+const PAYLOAD_MARKER: u8 = 0xff;
+
+/// A CoAP message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Message<'m> {
+    ty: Type,
+    code: Code,
+    message_id: u16,
+    token: &'m [u8],
+    options: Options<'m>,
+    payload: &'m [u8],
+}
+
+impl<'m> Message<'m> {
+    /// Parse a message.
+    pub fn parse(data: &'m [u8]) -> Result<Self, Error> {
+        if data.len() < 4 || data[0] >> 6 != VERSION {
+            return Err(Error::Malformed);
+        }
+        let token_len = (data[0] & 0x0f) as usize;
+        if token_len > MAX_TOKEN_LEN || data.len() < 4 + token_len {
+            return Err(Error::Malformed);
+        }
+        let code = Code(data[1]);
+        if code == Code::EMPTY && data.len() > 4 {
+            return Err(Error::Malformed);
+        }
+
+        let rest = &data[4 + token_len..];
+        let mut len = 0;
+        let mut number = 0u16;
+        while len < rest.len() && rest[len] != PAYLOAD_MARKER {
+            let (delta, _, option_len) = parse_option(&rest[len..]).ok_or(Error::Malformed)?;
+            number = number.checked_add(delta).ok_or(Error::Malformed)?;
+            len += option_len;
+        }
+        let (options, payload) = rest.split_at(len);
+        let payload = match payload.split_first() {
+            // The marker must be followed by a payload.
+            Some((_, [])) => return Err(Error::Malformed),
+            Some((_, payload)) => payload,
+            None => &[],
+        };
+
+        Ok(Self {
+            ty: Type::from_bits(data[0] >> 4),
+            code,
+            message_id: u16::from_be_bytes([data[2], data[3]]),
+            token: &data[4..4 + token_len],
+            options: Options::new(options),
+            payload,
+        })
+    }
+
+    /// Get the type of the message.
+    pub fn ty(&self) -> Type {
+        self.ty
+    }
+
+    /// Get the code of the message.
+    pub fn code(&self) -> Code {
+        self.code
+    }
+
+    /// Get the message ID, matching acknowledgements and resets to confirmable messages.
+    pub fn message_id(&self) -> u16 {
+        self.message_id
+    }
+
+    /// Get the token, matching responses to requests.
+    pub fn token(&self) -> &'m [u8] {
+        self.token
+    }
+
+    /// Get the options.
+    pub fn options(&self) -> Options<'m> {
+        self.options
+    }
+
+    /// Get the payload.
+    pub fn payload(&self) -> &'m [u8] {
+        self.payload
+    }
+}
+
+/// Iterator over the options of a message, with their numbers and values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options<'m> {
+    data: &'m [u8],
+    number: u16,
+}
+
+impl<'m> Options<'m> {
+    /// Iterate over options which have been checked by [`Message::parse`].
+    pub(crate) const fn new(data: &'m [u8]) -> Self {
+        Self { data, number: 0 }
+    }
+
+    /// Get the encoded options which are left.
+    pub(crate) fn as_bytes(&self) -> &'m [u8] {
+        self.data
+    }
+
+    /// Get the value of the first option `number`.
+    pub fn get(&self, number: OptionNumber) -> Option<&'m [u8]> {
+        self.get_all(number).next()
+    }
+
+    /// Get the values of all the options `number`, e.g. the segments of the path.
+    pub fn get_all(&self, number: OptionNumber) -> impl Iterator<Item = &'m [u8]> {
+        // Options are sorted by number.
+        self.skip_while(move |(n, _)| *n < number)
+            .take_while(move |(n, _)| *n == number)
+            .map(|(_, value)| value)
+    }
+
+    /// Get the value of the first option `number`, as an unsigned integer.
+    pub fn get_uint(&self, number: OptionNumber) -> Option<u32> {
+        let value = self.get(number)?;
+        if value.len() > 4 {
+            return None;
+        }
+        Some(value.iter().fold(0, |acc, b| (acc << 8) | *b as u32))
+    }
+
+    /// Get the Content-Format option.
+    pub fn content_format(&self) -> Option<ContentFormat> {
+        self.get_uint(OptionNumber::CONTENT_FORMAT)
+            .map(|v| ContentFormat(v as u16))
+    }
+
+    /// Get the Observe option.
+    pub fn observe(&self) -> Option<u32> {
+        self.get_uint(OptionNumber::OBSERVE)
+    }
+
+    /// Get the Block1 option.
+    pub fn block1(&self) -> Option<Block> {
+        self.get_uint(OptionNumber::BLOCK1).and_then(Block::from_uint)
+    }
+
+    /// Get the Block2 option.
+    pub fn block2(&self) -> Option<Block> {
+        self.get_uint(OptionNumber::BLOCK2).and_then(Block::from_uint)
+    }
+}
+
+impl<'m> Iterator for Options<'m> {
+    type Item = (OptionNumber, &'m [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (delta, value, len) = parse_option(self.data)?;
+        self.number += delta;
+        self.data = &self.data[len..];
+        Some((OptionNumber(self.number), value))
+    }
+}
+
+/// Parse the option at the start of `data`, returning its number delta, its value and its
+/// length.
+fn parse_option(data: &[u8]) -> Option<(u16, &[u8], usize)> {
+    let (&first, mut rest) = data.split_first()?;
+    let delta = extended(first >> 4, &mut rest)?;
+    let len = extended(first & 0x0f, &mut rest)? as usize;
+    let value = rest.get(..len)?;
+    Some((delta, value, data.len() - rest.len() + len))
+}
+
+/// Decode the option delta or length `nibble`, taking its extended bytes from `data`.
+fn extended(nibble: u8, data: &mut &[u8]) -> Option<u16> {
+    let (value, len) = match nibble {
+        0..=12 => return Some(nibble as u16),
+        13 => (*data.first()? as u16 + 13, 1),
+        14 => {
+            let bytes = data.get(..2)?;
+            (u16::from_be_bytes([bytes[0], bytes[1]]).checked_add(269)?, 2)
+        }
+        _ => return None,
+    };
+    *data = &data[len..];
+    Some(value)
+}
+
+/// Encode an option delta or length, as a nibble and extended bytes.
+fn extend(value: u16) -> (u8, [u8; 2], usize) {
+    match value {
+        0..=12 => (value as u8, [0; 2], 0),
+        13..=268 => (13, [(value - 13) as u8, 0], 1),
+        _ => (14, (value - 269).to_be_bytes(), 2),
+    }
+}
+
+/// Writes a message into a buffer.
+///
+/// Options must be written by increasing number, before the payload.
+pub(crate) struct Writer<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+    number: u16,
+}
+
+impl<'b> Writer<'b> {
+    /// Write the header of a message.
+    pub(crate) fn new(buf: &'b mut [u8], ty: Type, code: Code, message_id: u16, token: &[u8]) -> Result<Self, Error> {
+        debug_assert!(token.len() <= MAX_TOKEN_LEN);
+        let len = 4 + token.len();
+        if buf.len() < len {
+            return Err(Error::BufferTooSmall);
+        }
+        buf[0] = (VERSION << 6) | ((ty as u8) << 4) | token.len() as u8;
+        buf[1] = code.0;
+        buf[2..4].copy_from_slice(&message_id.to_be_bytes());
+        buf[4..len].copy_from_slice(token);
+        Ok(Self { buf, len, number: 0 })
+    }
+
+    /// Get the length of the message written so far.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Write an option.
+    pub(crate) fn option(&mut self, number: OptionNumber, value: &[u8]) -> Result<(), Error> {
+        debug_assert!(number.0 >= self.number);
+        let (delta, delta_ext, delta_ext_len) = extend(number.0 - self.number);
+        let (len, len_ext, len_ext_len) = extend(value.len() as u16);
+        let option_len = 1 + delta_ext_len + len_ext_len + value.len();
+        let Some(buf) = self.buf.get_mut(self.len..self.len + option_len) else {
+            return Err(Error::BufferTooSmall);
+        };
+        buf[0] = (delta << 4) | len;
+        let (ext, value_buf) = buf[1..].split_at_mut(delta_ext_len + len_ext_len);
+        ext[..delta_ext_len].copy_from_slice(&delta_ext[..delta_ext_len]);
+        ext[delta_ext_len..].copy_from_slice(&len_ext[..len_ext_len]);
+        value_buf.copy_from_slice(value);
+        self.len += option_len;
+        self.number = number.0;
+        Ok(())
+    }
+
+    /// Write an option with an unsigned integer value, in as few bytes as possible.
+    pub(crate) fn uint_option(&mut self, number: OptionNumber, value: u32) -> Result<(), Error> {
+        let bytes = value.to_be_bytes();
+        self.option(number, &bytes[value.leading_zeros() as usize / 8..])
+    }
+
+    /// Write the payload, if it isn't empty.
+    pub(crate) fn payload(&mut self, payload: &[u8]) -> Result<(), Error> {
+        if payload.is_empty() {
+            return Ok(());
+        }
+        let Some(buf) = self.buf.get_mut(self.len..self.len + 1 + payload.len()) else {
+            return Err(Error::BufferTooSmall);
+        };
+        buf[0] = PAYLOAD_MARKER;
+        buf[1..].copy_from_slice(payload);
+        self.len += 1 + payload.len();
+        Ok(())
+    }
+
+    /// Write the payload, if it isn't empty, from `len` bytes at `start` in the buffer, after
+    /// the message written so far.
+    pub(crate) fn payload_from(&mut self, start: usize, len: usize) -> Result<(), Error> {
+        if len == 0 {
+            return Ok(());
+        }
+        if self.len >= start {
+            return Err(Error::BufferTooSmall);
+        }
+        self.buf[self.len] = PAYLOAD_MARKER;
+        self.buf.copy_within(start..start + len, self.len + 1);
+        self.len += 1 + len;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut buf = [0; 600];
+        let mut writer = Writer::new(&mut buf, Type::Confirmable, Code::PUT, 0x1234, &[0xaa, 0xbb]).unwrap();
+        writer.option(OptionNumber::URI_PATH, b"firmware").unwrap();
+        // A delta of 16 and a length of 13: one extended byte each.
+        writer.option(OptionNumber::BLOCK1, &[0; 13]).unwrap();
+        // A delta of 1000 and a length of 300: two extended bytes each.
+        writer.option(OptionNumber(1027), &[0x5a; 300]).unwrap();
+        writer.payload(b"data").unwrap();
+        let len = writer.len();
+
+        assert_eq!(buf[..5], [0x42, 0x03, 0x12, 0x34, 0xaa]);
+        assert_eq!(buf[6], 0xb8);
+        assert_eq!(buf[15..17], [0xdd, 16 - 13]);
+        assert_eq!(buf[31..36], [0xee, 0x02, 0xdb, 0x00, 0x1f]);
+        assert_eq!(len, 36 + 300 + 5);
+
+        let message = Message::parse(&buf[..len]).unwrap();
+        assert_eq!(message.ty(), Type::Confirmable);
+        assert_eq!(message.code(), Code::PUT);
+        assert_eq!(message.message_id(), 0x1234);
+        assert_eq!(message.token(), [0xaa, 0xbb]);
+        let options: Vec<_> = message.options().collect();
+        core::assert_eq!(
+            options,
+            [
+                (OptionNumber::URI_PATH, &b"firmware"[..]),
+                (OptionNumber::BLOCK1, &[0; 13][..]),
+                (OptionNumber(1027), &[0x5a; 300][..]),
+            ]
+        );
+        assert_eq!(message.payload(), b"data");
+    }
+
+    #[test]
+    fn uint_options() {
+        let mut buf = [0; 32];
+        let mut writer = Writer::new(&mut buf, Type::NonConfirmable, Code::CONTENT, 1, &[]).unwrap();
+        writer.uint_option(OptionNumber::OBSERVE, 0).unwrap();
+        writer.uint_option(OptionNumber::CONTENT_FORMAT, 50).unwrap();
+        writer
+            .uint_option(
+                OptionNumber::BLOCK2,
+                Block {
+                    num: 3,
+                    more: true,
+                    szx: 2,
+                }
+                .to_uint(),
+            )
+            .unwrap();
+        let len = writer.len();
+        // Zero is encoded without bytes.
+        assert_eq!(buf[4..len], [0x60, 0x61, 50, 0xb1, 0x3a]);
+
+        let options = Message::parse(&buf[..len]).unwrap().options();
+        assert_eq!(options.observe(), Some(0));
+        assert_eq!(options.content_format(), Some(ContentFormat::JSON));
+        assert_eq!(
+            options.block2(),
+            Some(Block {
+                num: 3,
+                more: true,
+                szx: 2
+            })
+        );
+        assert_eq!(options.block2().unwrap().offset(), 192);
+    }
+
+    #[test]
+    fn malformed() {
+        // A payload marker without payload.
+        core::assert_eq!(Message::parse(&[0x40, 0x01, 0, 1, 0xff]), Err(Error::Malformed));
+        // Truncated option value, extended delta and extended length.
+        core::assert_eq!(Message::parse(&[0x40, 0x01, 0, 1, 0xb4, b'a']), Err(Error::Malformed));
+        core::assert_eq!(Message::parse(&[0x40, 0x01, 0, 1, 0xe0, 0x01]), Err(Error::Malformed));
+        core::assert_eq!(Message::parse(&[0x40, 0x01, 0, 1, 0x1d]), Err(Error::Malformed));
+        // Reserved nibble.
+        core::assert_eq!(Message::parse(&[0x40, 0x01, 0, 1, 0xf0]), Err(Error::Malformed));
+        // Option numbers over 65535.
+        core::assert_eq!(
+            Message::parse(&[0x40, 0x01, 0, 1, 0xe0, 0xff, 0x00, 0xe0, 0xff, 0x00]),
+            Err(Error::Malformed)
+        );
+        // Token longer than the message, and longer than 8 bytes.
+        core::assert_eq!(Message::parse(&[0x42, 0x01, 0, 1, 0xaa]), Err(Error::Malformed));
+        core::assert_eq!(
+            Message::parse(&[0x49, 0x01, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+            Err(Error::Malformed)
+        );
+        // Empty messages have nothing after the header.
+        core::assert_eq!(Message::parse(&[0x60, 0x00, 0, 1, 0xaa]), Err(Error::Malformed));
+        // Version 2.
+        core::assert_eq!(Message::parse(&[0x80, 0x01, 0, 1]), Err(Error::Malformed));
+
+        // An empty acknowledgement.
+        let ack = Message::parse(&[0x60, 0x00, 0, 1]).unwrap();
+        assert_eq!(
+            (ack.ty(), ack.code(), ack.payload()),
+            (Type::Acknowledgement, Code::EMPTY, &[][..])
+        );
+    }
+
+    #[test]
+    fn buffer_too_small() {
+        let mut buf = [0; 8];
+        assert!(Writer::new(&mut buf[..3], Type::Confirmable, Code::GET, 1, &[]).is_err());
+        let mut writer = Writer::new(&mut buf, Type::Confirmable, Code::GET, 1, &[]).unwrap();
+        assert_eq!(
+            writer.option(OptionNumber::URI_PATH, b"long"),
+            Err(Error::BufferTooSmall)
+        );
+        assert_eq!(writer.payload(b"long"), Err(Error::BufferTooSmall));
+    }
+}
@@ -0,0 +1,472 @@
+//! CoAP (RFC 7252) client and server, over UDP.
+//!
+//! Both sides support:
+//!
+//! - confirmable messages, retransmitted with exponential backoff until they are acknowledged;
+//! - deduplication of the messages received more than once;
+//! - block-wise transfers (RFC 7959), for representations larger than a datagram, such as
+//!   firmware images;
+//! - observing resources (RFC 7641): the server sends a notification to the observers of a
+//!   resource each time [`Server::notify`](server::Server::notify) is called for it, e.g. by
+//!   [`Server::notify_on_change`](server::Server::notify_on_change) when the value of a
+//!   [`Watch`](embassy_sync::watch::Watch) changes.
+//!
+//! A [`Server`](server::Server) dispatches requests to handlers with a static route table, like
+//! the [HTTP server](crate::http::server). A [`Client`](client::Client) sends requests and
+//! observes resources.
+//!
+//! Both take a [`RngCore`] for their message IDs, tokens and retransmission timeouts. It should be
+//! a hardware random number generator, or seeded from one, so that tokens are hard to guess and
+//! message IDs aren't reused after a reboot.
+
+use embassy_time::{Duration, Instant};
+use rand_core::RngCore;
+
+use crate::udp::SendError;
+
+pub mod client;
+mod message;
+pub mod server;
+
+pub use message::{Message, Options};
+
+/// Default CoAP port.
+pub const PORT: u16 = 5683;
+
+/// Largest token length.
+const MAX_TOKEN_LEN: usize = 8;
+
+/// Room for the header and options of a message, next to a block of payload.
+const PAYLOAD_ROOM: usize = 64;
+
+/// Largest number of retransmissions of a confirmable message.
+const MAX_RETRANSMIT_LIMIT: u8 = 16;
+
+/// Type of a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Type {
+    /// A message which must be acknowledged, and is retransmitted until it is.
+    Confirmable = 0,
+    /// A message which isn't acknowledged.
+    NonConfirmable = 1,
+    /// An acknowledgement of a confirmable message.
+    Acknowledgement = 2,
+    /// A rejection of a message, which couldn't be processed.
+    Reset = 3,
+}
+
+impl Type {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0x03 {
+            0 => Type::Confirmable,
+            1 => Type::NonConfirmable,
+            2 => Type::Acknowledgement,
+            _ => Type::Reset,
+        }
+    }
+}
+
+/// Method of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Method {
+    /// GET
+    Get,
+    /// POST
+    Post,
+    /// PUT
+    Put,
+    /// DELETE
+    Delete,
+}
+
+impl Method {
+    /// Get the code of requests with this method.
+    pub const fn code(self) -> Code {
+        match self {
+            Method::Get => Code::GET,
+            Method::Post => Code::POST,
+            Method::Put => Code::PUT,
+            Method::Delete => Code::DELETE,
+        }
+    }
+
+    fn from_code(code: Code) -> Option<Self> {
+        match code {
+            Code::GET => Some(Method::Get),
+            Code::POST => Some(Method::Post),
+            Code::PUT => Some(Method::Put),
+            Code::DELETE => Some(Method::Delete),
+            _ => None,
+        }
+    }
+}
+
+/// Code of a message: a request method, a response code, or [`Code::EMPTY`].
+///
+/// Codes are written `c.dd`, with a class `c` and a detail `dd`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Code(pub u8);
+
+impl Code {
+    /// 0.00 Empty, for messages which are neither requests nor responses
+    pub const EMPTY: Self = Self::new(0, 0);
+
+    /// 0.01 GET
+    pub const GET: Self = Self::new(0, 1);
+    /// 0.02 POST
+    pub const POST: Self = Self::new(0, 2);
+    /// 0.03 PUT
+    pub const PUT: Self = Self::new(0, 3);
+    /// 0.04 DELETE
+    pub const DELETE: Self = Self::new(0, 4);
+
+    /// 2.01 Created
+    pub const CREATED: Self = Self::new(2, 1);
+    /// 2.02 Deleted
+    pub const DELETED: Self = Self::new(2, 2);
+    /// 2.03 Valid
+    pub const VALID: Self = Self::new(2, 3);
+    /// 2.04 Changed
+    pub const CHANGED: Self = Self::new(2, 4);
+    /// 2.05 Content
+    pub const CONTENT: Self = Self::new(2, 5);
+    /// 2.31 Continue, asking for the next block of a request
+    pub const CONTINUE: Self = Self::new(2, 31);
+
+    /// 4.00 Bad Request
+    pub const BAD_REQUEST: Self = Self::new(4, 0);
+    /// 4.01 Unauthorized
+    pub const UNAUTHORIZED: Self = Self::new(4, 1);
+    /// 4.02 Bad Option
+    pub const BAD_OPTION: Self = Self::new(4, 2);
+    /// 4.03 Forbidden
+    pub const FORBIDDEN: Self = Self::new(4, 3);
+    /// 4.04 Not Found
+    pub const NOT_FOUND: Self = Self::new(4, 4);
+    /// 4.05 Method Not Allowed
+    pub const METHOD_NOT_ALLOWED: Self = Self::new(4, 5);
+    /// 4.06 Not Acceptable
+    pub const NOT_ACCEPTABLE: Self = Self::new(4, 6);
+    /// 4.08 Request Entity Incomplete
+    pub const REQUEST_ENTITY_INCOMPLETE: Self = Self::new(4, 8);
+    /// 4.12 Precondition Failed
+    pub const PRECONDITION_FAILED: Self = Self::new(4, 12);
+    /// 4.13 Request Entity Too Large
+    pub const REQUEST_ENTITY_TOO_LARGE: Self = Self::new(4, 13);
+    /// 4.15 Unsupported Content-Format
+    pub const UNSUPPORTED_CONTENT_FORMAT: Self = Self::new(4, 15);
+
+    /// 5.00 Internal Server Error
+    pub const INTERNAL_SERVER_ERROR: Self = Self::new(5, 0);
+    /// 5.01 Not Implemented
+    pub const NOT_IMPLEMENTED: Self = Self::new(5, 1);
+    /// 5.02 Bad Gateway
+    pub const BAD_GATEWAY: Self = Self::new(5, 2);
+    /// 5.03 Service Unavailable
+    pub const SERVICE_UNAVAILABLE: Self = Self::new(5, 3);
+    /// 5.04 Gateway Timeout
+    pub const GATEWAY_TIMEOUT: Self = Self::new(5, 4);
+    /// 5.05 Proxying Not Supported
+    pub const PROXYING_NOT_SUPPORTED: Self = Self::new(5, 5);
+
+    /// Create the code `class.detail`.
+    pub const fn new(class: u8, detail: u8) -> Self {
+        Self((class << 5) | (detail & 0x1f))
+    }
+
+    /// Get the class of the code: 0 for requests, 2 for success, 4 for client errors and 5 for
+    /// server errors.
+    pub const fn class(self) -> u8 {
+        self.0 >> 5
+    }
+
+    /// Get the detail of the code.
+    pub const fn detail(self) -> u8 {
+        self.0 & 0x1f
+    }
+
+    /// Whether this is the code of a request.
+    pub const fn is_request(self) -> bool {
+        self.class() == 0 && self.0 != 0
+    }
+
+    /// Whether this is the code of a successful response.
+    pub const fn is_success(self) -> bool {
+        self.class() == 2
+    }
+}
+
+/// Number of an option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OptionNumber(pub u16);
+
+impl OptionNumber {
+    /// If-Match
+    pub const IF_MATCH: Self = Self(1);
+    /// Uri-Host
+    pub const URI_HOST: Self = Self(3);
+    /// ETag
+    pub const ETAG: Self = Self(4);
+    /// If-None-Match
+    pub const IF_NONE_MATCH: Self = Self(5);
+    /// Observe (RFC 7641)
+    pub const OBSERVE: Self = Self(6);
+    /// Uri-Port
+    pub const URI_PORT: Self = Self(7);
+    /// Location-Path
+    pub const LOCATION_PATH: Self = Self(8);
+    /// Uri-Path, one option per path segment
+    pub const URI_PATH: Self = Self(11);
+    /// Content-Format
+    pub const CONTENT_FORMAT: Self = Self(12);
+    /// Max-Age
+    pub const MAX_AGE: Self = Self(14);
+    /// Uri-Query, one option per query argument
+    pub const URI_QUERY: Self = Self(15);
+    /// Accept
+    pub const ACCEPT: Self = Self(17);
+    /// Location-Query
+    pub const LOCATION_QUERY: Self = Self(20);
+    /// Block2 (RFC 7959), for the blocks of responses
+    pub const BLOCK2: Self = Self(23);
+    /// Block1 (RFC 7959), for the blocks of requests
+    pub const BLOCK1: Self = Self(27);
+    /// Size2 (RFC 7959)
+    pub const SIZE2: Self = Self(28);
+    /// Proxy-Uri
+    pub const PROXY_URI: Self = Self(35);
+    /// Proxy-Scheme
+    pub const PROXY_SCHEME: Self = Self(39);
+    /// Size1
+    pub const SIZE1: Self = Self(60);
+
+    /// Whether the option is critical: a message with a critical option which isn't understood
+    /// must be rejected.
+    pub const fn is_critical(self) -> bool {
+        self.0 & 1 != 0
+    }
+}
+
+/// Content format of a payload, from the CoAP Content-Formats registry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ContentFormat(pub u16);
+
+impl ContentFormat {
+    /// `text/plain; charset=utf-8`
+    pub const TEXT_PLAIN: Self = Self(0);
+    /// `application/link-format`
+    pub const LINK_FORMAT: Self = Self(40);
+    /// `application/xml`
+    pub const XML: Self = Self(41);
+    /// `application/octet-stream`
+    pub const OCTET_STREAM: Self = Self(42);
+    /// `application/exi`
+    pub const EXI: Self = Self(47);
+    /// `application/json`
+    pub const JSON: Self = Self(50);
+    /// `application/cbor`
+    pub const CBOR: Self = Self(60);
+}
+
+/// Block option of a block-wise transfer (RFC 7959).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Block {
+    /// Number of the block.
+    pub num: u32,
+    /// Whether more blocks follow this one.
+    pub more: bool,
+    /// Size exponent: blocks are `2 ^ (szx + 4)` bytes long, from 16 to 1024 bytes.
+    pub szx: u8,
+}
+
+impl Block {
+    /// Size of the blocks, in bytes.
+    pub const fn size(&self) -> usize {
+        16 << self.szx
+    }
+
+    /// Offset of this block in the whole representation, in bytes.
+    pub const fn offset(&self) -> usize {
+        self.num as usize * self.size()
+    }
+
+    fn from_uint(value: u32) -> Option<Self> {
+        let szx = (value & 0x07) as u8;
+        if szx == 7 {
+            return None;
+        }
+        Some(Self {
+            num: value >> 4,
+            more: value & 0x08 != 0,
+            szx,
+        })
+    }
+
+    fn to_uint(self) -> u32 {
+        (self.num << 4) | ((self.more as u32) << 3) | self.szx as u32
+    }
+}
+
+/// Get the size exponent of the largest block size up to `size`.
+fn size_exponent(size: usize) -> u8 {
+    (size.clamp(16, 1024).ilog2() - 4) as u8
+}
+
+/// CoAP error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// A message couldn't be sent.
+    Send(SendError),
+    /// The peer didn't answer in time.
+    Timeout,
+    /// The peer rejected a message with a reset.
+    Reset,
+    /// The peer sent an invalid message.
+    Malformed,
+    /// A message doesn't fit in the buffer.
+    BufferTooSmall,
+    /// The peer didn't follow a block-wise transfer.
+    UnexpectedBlock,
+    /// The client isn't observing a resource.
+    NotObserving,
+}
+
+impl From<SendError> for Error {
+    fn from(e: SendError) -> Self {
+        Error::Send(e)
+    }
+}
+
+/// CoAP configuration, for clients and servers.
+///
+/// The transmission parameters default to the values of RFC 7252 section 4.8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Config {
+    /// Port a [`Server`](server::Server) listens on. Clients use an ephemeral port.
+    pub port: u16,
+    /// Time to wait for the acknowledgement of a confirmable message before sending it again.
+    ///
+    /// The first timeout is picked at random between this value and 1.5 times this value, and
+    /// doubles after each retransmission.
+    pub ack_timeout: Duration,
+    /// Number of retransmissions of a confirmable message before giving up, up to 16.
+    ///
+    /// Larger values are taken as 16.
+    pub max_retransmit: u8,
+    /// Largest size of the blocks of block-wise transfers, from 16 to 1024 bytes.
+    ///
+    /// Sizes which aren't a power of two are rounded down. A smaller size may be used when the
+    /// peer asks for it, or when the buffers are too small.
+    pub block_size: usize,
+}
+
+impl Config {
+    /// Create a default configuration.
+    pub const fn new() -> Self {
+        Self {
+            port: PORT,
+            ack_timeout: Duration::from_secs(2),
+            max_retransmit: 4,
+            block_size: 256,
+        }
+    }
+
+    /// Get the number of retransmissions of a confirmable message.
+    fn max_retransmit(&self) -> u8 {
+        self.max_retransmit.min(MAX_RETRANSMIT_LIMIT)
+    }
+
+    /// Pick the timeout of the first transmission of a confirmable message.
+    fn initial_timeout(&self, rng: &mut impl RngCore) -> Duration {
+        let jitter = (rng.next_u32() % 501) as u64;
+        let ticks = self.ack_timeout.as_ticks();
+        Duration::from_ticks(ticks.saturating_add(ticks.saturating_mul(jitter) / 1000))
+    }
+
+    /// Get `factor` times the longest initial timeout, saturating.
+    fn ack_timeouts(&self, factor: u64) -> Duration {
+        Duration::from_ticks((self.ack_timeout.as_ticks().saturating_mul(factor) / 2).saturating_mul(3))
+    }
+
+    /// Longest time from the first transmission of a confirmable message to giving up on its
+    /// acknowledgement (MAX_TRANSMIT_WAIT).
+    fn max_transmit_wait(&self) -> Duration {
+        self.ack_timeouts((2 << self.max_retransmit()) - 1)
+    }
+
+    /// How long a message ID must not be reused, and duplicates of a message are recognized
+    /// (EXCHANGE_LIFETIME).
+    fn exchange_lifetime(&self) -> Duration {
+        const MAX_LATENCY: Duration = Duration::from_secs(100);
+        let max_transmit_span = self.ack_timeouts((1 << self.max_retransmit()) - 1);
+        Duration::from_ticks(
+            max_transmit_span
+                .as_ticks()
+                .saturating_add(MAX_LATENCY.as_ticks() * 2)
+                .saturating_add(self.ack_timeout.as_ticks()),
+        )
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Double a retransmission timeout, saturating.
+fn backoff(timeout: Duration) -> Duration {
+    Duration::from_ticks(timeout.as_ticks().saturating_mul(2))
+}
+
+/// Get the instant `duration` after `instant`, saturating.
+fn after(instant: Instant, duration: Duration) -> Instant {
+    instant.checked_add(duration).unwrap_or(Instant::MAX)
+}
+
+fn next_message_id(message_id: &mut u16) -> u16 {
+    *message_id = message_id.wrapping_add(1);
+    *message_id
+}
+
+/// Split a path with an optional query, like `sensors/temperature?unit=C&precise`, into its
+/// segments and query arguments.
+fn split_path(path: &str) -> (impl Iterator<Item = &str>, impl Iterator<Item = &str>) {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    (
+        path.split('/').filter(|s| !s.is_empty()),
+        query.split('&').filter(|s| !s.is_empty()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transmission_parameters() {
+        // The default values of RFC 7252 section 4.8.2.
+        let config = Config::new();
+        assert_eq!(config.max_transmit_wait(), Duration::from_secs(93));
+        assert_eq!(config.exchange_lifetime(), Duration::from_secs(247));
+
+        let mut config = Config::new();
+        config.max_retransmit = u8::MAX;
+        assert_eq!(config.max_transmit_wait(), config.ack_timeouts((2 << 16) - 1));
+
+        // Huge timeouts saturate.
+        config.ack_timeout = Duration::MAX;
+        assert_eq!(config.max_transmit_wait(), Duration::MAX);
+        assert_eq!(config.exchange_lifetime(), Duration::MAX);
+        assert_eq!(backoff(config.ack_timeout), Duration::MAX);
+        assert_eq!(after(Instant::from_secs(1), config.ack_timeout), Instant::MAX);
+    }
+}
@@ -0,0 +1,859 @@
+//! CoAP server.
+//!
+//! Requests are dispatched to handlers with a static route table. All the handlers of a server
+//! have the same type, usually an enum implementing [`Handler`].
+//!
+//! Responses are piggybacked on the acknowledgements of confirmable requests, and the last
+//! responses are kept in the [`ServerState`] to answer duplicate requests. Representations
+//! larger than [`Config::block_size`] are sent in blocks (Block2), and the blocks of large
+//! requests are passed to the handler one at a time (Block1).
+//!
+//! Clients can observe the resources of GET routes. Each time [`Server::notify`] is called for a
+//! route, the handler is called again for each of its observers, and the response is sent as a
+//! confirmable notification. Observers which don't acknowledge a notification are forgotten.
+//!
+//! ```ignore
+//! use embassy_net::coap::server::{Handler, Request, Response, Route, Server, ServerState};
+//! use embassy_net::coap::{Code, Config, Method};
+//! use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+//! use embassy_sync::watch::Watch;
+//!
+//! static TEMPERATURE: Watch<CriticalSectionRawMutex, i32, 2> = Watch::new();
+//!
+//! enum Api {
+//!     Temperature,
+//!     Firmware,
+//! }
+//!
+//! impl Handler for Api {
+//!     async fn handle(&self, request: &Request<'_>, response: &mut Response<'_>) {
+//!         match self {
+//!             Api::Temperature => {
+//!                 let value = TEMPERATURE.try_get().unwrap_or_default();
+//!                 write!(response, "{}", value).unwrap();
+//!             }
+//!             Api::Firmware => {
+//!                 let block = request.block1();
+//!                 write_flash(block.map_or(0, |b| b.offset()), request.payload()).await;
+//!                 if block.is_some_and(|b| b.more) {
+//!                     response.set_code(Code::CONTINUE);
+//!                 }
+//!             }
+//!         }
+//!     }
+//! }
+//!
+//! static ROUTES: [Route<'static, Api>; 2] = [
+//!     Route::new(Method::Get, "sensors/temperature", Api::Temperature),
+//!     Route::new(Method::Put, "firmware", Api::Firmware),
+//! ];
+//! static STATE: StaticCell<ServerState<4, 4>> = StaticCell::new();
+//!
+//! let server: Server<'_, CriticalSectionRawMutex, _, _> = Server::new(socket, Config::new(), &ROUTES, rng).unwrap();
+//! join(
+//!     server.run(STATE.init(ServerState::new())),
+//!     server.notify_on_change("sensors/temperature", TEMPERATURE.dyn_receiver().unwrap()),
+//! )
+//! .await;
+//! ```
+
+use core::cell::{Cell, RefCell};
+use core::fmt;
+use core::future::pending;
+use core::ops::Range;
+
+use embassy_futures::select::{select3, Either3};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_sync::watch::DynReceiver;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::Vec;
+use rand_core::RngCore;
+
+use super::message::{Message, Options, Writer};
+use super::{
+    after, backoff, next_message_id, size_exponent, Block, Code, Config, ContentFormat, Error, Method, OptionNumber,
+    Type, MAX_TOKEN_LEN, PAYLOAD_ROOM,
+};
+use crate::udp::{BindError, UdpSocket};
+use crate::IpEndpoint;
+
+/// Largest length of the options of an observation request, which are kept to produce the
+/// notifications.
+const MAX_OBSERVE_OPTIONS_LEN: usize = 64;
+
+/// Number of routes which can be observed, from the start of the route table.
+const MAX_OBSERVABLE_ROUTES: usize = 32;
+
+/// Critical options handled by the server or passed to handlers. Requests with other critical
+/// options are rejected.
+const KNOWN_CRITICAL_OPTIONS: [OptionNumber; 7] = [
+    OptionNumber::URI_HOST,
+    OptionNumber::URI_PORT,
+    OptionNumber::URI_PATH,
+    OptionNumber::URI_QUERY,
+    OptionNumber::ACCEPT,
+    OptionNumber::BLOCK2,
+    OptionNumber::BLOCK1,
+];
+
+/// A route, mapping requests to a handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Route<'a, H> {
+    /// Method of the requests.
+    pub method: Method,
+    /// Path of the requests, like `sensors/temperature`.
+    ///
+    /// A last segment `*` matches any remaining segments.
+    pub path: &'a str,
+    /// Handler of the requests.
+    pub handler: H,
+}
+
+impl<'a, H> Route<'a, H> {
+    /// Create a route.
+    pub const fn new(method: Method, path: &'a str, handler: H) -> Self {
+        Self { method, path, handler }
+    }
+
+    fn matches_path<'p>(&self, mut path: impl Iterator<Item = &'p [u8]>) -> bool {
+        for segment in self.path.split('/').filter(|s| !s.is_empty()) {
+            if segment == "*" {
+                return true;
+            }
+            if path.next() != Some(segment.as_bytes()) {
+                return false;
+            }
+        }
+        path.next().is_none()
+    }
+}
+
+/// Request handler.
+pub trait Handler {
+    /// Handle a request, filling the response.
+    ///
+    /// The response code is 2.05 Content for GET requests, 2.04 Changed for POST and PUT
+    /// requests and 2.02 Deleted for DELETE requests, unless set with [`Response::set_code`].
+    ///
+    /// GET requests are handled again for each block of a large representation, and for each
+    /// notification to observers: the handler must write the whole representation each time.
+    async fn handle(&self, request: &Request<'_>, response: &mut Response<'_>);
+}
+
+/// A request received by a [`Server`].
+pub struct Request<'r> {
+    remote: IpEndpoint,
+    method: Method,
+    options: Options<'r>,
+    payload: &'r [u8],
+    notification: bool,
+}
+
+impl<'r> Request<'r> {
+    /// Get the address and port of the client.
+    pub fn remote_endpoint(&self) -> IpEndpoint {
+        self.remote
+    }
+
+    /// Get the method of the request.
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    /// Get the segments of the path. Segments which aren't valid UTF-8 are empty.
+    pub fn path(&self) -> impl Iterator<Item = &'r str> {
+        self.options
+            .get_all(OptionNumber::URI_PATH)
+            .map(|s| core::str::from_utf8(s).unwrap_or(""))
+    }
+
+    /// Get the arguments of the query, like `unit=C`. Arguments which aren't valid UTF-8 are
+    /// empty.
+    pub fn query(&self) -> impl Iterator<Item = &'r str> {
+        self.options
+            .get_all(OptionNumber::URI_QUERY)
+            .map(|s| core::str::from_utf8(s).unwrap_or(""))
+    }
+
+    /// Get the options of the request.
+    pub fn options(&self) -> Options<'r> {
+        self.options
+    }
+
+    /// Get the Block1 option, when the payload is a block of a larger request.
+    ///
+    /// The handler should answer [`Code::CONTINUE`] to all the blocks but the last one, and
+    /// [`Code::REQUEST_ENTITY_INCOMPLETE`] to a block it doesn't expect.
+    pub fn block1(&self) -> Option<Block> {
+        self.options.block1()
+    }
+
+    /// Get the payload of the request.
+    pub fn payload(&self) -> &'r [u8] {
+        self.payload
+    }
+
+    /// Whether the request is repeated to send a notification to an observer.
+    pub fn is_notification(&self) -> bool {
+        self.notification
+    }
+}
+
+/// Response to a request received by a [`Server`].
+///
+/// The representation is written with [`write`](Self::write), or with [`write!`]. Only the part
+/// of it in the [`window`](Self::window) is sent: the rest is sent in other blocks.
+pub struct Response<'r> {
+    code: Code,
+    content_format: Option<ContentFormat>,
+    max_age: Option<u32>,
+    etag: Vec<u8, 8>,
+    window: Range<usize>,
+    /// Bytes of the window.
+    buf: &'r mut [u8],
+    /// Length of the representation written so far.
+    len: usize,
+}
+
+impl<'r> Response<'r> {
+    fn new(code: Code, window: Range<usize>, buf: &'r mut [u8]) -> Self {
+        Self {
+            code,
+            content_format: None,
+            max_age: None,
+            etag: Vec::new(),
+            window,
+            buf,
+            len: 0,
+        }
+    }
+
+    /// Set the response code.
+    pub fn set_code(&mut self, code: Code) {
+        self.code = code;
+    }
+
+    /// Set the content format of the representation.
+    pub fn set_content_format(&mut self, content_format: ContentFormat) {
+        self.content_format = Some(content_format);
+    }
+
+    /// Set how many seconds the response can be cached. The default is 60 seconds.
+    pub fn set_max_age(&mut self, seconds: u32) {
+        self.max_age = Some(seconds);
+    }
+
+    /// Set the entity tag of the representation, of 1 to 8 bytes.
+    pub fn set_etag(&mut self, etag: &[u8]) {
+        assert!(!etag.is_empty() && etag.len() <= 8);
+        self.etag = unwrap!(Vec::from_slice(etag));
+    }
+
+    /// Get the part of the representation which is sent in this response.
+    ///
+    /// Handlers producing large representations, like firmware images, can
+    /// [`skip`](Self::skip) to the start of the window and write it only.
+    pub fn window(&self) -> Range<usize> {
+        self.window.clone()
+    }
+
+    /// Write bytes of the representation.
+    ///
+    /// Bytes outside of the window are dropped, but counted in the length of the representation.
+    pub fn write(&mut self, data: &[u8]) {
+        let start = self.len;
+        self.len += data.len();
+        if let Some(range) = self.overlap(start) {
+            self.buf[range.start - self.window.start..range.end - self.window.start]
+                .copy_from_slice(&data[range.start - start..range.end - start]);
+        }
+    }
+
+    /// Skip `len` bytes of the representation. Those in the window are zeroed.
+    pub fn skip(&mut self, len: usize) {
+        let start = self.len;
+        self.len += len;
+        if let Some(range) = self.overlap(start) {
+            self.buf[range.start - self.window.start..range.end - self.window.start].fill(0);
+        }
+    }
+
+    /// Get the part of the window from `start` to the end of the representation.
+    fn overlap(&self, start: usize) -> Option<Range<usize>> {
+        let range = start.max(self.window.start)..self.len.min(self.window.end);
+        (!range.is_empty()).then_some(range)
+    }
+}
+
+impl fmt::Write for Response<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// A notification waiting for an acknowledgement.
+#[derive(Clone, Copy)]
+struct Pending {
+    message_id: u16,
+    retransmissions: u8,
+    timeout: Duration,
+    deadline: Instant,
+}
+
+/// A client observing a resource.
+struct Observer {
+    remote: IpEndpoint,
+    token: Vec<u8, MAX_TOKEN_LEN>,
+    /// Index of the route.
+    route: usize,
+    /// Options of the request, to repeat it for the notifications.
+    options: Vec<u8, MAX_OBSERVE_OPTIONS_LEN>,
+    sequence: u32,
+    /// Whether the resource changed since the last notification.
+    changed: bool,
+    pending: Option<Pending>,
+}
+
+/// A request recently received, to recognize its duplicates.
+struct Exchange<const SZ: usize> {
+    remote: Option<IpEndpoint>,
+    message_id: u16,
+    expires: Instant,
+    /// Length of the response, sent again to duplicates. Duplicates of non-confirmable requests
+    /// are ignored, so their responses aren't kept.
+    len: usize,
+    response: [u8; SZ],
+}
+
+impl<const SZ: usize> Exchange<SZ> {
+    const NEW: Self = Self {
+        remote: None,
+        message_id: 0,
+        expires: Instant::from_ticks(0),
+        len: 0,
+        response: [0; SZ],
+    };
+}
+
+/// State of a [`Server`]: buffers, recent requests and observers.
+///
+/// The responses to the last `N` requests are kept, to answer duplicates, and up to `O` clients
+/// can observe resources. Messages are received and sent in buffers of `SZ` bytes: blocks are
+/// made smaller when needed to fit in them.
+pub struct ServerState<const N: usize, const O: usize, const SZ: usize = 512> {
+    rx: [u8; SZ],
+    tx: [u8; SZ],
+    exchanges: [Exchange<SZ>; N],
+    observers: [Option<Observer>; O],
+    message_id: u16,
+}
+
+impl<const N: usize, const O: usize, const SZ: usize> ServerState<N, O, SZ> {
+    const NO_OBSERVER: Option<Observer> = None;
+
+    /// Create a new `ServerState`.
+    pub const fn new() -> Self {
+        Self {
+            rx: [0; SZ],
+            tx: [0; SZ],
+            exchanges: [Exchange::NEW; N],
+            observers: [Self::NO_OBSERVER; O],
+            message_id: 0,
+        }
+    }
+}
+
+impl<const N: usize, const O: usize, const SZ: usize> Default for ServerState<N, O, SZ> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Header of a response.
+struct Reply<'t> {
+    ty: Type,
+    message_id: u16,
+    token: &'t [u8],
+    /// Observe option of successful responses.
+    observe: Option<u32>,
+}
+
+impl Reply<'_> {
+    /// Write a response without options nor payload.
+    fn empty(&self, tx: &mut [u8], code: Code) -> Result<(usize, Code), Error> {
+        let writer = Writer::new(tx, self.ty, code, self.message_id, self.token)?;
+        Ok((writer.len(), code))
+    }
+}
+
+/// CoAP server.
+pub struct Server<'a, M: RawMutex, H: Handler, R: RngCore> {
+    socket: UdpSocket<'a>,
+    config: Config,
+    routes: &'a [Route<'a, H>],
+    /// Routes which changed since the last notifications, by index.
+    changed: Mutex<M, Cell<u32>>,
+    signal: Signal<M, ()>,
+    rng: Mutex<M, RefCell<R>>,
+}
+
+impl<'a, M: RawMutex, H: Handler, R: RngCore> Server<'a, M, H, R> {
+    /// Create a server dispatching requests with `routes`, binding `socket` to
+    /// [`Config::port`].
+    ///
+    /// The first route matching a request is used. Only the first 32 routes can be observed.
+    pub fn new(
+        mut socket: UdpSocket<'a>,
+        config: Config,
+        routes: &'a [Route<'a, H>],
+        rng: R,
+    ) -> Result<Self, BindError> {
+        socket.bind(config.port)?;
+        Ok(Self {
+            socket,
+            config,
+            routes,
+            changed: Mutex::new(Cell::new(0)),
+            signal: Signal::new(),
+            rng: Mutex::new(RefCell::new(rng)),
+        })
+    }
+
+    /// Notify the observers of the resource at `path` that it changed.
+    ///
+    /// The observers of the first GET route matching `path` get a notification. If the resource
+    /// changes again before a notification is acknowledged, the observer only gets the latest
+    /// representation.
+    pub fn notify(&self, path: &str) {
+        let segments = || path.split('/').filter(|s| !s.is_empty()).map(str::as_bytes);
+        let route = self
+            .routes
+            .iter()
+            .take(MAX_OBSERVABLE_ROUTES)
+            .position(|r| r.method == Method::Get && r.matches_path(segments()));
+        match route {
+            Some(index) => {
+                self.changed.lock(|c| c.set(c.get() | (1 << index)));
+                self.signal.signal(());
+            }
+            None => warn!("CoAP server: no observable route for {}", path),
+        }
+    }
+
+    /// Notify the observers of the resource at `path` each time `receiver` gets a new value,
+    /// forever.
+    pub async fn notify_on_change<T: Clone>(&self, path: &str, mut receiver: DynReceiver<'_, T>) -> ! {
+        // Observers get the current value when they register.
+        receiver.try_get();
+        loop {
+            receiver.changed().await;
+            self.notify(path);
+        }
+    }
+
+    /// Serve requests and send notifications forever.
+    pub async fn run<const N: usize, const O: usize, const SZ: usize>(&self, state: &mut ServerState<N, O, SZ>) -> ! {
+        state.message_id = self.rng.lock(|rng| rng.borrow_mut().next_u32() as u16);
+        loop {
+            let deadline = state
+                .observers
+                .iter()
+                .flatten()
+                .filter_map(|o| o.pending.map(|p| p.deadline))
+                .min();
+            let retransmission = async {
+                match deadline {
+                    Some(deadline) => Timer::at(deadline).await,
+                    None => pending().await,
+                }
+            };
+            match select3(self.socket.recv_from(&mut state.rx), retransmission, self.signal.wait()).await {
+                Either3::First(Ok((len, meta))) => self.handle_datagram(state, len, meta.endpoint).await,
+                Either3::First(Err(e)) => warn!("CoAP server: receive error: {:?}", e),
+                Either3::Second(()) => self.retransmit(state).await,
+                Either3::Third(()) => {
+                    let changed = self.changed.lock(|c| c.replace(0));
+                    for observer in state.observers.iter_mut().flatten() {
+                        observer.changed |= changed & (1 << observer.route) != 0;
+                    }
+                }
+            }
+
+            for index in 0..O {
+                if matches!(&state.observers[index], Some(o) if o.changed && o.pending.is_none()) {
+                    self.notify_observer(state, index, None).await;
+                }
+            }
+        }
+    }
+
+    async fn handle_datagram<const N: usize, const O: usize, const SZ: usize>(
+        &self,
+        state: &mut ServerState<N, O, SZ>,
+        len: usize,
+        remote: IpEndpoint,
+    ) {
+        let ServerState {
+            rx,
+            tx,
+            exchanges,
+            observers,
+            message_id,
+            ..
+        } = state;
+        let message = match Message::parse(&rx[..len]) {
+            Ok(message) => message,
+            Err(_) => {
+                // Reject malformed confirmable messages (section 4.2).
+                if len >= 4 && rx[0] >> 4 == 0x04 {
+                    self.reset(tx, u16::from_be_bytes([rx[2], rx[3]]), remote).await;
+                }
+                return;
+            }
+        };
+
+        match message.ty() {
+            Type::Acknowledgement | Type::Reset => {
+                self.handle_ack(observers, &message, remote);
+                return;
+            }
+            Type::Confirmable | Type::NonConfirmable => {}
+        }
+        if !message.code().is_request() {
+            // Pings are empty confirmable messages, answered with a reset.
+            if message.ty() == Type::Confirmable {
+                self.reset(tx, message.message_id(), remote).await;
+            }
+            return;
+        }
+
+        let now = Instant::now();
+        let duplicate = exchanges
+            .iter()
+            .find(|e| e.remote == Some(remote) && e.message_id == message.message_id() && e.expires > now);
+        if let Some(exchange) = duplicate {
+            debug!(
+                "CoAP server: duplicate request {} from {}",
+                message.message_id(),
+                remote
+            );
+            if exchange.len > 0 {
+                self.send(&exchange.response[..exchange.len], remote).await;
+            }
+            return;
+        }
+
+        let len = match self.handle_request(&message, remote, tx, observers, message_id).await {
+            Ok((len, _)) => len,
+            Err(e) => {
+                warn!("CoAP server: no response to request from {}: {:?}", remote, e);
+                return;
+            }
+        };
+        self.send(&tx[..len], remote).await;
+
+        if let Some(exchange) = exchanges.iter_mut().min_by_key(|e| e.expires) {
+            exchange.remote = Some(remote);
+            exchange.message_id = message.message_id();
+            exchange.expires = after(now, self.config.exchange_lifetime());
+            exchange.len = match message.ty() {
+                Type::Confirmable => len,
+                _ => 0,
+            };
+            exchange.response[..exchange.len].copy_from_slice(&tx[..exchange.len]);
+        }
+    }
+
+    /// Handle the acknowledgement or reset of a notification.
+    fn handle_ack(&self, observers: &mut [Option<Observer>], message: &Message<'_>, remote: IpEndpoint) {
+        for slot in observers.iter_mut() {
+            let Some(observer) = slot else {
+                continue;
+            };
+            let message_id = observer.pending.map(|p| p.message_id);
+            if observer.remote != remote || message_id != Some(message.message_id()) {
+                continue;
+            }
+            match message.ty() {
+                Type::Reset => {
+                    debug!("CoAP server: {} stopped observing", remote);
+                    *slot = None;
+                }
+                _ => observer.pending = None,
+            }
+        }
+    }
+
+    /// Handle a request, writing the response into `tx`.
+    async fn handle_request(
+        &self,
+        message: &Message<'_>,
+        remote: IpEndpoint,
+        tx: &mut [u8],
+        observers: &mut [Option<Observer>],
+        message_id: &mut u16,
+    ) -> Result<(usize, Code), Error> {
+        let mut reply = Reply {
+            ty: Type::Acknowledgement,
+            message_id: message.message_id(),
+            token: message.token(),
+            observe: None,
+        };
+        if message.ty() == Type::NonConfirmable {
+            reply.ty = Type::NonConfirmable;
+            reply.message_id = next_message_id(message_id);
+        }
+
+        let options = message.options();
+        if message
+            .options()
+            .any(|(n, _)| n.is_critical() && !KNOWN_CRITICAL_OPTIONS.contains(&n))
+        {
+            return reply.empty(tx, Code::BAD_OPTION);
+        }
+        let path = || options.get_all(OptionNumber::URI_PATH);
+        let mut routes = self
+            .routes
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.matches_path(path()))
+            .peekable();
+        if routes.peek().is_none() {
+            return reply.empty(tx, Code::NOT_FOUND);
+        }
+        let method = Method::from_code(message.code());
+        let Some((index, route)) = routes.find(|(_, r)| Some(r.method) == method) else {
+            return reply.empty(tx, Code::METHOD_NOT_ALLOWED);
+        };
+
+        // A GET request with the token of an observation registers it again or cancels it
+        // (RFC 7641 section 3.6).
+        let mut registration = None;
+        if route.method == Method::Get {
+            let existing = observers
+                .iter()
+                .position(|o| matches!(o, Some(o) if o.remote == remote && o.token == message.token()));
+            let observable = index < MAX_OBSERVABLE_ROUTES && options.as_bytes().len() <= MAX_OBSERVE_OPTIONS_LEN;
+            match options.observe() {
+                Some(0) if observable => {
+                    registration = existing.or_else(|| observers.iter().position(Option::is_none));
+                    if registration.is_none() {
+                        debug!("CoAP server: too many observers");
+                    }
+                }
+                _ => {
+                    if let Some(existing) = existing {
+                        observers[existing] = None;
+                    }
+                }
+            }
+        }
+        if let Some(registration) = registration {
+            let sequence = observers[registration].as_ref().map_or(0, |o| o.sequence + 1);
+            reply.observe = Some(sequence & 0xff_ffff);
+        }
+
+        let request = Request {
+            remote,
+            method: route.method,
+            options,
+            payload: message.payload(),
+            notification: false,
+        };
+        let (len, code) = self.respond(&route.handler, &request, &reply, tx).await?;
+
+        if let (Some(registration), Some(sequence)) = (registration, reply.observe) {
+            observers[registration] = match code.is_success() {
+                true => Some(Observer {
+                    remote,
+                    token: unwrap!(Vec::from_slice(message.token())),
+                    route: index,
+                    options: unwrap!(Vec::from_slice(options.as_bytes())),
+                    sequence,
+                    changed: false,
+                    pending: None,
+                }),
+                false => None,
+            };
+        }
+        Ok((len, code))
+    }
+
+    /// Call `handler` and write its response into `tx`.
+    async fn respond(
+        &self,
+        handler: &H,
+        request: &Request<'_>,
+        reply: &Reply<'_>,
+        tx: &mut [u8],
+    ) -> Result<(usize, Code), Error> {
+        // The representation is written at the end of the buffer, then moved after the options.
+        let szx = size_exponent(self.config.block_size.min(tx.len().saturating_sub(PAYLOAD_ROOM)));
+        let requested = match request.notification {
+            true => None,
+            false => request.options.block2(),
+        };
+        let mut block = Block {
+            num: 0,
+            more: false,
+            szx,
+        };
+        if let Some(requested) = requested {
+            block.szx = requested.szx.min(szx);
+            block.num = (requested.offset() / block.size()) as u32;
+        }
+        let (offset, size) = (block.offset(), block.size());
+        let window_start = tx.len() - size;
+
+        let code = match request.method {
+            Method::Get => Code::CONTENT,
+            Method::Post | Method::Put => Code::CHANGED,
+            Method::Delete => Code::DELETED,
+        };
+        let mut response = Response::new(code, offset..offset + size, &mut tx[window_start..]);
+        handler.handle(request, &mut response).await;
+        let Response {
+            code,
+            content_format,
+            max_age,
+            etag,
+            len: total,
+            ..
+        } = response;
+
+        let blockwise = code.is_success() && (requested.is_some() || total > size);
+        if blockwise && offset > 0 && offset >= total {
+            return reply.empty(tx, Code::BAD_OPTION);
+        }
+        block.more = total > offset + size;
+
+        let mut writer = Writer::new(tx, reply.ty, code, reply.message_id, reply.token)?;
+        if !etag.is_empty() {
+            writer.option(OptionNumber::ETAG, &etag)?;
+        }
+        if let (true, Some(sequence)) = (code.is_success(), reply.observe) {
+            writer.uint_option(OptionNumber::OBSERVE, sequence)?;
+        }
+        if let Some(content_format) = content_format {
+            writer.uint_option(OptionNumber::CONTENT_FORMAT, content_format.0 as u32)?;
+        }
+        if let Some(max_age) = max_age {
+            writer.uint_option(OptionNumber::MAX_AGE, max_age)?;
+        }
+        if blockwise {
+            writer.uint_option(OptionNumber::BLOCK2, block.to_uint())?;
+        }
+        if let Some(block1) = request.block1() {
+            writer.uint_option(OptionNumber::BLOCK1, block1.to_uint())?;
+        }
+        if blockwise && block.num == 0 && block.more {
+            writer.uint_option(OptionNumber::SIZE2, total as u32)?;
+        }
+        writer.payload_from(window_start, total.clamp(offset, offset + size) - offset)?;
+        Ok((writer.len(), code))
+    }
+
+    /// Send the notifications waiting for an acknowledgement again, or forget their observers
+    /// after too many retransmissions.
+    async fn retransmit<const N: usize, const O: usize, const SZ: usize>(&self, state: &mut ServerState<N, O, SZ>) {
+        let now = Instant::now();
+        for index in 0..O {
+            let Some(pending) = state.observers[index].as_ref().and_then(|o| o.pending) else {
+                continue;
+            };
+            if pending.deadline > now {
+                continue;
+            }
+            if pending.retransmissions >= self.config.max_retransmit() {
+                debug!("CoAP server: observer not responding");
+                state.observers[index] = None;
+                continue;
+            }
+            let timeout = backoff(pending.timeout);
+            let pending = Pending {
+                retransmissions: pending.retransmissions + 1,
+                timeout,
+                deadline: after(now, timeout),
+                ..pending
+            };
+            self.notify_observer(state, index, Some(pending)).await;
+        }
+    }
+
+    /// Send a notification to an observer, or send it again with the state of `pending`.
+    ///
+    /// Retransmissions carry the latest representation of the resource.
+    async fn notify_observer<const N: usize, const O: usize, const SZ: usize>(
+        &self,
+        state: &mut ServerState<N, O, SZ>,
+        index: usize,
+        pending: Option<Pending>,
+    ) {
+        let ServerState {
+            tx,
+            observers,
+            message_id,
+            ..
+        } = state;
+        let Some(observer) = &mut observers[index] else {
+            return;
+        };
+        let pending = pending.unwrap_or_else(|| {
+            let timeout = self.rng.lock(|rng| self.config.initial_timeout(&mut *rng.borrow_mut()));
+            Pending {
+                message_id: next_message_id(message_id),
+                retransmissions: 0,
+                timeout,
+                deadline: after(Instant::now(), timeout),
+            }
+        });
+        observer.changed = false;
+        observer.sequence = (observer.sequence + 1) & 0xff_ffff;
+
+        let request = Request {
+            remote: observer.remote,
+            method: Method::Get,
+            options: Options::new(&observer.options),
+            payload: &[],
+            notification: true,
+        };
+        let reply = Reply {
+            ty: Type::Confirmable,
+            message_id: pending.message_id,
+            token: &observer.token,
+            observe: Some(observer.sequence),
+        };
+        let route = &self.routes[observer.route];
+        let remote = observer.remote;
+        match self.respond(&route.handler, &request, &reply, tx).await {
+            Ok((len, code)) => {
+                self.send(&tx[..len], remote).await;
+                // An error ends the observation.
+                match code.is_success() {
+                    true => observer.pending = Some(pending),
+                    false => observers[index] = None,
+                }
+            }
+            Err(e) => {
+                warn!("CoAP server: no notification for {}: {:?}", remote, e);
+                observers[index] = None;
+            }
+        }
+    }
+
+    async fn reset(&self, tx: &mut [u8], message_id: u16, remote: IpEndpoint) {
+        if let Ok(writer) = Writer::new(tx, Type::Reset, Code::EMPTY, message_id, &[]) {
+            let len = writer.len();
+            self.send(&tx[..len], remote).await;
+        }
+    }
+
+    async fn send(&self, data: &[u8], remote: IpEndpoint) {
+        if let Err(e) = self.socket.send_to(data, remote).await {
+            warn!("CoAP server: send error: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,712 @@
+//! DHCPv4 server.
+//!
+//! Hands out addresses to clients on the local link, for example when the device runs as a
+//! Wi-Fi access point or a USB network gadget. The server runs on a [`UdpSocket`], so it can
+//! be used with any [`Stack`](crate::Stack).
+//!
+//! ```ignore
+//! use embassy_net::dhcp_server::{Config, DhcpServer, LeaseEvent};
+//! use embassy_net::udp::{PacketMetadata, UdpSocket};
+//! use embassy_net::{Ipv4Address, Ipv4Cidr};
+//!
+//! let mut rx_meta = [PacketMetadata::EMPTY; 4];
+//! let mut rx_buffer = [0; 1024];
+//! let mut tx_meta = [PacketMetadata::EMPTY; 4];
+//! let mut tx_buffer = [0; 1024];
+//! let socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+//!
+//! let config = Config::new(
+//!     Ipv4Cidr::new(Ipv4Address::new(192, 168, 4, 1), 24),
+//!     Ipv4Address::new(192, 168, 4, 100),
+//!     16,
+//! );
+//! let mut server = DhcpServer::<16>::new(socket, config).unwrap();
+//! server
+//!     .run(|event| match event {
+//!         LeaseEvent::Leased(lease) => info!("leased {:?} to {:?}", lease.address, lease.mac),
+//!         LeaseEvent::Released(lease) | LeaseEvent::Expired(lease) => info!("freed {:?}", lease.address),
+//!     })
+//!     .await;
+//! ```
+
+use embassy_time::{with_deadline, Duration, Instant};
+use heapless::Vec;
+use smoltcp::wire::{DhcpMessageType, DhcpOpCode, DhcpPacket, DhcpRepr, DHCP_CLIENT_PORT, DHCP_SERVER_PORT};
+
+use crate::udp::{BindError, RecvError, UdpSocket};
+use crate::{Ipv4Address, Ipv4Cidr};
+
+/// How long an offered address is kept for the client before it can be offered to others.
+const OFFER_TIMEOUT: Duration = Duration::from_secs(60);
+/// Largest DHCP message we expect from clients (RFC 2131 section 2).
+const MAX_MESSAGE_LEN: usize = 576;
+/// Minimum BOOTP message length, some clients drop shorter replies.
+const MIN_MESSAGE_LEN: usize = 300;
+
+/// A static address reservation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reservation {
+    /// Client hardware address.
+    pub mac: [u8; 6],
+    /// Address always given to this client.
+    pub address: Ipv4Address,
+}
+
+/// DHCP server configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Config<'a> {
+    /// Address of the server, and subnet served.
+    pub server_address: Ipv4Cidr,
+    /// First address of the pool.
+    pub pool_start: Ipv4Address,
+    /// Number of addresses in the pool.
+    pub pool_size: u16,
+    /// Lease time given to clients.
+    pub lease_time: Duration,
+    /// Default gateway given to clients.
+    pub router: Option<Ipv4Address>,
+    /// DNS servers given to clients.
+    pub dns_servers: Vec<Ipv4Address, 3>,
+    /// Static address reservations. Reserved addresses should be outside of the pool.
+    pub reservations: &'a [Reservation],
+}
+
+impl Config<'_> {
+    /// Create a configuration serving `pool_size` addresses starting at `pool_start`.
+    ///
+    /// The server is advertised as the default gateway, leases last one day, and no DNS
+    /// servers or reservations are configured.
+    pub fn new(server_address: Ipv4Cidr, pool_start: Ipv4Address, pool_size: u16) -> Self {
+        Self {
+            server_address,
+            pool_start,
+            pool_size,
+            lease_time: Duration::from_secs(24 * 60 * 60),
+            router: Some(server_address.address()),
+            dns_servers: Vec::new(),
+            reservations: &[],
+        }
+    }
+}
+
+/// An address leased to a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lease {
+    /// Client hardware address.
+    pub mac: [u8; 6],
+    /// Leased address.
+    pub address: Ipv4Address,
+    /// When the lease expires, unless renewed.
+    pub expires_at: Instant,
+}
+
+/// Lease event, reported by [`DhcpServer::run`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaseEvent {
+    /// A client was given a new address.
+    Leased(Lease),
+    /// A client released its address.
+    Released(Lease),
+    /// A lease expired without being renewed.
+    Expired(Lease),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Offered,
+    Bound,
+    /// The client reported the address as in use by another host.
+    Declined,
+}
+
+struct Entry {
+    lease: Lease,
+    state: State,
+}
+
+/// A DHCPv4 server, tracking up to `N` leases.
+pub struct DhcpServer<'a, const N: usize> {
+    socket: UdpSocket<'a>,
+    config: Config<'a>,
+    entries: Vec<Entry, N>,
+}
+
+impl<'a, const N: usize> DhcpServer<'a, N> {
+    /// Create a server, binding `socket` to the DHCP server port.
+    pub fn new(mut socket: UdpSocket<'a>, config: Config<'a>) -> Result<Self, BindError> {
+        socket.bind(DHCP_SERVER_PORT)?;
+        Ok(Self {
+            socket,
+            config,
+            entries: Vec::new(),
+        })
+    }
+
+    /// Get the configuration.
+    pub fn config(&self) -> &Config<'a> {
+        &self.config
+    }
+
+    /// Iterate over the active leases.
+    pub fn leases(&self) -> impl Iterator<Item = &Lease> + '_ {
+        self.entries
+            .iter()
+            .filter(|e| e.state == State::Bound)
+            .map(|e| &e.lease)
+    }
+
+    /// Serve clients forever, calling `on_event` when leases change.
+    pub async fn run(&mut self, mut on_event: impl FnMut(LeaseEvent)) -> ! {
+        let mut buf = [0; MAX_MESSAGE_LEN];
+        loop {
+            self.expire(Instant::now(), &mut on_event);
+
+            let deadline = self
+                .entries
+                .iter()
+                .map(|e| e.lease.expires_at)
+                .min()
+                .unwrap_or(Instant::MAX);
+            let n = match with_deadline(deadline, self.socket.recv_from(&mut buf)).await {
+                Ok(Ok((n, _))) => n,
+                Ok(Err(RecvError::Truncated)) => {
+                    debug!("DHCP server: message too long");
+                    continue;
+                }
+                // A lease expired.
+                Err(_) => continue,
+            };
+
+            let Ok(packet) = DhcpPacket::new_checked(&buf[..n]) else {
+                continue;
+            };
+            let Ok(request) = DhcpRepr::parse(&packet) else {
+                continue;
+            };
+            if packet.opcode() != DhcpOpCode::Request {
+                continue;
+            }
+
+            if let Some((reply, dst)) = self.process(&request, Instant::now(), &mut on_event) {
+                let mut out = [0; MAX_MESSAGE_LEN];
+                let len = reply.buffer_len().max(MIN_MESSAGE_LEN);
+                let mut packet = DhcpPacket::new_unchecked(&mut out[..len]);
+                if reply.emit(&mut packet).is_err() {
+                    continue;
+                }
+                if let Err(e) = self.socket.send_to(&out[..len], dst).await {
+                    warn!("DHCP server: send error: {:?}", e);
+                }
+            }
+        }
+    }
+
+    fn process(
+        &mut self,
+        request: &DhcpRepr<'_>,
+        now: Instant,
+        on_event: &mut impl FnMut(LeaseEvent),
+    ) -> Option<(DhcpRepr<'static>, (Ipv4Address, u16))> {
+        let mac = request.client_hardware_address.0;
+        let server_address = self.config.server_address.address();
+
+        match request.message_type {
+            DhcpMessageType::Discover => {
+                let Some(address) = self.select_address(mac, request.requested_ip) else {
+                    warn!("DHCP server: no address available for {:?}", mac);
+                    return None;
+                };
+                // A client may rediscover the address it holds, it keeps its lease meanwhile.
+                if !self.is_bound(mac, address) {
+                    self.record(mac, address, State::Offered, now + OFFER_TIMEOUT, on_event)?;
+                }
+                Some(self.reply(request, DhcpMessageType::Offer, address))
+            }
+            DhcpMessageType::Request => {
+                if let Some(server_identifier) = request.server_identifier {
+                    if server_identifier != server_address {
+                        // The client accepted an offer from another server.
+                        self.entries
+                            .retain(|e| !(e.lease.mac == mac && e.state == State::Offered));
+                        return None;
+                    }
+                }
+
+                // Selecting or init-reboot clients set the requested address option, renewing
+                // and rebinding clients fill in ciaddr.
+                let address = match request.requested_ip {
+                    Some(address) => address,
+                    None if !request.client_ip.is_unspecified() => request.client_ip,
+                    None => return None,
+                };
+
+                if !self.may_lease(mac, address) {
+                    // Stay silent about addresses from other networks.
+                    if request.server_identifier.is_none() && !self.config.server_address.contains_addr(&address) {
+                        return None;
+                    }
+                    debug!("DHCP server: refusing {:?} to {:?}", address, mac);
+                    return Some(self.reply(request, DhcpMessageType::Nak, Ipv4Address::UNSPECIFIED));
+                }
+
+                let is_new = !self.is_bound(mac, address);
+                let lease = self.record(mac, address, State::Bound, now + self.config.lease_time, on_event)?;
+                if is_new {
+                    debug!("DHCP server: leased {:?} to {:?}", address, mac);
+                    on_event(LeaseEvent::Leased(lease));
+                }
+                Some(self.reply(request, DhcpMessageType::Ack, address))
+            }
+            DhcpMessageType::Decline => {
+                let address = request.requested_ip?;
+                let entry = self.entries.iter_mut().find(|e| e.lease.address == address)?;
+                if entry.lease.mac == mac {
+                    warn!("DHCP server: {:?} declined {:?}, address in use", mac, address);
+                    entry.lease.mac = [0; 6];
+                    entry.lease.expires_at = now + self.config.lease_time;
+                    entry.state = State::Declined;
+                }
+                None
+            }
+            DhcpMessageType::Release => {
+                let i = self.entries.iter().position(|e| {
+                    e.lease.mac == mac && e.lease.address == request.client_ip && e.state == State::Bound
+                })?;
+                let entry = self.entries.swap_remove(i);
+                debug!("DHCP server: {:?} released {:?}", mac, entry.lease.address);
+                on_event(LeaseEvent::Released(entry.lease));
+                None
+            }
+            DhcpMessageType::Inform if !request.client_ip.is_unspecified() => {
+                Some(self.reply(request, DhcpMessageType::Ack, Ipv4Address::UNSPECIFIED))
+            }
+            _ => None,
+        }
+    }
+
+    fn reply(
+        &self,
+        request: &DhcpRepr<'_>,
+        message_type: DhcpMessageType,
+        your_ip: Ipv4Address,
+    ) -> (DhcpRepr<'static>, (Ipv4Address, u16)) {
+        let nak = message_type == DhcpMessageType::Nak;
+        let inform = request.message_type == DhcpMessageType::Inform;
+        let reply = DhcpRepr {
+            message_type,
+            transaction_id: request.transaction_id,
+            secs: 0,
+            client_hardware_address: request.client_hardware_address,
+            client_ip: match inform {
+                true => request.client_ip,
+                false => Ipv4Address::UNSPECIFIED,
+            },
+            your_ip,
+            server_ip: Ipv4Address::UNSPECIFIED,
+            router: self.config.router.filter(|_| !nak),
+            subnet_mask: (!nak).then(|| self.config.server_address.netmask()),
+            relay_agent_ip: request.relay_agent_ip,
+            broadcast: request.broadcast,
+            requested_ip: None,
+            client_identifier: None,
+            server_identifier: Some(self.config.server_address.address()),
+            parameter_request_list: None,
+            dns_servers: (!nak && !self.config.dns_servers.is_empty())
+                .then(|| self.config.dns_servers.iter().copied().collect()),
+            max_size: None,
+            lease_duration: (!nak && !inform).then(|| self.config.lease_time.as_secs().min(u32::MAX as u64) as u32),
+            renew_duration: None,
+            rebind_duration: None,
+            additional_options: &[],
+        };
+
+        // RFC 2131 section 4.1. Clients without an address are always answered with a
+        // broadcast, since unicasting to them would need an ARP entry we can't add.
+        let dst = if !request.relay_agent_ip.is_unspecified() {
+            (request.relay_agent_ip, DHCP_SERVER_PORT)
+        } else if !nak && !request.client_ip.is_unspecified() {
+            (request.client_ip, DHCP_CLIENT_PORT)
+        } else {
+            (Ipv4Address::BROADCAST, DHCP_CLIENT_PORT)
+        };
+        (reply, dst)
+    }
+
+    fn expire(&mut self, now: Instant, on_event: &mut impl FnMut(LeaseEvent)) {
+        let mut i = 0;
+        while i < self.entries.len() {
+            if self.entries[i].lease.expires_at <= now {
+                let entry = self.entries.swap_remove(i);
+                if entry.state == State::Bound {
+                    debug!("DHCP server: lease of {:?} expired", entry.lease.address);
+                    on_event(LeaseEvent::Expired(entry.lease));
+                }
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    fn reservation(&self, mac: [u8; 6]) -> Option<Ipv4Address> {
+        self.config
+            .reservations
+            .iter()
+            .find(|r| r.mac == mac)
+            .map(|r| r.address)
+    }
+
+    fn in_pool(&self, address: Ipv4Address) -> bool {
+        let start = u32::from(self.config.pool_start);
+        let address = u32::from(address);
+        address >= start && address - start < self.config.pool_size as u32
+    }
+
+    /// Whether `address` is free to be given to anyone.
+    fn is_free(&self, address: Ipv4Address) -> bool {
+        self.in_pool(address)
+            && address != self.config.server_address.address()
+            && !self.entries.iter().any(|e| e.lease.address == address)
+            && !self.config.reservations.iter().any(|r| r.address == address)
+    }
+
+    fn is_bound(&self, mac: [u8; 6], address: Ipv4Address) -> bool {
+        self.entries
+            .iter()
+            .any(|e| e.lease.mac == mac && e.lease.address == address && e.state == State::Bound)
+    }
+
+    /// Whether `address` is leased to another client, or declined.
+    fn is_taken(&self, mac: [u8; 6], address: Ipv4Address) -> bool {
+        self.entries
+            .iter()
+            .any(|e| e.lease.address == address && e.state != State::Offered && e.lease.mac != mac)
+    }
+
+    fn may_lease(&self, mac: [u8; 6], address: Ipv4Address) -> bool {
+        if let Some(reserved) = self.reservation(mac) {
+            return reserved == address && !self.is_taken(mac, reserved);
+        }
+        match self.entries.iter().find(|e| e.lease.address == address) {
+            Some(e) => e.lease.mac == mac && e.state != State::Declined,
+            None => self.is_free(address),
+        }
+    }
+
+    fn select_address(&self, mac: [u8; 6], requested: Option<Ipv4Address>) -> Option<Ipv4Address> {
+        if let Some(address) = self.reservation(mac) {
+            return (!self.is_taken(mac, address)).then_some(address);
+        }
+        let current = self
+            .entries
+            .iter()
+            .find(|e| e.lease.mac == mac && e.state != State::Declined);
+        if let Some(e) = current {
+            return Some(e.lease.address);
+        }
+        if let Some(address) = requested.filter(|a| self.is_free(*a)) {
+            return Some(address);
+        }
+        let start = u32::from(self.config.pool_start);
+        (0..self.config.pool_size as u32)
+            .map(|i| Ipv4Address::from(start.wrapping_add(i)))
+            .find(|a| self.is_free(*a))
+    }
+
+    /// Record `address` as offered or bound to `mac`.
+    ///
+    /// This replaces the offers of the client and for the address. Binding also replaces the
+    /// client's lease, which is reported as released if it was for another address.
+    fn record(
+        &mut self,
+        mac: [u8; 6],
+        address: Ipv4Address,
+        state: State,
+        expires_at: Instant,
+        on_event: &mut impl FnMut(LeaseEvent),
+    ) -> Option<Lease> {
+        if self.is_taken(mac, address) {
+            return None;
+        }
+
+        let mut i = 0;
+        while i < self.entries.len() {
+            let e = &self.entries[i];
+            let replaced = match e.state {
+                State::Offered => e.lease.mac == mac || e.lease.address == address,
+                State::Bound => e.lease.mac == mac && state == State::Bound,
+                State::Declined => false,
+            };
+            if replaced {
+                let entry = self.entries.swap_remove(i);
+                if entry.state == State::Bound && entry.lease.address != address {
+                    debug!("DHCP server: {:?} moved from {:?}", mac, entry.lease.address);
+                    on_event(LeaseEvent::Released(entry.lease));
+                }
+            } else {
+                i += 1;
+            }
+        }
+
+        let lease = Lease {
+            mac,
+            address,
+            expires_at,
+        };
+        if self.entries.push(Entry { lease, state }).is_err() {
+            warn!("DHCP server: lease table full");
+            return None;
+        }
+        Some(lease)
+    }
+}
+
+#[cfg(all(test, feature = "medium-ip"))]
+mod tests {
+    use smoltcp::wire::EthernetAddress;
+
+    use super::*;
+    use crate::udp::PacketMetadata;
+    use crate::StackResources;
+
+    const MAC_A: [u8; 6] = [0x02, 0, 0, 0, 0, 0xA];
+    const MAC_B: [u8; 6] = [0x02, 0, 0, 0, 0, 0xB];
+    const SERVER: Ipv4Address = Ipv4Address::new(192, 168, 4, 1);
+    const POOL_START: Ipv4Address = Ipv4Address::new(192, 168, 4, 100);
+
+    fn server(reservations: &'static [Reservation]) -> DhcpServer<'static, 4> {
+        let (stack, _) = crate::new(
+            crate::tests::device(),
+            crate::Config::default(),
+            Box::leak(Box::new(StackResources::<2>::new())),
+            0,
+        );
+        let meta = || &mut Box::leak(Box::new([PacketMetadata::EMPTY; 1]))[..];
+        let buf = || &mut Box::leak(Box::new([0u8; 16]))[..];
+        let socket = UdpSocket::new(stack, meta(), buf(), meta(), buf());
+        let mut config = Config::new(Ipv4Cidr::new(SERVER, 24), POOL_START, 4);
+        config.reservations = reservations;
+        unwrap!(DhcpServer::new(socket, config).ok())
+    }
+
+    fn message(message_type: DhcpMessageType, mac: [u8; 6]) -> DhcpRepr<'static> {
+        DhcpRepr {
+            message_type,
+            transaction_id: 0x1234,
+            secs: 0,
+            client_hardware_address: EthernetAddress(mac),
+            client_ip: Ipv4Address::UNSPECIFIED,
+            your_ip: Ipv4Address::UNSPECIFIED,
+            server_ip: Ipv4Address::UNSPECIFIED,
+            router: None,
+            subnet_mask: None,
+            relay_agent_ip: Ipv4Address::UNSPECIFIED,
+            broadcast: false,
+            requested_ip: None,
+            client_identifier: None,
+            server_identifier: None,
+            parameter_request_list: None,
+            dns_servers: None,
+            max_size: None,
+            lease_duration: None,
+            renew_duration: None,
+            rebind_duration: None,
+            additional_options: &[],
+        }
+    }
+
+    fn discover(mac: [u8; 6]) -> DhcpRepr<'static> {
+        message(DhcpMessageType::Discover, mac)
+    }
+
+    fn request(mac: [u8; 6], address: Ipv4Address) -> DhcpRepr<'static> {
+        DhcpRepr {
+            requested_ip: Some(address),
+            server_identifier: Some(SERVER),
+            ..message(DhcpMessageType::Request, mac)
+        }
+    }
+
+    fn release(mac: [u8; 6], address: Ipv4Address) -> DhcpRepr<'static> {
+        DhcpRepr {
+            client_ip: address,
+            server_identifier: Some(SERVER),
+            ..message(DhcpMessageType::Release, mac)
+        }
+    }
+
+    /// Process `request` at `now` seconds, returning the reply type and address, and the events.
+    fn process(
+        server: &mut DhcpServer<'static, 4>,
+        request: DhcpRepr<'_>,
+        now: u64,
+    ) -> (Option<(DhcpMessageType, Ipv4Address)>, std::vec::Vec<LeaseEvent>) {
+        let mut events = std::vec::Vec::new();
+        let reply = server.process(&request, Instant::from_secs(now), &mut |e| events.push(e));
+        (reply.map(|(r, _)| (r.message_type, r.your_ip)), events)
+    }
+
+    fn lease(mac: [u8; 6], address: Ipv4Address, expires_at: u64) -> Lease {
+        Lease {
+            mac,
+            address,
+            expires_at: Instant::from_secs(expires_at),
+        }
+    }
+
+    #[test]
+    fn discover_request_release() {
+        let mut server = server(&[]);
+        let lease_time = server.config().lease_time.as_secs();
+
+        let (reply, events) = process(&mut server, discover(MAC_A), 10);
+        assert_eq!(reply, Some((DhcpMessageType::Offer, POOL_START)));
+        assert!(events.is_empty());
+        assert_eq!(server.leases().count(), 0);
+
+        // The offered address is kept for the client.
+        let (reply, _) = process(&mut server, discover(MAC_B), 10);
+        let address_b = Ipv4Address::new(192, 168, 4, 101);
+        assert_eq!(reply, Some((DhcpMessageType::Offer, address_b)));
+
+        let (reply, events) = process(&mut server, request(MAC_A, POOL_START), 11);
+        assert_eq!(reply, Some((DhcpMessageType::Ack, POOL_START)));
+        core::assert_eq!(events, [LeaseEvent::Leased(lease(MAC_A, POOL_START, 11 + lease_time))]);
+        assert_eq!(server.leases().count(), 1);
+
+        // Another client can't take it.
+        let (reply, events) = process(&mut server, request(MAC_B, POOL_START), 12);
+        assert_eq!(reply, Some((DhcpMessageType::Nak, Ipv4Address::UNSPECIFIED)));
+        assert!(events.is_empty());
+
+        let (reply, events) = process(&mut server, release(MAC_A, POOL_START), 13);
+        assert_eq!(reply, None);
+        core::assert_eq!(
+            events,
+            [LeaseEvent::Released(lease(MAC_A, POOL_START, 11 + lease_time))]
+        );
+        assert_eq!(server.leases().count(), 0);
+    }
+
+    #[test]
+    fn rediscover_keeps_lease() {
+        let mut server = server(&[]);
+        let lease_time = server.config().lease_time.as_secs();
+        process(&mut server, discover(MAC_A), 10);
+        process(&mut server, request(MAC_A, POOL_START), 10);
+
+        // A rebooted client discovers again, and is offered its address.
+        let (reply, events) = process(&mut server, discover(MAC_A), 20);
+        assert_eq!(reply, Some((DhcpMessageType::Offer, POOL_START)));
+        assert!(events.is_empty());
+        core::assert_eq!(
+            server.leases().copied().collect::<std::vec::Vec<_>>(),
+            [lease(MAC_A, POOL_START, 10 + lease_time)]
+        );
+
+        // The lease outlives the offer timeout.
+        let mut events = std::vec::Vec::new();
+        server.expire(Instant::from_secs(20 + OFFER_TIMEOUT.as_secs() + 1), &mut |e| {
+            events.push(e)
+        });
+        assert!(events.is_empty());
+        assert_eq!(server.leases().count(), 1);
+
+        // Requesting it again renews it, without a second event.
+        let (reply, events) = process(&mut server, request(MAC_A, POOL_START), 30);
+        assert_eq!(reply, Some((DhcpMessageType::Ack, POOL_START)));
+        assert!(events.is_empty());
+        assert_eq!(
+            server.leases().next().unwrap().expires_at,
+            Instant::from_secs(30 + lease_time)
+        );
+    }
+
+    #[test]
+    fn expiry() {
+        let mut server = server(&[]);
+        let lease_time = server.config().lease_time.as_secs();
+        process(&mut server, discover(MAC_A), 10);
+        process(&mut server, request(MAC_A, POOL_START), 10);
+        process(&mut server, discover(MAC_B), 10);
+
+        // The offer to B expires silently.
+        let mut events = std::vec::Vec::new();
+        server.expire(Instant::from_secs(10 + OFFER_TIMEOUT.as_secs()), &mut |e| {
+            events.push(e)
+        });
+        assert!(events.is_empty());
+        assert_eq!(server.entries.len(), 1);
+
+        server.expire(Instant::from_secs(10 + lease_time - 1), &mut |e| events.push(e));
+        assert!(events.is_empty());
+        server.expire(Instant::from_secs(10 + lease_time), &mut |e| events.push(e));
+        core::assert_eq!(events, [LeaseEvent::Expired(lease(MAC_A, POOL_START, 10 + lease_time))]);
+        assert_eq!(server.leases().count(), 0);
+
+        // The address is free again.
+        let (reply, _) = process(&mut server, discover(MAC_B), 10 + lease_time);
+        assert_eq!(reply, Some((DhcpMessageType::Offer, POOL_START)));
+    }
+
+    #[test]
+    fn reservation_doesnt_evict_lease() {
+        static RESERVATIONS: [Reservation; 1] = [Reservation {
+            mac: MAC_B,
+            address: Ipv4Address::new(192, 168, 4, 50),
+        }];
+        let mut server = server(&RESERVATIONS);
+        let reserved = RESERVATIONS[0].address;
+
+        // A holds the reserved address, outside of the pool, for example from before the reservation.
+        let (reply, events) = process(&mut server, discover(MAC_A), 10);
+        assert_eq!(reply, Some((DhcpMessageType::Offer, POOL_START)));
+        assert!(events.is_empty());
+        server.entries[0].lease.address = reserved;
+        server.entries[0].state = State::Bound;
+
+        let (reply, events) = process(&mut server, discover(MAC_B), 11);
+        assert_eq!(reply, None);
+        assert!(events.is_empty());
+        let (reply, events) = process(&mut server, request(MAC_B, reserved), 11);
+        assert_eq!(reply, Some((DhcpMessageType::Nak, Ipv4Address::UNSPECIFIED)));
+        assert!(events.is_empty());
+        assert_eq!(server.leases().next().unwrap().mac, MAC_A);
+
+        // Once A releases it, B gets its reservation.
+        process(&mut server, release(MAC_A, reserved), 12);
+        let (reply, _) = process(&mut server, discover(MAC_B), 13);
+        assert_eq!(reply, Some((DhcpMessageType::Offer, reserved)));
+    }
+
+    #[test]
+    fn move_to_reserved_address() {
+        static RESERVATIONS: [Reservation; 1] = [Reservation {
+            mac: MAC_A,
+            address: Ipv4Address::new(192, 168, 4, 50),
+        }];
+        let mut server = server(&[]);
+        let lease_time = server.config().lease_time.as_secs();
+        process(&mut server, discover(MAC_A), 10);
+        process(&mut server, request(MAC_A, POOL_START), 10);
+
+        // The reservation is added later: the client keeps its lease until it takes the new address.
+        server.config.reservations = &RESERVATIONS;
+        let reserved = RESERVATIONS[0].address;
+        let (reply, events) = process(&mut server, discover(MAC_A), 20);
+        assert_eq!(reply, Some((DhcpMessageType::Offer, reserved)));
+        assert!(events.is_empty());
+        assert_eq!(server.leases().count(), 1);
+
+        let (reply, events) = process(&mut server, request(MAC_A, reserved), 21);
+        assert_eq!(reply, Some((DhcpMessageType::Ack, reserved)));
+        core::assert_eq!(
+            events,
+            [
+                LeaseEvent::Released(lease(MAC_A, POOL_START, 10 + lease_time)),
+                LeaseEvent::Leased(lease(MAC_A, reserved, 21 + lease_time)),
+            ]
+        );
+        assert_eq!(server.leases().count(), 1);
+    }
+}
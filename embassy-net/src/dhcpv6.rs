@@ -0,0 +1,1026 @@
+//! DHCPv6 client (RFC 8415).
+//!
+//! In stateful mode an address is requested with an IA_NA, in stateless mode the address is
+//! autoconfigured with SLAAC and only DNS servers are requested with an Information-Request.
+//! DHCPv6 doesn't carry a default router, so Router Advertisements are always processed by an
+//! embedded [`Slaac`] state machine, which also provides the link-local address used to talk to
+//! servers.
+
+use core::mem::MaybeUninit;
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use smoltcp::iface::{SocketHandle, SocketSet};
+use smoltcp::phy::PacketMeta;
+use smoltcp::socket::udp;
+use smoltcp::wire::{HardwareAddress, IpEndpoint, Ipv6Address, Ipv6Cidr};
+
+use crate::slaac::{expiry, lifetime, Event, Slaac, SlaacResources};
+use crate::{Dhcpv6Config, Dhcpv6Mode, StaticConfigV6};
+
+const CLIENT_PORT: u16 = 546;
+const SERVER_PORT: u16 = 547;
+const ALL_DHCP_RELAY_AGENTS_AND_SERVERS: Ipv6Address = Ipv6Address::new(0xff02, 0, 0, 0, 0, 0, 1, 2);
+
+const SOLICIT: u8 = 1;
+const ADVERTISE: u8 = 2;
+const REQUEST: u8 = 3;
+const RENEW: u8 = 5;
+const REBIND: u8 = 6;
+const REPLY: u8 = 7;
+const INFORMATION_REQUEST: u8 = 11;
+
+const OPTION_CLIENTID: u16 = 1;
+const OPTION_SERVERID: u16 = 2;
+const OPTION_IA_NA: u16 = 3;
+const OPTION_IAADDR: u16 = 5;
+const OPTION_ORO: u16 = 6;
+const OPTION_ELAPSED_TIME: u16 = 8;
+const OPTION_STATUS_CODE: u16 = 13;
+const OPTION_DNS_SERVERS: u16 = 23;
+const OPTION_INFORMATION_REFRESH_TIME: u16 = 32;
+
+const STATUS_SUCCESS: u16 = 0;
+
+// Transmission parameters, RFC 8415 section 7.6.
+const SOL_TIMEOUT: Duration = Duration::from_secs(1);
+const SOL_MAX_RT: Duration = Duration::from_secs(3600);
+const REQ_TIMEOUT: Duration = Duration::from_secs(1);
+const REQ_MAX_RT: Duration = Duration::from_secs(30);
+const REQ_MAX_RC: u8 = 10;
+const REN_TIMEOUT: Duration = Duration::from_secs(10);
+const REN_MAX_RT: Duration = Duration::from_secs(600);
+const REB_TIMEOUT: Duration = Duration::from_secs(10);
+const REB_MAX_RT: Duration = Duration::from_secs(600);
+const INF_TIMEOUT: Duration = Duration::from_secs(1);
+const INF_MAX_RT: Duration = Duration::from_secs(3600);
+const IRT_DEFAULT: u32 = 86400;
+const IRT_MINIMUM: u32 = 600;
+
+/// RFC 8415 section 11.1.
+const MAX_DUID_LEN: usize = 130;
+
+const RX_META: usize = 2;
+const RX_BUF: usize = 1024;
+const TX_META: usize = 1;
+const TX_BUF: usize = 512;
+
+pub(crate) struct Dhcpv6Resources {
+    rx_meta: MaybeUninit<[udp::PacketMetadata; RX_META]>,
+    rx_buffer: MaybeUninit<[u8; RX_BUF]>,
+    tx_meta: MaybeUninit<[udp::PacketMetadata; TX_META]>,
+    tx_buffer: MaybeUninit<[u8; TX_BUF]>,
+}
+
+impl Dhcpv6Resources {
+    pub(crate) const fn new() -> Self {
+        Self {
+            rx_meta: MaybeUninit::uninit(),
+            rx_buffer: MaybeUninit::uninit(),
+            tx_meta: MaybeUninit::uninit(),
+            tx_buffer: MaybeUninit::uninit(),
+        }
+    }
+}
+
+enum State {
+    Soliciting,
+    Requesting {
+        server_id: Vec<u8, MAX_DUID_LEN>,
+        address: Ipv6Address,
+    },
+    Bound,
+    Renewing,
+    Rebinding,
+    Informing,
+    Informed {
+        refresh_at: Instant,
+    },
+}
+
+/// A message exchange in progress, retransmitted until a reply arrives.
+struct Exchange {
+    xid: [u8; 3],
+    started: Instant,
+    next: Instant,
+    timeout: Duration,
+    max_timeout: Duration,
+    count: u8,
+}
+
+struct Lease {
+    server_id: Vec<u8, MAX_DUID_LEN>,
+    address: Ipv6Address,
+    renew_at: Instant,
+    rebind_at: Instant,
+    expires_at: Instant,
+    dns_servers: Vec<Ipv6Address, 3>,
+}
+
+pub(crate) struct Dhcpv6 {
+    handle: SocketHandle,
+    slaac: Slaac,
+    config: Dhcpv6Config,
+    duid: Vec<u8, 12>,
+    iaid: [u8; 4],
+    rng: u64,
+    state: State,
+    exchange: Option<Exchange>,
+    lease: Option<Lease>,
+    dns_servers: Vec<Ipv6Address, 3>,
+    current: Option<StaticConfigV6>,
+}
+
+impl Dhcpv6 {
+    /// Create the client, its UDP socket and the raw socket used for router discovery.
+    ///
+    /// # Safety
+    /// `resources` and `slaac_resources` must outlive the socket set, and must not be in use by
+    /// another socket.
+    pub(crate) unsafe fn new(
+        sockets: &mut SocketSet<'static>,
+        resources: *mut Dhcpv6Resources,
+        slaac_resources: *mut SlaacResources,
+        config: Dhcpv6Config,
+        hardware_address: HardwareAddress,
+        random_seed: u64,
+    ) -> Self {
+        let r = &mut *resources;
+        let mut socket = udp::Socket::new(
+            udp::PacketBuffer::new(
+                &mut r.rx_meta.write([udp::PacketMetadata::EMPTY; RX_META])[..],
+                &mut r.rx_buffer.write([0; RX_BUF])[..],
+            ),
+            udp::PacketBuffer::new(
+                &mut r.tx_meta.write([udp::PacketMetadata::EMPTY; TX_META])[..],
+                &mut r.tx_buffer.write([0; TX_BUF])[..],
+            ),
+        );
+        unwrap!(socket.bind(CLIENT_PORT));
+        let handle = sockets.add(socket);
+
+        let slaac = Slaac::new(
+            sockets,
+            slaac_resources,
+            config.router_discovery.clone(),
+            config.mode == Dhcpv6Mode::Stateless,
+            hardware_address,
+            random_seed,
+        );
+
+        // DUID-LL (RFC 8415 section 11.4), using the EUI-64 hardware type for mediums without a
+        // link-layer address so that the DUID matches the interface identifier.
+        let mut duid = Vec::new();
+        match hardware_address {
+            #[cfg(feature = "medium-ethernet")]
+            HardwareAddress::Ethernet(addr) => {
+                unwrap!(duid.extend_from_slice(&[0, 3, 0, 1]));
+                unwrap!(duid.extend_from_slice(&addr.0));
+            }
+            #[allow(unreachable_patterns)]
+            _ => {
+                unwrap!(duid.extend_from_slice(&[0, 3, 0, 27]));
+                unwrap!(duid.extend_from_slice(&slaac.interface_identifier()));
+            }
+        }
+
+        let iid = slaac.interface_identifier();
+        let mut this = Self {
+            handle,
+            slaac,
+            config,
+            duid,
+            iaid: [iid[4], iid[5], iid[6], iid[7]],
+            rng: random_seed,
+            state: State::Soliciting,
+            exchange: None,
+            lease: None,
+            dns_servers: Vec::new(),
+            current: None,
+        };
+        this.reset(Instant::now());
+        this
+    }
+
+    /// Handles of the UDP socket and of the router discovery socket.
+    pub(crate) fn handles(&self) -> [SocketHandle; 2] {
+        [self.handle, self.slaac.handle()]
+    }
+
+    pub(crate) fn set_config(&mut self, config: Dhcpv6Config) {
+        self.slaac
+            .set_config(config.router_discovery.clone(), config.mode == Dhcpv6Mode::Stateless);
+        self.config = config;
+    }
+
+    /// Link-local address used to talk to servers.
+    pub(crate) fn link_local(&self) -> Ipv6Cidr {
+        self.slaac.link_local()
+    }
+
+    /// Drop the lease and restart from scratch.
+    pub(crate) fn reset(&mut self, now: Instant) {
+        self.slaac.reset(now);
+        self.lease = None;
+        self.dns_servers.clear();
+        self.current = None;
+        match self.config.mode {
+            Dhcpv6Mode::Stateful => self.start(State::Soliciting, now),
+            Dhcpv6Mode::Stateless => self.start(State::Informing, now),
+        }
+    }
+
+    /// Process received messages, run timers and transmit pending messages.
+    pub(crate) fn poll(
+        &mut self,
+        sockets: &mut SocketSet<'static>,
+        link_local_assigned: bool,
+        now: Instant,
+    ) -> Option<Event> {
+        self.slaac.poll(sockets, link_local_assigned, now);
+
+        let socket = sockets.get_mut::<udp::Socket>(self.handle);
+        while let Ok((data, _)) = socket.recv() {
+            self.process(data, now);
+        }
+
+        self.expire(now);
+
+        let due = matches!(&self.exchange, Some(exchange) if exchange.next <= now);
+        if due {
+            if let (State::Requesting { .. }, Some(exchange)) = (&self.state, &self.exchange) {
+                if exchange.count >= REQ_MAX_RC {
+                    debug!("DHCPv6: no reply to request, soliciting again");
+                    self.start(State::Soliciting, now);
+                }
+            }
+            let src = link_local_assigned.then(|| self.link_local().address());
+            self.transmit(socket, src, now);
+        }
+
+        self.update()
+    }
+
+    /// Next instant at which [`poll`](Self::poll) has work to do.
+    pub(crate) fn poll_at(&self) -> Option<Instant> {
+        let mut at = self.slaac.poll_at();
+        let mut earliest = |t: Instant| {
+            if t != Instant::MAX {
+                at = Some(at.map_or(t, |at| at.min(t)));
+            }
+        };
+        if let Some(exchange) = &self.exchange {
+            earliest(exchange.next);
+        }
+        if let Some(lease) = &self.lease {
+            earliest(lease.expires_at);
+            match self.state {
+                State::Bound => earliest(lease.renew_at),
+                State::Renewing => earliest(lease.rebind_at),
+                _ => {}
+            }
+        }
+        if let State::Informed { refresh_at } = self.state {
+            earliest(refresh_at);
+        }
+        at
+    }
+
+    fn update(&mut self) -> Option<Event> {
+        let config = match self.config.mode {
+            Dhcpv6Mode::Stateful => self.lease.as_ref().map(|lease| StaticConfigV6 {
+                address: Ipv6Cidr::new(lease.address, 128),
+                gateway: self.slaac.router(),
+                dns_servers: self.merge_dns_servers(&lease.dns_servers),
+            }),
+            Dhcpv6Mode::Stateless => self.slaac.address().map(|address| StaticConfigV6 {
+                address,
+                gateway: self.slaac.router(),
+                dns_servers: self.merge_dns_servers(&self.dns_servers),
+            }),
+        };
+
+        if config == self.current {
+            return None;
+        }
+        self.current = config.clone();
+        match config {
+            Some(config) => Some(Event::Configured(config)),
+            None => Some(Event::Deconfigured),
+        }
+    }
+
+    /// DHCPv6 servers first, then RDNSS servers from Router Advertisements.
+    fn merge_dns_servers(&self, servers: &[Ipv6Address]) -> Vec<Ipv6Address, 3> {
+        let mut merged = Vec::new();
+        for addr in servers.iter().copied().chain(self.slaac.dns_servers()) {
+            if !merged.contains(&addr) && merged.push(addr).is_err() {
+                break;
+            }
+        }
+        merged
+    }
+
+    fn expire(&mut self, now: Instant) {
+        if let Some(lease) = &self.lease {
+            if lease.expires_at <= now {
+                warn!("DHCPv6: lease expired");
+                self.lease = None;
+                self.start(State::Soliciting, now);
+            } else if matches!(self.state, State::Bound) && lease.renew_at <= now {
+                debug!("DHCPv6: renewing");
+                self.start(State::Renewing, now);
+            } else if matches!(self.state, State::Renewing) && lease.rebind_at <= now {
+                debug!("DHCPv6: rebinding");
+                self.start(State::Rebinding, now);
+            }
+        }
+        if matches!(self.state, State::Informed { refresh_at } if refresh_at <= now) {
+            self.start(State::Informing, now);
+        }
+    }
+
+    fn start(&mut self, state: State, now: Instant) {
+        let (timeout, max_timeout) = match state {
+            State::Soliciting => (SOL_TIMEOUT, SOL_MAX_RT),
+            State::Requesting { .. } => (REQ_TIMEOUT, REQ_MAX_RT),
+            State::Renewing => (REN_TIMEOUT, REN_MAX_RT),
+            State::Rebinding => (REB_TIMEOUT, REB_MAX_RT),
+            State::Informing => (INF_TIMEOUT, INF_MAX_RT),
+            State::Bound | State::Informed { .. } => {
+                self.state = state;
+                self.exchange = None;
+                return;
+            }
+        };
+        let xid = self.random().to_be_bytes();
+        self.exchange = Some(Exchange {
+            xid: [xid[0], xid[1], xid[2]],
+            started: now,
+            next: now,
+            timeout,
+            max_timeout,
+            count: 0,
+        });
+        self.state = state;
+    }
+
+    fn transmit(&mut self, socket: &mut udp::Socket, src: Option<Ipv6Address>, now: Instant) {
+        let mut buf = [0; TX_BUF];
+        let Some(len) = self.write_message(&mut buf, now) else {
+            return;
+        };
+        let meta = udp::UdpMetadata {
+            endpoint: IpEndpoint::new(ALL_DHCP_RELAY_AGENTS_AND_SERVERS.into(), SERVER_PORT),
+            local_address: src.map(Into::into),
+            meta: PacketMeta::default(),
+        };
+        if socket.send_slice(&buf[..len], meta).is_err() {
+            warn!("DHCPv6: transmit buffer full");
+        }
+
+        let jitter = self.random();
+        let Some(exchange) = &mut self.exchange else {
+            return;
+        };
+        // Exponential backoff with +/-10% randomization, RFC 8415 section 15.
+        let timeout = exchange.timeout.as_millis();
+        let timeout = timeout - timeout / 10 + jitter % (timeout / 5 + 1);
+        exchange.next = now + Duration::from_millis(timeout);
+        exchange.timeout = (exchange.timeout * 2).min(exchange.max_timeout);
+        exchange.count = exchange.count.saturating_add(1);
+    }
+
+    /// Write the message of the exchange in progress, returning its length.
+    fn write_message(&self, buf: &mut [u8; TX_BUF], now: Instant) -> Option<usize> {
+        let exchange = self.exchange.as_ref()?;
+        let msg_type = match self.state {
+            State::Soliciting => SOLICIT,
+            State::Requesting { .. } => REQUEST,
+            State::Renewing => RENEW,
+            State::Rebinding => REBIND,
+            State::Informing => INFORMATION_REQUEST,
+            State::Bound | State::Informed { .. } => return None,
+        };
+
+        let mut w = Writer { buf, len: 0 };
+        w.bytes(&[msg_type]);
+        w.bytes(&exchange.xid);
+        w.option(OPTION_CLIENTID, &[&self.duid]);
+
+        let server_id = match &self.state {
+            State::Requesting { server_id, .. } => Some(&server_id[..]),
+            State::Renewing => self.lease.as_ref().map(|lease| &lease.server_id[..]),
+            _ => None,
+        };
+        if let Some(server_id) = server_id {
+            w.option(OPTION_SERVERID, &[server_id]);
+        }
+
+        if msg_type != INFORMATION_REQUEST {
+            let address = match &self.state {
+                State::Requesting { address, .. } => Some(*address),
+                _ => self.lease.as_ref().map(|lease| lease.address),
+            };
+            // IAID, with T1 and T2 left for the server to choose, and the address we want if any.
+            let mut ia = [0; 12];
+            ia[..4].copy_from_slice(&self.iaid);
+            match address {
+                Some(address) => {
+                    w.option_header(OPTION_IA_NA, ia.len() + 4 + 24);
+                    w.bytes(&ia);
+                    w.option(OPTION_IAADDR, &[&address.octets(), &[0; 8]]);
+                }
+                None => w.option(OPTION_IA_NA, &[&ia]),
+            }
+        }
+
+        w.option_header(OPTION_ORO, if msg_type == INFORMATION_REQUEST { 4 } else { 2 });
+        w.bytes(&OPTION_DNS_SERVERS.to_be_bytes());
+        if msg_type == INFORMATION_REQUEST {
+            w.bytes(&OPTION_INFORMATION_REFRESH_TIME.to_be_bytes());
+        }
+
+        // Elapsed time in hundredths of a second, zero for the first transmission.
+        let elapsed = now.saturating_duration_since(exchange.started).as_millis() / 10;
+        w.option(OPTION_ELAPSED_TIME, &[&(elapsed.min(0xffff) as u16).to_be_bytes()]);
+        Some(w.len)
+    }
+
+    fn process(&mut self, data: &[u8], now: Instant) {
+        let Some(exchange) = &self.exchange else {
+            return;
+        };
+        let Some(msg) = Message::parse(data) else {
+            return;
+        };
+        if msg.xid != exchange.xid || msg.client_id != Some(&self.duid[..]) {
+            return;
+        }
+        let lease = msg.lease(self.iaid);
+
+        match (&self.state, msg.msg_type) {
+            (State::Soliciting, ADVERTISE) => {
+                // Take the first usable advertisement, instead of collecting advertisements for
+                // a second and comparing their preferences.
+                let (Some(server_id), Some(lease)) = (msg.server_id, lease) else {
+                    return;
+                };
+                let Ok(server_id) = Vec::from_slice(server_id) else {
+                    return;
+                };
+                debug!("DHCPv6: advertised {:?}", lease.address);
+                self.start(
+                    State::Requesting {
+                        server_id,
+                        address: lease.address,
+                    },
+                    now,
+                );
+            }
+            (State::Requesting { .. } | State::Renewing | State::Rebinding, REPLY) => {
+                let server_id = msg.server_id.and_then(|id| Vec::from_slice(id).ok());
+                match (server_id, lease) {
+                    (Some(server_id), Some(lease)) if msg.status == STATUS_SUCCESS => {
+                        self.bind(server_id, lease, msg.dns_servers, now)
+                    }
+                    // A failed renewal is retried until the lease expires.
+                    _ if matches!(self.state, State::Requesting { .. }) => {
+                        debug!("DHCPv6: request refused, status {}", msg.status);
+                        self.start(State::Soliciting, now);
+                    }
+                    _ => {}
+                }
+            }
+            (State::Informing, REPLY) if msg.status == STATUS_SUCCESS => {
+                debug!("DHCPv6: received information");
+                self.dns_servers = msg.dns_servers;
+                let refresh = msg.refresh_time.unwrap_or(IRT_DEFAULT).max(IRT_MINIMUM);
+                self.start(
+                    State::Informed {
+                        refresh_at: expiry(now, lifetime(refresh)),
+                    },
+                    now,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    fn bind(
+        &mut self,
+        server_id: Vec<u8, MAX_DUID_LEN>,
+        lease: LeaseInfo,
+        dns_servers: Vec<Ipv6Address, 3>,
+        now: Instant,
+    ) {
+        if !matches!(&self.lease, Some(l) if l.address == lease.address) {
+            info!("DHCPv6: leased {:?}", lease.address);
+        }
+
+        // RFC 8415 section 21.4: pick T1 and T2 ourselves if the server left them to us.
+        let preferred = lease.preferred as u64;
+        let t1 = match (lease.t1, lease.preferred) {
+            (0, u32::MAX) => u32::MAX,
+            (0, _) => (preferred / 2) as u32,
+            (t1, _) => t1,
+        };
+        let t2 = match (lease.t2, lease.preferred) {
+            (0, u32::MAX) => u32::MAX,
+            (0, _) => (preferred * 4 / 5) as u32,
+            (t2, _) => t2,
+        };
+
+        self.lease = Some(Lease {
+            server_id,
+            address: lease.address,
+            renew_at: expiry(now, lifetime(t1)),
+            rebind_at: expiry(now, lifetime(t2)),
+            expires_at: expiry(now, lifetime(lease.valid)),
+            dns_servers,
+        });
+        self.start(State::Bound, now);
+    }
+
+    fn random(&mut self) -> u64 {
+        self.rng = self
+            .rng
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        self.rng >> 32
+    }
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, data: &[u8]) {
+        self.buf[self.len..][..data.len()].copy_from_slice(data);
+        self.len += data.len();
+    }
+
+    fn option_header(&mut self, code: u16, len: usize) {
+        self.bytes(&code.to_be_bytes());
+        self.bytes(&(len as u16).to_be_bytes());
+    }
+
+    fn option(&mut self, code: u16, data: &[&[u8]]) {
+        self.option_header(code, data.iter().map(|d| d.len()).sum());
+        for d in data {
+            self.bytes(d);
+        }
+    }
+}
+
+/// Iterator over `(code, data)` options.
+///
+/// Stops at a truncated option, leaving it in `.0`.
+struct Options<'a>(&'a [u8]);
+
+impl<'a> Iterator for Options<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.len() < 4 {
+            return None;
+        }
+        let code = u16::from_be_bytes([self.0[0], self.0[1]]);
+        let len = u16::from_be_bytes([self.0[2], self.0[3]]) as usize;
+        let data = self.0.get(4..4 + len)?;
+        self.0 = &self.0[4 + len..];
+        Some((code, data))
+    }
+}
+
+struct Message<'a> {
+    msg_type: u8,
+    xid: [u8; 3],
+    client_id: Option<&'a [u8]>,
+    server_id: Option<&'a [u8]>,
+    status: u16,
+    ia_na: Option<&'a [u8]>,
+    dns_servers: Vec<Ipv6Address, 3>,
+    refresh_time: Option<u32>,
+}
+
+/// An address assigned in an IA_NA.
+struct LeaseInfo {
+    address: Ipv6Address,
+    t1: u32,
+    t2: u32,
+    preferred: u32,
+    valid: u32,
+}
+
+impl<'a> Message<'a> {
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < 4 {
+            return None;
+        }
+        let mut msg = Message {
+            msg_type: data[0],
+            xid: [data[1], data[2], data[3]],
+            client_id: None,
+            server_id: None,
+            status: STATUS_SUCCESS,
+            ia_na: None,
+            dns_servers: Vec::new(),
+            refresh_time: None,
+        };
+        let mut options = Options(&data[4..]);
+        for (code, data) in options.by_ref() {
+            match code {
+                OPTION_CLIENTID => msg.client_id = Some(data),
+                OPTION_SERVERID => msg.server_id = Some(data),
+                OPTION_STATUS_CODE => msg.status = status(data),
+                OPTION_IA_NA => msg.ia_na = Some(data),
+                OPTION_DNS_SERVERS => {
+                    if data.len() % 16 != 0 {
+                        return None;
+                    }
+                    for chunk in data.chunks_exact(16) {
+                        let addr = Ipv6Address::from(<[u8; 16]>::try_from(chunk).ok()?);
+                        if msg.dns_servers.push(addr).is_err() {
+                            debug!("DHCPv6: ignoring DNS server {:?}, list full", addr);
+                        }
+                    }
+                }
+                OPTION_INFORMATION_REFRESH_TIME if data.len() == 4 => {
+                    msg.refresh_time = Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+                }
+                _ => {}
+            }
+        }
+        if !options.0.is_empty() {
+            return None;
+        }
+        Some(msg)
+    }
+
+    /// The valid address assigned to our IA_NA, if any.
+    fn lease(&self, iaid: [u8; 4]) -> Option<LeaseInfo> {
+        // IAID, T1, T2, options.
+        let ia = self.ia_na?;
+        if ia.len() < 12 || ia[..4] != iaid {
+            return None;
+        }
+        let t1 = u32::from_be_bytes([ia[4], ia[5], ia[6], ia[7]]);
+        let t2 = u32::from_be_bytes([ia[8], ia[9], ia[10], ia[11]]);
+
+        let mut lease = None;
+        let mut options = Options(&ia[12..]);
+        for (code, data) in options.by_ref() {
+            match code {
+                OPTION_STATUS_CODE if status(data) != STATUS_SUCCESS => return None,
+                // Address, preferred lifetime, valid lifetime, options.
+                OPTION_IAADDR if data.len() >= 24 => {
+                    let valid = u32::from_be_bytes([data[20], data[21], data[22], data[23]]);
+                    let address_status = Options(&data[24..])
+                        .find(|(code, _)| *code == OPTION_STATUS_CODE)
+                        .map_or(STATUS_SUCCESS, |(_, data)| status(data));
+                    if valid == 0 || address_status != STATUS_SUCCESS || lease.is_some() {
+                        continue;
+                    }
+                    lease = Some(LeaseInfo {
+                        address: Ipv6Address::from(<[u8; 16]>::try_from(&data[..16]).ok()?),
+                        t1,
+                        t2,
+                        preferred: u32::from_be_bytes([data[16], data[17], data[18], data[19]]),
+                        valid,
+                    });
+                }
+                _ => {}
+            }
+        }
+        if !options.0.is_empty() {
+            return None;
+        }
+        lease
+    }
+}
+
+fn status(data: &[u8]) -> u16 {
+    match data {
+        [a, b, ..] => u16::from_be_bytes([*a, *b]),
+        _ => STATUS_SUCCESS,
+    }
+}
+
+#[cfg(all(test, feature = "medium-ethernet"))]
+mod tests {
+    use std::vec::Vec;
+
+    use smoltcp::iface::SocketStorage;
+    use smoltcp::wire::EthernetAddress;
+
+    use super::*;
+
+    const T0: Instant = Instant::from_secs(1000);
+    const ADDRESS: Ipv6Address = Ipv6Address::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x100);
+    const DNS: Ipv6Address = Ipv6Address::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53);
+    const SERVER_ID: &[u8] = &[0, 3, 0, 1, 0x02, 0, 0, 0, 0, 0xff];
+    const NO_ADDRS_AVAIL: u16 = 2;
+
+    fn client(mode: Dhcpv6Mode) -> Dhcpv6 {
+        let storage = Box::leak(Box::new([SocketStorage::EMPTY; 2]));
+        let mut sockets = SocketSet::new(&mut storage[..]);
+        let resources = Box::leak(Box::new(Dhcpv6Resources::new()));
+        let slaac_resources = Box::leak(Box::new(SlaacResources::new()));
+        let config = Dhcpv6Config {
+            mode,
+            ..Default::default()
+        };
+        let hardware_address = HardwareAddress::Ethernet(EthernetAddress([0x02, 0, 0, 0, 0, 1]));
+        // safety: the resources are leaked, and only used by these sockets.
+        let mut client = unsafe { Dhcpv6::new(&mut sockets, resources, slaac_resources, config, hardware_address, 0) };
+        client.reset(T0);
+        client
+    }
+
+    fn option(code: u16, data: &[u8]) -> Vec<u8> {
+        let mut option = Vec::from(code.to_be_bytes());
+        option.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        option.extend_from_slice(data);
+        option
+    }
+
+    fn status_code(code: u16) -> Vec<u8> {
+        let mut data = Vec::from(code.to_be_bytes());
+        data.extend_from_slice(b"message");
+        option(OPTION_STATUS_CODE, &data)
+    }
+
+    fn iaaddr(address: Ipv6Address, preferred: u32, valid: u32, options: &[u8]) -> Vec<u8> {
+        let mut data = Vec::from(address.octets());
+        data.extend_from_slice(&preferred.to_be_bytes());
+        data.extend_from_slice(&valid.to_be_bytes());
+        data.extend_from_slice(options);
+        option(OPTION_IAADDR, &data)
+    }
+
+    fn ia_na(iaid: [u8; 4], t1: u32, t2: u32, options: &[u8]) -> Vec<u8> {
+        let mut data = Vec::from(iaid);
+        data.extend_from_slice(&t1.to_be_bytes());
+        data.extend_from_slice(&t2.to_be_bytes());
+        data.extend_from_slice(options);
+        option(OPTION_IA_NA, &data)
+    }
+
+    /// A message from the server answering the exchange in progress.
+    fn message(client: &Dhcpv6, msg_type: u8, options: &[u8]) -> Vec<u8> {
+        let mut msg = Vec::from([msg_type]);
+        msg.extend_from_slice(&unwrap!(client.exchange.as_ref()).xid);
+        msg.extend_from_slice(&option(OPTION_CLIENTID, &client.duid));
+        msg.extend_from_slice(&option(OPTION_SERVERID, SERVER_ID));
+        msg.extend_from_slice(options);
+        msg
+    }
+
+    /// A lease of `ADDRESS` for an hour, with T1 and T2 left to the client.
+    fn lease(client: &Dhcpv6) -> Vec<u8> {
+        ia_na(client.iaid, 0, 0, &iaaddr(ADDRESS, 3600, 7200, &[]))
+    }
+
+    /// The message the client would transmit, and its options.
+    fn sent(client: &Dhcpv6, now: Instant) -> (u8, Vec<(u16, Vec<u8>)>) {
+        let mut buf = [0; TX_BUF];
+        let len = unwrap!(client.write_message(&mut buf, now));
+        let options = Options(&buf[4..len])
+            .map(|(code, data)| (code, data.to_vec()))
+            .collect();
+        (buf[0], options)
+    }
+
+    fn sent_option(options: &[(u16, Vec<u8>)], code: u16) -> Option<&[u8]> {
+        options.iter().find(|(c, _)| *c == code).map(|(_, data)| &data[..])
+    }
+
+    #[test]
+    fn parse() {
+        let mut dns = Vec::from(DNS.octets());
+        dns.extend_from_slice(&ADDRESS.octets());
+        let mut data = Vec::from([REPLY, 1, 2, 3]);
+        data.extend_from_slice(&option(OPTION_DNS_SERVERS, &dns));
+        data.extend_from_slice(&option(OPTION_INFORMATION_REFRESH_TIME, &700u32.to_be_bytes()));
+        data.extend_from_slice(&status_code(NO_ADDRS_AVAIL));
+        let msg = unwrap!(Message::parse(&data));
+        assert_eq!((msg.msg_type, msg.xid), (REPLY, [1, 2, 3]));
+        assert_eq!(msg.dns_servers, [DNS, ADDRESS]);
+        assert_eq!(msg.refresh_time, Some(700));
+        assert_eq!(msg.status, NO_ADDRS_AVAIL);
+        assert_eq!((msg.client_id, msg.server_id), (None, None));
+
+        // Truncated messages and options, and DNS servers that aren't a list of addresses.
+        assert!(Message::parse(&[REPLY, 1, 2]).is_none());
+        assert!(Message::parse(&data[..data.len() - 1]).is_none());
+        assert!(Message::parse(&data[..data.len() - 10]).is_none());
+        let mut data = Vec::from([REPLY, 1, 2, 3]);
+        data.extend_from_slice(&option(OPTION_DNS_SERVERS, &[0; 20]));
+        assert!(Message::parse(&data).is_none());
+    }
+
+    fn parse_lease(ia: &[u8]) -> Option<(Ipv6Address, u32, u32, u32, u32)> {
+        let mut data = Vec::from([REPLY, 1, 2, 3]);
+        data.extend_from_slice(ia);
+        let lease = unwrap!(Message::parse(&data)).lease([1, 2, 3, 4])?;
+        Some((lease.address, lease.t1, lease.t2, lease.preferred, lease.valid))
+    }
+
+    #[test]
+    fn parse_ia_na() {
+        let iaid = [1, 2, 3, 4];
+        let address = iaaddr(ADDRESS, 3600, 7200, &[]);
+        assert_eq!(
+            parse_lease(&ia_na(iaid, 1800, 2880, &address)),
+            Some((ADDRESS, 1800, 2880, 3600, 7200))
+        );
+        // Another IA, or none.
+        assert_eq!(parse_lease(&ia_na([4, 3, 2, 1], 1800, 2880, &address)), None);
+        assert_eq!(parse_lease(&[]), None);
+
+        // A failure status for the IA, or for the address.
+        let mut options = status_code(NO_ADDRS_AVAIL);
+        options.extend_from_slice(&address);
+        assert_eq!(parse_lease(&ia_na(iaid, 0, 0, &options)), None);
+        let mut options = iaaddr(Ipv6Address::LOCALHOST, 3600, 7200, &status_code(NO_ADDRS_AVAIL));
+        options.extend_from_slice(&address);
+        assert_eq!(parse_lease(&ia_na(iaid, 0, 0, &options)).map(|l| l.0), Some(ADDRESS));
+
+        // Addresses that are no longer valid, or too short, are skipped.
+        let mut options = iaaddr(Ipv6Address::LOCALHOST, 0, 0, &[]);
+        options.extend_from_slice(&option(OPTION_IAADDR, &[0; 20]));
+        options.extend_from_slice(&address);
+        assert_eq!(parse_lease(&ia_na(iaid, 0, 0, &options)).map(|l| l.0), Some(ADDRESS));
+
+        // Truncated IAs and options.
+        let ia = ia_na(iaid, 0, 0, &address);
+        assert_eq!(parse_lease(&option(OPTION_IA_NA, &ia[4..12])), None);
+        assert_eq!(parse_lease(&option(OPTION_IA_NA, &ia[4..ia.len() - 1])), None);
+    }
+
+    fn times(client: &Dhcpv6) -> (Instant, Instant, Instant) {
+        let lease = unwrap!(client.lease.as_ref());
+        (lease.renew_at, lease.rebind_at, lease.expires_at)
+    }
+
+    fn lease_info(t1: u32, t2: u32, preferred: u32, valid: u32) -> LeaseInfo {
+        LeaseInfo {
+            address: ADDRESS,
+            t1,
+            t2,
+            preferred,
+            valid,
+        }
+    }
+
+    #[test]
+    fn renewal_times() {
+        let mut client = client(Dhcpv6Mode::Stateful);
+        let server_id = || unwrap!(heapless::Vec::from_slice(SERVER_ID));
+
+        client.bind(server_id(), lease_info(100, 200, 1000, 2000), heapless::Vec::new(), T0);
+        let secs = |s| T0 + Duration::from_secs(s);
+        assert_eq!(times(&client), (secs(100), secs(200), secs(2000)));
+
+        // Left to the client: half and 80% of the preferred lifetime.
+        client.bind(server_id(), lease_info(0, 0, 1000, 2000), heapless::Vec::new(), T0);
+        assert_eq!(times(&client), (secs(500), secs(800), secs(2000)));
+
+        // Infinite lifetimes never need renewing.
+        client.bind(
+            server_id(),
+            lease_info(0, 0, u32::MAX, u32::MAX),
+            heapless::Vec::new(),
+            T0,
+        );
+        assert_eq!(times(&client), (Instant::MAX, Instant::MAX, Instant::MAX));
+    }
+
+    #[test]
+    fn stateful() {
+        let mut client = client(Dhcpv6Mode::Stateful);
+
+        // Solicit, without an address.
+        assert!(matches!(client.state, State::Soliciting));
+        let (msg_type, options) = sent(&client, T0);
+        assert_eq!(msg_type, SOLICIT);
+        assert_eq!(sent_option(&options, OPTION_CLIENTID), Some(&client.duid[..]));
+        assert_eq!(sent_option(&options, OPTION_SERVERID), None);
+        assert_eq!(
+            sent_option(&options, OPTION_IA_NA),
+            Some(&[&client.iaid[..], &[0; 8]].concat()[..])
+        );
+        assert_eq!(
+            sent_option(&options, OPTION_ORO),
+            Some(&OPTION_DNS_SERVERS.to_be_bytes()[..])
+        );
+
+        // Advertisements for another exchange or client are ignored.
+        let mut advertise = message(&client, ADVERTISE, &lease(&client));
+        advertise[1] ^= 1;
+        client.process(&advertise, T0);
+        assert!(matches!(client.state, State::Soliciting));
+        let mut advertise = Vec::from([ADVERTISE]);
+        advertise.extend_from_slice(&unwrap!(client.exchange.as_ref()).xid);
+        advertise.extend_from_slice(&option(OPTION_CLIENTID, &[0, 3, 0, 1, 0x02, 0, 0, 0, 0, 2]));
+        advertise.extend_from_slice(&option(OPTION_SERVERID, SERVER_ID));
+        advertise.extend_from_slice(&lease(&client));
+        client.process(&advertise, T0);
+        assert!(matches!(client.state, State::Soliciting));
+
+        // Request the advertised address from the server.
+        client.process(&message(&client, ADVERTISE, &lease(&client)), T0);
+        assert!(matches!(client.state, State::Requesting { address: ADDRESS, .. }));
+        let (msg_type, options) = sent(&client, T0);
+        assert_eq!(msg_type, REQUEST);
+        assert_eq!(sent_option(&options, OPTION_SERVERID), Some(SERVER_ID));
+        let ia = unwrap!(sent_option(&options, OPTION_IA_NA));
+        assert_eq!(ia[16..32], ADDRESS.octets());
+
+        // A refusal starts over.
+        let refused = message(&client, REPLY, &status_code(NO_ADDRS_AVAIL));
+        client.process(&refused, T0);
+        assert!(matches!(client.state, State::Soliciting));
+        client.process(&message(&client, ADVERTISE, &lease(&client)), T0);
+
+        let mut options = lease(&client);
+        options.extend_from_slice(&option(OPTION_DNS_SERVERS, &DNS.octets()));
+        client.process(&message(&client, REPLY, &options), T0);
+        assert!(matches!(client.state, State::Bound));
+        assert!(client.write_message(&mut [0; TX_BUF], T0).is_none());
+        match client.update() {
+            Some(Event::Configured(config)) => {
+                assert_eq!(config.address, Ipv6Cidr::new(ADDRESS, 128));
+                assert_eq!(config.dns_servers, [DNS]);
+            }
+            _ => panic!("not configured"),
+        }
+        assert!(client.update().is_none());
+
+        // Renew with the server at T1, then rebind with any server at T2.
+        let (renew_at, rebind_at, expires_at) = times(&client);
+        client.expire(renew_at);
+        assert!(matches!(client.state, State::Renewing));
+        let (msg_type, options) = sent(&client, renew_at);
+        assert_eq!(msg_type, RENEW);
+        assert_eq!(sent_option(&options, OPTION_SERVERID), Some(SERVER_ID));
+        // Failures are retried until the lease expires.
+        client.process(&message(&client, REPLY, &status_code(NO_ADDRS_AVAIL)), renew_at);
+        assert!(matches!(client.state, State::Renewing));
+
+        client.expire(rebind_at);
+        assert!(matches!(client.state, State::Rebinding));
+        let (msg_type, options) = sent(&client, rebind_at);
+        assert_eq!(msg_type, REBIND);
+        assert_eq!(sent_option(&options, OPTION_SERVERID), None);
+        client.process(&message(&client, REPLY, &lease(&client)), rebind_at);
+        assert!(matches!(client.state, State::Bound));
+        assert_eq!(times(&client).2, expiry(rebind_at, Duration::from_secs(7200)));
+
+        // Without a reply, the address is lost when the lease expires.
+        let (renew_at, rebind_at, expires_at_2) = times(&client);
+        assert!(expires_at_2 > expires_at);
+        client.expire(renew_at);
+        client.expire(rebind_at);
+        client.expire(expires_at_2);
+        assert!(matches!(client.state, State::Soliciting));
+        assert!(client.lease.is_none());
+        assert!(matches!(client.update(), Some(Event::Deconfigured)));
+    }
+
+    #[test]
+    fn stateless() {
+        let mut client = client(Dhcpv6Mode::Stateless);
+
+        assert!(matches!(client.state, State::Informing));
+        let (msg_type, options) = sent(&client, T0);
+        assert_eq!(msg_type, INFORMATION_REQUEST);
+        assert_eq!(sent_option(&options, OPTION_IA_NA), None);
+        let oro = [
+            OPTION_DNS_SERVERS.to_be_bytes(),
+            OPTION_INFORMATION_REFRESH_TIME.to_be_bytes(),
+        ]
+        .concat();
+        assert_eq!(sent_option(&options, OPTION_ORO), Some(&oro[..]));
+
+        // The refresh time is at least IRT_MINIMUM.
+        let mut options = option(OPTION_DNS_SERVERS, &DNS.octets());
+        options.extend_from_slice(&option(OPTION_INFORMATION_REFRESH_TIME, &60u32.to_be_bytes()));
+        client.process(&message(&client, REPLY, &options), T0);
+        let refresh_at = T0 + Duration::from_secs(IRT_MINIMUM as u64);
+        assert!(matches!(client.state, State::Informed { refresh_at: at } if at == refresh_at));
+        assert_eq!(client.dns_servers, [DNS]);
+        assert!(client.lease.is_none());
+
+        // Then information is requested again, in a new exchange.
+        client.expire(refresh_at);
+        assert!(matches!(client.state, State::Informing));
+        assert_eq!(sent(&client, refresh_at).0, INFORMATION_REQUEST);
+        client.process(&message(&client, REPLY, &[]), refresh_at);
+        let refresh_at = refresh_at + Duration::from_secs(IRT_DEFAULT as u64);
+        assert!(matches!(client.state, State::Informed { refresh_at: at } if at == refresh_at));
+    }
+}
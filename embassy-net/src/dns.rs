@@ -4,9 +4,17 @@
 //! Prefer using [`Stack::dns_query`](crate::Stack::dns_query) directly if you're
 //! not using `embedded-nal-async`.
 
+#[cfg(feature = "dns-cache")]
+use embassy_time::{Duration, Instant};
+#[cfg(feature = "dns-cache")]
+use heapless::String;
 use heapless::Vec;
+#[cfg(feature = "dns-cache")]
+use smoltcp::phy::Medium;
 pub use smoltcp::socket::dns::{DnsQuery, Socket};
 pub(crate) use smoltcp::socket::dns::{GetQueryResultError, StartQueryError};
+#[cfg(feature = "dns-cache")]
+use smoltcp::wire::IpEndpoint;
 pub use smoltcp::wire::{DnsQueryType, IpAddress};
 
 use crate::Stack;
@@ -115,6 +123,531 @@ impl<'a> embedded_nal_async::Dns for DnsSocket<'a> {
     }
 }
 
+/// Number of answers kept by the cache, including queries in flight.
+#[cfg(feature = "dns-cache")]
+const CACHE_SIZE: usize = 8;
+/// Longest name cached, longer names are always queried.
+#[cfg(feature = "dns-cache")]
+const MAX_CACHED_NAME_LEN: usize = 64;
+/// Longest time an answer is cached, whatever its TTL.
+#[cfg(feature = "dns-cache")]
+const MAX_TTL: u32 = 60 * 60;
+#[cfg(feature = "dns-cache")]
+const DNS_PORT: u16 = 53;
+
+/// A query sent by smoltcp, to recognize its response.
+#[cfg(feature = "dns-cache")]
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct SentQuery {
+    /// DNS server the query was sent to.
+    server: IpAddress,
+    /// Local port the query was sent from.
+    port: u16,
+    transaction_id: u16,
+}
+
+#[cfg(feature = "dns-cache")]
+enum CacheState {
+    /// The query is in flight, sent to the servers in `sent`, with the TTL of its response once
+    /// it's been seen.
+    Pending {
+        sent: Vec<SentQuery, { smoltcp::config::DNS_MAX_SERVER_COUNT }>,
+        ttl: Option<u32>,
+    },
+    Valid {
+        addrs: Vec<IpAddress, { smoltcp::config::DNS_MAX_RESULT_COUNT }>,
+        expires_at: Instant,
+    },
+}
+
+#[cfg(feature = "dns-cache")]
+struct CacheEntry {
+    /// Lowercase name, without the trailing dot.
+    name: String<MAX_CACHED_NAME_LEN>,
+    qtype: DnsQueryType,
+    state: CacheState,
+}
+
+/// Cache of the answers to [`Stack::dns_query`].
+///
+/// smoltcp's DNS socket only gives the addresses of an answer, so the TTL is taken from the
+/// response itself, looking at the frames received while the query is in flight. Only responses
+/// from the server a query was sent to, with its transaction ID, are taken: the queries are
+/// seen in the frames transmitted. Frames on IEEE 802.15.4 interfaces are compressed, so their
+/// answers are never cached.
+#[cfg(feature = "dns-cache")]
+pub(crate) struct Cache {
+    entries: Vec<CacheEntry, CACHE_SIZE>,
+}
+
+#[cfg(feature = "dns-cache")]
+impl Cache {
+    pub(crate) const fn new() -> Self {
+        Self { entries: Vec::new() }
+    }
+
+    /// Get the cached answer for `name`, if it hasn't expired.
+    pub(crate) fn get(
+        &mut self,
+        name: &str,
+        qtype: DnsQueryType,
+        now: Instant,
+    ) -> Option<Vec<IpAddress, { smoltcp::config::DNS_MAX_RESULT_COUNT }>> {
+        self.entries
+            .retain(|e| !matches!(e.state, CacheState::Valid { expires_at, .. } if expires_at <= now));
+        self.entries.iter().find_map(|e| match &e.state {
+            CacheState::Valid { addrs, .. } if e.matches(name, qtype) => Some(addrs.clone()),
+            _ => None,
+        })
+    }
+
+    /// Start watching for the response to a query for `name`.
+    pub(crate) fn start(&mut self, name: &str, qtype: DnsQueryType) {
+        if self.position(name, qtype).is_some() {
+            return;
+        }
+        let Some(name) = normalize(name) else {
+            return;
+        };
+        let entry = CacheEntry {
+            name,
+            qtype,
+            state: CacheState::Pending {
+                sent: Vec::new(),
+                ttl: None,
+            },
+        };
+        if self.entries.is_full() {
+            // Evict the answer expiring first. If all queries are in flight, don't cache this one.
+            let Some(i) = self
+                .entries
+                .iter()
+                .enumerate()
+                .filter_map(|(i, e)| match e.state {
+                    CacheState::Valid { expires_at, .. } => Some((i, expires_at)),
+                    CacheState::Pending { .. } => None,
+                })
+                .min_by_key(|(_, expires_at)| *expires_at)
+                .map(|(i, _)| i)
+            else {
+                return;
+            };
+            self.entries.swap_remove(i);
+        }
+        let _ = self.entries.push(entry);
+    }
+
+    /// Cache the answer to a query, if the TTL of its response was seen.
+    pub(crate) fn complete(
+        &mut self,
+        name: &str,
+        qtype: DnsQueryType,
+        addrs: &Vec<IpAddress, { smoltcp::config::DNS_MAX_RESULT_COUNT }>,
+        now: Instant,
+    ) {
+        let Some(i) = self.position(name, qtype) else {
+            return;
+        };
+        match self.entries[i].state {
+            CacheState::Pending { ttl: Some(ttl), .. } if ttl > 0 && !addrs.is_empty() => {
+                self.entries[i].state = CacheState::Valid {
+                    addrs: addrs.clone(),
+                    expires_at: now + Duration::from_secs(ttl.min(MAX_TTL) as u64),
+                };
+            }
+            CacheState::Pending { .. } => {
+                self.entries.swap_remove(i);
+            }
+            // Another query for the same name completed first.
+            CacheState::Valid { .. } => {}
+        }
+    }
+
+    /// Stop watching for the response to a query which failed or was cancelled.
+    pub(crate) fn cancel(&mut self, name: &str, qtype: DnsQueryType) {
+        if let Some(i) = self.position(name, qtype) {
+            if let CacheState::Pending { .. } = self.entries[i].state {
+                self.entries.swap_remove(i);
+            }
+        }
+    }
+
+    /// Forget all answers, keeping the queries in flight.
+    pub(crate) fn flush(&mut self) {
+        self.entries.retain(|e| matches!(e.state, CacheState::Pending { .. }));
+    }
+
+    /// Look at a frame transmitted on an interface, for the queries in flight.
+    pub(crate) fn transmitted(&mut self, frame: &[u8], medium: Medium) {
+        use smoltcp::wire::{DnsFlags, DnsPacket, DnsQuestion};
+
+        if !self.has_pending() {
+            return;
+        }
+        let Some((src, dst, payload)) = udp_datagram(frame, medium) else {
+            return;
+        };
+        let Ok(packet) = DnsPacket::new_checked(payload) else {
+            return;
+        };
+        if dst.port != DNS_PORT || packet.flags().contains(DnsFlags::RESPONSE) || packet.question_count() != 1 {
+            return;
+        }
+        let Ok((_, question)) = DnsQuestion::parse(packet.payload()) else {
+            return;
+        };
+        let query = SentQuery {
+            server: dst.addr,
+            port: src.port,
+            transaction_id: packet.transaction_id(),
+        };
+        for entry in self.entries.iter_mut() {
+            if let CacheState::Pending { sent, .. } = &mut entry.state {
+                if entry.qtype == question.type_
+                    && name_eq(packet.parse_name(question.name), &entry.name)
+                    && !sent.contains(&query)
+                {
+                    // Responses to queries which don't fit aren't cached.
+                    let _ = sent.push(query);
+                }
+            }
+        }
+    }
+
+    /// Look at a frame received on an interface, for responses to the queries in flight.
+    pub(crate) fn received(&mut self, frame: &[u8], medium: Medium) {
+        use smoltcp::wire::{DnsFlags, DnsPacket, DnsQuestion, DnsRcode, DnsRecord, DnsRecordData};
+
+        if !self.has_pending() {
+            return;
+        }
+        let Some((src, dst, payload)) = udp_datagram(frame, medium) else {
+            return;
+        };
+        let Ok(packet) = DnsPacket::new_checked(payload) else {
+            return;
+        };
+        if src.port != DNS_PORT
+            || !packet.flags().contains(DnsFlags::RESPONSE)
+            || packet.rcode() != DnsRcode::NoError
+            || packet.question_count() != 1
+        {
+            return;
+        }
+        let Ok((mut rest, question)) = DnsQuestion::parse(packet.payload()) else {
+            return;
+        };
+        let query = SentQuery {
+            server: src.addr,
+            port: dst.port,
+            transaction_id: packet.transaction_id(),
+        };
+        let Some(entry) = self.entries.iter_mut().find(|e| {
+            matches!(&e.state, CacheState::Pending { sent, .. } if sent.contains(&query))
+                && e.qtype == question.type_
+                && name_eq(packet.parse_name(question.name), &e.name)
+        }) else {
+            return;
+        };
+
+        // The answer holds the records of the name, and the CNAME records leading to it.
+        let mut ttl: Option<u32> = None;
+        for _ in 0..packet.answer_record_count() {
+            let Ok((next, record)) = DnsRecord::parse(rest) else {
+                return;
+            };
+            rest = next;
+            if let DnsRecordData::Other(..) = record.data {
+                continue;
+            }
+            // RFC 2181 section 8: TTLs with the most significant bit set are treated as zero.
+            let record_ttl = if record.ttl & 0x8000_0000 != 0 { 0 } else { record.ttl };
+            ttl = Some(ttl.map_or(record_ttl, |t| t.min(record_ttl)));
+        }
+        if let (Some(ttl), CacheState::Pending { ttl: seen, .. }) = (ttl, &mut entry.state) {
+            *seen = Some(ttl);
+        }
+    }
+
+    fn has_pending(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|e| matches!(e.state, CacheState::Pending { .. }))
+    }
+
+    fn position(&self, name: &str, qtype: DnsQueryType) -> Option<usize> {
+        self.entries.iter().position(|e| e.matches(name, qtype))
+    }
+}
+
+#[cfg(feature = "dns-cache")]
+impl CacheEntry {
+    fn matches(&self, name: &str, qtype: DnsQueryType) -> bool {
+        self.qtype == qtype && self.name.eq_ignore_ascii_case(name.strip_suffix('.').unwrap_or(name))
+    }
+}
+
+/// Lowercase `name` and remove its trailing dot, if it fits in the cache.
+#[cfg(feature = "dns-cache")]
+fn normalize(name: &str) -> Option<String<MAX_CACHED_NAME_LEN>> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    let mut out = String::new();
+    for c in name.chars() {
+        out.push(c.to_ascii_lowercase()).ok()?;
+    }
+    Some(out)
+}
+
+/// Compare the labels of a name in a DNS message with a dotted name, ignoring case.
+#[cfg(feature = "dns-cache")]
+fn name_eq<'a>(mut labels: impl Iterator<Item = Result<&'a [u8], smoltcp::wire::Error>>, name: &str) -> bool {
+    for expected in name.split('.') {
+        match labels.next() {
+            Some(Ok(label)) if label.eq_ignore_ascii_case(expected.as_bytes()) => {}
+            _ => return false,
+        }
+    }
+    labels.next().is_none()
+}
+
+/// Get the source, destination and payload of a UDP datagram in `frame`, if it isn't
+/// fragmented.
+#[cfg(feature = "dns-cache")]
+fn udp_datagram(frame: &[u8], medium: Medium) -> Option<(IpEndpoint, IpEndpoint, &[u8])> {
+    use smoltcp::wire::{IpProtocol, UdpPacket};
+
+    let packet = match medium {
+        #[cfg(feature = "medium-ethernet")]
+        Medium::Ethernet => {
+            use smoltcp::wire::{EthernetFrame, EthernetProtocol};
+
+            let frame = EthernetFrame::new_checked(frame).ok()?;
+            match frame.ethertype() {
+                EthernetProtocol::Ipv4 | EthernetProtocol::Ipv6 => {}
+                _ => return None,
+            }
+            &frame.into_inner()[EthernetFrame::<&[u8]>::header_len()..]
+        }
+        #[cfg(feature = "medium-ip")]
+        Medium::Ip => frame,
+        #[allow(unreachable_patterns)]
+        _ => return None,
+    };
+
+    let (src, dst, udp): (IpAddress, IpAddress, _) = match packet.first()? >> 4 {
+        #[cfg(feature = "proto-ipv4")]
+        4 => {
+            let packet = smoltcp::wire::Ipv4Packet::new_checked(packet).ok()?;
+            if packet.next_header() != IpProtocol::Udp || packet.more_frags() || packet.frag_offset() != 0 {
+                return None;
+            }
+            let header_len = packet.header_len() as usize;
+            let total_len = packet.total_len() as usize;
+            let (src, dst) = (packet.src_addr(), packet.dst_addr());
+            (src.into(), dst.into(), &packet.into_inner()[header_len..total_len])
+        }
+        #[cfg(feature = "proto-ipv6")]
+        6 => {
+            let packet = smoltcp::wire::Ipv6Packet::new_checked(packet).ok()?;
+            if packet.next_header() != IpProtocol::Udp {
+                return None;
+            }
+            let len = packet.payload_len() as usize;
+            let (src, dst) = (packet.src_addr(), packet.dst_addr());
+            (
+                src.into(),
+                dst.into(),
+                &packet.into_inner()[smoltcp::wire::IPV6_HEADER_LEN..][..len],
+            )
+        }
+        _ => return None,
+    };
+
+    let datagram = UdpPacket::new_checked(udp).ok()?;
+    let src = IpEndpoint::new(src, datagram.src_port());
+    let dst = IpEndpoint::new(dst, datagram.dst_port());
+    let len = datagram.len() as usize;
+    Some((src, dst, &datagram.into_inner()[smoltcp::wire::UDP_HEADER_LEN..len]))
+}
+
 fn _assert_covariant<'a, 'b: 'a>(x: DnsSocket<'b>) -> DnsSocket<'a> {
     x
 }
+
+#[cfg(all(test, feature = "dns-cache", feature = "proto-ipv4", feature = "medium-ip"))]
+pub(crate) mod tests {
+    use std::vec::Vec as StdVec;
+
+    use smoltcp::phy::ChecksumCapabilities;
+    use smoltcp::wire::{IpProtocol, Ipv4Address, Ipv4Packet, Ipv4Repr, UdpPacket, UdpRepr};
+
+    use super::*;
+
+    const HOST: Ipv4Address = Ipv4Address::new(10, 0, 0, 2);
+    const SERVER: Ipv4Address = Ipv4Address::new(10, 0, 0, 1);
+    const ADDRESS: Ipv4Address = Ipv4Address::new(93, 184, 216, 34);
+    const PORT: u16 = 49152;
+
+    /// An IPv4 frame with a UDP datagram.
+    fn datagram(src: (Ipv4Address, u16), dst: (Ipv4Address, u16), payload: &[u8]) -> StdVec<u8> {
+        let udp = UdpRepr {
+            src_port: src.1,
+            dst_port: dst.1,
+        };
+        let ip = Ipv4Repr {
+            src_addr: src.0,
+            dst_addr: dst.0,
+            next_header: IpProtocol::Udp,
+            payload_len: udp.header_len() + payload.len(),
+            hop_limit: 64,
+        };
+        let caps = ChecksumCapabilities::default();
+        let mut buf = vec![0; ip.buffer_len() + ip.payload_len];
+        let mut packet = Ipv4Packet::new_unchecked(&mut buf[..]);
+        ip.emit(&mut packet, &caps);
+        let mut datagram = UdpPacket::new_unchecked(packet.payload_mut());
+        udp.emit(
+            &mut datagram,
+            &src.0.into(),
+            &dst.0.into(),
+            payload.len(),
+            |buf| buf.copy_from_slice(payload),
+            &caps,
+        );
+        buf
+    }
+
+    /// A DNS message about `name`, without answers for queries.
+    fn message(id: u16, name: &str, ttl: Option<u32>) -> StdVec<u8> {
+        let (flags, answers): (u16, u16) = match ttl {
+            Some(_) => (0x8180, 1),
+            None => (0x0100, 0),
+        };
+        let mut msg = StdVec::new();
+        for field in [id, flags, 1, answers, 0, 0] {
+            msg.extend_from_slice(&field.to_be_bytes());
+        }
+        for label in name.split('.') {
+            msg.push(label.len() as u8);
+            msg.extend_from_slice(label.as_bytes());
+        }
+        msg.extend_from_slice(&[0, 0, 1, 0, 1]);
+        if let Some(ttl) = ttl {
+            msg.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1]);
+            msg.extend_from_slice(&ttl.to_be_bytes());
+            msg.extend_from_slice(&[0, 4]);
+            msg.extend_from_slice(&ADDRESS.octets());
+        }
+        msg
+    }
+
+    fn addrs() -> Vec<IpAddress, { smoltcp::config::DNS_MAX_RESULT_COUNT }> {
+        [ADDRESS.into()].into_iter().collect()
+    }
+
+    /// Resolve `name` through `cache`, with a response of `ttl` seconds to a query `id`.
+    pub(crate) fn resolve(cache: &mut Cache, name: &str, id: u16, ttl: u32, now: Instant) {
+        cache.start(name, DnsQueryType::A);
+        let query = datagram((HOST, PORT), (SERVER, DNS_PORT), &message(id, name, None));
+        cache.transmitted(&query, Medium::Ip);
+        let response = datagram((SERVER, DNS_PORT), (HOST, PORT), &message(id, name, Some(ttl)));
+        cache.received(&response, Medium::Ip);
+        cache.complete(name, DnsQueryType::A, &addrs(), now);
+    }
+
+    pub(crate) fn cached(cache: &mut Cache, name: &str, now: Instant) -> bool {
+        cache.get(name, DnsQueryType::A, now).is_some()
+    }
+
+    #[test]
+    fn ttl_expiry() {
+        let mut cache = Cache::new();
+        resolve(&mut cache, "example.com", 0x1234, 30, Instant::from_secs(100));
+        assert!(cached(&mut cache, "Example.COM.", Instant::from_secs(129)));
+        assert!(!cached(&mut cache, "example.com", Instant::from_secs(130)));
+
+        // Long TTLs are capped.
+        resolve(&mut cache, "example.com", 0x1235, u32::MAX >> 1, Instant::from_secs(0));
+        assert!(cached(
+            &mut cache,
+            "example.com",
+            Instant::from_secs(MAX_TTL as u64 - 1)
+        ));
+        assert!(!cached(&mut cache, "example.com", Instant::from_secs(MAX_TTL as u64)));
+
+        // A TTL of zero isn't cached.
+        resolve(&mut cache, "example.com", 0x1236, 0, Instant::from_secs(0));
+        assert!(!cached(&mut cache, "example.com", Instant::from_secs(0)));
+    }
+
+    #[test]
+    fn unsolicited_responses() {
+        let response = |src, dst, id| datagram(src, dst, &message(id, "example.com", Some(300)));
+        let other = Ipv4Address::new(10, 0, 0, 3);
+        let spoofed = [
+            // Another transaction ID.
+            response((SERVER, DNS_PORT), (HOST, PORT), 0x4321),
+            // Another server.
+            response((other, DNS_PORT), (HOST, PORT), 0x1234),
+            // Another port.
+            response((SERVER, DNS_PORT), (HOST, PORT + 1), 0x1234),
+            response((SERVER, 5353), (HOST, PORT), 0x1234),
+        ];
+
+        let mut cache = Cache::new();
+        for frame in &spoofed {
+            cache.start("example.com", DnsQueryType::A);
+            let query = datagram((HOST, PORT), (SERVER, DNS_PORT), &message(0x1234, "example.com", None));
+            cache.transmitted(&query, Medium::Ip);
+            cache.received(frame, Medium::Ip);
+            cache.complete("example.com", DnsQueryType::A, &addrs(), Instant::from_secs(0));
+            assert!(!cached(&mut cache, "example.com", Instant::from_secs(0)));
+        }
+
+        // Responses to queries which weren't seen.
+        cache.start("example.com", DnsQueryType::A);
+        cache.received(&response((SERVER, DNS_PORT), (HOST, PORT), 0x1234), Medium::Ip);
+        cache.complete("example.com", DnsQueryType::A, &addrs(), Instant::from_secs(0));
+        assert!(!cached(&mut cache, "example.com", Instant::from_secs(0)));
+    }
+
+    #[test]
+    fn eviction() {
+        let name = |i: u64| std::format!("host{}.example.com", i);
+        let mut cache = Cache::new();
+        for i in 0..CACHE_SIZE as u64 {
+            resolve(&mut cache, &name(i), i as u16, 100 - i as u32, Instant::from_secs(0));
+        }
+        // The answer expiring first makes room.
+        resolve(&mut cache, "example.com", 100, 60, Instant::from_secs(0));
+        assert!(cached(&mut cache, "example.com", Instant::from_secs(0)));
+        let evicted = CACHE_SIZE as u64 - 1;
+        assert!(!cached(&mut cache, &name(evicted), Instant::from_secs(0)));
+        assert!((0..evicted).all(|i| cached(&mut cache, &name(i), Instant::from_secs(0))));
+
+        // When all queries are in flight, others aren't cached.
+        let mut cache = Cache::new();
+        for i in 0..CACHE_SIZE as u64 {
+            cache.start(&name(i), DnsQueryType::A);
+        }
+        resolve(&mut cache, "example.com", 100, 60, Instant::from_secs(0));
+        assert!(!cached(&mut cache, "example.com", Instant::from_secs(0)));
+    }
+
+    #[test]
+    fn flush() {
+        let mut cache = Cache::new();
+        resolve(&mut cache, "example.com", 1, 60, Instant::from_secs(0));
+        cache.start("example.org", DnsQueryType::A);
+        cache.flush();
+        assert!(!cached(&mut cache, "example.com", Instant::from_secs(0)));
+
+        // Queries in flight are still cached.
+        let query = datagram((HOST, PORT), (SERVER, DNS_PORT), &message(2, "example.org", None));
+        cache.transmitted(&query, Medium::Ip);
+        let response = datagram((SERVER, DNS_PORT), (HOST, PORT), &message(2, "example.org", Some(60)));
+        cache.received(&response, Medium::Ip);
+        cache.complete("example.org", DnsQueryType::A, &addrs(), Instant::from_secs(0));
+        assert!(cached(&mut cache, "example.org", Instant::from_secs(0)));
+    }
+}
@@ -0,0 +1,422 @@
+//! DNS server.
+//!
+//! Answers queries from a static host table, and optionally gives one address for every other
+//! name. The latter is what captive portals do: when the device runs as a Wi-Fi access point
+//! (for example `cyw43` in AP mode) for provisioning, clients are sent to its web server
+//! whatever name they look up. Hand out the server's address as DNS server with the
+//! [`dhcp_server`](crate::dhcp_server) so clients use it.
+//!
+//! ```ignore
+//! use embassy_net::dns_server::{Config, DnsServer};
+//! use embassy_net::udp::{PacketMetadata, UdpSocket};
+//! use embassy_net::Ipv4Address;
+//!
+//! let mut rx_meta = [PacketMetadata::EMPTY; 4];
+//! let mut rx_buffer = [0; 1024];
+//! let mut tx_meta = [PacketMetadata::EMPTY; 4];
+//! let mut tx_buffer = [0; 1024];
+//! let socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+//!
+//! let config = Config::captive(Ipv4Address::new(192, 168, 4, 1).into());
+//! let mut server = DnsServer::new(socket, config).unwrap();
+//! server.run().await;
+//! ```
+
+use smoltcp::wire::{DnsFlags, DnsOpcode, DnsPacket, DnsQueryType, DnsQuestion, DnsRcode};
+
+use crate::udp::{BindError, RecvError, UdpSocket};
+use crate::IpAddress;
+
+const DNS_PORT: u16 = 53;
+/// Largest message without EDNS (RFC 1035 section 4.2.1).
+const MAX_MESSAGE_LEN: usize = 512;
+const HEADER_LEN: usize = 12;
+const CLASS_IN: u16 = 1;
+const TYPE_ANY: u16 = 255;
+/// Compression pointer to the name of the question, right after the header.
+const QUESTION_NAME_PTR: [u8; 2] = [0xc0, HEADER_LEN as u8];
+
+/// A host name and one of its addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Host<'a> {
+    /// Name, without the trailing dot. Names are compared ignoring case.
+    pub name: &'a str,
+    /// Address answered for `name`, as an A or AAAA record.
+    pub address: IpAddress,
+}
+
+impl<'a> Host<'a> {
+    /// Create a host entry.
+    pub const fn new(name: &'a str, address: IpAddress) -> Self {
+        Self { name, address }
+    }
+}
+
+/// DNS server configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Config<'a> {
+    /// Static host table. A name may appear several times, once per address.
+    pub hosts: &'a [Host<'a>],
+    /// Address given for every name not in `hosts`, for captive portals.
+    ///
+    /// Only queries for the same address family are answered, other ones get an empty answer
+    /// so clients fall back to it. Without it, other names don't exist.
+    pub catch_all: Option<IpAddress>,
+    /// Time to live of the answers, in seconds.
+    pub ttl: u32,
+}
+
+impl<'a> Config<'a> {
+    /// Create a configuration answering from `hosts` only.
+    pub const fn hosts(hosts: &'a [Host<'a>]) -> Self {
+        Self {
+            hosts,
+            catch_all: None,
+            ttl: 60,
+        }
+    }
+
+    /// Create a configuration answering `address` for every name, for captive portals.
+    pub const fn captive(address: IpAddress) -> Self {
+        Self {
+            hosts: &[],
+            catch_all: Some(address),
+            ttl: 60,
+        }
+    }
+
+    /// Write the response to `query` in `out`, returning its length.
+    fn answer(&self, query: &[u8], out: &mut [u8; MAX_MESSAGE_LEN]) -> Option<usize> {
+        let packet = DnsPacket::new_checked(query).ok()?;
+        if packet.flags().contains(DnsFlags::RESPONSE) {
+            return None;
+        }
+        let recursion_desired = packet.flags() & DnsFlags::RECURSION_DESIRED;
+        let mut response = Response::new(out, packet.transaction_id(), recursion_desired);
+
+        if packet.opcode() != DnsOpcode::Query {
+            return Some(response.finish(DnsRcode::NotImp));
+        }
+        if packet.question_count() != 1 {
+            return Some(response.finish(DnsRcode::FormErr));
+        }
+        let Ok((_, question)) = DnsQuestion::parse(packet.payload()) else {
+            return Some(response.finish(DnsRcode::FormErr));
+        };
+        // Names are only compressed with pointers to earlier ones, there are none in a query.
+        let Some(name) = Labels::new(question.name) else {
+            return Some(response.finish(DnsRcode::FormErr));
+        };
+        response.question(question.name, question.type_);
+
+        let qtype = u16::from(question.type_);
+        let mut hosts = self.hosts.iter().filter(|h| name.eq(h.name)).peekable();
+        let rcode = match (hosts.peek(), self.catch_all) {
+            (Some(_), _) => {
+                for host in hosts.filter(|h| qtype == TYPE_ANY || qtype == record_type(h.address)) {
+                    response.record(host.address, self.ttl);
+                }
+                DnsRcode::NoError
+            }
+            (None, Some(address)) => {
+                if qtype == TYPE_ANY || qtype == record_type(address) {
+                    response.record(address, self.ttl);
+                }
+                DnsRcode::NoError
+            }
+            (None, None) => DnsRcode::NXDomain,
+        };
+        Some(response.finish(rcode))
+    }
+}
+
+/// A DNS server.
+pub struct DnsServer<'a> {
+    socket: UdpSocket<'a>,
+    config: Config<'a>,
+}
+
+impl<'a> DnsServer<'a> {
+    /// Create a server, binding `socket` to the DNS port.
+    pub fn new(mut socket: UdpSocket<'a>, config: Config<'a>) -> Result<Self, BindError> {
+        socket.bind(DNS_PORT)?;
+        Ok(Self { socket, config })
+    }
+
+    /// Get the configuration.
+    pub fn config(&self) -> &Config<'a> {
+        &self.config
+    }
+
+    /// Answer queries forever.
+    pub async fn run(&mut self) -> ! {
+        let mut buf = [0; MAX_MESSAGE_LEN];
+        let mut out = [0; MAX_MESSAGE_LEN];
+        loop {
+            let (n, meta) = match self.socket.recv_from(&mut buf).await {
+                Ok(r) => r,
+                Err(RecvError::Truncated) => {
+                    debug!("DNS server: message too long");
+                    continue;
+                }
+            };
+            let Some(len) = self.config.answer(&buf[..n], &mut out) else {
+                continue;
+            };
+            if let Err(e) = self.socket.send_to(&out[..len], meta.endpoint).await {
+                warn!("DNS server: send error: {:?}", e);
+            }
+        }
+    }
+}
+
+/// A DNS response, with up to one question and answers to it.
+struct Response<'b> {
+    buf: &'b mut [u8; MAX_MESSAGE_LEN],
+    len: usize,
+    flags: u16,
+    questions: u16,
+    answers: u16,
+}
+
+impl<'b> Response<'b> {
+    fn new(buf: &'b mut [u8; MAX_MESSAGE_LEN], id: u16, recursion_desired: DnsFlags) -> Self {
+        buf[..2].copy_from_slice(&id.to_be_bytes());
+        Self {
+            buf,
+            len: HEADER_LEN,
+            flags: (DnsFlags::RESPONSE | DnsFlags::AUTHORITATIVE | recursion_desired).bits(),
+            questions: 0,
+            answers: 0,
+        }
+    }
+
+    fn question(&mut self, name: &[u8], qtype: DnsQueryType) {
+        let end = self.len + name.len() + 4;
+        self.buf[self.len..end - 4].copy_from_slice(name);
+        self.buf[end - 4..end - 2].copy_from_slice(&u16::from(qtype).to_be_bytes());
+        self.buf[end - 2..end].copy_from_slice(&CLASS_IN.to_be_bytes());
+        self.len = end;
+        self.questions = 1;
+    }
+
+    /// Add an answer record for the name of the question, truncating the response if it's full.
+    fn record(&mut self, address: IpAddress, ttl: u32) {
+        let data_len = match address {
+            #[cfg(feature = "proto-ipv4")]
+            IpAddress::Ipv4(_) => 4,
+            #[cfg(feature = "proto-ipv6")]
+            IpAddress::Ipv6(_) => 16,
+        };
+        let Some(record) = self.buf.get_mut(self.len..self.len + 12 + data_len) else {
+            self.flags |= DnsFlags::TRUNCATED.bits();
+            return;
+        };
+        record[0..2].copy_from_slice(&QUESTION_NAME_PTR);
+        record[2..4].copy_from_slice(&record_type(address).to_be_bytes());
+        record[4..6].copy_from_slice(&CLASS_IN.to_be_bytes());
+        record[6..10].copy_from_slice(&ttl.to_be_bytes());
+        record[10..12].copy_from_slice(&(data_len as u16).to_be_bytes());
+        match address {
+            #[cfg(feature = "proto-ipv4")]
+            IpAddress::Ipv4(a) => record[12..].copy_from_slice(&a.octets()),
+            #[cfg(feature = "proto-ipv6")]
+            IpAddress::Ipv6(a) => record[12..].copy_from_slice(&a.octets()),
+        }
+        self.len += record.len();
+        self.answers += 1;
+    }
+
+    /// Write the header, returning the length of the response.
+    fn finish(self, rcode: DnsRcode) -> usize {
+        let flags = self.flags | u8::from(rcode) as u16;
+        self.buf[2..4].copy_from_slice(&flags.to_be_bytes());
+        self.buf[4..6].copy_from_slice(&self.questions.to_be_bytes());
+        self.buf[6..8].copy_from_slice(&self.answers.to_be_bytes());
+        self.buf[8..12].fill(0);
+        self.len
+    }
+}
+
+/// An uncompressed name in wire format.
+struct Labels<'m>(&'m [u8]);
+
+impl<'m> Labels<'m> {
+    /// Check that `name` has no compression pointers.
+    fn new(name: &'m [u8]) -> Option<Self> {
+        let mut pos = 0;
+        loop {
+            match *name.get(pos)? {
+                0 => return (pos + 1 == name.len()).then_some(Self(name)),
+                len if len & 0xc0 == 0 => pos += 1 + len as usize,
+                _ => return None,
+            }
+        }
+    }
+
+    /// Compare with a dotted name, ignoring case and a trailing dot.
+    fn eq(&self, name: &str) -> bool {
+        let name = name.strip_suffix('.').unwrap_or(name);
+        let mut wire = self.0;
+        for label in name.split('.').filter(|_| !name.is_empty()) {
+            let Some((&len, rest)) = wire.split_first() else {
+                return false;
+            };
+            match rest.get(..len as usize) {
+                Some(l) if l.eq_ignore_ascii_case(label.as_bytes()) => wire = &rest[len as usize..],
+                _ => return false,
+            }
+        }
+        wire == [0]
+    }
+}
+
+fn record_type(address: IpAddress) -> u16 {
+    match address {
+        #[cfg(feature = "proto-ipv4")]
+        IpAddress::Ipv4(_) => DnsQueryType::A.into(),
+        #[cfg(feature = "proto-ipv6")]
+        IpAddress::Ipv6(_) => DnsQueryType::Aaaa.into(),
+    }
+}
+
+#[cfg(all(test, feature = "proto-ipv4"))]
+mod tests {
+    use std::vec::Vec;
+
+    use smoltcp::wire::Ipv4Address;
+
+    use super::*;
+
+    const TYPE_A: u16 = 1;
+    const TYPE_AAAA: u16 = 28;
+    const PORTAL: Ipv4Address = Ipv4Address::new(192, 168, 4, 1);
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut wire = Vec::new();
+        for label in name.split('.') {
+            wire.push(label.len() as u8);
+            wire.extend_from_slice(label.as_bytes());
+        }
+        wire.push(0);
+        wire
+    }
+
+    /// A query with the recursion desired flag, and `questions` in wire format.
+    fn query(questions: &[(&[u8], u16)]) -> Vec<u8> {
+        let mut query = Vec::new();
+        for field in [0xbeef, 0x0100, questions.len() as u16, 0, 0, 0] {
+            query.extend_from_slice(&u16::to_be_bytes(field));
+        }
+        for (name, qtype) in questions {
+            query.extend_from_slice(name);
+            query.extend_from_slice(&qtype.to_be_bytes());
+            query.extend_from_slice(&CLASS_IN.to_be_bytes());
+        }
+        query
+    }
+
+    struct Answer {
+        truncated: bool,
+        rcode: DnsRcode,
+        addresses: Vec<[u8; 4]>,
+    }
+
+    fn answer(config: &Config<'_>, query: &[u8]) -> Answer {
+        let mut out = [0; MAX_MESSAGE_LEN];
+        let len = config.answer(query, &mut out).unwrap();
+        let packet = DnsPacket::new_checked(&out[..len]).unwrap();
+        assert_eq!(packet.transaction_id(), 0xbeef);
+        let flags = packet.flags();
+        assert!(flags.contains(DnsFlags::RESPONSE | DnsFlags::AUTHORITATIVE | DnsFlags::RECURSION_DESIRED));
+
+        let mut rest = packet.payload();
+        for _ in 0..packet.question_count() {
+            rest = DnsQuestion::parse(rest).unwrap().0;
+        }
+        let mut addresses = Vec::new();
+        for _ in 0..packet.answer_record_count() {
+            let (next, record) = smoltcp::wire::DnsRecord::parse(rest).unwrap();
+            assert_eq!(record.ttl, config.ttl);
+            match record.data {
+                smoltcp::wire::DnsRecordData::A(address) => addresses.push(address.octets()),
+                _ => panic!("not an A record"),
+            }
+            rest = next;
+        }
+        assert!(rest.is_empty());
+        Answer {
+            truncated: flags.contains(DnsFlags::TRUNCATED),
+            rcode: packet.rcode(),
+            addresses,
+        }
+    }
+
+    #[test]
+    fn hosts() {
+        let hosts = [
+            Host::new("printer.lan", Ipv4Address::new(10, 0, 0, 9).into()),
+            Host::new("nas.lan", Ipv4Address::new(10, 0, 0, 7).into()),
+            Host::new("nas.lan", Ipv4Address::new(10, 0, 0, 8).into()),
+        ];
+        let config = Config::hosts(&hosts);
+
+        let found = answer(&config, &query(&[(&name("NAS.lan"), TYPE_A)]));
+        assert_eq!(found.rcode, DnsRcode::NoError);
+        core::assert_eq!(found.addresses, [[10, 0, 0, 7], [10, 0, 0, 8]]);
+        assert!(!found.truncated);
+
+        // The name exists, without addresses of this family.
+        let other_family = answer(&config, &query(&[(&name("nas.lan"), TYPE_AAAA)]));
+        assert_eq!(
+            (other_family.rcode, other_family.addresses.len()),
+            (DnsRcode::NoError, 0)
+        );
+
+        let unknown = answer(&config, &query(&[(&name("example.com"), TYPE_A)]));
+        assert_eq!((unknown.rcode, unknown.addresses.len()), (DnsRcode::NXDomain, 0));
+    }
+
+    #[test]
+    fn catch_all() {
+        let config = Config::captive(PORTAL.into());
+        let a = answer(&config, &query(&[(&name("connectivitycheck.gstatic.com"), TYPE_A)]));
+        core::assert_eq!((a.rcode, a.addresses), (DnsRcode::NoError, vec![PORTAL.octets()]));
+
+        // Queries for the other family get an empty answer, not NXDOMAIN.
+        let aaaa = answer(&config, &query(&[(&name("example.com"), TYPE_AAAA)]));
+        assert_eq!((aaaa.rcode, aaaa.addresses.len()), (DnsRcode::NoError, 0));
+    }
+
+    #[test]
+    fn format_errors() {
+        let config = Config::captive(PORTAL.into());
+        let compressed = [0xc0, 12];
+        assert_eq!(
+            answer(&config, &query(&[(&compressed, TYPE_A)])).rcode,
+            DnsRcode::FormErr
+        );
+        let two = query(&[(&name("a.lan"), TYPE_A), (&name("b.lan"), TYPE_A)]);
+        assert_eq!(answer(&config, &two).rcode, DnsRcode::FormErr);
+        assert_eq!(answer(&config, &query(&[])).rcode, DnsRcode::FormErr);
+
+        // Responses aren't answered.
+        let mut response = query(&[(&name("a.lan"), TYPE_A)]);
+        response[2] |= 0x80;
+        assert!(config.answer(&response, &mut [0; MAX_MESSAGE_LEN]).is_none());
+    }
+
+    #[test]
+    fn truncated() {
+        let hosts: Vec<_> = (0..40)
+            .map(|i| Host::new("pool.lan", Ipv4Address::new(10, 0, 1, i).into()))
+            .collect();
+        let config = Config::hosts(&hosts);
+        let query = query(&[(&name("pool.lan"), TYPE_A)]);
+        let truncated = answer(&config, &query);
+        assert!(truncated.truncated);
+        // The header, the question, and as many records of 16 bytes as fit.
+        assert_eq!(truncated.addresses.len(), (MAX_MESSAGE_LEN - query.len()) / 16);
+    }
+}
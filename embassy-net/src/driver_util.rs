@@ -1,9 +1,25 @@
+#[cfg(any(feature = "statistics", feature = "dns-cache"))]
+use core::cell::RefCell;
+use core::marker::PhantomData;
 use core::task::Context;
 
 use embassy_net_driver::{Capabilities, Checksum, Driver, RxToken, TxToken};
 use smoltcp::phy::{self, Medium};
 use smoltcp::time::Instant;
 
+#[cfg(feature = "autoip")]
+use crate::autoip::AutoIp;
+#[cfg(feature = "dns-cache")]
+use crate::dns::Cache as DnsCache;
+#[cfg(feature = "statistics-inspect")]
+use crate::stats::Inspect;
+#[cfg(feature = "statistics")]
+use crate::stats::{Recorder, Tap};
+
+/// Hook called with each received frame. Returns `true` if it forwarded the frame to another interface.
+#[cfg(feature = "forwarding")]
+pub(crate) type ForwardFn<'a> = dyn FnMut(&mut [u8]) -> bool + 'a;
+
 pub(crate) struct DriverAdapter<'d, 'c, T>
 where
     T: Driver,
@@ -12,30 +28,68 @@ where
     pub cx: Option<&'d mut Context<'c>>,
     pub inner: &'d mut T,
     pub medium: Medium,
+    #[cfg(feature = "statistics")]
+    pub stats: Option<&'d RefCell<Recorder>>,
+    /// Link-local address autoconfiguration, watching received ARP packets.
+    #[cfg(feature = "autoip")]
+    pub autoip: Option<&'d mut AutoIp>,
+    /// DNS cache, watching transmitted queries and received responses for their TTL.
+    #[cfg(feature = "dns-cache")]
+    pub dns_cache: Option<&'d RefCell<DnsCache>>,
 }
 
-impl<'d, 'c, T> phy::Device for DriverAdapter<'d, 'c, T>
+impl<T> phy::Device for DriverAdapter<'_, '_, T>
 where
     T: Driver,
 {
     type RxToken<'a>
-        = RxTokenAdapter<T::RxToken<'a>>
+        = RxTokenAdapter<'a, T::RxToken<'a>>
     where
         Self: 'a;
     type TxToken<'a>
-        = TxTokenAdapter<T::TxToken<'a>>
+        = TxTokenAdapter<'a, T::TxToken<'a>>
     where
         Self: 'a;
 
     fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
-        self.inner
-            .receive(unwrap!(self.cx.as_deref_mut()))
-            .map(|(rx, tx)| (RxTokenAdapter(rx), TxTokenAdapter(tx)))
+        #[cfg(feature = "statistics")]
+        let stats = self.tap(_timestamp);
+        let (rx, tx) = self.inner.receive(unwrap!(self.cx.as_deref_mut()))?;
+        let rx = RxTokenAdapter {
+            inner: rx,
+            #[cfg(feature = "statistics")]
+            stats,
+            #[cfg(feature = "autoip")]
+            autoip: self.autoip.as_deref_mut(),
+            #[cfg(feature = "dns-cache")]
+            dns_cache: self.dns_cache.map(|c| (c, self.medium)),
+            _phantom: PhantomData,
+        };
+        let tx = TxTokenAdapter {
+            inner: tx,
+            #[cfg(feature = "statistics")]
+            stats,
+            #[cfg(feature = "dns-cache")]
+            dns_cache: self.dns_cache.map(|c| (c, self.medium)),
+            _phantom: PhantomData,
+        };
+        Some((rx, tx))
     }
 
     /// Construct a transmit token.
     fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
-        self.inner.transmit(unwrap!(self.cx.as_deref_mut())).map(TxTokenAdapter)
+        #[cfg(feature = "statistics")]
+        let stats = self.tap(_timestamp);
+        self.inner
+            .transmit(unwrap!(self.cx.as_deref_mut()))
+            .map(|inner| TxTokenAdapter {
+                inner,
+                #[cfg(feature = "statistics")]
+                stats,
+                #[cfg(feature = "dns-cache")]
+                dns_cache: self.dns_cache.map(|c| (c, self.medium)),
+                _phantom: PhantomData,
+            })
     }
 
     /// Get a description of device capabilities.
@@ -70,11 +124,82 @@ where
     }
 }
 
-pub(crate) struct RxTokenAdapter<T>(T)
+#[cfg(feature = "statistics")]
+impl<'d, T> DriverAdapter<'d, '_, T>
+where
+    T: Driver,
+{
+    fn tap(&self, _timestamp: Instant) -> Option<Tap<'d>> {
+        let recorder = self.stats?;
+        Some(Tap::new(
+            recorder,
+            #[cfg(feature = "statistics-inspect")]
+            Inspect::new(
+                self.medium,
+                &self.inner.capabilities().checksum,
+                crate::time::instant_from_smoltcp(_timestamp),
+            ),
+        ))
+    }
+}
+
+pub(crate) struct RxTokenAdapter<'a, T>
 where
-    T: RxToken;
+    T: RxToken,
+{
+    inner: T,
+    #[cfg(feature = "statistics")]
+    stats: Option<Tap<'a>>,
+    #[cfg(feature = "autoip")]
+    autoip: Option<&'a mut AutoIp>,
+    #[cfg(feature = "dns-cache")]
+    dns_cache: Option<(&'a RefCell<DnsCache>, Medium)>,
+    _phantom: PhantomData<&'a ()>,
+}
 
-impl<T> phy::RxToken for RxTokenAdapter<T>
+impl<T> RxTokenAdapter<'_, T>
+where
+    T: RxToken,
+{
+    /// Consume the frame with `f`, unless `forward` forwards it to another interface.
+    ///
+    /// Forwarded frames are only counted in the statistics, the other hooks don't see them.
+    #[cfg(feature = "forwarding")]
+    pub fn consume_unless_forwarded<R>(self, forward: &mut ForwardFn<'_>, f: impl FnOnce(&[u8]) -> R) -> Option<R> {
+        #[cfg(feature = "statistics")]
+        let stats = self.stats;
+        #[cfg(feature = "autoip")]
+        let autoip = self.autoip;
+        #[cfg(feature = "dns-cache")]
+        let dns_cache = self.dns_cache;
+        self.inner.consume(|buf| {
+            #[cfg(feature = "packet-trace")]
+            trace!("embassy device rx: {:02x}", buf);
+            if forward(buf) {
+                #[cfg(feature = "statistics")]
+                if let Some(stats) = stats {
+                    stats.forwarded(buf);
+                }
+                return None;
+            }
+            #[cfg(feature = "statistics")]
+            if let Some(stats) = stats {
+                stats.received(buf);
+            }
+            #[cfg(feature = "autoip")]
+            if let Some(autoip) = autoip {
+                autoip.received(buf);
+            }
+            #[cfg(feature = "dns-cache")]
+            if let Some((dns_cache, medium)) = dns_cache {
+                dns_cache.borrow_mut().received(buf, medium);
+            }
+            Some(f(buf))
+        })
+    }
+}
+
+impl<T> phy::RxToken for RxTokenAdapter<'_, T>
 where
     T: RxToken,
 {
@@ -82,19 +207,45 @@ where
     where
         F: FnOnce(&[u8]) -> R,
     {
-        self.0.consume(|buf| {
+        #[cfg(feature = "statistics")]
+        let stats = self.stats;
+        #[cfg(feature = "autoip")]
+        let autoip = self.autoip;
+        #[cfg(feature = "dns-cache")]
+        let dns_cache = self.dns_cache;
+        self.inner.consume(|buf| {
             #[cfg(feature = "packet-trace")]
             trace!("embassy device rx: {:02x}", buf);
+            #[cfg(feature = "statistics")]
+            if let Some(stats) = stats {
+                stats.received(buf);
+            }
+            #[cfg(feature = "autoip")]
+            if let Some(autoip) = autoip {
+                autoip.received(buf);
+            }
+            #[cfg(feature = "dns-cache")]
+            if let Some((dns_cache, medium)) = dns_cache {
+                dns_cache.borrow_mut().received(buf, medium);
+            }
             f(buf)
         })
     }
 }
 
-pub(crate) struct TxTokenAdapter<T>(T)
+pub(crate) struct TxTokenAdapter<'a, T>
 where
-    T: TxToken;
+    T: TxToken,
+{
+    inner: T,
+    #[cfg(feature = "statistics")]
+    stats: Option<Tap<'a>>,
+    #[cfg(feature = "dns-cache")]
+    dns_cache: Option<(&'a RefCell<DnsCache>, Medium)>,
+    _phantom: PhantomData<&'a ()>,
+}
 
-impl<T> phy::TxToken for TxTokenAdapter<T>
+impl<T> phy::TxToken for TxTokenAdapter<'_, T>
 where
     T: TxToken,
 {
@@ -102,11 +253,85 @@ where
     where
         F: FnOnce(&mut [u8]) -> R,
     {
-        self.0.consume(len, |buf| {
+        #[cfg(feature = "statistics")]
+        let stats = self.stats;
+        #[cfg(feature = "dns-cache")]
+        let dns_cache = self.dns_cache;
+        self.inner.consume(len, |buf| {
             let r = f(buf);
             #[cfg(feature = "packet-trace")]
             trace!("embassy device tx: {:02x}", buf);
+            #[cfg(feature = "statistics")]
+            if let Some(stats) = stats {
+                stats.transmitted(buf);
+            }
+            #[cfg(feature = "dns-cache")]
+            if let Some((dns_cache, medium)) = dns_cache {
+                dns_cache.borrow_mut().transmitted(buf, medium);
+            }
             r
         })
     }
 }
+
+/// Device giving smoltcp a frame that has already been received, along with the token to answer it.
+#[cfg(feature = "forwarding")]
+pub(crate) struct Received<'f, T> {
+    frame: Option<&'f [u8]>,
+    tx: Option<T>,
+    caps: phy::DeviceCapabilities,
+}
+
+#[cfg(feature = "forwarding")]
+impl<'f, T> Received<'f, T>
+where
+    T: phy::TxToken,
+{
+    pub fn new(frame: &'f [u8], tx: T, caps: phy::DeviceCapabilities) -> Self {
+        Self {
+            frame: Some(frame),
+            tx: Some(tx),
+            caps,
+        }
+    }
+}
+
+#[cfg(feature = "forwarding")]
+impl<'f, T> phy::Device for Received<'f, T>
+where
+    T: phy::TxToken,
+{
+    type RxToken<'a>
+        = ReceivedToken<'f>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = T
+    where
+        Self: 'a;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        Some((ReceivedToken(self.frame.take()?), self.tx.take()?))
+    }
+
+    fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        None
+    }
+
+    fn capabilities(&self) -> phy::DeviceCapabilities {
+        self.caps.clone()
+    }
+}
+
+#[cfg(feature = "forwarding")]
+pub(crate) struct ReceivedToken<'f>(&'f [u8]);
+
+#[cfg(feature = "forwarding")]
+impl phy::RxToken for ReceivedToken<'_> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        f(self.0)
+    }
+}
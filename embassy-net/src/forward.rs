@@ -0,0 +1,624 @@
+//! IPv4 forwarding between interfaces, with optional NAT.
+//!
+//! Forwarding is enabled with [`Stack::enable_forwarding`](crate::Stack::enable_forwarding). IPv4 packets
+//! received on an interface that aren't for the stack itself are sent out through the interface selected
+//! by the routing table, see [`Stack::route`](crate::Stack::route).
+//!
+//! With [`Config::masquerade`], connections leaving through one interface (e.g. an LTE or PPP uplink) are
+//! translated to the address of that interface (NAPT), so that hosts on the other interfaces (e.g. a Wi-Fi
+//! access point or a USB-NCM link) share it. Translated connections are tracked in a fixed size table,
+//! and expire after a protocol dependent timeout.
+//!
+//! Only TCP, UDP and ICMP are forwarded, and fragmented packets are not. Translated ICMP is limited to
+//! echo requests and replies. No ICMP errors are generated, packets that can't be forwarded are dropped.
+//!
+//! Packets wait to be sent in buffers of the [`Resources`], through raw sockets that only take a socket
+//! slot of the egress interface while they have packets to send.
+
+use core::mem::MaybeUninit;
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use smoltcp::config::IFACE_MAX_ADDR_COUNT;
+use smoltcp::iface::{SocketHandle, SocketSet};
+use smoltcp::socket::{raw, Socket};
+#[cfg(feature = "medium-ethernet")]
+use smoltcp::wire::{EthernetFrame, EthernetProtocol};
+use smoltcp::wire::{
+    Icmpv4Message, Icmpv4Packet, IpAddress, IpCidr, IpProtocol, IpVersion, Ipv4Address, Ipv4Packet, TcpPacket,
+    UdpPacket,
+};
+
+use crate::{HardwareAddress, Iface, InterfaceId, Route, MAX_INTERFACES};
+
+/// Packets queued for forwarding through an interface, per protocol.
+const QUEUE_META: usize = 4;
+
+/// First port used for translated connections.
+const NAT_PORT_MIN: u16 = 49152;
+/// Number of ports used for translated connections.
+const NAT_PORT_COUNT: usize = (u16::MAX - NAT_PORT_MIN) as usize + 1;
+
+/// Forwarding configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Config {
+    /// Translate connections leaving through this interface to its IPv4 address (NAPT).
+    ///
+    /// If `None`, packets are forwarded unchanged.
+    pub masquerade: Option<InterfaceId>,
+    /// Timeout of established TCP connections.
+    pub tcp_timeout: Duration,
+    /// Timeout of TCP connections that are being opened or closed.
+    pub tcp_transitory_timeout: Duration,
+    /// Timeout of UDP connections.
+    pub udp_timeout: Duration,
+    /// Timeout of ICMP echo queries.
+    pub icmp_timeout: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        // Timeouts from RFC 5382 (TCP), RFC 4787 (UDP) and RFC 5508 (ICMP).
+        Self {
+            masquerade: None,
+            tcp_timeout: Duration::from_secs(2 * 60 * 60 + 4 * 60),
+            tcp_transitory_timeout: Duration::from_secs(4 * 60),
+            udp_timeout: Duration::from_secs(2 * 60),
+            icmp_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Config {
+    /// Forward packets, translating connections leaving through `interface` to its address.
+    pub fn masquerade(interface: InterfaceId) -> Self {
+        Self {
+            masquerade: Some(interface),
+            ..Default::default()
+        }
+    }
+}
+
+/// Memory resources needed for forwarding.
+///
+/// `CONN` is the number of connections that can be translated at the same time. `QUEUE` is the size
+/// of the buffers packets wait in until they're sent, one per interface and protocol.
+pub struct Resources<const CONN: usize, const QUEUE: usize = 1536> {
+    connections: MaybeUninit<[Option<Connection>; CONN]>,
+    queues: MaybeUninit<[[QueueBuffer<QUEUE>; 3]; MAX_INTERFACES]>,
+}
+
+impl<const CONN: usize, const QUEUE: usize> Resources<CONN, QUEUE> {
+    /// Create a new set of forwarding resources.
+    pub const fn new() -> Self {
+        Self {
+            connections: MaybeUninit::uninit(),
+            queues: MaybeUninit::uninit(),
+        }
+    }
+}
+
+impl<const CONN: usize, const QUEUE: usize> Default for Resources<CONN, QUEUE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct QueueBuffer<const N: usize> {
+    meta: [raw::PacketMetadata; QUEUE_META],
+    buffer: [u8; N],
+}
+
+const PROTOCOLS: [IpProtocol; 3] = [IpProtocol::Tcp, IpProtocol::Udp, IpProtocol::Icmp];
+
+/// Raw socket an interface sends the forwarded packets of one protocol with.
+///
+/// It's only in the socket set of the interface while it has packets to send: smoltcp gives raw
+/// sockets all the packets of their protocol, and doesn't answer the UDP datagrams they got with
+/// ICMP port unreachable errors.
+struct Queue {
+    /// The socket, while it's not in the socket set.
+    idle: Option<raw::Socket<'static>>,
+    handle: Option<SocketHandle>,
+}
+
+impl Queue {
+    fn new<const N: usize>(protocol: IpProtocol, buffer: &'static mut QueueBuffer<N>) -> Self {
+        // Forwarding only sends, so the receive buffers are empty. Packets for the stack are
+        // still delivered to its own sockets.
+        let socket = raw::Socket::new(
+            IpVersion::Ipv4,
+            protocol,
+            raw::PacketBuffer::new(&mut [][..], &mut [][..]),
+            raw::PacketBuffer::new(&mut buffer.meta[..], &mut buffer.buffer[..]),
+        );
+        Self {
+            idle: Some(socket),
+            handle: None,
+        }
+    }
+
+    /// Queue `packet`, adding the socket to `sockets` if needed.
+    fn send(&mut self, sockets: &mut SocketSet<'static>, capacity: usize, packet: &[u8]) -> Result<(), QueueError> {
+        let handle = match self.handle {
+            Some(handle) => handle,
+            None if sockets.iter().count() < capacity => *self.handle.insert(sockets.add(unwrap!(self.idle.take()))),
+            None => return Err(QueueError::NoSocket),
+        };
+        match sockets.get_mut::<raw::Socket>(handle).send_slice(packet) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(QueueError::Full),
+        }
+    }
+
+    /// Take the socket out of `sockets`, if it has sent all its packets or `force` is set.
+    fn release(&mut self, sockets: &mut SocketSet<'static>, force: bool) {
+        let Some(handle) = self.handle else {
+            return;
+        };
+        if !force && sockets.get::<raw::Socket>(handle).send_queue() != 0 {
+            return;
+        }
+        self.handle = None;
+        match sockets.remove(handle) {
+            Socket::Raw(socket) => self.idle = Some(socket),
+            #[allow(unreachable_patterns)]
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Why a packet couldn't be queued.
+enum QueueError {
+    /// The interface has no free socket slot for the queue.
+    NoSocket,
+    /// The queue is full.
+    Full,
+}
+
+/// A translated connection.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Connection {
+    protocol: IpProtocol,
+    inside_addr: Ipv4Address,
+    /// Port, or ICMP echo identifier.
+    inside_port: u16,
+    remote_addr: Ipv4Address,
+    /// Port, or 0 for ICMP.
+    remote_port: u16,
+    outside_port: u16,
+    expires_at: Instant,
+    /// A reply has been seen.
+    established: bool,
+    /// A TCP FIN or RST has been seen.
+    closing: bool,
+}
+
+/// Forwarding state of the stack.
+pub(crate) struct Forwarding {
+    config: Config,
+    connections: &'static mut [Option<Connection>],
+    /// Packets forwarded through each interface, per protocol.
+    queues: [[Queue; 3]; MAX_INTERFACES],
+}
+
+impl Forwarding {
+    pub(crate) fn new<const CONN: usize, const QUEUE: usize>(
+        config: Config,
+        resources: &'static mut Resources<CONN, QUEUE>,
+    ) -> Self {
+        let buffer = || QueueBuffer {
+            meta: [raw::PacketMetadata::EMPTY; QUEUE_META],
+            buffer: [0; QUEUE],
+        };
+        let queues = resources
+            .queues
+            .write(core::array::from_fn(|_| core::array::from_fn(|_| buffer())));
+        let mut queues = queues.iter_mut();
+        Self {
+            config,
+            connections: resources.connections.write([None; CONN]),
+            queues: core::array::from_fn(|_| {
+                let mut buffers = unwrap!(queues.next()).iter_mut();
+                PROTOCOLS.map(|protocol| Queue::new(protocol, unwrap!(buffers.next())))
+            }),
+        }
+    }
+
+    /// Take the sockets of interface `id` that have sent all their packets out of its socket set.
+    pub(crate) fn release_sockets(&mut self, id: InterfaceId, sockets: &mut SocketSet<'static>) {
+        for queue in &mut self.queues[id.0 as usize] {
+            queue.release(sockets, false);
+        }
+    }
+
+    /// Take all the sockets of interface `id` out of its socket set, dropping the packets they queue.
+    pub(crate) fn remove_sockets(&mut self, id: InterfaceId, sockets: &mut SocketSet<'static>) {
+        for queue in &mut self.queues[id.0 as usize] {
+            queue.release(sockets, true);
+        }
+    }
+
+    /// Find the connection of an outgoing packet, or create it. Returns its index in the table.
+    fn outbound(&mut self, key: &Key, now: Instant) -> Option<usize> {
+        let found = self.connections.iter().position(|c| {
+            c.is_some_and(|c| {
+                c.expires_at > now
+                    && c.protocol == key.protocol
+                    && c.inside_addr == key.src_addr
+                    && c.inside_port == key.src_port
+                    && c.remote_addr == key.dst_addr
+                    && c.remote_port == key.dst_port
+            })
+        });
+        if found.is_some() {
+            return found;
+        }
+
+        let Some(n) = self
+            .connections
+            .iter()
+            .position(|c| c.is_none_or(|c| c.expires_at <= now))
+        else {
+            debug!("NAT: connection table full, dropping packet");
+            return None;
+        };
+        let outside_port = self.allocate_port(n)?;
+        trace!(
+            "NAT: new connection {:?}:{} -> {:?}:{}, port {}",
+            key.src_addr,
+            key.src_port,
+            key.dst_addr,
+            key.dst_port,
+            outside_port
+        );
+        self.connections[n] = Some(Connection {
+            protocol: key.protocol,
+            inside_addr: key.src_addr,
+            inside_port: key.src_port,
+            remote_addr: key.dst_addr,
+            remote_port: key.dst_port,
+            outside_port,
+            expires_at: now,
+            established: false,
+            closing: false,
+        });
+        Some(n)
+    }
+
+    /// Find the connection of an incoming packet. Returns its index in the table.
+    fn inbound(&self, key: &Key, now: Instant) -> Option<usize> {
+        self.connections.iter().position(|c| {
+            c.is_some_and(|c| {
+                c.expires_at > now
+                    && c.protocol == key.protocol
+                    && c.outside_port == key.dst_port
+                    && c.remote_addr == key.src_addr
+                    && c.remote_port == key.src_port
+            })
+        })
+    }
+
+    /// Port of a new connection in slot `n` of the table.
+    ///
+    /// Slot `n` takes the ports `NAT_PORT_MIN + n + k * CONN` in turn, so that no other connection has
+    /// the port, and the port of the previous connection in the slot isn't used again right away.
+    fn allocate_port(&self, n: usize) -> Option<u16> {
+        if n >= NAT_PORT_COUNT {
+            debug!("NAT: no free port, dropping packet");
+            return None;
+        }
+        let slots = self.connections.len();
+        let ports = (NAT_PORT_COUNT - n - 1) / slots + 1;
+        let k = match &self.connections[n] {
+            Some(c) => ((c.outside_port - NAT_PORT_MIN) as usize / slots + 1) % ports,
+            None => 0,
+        };
+        Some(NAT_PORT_MIN + (n + k * slots) as u16)
+    }
+
+    /// Update the state and expiry of connection `n` for a packet. `reply` is true for incoming packets.
+    fn update(&mut self, n: usize, flags: TcpFlags, reply: bool, now: Instant) -> Connection {
+        let config = &self.config;
+        let conn = unwrap!(self.connections[n].as_mut());
+        conn.established |= reply;
+        conn.closing |= flags.fin || flags.rst;
+        let timeout = match conn.protocol {
+            IpProtocol::Tcp if conn.established && !conn.closing => config.tcp_timeout,
+            IpProtocol::Tcp => config.tcp_transitory_timeout,
+            IpProtocol::Udp => config.udp_timeout,
+            _ => config.icmp_timeout,
+        };
+        conn.expires_at = now + timeout;
+        *conn
+    }
+}
+
+/// Addresses and ports of a packet. For ICMP echo messages, the port of the querier is the identifier,
+/// and the other one is 0.
+#[derive(Clone, Copy)]
+struct Key {
+    protocol: IpProtocol,
+    src_addr: Ipv4Address,
+    src_port: u16,
+    dst_addr: Ipv4Address,
+    dst_port: u16,
+}
+
+#[derive(Default, Clone, Copy)]
+struct TcpFlags {
+    fin: bool,
+    rst: bool,
+}
+
+/// Forwards the packets received on one interface, while it's being polled.
+pub(crate) struct Forwarder<'a> {
+    pub(crate) forwarding: &'a mut Forwarding,
+    pub(crate) routes: &'a [Route],
+    pub(crate) ingress: InterfaceId,
+    pub(crate) ingress_hardware_address: HardwareAddress,
+    pub(crate) ingress_addrs: Vec<IpCidr, IFACE_MAX_ADDR_COUNT>,
+    /// All the interfaces, except the ingress one which is `None`.
+    pub(crate) ifaces: Vec<Option<&'a mut Iface>, MAX_INTERFACES>,
+    /// Packets dropped because the egress interface had no free socket slot.
+    #[cfg(feature = "statistics")]
+    pub(crate) socket_set_full: u32,
+}
+
+impl Forwarder<'_> {
+    /// Forward a frame received on the ingress interface.
+    ///
+    /// Returns `false` if the frame isn't forwarded, and must be processed by the interface.
+    pub(crate) fn forward(&mut self, frame: &mut [u8]) -> bool {
+        let packet = match self.ingress_hardware_address {
+            #[cfg(feature = "medium-ethernet")]
+            HardwareAddress::Ethernet(addr) => {
+                let Ok(frame) = EthernetFrame::new_checked(&mut frame[..]) else {
+                    return false;
+                };
+                // Broadcast and multicast frames are never forwarded.
+                if frame.dst_addr() != addr || frame.ethertype() != EthernetProtocol::Ipv4 {
+                    return false;
+                }
+                &mut frame.into_inner()[EthernetFrame::<&[u8]>::header_len()..]
+            }
+            #[cfg(feature = "medium-ip")]
+            HardwareAddress::Ip => frame,
+            #[allow(unreachable_patterns)]
+            _ => return false,
+        };
+
+        let Ok(mut ip) = Ipv4Packet::new_checked(&mut packet[..]) else {
+            return false;
+        };
+        if !ip.verify_checksum() || ip.more_frags() || ip.frag_offset() != 0 {
+            return false;
+        }
+        let len = ip.total_len() as usize;
+        let protocol = ip.next_header();
+        if !PROTOCOLS.contains(&protocol) {
+            return false;
+        }
+        let Some(mut key) = parse_key(&mut ip) else {
+            return false;
+        };
+        let flags = tcp_flags(&mut ip);
+        let now = Instant::now();
+
+        let mut translated = false;
+        if self
+            .ingress_addrs
+            .iter()
+            .any(|cidr| cidr.address() == IpAddress::Ipv4(key.dst_addr))
+        {
+            // For the stack itself, or a reply to a translated connection.
+            if self.forwarding.config.masquerade != Some(self.ingress) {
+                return false;
+            }
+            let Some(n) = self.forwarding.inbound(&key, now) else {
+                return false;
+            };
+            let conn = self.forwarding.update(n, flags, true, now);
+            key.dst_addr = conn.inside_addr;
+            key.dst_port = conn.inside_port;
+            translated = true;
+        } else if key.dst_addr.is_broadcast()
+            || key.dst_addr.is_multicast()
+            || self.ingress_addrs.iter().any(|cidr| match cidr {
+                IpCidr::Ipv4(cidr) => cidr.contains_addr(&key.dst_addr) || cidr.broadcast() == Some(key.dst_addr),
+                #[allow(unreachable_patterns)]
+                _ => false,
+            })
+        {
+            // Not for us, but for a host on the same link.
+            return false;
+        }
+
+        let Some(egress) = self.route(IpAddress::Ipv4(key.dst_addr)) else {
+            return false;
+        };
+
+        if ip.hop_limit() <= 1 {
+            trace!("forward: TTL exceeded for {:?}", key.dst_addr);
+            return true;
+        }
+        ip.set_hop_limit(ip.hop_limit() - 1);
+
+        if !translated && self.forwarding.config.masquerade == Some(egress) {
+            // Outgoing connection, translate the source.
+            if protocol == IpProtocol::Icmp && !is_echo_request(&mut ip) {
+                return true;
+            }
+            let Some(outside_addr) = self.ifaces[egress.0 as usize].as_deref().and_then(ipv4_addr) else {
+                return true;
+            };
+            let Some(n) = self.forwarding.outbound(&key, now) else {
+                return true;
+            };
+            let conn = self.forwarding.update(n, flags, false, now);
+            key.src_addr = outside_addr;
+            key.src_port = conn.outside_port;
+        }
+
+        rewrite(&mut ip, &key);
+
+        let iface = unwrap!(self.ifaces[egress.0 as usize].as_deref_mut());
+        let n = unwrap!(PROTOCOLS.iter().position(|p| *p == protocol));
+        let queue = &mut self.forwarding.queues[egress.0 as usize][n];
+        match queue.send(&mut iface.sockets, iface.socket_capacity, &ip.into_inner()[..len]) {
+            Ok(()) => iface.waker.wake(),
+            Err(QueueError::NoSocket) => {
+                trace!("forward: no free socket on interface {:?}", egress);
+                #[cfg(feature = "statistics")]
+                {
+                    self.socket_set_full += 1;
+                }
+            }
+            Err(QueueError::Full) => trace!("forward: queue full on interface {:?}", egress),
+        }
+        true
+    }
+
+    /// Egress interface for `addr`, unless it's the ingress interface.
+    fn route(&self, addr: IpAddress) -> Option<InterfaceId> {
+        let ifaces = self
+            .ifaces
+            .iter()
+            .enumerate()
+            .filter_map(|(n, i)| Some((InterfaceId(n as u8), &**i.as_ref()?)));
+        crate::route_addr(addr, self.routes, ifaces).filter(|id| *id != self.ingress)
+    }
+}
+
+fn ipv4_addr(iface: &Iface) -> Option<Ipv4Address> {
+    iface.iface.ip_addrs().iter().find_map(|cidr| match cidr.address() {
+        IpAddress::Ipv4(addr) => Some(addr),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    })
+}
+
+fn parse_key(ip: &mut Ipv4Packet<&mut [u8]>) -> Option<Key> {
+    let protocol = ip.next_header();
+    let (src_addr, dst_addr) = (ip.src_addr(), ip.dst_addr());
+    let (src_port, dst_port) = match protocol {
+        IpProtocol::Tcp => {
+            let tcp = TcpPacket::new_checked(ip.payload_mut()).ok()?;
+            (tcp.src_port(), tcp.dst_port())
+        }
+        IpProtocol::Udp => {
+            let udp = UdpPacket::new_checked(ip.payload_mut()).ok()?;
+            (udp.src_port(), udp.dst_port())
+        }
+        _ => {
+            // The echo identifier stands in for the port of the querier.
+            let icmp = Icmpv4Packet::new_checked(ip.payload_mut()).ok()?;
+            match icmp.msg_type() {
+                Icmpv4Message::EchoRequest => (icmp.echo_ident(), 0),
+                Icmpv4Message::EchoReply => (0, icmp.echo_ident()),
+                _ => (0, 0),
+            }
+        }
+    };
+    Some(Key {
+        protocol,
+        src_addr,
+        src_port,
+        dst_addr,
+        dst_port,
+    })
+}
+
+fn is_echo_request(ip: &mut Ipv4Packet<&mut [u8]>) -> bool {
+    Icmpv4Packet::new_unchecked(ip.payload_mut()).msg_type() == Icmpv4Message::EchoRequest
+}
+
+fn tcp_flags(ip: &mut Ipv4Packet<&mut [u8]>) -> TcpFlags {
+    if ip.next_header() != IpProtocol::Tcp {
+        return TcpFlags::default();
+    }
+    let tcp = TcpPacket::new_unchecked(ip.payload_mut());
+    TcpFlags {
+        fin: tcp.fin(),
+        rst: tcp.rst(),
+    }
+}
+
+/// Set the addresses and ports of the packet, and update its checksums.
+fn rewrite(ip: &mut Ipv4Packet<&mut [u8]>, key: &Key) {
+    ip.set_src_addr(key.src_addr);
+    ip.set_dst_addr(key.dst_addr);
+    let (src, dst) = (IpAddress::Ipv4(key.src_addr), IpAddress::Ipv4(key.dst_addr));
+    match ip.next_header() {
+        IpProtocol::Tcp => {
+            let mut tcp = TcpPacket::new_unchecked(ip.payload_mut());
+            tcp.set_src_port(key.src_port);
+            tcp.set_dst_port(key.dst_port);
+            tcp.fill_checksum(&src, &dst);
+        }
+        IpProtocol::Udp => {
+            let mut udp = UdpPacket::new_unchecked(ip.payload_mut());
+            udp.set_src_port(key.src_port);
+            udp.set_dst_port(key.dst_port);
+            // A zero checksum means the sender didn't compute one.
+            if udp.checksum() != 0 {
+                udp.fill_checksum(&src, &dst);
+            }
+        }
+        _ => {
+            let mut icmp = Icmpv4Packet::new_unchecked(ip.payload_mut());
+            if matches!(icmp.msg_type(), Icmpv4Message::EchoRequest | Icmpv4Message::EchoReply) {
+                let ident = match icmp.msg_type() {
+                    Icmpv4Message::EchoRequest => key.src_port,
+                    _ => key.dst_port,
+                };
+                icmp.set_echo_ident(ident);
+                icmp.fill_checksum();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(port: u16) -> Key {
+        Key {
+            protocol: IpProtocol::Udp,
+            src_addr: Ipv4Address::new(192, 168, 1, 2),
+            src_port: port,
+            dst_addr: Ipv4Address::new(10, 0, 0, 2),
+            dst_port: 53,
+        }
+    }
+
+    fn open(forwarding: &mut Forwarding, port: u16, now: Instant) -> Option<u16> {
+        let n = forwarding.outbound(&key(port), now)?;
+        Some(forwarding.update(n, TcpFlags::default(), false, now).outside_port)
+    }
+
+    #[test]
+    fn nat_ports() {
+        let resources = Box::leak(Box::new(Resources::<3, 0>::new()));
+        let mut forwarding = Forwarding::new(Config::default(), resources);
+        let now = Instant::from_ticks(0);
+        let ports: std::vec::Vec<_> = (1..=3).map(|port| open(&mut forwarding, port, now)).collect();
+        core::assert_eq!(ports, [Some(49152), Some(49153), Some(49154)]);
+        assert_eq!(open(&mut forwarding, 1, now), Some(49152));
+        assert_eq!(open(&mut forwarding, 4, now), None);
+
+        // Slots of expired connections take their next port.
+        let later = now + forwarding.config.udp_timeout;
+        assert_eq!(open(&mut forwarding, 4, later), Some(49155));
+        assert_eq!(open(&mut forwarding, 5, later), Some(49156));
+        assert_eq!(open(&mut forwarding, 1, later), Some(49157));
+
+        // Each slot wraps around within its own ports.
+        unwrap!(forwarding.connections[0].as_mut()).outside_port = 65535;
+        assert_eq!(forwarding.allocate_port(0), Some(49152));
+        unwrap!(forwarding.connections[1].as_mut()).outside_port = 65533;
+        assert_eq!(forwarding.allocate_port(1), Some(49153));
+    }
+}
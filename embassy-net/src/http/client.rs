@@ -0,0 +1,381 @@
+//! HTTP/1.1 client.
+//!
+//! ```ignore
+//! use embassy_net::dns::DnsSocket;
+//! use embassy_net::http::client::{HttpClient, Request};
+//! use embassy_net::tcp::client::{TcpClient, TcpClientState};
+//!
+//! let state: TcpClientState<2, 1024, 1024> = TcpClientState::new();
+//! let tcp = TcpClient::new(stack, &state);
+//! let dns = DnsSocket::new(stack);
+//! let client: HttpClient<_, _, 2> = HttpClient::new(&tcp, &dns);
+//!
+//! let mut rx_buf = [0; 1024];
+//! let request = Request::get("http://example.com/api/status").headers(&[("Accept", "application/json")]);
+//! let mut response = client.send(&request, &mut rx_buf).await?;
+//! info!("status: {}", response.status().0);
+//! let mut body = [0; 512];
+//! let len = response.read_to_end(&mut body).await?;
+//! ```
+
+use core::cell::RefCell;
+use core::net::{IpAddr, SocketAddr};
+
+use embedded_io_async::{Error as _, ErrorKind, Read, Write};
+use embedded_nal_async::{AddrType, Dns, TcpConnect};
+
+use super::{decimal, io_error, read_head, write_head, BodyReader, Error, Framing, Head, Headers, Method, Status};
+
+/// An HTTP request to send.
+#[derive(Debug, Clone, Copy)]
+pub struct Request<'r> {
+    method: Method,
+    url: &'r str,
+    headers: &'r [(&'r str, &'r str)],
+    body: Option<&'r [u8]>,
+}
+
+impl<'r> Request<'r> {
+    /// Create a request without headers nor body.
+    ///
+    /// `url` must start with `http://` or `https://`. The scheme only selects the default port:
+    /// for HTTPS, the client must connect with TLS, e.g. with a
+    /// [`TlsClient`](crate::tls::TlsClient) when the `tls` feature is enabled.
+    pub const fn new(method: Method, url: &'r str) -> Self {
+        Self {
+            method,
+            url,
+            headers: &[],
+            body: None,
+        }
+    }
+
+    /// Create a GET request.
+    pub const fn get(url: &'r str) -> Self {
+        Self::new(Method::Get, url)
+    }
+
+    /// Create a POST request with a body.
+    pub const fn post(url: &'r str, body: &'r [u8]) -> Self {
+        Self::new(Method::Post, url).body(body)
+    }
+
+    /// Create a PUT request with a body.
+    pub const fn put(url: &'r str, body: &'r [u8]) -> Self {
+        Self::new(Method::Put, url).body(body)
+    }
+
+    /// Create a DELETE request.
+    pub const fn delete(url: &'r str) -> Self {
+        Self::new(Method::Delete, url)
+    }
+
+    /// Set the headers to send, besides `Host` and `Content-Length` which are added by the client.
+    pub const fn headers(mut self, headers: &'r [(&'r str, &'r str)]) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Set the body to send.
+    pub const fn body(mut self, body: &'r [u8]) -> Self {
+        self.body = Some(body);
+        self
+    }
+}
+
+/// Parts of an URL.
+struct Url<'u> {
+    /// Host and port, as written in the URL.
+    authority: &'u str,
+    host: &'u str,
+    port: u16,
+    /// Path and query.
+    target: &'u str,
+}
+
+impl<'u> Url<'u> {
+    fn parse(url: &'u str) -> Result<Self, Error> {
+        let (scheme, rest) = url.split_once("://").ok_or(Error::InvalidUrl)?;
+        let default_port = match scheme {
+            s if s.eq_ignore_ascii_case("http") => 80,
+            s if s.eq_ignore_ascii_case("https") => 443,
+            _ => return Err(Error::InvalidUrl),
+        };
+        let (authority, target) = match rest.find(['/', '?', '#']) {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+        let target = target.split('#').next().unwrap_or_default();
+        // User information isn't supported, and dropped.
+        let authority = authority.rsplit_once('@').map_or(authority, |(_, a)| a);
+
+        let (host, port) = match authority.strip_prefix('[') {
+            // IPv6 literal.
+            Some(rest) => {
+                let (host, rest) = rest.split_once(']').ok_or(Error::InvalidUrl)?;
+                (host, rest.strip_prefix(':'))
+            }
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| Error::InvalidUrl)?,
+            None => default_port,
+        };
+        if host.is_empty() {
+            return Err(Error::InvalidUrl);
+        }
+        Ok(Self {
+            authority,
+            host,
+            port,
+            target,
+        })
+    }
+}
+
+/// HTTP/1.1 client, keeping up to `N` idle connections alive.
+///
+/// Connections are opened with `T`, and host names are resolved with `D`.
+pub struct HttpClient<'a, T: TcpConnect + 'a, D: Dns + 'a, const N: usize = 1> {
+    tcp: &'a T,
+    dns: &'a D,
+    idle: RefCell<heapless::Vec<(SocketAddr, T::Connection<'a>), N>>,
+}
+
+impl<'a, T: TcpConnect + 'a, D: Dns + 'a, const N: usize> HttpClient<'a, T, D, N> {
+    /// Create a new client.
+    pub fn new(tcp: &'a T, dns: &'a D) -> Self {
+        Self {
+            tcp,
+            dns,
+            idle: RefCell::new(heapless::Vec::new()),
+        }
+    }
+
+    /// Close the idle connections.
+    pub fn close_idle(&self) {
+        self.idle.borrow_mut().clear();
+    }
+
+    /// Send a request, and receive the head of the response.
+    ///
+    /// The response head is stored in `buf`, which must be large enough for it. The rest of `buf`
+    /// is used to buffer the body.
+    pub async fn send<'c, 'b>(
+        &'c self,
+        request: &Request<'_>,
+        buf: &'b mut [u8],
+    ) -> Result<Response<'c, 'a, 'b, T, D, N>, Error> {
+        let url = Url::parse(request.url)?;
+        let addr = self.resolve(&url).await?;
+
+        let (mut conn, mut reused) = match self.take_idle(addr) {
+            Some(conn) => (conn, true),
+            None => (self.connect(addr).await?, false),
+        };
+        let (head_len, filled) = loop {
+            match self.exchange(&mut conn, request, &url, buf).await {
+                Ok(Some(head)) => break head,
+                // The server closed an idle connection, try a new one.
+                Ok(None) | Err(Error::Io(_)) if reused && request.method.is_idempotent() => {
+                    debug!("HTTP: idle connection closed, reconnecting");
+                    // Release the dead connection first, the pool may have no room for another.
+                    drop(conn);
+                    conn = self.connect(addr).await?;
+                    reused = false;
+                }
+                Ok(None) => return Err(Error::ConnectionClosed),
+                Err(e) => return Err(e),
+            }
+        };
+
+        let (head, rest) = buf.split_at_mut(head_len);
+        let parsed = Head::parse(head)?;
+        let mut parts = parsed.start.splitn(3, ' ');
+        let version = parts.next().unwrap_or_default();
+        let status = parts
+            .next()
+            .and_then(|s| s.parse().ok())
+            .map(Status)
+            .ok_or(Error::InvalidMessage)?;
+        let headers = parsed.headers;
+        let mut keep_alive = match version {
+            "HTTP/1.1" => !headers.contains_token("connection", "close"),
+            "HTTP/1.0" => headers.contains_token("connection", "keep-alive"),
+            _ => return Err(Error::InvalidMessage),
+        };
+        let framing = match request.method == Method::Head || status.has_no_body() {
+            true => Framing::Done,
+            false => headers.framing()?,
+        };
+        if framing == Framing::UntilClose {
+            keep_alive = false;
+        }
+
+        Ok(Response {
+            client: self,
+            addr,
+            conn: Some(conn),
+            status,
+            headers,
+            body: BodyReader::new(rest, filled - head_len, framing),
+            keep_alive,
+        })
+    }
+
+    async fn resolve(&self, url: &Url<'_>) -> Result<SocketAddr, Error> {
+        let ip = match url.host.parse::<IpAddr>() {
+            Ok(ip) => ip,
+            Err(_) => self
+                .dns
+                .get_host_by_name(url.host, AddrType::Either)
+                .await
+                .map_err(|_| Error::Dns)?,
+        };
+        Ok(SocketAddr::new(ip, url.port))
+    }
+
+    fn take_idle(&self, addr: SocketAddr) -> Option<T::Connection<'a>> {
+        let mut idle = self.idle.borrow_mut();
+        let i = idle.iter().position(|(a, _)| *a == addr)?;
+        Some(idle.remove(i).1)
+    }
+
+    async fn connect(&self, addr: SocketAddr) -> Result<T::Connection<'a>, Error> {
+        loop {
+            match self.tcp.connect(addr).await {
+                Ok(conn) => return Ok(conn),
+                Err(e) if e.kind() == ErrorKind::OutOfMemory => {
+                    // The connection pool is full, maybe of idle connections: close the oldest one
+                    // and try again.
+                    let mut idle = self.idle.borrow_mut();
+                    if idle.is_empty() {
+                        return Err(Error::Connect);
+                    }
+                    idle.remove(0);
+                }
+                Err(_) => return Err(Error::Connect),
+            }
+        }
+    }
+
+    /// Send a request and read the head of the response, skipping informational ones.
+    ///
+    /// Returns `None` if the connection was closed before the response.
+    async fn exchange(
+        &self,
+        conn: &mut T::Connection<'a>,
+        request: &Request<'_>,
+        url: &Url<'_>,
+        buf: &mut [u8],
+    ) -> Result<Option<(usize, usize)>, Error> {
+        let length = decimal(request.body.map_or(0, |b| b.len()));
+        let has_length = request.body.is_some() || matches!(request.method, Method::Post | Method::Put);
+        let extra = [("Host", url.authority), ("Content-Length", length.as_str())];
+        let extra = match has_length {
+            true => &extra[..],
+            false => &extra[..1],
+        };
+        let slash = match url.target.starts_with('/') {
+            true => "",
+            false => "/",
+        };
+        write_head(
+            conn,
+            &[request.method.as_str(), " ", slash, url.target, " HTTP/1.1"],
+            request.headers,
+            extra,
+        )
+        .await?;
+        if let Some(body) = request.body {
+            conn.write_all(body).await.map_err(io_error)?;
+        }
+        conn.flush().await.map_err(io_error)?;
+
+        let mut filled = 0;
+        loop {
+            let Some((head_len, len)) = read_head(conn, buf, filled).await? else {
+                return Ok(None);
+            };
+            let status = Head::parse(&buf[..head_len])?
+                .start
+                .split(' ')
+                .nth(1)
+                .and_then(|s| s.parse::<u16>().ok())
+                .ok_or(Error::InvalidMessage)?;
+            // Skip informational responses, except 101 Switching Protocols.
+            if !(100..200).contains(&status) || status == 101 {
+                return Ok(Some((head_len, len)));
+            }
+            buf.copy_within(head_len..len, 0);
+            filled = len - head_len;
+        }
+    }
+}
+
+/// A response received by a [`HttpClient`].
+///
+/// The body can be read with [`read`](Read::read) or [`read_to_end`](Self::read_to_end). When the
+/// response is dropped after the whole body was read, the connection is kept to be reused.
+pub struct Response<'c, 'a, 'b, T: TcpConnect + 'a, D: Dns + 'a, const N: usize> {
+    client: &'c HttpClient<'a, T, D, N>,
+    addr: SocketAddr,
+    conn: Option<T::Connection<'a>>,
+    status: Status,
+    headers: Headers<'b>,
+    body: BodyReader<'b>,
+    keep_alive: bool,
+}
+
+impl<'a, 'b, T: TcpConnect + 'a, D: Dns + 'a, const N: usize> Response<'_, 'a, 'b, T, D, N> {
+    /// Get the status of the response.
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Get the headers of the response.
+    pub fn headers(&self) -> Headers<'b> {
+        self.headers
+    }
+
+    /// Read the whole body into `buf`, returning its length.
+    ///
+    /// Returns [`Error::BufferTooSmall`] if the body doesn't fit.
+    pub async fn read_to_end(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let conn = self.conn.as_mut().ok_or(Error::ConnectionClosed)?;
+        self.body.read_to_end(conn, buf).await
+    }
+}
+
+impl<'a, T: TcpConnect + 'a, D: Dns + 'a, const N: usize> embedded_io_async::ErrorType
+    for Response<'_, 'a, '_, T, D, N>
+{
+    type Error = Error;
+}
+
+impl<'a, T: TcpConnect + 'a, D: Dns + 'a, const N: usize> Read for Response<'_, 'a, '_, T, D, N> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let conn = self.conn.as_mut().ok_or(Error::ConnectionClosed)?;
+        self.body.read(conn, buf).await
+    }
+}
+
+impl<'a, T: TcpConnect + 'a, D: Dns + 'a, const N: usize> Drop for Response<'_, 'a, '_, T, D, N> {
+    fn drop(&mut self) {
+        // Connections can only be reused at the end of a response, and if the server didn't send
+        // anything more.
+        if !self.keep_alive || !self.body.is_done() || !self.body.remaining().is_empty() || N == 0 {
+            return;
+        }
+        if let Some(conn) = self.conn.take() {
+            let mut idle = self.client.idle.borrow_mut();
+            if idle.is_full() {
+                idle.remove(0);
+            }
+            let _ = idle.push((self.addr, conn));
+        }
+    }
+}
@@ -0,0 +1,654 @@
+//! HTTP/1.1 client and server.
+//!
+//! Both sides work with fixed buffers given by the caller, and stream message bodies instead of
+//! storing them:
+//!
+//! - [`client::HttpClient`] sends requests over any [`TcpConnect`](embedded_nal_async::TcpConnect)
+//!   implementation, such as [`TcpClient`](crate::tcp::client::TcpClient), and keeps connections
+//!   alive to reuse them for later requests to the same server.
+//! - [`server::Server`] accepts connections on several sockets at once and dispatches requests to
+//!   handlers using a static route table.
+//!
+//! Bodies sent with `Transfer-Encoding: chunked` are decoded transparently.
+
+use core::fmt::Write as _;
+use core::ops::Range;
+
+use embedded_io_async::{Read, Write};
+use heapless::String;
+
+pub mod client;
+pub mod server;
+
+/// HTTP request method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Method {
+    /// GET
+    Get,
+    /// HEAD
+    Head,
+    /// POST
+    Post,
+    /// PUT
+    Put,
+    /// DELETE
+    Delete,
+    /// PATCH
+    Patch,
+    /// OPTIONS
+    Options,
+}
+
+impl Method {
+    /// Get the name of the method, as sent on the wire.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        [
+            Method::Get,
+            Method::Head,
+            Method::Post,
+            Method::Put,
+            Method::Delete,
+            Method::Patch,
+            Method::Options,
+        ]
+        .into_iter()
+        .find(|m| m.as_str() == s)
+    }
+
+    /// Whether requests with this method can be sent again safely (RFC 9110 section 9.2.2).
+    fn is_idempotent(self) -> bool {
+        !matches!(self, Method::Post | Method::Patch)
+    }
+}
+
+/// HTTP response status code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Status(pub u16);
+
+impl Status {
+    /// 100 Continue
+    pub const CONTINUE: Self = Self(100);
+    /// 101 Switching Protocols
+    pub const SWITCHING_PROTOCOLS: Self = Self(101);
+    /// 200 OK
+    pub const OK: Self = Self(200);
+    /// 201 Created
+    pub const CREATED: Self = Self(201);
+    /// 204 No Content
+    pub const NO_CONTENT: Self = Self(204);
+    /// 301 Moved Permanently
+    pub const MOVED_PERMANENTLY: Self = Self(301);
+    /// 302 Found
+    pub const FOUND: Self = Self(302);
+    /// 304 Not Modified
+    pub const NOT_MODIFIED: Self = Self(304);
+    /// 400 Bad Request
+    pub const BAD_REQUEST: Self = Self(400);
+    /// 401 Unauthorized
+    pub const UNAUTHORIZED: Self = Self(401);
+    /// 403 Forbidden
+    pub const FORBIDDEN: Self = Self(403);
+    /// 404 Not Found
+    pub const NOT_FOUND: Self = Self(404);
+    /// 405 Method Not Allowed
+    pub const METHOD_NOT_ALLOWED: Self = Self(405);
+    /// 413 Content Too Large
+    pub const CONTENT_TOO_LARGE: Self = Self(413);
+    /// 426 Upgrade Required
+    pub const UPGRADE_REQUIRED: Self = Self(426);
+    /// 431 Request Header Fields Too Large
+    pub const REQUEST_HEADER_FIELDS_TOO_LARGE: Self = Self(431);
+    /// 500 Internal Server Error
+    pub const INTERNAL_SERVER_ERROR: Self = Self(500);
+    /// 501 Not Implemented
+    pub const NOT_IMPLEMENTED: Self = Self(501);
+    /// 503 Service Unavailable
+    pub const SERVICE_UNAVAILABLE: Self = Self(503);
+
+    /// Whether the status is a success, 2xx.
+    pub const fn is_success(self) -> bool {
+        self.0 >= 200 && self.0 < 300
+    }
+
+    /// Get the reason phrase of the status, or an empty string for unknown ones.
+    pub const fn reason(self) -> &'static str {
+        match self.0 {
+            100 => "Continue",
+            101 => "Switching Protocols",
+            200 => "OK",
+            201 => "Created",
+            202 => "Accepted",
+            204 => "No Content",
+            301 => "Moved Permanently",
+            302 => "Found",
+            303 => "See Other",
+            304 => "Not Modified",
+            307 => "Temporary Redirect",
+            308 => "Permanent Redirect",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            408 => "Request Timeout",
+            409 => "Conflict",
+            413 => "Content Too Large",
+            415 => "Unsupported Media Type",
+            426 => "Upgrade Required",
+            429 => "Too Many Requests",
+            431 => "Request Header Fields Too Large",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            _ => "",
+        }
+    }
+
+    /// Whether responses with this status have no body (RFC 9112 section 6.3).
+    fn has_no_body(self) -> bool {
+        self.0 < 200 || self.0 == 204 || self.0 == 304
+    }
+}
+
+/// HTTP error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The URL is malformed, or its scheme isn't `http` or `https`.
+    InvalidUrl,
+    /// The host name couldn't be resolved.
+    Dns,
+    /// The connection couldn't be established.
+    Connect,
+    /// Reading from or writing to the connection failed.
+    Io(embedded_io_async::ErrorKind),
+    /// The connection was closed in the middle of a message.
+    ConnectionClosed,
+    /// The message is malformed.
+    InvalidMessage,
+    /// The message head doesn't fit in the buffer, or the body doesn't fit in the buffer given to
+    /// `read_to_end`.
+    BufferTooSmall,
+    /// The response was already started, or more data was written than announced.
+    InvalidState,
+}
+
+impl embedded_io_async::Error for Error {
+    fn kind(&self) -> embedded_io_async::ErrorKind {
+        match self {
+            Error::Io(kind) => *kind,
+            Error::ConnectionClosed => embedded_io_async::ErrorKind::ConnectionReset,
+            Error::InvalidMessage | Error::InvalidUrl => embedded_io_async::ErrorKind::InvalidData,
+            Error::BufferTooSmall => embedded_io_async::ErrorKind::OutOfMemory,
+            _ => embedded_io_async::ErrorKind::Other,
+        }
+    }
+}
+
+fn io_error<E: embedded_io_async::Error>(e: E) -> Error {
+    Error::Io(e.kind())
+}
+
+/// Headers of a message.
+#[derive(Debug, Clone, Copy)]
+pub struct Headers<'a> {
+    /// Header lines, each terminated by a line break.
+    lines: &'a [u8],
+}
+
+impl<'a> Headers<'a> {
+    /// Get the value of the first header named `name`, ignoring case.
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v)
+    }
+
+    /// Iterate over the header names and values.
+    ///
+    /// Header lines which aren't valid UTF-8 are skipped.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.lines
+            .split(|&b| b == b'\n')
+            .filter_map(|line| core::str::from_utf8(line).ok())
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim(), value.trim()))
+    }
+
+    /// Whether the comma-separated values of header `name` contain `token`, ignoring case.
+    pub(crate) fn contains_token(&self, name: &str, token: &str) -> bool {
+        self.iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .flat_map(|(_, v)| v.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Get how the body is delimited (RFC 9112 section 6.3).
+    fn framing(&self) -> Result<Framing, Error> {
+        if self.get("transfer-encoding").is_some() {
+            return match self.contains_token("transfer-encoding", "chunked") {
+                true => Ok(Framing::Chunked(Chunk::Size)),
+                false => Err(Error::InvalidMessage),
+            };
+        }
+        match self.get("content-length") {
+            Some(len) => len
+                .parse()
+                .map(|len| match len {
+                    0 => Framing::Done,
+                    len => Framing::Length(len),
+                })
+                .map_err(|_| Error::InvalidMessage),
+            None => Ok(Framing::UntilClose),
+        }
+    }
+}
+
+/// A parsed message head.
+pub(crate) struct Head<'a> {
+    /// Start line, without the line break.
+    pub(crate) start: &'a str,
+    pub(crate) headers: Headers<'a>,
+}
+
+impl<'a> Head<'a> {
+    pub(crate) fn parse(head: &'a [u8]) -> Result<Self, Error> {
+        let end = head.iter().position(|&b| b == b'\n').ok_or(Error::InvalidMessage)?;
+        let start = core::str::from_utf8(&head[..end]).map_err(|_| Error::InvalidMessage)?;
+        Ok(Self {
+            start: start.trim_end(),
+            headers: Headers {
+                lines: &head[end + 1..],
+            },
+        })
+    }
+}
+
+/// Find the end of the message head in `buf`, after the empty line.
+fn find_head_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(2)
+        .position(|w| w == b"\n\n")
+        .map(|i| i + 2)
+        .into_iter()
+        .chain(buf.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4))
+        .min()
+}
+
+/// Read a message head in `buf`, after the `filled` bytes already there.
+///
+/// Returns the length of the head and the number of bytes in `buf`, or `None` if the connection
+/// was closed before any byte was received.
+pub(crate) async fn read_head<C: Read>(
+    conn: &mut C,
+    buf: &mut [u8],
+    mut filled: usize,
+) -> Result<Option<(usize, usize)>, Error> {
+    loop {
+        // Empty lines before a message must be ignored (RFC 9112 section 2.2).
+        let blank = buf[..filled].iter().take_while(|b| b.is_ascii_whitespace()).count();
+        if blank > 0 {
+            buf.copy_within(blank..filled, 0);
+            filled -= blank;
+        }
+        if let Some(end) = find_head_end(&buf[..filled]) {
+            return Ok(Some((end, filled)));
+        }
+        if filled == buf.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        match conn.read(&mut buf[filled..]).await.map_err(io_error)? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(Error::ConnectionClosed),
+            n => filled += n,
+        }
+    }
+}
+
+/// Write a message head, with `extra` headers after the given ones.
+pub(crate) async fn write_head<C: Write>(
+    conn: &mut C,
+    start: &[&str],
+    headers: &[(&str, &str)],
+    extra: &[(&str, &str)],
+) -> Result<(), Error> {
+    for part in start {
+        conn.write_all(part.as_bytes()).await.map_err(io_error)?;
+    }
+    conn.write_all(b"\r\n").await.map_err(io_error)?;
+    for (name, value) in headers.iter().chain(extra) {
+        for part in [name, ": ", value, "\r\n"] {
+            conn.write_all(part.as_bytes()).await.map_err(io_error)?;
+        }
+    }
+    conn.write_all(b"\r\n").await.map_err(io_error)
+}
+
+pub(crate) fn decimal(n: usize) -> String<20> {
+    let mut s = String::new();
+    let _ = write!(s, "{}", n);
+    s
+}
+
+/// How a message body is delimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum Framing {
+    /// This many bytes are left.
+    Length(usize),
+    Chunked(Chunk),
+    /// The body ends when the connection is closed.
+    UntilClose,
+    Done,
+}
+
+/// Position in a chunked body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum Chunk {
+    /// Before the size line of a chunk.
+    Size,
+    /// This many bytes are left in the chunk.
+    Data(usize),
+    /// Before the line break at the end of a chunk.
+    DataEnd,
+    /// After the last chunk.
+    Trailers,
+}
+
+/// Reads a message body, decoding chunks.
+///
+/// Bytes received after the head are kept in `buf`, and read before reading from the connection.
+struct BodyReader<'b> {
+    buf: &'b mut [u8],
+    pos: usize,
+    len: usize,
+    framing: Framing,
+}
+
+impl<'b> BodyReader<'b> {
+    fn new(buf: &'b mut [u8], len: usize, framing: Framing) -> Self {
+        Self {
+            buf,
+            pos: 0,
+            len,
+            framing,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.framing == Framing::Done
+    }
+
+    /// Bytes received after the body.
+    fn remaining(&self) -> &[u8] {
+        &self.buf[self.remaining_range()]
+    }
+
+    /// Position of the bytes received after the body in the buffer.
+    fn remaining_range(&self) -> Range<usize> {
+        self.pos..self.len
+    }
+
+    /// Read bytes, buffered ones first. Returns 0 at the end of the connection.
+    async fn read_raw<C: Read>(&mut self, conn: &mut C, out: &mut [u8]) -> Result<usize, Error> {
+        if self.pos < self.len {
+            let n = out.len().min(self.len - self.pos);
+            out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+            self.pos += n;
+            return Ok(n);
+        }
+        conn.read(out).await.map_err(io_error)
+    }
+
+    /// Read a line, keeping its beginning in `line`. Returns the number of bytes kept.
+    async fn read_line<C: Read>(&mut self, conn: &mut C, line: &mut [u8]) -> Result<usize, Error> {
+        let mut len = 0;
+        loop {
+            let mut b = [0];
+            if self.read_raw(conn, &mut b).await? == 0 {
+                return Err(Error::ConnectionClosed);
+            }
+            match b[0] {
+                b'\n' => return Ok(len),
+                b'\r' => {}
+                b => {
+                    if len < line.len() {
+                        line[len] = b;
+                        len += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn read<C: Read>(&mut self, conn: &mut C, out: &mut [u8]) -> Result<usize, Error> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.framing {
+                Framing::Done => return Ok(0),
+                Framing::UntilClose => {
+                    let n = self.read_raw(conn, out).await?;
+                    if n == 0 {
+                        self.framing = Framing::Done;
+                    }
+                    return Ok(n);
+                }
+                Framing::Length(left) => {
+                    let max = out.len().min(left);
+                    let n = self.read_raw(conn, &mut out[..max]).await?;
+                    if n == 0 {
+                        return Err(Error::ConnectionClosed);
+                    }
+                    self.framing = match left - n {
+                        0 => Framing::Done,
+                        left => Framing::Length(left),
+                    };
+                    return Ok(n);
+                }
+                Framing::Chunked(Chunk::Size) => {
+                    let mut line = [0; 16];
+                    let len = self.read_line(conn, &mut line).await?;
+                    // Chunk extensions after the size are ignored.
+                    let digits = line[..len].iter().take_while(|b| b.is_ascii_hexdigit()).count();
+                    let size = core::str::from_utf8(&line[..digits])
+                        .ok()
+                        .and_then(|s| usize::from_str_radix(s, 16).ok())
+                        .ok_or(Error::InvalidMessage)?;
+                    self.framing = Framing::Chunked(match size {
+                        0 => Chunk::Trailers,
+                        size => Chunk::Data(size),
+                    });
+                }
+                Framing::Chunked(Chunk::Data(left)) => {
+                    let max = out.len().min(left);
+                    let n = self.read_raw(conn, &mut out[..max]).await?;
+                    if n == 0 {
+                        return Err(Error::ConnectionClosed);
+                    }
+                    self.framing = Framing::Chunked(match left - n {
+                        0 => Chunk::DataEnd,
+                        left => Chunk::Data(left),
+                    });
+                    return Ok(n);
+                }
+                Framing::Chunked(Chunk::DataEnd) => {
+                    let mut line = [0; 1];
+                    if self.read_line(conn, &mut line).await? != 0 {
+                        return Err(Error::InvalidMessage);
+                    }
+                    self.framing = Framing::Chunked(Chunk::Size);
+                }
+                Framing::Chunked(Chunk::Trailers) => {
+                    let mut line = [0; 1];
+                    if self.read_line(conn, &mut line).await? == 0 {
+                        self.framing = Framing::Done;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Read the whole body into `out`, returning its length.
+    async fn read_to_end<C: Read>(&mut self, conn: &mut C, out: &mut [u8]) -> Result<usize, Error> {
+        let mut len = 0;
+        loop {
+            if len == out.len() {
+                // Check whether the body is exactly the size of the buffer.
+                let mut b = [0];
+                return match self.read(conn, &mut b).await? {
+                    0 => Ok(len),
+                    _ => Err(Error::BufferTooSmall),
+                };
+            }
+            match self.read(conn, &mut out[len..]).await? {
+                0 => return Ok(len),
+                n => len += n,
+            }
+        }
+    }
+
+    /// Read and drop the rest of the body.
+    async fn skip<C: Read>(&mut self, conn: &mut C) -> Result<(), Error> {
+        let mut discard = [0; 64];
+        while self.read(conn, &mut discard).await? != 0 {}
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    const HEAD: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nX-List: a, B\r\nx-list: c\r\n\r\n";
+
+    /// Read a whole body, with `buffered` bytes received along with the head.
+    fn read_body(buffered: &[u8], mut conn: &[u8], framing: Framing) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        let mut buf = buffered.to_vec();
+        let mut body = BodyReader::new(&mut buf, buffered.len(), framing);
+        let mut out = vec![0; 64];
+        let len = block_on(body.read_to_end(&mut conn, &mut out))?;
+        out.truncate(len);
+        Ok((out, body.remaining().to_vec()))
+    }
+
+    #[test]
+    fn head() {
+        let head = Head::parse(&HEAD[..HEAD.len() - 2]).unwrap();
+        assert_eq!(head.start, "HTTP/1.1 200 OK");
+        assert_eq!(head.headers.get("content-type"), Some("text/plain"));
+        assert_eq!(head.headers.get("x-list"), Some("a, B"));
+        assert_eq!(head.headers.iter().count(), 3);
+        assert!(head.headers.contains_token("X-List", "b"));
+        assert!(head.headers.contains_token("X-List", "c"));
+        assert!(!head.headers.contains_token("Content-Type", "plain"));
+        assert_eq!(head.headers.framing(), Ok(Framing::UntilClose));
+        assert!(Head::parse(b"HTTP/1.1 200 OK").is_err());
+    }
+
+    #[test]
+    fn read_head_skips_blank_lines() {
+        let mut buf = [0; 128];
+        let received = [b"\r\n", HEAD, b"body"].concat();
+        let (head_len, len) = block_on(read_head(&mut &received[..], &mut buf, 0)).unwrap().unwrap();
+        assert_eq!(&buf[..head_len], HEAD);
+        assert_eq!(&buf[head_len..len], b"body");
+
+        // Bare line feeds.
+        let mut conn = &b"GET / HTTP/1.1\nHost: a\n\n"[..];
+        assert_eq!(block_on(read_head(&mut conn, &mut buf, 0)), Ok(Some((24, 24))));
+        assert_eq!(block_on(read_head(&mut &b""[..], &mut buf, 0)), Ok(None));
+        assert_eq!(
+            block_on(read_head(&mut &b"GET / HTTP/1.1\r\n"[..], &mut buf, 0)),
+            Err(Error::ConnectionClosed)
+        );
+        assert_eq!(
+            block_on(read_head(&mut &HEAD[..], &mut buf[..16], 0)),
+            Err(Error::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn framing() {
+        let framing = |headers: &'static [u8]| Headers { lines: headers }.framing();
+        assert_eq!(framing(b"Content-Length: 12\r\n"), Ok(Framing::Length(12)));
+        assert_eq!(framing(b"Content-Length: 0\r\n"), Ok(Framing::Done));
+        assert_eq!(framing(b"Content-Length: -1\r\n"), Err(Error::InvalidMessage));
+        // Transfer-Encoding takes precedence over Content-Length.
+        assert_eq!(
+            framing(b"Content-Length: 12\r\nTransfer-Encoding: gzip, Chunked\r\n"),
+            Ok(Framing::Chunked(Chunk::Size))
+        );
+        assert_eq!(framing(b"Transfer-Encoding: gzip\r\n"), Err(Error::InvalidMessage));
+    }
+
+    #[test]
+    fn chunked_body() {
+        let chunked = Framing::Chunked(Chunk::Size);
+        let body = b"4\r\nWiki\r\n5;ext=1\r\npedia\r\nE\r\n in\r\n\r\nchunks.\r\n0\r\nTrailer: x\r\n\r\nnext";
+        let expected = b"Wikipedia in\r\n\r\nchunks.";
+        // All received with the head: the bytes after the body are kept.
+        let (out, rest) = read_body(body, b"", chunked).unwrap();
+        assert_eq!(&out[..], expected);
+        assert_eq!(&rest[..], b"next");
+        // All read from the connection.
+        let (out, _) = read_body(b"", body, chunked).unwrap();
+        assert_eq!(&out[..], expected);
+        // Split in the middle of a size line.
+        let (out, _) = read_body(&body[..12], &body[12..], chunked).unwrap();
+        assert_eq!(&out[..], expected);
+    }
+
+    #[test]
+    fn invalid_chunked_body() {
+        let chunked = Framing::Chunked(Chunk::Size);
+        assert_eq!(read_body(b"x\r\n", b"", chunked).unwrap_err(), Error::InvalidMessage);
+        // Chunk longer than its size.
+        let body = b"2\r\nabc\r\n0\r\n\r\n";
+        assert_eq!(read_body(body, b"", chunked).unwrap_err(), Error::InvalidMessage);
+        // Connection closed before the last chunk.
+        let body = b"3\r\nabc\r\n";
+        assert_eq!(read_body(body, b"", chunked).unwrap_err(), Error::ConnectionClosed);
+    }
+
+    #[test]
+    fn length_body() {
+        let (out, rest) = read_body(b"abc", b"defgh", Framing::Length(6)).unwrap();
+        assert_eq!(&out[..], b"abcdef");
+        assert!(rest.is_empty());
+        let err = read_body(b"abc", b"", Framing::Length(6)).unwrap_err();
+        assert_eq!(err, Error::ConnectionClosed);
+        let (out, _) = read_body(b"abc", b"def", Framing::UntilClose).unwrap();
+        assert_eq!(&out[..], b"abcdef");
+    }
+
+    #[test]
+    fn body_too_large() {
+        let mut buf = *b"abcdef";
+        let mut body = BodyReader::new(&mut buf, 6, Framing::Length(6));
+        let mut out = [0; 6];
+        assert_eq!(block_on(body.read_to_end(&mut &b""[..], &mut out)), Ok(6));
+        let mut body = BodyReader::new(&mut buf, 6, Framing::UntilClose);
+        assert_eq!(
+            block_on(body.read_to_end(&mut &b"g"[..], &mut out)),
+            Err(Error::BufferTooSmall)
+        );
+    }
+}
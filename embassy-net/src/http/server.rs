@@ -0,0 +1,521 @@
+//! HTTP/1.1 server.
+//!
+//! Requests are dispatched to handlers with a static route table. All the handlers of a server
+//! have the same type, usually an enum implementing [`Handler`].
+//!
+//! ```ignore
+//! use embassy_net::http::server::{Config, Handler, Request, Response, Route, Server, ServerState};
+//! use embassy_net::http::{Error, Method, Status};
+//!
+//! enum Api {
+//!     Status,
+//!     Echo,
+//! }
+//!
+//! impl Handler for Api {
+//!     async fn handle(&self, request: &mut Request<'_, '_>, response: &mut Response<'_, '_>) -> Result<(), Error> {
+//!         match self {
+//!             Api::Status => response.send(Status::OK, &[("Content-Type", "application/json")], b"{\"up\":true}").await,
+//!             Api::Echo => {
+//!                 let mut buf = [0; 256];
+//!                 let len = request.read_to_end(&mut buf).await?;
+//!                 response.send(Status::OK, &[], &buf[..len]).await
+//!             }
+//!         }
+//!     }
+//! }
+//!
+//! static ROUTES: [Route<'static, Api>; 2] = [
+//!     Route::new(Method::Get, "/status", Api::Status),
+//!     Route::new(Method::Post, "/echo", Api::Echo),
+//! ];
+//! static STATE: StaticCell<ServerState<2>> = StaticCell::new();
+//!
+//! let server = Server::new(stack, Config::new(80), &ROUTES);
+//! server.run(STATE.init(ServerState::new())).await;
+//! ```
+
+use core::ops::Range;
+
+use embassy_futures::join::join_array;
+use embassy_time::Duration;
+use embedded_io_async::{Read, Write};
+
+use super::{decimal, io_error, read_head, write_head, BodyReader, Error, Framing, Head, Headers, Method, Status};
+use crate::tcp::{TcpReader, TcpSocket, TcpWriter};
+use crate::{IpEndpoint, Stack};
+
+/// HTTP server configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Config {
+    /// Port to listen on.
+    pub port: u16,
+    /// Connections are closed when no data is received for this long.
+    pub timeout: Option<Duration>,
+    /// Whether connections are kept open after a response, for further requests.
+    pub keep_alive: bool,
+}
+
+impl Config {
+    /// Create a configuration listening on `port`.
+    pub const fn new(port: u16) -> Self {
+        Self {
+            port,
+            timeout: Some(Duration::from_secs(10)),
+            keep_alive: true,
+        }
+    }
+}
+
+/// A route, mapping requests to a handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Route<'a, H> {
+    /// Method of the requests. Routes for GET also handle HEAD requests.
+    pub method: Method,
+    /// Path of the requests, without the query.
+    ///
+    /// A path ending with `*` matches all the paths starting with what's before it.
+    pub path: &'a str,
+    /// Handler of the requests.
+    pub handler: H,
+}
+
+impl<'a, H> Route<'a, H> {
+    /// Create a route.
+    pub const fn new(method: Method, path: &'a str, handler: H) -> Self {
+        Self { method, path, handler }
+    }
+
+    fn matches_path(&self, path: &str) -> bool {
+        match self.path.strip_suffix('*') {
+            Some(prefix) => path.starts_with(prefix),
+            None => path == self.path,
+        }
+    }
+
+    fn matches_method(&self, method: Method) -> bool {
+        self.method == method || (self.method == Method::Get && method == Method::Head)
+    }
+}
+
+/// Request handler.
+pub trait Handler {
+    /// Handle a request, sending a response.
+    ///
+    /// The part of the request body that isn't read is skipped. If the handler returns an error
+    /// before starting the response, a `500 Internal Server Error` response is sent.
+    async fn handle(&self, request: &mut Request<'_, '_>, response: &mut Response<'_, '_>) -> Result<(), Error>;
+}
+
+/// A request received by a [`Server`].
+///
+/// The body can be read with [`read`](Read::read) or [`read_to_end`](Self::read_to_end).
+pub struct Request<'r, 's> {
+    remote: Option<IpEndpoint>,
+    method: Method,
+    path: &'r str,
+    query: Option<&'r str>,
+    headers: Headers<'r>,
+    reader: &'r mut TcpReader<'s>,
+    body: BodyReader<'r>,
+}
+
+impl<'r> Request<'r, '_> {
+    /// Get the address and port of the client.
+    pub fn remote_endpoint(&self) -> Option<IpEndpoint> {
+        self.remote
+    }
+
+    /// Get the method of the request.
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    /// Get the path of the request, without the query.
+    pub fn path(&self) -> &'r str {
+        self.path
+    }
+
+    /// Get the query of the request, after the `?`.
+    pub fn query(&self) -> Option<&'r str> {
+        self.query
+    }
+
+    /// Get the headers of the request.
+    pub fn headers(&self) -> Headers<'r> {
+        self.headers
+    }
+
+    /// Read the whole body into `buf`, returning its length.
+    ///
+    /// Returns [`Error::BufferTooSmall`] if the body doesn't fit.
+    pub async fn read_to_end(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        self.body.read_to_end(self.reader, buf).await
+    }
+}
+
+impl embedded_io_async::ErrorType for Request<'_, '_> {
+    type Error = Error;
+}
+
+impl Read for Request<'_, '_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        self.body.read(self.reader, buf).await
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResponseState {
+    NotStarted,
+    /// This many bytes of body are left.
+    Length(usize),
+    Chunked,
+    /// The body ends when the connection is closed.
+    UntilClose,
+    Done,
+}
+
+/// The response to a request received by a [`Server`].
+///
+/// Either send a whole response with [`send`](Self::send), or [`start`](Self::start) it and
+/// stream the body with [`write`](Write::write).
+pub struct Response<'r, 's> {
+    writer: &'r mut TcpWriter<'s>,
+    state: ResponseState,
+    keep_alive: bool,
+    /// Whether the body is left out, for HEAD requests.
+    head: bool,
+    /// Whether the client supports chunked transfer encoding, which HTTP/1.0 clients don't.
+    chunked: bool,
+}
+
+impl<'r, 's> Response<'r, 's> {
+    fn new(writer: &'r mut TcpWriter<'s>, keep_alive: bool, head: bool, chunked: bool) -> Self {
+        Self {
+            writer,
+            state: ResponseState::NotStarted,
+            keep_alive,
+            head,
+            chunked,
+        }
+    }
+
+    /// Whether the response was started.
+    pub fn is_started(&self) -> bool {
+        self.state != ResponseState::NotStarted
+    }
+
+    /// Send a whole response.
+    pub async fn send(&mut self, status: Status, headers: &[(&str, &str)], body: &[u8]) -> Result<(), Error> {
+        self.start(status, headers, Some(body.len())).await?;
+        self.write_all(body).await
+    }
+
+    /// Send the head of the response.
+    ///
+    /// If `content_length` is `None`, the body is sent with chunked transfer encoding, or until
+    /// the connection is closed for HTTP/1.0 clients. The body is then written with
+    /// [`write`](Write::write), and ends when the handler returns.
+    pub async fn start(
+        &mut self,
+        status: Status,
+        headers: &[(&str, &str)],
+        content_length: Option<usize>,
+    ) -> Result<(), Error> {
+        if self.is_started() {
+            return Err(Error::InvalidState);
+        }
+        let until_close = content_length.is_none() && !self.chunked && !status.has_no_body();
+        if until_close {
+            self.keep_alive = false;
+        }
+        let length = decimal(content_length.unwrap_or_default());
+        let mut extra: heapless::Vec<(&str, &str), 2> = heapless::Vec::new();
+        if !status.has_no_body() {
+            let _ = match content_length {
+                Some(_) => extra.push(("Content-Length", length.as_str())),
+                None if until_close => Ok(()),
+                None => extra.push(("Transfer-Encoding", "chunked")),
+            };
+        }
+        if !self.keep_alive {
+            let _ = extra.push(("Connection", "close"));
+        }
+        let code = decimal(status.0 as usize);
+        write_head(
+            self.writer,
+            &["HTTP/1.1 ", code.as_str(), " ", status.reason()],
+            headers,
+            &extra,
+        )
+        .await?;
+
+        self.state = match (status.has_no_body(), content_length) {
+            (true, _) | (false, Some(0)) => ResponseState::Done,
+            (false, Some(len)) => ResponseState::Length(len),
+            (false, None) if until_close => ResponseState::UntilClose,
+            (false, None) => ResponseState::Chunked,
+        };
+        Ok(())
+    }
+
+    /// End the response.
+    async fn finish(&mut self) -> Result<(), Error> {
+        match self.state {
+            ResponseState::Chunked if !self.head => {
+                self.writer.write_all(b"0\r\n\r\n").await.map_err(io_error)?;
+            }
+            // The connection must be closed to let the client know the response is truncated.
+            ResponseState::Length(_) if !self.head => return Err(Error::InvalidState),
+            _ => {}
+        }
+        self.state = ResponseState::Done;
+        self.writer.flush().await.map_err(io_error)
+    }
+}
+
+impl embedded_io_async::ErrorType for Response<'_, '_> {
+    type Error = Error;
+}
+
+impl Write for Response<'_, '_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.state {
+            ResponseState::Length(left) if buf.len() <= left => {
+                self.state = match left - buf.len() {
+                    0 => ResponseState::Done,
+                    left => ResponseState::Length(left),
+                };
+                if !self.head {
+                    self.writer.write_all(buf).await.map_err(io_error)?;
+                }
+            }
+            ResponseState::UntilClose => {
+                if !self.head {
+                    self.writer.write_all(buf).await.map_err(io_error)?;
+                }
+            }
+            ResponseState::Chunked => {
+                if !self.head {
+                    let mut size: heapless::String<16> = heapless::String::new();
+                    let _ = core::fmt::write(&mut size, format_args!("{:x}\r\n", buf.len()));
+                    self.writer.write_all(size.as_bytes()).await.map_err(io_error)?;
+                    self.writer.write_all(buf).await.map_err(io_error)?;
+                    self.writer.write_all(b"\r\n").await.map_err(io_error)?;
+                }
+            }
+            _ => return Err(Error::InvalidState),
+        }
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush().await.map_err(io_error)
+    }
+}
+
+struct Slot<const SZ: usize> {
+    rx: [u8; SZ],
+    tx: [u8; SZ],
+    /// Request head and body buffer.
+    buf: [u8; SZ],
+}
+
+impl<const SZ: usize> Slot<SZ> {
+    const NEW: Self = Self {
+        rx: [0; SZ],
+        tx: [0; SZ],
+        buf: [0; SZ],
+    };
+}
+
+/// Buffers of a [`Server`], for `N` concurrent connections.
+///
+/// Each connection has socket buffers of `SZ` bytes, and a buffer of `SZ` bytes for the request
+/// head.
+pub struct ServerState<const N: usize, const SZ: usize = 1024> {
+    slots: [Slot<SZ>; N],
+}
+
+impl<const N: usize, const SZ: usize> ServerState<N, SZ> {
+    /// Create a new `ServerState`.
+    pub const fn new() -> Self {
+        Self { slots: [Slot::NEW; N] }
+    }
+}
+
+impl<const N: usize, const SZ: usize> Default for ServerState<N, SZ> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// HTTP/1.1 server.
+pub struct Server<'a, H: Handler> {
+    stack: Stack<'a>,
+    config: Config,
+    routes: &'a [Route<'a, H>],
+}
+
+impl<'a, H: Handler> Server<'a, H> {
+    /// Create a server dispatching requests with `routes`.
+    ///
+    /// The first route matching a request is used.
+    pub fn new(stack: Stack<'a>, config: Config, routes: &'a [Route<'a, H>]) -> Self {
+        Self { stack, config, routes }
+    }
+
+    /// Serve requests forever, on up to `N` connections at once.
+    pub async fn run<const N: usize, const SZ: usize>(&self, state: &mut ServerState<N, SZ>) -> ! {
+        let mut slots = state.slots.iter_mut();
+        let slots: [_; N] = core::array::from_fn(|_| self.serve_slot(slots.next().unwrap()));
+        join_array(slots).await;
+        unreachable!()
+    }
+
+    async fn serve_slot<const SZ: usize>(&self, slot: &mut Slot<SZ>) {
+        loop {
+            let mut socket = TcpSocket::new(self.stack, &mut slot.rx, &mut slot.tx);
+            socket.set_timeout(self.config.timeout);
+            if let Err(e) = socket.accept(self.config.port).await {
+                warn!("HTTP server: accept error: {:?}", e);
+                continue;
+            }
+            match self.serve_connection(&mut socket, &mut slot.buf).await {
+                Ok(()) => socket.close(),
+                Err(e) => {
+                    debug!("HTTP server: connection error: {:?}", e);
+                    socket.abort();
+                }
+            }
+            let _ = socket.flush().await;
+        }
+    }
+
+    /// Serve the requests of a connection, until it must be closed.
+    async fn serve_connection(&self, socket: &mut TcpSocket<'_>, buf: &mut [u8]) -> Result<(), Error> {
+        let remote = socket.remote_endpoint();
+        let (mut reader, mut writer) = socket.split();
+        let mut filled = 0;
+        loop {
+            let (head_len, len) = match read_head(&mut reader, buf, filled).await {
+                Ok(Some(head)) => head,
+                Ok(None) => return Ok(()),
+                Err(Error::BufferTooSmall) => {
+                    let mut response = Response::new(&mut writer, false, false, false);
+                    return response.send(Status::REQUEST_HEADER_FIELDS_TOO_LARGE, &[], &[]).await;
+                }
+                Err(e) => return Err(e),
+            };
+
+            let (head, rest) = buf.split_at_mut(head_len);
+            let Some((method, target, headers, version, framing)) = self.parse_request(head) else {
+                let mut response = Response::new(&mut writer, false, false, false);
+                return response.send(Status::BAD_REQUEST, &[], &[]).await;
+            };
+            let Some(method) = method else {
+                let mut response = Response::new(&mut writer, false, false, false);
+                return response.send(Status::NOT_IMPLEMENTED, &[], &[]).await;
+            };
+            if framing != Framing::Done && headers.contains_token("expect", "100-continue") {
+                writer
+                    .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                    .await
+                    .map_err(io_error)?;
+            }
+
+            let (path, query) = match target.split_once('?') {
+                Some((path, query)) => (path, Some(query)),
+                None => (target, None),
+            };
+            let mut request = Request {
+                remote,
+                method,
+                path,
+                query,
+                headers,
+                reader: &mut reader,
+                body: BodyReader::new(rest, len - head_len, framing),
+            };
+            let mut response = Response::new(
+                &mut writer,
+                version.keep_alive && self.config.keep_alive,
+                method == Method::Head,
+                version.chunked,
+            );
+
+            let mut routes = self.routes.iter().filter(|r| r.matches_path(path)).peekable();
+            let result = match routes.peek().is_some() {
+                false => response.send(Status::NOT_FOUND, &[], &[]).await,
+                true => match routes.find(|r| r.matches_method(method)) {
+                    Some(route) => route.handler.handle(&mut request, &mut response).await,
+                    None => response.send(Status::METHOD_NOT_ALLOWED, &[], &[]).await,
+                },
+            };
+            if let Err(e) = result {
+                warn!("HTTP server: {} {}: {:?}", method.as_str(), path, e);
+                if response.is_started() {
+                    return Err(e);
+                }
+                response.keep_alive = false;
+                return response.send(Status::INTERNAL_SERVER_ERROR, &[], &[]).await;
+            }
+            if !response.is_started() {
+                warn!("HTTP server: {} {}: no response", method.as_str(), path);
+                response.send(Status::INTERNAL_SERVER_ERROR, &[], &[]).await?;
+            }
+            response.finish().await?;
+            if !response.keep_alive {
+                return Ok(());
+            }
+
+            // Keep the bytes of the next request, if any.
+            request.body.skip(request.reader).await?;
+            let Range { start, end } = request.body.remaining_range();
+            buf.copy_within(head_len + start..head_len + end, 0);
+            filled = end - start;
+        }
+    }
+
+    /// Parse a request head, returning `None` if it's malformed.
+    #[allow(clippy::type_complexity)]
+    fn parse_request<'h>(&self, head: &'h [u8]) -> Option<(Option<Method>, &'h str, Headers<'h>, Version, Framing)> {
+        let head = Head::parse(head).ok()?;
+        let mut parts = head.start.split(' ');
+        let (method, target, version) = (parts.next()?, parts.next()?, parts.next()?);
+        if parts.next().is_some() || !target.starts_with('/') {
+            return None;
+        }
+        let headers = head.headers;
+        let version = match version {
+            "HTTP/1.1" => Version {
+                keep_alive: !headers.contains_token("connection", "close"),
+                chunked: true,
+            },
+            "HTTP/1.0" => Version {
+                keep_alive: headers.contains_token("connection", "keep-alive"),
+                chunked: false,
+            },
+            _ => return None,
+        };
+        // Requests without a length have no body.
+        let framing = match headers.framing().ok()? {
+            Framing::UntilClose => Framing::Done,
+            framing => framing,
+        };
+        Some((Method::parse(method), target, headers, version, framing))
+    }
+}
+
+/// What the client supports, from the HTTP version of its request.
+#[derive(Debug, Clone, Copy)]
+struct Version {
+    /// Whether the connection can be kept open after the response.
+    keep_alive: bool,
+    /// Whether the response body can be sent with chunked transfer encoding.
+    chunked: bool,
+}
@@ -4,7 +4,7 @@ use core::future::{poll_fn, Future};
 use core::mem;
 use core::task::{Context, Poll};
 
-use smoltcp::iface::{Interface, SocketHandle};
+use smoltcp::iface::Interface;
 pub use smoltcp::phy::ChecksumCapabilities;
 use smoltcp::socket::icmp;
 pub use smoltcp::socket::icmp::{Endpoint as IcmpEndpoint, PacketMetadata};
@@ -14,7 +14,7 @@ pub use smoltcp::wire::{Icmpv4Message, Icmpv4Packet, Icmpv4Repr};
 #[cfg(feature = "proto-ipv6")]
 pub use smoltcp::wire::{Icmpv6Message, Icmpv6Packet, Icmpv6Repr};
 
-use crate::Stack;
+use crate::{InterfaceBindError, InterfaceId, SocketId, Stack};
 
 /// Error returned by [`IcmpSocket::bind`].
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -51,7 +51,7 @@ pub enum RecvError {
 /// An ICMP socket.
 pub struct IcmpSocket<'a> {
     stack: Stack<'a>,
-    handle: SocketHandle,
+    handle: SocketId,
 }
 
 impl<'a> IcmpSocket<'a> {
@@ -68,7 +68,7 @@ impl<'a> IcmpSocket<'a> {
             let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
             let tx_meta: &'static mut [PacketMetadata] = unsafe { mem::transmute(tx_meta) };
             let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
-            i.sockets.add(icmp::Socket::new(
+            i.add_socket(icmp::Socket::new(
                 icmp::PacketBuffer::new(rx_meta, rx_buffer),
                 icmp::PacketBuffer::new(tx_meta, tx_buffer),
             ))
@@ -77,6 +77,14 @@ impl<'a> IcmpSocket<'a> {
         Self { stack, handle }
     }
 
+    /// Bind the socket to a network interface.
+    ///
+    /// Sockets are created on the default interface, and stay there unless bound to another one.
+    pub fn bind_to_interface(&mut self, id: InterfaceId) -> Result<(), InterfaceBindError> {
+        self.handle = self.stack.with_mut(|i| i.bind_socket(self.handle, id))?;
+        Ok(())
+    }
+
     /// Bind the socket to the given endpoint.
     pub fn bind<T>(&mut self, endpoint: T) -> Result<(), BindError>
     where
@@ -96,19 +104,11 @@ impl<'a> IcmpSocket<'a> {
     }
 
     fn with<R>(&self, f: impl FnOnce(&icmp::Socket, &Interface) -> R) -> R {
-        self.stack.with(|i| {
-            let socket = i.sockets.get::<icmp::Socket>(self.handle);
-            f(socket, &i.iface)
-        })
+        self.stack.with(|i| i.with_socket(self.handle, f))
     }
 
     fn with_mut<R>(&self, f: impl FnOnce(&mut icmp::Socket, &mut Interface) -> R) -> R {
-        self.stack.with_mut(|i| {
-            let socket = i.sockets.get_mut::<icmp::Socket>(self.handle);
-            let res = f(socket, &mut i.iface);
-            i.waker.wake();
-            res
-        })
+        self.stack.with_mut(|i| i.with_socket_mut(self.handle, f))
     }
 
     /// Wait until the socket becomes readable.
@@ -366,7 +366,7 @@ impl<'a> IcmpSocket<'a> {
 
 impl Drop for IcmpSocket<'_> {
     fn drop(&mut self) {
-        self.stack.with_mut(|i| i.sockets.remove(self.handle));
+        self.stack.with_mut(|i| i.remove_socket(self.handle));
     }
 }
 
@@ -378,7 +378,7 @@ pub mod ping {
     //!
     //! ## Usage
     //!
-    //! ```
+    //! ```ignore
     //! use core::net::Ipv4Addr;
     //! use core::str::FromStr;
     //!
@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![allow(async_fn_in_trait)]
 #![warn(missing_docs)]
 #![doc = include_str!("../README.md")]
@@ -12,18 +12,48 @@ compile_error!("You must enable at least one of the following features: proto-ip
 // This mod MUST go first, so that the others see its macros.
 pub(crate) mod fmt;
 
+#[cfg(feature = "autoip")]
+mod autoip;
+#[cfg(feature = "coap")]
+pub mod coap;
+#[cfg(feature = "dhcpv4-server")]
+pub mod dhcp_server;
+#[cfg(feature = "dhcpv6")]
+mod dhcpv6;
 #[cfg(feature = "dns")]
 pub mod dns;
+#[cfg(feature = "dns-server")]
+pub mod dns_server;
 mod driver_util;
+#[cfg(feature = "forwarding")]
+pub mod forward;
+#[cfg(feature = "http")]
+pub mod http;
 #[cfg(feature = "icmp")]
 pub mod icmp;
+#[cfg(feature = "mdns-responder")]
+pub mod mdns;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "pcap")]
+pub mod pcap;
 #[cfg(feature = "raw")]
 pub mod raw;
+#[cfg(feature = "slaac")]
+mod slaac;
+#[cfg(feature = "sntp")]
+pub mod sntp;
+#[cfg(feature = "statistics")]
+pub mod stats;
 #[cfg(feature = "tcp")]
 pub mod tcp;
 mod time;
+#[cfg(feature = "tls")]
+pub mod tls;
 #[cfg(feature = "udp")]
 pub mod udp;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
 use core::cell::RefCell;
 use core::future::{poll_fn, Future};
@@ -40,12 +70,11 @@ use heapless::Vec;
 pub use smoltcp::config::DNS_MAX_SERVER_COUNT;
 #[cfg(feature = "multicast")]
 pub use smoltcp::iface::MulticastError;
-#[cfg(any(feature = "dns", feature = "dhcpv4"))]
-use smoltcp::iface::SocketHandle;
-use smoltcp::iface::{Interface, SocketSet, SocketStorage};
+use smoltcp::iface::{Interface, SocketHandle, SocketSet, SocketStorage};
 use smoltcp::phy::Medium;
 #[cfg(feature = "dhcpv4")]
 use smoltcp::socket::dhcpv4::{self, RetryConfig};
+use smoltcp::socket::{AnySocket, Socket};
 #[cfg(feature = "medium-ethernet")]
 pub use smoltcp::wire::EthernetAddress;
 #[cfg(any(feature = "medium-ethernet", feature = "medium-ieee802154", feature = "medium-ip"))]
@@ -61,6 +90,8 @@ pub use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
 pub use smoltcp::wire::{Ipv6Address, Ipv6Cidr};
 
 use crate::driver_util::DriverAdapter;
+#[cfg(feature = "forwarding")]
+use crate::driver_util::{ForwardFn, Received};
 use crate::time::{instant_from_smoltcp, instant_to_smoltcp};
 
 const LOCAL_PORT_MIN: u16 = 1025;
@@ -69,15 +100,46 @@ const LOCAL_PORT_MAX: u16 = 65535;
 const MAX_QUERIES: usize = 4;
 #[cfg(feature = "dhcpv4-hostname")]
 const MAX_HOSTNAME_LEN: usize = 32;
+/// Maximum number of interfaces in a stack, including the default one.
+pub const MAX_INTERFACES: usize = 4;
+/// Maximum number of entries in the routing table.
+pub const MAX_ROUTES: usize = 8;
 
 /// Memory resources needed for a network stack.
+///
+/// `SOCK` is the number of sockets on the default interface. Interfaces added with
+/// [`Stack::add_interface`] have their own [`InterfaceResources`].
 pub struct StackResources<const SOCK: usize> {
-    sockets: MaybeUninit<[SocketStorage<'static>; SOCK]>,
+    interface: InterfaceResources<SOCK>,
     inner: MaybeUninit<RefCell<Inner>>,
     #[cfg(feature = "dns")]
     queries: MaybeUninit<[Option<dns::DnsQuery>; MAX_QUERIES]>,
+}
+
+impl<const SOCK: usize> StackResources<SOCK> {
+    /// Create a new set of stack resources.
+    pub const fn new() -> Self {
+        Self {
+            interface: InterfaceResources::new(),
+            inner: MaybeUninit::uninit(),
+            #[cfg(feature = "dns")]
+            queries: MaybeUninit::uninit(),
+        }
+    }
+}
+
+/// Memory resources needed for a network interface.
+pub struct InterfaceResources<const SOCK: usize> {
+    sockets: MaybeUninit<[SocketStorage<'static>; SOCK]>,
+    iface: MaybeUninit<Iface>,
     #[cfg(feature = "dhcpv4-hostname")]
     hostname: HostnameResources,
+    #[cfg(feature = "slaac")]
+    slaac: slaac::SlaacResources,
+    #[cfg(feature = "dhcpv6")]
+    dhcpv6: dhcpv6::Dhcpv6Resources,
+    #[cfg(feature = "statistics-inspect")]
+    connections: MaybeUninit<[Option<stats::Connection>; SOCK]>,
 }
 
 #[cfg(feature = "dhcpv4-hostname")]
@@ -86,23 +148,83 @@ struct HostnameResources {
     data: MaybeUninit<[u8; MAX_HOSTNAME_LEN]>,
 }
 
-impl<const SOCK: usize> StackResources<SOCK> {
-    /// Create a new set of stack resources.
+impl<const SOCK: usize> InterfaceResources<SOCK> {
+    /// Create a new set of interface resources.
     pub const fn new() -> Self {
         Self {
             sockets: MaybeUninit::uninit(),
-            inner: MaybeUninit::uninit(),
-            #[cfg(feature = "dns")]
-            queries: MaybeUninit::uninit(),
+            iface: MaybeUninit::uninit(),
             #[cfg(feature = "dhcpv4-hostname")]
             hostname: HostnameResources {
                 option: MaybeUninit::uninit(),
                 data: MaybeUninit::uninit(),
             },
+            #[cfg(feature = "slaac")]
+            slaac: slaac::SlaacResources::new(),
+            #[cfg(feature = "dhcpv6")]
+            dhcpv6: dhcpv6::Dhcpv6Resources::new(),
+            #[cfg(feature = "statistics-inspect")]
+            connections: MaybeUninit::uninit(),
         }
     }
 }
 
+impl<const SOCK: usize> Default for InterfaceResources<SOCK> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Identifies a network interface of a [`Stack`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InterfaceId(u8);
+
+impl InterfaceId {
+    /// The interface the stack was created with in [`new`].
+    pub const DEFAULT: Self = Self(0);
+}
+
+/// Routing table entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Route {
+    /// Destination network.
+    pub destination: IpCidr,
+    /// Next hop, or `None` if the destination is directly reachable through the interface.
+    pub gateway: Option<IpAddress>,
+    /// Egress interface.
+    pub interface: InterfaceId,
+}
+
+/// Error returned by [`Stack::add_route`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RouteError {
+    /// The routing table already has [`MAX_ROUTES`] entries.
+    TableFull,
+    /// The interface doesn't exist.
+    InvalidInterface,
+}
+
+/// Error returned by [`Stack::add_interface`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AddInterfaceError {
+    /// The stack already has [`MAX_INTERFACES`] interfaces.
+    TooManyInterfaces,
+}
+
+/// Error returned when binding a socket to an interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum InterfaceBindError {
+    /// The interface doesn't exist.
+    InvalidInterface,
+    /// The interface has no free socket slot.
+    NoFreeSocket,
+}
+
 /// Static IP address configuration.
 #[cfg(feature = "proto-ipv4")]
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -150,6 +272,11 @@ pub struct DhcpConfig {
     /// Our hostname. This will be sent to the DHCP server as Option 12.
     #[cfg(feature = "dhcpv4-hostname")]
     pub hostname: Option<heapless::String<MAX_HOSTNAME_LEN>>,
+    /// Fall back to a link-local address when no lease is obtained.
+    ///
+    /// The DHCP client keeps running, and replaces the link-local address when it gets a lease.
+    #[cfg(feature = "autoip")]
+    pub link_local_fallback: Option<LinkLocalConfig>,
 }
 
 #[cfg(feature = "dhcpv4")]
@@ -163,6 +290,91 @@ impl Default for DhcpConfig {
             client_port: smoltcp::wire::DHCP_CLIENT_PORT,
             #[cfg(feature = "dhcpv4-hostname")]
             hostname: None,
+            #[cfg(feature = "autoip")]
+            link_local_fallback: None,
+        }
+    }
+}
+
+/// IPv4 link-local address configuration (RFC 3927).
+#[cfg(feature = "autoip")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LinkLocalConfig {
+    /// Address to try first, for example the one used before a reboot.
+    ///
+    /// If `None` or not a valid link-local address, a pseudo-random address derived from the
+    /// hardware address is used.
+    pub address: Option<Ipv4Address>,
+    /// When used as a DHCP fallback, how long to wait for a lease before probing for a
+    /// link-local address.
+    pub dhcp_timeout: embassy_time::Duration,
+}
+
+#[cfg(feature = "autoip")]
+impl Default for LinkLocalConfig {
+    fn default() -> Self {
+        Self {
+            address: None,
+            dhcp_timeout: embassy_time::Duration::from_secs(10),
+        }
+    }
+}
+
+/// IPv6 stateless address autoconfiguration (SLAAC) configuration.
+#[cfg(feature = "slaac")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SlaacConfig {
+    /// Number of Router Solicitations sent at `router_solicitation_interval`, before backing off
+    /// exponentially up to one hour between solicitations.
+    pub max_router_solicitations: u8,
+    /// Interval between Router Solicitations.
+    pub router_solicitation_interval: embassy_time::Duration,
+    /// Number of Neighbor Solicitations sent for Duplicate Address Detection. 0 disables DAD.
+    pub dad_transmits: u8,
+}
+
+#[cfg(feature = "slaac")]
+impl Default for SlaacConfig {
+    fn default() -> Self {
+        Self {
+            max_router_solicitations: 3,
+            router_solicitation_interval: embassy_time::Duration::from_secs(4),
+            dad_transmits: 1,
+        }
+    }
+}
+
+/// DHCPv6 client mode.
+#[cfg(feature = "dhcpv6")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dhcpv6Mode {
+    /// Request an address (IA_NA) and DNS servers.
+    Stateful,
+    /// Autoconfigure the address with SLAAC, and only request DNS servers (Information-Request).
+    Stateless,
+}
+
+/// DHCPv6 configuration.
+#[cfg(feature = "dhcpv6")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Dhcpv6Config {
+    /// Whether to request an address, or only other configuration.
+    pub mode: Dhcpv6Mode,
+    /// Router discovery configuration.
+    ///
+    /// DHCPv6 doesn't provide a default gateway, it is learned from Router Advertisements.
+    pub router_discovery: SlaacConfig,
+}
+
+#[cfg(feature = "dhcpv6")]
+impl Default for Dhcpv6Config {
+    fn default() -> Self {
+        Self {
+            mode: Dhcpv6Mode::Stateful,
+            router_discovery: Default::default(),
         }
     }
 }
@@ -215,6 +427,54 @@ impl Config {
             ipv6: ConfigV6::None,
         }
     }
+
+    /// IPv4 configuration with a link-local address.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use embassy_net::Config;
+    /// let _cfg = Config::link_local(Default::default());
+    /// ```
+    #[cfg(feature = "autoip")]
+    pub const fn link_local(config: LinkLocalConfig) -> Self {
+        Self {
+            ipv4: ConfigV4::LinkLocal(config),
+            #[cfg(feature = "proto-ipv6")]
+            ipv6: ConfigV6::None,
+        }
+    }
+
+    /// IPv6 configuration with stateless address autoconfiguration.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use embassy_net::Config;
+    /// let _cfg = Config::slaac(Default::default());
+    /// ```
+    #[cfg(feature = "slaac")]
+    pub const fn slaac(config: SlaacConfig) -> Self {
+        Self {
+            #[cfg(feature = "proto-ipv4")]
+            ipv4: ConfigV4::None,
+            ipv6: ConfigV6::Slaac(config),
+        }
+    }
+
+    /// IPv6 configuration with DHCPv6.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use embassy_net::Config;
+    /// let _cfg = Config::dhcpv6(Default::default());
+    /// ```
+    #[cfg(feature = "dhcpv6")]
+    pub const fn dhcpv6(config: Dhcpv6Config) -> Self {
+        Self {
+            #[cfg(feature = "proto-ipv4")]
+            ipv4: ConfigV4::None,
+            ipv6: ConfigV6::Dhcp(config),
+        }
+    }
 }
 
 /// Network stack IPv4 configuration.
@@ -229,6 +489,9 @@ pub enum ConfigV4 {
     /// Use DHCP to obtain an IP address configuration.
     #[cfg(feature = "dhcpv4")]
     Dhcp(DhcpConfig),
+    /// Use a link-local address in 169.254.0.0/16, picked and defended with ARP.
+    #[cfg(feature = "autoip")]
+    LinkLocal(LinkLocalConfig),
 }
 
 /// Network stack IPv6 configuration.
@@ -240,14 +503,22 @@ pub enum ConfigV6 {
     None,
     /// Use a static IPv6 address configuration.
     Static(StaticConfigV6),
+    /// Use stateless address autoconfiguration from Router Advertisements.
+    #[cfg(feature = "slaac")]
+    Slaac(SlaacConfig),
+    /// Use DHCPv6 to obtain an address or DNS servers.
+    #[cfg(feature = "dhcpv6")]
+    Dhcp(Dhcpv6Config),
 }
 
 /// Network stack runner.
 ///
 /// You must call [`Runner::run()`] in a background task for the network stack to work.
+/// There's one runner per interface.
 pub struct Runner<'d, D: Driver> {
     driver: D,
     stack: Stack<'d>,
+    id: InterfaceId,
 }
 
 /// Network stack handle
@@ -259,15 +530,39 @@ pub struct Stack<'d> {
     inner: &'d RefCell<Inner>,
 }
 
+/// Handle to one of the network interfaces of a [`Stack`].
+///
+/// It's `Copy`, so you can pass it by value instead of by reference.
+#[derive(Copy, Clone)]
+pub struct InterfaceHandle<'d> {
+    stack: Stack<'d>,
+    id: InterfaceId,
+}
+
 pub(crate) struct Inner {
-    pub(crate) sockets: SocketSet<'static>, // Lifetime type-erased.
-    pub(crate) iface: Interface,
-    /// Waker used for triggering polls.
-    pub(crate) waker: WakerRegistration,
+    ifaces: Vec<&'static mut Iface, MAX_INTERFACES>, // Lifetime type-erased.
+    routes: Vec<Route, MAX_ROUTES>,
     /// Waker used for waiting for link up or config up.
     state_waker: WakerRegistration,
-    hardware_address: HardwareAddress,
     next_local_port: u16,
+    random_seed: u64,
+    #[cfg(feature = "dns")]
+    dns_socket: SocketId,
+    #[cfg(feature = "dns")]
+    dns_waker: WakerRegistration,
+    #[cfg(feature = "dns-cache")]
+    dns_cache: RefCell<dns::Cache>,
+    #[cfg(feature = "forwarding")]
+    forwarding: Option<forward::Forwarding>,
+}
+
+pub(crate) struct Iface {
+    sockets: SocketSet<'static>, // Lifetime type-erased.
+    socket_capacity: usize,
+    iface: Interface,
+    /// Waker used for triggering polls.
+    waker: WakerRegistration,
+    hardware_address: HardwareAddress,
     link_up: bool,
     #[cfg(feature = "proto-ipv4")]
     static_v4: Option<StaticConfigV4>,
@@ -275,18 +570,77 @@ pub(crate) struct Inner {
     static_v6: Option<StaticConfigV6>,
     #[cfg(feature = "dhcpv4")]
     dhcp_socket: Option<SocketHandle>,
-    #[cfg(feature = "dns")]
-    dns_socket: SocketHandle,
-    #[cfg(feature = "dns")]
-    dns_waker: WakerRegistration,
     #[cfg(feature = "dhcpv4-hostname")]
     hostname: *mut HostnameResources,
+    #[cfg(feature = "autoip")]
+    autoip: Option<autoip::AutoIp>,
+    #[cfg(feature = "slaac")]
+    slaac: Option<slaac::Slaac>,
+    #[cfg(feature = "slaac")]
+    slaac_resources: *mut slaac::SlaacResources,
+    #[cfg(feature = "slaac")]
+    random_seed: u64,
+    #[cfg(feature = "dhcpv6")]
+    dhcpv6: Option<dhcpv6::Dhcpv6>,
+    #[cfg(feature = "dhcpv6")]
+    dhcpv6_resources: *mut dhcpv6::Dhcpv6Resources,
+    #[cfg(feature = "statistics")]
+    stats: RefCell<stats::Recorder>,
+}
+
+/// A socket in the socket set of one of the interfaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SocketId {
+    iface: InterfaceId,
+    handle: SocketHandle,
+}
+
+/// State changes found while polling an interface.
+#[derive(Default)]
+struct PollChanges {
+    link: bool,
+    config: bool,
 }
 
 fn _assert_covariant<'a, 'b: 'a>(x: Stack<'b>) -> Stack<'a> {
     x
 }
 
+/// Select the interface to send to `addr` through, among `ifaces`. See [`Stack::route`].
+fn route_addr<'a>(
+    addr: IpAddress,
+    routes: &[Route],
+    ifaces: impl Iterator<Item = (InterfaceId, &'a Iface)> + Clone,
+) -> Option<InterfaceId> {
+    let mut best: Option<(u8, InterfaceId)> = None;
+    let mut consider = |cidr: &IpCidr, id: InterfaceId| {
+        if cidr.contains_addr(&addr) && best.is_none_or(|(len, _)| cidr.prefix_len() > len) {
+            best = Some((cidr.prefix_len(), id));
+        }
+    };
+
+    for route in routes {
+        consider(&route.destination, route.interface);
+    }
+    for (id, iface) in ifaces.clone() {
+        for cidr in iface.iface.ip_addrs() {
+            consider(cidr, id);
+        }
+    }
+    if let Some((_, id)) = best {
+        return Some(id);
+    }
+
+    ifaces
+        .filter(|(_, iface)| iface.link_up && iface.has_default_gateway(&addr))
+        .map(|(id, _)| id)
+        .next()
+}
+
+unsafe fn transmute_slice<T>(x: &mut [T]) -> &'static mut [T] {
+    core::mem::transmute(x)
+}
+
 /// Create a new network stack.
 pub fn new<'d, D: Driver, const SOCK: usize>(
     mut driver: D,
@@ -294,69 +648,52 @@ pub fn new<'d, D: Driver, const SOCK: usize>(
     resources: &'d mut StackResources<SOCK>,
     random_seed: u64,
 ) -> (Stack<'d>, Runner<'d, D>) {
-    let (hardware_address, medium) = to_smoltcp_hardware_address(driver.hardware_address());
-    let mut iface_cfg = smoltcp::iface::Config::new(hardware_address);
-    iface_cfg.random_seed = random_seed;
-
-    let iface = Interface::new(
-        iface_cfg,
-        &mut DriverAdapter {
-            inner: &mut driver,
-            cx: None,
-            medium,
-        },
-        instant_to_smoltcp(Instant::now()),
-    );
-
-    unsafe fn transmute_slice<T>(x: &mut [T]) -> &'static mut [T] {
-        core::mem::transmute(x)
-    }
-
-    let sockets = resources.sockets.write([SocketStorage::EMPTY; SOCK]);
-    #[allow(unused_mut)]
-    let mut sockets: SocketSet<'static> = SocketSet::new(unsafe { transmute_slice(sockets) });
+    // safety: the interface resources are borrowed for `'d`, like the stack.
+    let iface = unsafe { Iface::new(&mut driver, &mut resources.interface, random_seed) };
 
     let next_local_port = (random_seed % (LOCAL_PORT_MAX - LOCAL_PORT_MIN) as u64) as u16 + LOCAL_PORT_MIN;
 
     #[cfg(feature = "dns")]
-    let dns_socket = sockets.add(dns::Socket::new(
-        &[],
-        managed::ManagedSlice::Borrowed(unsafe {
-            transmute_slice(resources.queries.write([const { None }; MAX_QUERIES]))
-        }),
-    ));
+    let dns_socket = SocketId {
+        iface: InterfaceId::DEFAULT,
+        handle: iface.sockets.add(dns::Socket::new(
+            &[],
+            managed::ManagedSlice::Borrowed(unsafe {
+                transmute_slice(resources.queries.write([const { None }; MAX_QUERIES]))
+            }),
+        )),
+    };
+
+    let mut ifaces = Vec::new();
+    unwrap!(ifaces.push(iface).ok());
 
     let mut inner = Inner {
-        sockets,
-        iface,
-        waker: WakerRegistration::new(),
+        ifaces,
+        routes: Vec::new(),
         state_waker: WakerRegistration::new(),
         next_local_port,
-        hardware_address,
-        link_up: false,
-        #[cfg(feature = "proto-ipv4")]
-        static_v4: None,
-        #[cfg(feature = "proto-ipv6")]
-        static_v6: None,
-        #[cfg(feature = "dhcpv4")]
-        dhcp_socket: None,
+        random_seed,
         #[cfg(feature = "dns")]
         dns_socket,
         #[cfg(feature = "dns")]
         dns_waker: WakerRegistration::new(),
-        #[cfg(feature = "dhcpv4-hostname")]
-        hostname: &mut resources.hostname,
+        #[cfg(feature = "dns-cache")]
+        dns_cache: RefCell::new(dns::Cache::new()),
+        #[cfg(feature = "forwarding")]
+        forwarding: None,
     };
-
-    #[cfg(feature = "proto-ipv4")]
-    inner.set_config_v4(config.ipv4);
-    #[cfg(feature = "proto-ipv6")]
-    inner.set_config_v6(config.ipv6);
-    inner.apply_static_config();
+    inner.set_config(InterfaceId::DEFAULT, config);
 
     let inner = &*resources.inner.write(RefCell::new(inner));
     let stack = Stack { inner };
-    (stack, Runner { driver, stack })
+    (
+        stack,
+        Runner {
+            driver,
+            stack,
+            id: InterfaceId::DEFAULT,
+        },
+    )
 }
 
 fn to_smoltcp_hardware_address(addr: driver::HardwareAddress) -> (HardwareAddress, Medium) {
@@ -388,54 +725,171 @@ impl<'d> Stack<'d> {
         f(&mut self.inner.borrow_mut())
     }
 
-    /// Get the hardware address of the network interface.
+    /// Add a network interface to the stack.
+    ///
+    /// The interface has its own IP configuration and link state. Sockets are created on the
+    /// default interface. TCP sockets move to the interface selected by [`Stack::route`] when
+    /// they connect, and UDP sockets to the interface that has the address they're bound to,
+    /// unless they're bound to an interface.
+    ///
+    /// You must call [`Runner::run()`] for the returned runner in a background task, like for
+    /// the default interface.
+    pub fn add_interface<D: Driver, const SOCK: usize>(
+        &self,
+        mut driver: D,
+        config: Config,
+        resources: &'static mut InterfaceResources<SOCK>,
+    ) -> Result<(InterfaceId, Runner<'d, D>), AddInterfaceError> {
+        let id = self.with_mut(|i| {
+            if i.ifaces.len() >= MAX_INTERFACES {
+                return Err(AddInterfaceError::TooManyInterfaces);
+            }
+            let id = InterfaceId(i.ifaces.len() as u8);
+            let random_seed = i.random_seed ^ (id.0 as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+
+            // safety: the resources are 'static.
+            let iface = unsafe { Iface::new(&mut driver, resources, random_seed) };
+            unwrap!(i.ifaces.push(iface).ok());
+            i.set_config(id, config);
+            Ok(id)
+        })?;
+        Ok((
+            id,
+            Runner {
+                driver,
+                stack: *self,
+                id,
+            },
+        ))
+    }
+
+    /// Get a handle to one of the interfaces of the stack.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the stack has no interface with this id.
+    pub fn interface(&self, id: InterfaceId) -> InterfaceHandle<'d> {
+        assert!(self.with(|i| (id.0 as usize) < i.ifaces.len()), "invalid interface");
+        InterfaceHandle { stack: *self, id }
+    }
+
+    fn default_interface(&self) -> InterfaceHandle<'d> {
+        InterfaceHandle {
+            stack: *self,
+            id: InterfaceId::DEFAULT,
+        }
+    }
+
+    /// Add an entry to the routing table.
+    ///
+    /// Routes select the interface sockets send through. Routes with a gateway are also
+    /// installed on the interface, which needs room for them in smoltcp's `IFACE_MAX_ROUTE_COUNT`.
+    /// An existing route to the same destination is replaced.
+    pub fn add_route(&self, route: Route) -> Result<(), RouteError> {
+        self.with_mut(|i| {
+            if route.interface.0 as usize >= i.ifaces.len() {
+                return Err(RouteError::InvalidInterface);
+            }
+            let old = match i.routes.iter().position(|r| r.destination == route.destination) {
+                Some(n) => Some(i.routes.remove(n)),
+                None if i.routes.is_full() => return Err(RouteError::TableFull),
+                None => None,
+            };
+            let interface = route.interface;
+            unwrap!(i.routes.push(route).ok());
+
+            if let Some(old) = old {
+                i.apply_routes(old.interface);
+            }
+            i.apply_routes(interface);
+            Ok(())
+        })
+    }
+
+    /// Remove the route to `destination` from the routing table.
+    ///
+    /// Returns the removed route, if any.
+    pub fn remove_route(&self, destination: IpCidr) -> Option<Route> {
+        self.with_mut(|i| {
+            let n = i.routes.iter().position(|r| r.destination == destination)?;
+            let route = i.routes.remove(n);
+            i.apply_routes(route.interface);
+            Some(route)
+        })
+    }
+
+    /// Get the routing table.
+    pub fn routes(&self) -> Vec<Route, MAX_ROUTES> {
+        self.with(|i| i.routes.clone())
+    }
+
+    /// Get the interface that traffic to `addr` is sent through.
+    ///
+    /// This is the most specific match among the routing table and the subnets of the interfaces.
+    /// If there's none, it's the first interface with link up and a default gateway for the
+    /// address family of `addr`.
+    pub fn route(&self, addr: IpAddress) -> Option<InterfaceId> {
+        self.with(|i| i.route(addr))
+    }
+
+    /// Enable forwarding IPv4 packets between the interfaces of the stack.
+    ///
+    /// While an interface has forwarded packets to send, they take up to 3 of its socket slots.
+    /// Packets are dropped when there are none free. See the [`forward`] module for details.
+    ///
+    /// If forwarding is already enabled, the configuration is replaced and all translated
+    /// connections are forgotten.
+    #[cfg(feature = "forwarding")]
+    pub fn enable_forwarding<const CONN: usize, const QUEUE: usize>(
+        &self,
+        config: forward::Config,
+        resources: &'static mut forward::Resources<CONN, QUEUE>,
+    ) {
+        self.with_mut(|i| {
+            i.disable_forwarding();
+            i.forwarding = Some(forward::Forwarding::new(config, resources));
+        })
+    }
+
+    /// Disable forwarding IPv4 packets between the interfaces of the stack.
+    #[cfg(feature = "forwarding")]
+    pub fn disable_forwarding(&self) {
+        self.with_mut(|i| i.disable_forwarding())
+    }
+
+    /// Get the hardware address of the default network interface.
     pub fn hardware_address(&self) -> HardwareAddress {
-        self.with(|i| i.hardware_address)
+        self.default_interface().hardware_address()
     }
 
-    /// Check whether the link is up.
+    /// Check whether the link of the default interface is up.
     pub fn is_link_up(&self) -> bool {
-        self.with(|i| i.link_up)
+        self.default_interface().is_link_up()
+    }
+
+    /// Get the statistics of the default interface.
+    #[cfg(feature = "statistics")]
+    pub fn stats(&self) -> stats::InterfaceStats {
+        self.default_interface().stats()
     }
 
-    /// Check whether the network stack has a valid IP configuration.
+    /// Check whether the default interface has a valid IP configuration.
     /// This is true if the network stack has a static IP configuration or if DHCP has completed
     pub fn is_config_up(&self) -> bool {
-        let v4_up;
-        let v6_up;
-
-        #[cfg(feature = "proto-ipv4")]
-        {
-            v4_up = self.config_v4().is_some();
-        }
-        #[cfg(not(feature = "proto-ipv4"))]
-        {
-            v4_up = false;
-        }
-
-        #[cfg(feature = "proto-ipv6")]
-        {
-            v6_up = self.config_v6().is_some();
-        }
-        #[cfg(not(feature = "proto-ipv6"))]
-        {
-            v6_up = false;
-        }
-
-        v4_up || v6_up
+        self.default_interface().is_config_up()
     }
 
-    /// Wait for the network device to obtain a link signal.
+    /// Wait for the default network device to obtain a link signal.
     pub async fn wait_link_up(&self) {
         self.wait(|| self.is_link_up()).await
     }
 
-    /// Wait for the network device to lose link signal.
+    /// Wait for the default network device to lose link signal.
     pub async fn wait_link_down(&self) {
         self.wait(|| !self.is_link_up()).await
     }
 
-    /// Wait for the network stack to obtain a valid IP configuration.
+    /// Wait for the default interface to obtain a valid IP configuration.
     ///
     /// ## Notes:
     /// - Ensure [`Runner::run`] has been started before using this function.
@@ -447,7 +901,7 @@ impl<'d> Stack<'d> {
     /// ```ignore
     /// let config = embassy_net::Config::dhcpv4(Default::default());
     /// // Init network stack
-    /// // NOTE: DHCP and DNS need one socket slot if enabled. This is why we're
+    /// // NOTE: DHCP, SLAAC and DNS need one socket slot if enabled, DHCPv6 needs two. This is why we're
     /// // provisioning space for 3 sockets here: one for DHCP, one for DNS, and one for your code (e.g. TCP).
     /// // If you use more sockets you must increase this. If you don't enable DHCP or DNS you can decrease it.
     /// static RESOURCES: StaticCell<embassy_net::StackResources<3>> = StaticCell::new();
@@ -468,7 +922,7 @@ impl<'d> Stack<'d> {
         self.wait(|| self.is_config_up()).await
     }
 
-    /// Wait for the network stack to lose a valid IP configuration.
+    /// Wait for the default interface to lose a valid IP configuration.
     pub async fn wait_config_down(&self) {
         self.wait(|| !self.is_config_up()).await
     }
@@ -491,40 +945,42 @@ impl<'d> Stack<'d> {
         })
     }
 
-    /// Get the current IPv4 configuration.
+    /// Get the current IPv4 configuration of the default interface.
     ///
     /// If using DHCP, this will be None if DHCP hasn't been able to
     /// acquire an IP address, or Some if it has.
     #[cfg(feature = "proto-ipv4")]
     pub fn config_v4(&self) -> Option<StaticConfigV4> {
-        self.with(|i| i.static_v4.clone())
+        self.default_interface().config_v4()
     }
 
-    /// Get the current IPv6 configuration.
+    /// Get the current IPv6 configuration of the default interface.
+    ///
+    /// If using SLAAC, this will be None until a Router Advertisement has provided
+    /// a prefix and Duplicate Address Detection has completed. If using DHCPv6, this
+    /// will be None until an address has been leased, or autoconfigured in stateless mode.
     #[cfg(feature = "proto-ipv6")]
     pub fn config_v6(&self) -> Option<StaticConfigV6> {
-        self.with(|i| i.static_v6.clone())
+        self.default_interface().config_v6()
     }
 
-    /// Set the IPv4 configuration.
+    /// Set the IPv4 configuration of the default interface.
     #[cfg(feature = "proto-ipv4")]
     pub fn set_config_v4(&self, config: ConfigV4) {
-        self.with_mut(|i| {
-            i.set_config_v4(config);
-            i.apply_static_config();
-        })
+        self.default_interface().set_config_v4(config)
     }
 
-    /// Set the IPv6 configuration.
+    /// Set the IPv6 configuration of the default interface.
     #[cfg(feature = "proto-ipv6")]
     pub fn set_config_v6(&self, config: ConfigV6) {
-        self.with_mut(|i| {
-            i.set_config_v6(config);
-            i.apply_static_config();
-        })
+        self.default_interface().set_config_v6(config)
     }
 
     /// Make a query for a given name and return the corresponding IP addresses.
+    ///
+    /// The DNS servers of the first interface that has any are used.
+    ///
+    /// With the `dns-cache` feature, answers are kept for their time to live.
     #[cfg(feature = "dns")]
     pub async fn dns_query(
         &self,
@@ -548,12 +1004,20 @@ impl<'d> Stack<'d> {
             _ => {}
         }
 
+        #[cfg(feature = "dns-cache")]
+        if let Some(addrs) = self.with_mut(|i| i.dns_cache.get_mut().get(name, qtype, Instant::now())) {
+            return Ok(addrs);
+        }
+
         let query = poll_fn(|cx| {
             self.with_mut(|i| {
-                let socket = i.sockets.get_mut::<dns::Socket>(i.dns_socket);
-                match socket.start_query(i.iface.context(), name, qtype) {
+                let res = i.with_socket_mut(i.dns_socket, |socket: &mut dns::Socket, iface| {
+                    socket.start_query(iface.context(), name, qtype)
+                });
+                match res {
                     Ok(handle) => {
-                        i.waker.wake();
+                        #[cfg(feature = "dns-cache")]
+                        i.dns_cache.get_mut().start(name, qtype);
                         Poll::Ready(Ok(handle))
                     }
                     Err(dns::StartQueryError::NoFreeSlot) => {
@@ -591,30 +1055,34 @@ impl<'d> Stack<'d> {
 
         let drop = OnDrop::new(|| {
             self.with_mut(|i| {
-                let socket = i.sockets.get_mut::<dns::Socket>(i.dns_socket);
-                socket.cancel_query(query);
-                i.waker.wake();
+                i.with_socket_mut(i.dns_socket, |socket: &mut dns::Socket, _| socket.cancel_query(query));
+                #[cfg(feature = "dns-cache")]
+                i.dns_cache.get_mut().cancel(name, qtype);
                 i.dns_waker.wake();
             })
         });
 
         let res = poll_fn(|cx| {
             self.with_mut(|i| {
-                let socket = i.sockets.get_mut::<dns::Socket>(i.dns_socket);
-                match socket.get_query_result(query) {
-                    Ok(addrs) => {
-                        i.dns_waker.wake();
-                        Poll::Ready(Ok(addrs))
-                    }
-                    Err(dns::GetQueryResultError::Pending) => {
-                        socket.register_query_waker(query, cx.waker());
-                        Poll::Pending
+                let res = i.with_socket_mut(i.dns_socket, |socket: &mut dns::Socket, _| {
+                    match socket.get_query_result(query) {
+                        Ok(addrs) => Poll::Ready(Ok(addrs)),
+                        Err(dns::GetQueryResultError::Pending) => {
+                            socket.register_query_waker(query, cx.waker());
+                            Poll::Pending
+                        }
+                        Err(e) => Poll::Ready(Err(e.into())),
                     }
-                    Err(e) => {
-                        i.dns_waker.wake();
-                        Poll::Ready(Err(e.into()))
+                });
+                if res.is_ready() {
+                    #[cfg(feature = "dns-cache")]
+                    match &res {
+                        Poll::Ready(Ok(addrs)) => i.dns_cache.get_mut().complete(name, qtype, addrs, Instant::now()),
+                        _ => i.dns_cache.get_mut().cancel(name, qtype),
                     }
+                    i.dns_waker.wake();
                 }
+                res
             })
         })
         .await;
@@ -623,34 +1091,470 @@ impl<'d> Stack<'d> {
 
         res
     }
+
+    /// Forget the answers cached by [`Stack::dns_query`].
+    ///
+    /// The cache is also flushed when the IP configuration of any interface changes.
+    #[cfg(feature = "dns-cache")]
+    pub fn flush_dns_cache(&self) {
+        self.with_mut(|i| i.dns_cache.get_mut().flush())
+    }
 }
 
 #[cfg(feature = "multicast")]
-impl<'d> Stack<'d> {
-    /// Join a multicast group.
+impl Stack<'_> {
+    /// Join a multicast group on the default interface.
     pub fn join_multicast_group(&self, addr: impl Into<IpAddress>) -> Result<(), MulticastError> {
-        self.with_mut(|i| i.iface.join_multicast_group(addr))
+        self.default_interface().join_multicast_group(addr)
     }
 
-    /// Leave a multicast group.
+    /// Leave a multicast group on the default interface.
     pub fn leave_multicast_group(&self, addr: impl Into<IpAddress>) -> Result<(), MulticastError> {
-        self.with_mut(|i| i.iface.leave_multicast_group(addr))
+        self.default_interface().leave_multicast_group(addr)
     }
 
-    /// Get whether the network stack has joined the given multicast group.
+    /// Get whether the default interface has joined the given multicast group.
     pub fn has_multicast_group(&self, addr: impl Into<IpAddress>) -> bool {
-        self.with(|i| i.iface.has_multicast_group(addr))
+        self.default_interface().has_multicast_group(addr)
     }
 }
 
-impl Inner {
-    #[allow(clippy::absurd_extreme_comparisons)]
-    pub fn get_local_port(&mut self) -> u16 {
-        let res = self.next_local_port;
-        self.next_local_port = if res >= LOCAL_PORT_MAX { LOCAL_PORT_MIN } else { res + 1 };
+impl InterfaceHandle<'_> {
+    fn with<R>(&self, f: impl FnOnce(&Iface) -> R) -> R {
+        self.stack.with(|i| f(i.iface(self.id)))
+    }
+
+    /// Get the id of the interface.
+    pub fn id(&self) -> InterfaceId {
+        self.id
+    }
+
+    /// Get the hardware address of the interface.
+    pub fn hardware_address(&self) -> HardwareAddress {
+        self.with(|i| i.hardware_address)
+    }
+
+    /// Check whether the link is up.
+    pub fn is_link_up(&self) -> bool {
+        self.with(|i| i.link_up)
+    }
+
+    /// Get the statistics of the interface.
+    #[cfg(feature = "statistics")]
+    pub fn stats(&self) -> stats::InterfaceStats {
+        self.with(|i| i.stats.borrow().stats())
+    }
+
+    /// Check whether the interface has a valid IP configuration.
+    /// This is true if the interface has a static IP configuration or if DHCP has completed
+    pub fn is_config_up(&self) -> bool {
+        let v4_up;
+        let v6_up;
+
+        #[cfg(feature = "proto-ipv4")]
+        {
+            v4_up = self.config_v4().is_some();
+        }
+        #[cfg(not(feature = "proto-ipv4"))]
+        {
+            v4_up = false;
+        }
+
+        #[cfg(feature = "proto-ipv6")]
+        {
+            v6_up = self.config_v6().is_some();
+        }
+        #[cfg(not(feature = "proto-ipv6"))]
+        {
+            v6_up = false;
+        }
+
+        v4_up || v6_up
+    }
+
+    /// Wait for the network device to obtain a link signal.
+    pub async fn wait_link_up(&self) {
+        self.stack.wait(|| self.is_link_up()).await
+    }
+
+    /// Wait for the network device to lose link signal.
+    pub async fn wait_link_down(&self) {
+        self.stack.wait(|| !self.is_link_up()).await
+    }
+
+    /// Wait for the interface to obtain a valid IP configuration.
+    ///
+    /// See [`Stack::wait_config_up`].
+    pub async fn wait_config_up(&self) {
+        self.stack.wait(|| self.is_config_up()).await
+    }
+
+    /// Wait for the interface to lose a valid IP configuration.
+    pub async fn wait_config_down(&self) {
+        self.stack.wait(|| !self.is_config_up()).await
+    }
+
+    /// Get the current IPv4 configuration.
+    #[cfg(feature = "proto-ipv4")]
+    pub fn config_v4(&self) -> Option<StaticConfigV4> {
+        self.with(|i| i.static_v4.clone())
+    }
+
+    /// Get the current IPv6 configuration.
+    #[cfg(feature = "proto-ipv6")]
+    pub fn config_v6(&self) -> Option<StaticConfigV6> {
+        self.with(|i| i.static_v6.clone())
+    }
+
+    /// Set the IPv4 configuration.
+    #[cfg(feature = "proto-ipv4")]
+    pub fn set_config_v4(&self, config: ConfigV4) {
+        self.stack.with_mut(|i| {
+            i.iface_mut(self.id).set_config_v4(config);
+            i.apply_static_config(self.id);
+        })
+    }
+
+    /// Set the IPv6 configuration.
+    #[cfg(feature = "proto-ipv6")]
+    pub fn set_config_v6(&self, config: ConfigV6) {
+        self.stack.with_mut(|i| {
+            i.iface_mut(self.id).set_config_v6(config);
+            i.apply_static_config(self.id);
+        })
+    }
+}
+
+#[cfg(feature = "multicast")]
+impl InterfaceHandle<'_> {
+    /// Join a multicast group.
+    pub fn join_multicast_group(&self, addr: impl Into<IpAddress>) -> Result<(), MulticastError> {
+        self.stack
+            .with_mut(|i| i.iface_mut(self.id).iface.join_multicast_group(addr))
+    }
+
+    /// Leave a multicast group.
+    pub fn leave_multicast_group(&self, addr: impl Into<IpAddress>) -> Result<(), MulticastError> {
+        self.stack
+            .with_mut(|i| i.iface_mut(self.id).iface.leave_multicast_group(addr))
+    }
+
+    /// Get whether the interface has joined the given multicast group.
+    pub fn has_multicast_group(&self, addr: impl Into<IpAddress>) -> bool {
+        self.with(|i| i.iface.has_multicast_group(addr))
+    }
+}
+
+impl Inner {
+    #[allow(clippy::absurd_extreme_comparisons)]
+    pub fn get_local_port(&mut self) -> u16 {
+        let res = self.next_local_port;
+        self.next_local_port = if res >= LOCAL_PORT_MAX { LOCAL_PORT_MIN } else { res + 1 };
         res
     }
 
+    fn iface(&self, id: InterfaceId) -> &Iface {
+        self.ifaces[id.0 as usize]
+    }
+
+    fn iface_mut(&mut self, id: InterfaceId) -> &mut Iface {
+        self.ifaces[id.0 as usize]
+    }
+
+    /// Add a socket to the default interface.
+    pub(crate) fn add_socket<T: AnySocket<'static>>(&mut self, socket: T) -> SocketId {
+        let iface = InterfaceId::DEFAULT;
+        let handle = self.iface_mut(iface).sockets.add(socket);
+        SocketId { iface, handle }
+    }
+
+    pub(crate) fn remove_socket(&mut self, socket: SocketId) {
+        self.iface_mut(socket.iface).sockets.remove(socket.handle);
+    }
+
+    pub(crate) fn with_socket<T: AnySocket<'static>, R>(
+        &self,
+        socket: SocketId,
+        f: impl FnOnce(&T, &Interface) -> R,
+    ) -> R {
+        let iface = self.iface(socket.iface);
+        f(iface.sockets.get::<T>(socket.handle), &iface.iface)
+    }
+
+    /// Call `f` with a socket and its interface, and wake the interface to process any changes.
+    pub(crate) fn with_socket_mut<T: AnySocket<'static>, R>(
+        &mut self,
+        socket: SocketId,
+        f: impl FnOnce(&mut T, &mut Interface) -> R,
+    ) -> R {
+        let iface = self.iface_mut(socket.iface);
+        let res = f(iface.sockets.get_mut::<T>(socket.handle), &mut iface.iface);
+        iface.waker.wake();
+        res
+    }
+
+    /// Move a socket to another interface.
+    ///
+    /// Returns `None` if the interface has no free socket slot, in which case the socket isn't moved.
+    pub(crate) fn move_socket(&mut self, socket: SocketId, to: InterfaceId) -> Option<SocketId> {
+        if socket.iface == to {
+            return Some(socket);
+        }
+        if self.iface(to).sockets.iter().count() >= self.iface(to).socket_capacity {
+            return None;
+        }
+
+        let s = self.iface_mut(socket.iface).sockets.remove(socket.handle);
+        let iface = self.iface_mut(to);
+        #[allow(unreachable_patterns)]
+        let handle = match s {
+            #[cfg(feature = "tcp")]
+            Socket::Tcp(s) => iface.sockets.add(s),
+            #[cfg(feature = "udp")]
+            Socket::Udp(s) => iface.sockets.add(s),
+            #[cfg(feature = "icmp")]
+            Socket::Icmp(s) => iface.sockets.add(s),
+            #[cfg(feature = "raw")]
+            Socket::Raw(s) => iface.sockets.add(s),
+            #[cfg(feature = "dns")]
+            Socket::Dns(s) => iface.sockets.add(s),
+            _ => unreachable!(),
+        };
+        iface.waker.wake();
+        Some(SocketId { iface: to, handle })
+    }
+
+    /// Move a socket to the interface `to`, for binding it there.
+    pub(crate) fn bind_socket(&mut self, socket: SocketId, to: InterfaceId) -> Result<SocketId, InterfaceBindError> {
+        if to.0 as usize >= self.ifaces.len() {
+            return Err(InterfaceBindError::InvalidInterface);
+        }
+        self.move_socket(socket, to).ok_or(InterfaceBindError::NoFreeSocket)
+    }
+
+    /// Get the interface that has the address `addr`.
+    #[cfg(any(feature = "udp", feature = "tcp"))]
+    pub(crate) fn interface_with_addr(&self, addr: IpAddress) -> Option<InterfaceId> {
+        self.ifaces
+            .iter()
+            .position(|iface| iface.iface.has_ip_addr(addr))
+            .map(|n| InterfaceId(n as u8))
+    }
+
+    pub(crate) fn route(&self, addr: IpAddress) -> Option<InterfaceId> {
+        let ifaces = self
+            .ifaces
+            .iter()
+            .enumerate()
+            .map(|(n, i)| (InterfaceId(n as u8), &**i));
+        route_addr(addr, &self.routes, ifaces)
+    }
+
+    fn set_config(&mut self, id: InterfaceId, config: Config) {
+        let iface = self.iface_mut(id);
+        #[cfg(feature = "proto-ipv4")]
+        iface.set_config_v4(config.ipv4);
+        #[cfg(feature = "proto-ipv6")]
+        iface.set_config_v6(config.ipv6);
+        self.apply_static_config(id);
+    }
+
+    fn apply_static_config(&mut self, id: InterfaceId) {
+        self.ifaces[id.0 as usize].apply_static_config(id, &self.routes);
+        #[cfg(feature = "dns")]
+        self.update_dns();
+        self.state_waker.wake();
+    }
+
+    fn apply_routes(&mut self, id: InterfaceId) {
+        self.ifaces[id.0 as usize].apply_routes(id, &self.routes);
+    }
+
+    /// Use the DNS servers of the first interface that has any.
+    #[cfg(feature = "dns")]
+    fn update_dns(&mut self) {
+        // Answers from the previous configuration may not hold anymore.
+        #[cfg(feature = "dns-cache")]
+        self.dns_cache.get_mut().flush();
+
+        let Some((n, dns_servers)) = self.ifaces.iter().enumerate().find_map(|(n, iface)| {
+            let servers = iface.dns_servers();
+            (!servers.is_empty()).then_some((n, servers))
+        }) else {
+            return;
+        };
+
+        let to = InterfaceId(n as u8);
+        match self.move_socket(self.dns_socket, to) {
+            Some(socket) => self.dns_socket = socket,
+            None => warn!("No free socket slot for DNS on interface {:?}.", to),
+        }
+
+        let count = if dns_servers.len() > DNS_MAX_SERVER_COUNT {
+            warn!("Number of DNS servers exceeds DNS_MAX_SERVER_COUNT, truncating list.");
+            DNS_MAX_SERVER_COUNT
+        } else {
+            dns_servers.len()
+        };
+        self.with_socket_mut(self.dns_socket, |socket: &mut dns::Socket, _| {
+            socket.update_servers(&dns_servers[..count])
+        });
+    }
+
+    #[cfg(feature = "forwarding")]
+    fn disable_forwarding(&mut self) {
+        if let Some(mut forwarding) = self.forwarding.take() {
+            for (n, iface) in self.ifaces.iter_mut().enumerate() {
+                forwarding.remove_sockets(InterfaceId(n as u8), &mut iface.sockets);
+            }
+        }
+    }
+
+    fn poll<D: Driver>(&mut self, id: InterfaceId, cx: &mut Context<'_>, driver: &mut D) {
+        #[cfg(not(feature = "forwarding"))]
+        let changes = self.ifaces[id.0 as usize].poll(
+            cx,
+            driver,
+            #[cfg(feature = "dns-cache")]
+            &self.dns_cache,
+        );
+        #[cfg(feature = "forwarding")]
+        let changes = match &mut self.forwarding {
+            None => self.ifaces[id.0 as usize].poll(
+                cx,
+                driver,
+                None,
+                #[cfg(feature = "dns-cache")]
+                &self.dns_cache,
+            ),
+            Some(forwarding) => {
+                // Split the ingress interface from the ones packets are forwarded to.
+                let mut ingress = None;
+                let mut ifaces = Vec::new();
+                for (n, iface) in self.ifaces.iter_mut().enumerate() {
+                    let iface = if n == id.0 as usize {
+                        ingress = Some(iface);
+                        None
+                    } else {
+                        Some(&mut **iface)
+                    };
+                    unwrap!(ifaces.push(iface).ok());
+                }
+                let ingress = unwrap!(ingress);
+                let mut forwarder = forward::Forwarder {
+                    forwarding,
+                    routes: &self.routes,
+                    ingress: id,
+                    ingress_hardware_address: ingress.hardware_address,
+                    ingress_addrs: ingress.iface.ip_addrs().iter().copied().collect(),
+                    ifaces,
+                    #[cfg(feature = "statistics")]
+                    socket_set_full: 0,
+                };
+                let changes = ingress.poll(
+                    cx,
+                    driver,
+                    Some(&mut |frame| forwarder.forward(frame)),
+                    #[cfg(feature = "dns-cache")]
+                    &self.dns_cache,
+                );
+                // The packets forwarded through the interface have been sent, unless their next hop
+                // is being resolved.
+                forwarder.forwarding.release_sockets(id, &mut ingress.sockets);
+                #[cfg(feature = "statistics")]
+                ingress.stats.get_mut().socket_set_full(forwarder.socket_set_full);
+                changes
+            }
+        };
+        if changes.config {
+            self.apply_static_config(id);
+        } else if changes.link {
+            self.state_waker.wake();
+        }
+    }
+}
+
+impl Iface {
+    /// Create an interface in `resources`.
+    ///
+    /// # Safety
+    ///
+    /// `resources` must not be used again, and must outlive the returned reference.
+    unsafe fn new<D: Driver, const SOCK: usize>(
+        driver: &mut D,
+        resources: &mut InterfaceResources<SOCK>,
+        random_seed: u64,
+    ) -> &'static mut Iface {
+        let (hardware_address, medium) = to_smoltcp_hardware_address(driver.hardware_address());
+        let mut iface_cfg = smoltcp::iface::Config::new(hardware_address);
+        iface_cfg.random_seed = random_seed;
+
+        let iface = Interface::new(
+            iface_cfg,
+            &mut DriverAdapter {
+                inner: driver,
+                cx: None,
+                medium,
+                #[cfg(feature = "statistics")]
+                stats: None,
+                #[cfg(feature = "autoip")]
+                autoip: None,
+                #[cfg(feature = "dns-cache")]
+                dns_cache: None,
+            },
+            instant_to_smoltcp(Instant::now()),
+        );
+
+        let sockets = resources.sockets.write([SocketStorage::EMPTY; SOCK]);
+        let sockets: SocketSet<'static> = SocketSet::new(transmute_slice(sockets));
+        #[cfg(feature = "statistics-inspect")]
+        let connections = transmute_slice(resources.connections.write([const { None }; SOCK]));
+
+        let iface = resources.iface.write(Iface {
+            sockets,
+            socket_capacity: SOCK,
+            iface,
+            waker: WakerRegistration::new(),
+            hardware_address,
+            link_up: false,
+            #[cfg(feature = "proto-ipv4")]
+            static_v4: None,
+            #[cfg(feature = "proto-ipv6")]
+            static_v6: None,
+            #[cfg(feature = "dhcpv4")]
+            dhcp_socket: None,
+            #[cfg(feature = "dhcpv4-hostname")]
+            hostname: &mut resources.hostname,
+            #[cfg(feature = "autoip")]
+            autoip: None,
+            #[cfg(feature = "slaac")]
+            slaac: None,
+            #[cfg(feature = "slaac")]
+            slaac_resources: &mut resources.slaac,
+            #[cfg(feature = "slaac")]
+            random_seed,
+            #[cfg(feature = "dhcpv6")]
+            dhcpv6: None,
+            #[cfg(feature = "dhcpv6")]
+            dhcpv6_resources: &mut resources.dhcpv6,
+            #[cfg(feature = "statistics")]
+            stats: RefCell::new(stats::Recorder::new(
+                #[cfg(feature = "statistics-inspect")]
+                connections,
+            )),
+        });
+        core::mem::transmute::<&mut Iface, &'static mut Iface>(iface)
+    }
+
+    fn has_default_gateway(&self, addr: &IpAddress) -> bool {
+        match addr {
+            #[cfg(feature = "proto-ipv4")]
+            IpAddress::Ipv4(_) => self.static_v4.as_ref().is_some_and(|c| c.gateway.is_some()),
+            #[cfg(feature = "proto-ipv6")]
+            IpAddress::Ipv6(_) => self.static_v6.as_ref().is_some_and(|c| c.gateway.is_some()),
+        }
+    }
+
     #[cfg(feature = "proto-ipv4")]
     pub fn set_config_v4(&mut self, config: ConfigV4) {
         // Handle static config.
@@ -658,9 +1562,39 @@ impl Inner {
             ConfigV4::None => None,
             #[cfg(feature = "dhcpv4")]
             ConfigV4::Dhcp(_) => None,
+            #[cfg(feature = "autoip")]
+            ConfigV4::LinkLocal(_) => None,
             ConfigV4::Static(c) => Some(c),
         };
 
+        // Handle link-local config, on its own or as a DHCP fallback.
+        #[cfg(feature = "autoip")]
+        {
+            let link_local = match &config {
+                ConfigV4::LinkLocal(c) => Some((c.clone(), embassy_time::Duration::from_ticks(0))),
+                #[cfg(feature = "dhcpv4")]
+                ConfigV4::Dhcp(DhcpConfig {
+                    link_local_fallback: Some(c),
+                    ..
+                }) => Some((c.clone(), c.dhcp_timeout)),
+                _ => None,
+            };
+            self.autoip = link_local.and_then(|(c, delay)| match self.hardware_address {
+                HardwareAddress::Ethernet(addr) => {
+                    let mut autoip = autoip::AutoIp::new(c, delay, addr);
+                    if self.link_up {
+                        autoip.start(Instant::now());
+                    }
+                    Some(autoip)
+                }
+                #[allow(unreachable_patterns)]
+                _ => {
+                    warn!("IPv4 link-local addresses are only supported on Ethernet interfaces.");
+                    None
+                }
+            });
+        }
+
         // Handle DHCP config.
         #[cfg(feature = "dhcpv4")]
         match config {
@@ -712,16 +1646,93 @@ impl Inner {
 
     #[cfg(feature = "proto-ipv6")]
     pub fn set_config_v6(&mut self, config: ConfigV6) {
-        self.static_v6 = match config {
+        // Handle static config.
+        self.static_v6 = match config.clone() {
             ConfigV6::None => None,
+            #[cfg(feature = "slaac")]
+            ConfigV6::Slaac(_) => None,
+            #[cfg(feature = "dhcpv6")]
+            ConfigV6::Dhcp(_) => None,
             ConfigV6::Static(c) => Some(c),
         };
+
+        // Handle DHCPv6 config. Done first, so that the SLAAC resources are free again when
+        // switching to SLAAC.
+        #[cfg(feature = "dhcpv6")]
+        match config.clone() {
+            ConfigV6::Dhcp(c) => {
+                if let Some(slaac) = self.slaac.take() {
+                    self.sockets.remove(slaac.handle());
+                }
+                match &mut self.dhcpv6 {
+                    Some(dhcpv6) => {
+                        dhcpv6.set_config(c);
+                        dhcpv6.reset(Instant::now());
+                    }
+                    None => {
+                        // safety: the resources live for as long as the stack. The SLAAC socket
+                        // has been removed above, and only one DHCPv6 client exists at a time.
+                        let dhcpv6 = unsafe {
+                            dhcpv6::Dhcpv6::new(
+                                &mut self.sockets,
+                                self.dhcpv6_resources,
+                                self.slaac_resources,
+                                c,
+                                self.hardware_address,
+                                self.random_seed,
+                            )
+                        };
+                        self.dhcpv6 = Some(dhcpv6);
+                    }
+                }
+            }
+            _ => {
+                // Remove DHCPv6 sockets if any.
+                if let Some(dhcpv6) = self.dhcpv6.take() {
+                    for handle in dhcpv6.handles() {
+                        self.sockets.remove(handle);
+                    }
+                }
+            }
+        }
+
+        // Handle SLAAC config.
+        #[cfg(feature = "slaac")]
+        match config {
+            ConfigV6::Slaac(c) => match &mut self.slaac {
+                Some(slaac) => {
+                    slaac.set_config(c, true);
+                    slaac.reset(Instant::now());
+                }
+                None => {
+                    // safety: the resources live for as long as the stack, and are not used by
+                    // any other socket since we only ever create one SLAAC socket at a time.
+                    let slaac = unsafe {
+                        slaac::Slaac::new(
+                            &mut self.sockets,
+                            self.slaac_resources,
+                            c,
+                            true,
+                            self.hardware_address,
+                            self.random_seed,
+                        )
+                    };
+                    self.slaac = Some(slaac);
+                }
+            },
+            #[cfg(feature = "dhcpv6")]
+            ConfigV6::Dhcp(_) => {}
+            _ => {
+                // Remove SLAAC socket if any.
+                if let Some(slaac) = self.slaac.take() {
+                    self.sockets.remove(slaac.handle());
+                }
+            }
+        }
     }
 
-    fn apply_static_config(&mut self) {
+    fn apply_static_config(&mut self, id: InterfaceId, routes: &[Route]) {
         let mut addrs = Vec::new();
-        #[cfg(feature = "dns")]
-        let mut dns_servers: Vec<_, 6> = Vec::new();
         #[cfg(feature = "proto-ipv4")]
         let mut gateway_v4 = None;
         #[cfg(feature = "proto-ipv6")]
@@ -738,7 +1749,6 @@ impl Inner {
             #[cfg(feature = "dns")]
             for s in &config.dns_servers {
                 debug!("   DNS server:      {:?}", s);
-                unwrap!(dns_servers.push(s.clone().into()).ok());
             }
         } else {
             info!("IPv4: DOWN");
@@ -755,12 +1765,25 @@ impl Inner {
             #[cfg(feature = "dns")]
             for s in &config.dns_servers {
                 debug!("   DNS server:      {:?}", s);
-                unwrap!(dns_servers.push(s.clone().into()).ok());
             }
         } else {
             info!("IPv6: DOWN");
         }
 
+        // SLAAC needs a link-local address to talk to routers.
+        #[cfg(feature = "slaac")]
+        if let Some(slaac) = &self.slaac {
+            if addrs.push(IpCidr::Ipv6(slaac.link_local())).is_err() {
+                warn!("No room for the IPv6 link-local address, increase smoltcp's IFACE_MAX_ADDR_COUNT.");
+            }
+        }
+        #[cfg(feature = "dhcpv6")]
+        if let Some(dhcpv6) = &self.dhcpv6 {
+            if addrs.push(IpCidr::Ipv6(dhcpv6.link_local())).is_err() {
+                warn!("No room for the IPv6 link-local address, increase smoltcp's IFACE_MAX_ADDR_COUNT.");
+            }
+        }
+
         // Apply addresses
         self.iface.update_ip_addrs(|a| *a = addrs);
 
@@ -778,24 +1801,55 @@ impl Inner {
             self.iface.routes_mut().remove_default_ipv6_route();
         }
 
-        // Apply DNS servers
-        #[cfg(feature = "dns")]
-        if !dns_servers.is_empty() {
-            let count = if dns_servers.len() > DNS_MAX_SERVER_COUNT {
-                warn!("Number of DNS servers exceeds DNS_MAX_SERVER_COUNT, truncating list.");
-                DNS_MAX_SERVER_COUNT
-            } else {
-                dns_servers.len()
-            };
-            self.sockets
-                .get_mut::<smoltcp::socket::dns::Socket>(self.dns_socket)
-                .update_servers(&dns_servers[..count]);
-        }
+        self.apply_routes(id, routes);
+    }
 
-        self.state_waker.wake();
+    /// Install the routes of the routing table that go through this interface via a gateway.
+    fn apply_routes(&mut self, id: InterfaceId, routes: &[Route]) {
+        self.iface.routes_mut().update(|table| {
+            // Keep the default routes, they come from the IP configuration.
+            table.retain(|r| r.cidr.prefix_len() == 0);
+            for route in routes.iter().filter(|r| r.interface == id) {
+                let Some(gateway) = route.gateway else { continue };
+                let route = smoltcp::iface::Route {
+                    cidr: route.destination,
+                    via_router: gateway,
+                    preferred_until: None,
+                    expires_at: None,
+                };
+                if table.push(route).is_err() {
+                    warn!("No room for route, increase smoltcp's IFACE_MAX_ROUTE_COUNT.");
+                }
+            }
+        });
     }
 
-    fn poll<D: Driver>(&mut self, cx: &mut Context<'_>, driver: &mut D) {
+    #[cfg(feature = "dns")]
+    fn dns_servers(&self) -> Vec<IpAddress, 6> {
+        let mut dns_servers = Vec::new();
+        #[cfg(feature = "proto-ipv4")]
+        if let Some(config) = &self.static_v4 {
+            for s in &config.dns_servers {
+                unwrap!(dns_servers.push((*s).into()).ok());
+            }
+        }
+        #[cfg(feature = "proto-ipv6")]
+        if let Some(config) = &self.static_v6 {
+            for s in &config.dns_servers {
+                unwrap!(dns_servers.push((*s).into()).ok());
+            }
+        }
+        dns_servers
+    }
+
+    fn poll<D: Driver>(
+        &mut self,
+        cx: &mut Context<'_>,
+        driver: &mut D,
+        #[cfg(feature = "forwarding")] forward: Option<&mut ForwardFn<'_>>,
+        #[cfg(feature = "dns-cache")] dns_cache: &RefCell<dns::Cache>,
+    ) -> PollChanges {
+        let mut changes = PollChanges::default();
         self.waker.register(cx.waker());
 
         let (_hardware_addr, medium) = to_smoltcp_hardware_address(driver.hardware_address());
@@ -820,7 +1874,29 @@ impl Inner {
             cx: Some(cx),
             inner: driver,
             medium,
+            #[cfg(feature = "statistics")]
+            stats: Some(&self.stats),
+            #[cfg(feature = "autoip")]
+            autoip: self.autoip.as_mut(),
+            #[cfg(feature = "dns-cache")]
+            dns_cache: Some(dns_cache),
         };
+        #[cfg(feature = "forwarding")]
+        if let Some(forward) = forward {
+            // Forwarded frames are consumed here, smoltcp is only given the other ones.
+            let caps = smoltcp::phy::Device::capabilities(&smoldev);
+            while let Some((rx, tx)) = smoltcp::phy::Device::receive(&mut smoldev, timestamp) {
+                rx.consume_unless_forwarded(forward, |frame| {
+                    let mut device = Received::new(frame, tx, caps.clone());
+                    self.iface
+                        .poll_ingress_single(timestamp, &mut device, &mut self.sockets)
+                });
+            }
+            self.iface.poll_egress(timestamp, &mut smoldev, &mut self.sockets);
+        } else {
+            self.iface.poll(timestamp, &mut smoldev, &mut self.sockets);
+        }
+        #[cfg(not(feature = "forwarding"))]
         self.iface.poll(timestamp, &mut smoldev, &mut self.sockets);
 
         // Update link up
@@ -830,7 +1906,7 @@ impl Inner {
         // Print when changed
         if old_link_up != self.link_up {
             info!("link_up = {:?}", self.link_up);
-            self.state_waker.wake();
+            changes.link = true;
         }
 
         #[cfg(feature = "dhcpv4")]
@@ -845,6 +1921,10 @@ impl Inner {
                     None => false,
                     Some(dhcpv4::Event::Deconfigured) => {
                         self.static_v4 = None;
+                        #[cfg(feature = "autoip")]
+                        if let Some(autoip) = &mut self.autoip {
+                            autoip.start(Instant::now());
+                        }
                         true
                     }
                     Some(dhcpv4::Event::Configured(config)) => {
@@ -853,6 +1933,11 @@ impl Inner {
                             gateway: config.router,
                             dns_servers: config.dns_servers,
                         });
+                        // The lease replaces the link-local address.
+                        #[cfg(feature = "autoip")]
+                        if let Some(autoip) = &mut self.autoip {
+                            let _ = autoip.stop();
+                        }
                         true
                     }
                 }
@@ -863,30 +1948,372 @@ impl Inner {
             } else {
                 false
             };
-            if configure {
-                self.apply_static_config()
+            changes.config |= configure;
+        }
+
+        #[cfg(feature = "autoip")]
+        if let Some(autoip) = &mut self.autoip {
+            let now = Instant::now();
+            let mut event = None;
+            if old_link_up != self.link_up {
+                match self.link_up {
+                    true => autoip.start(now),
+                    false => event = autoip.stop(),
+                }
             }
+            let mut dev = DriverAdapter {
+                cx: Some(cx),
+                inner: driver,
+                medium,
+                #[cfg(feature = "statistics")]
+                stats: Some(&self.stats),
+                autoip: None,
+                #[cfg(feature = "dns-cache")]
+                dns_cache: None,
+            };
+            let configure = match event.or_else(|| autoip.poll(&mut dev, now)) {
+                None => false,
+                Some(autoip::Event::Deconfigured) => {
+                    self.static_v4 = None;
+                    true
+                }
+                Some(autoip::Event::Configured(config)) => {
+                    self.static_v4 = Some(config);
+                    true
+                }
+            };
+            changes.config |= configure;
+        }
+
+        #[cfg(feature = "slaac")]
+        if let Some(slaac) = &mut self.slaac {
+            let configure = if self.link_up {
+                let now = Instant::now();
+                if old_link_up != self.link_up {
+                    slaac.reset(now);
+                }
+                let link_local = IpCidr::Ipv6(slaac.link_local());
+                let link_local_assigned = self.iface.ip_addrs().contains(&link_local);
+                match slaac.poll(&mut self.sockets, link_local_assigned, now) {
+                    None => false,
+                    Some(slaac::Event::Deconfigured) => {
+                        self.static_v6 = None;
+                        true
+                    }
+                    Some(slaac::Event::Configured(config)) => {
+                        self.static_v6 = Some(config);
+                        true
+                    }
+                }
+            } else if old_link_up {
+                slaac.reset(Instant::now());
+                self.static_v6 = None;
+                true
+            } else {
+                false
+            };
+            changes.config |= configure;
+        }
+
+        #[cfg(feature = "dhcpv6")]
+        if let Some(dhcpv6) = &mut self.dhcpv6 {
+            let configure = if self.link_up {
+                let now = Instant::now();
+                if old_link_up != self.link_up {
+                    dhcpv6.reset(now);
+                }
+                let link_local = IpCidr::Ipv6(dhcpv6.link_local());
+                let link_local_assigned = self.iface.ip_addrs().contains(&link_local);
+                match dhcpv6.poll(&mut self.sockets, link_local_assigned, now) {
+                    None => false,
+                    Some(slaac::Event::Deconfigured) => {
+                        self.static_v6 = None;
+                        true
+                    }
+                    Some(slaac::Event::Configured(config)) => {
+                        self.static_v6 = Some(config);
+                        true
+                    }
+                }
+            } else if old_link_up {
+                dhcpv6.reset(Instant::now());
+                self.static_v6 = None;
+                true
+            } else {
+                false
+            };
+            changes.config |= configure;
+        }
+
+        #[allow(unused_mut)]
+        let mut poll_at = self
+            .iface
+            .poll_at(timestamp, &mut self.sockets)
+            .map(instant_from_smoltcp);
+        #[cfg(feature = "autoip")]
+        if let Some(t) = self.autoip.as_ref().and_then(|a| a.poll_at()) {
+            poll_at = Some(poll_at.map_or(t, |p| p.min(t)));
+        }
+        #[cfg(feature = "slaac")]
+        if let Some(t) = self.slaac.as_ref().and_then(|s| s.poll_at()) {
+            poll_at = Some(poll_at.map_or(t, |p| p.min(t)));
+        }
+        #[cfg(feature = "dhcpv6")]
+        if let Some(t) = self.dhcpv6.as_ref().and_then(|d| d.poll_at()) {
+            poll_at = Some(poll_at.map_or(t, |p| p.min(t)));
         }
 
-        if let Some(poll_at) = self.iface.poll_at(timestamp, &mut self.sockets) {
-            let t = pin!(Timer::at(instant_from_smoltcp(poll_at)));
+        if let Some(poll_at) = poll_at {
+            let t = pin!(Timer::at(poll_at));
             if t.poll(cx).is_ready() {
                 cx.waker().wake_by_ref();
             }
         }
+
+        changes
     }
 }
 
 impl<'d, D: Driver> Runner<'d, D> {
+    /// Get the id of the interface this runner drives.
+    pub fn interface(&self) -> InterfaceId {
+        self.id
+    }
+
     /// Run the network stack.
     ///
     /// You must call this in a background task, to process network events.
     pub async fn run(&mut self) -> ! {
         poll_fn(|cx| {
-            self.stack.with_mut(|i| i.poll(cx, &mut self.driver));
+            self.stack.with_mut(|i| i.poll(self.id, cx, &mut self.driver));
             Poll::<()>::Pending
         })
         .await;
         unreachable!()
     }
 }
+
+#[cfg(all(test, feature = "proto-ipv4", feature = "medium-ip"))]
+mod tests {
+    use core::task::Poll;
+
+    use embassy_futures::poll_once;
+    use embassy_net_sim::driver::HardwareAddress as DriverAddress;
+
+    use super::*;
+
+    type Device = embassy_net_sim::Device<'static, 1500, 4>;
+
+    pub(crate) fn device() -> Device {
+        let state = Box::leak(Box::new(embassy_net_sim::State::new()));
+        embassy_net_sim::new(state, DriverAddress::Ip, DriverAddress::Ip).0
+    }
+
+    fn config(address: Ipv4Cidr, gateway: Option<Ipv4Address>) -> Config {
+        Config::ipv4_static(StaticConfigV4 {
+            address,
+            gateway,
+            dns_servers: Vec::new(),
+        })
+    }
+
+    /// A stack with an uplink as default interface, and a local interface with a single socket slot.
+    fn stack() -> (Stack<'static>, InterfaceId) {
+        let (stack, _) = new(
+            device(),
+            config(
+                Ipv4Cidr::new(Ipv4Address::new(192, 168, 1, 2), 24),
+                Some(Ipv4Address::new(192, 168, 1, 1)),
+            ),
+            Box::leak(Box::new(StackResources::<4>::new())),
+            0x1234,
+        );
+        let (local, _) = stack
+            .add_interface(
+                device(),
+                config(Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, 1), 24), None),
+                Box::leak(Box::new(InterfaceResources::<1>::new())),
+            )
+            .unwrap();
+        for iface in stack.inner.borrow_mut().ifaces.iter_mut() {
+            iface.link_up = true;
+        }
+        (stack, local)
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_socket() -> smoltcp::socket::tcp::Socket<'static> {
+        let buffer = || smoltcp::socket::tcp::SocketBuffer::new(&mut [][..]);
+        smoltcp::socket::tcp::Socket::new(buffer(), buffer())
+    }
+
+    fn socket_count(stack: Stack<'_>, id: InterfaceId) -> usize {
+        stack.with(|i| i.iface(id).sockets.iter().count())
+    }
+
+    #[test]
+    fn route_by_subnet_and_gateway() {
+        let (stack, local) = stack();
+        assert_eq!(stack.route(Ipv4Address::new(10, 0, 0, 7).into()), Some(local));
+        assert_eq!(
+            stack.route(Ipv4Address::new(192, 168, 1, 7).into()),
+            Some(InterfaceId::DEFAULT)
+        );
+        // Only the uplink has a default gateway.
+        assert_eq!(
+            stack.route(Ipv4Address::new(8, 8, 8, 8).into()),
+            Some(InterfaceId::DEFAULT)
+        );
+
+        let destination = Ipv4Cidr::new(Ipv4Address::new(172, 16, 0, 0), 12).into();
+        stack
+            .add_route(Route {
+                destination,
+                gateway: None,
+                interface: local,
+            })
+            .unwrap();
+        assert_eq!(stack.route(Ipv4Address::new(172, 16, 3, 4).into()), Some(local));
+        stack.remove_route(destination).unwrap();
+        assert_eq!(
+            stack.route(Ipv4Address::new(172, 16, 3, 4).into()),
+            Some(InterfaceId::DEFAULT)
+        );
+
+        // Without link, the uplink isn't a default route anymore.
+        stack.inner.borrow_mut().ifaces[0].link_up = false;
+        assert_eq!(stack.route(Ipv4Address::new(8, 8, 8, 8).into()), None);
+    }
+
+    #[test]
+    fn add_interface_limit() {
+        let (stack, _) = stack();
+        for _ in 2..MAX_INTERFACES {
+            let resources = Box::leak(Box::new(InterfaceResources::<1>::new()));
+            assert!(stack.add_interface(device(), Config::default(), resources).is_ok());
+        }
+        let resources = Box::leak(Box::new(InterfaceResources::<1>::new()));
+        assert!(matches!(
+            stack.add_interface(device(), Config::default(), resources),
+            Err(AddInterfaceError::TooManyInterfaces)
+        ));
+    }
+
+    #[cfg(feature = "tcp")]
+    #[test]
+    fn move_socket() {
+        let (stack, local) = stack();
+        let base = socket_count(stack, InterfaceId::DEFAULT);
+        let socket = stack.with_mut(|i| i.add_socket(tcp_socket()));
+        assert_eq!(socket.iface, InterfaceId::DEFAULT);
+
+        let moved = stack.with_mut(|i| i.move_socket(socket, local)).unwrap();
+        assert_eq!(moved.iface, local);
+        assert_eq!(socket_count(stack, InterfaceId::DEFAULT), base);
+        assert_eq!(socket_count(stack, local), 1);
+
+        // The local interface is full, the second socket stays where it is.
+        let other = stack.with_mut(|i| i.add_socket(tcp_socket()));
+        core::assert_eq!(stack.with_mut(|i| i.move_socket(other, local)), None);
+        core::assert_eq!(
+            stack.with_mut(|i| i.bind_socket(other, local)),
+            Err(InterfaceBindError::NoFreeSocket)
+        );
+        core::assert_eq!(
+            stack.with_mut(|i| i.bind_socket(other, InterfaceId(MAX_INTERFACES as u8))),
+            Err(InterfaceBindError::InvalidInterface)
+        );
+        assert_eq!(socket_count(stack, InterfaceId::DEFAULT), base + 1);
+
+        // Moving back frees the slot.
+        let back = stack.with_mut(|i| i.move_socket(moved, InterfaceId::DEFAULT)).unwrap();
+        assert_eq!(back.iface, InterfaceId::DEFAULT);
+        assert_eq!(socket_count(stack, local), 0);
+        assert!(stack.with_mut(|i| i.move_socket(other, local)).is_some());
+    }
+
+    #[cfg(feature = "udp")]
+    #[test]
+    fn udp_routes_at_bind_only() {
+        let (stack, local) = stack();
+        let buf = || &mut Box::leak(Box::new([0u8; 64]))[..];
+        let meta = || &mut Box::leak(Box::new([udp::PacketMetadata::EMPTY; 2]))[..];
+        let base = socket_count(stack, InterfaceId::DEFAULT);
+
+        // Bound to a port only, the socket stays on the default interface, whatever it sends to.
+        let mut server = udp::UdpSocket::new(stack, meta(), buf(), meta(), buf());
+        server.bind(67).unwrap();
+        core::assert_eq!(
+            poll_once(server.send_to(b"offer", (Ipv4Address::BROADCAST, 68))),
+            Poll::Ready(Ok(()))
+        );
+        core::assert_eq!(
+            poll_once(server.send_to(b"ack", (Ipv4Address::new(10, 0, 0, 7), 68))),
+            Poll::Ready(Ok(()))
+        );
+        assert_eq!(socket_count(stack, InterfaceId::DEFAULT), base + 1);
+        assert_eq!(socket_count(stack, local), 0);
+
+        // Bound to an address, it moves to the interface that has it.
+        let mut socket = udp::UdpSocket::new(stack, meta(), buf(), meta(), buf());
+        socket.bind((Ipv4Address::new(10, 0, 0, 1), 5000)).unwrap();
+        assert_eq!(socket_count(stack, local), 1);
+
+        // The local interface is full.
+        let mut other = udp::UdpSocket::new(stack, meta(), buf(), meta(), buf());
+        assert_eq!(
+            other.bind((Ipv4Address::new(10, 0, 0, 1), 5001)),
+            Err(udp::BindError::NoFreeSocket)
+        );
+        assert_eq!(other.bind_to_interface(local), Err(InterfaceBindError::NoFreeSocket));
+
+        drop(socket);
+        assert_eq!(socket_count(stack, local), 0);
+        other.bind_to_interface(local).unwrap();
+        assert_eq!(socket_count(stack, local), 1);
+    }
+
+    #[cfg(feature = "tcp")]
+    #[test]
+    fn tcp_connect_moves_socket() {
+        let (stack, local) = stack();
+        let buf = || &mut Box::leak(Box::new([0u8; 256]))[..];
+        let base = socket_count(stack, InterfaceId::DEFAULT);
+
+        let mut socket = tcp::TcpSocket::new(stack, buf(), buf());
+        assert!(poll_once(socket.connect((Ipv4Address::new(10, 0, 0, 7), 80))).is_pending());
+        assert_eq!(socket_count(stack, InterfaceId::DEFAULT), base);
+        assert_eq!(socket_count(stack, local), 1);
+
+        let mut other = tcp::TcpSocket::new(stack, buf(), buf());
+        core::assert_eq!(
+            poll_once(other.connect((Ipv4Address::new(10, 0, 0, 8), 80))),
+            Poll::Ready(Err(tcp::ConnectError::NoFreeSocket))
+        );
+        // Sockets to other hosts don't need the local interface.
+        assert!(poll_once(other.connect((Ipv4Address::new(8, 8, 8, 8), 80))).is_pending());
+        assert_eq!(socket_count(stack, InterfaceId::DEFAULT), base + 1);
+    }
+
+    #[cfg(feature = "dns-cache")]
+    #[test]
+    fn dns_servers_change_flushes_cache() {
+        use crate::dns::tests::{cached, resolve};
+
+        let (stack, _) = stack();
+        let now = Instant::from_secs(0);
+        resolve(stack.inner.borrow_mut().dns_cache.get_mut(), "example.com", 1, 60, now);
+        assert!(cached(stack.inner.borrow_mut().dns_cache.get_mut(), "example.com", now));
+
+        stack.set_config_v4(ConfigV4::Static(StaticConfigV4 {
+            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 1, 2), 24),
+            gateway: Some(Ipv4Address::new(192, 168, 1, 1)),
+            dns_servers: [Ipv4Address::new(192, 168, 1, 53)].into_iter().collect(),
+        }));
+        assert!(!cached(
+            stack.inner.borrow_mut().dns_cache.get_mut(),
+            "example.com",
+            now
+        ));
+    }
+}
@@ -0,0 +1,1466 @@
+//! mDNS / DNS-SD responder.
+//!
+//! Answers multicast DNS queries (RFC 6762) for a `.local` host name with the addresses of the
+//! default interface, and advertises DNS-SD services (RFC 6763), so the device can be found
+//! without knowing its address.
+//!
+//! When the interface gets an IP configuration, the responder probes the network to check that
+//! its names are unique, picking new ones (`name-2`, `Instance (2)`, ...) on conflicts, then
+//! announces its records. It does so again whenever the addresses change.
+//!
+//! ```ignore
+//! use embassy_net::mdns::{Config, Responder, Service};
+//! use embassy_net::udp::{PacketMetadata, UdpSocket};
+//!
+//! let mut rx_meta = [PacketMetadata::EMPTY; 4];
+//! let mut rx_buffer = [0; 2048];
+//! let mut tx_meta = [PacketMetadata::EMPTY; 4];
+//! let mut tx_buffer = [0; 2048];
+//! let socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+//!
+//! let services = [Service::new("My Device", "_http._tcp", 80)];
+//! let mut config = Config::new("my-device");
+//! config.services = &services;
+//! let mut responder = Responder::new(socket, config, &mut rng).unwrap();
+//! responder.run().await;
+//! ```
+
+use core::cmp::Ordering;
+use core::fmt::Write as _;
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::task::Poll;
+
+use embassy_time::{with_deadline, Duration, Instant, Timer};
+use heapless::String;
+use rand_core::RngCore;
+
+use crate::udp::{BindError, RecvError, UdpMetadata, UdpSocket};
+#[cfg(feature = "proto-ipv4")]
+use crate::Ipv4Address;
+#[cfg(feature = "proto-ipv6")]
+use crate::Ipv6Address;
+use crate::{IpAddress, IpEndpoint, MulticastError, Stack};
+
+const MDNS_PORT: u16 = 5353;
+#[cfg(feature = "proto-ipv4")]
+const MDNS_GROUP_V4: Ipv4Address = Ipv4Address::new(224, 0, 0, 251);
+#[cfg(feature = "proto-ipv6")]
+const MDNS_GROUP_V6: Ipv6Address = Ipv6Address::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+
+/// Largest message sent or received, the UDP payload of a 1500 bytes IPv4 packet.
+const MAX_MESSAGE_LEN: usize = 1472;
+/// Longest name, in wire format.
+const MAX_NAME_LEN: usize = 255;
+/// Largest number of services advertised.
+pub const MAX_SERVICES: usize = 8;
+
+/// TTL of records containing a host name (RFC 6762 section 10).
+const HOST_TTL: u32 = 120;
+/// TTL of other records.
+const OTHER_TTL: u32 = 75 * 60;
+/// Largest TTL of answers to legacy unicast queries (RFC 6762 section 6.7).
+const LEGACY_TTL: u32 = 10;
+
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+const PROBE_COUNT: usize = 3;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+const ANNOUNCE_COUNT: usize = 2;
+/// How long to wait before probing again after losing a tie-break (RFC 6762 section 8.2).
+const TIEBREAK_DELAY: Duration = Duration::from_secs(1);
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Cache flush bit of the record class, or unicast response bit of the question class.
+const CLASS_FLAG: u16 = 0x8000;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const OPCODE_MASK: u16 = 0x7800;
+
+/// A DNS-SD service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Service<'a> {
+    /// Instance name, shown to users, e.g. `Living Room Speaker`.
+    pub instance: &'a str,
+    /// Service type, with the protocol, e.g. `_http._tcp`.
+    pub service_type: &'a str,
+    /// Port of the service.
+    pub port: u16,
+    /// TXT record entries, e.g. `path=/`.
+    pub txt: &'a [&'a str],
+}
+
+impl<'a> Service<'a> {
+    /// Create a service without TXT record entries.
+    pub const fn new(instance: &'a str, service_type: &'a str, port: u16) -> Self {
+        Self {
+            instance,
+            service_type,
+            port,
+            txt: &[],
+        }
+    }
+}
+
+/// mDNS responder configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Config<'a> {
+    /// Host name, without the `.local` domain.
+    pub hostname: &'a str,
+    /// Advertised services, at most [`MAX_SERVICES`].
+    pub services: &'a [Service<'a>],
+}
+
+impl<'a> Config<'a> {
+    /// Create a configuration answering for `hostname`.local, without services.
+    pub const fn new(hostname: &'a str) -> Self {
+        Self {
+            hostname,
+            services: &[],
+        }
+    }
+}
+
+/// Error returned by [`Responder::new`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The socket couldn't be bound to the mDNS port.
+    Bind(BindError),
+    /// The mDNS multicast group couldn't be joined.
+    Multicast(MulticastError),
+    /// There are more than [`MAX_SERVICES`] services.
+    TooManyServices,
+}
+
+/// A name owned by the responder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Name {
+    /// `host.local`
+    Host,
+    /// `_services._dns-sd._udp.local`, for service type enumeration.
+    Services,
+    /// `_type._proto.local` of a service.
+    ServiceType(usize),
+    /// `Instance._type._proto.local` of a service.
+    Instance(usize),
+}
+
+impl Name {
+    /// Names whose records must be unique on the network.
+    fn is_unique(self) -> bool {
+        matches!(self, Name::Host | Name::Instance(_))
+    }
+}
+
+/// A record of the responder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Record {
+    A,
+    Aaaa,
+    /// Service type to instance.
+    Ptr(usize),
+    Srv(usize),
+    Txt(usize),
+    /// Service type enumeration to service type.
+    Enumeration(usize),
+}
+
+impl Record {
+    fn index(self) -> u32 {
+        match self {
+            Record::A => 0,
+            Record::Aaaa => 1,
+            Record::Ptr(i) => 2 + 4 * i as u32,
+            Record::Srv(i) => 3 + 4 * i as u32,
+            Record::Txt(i) => 4 + 4 * i as u32,
+            Record::Enumeration(i) => 5 + 4 * i as u32,
+        }
+    }
+
+    fn from_index(index: u32) -> Self {
+        let i = index.saturating_sub(2) as usize / 4;
+        match index {
+            0 => Record::A,
+            1 => Record::Aaaa,
+            _ => match (index - 2) % 4 {
+                0 => Record::Ptr(i),
+                1 => Record::Srv(i),
+                2 => Record::Txt(i),
+                _ => Record::Enumeration(i),
+            },
+        }
+    }
+
+    fn name(self) -> Name {
+        match self {
+            Record::A | Record::Aaaa => Name::Host,
+            Record::Ptr(i) => Name::ServiceType(i),
+            Record::Srv(i) | Record::Txt(i) => Name::Instance(i),
+            Record::Enumeration(_) => Name::Services,
+        }
+    }
+
+    fn rtype(self) -> u16 {
+        match self {
+            Record::A => TYPE_A,
+            Record::Aaaa => TYPE_AAAA,
+            Record::Ptr(_) | Record::Enumeration(_) => TYPE_PTR,
+            Record::Srv(_) => TYPE_SRV,
+            Record::Txt(_) => TYPE_TXT,
+        }
+    }
+
+    fn ttl(self) -> u32 {
+        match self {
+            Record::A | Record::Aaaa | Record::Srv(_) => HOST_TTL,
+            _ => OTHER_TTL,
+        }
+    }
+
+    fn bit(self) -> u64 {
+        1 << self.index()
+    }
+}
+
+/// Iterate over the records of a set.
+fn records(set: u64) -> impl Iterator<Item = Record> {
+    (0..64).filter(move |i| set & (1 << i) != 0).map(Record::from_index)
+}
+
+/// What woke up the responder while serving.
+enum Event {
+    Received(Result<(usize, UdpMetadata), RecvError>),
+    Announce,
+    AddressesChanged,
+}
+
+/// Result of checking a message received while probing.
+enum ProbeCheck {
+    None,
+    /// Another host uses this name.
+    Conflict(Name),
+    /// Another host probes for this name, and wins the tie-break.
+    Lost,
+}
+
+struct BufferFull;
+
+struct Writer<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl<'b> Writer<'b> {
+    fn new(buf: &'b mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    fn written(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    fn bytes(&mut self, data: &[u8]) -> Result<(), BufferFull> {
+        let dst = self.buf.get_mut(self.len..self.len + data.len()).ok_or(BufferFull)?;
+        dst.copy_from_slice(data);
+        self.len += data.len();
+        Ok(())
+    }
+
+    fn u16(&mut self, v: u16) -> Result<(), BufferFull> {
+        self.bytes(&v.to_be_bytes())
+    }
+
+    fn u32(&mut self, v: u32) -> Result<(), BufferFull> {
+        self.bytes(&v.to_be_bytes())
+    }
+
+    /// Write a length-prefixed string, a label or a TXT entry.
+    fn string(&mut self, s: &[u8]) -> Result<(), BufferFull> {
+        let len = u8::try_from(s.len()).map_err(|_| BufferFull)?;
+        self.bytes(&[len])?;
+        self.bytes(s)
+    }
+
+    fn labels(&mut self, name: &str) -> Result<(), BufferFull> {
+        name.split('.')
+            .filter(|l| !l.is_empty())
+            .try_for_each(|l| self.string(l.as_bytes()))
+    }
+}
+
+/// A DNS message being written.
+struct Message<'b> {
+    w: Writer<'b>,
+    /// Number of questions, answers, authority and additional records.
+    counts: [u16; 4],
+}
+
+const QUESTIONS: usize = 0;
+const ANSWERS: usize = 1;
+const AUTHORITY: usize = 2;
+const ADDITIONAL: usize = 3;
+
+impl<'b> Message<'b> {
+    fn new(buf: &'b mut [u8], id: u16, flags: u16) -> Self {
+        let mut w = Writer::new(buf);
+        // The buffer always fits a header.
+        let _ = w.u16(id);
+        let _ = w.u16(flags);
+        let _ = w.bytes(&[0; 8]);
+        Self { w, counts: [0; 4] }
+    }
+
+    /// Write an entry of `section` with `f`, leaving the message unchanged if it doesn't fit.
+    fn entry(&mut self, section: usize, f: impl FnOnce(&mut Writer<'b>) -> Result<(), BufferFull>) -> bool {
+        let len = self.w.len;
+        match f(&mut self.w) {
+            Ok(()) => {
+                self.counts[section] += 1;
+                true
+            }
+            Err(BufferFull) => {
+                self.w.len = len;
+                false
+            }
+        }
+    }
+
+    fn finish(self) -> usize {
+        for (i, count) in self.counts.iter().enumerate() {
+            self.w.buf[4 + 2 * i..6 + 2 * i].copy_from_slice(&count.to_be_bytes());
+        }
+        self.w.len
+    }
+}
+
+/// Read a possibly compressed name at `*pos`, in uncompressed wire format.
+///
+/// Returns the length of the name in `out`.
+fn read_name(msg: &[u8], pos: &mut usize, out: &mut [u8; MAX_NAME_LEN]) -> Option<usize> {
+    let mut p = *pos;
+    let mut len = 0;
+    let mut jumps = 0;
+    loop {
+        let label_len = *msg.get(p)? as usize;
+        match label_len & 0xc0 {
+            0x00 => {
+                let label = msg.get(p..p + 1 + label_len)?;
+                out.get_mut(len..len + 1 + label_len)?.copy_from_slice(label);
+                len += 1 + label_len;
+                p += 1 + label_len;
+                if jumps == 0 {
+                    *pos = p;
+                }
+                if label_len == 0 {
+                    return Some(len);
+                }
+            }
+            0xc0 => {
+                let target = ((label_len & 0x3f) << 8) | *msg.get(p + 1)? as usize;
+                if jumps == 0 {
+                    *pos = p + 2;
+                }
+                // Only follow pointers backwards, so that there are no loops.
+                jumps += 1;
+                if target >= p {
+                    return None;
+                }
+                p = target;
+            }
+            _ => return None,
+        }
+    }
+}
+
+fn read_u16(msg: &[u8], pos: &mut usize) -> Option<u16> {
+    let v = u16::from_be_bytes(msg.get(*pos..*pos + 2)?.try_into().ok()?);
+    *pos += 2;
+    Some(v)
+}
+
+fn read_u32(msg: &[u8], pos: &mut usize) -> Option<u32> {
+    let v = u32::from_be_bytes(msg.get(*pos..*pos + 4)?.try_into().ok()?);
+    *pos += 4;
+    Some(v)
+}
+
+struct Header {
+    id: u16,
+    flags: u16,
+    counts: [u16; 4],
+}
+
+impl Header {
+    fn parse(msg: &[u8]) -> Option<Self> {
+        let mut pos = 0;
+        let id = read_u16(msg, &mut pos)?;
+        let flags = read_u16(msg, &mut pos)?;
+        let mut counts = [0; 4];
+        for count in counts.iter_mut() {
+            *count = read_u16(msg, &mut pos)?;
+        }
+        Some(Self { id, flags, counts })
+    }
+
+    fn is_response(&self) -> bool {
+        self.flags & FLAG_RESPONSE != 0
+    }
+}
+
+struct Question {
+    name: [u8; MAX_NAME_LEN],
+    name_len: usize,
+    qtype: u16,
+    qclass: u16,
+}
+
+impl Question {
+    fn parse(msg: &[u8], pos: &mut usize) -> Option<Self> {
+        let mut name = [0; MAX_NAME_LEN];
+        let name_len = read_name(msg, pos, &mut name)?;
+        Some(Self {
+            name,
+            name_len,
+            qtype: read_u16(msg, pos)?,
+            qclass: read_u16(msg, pos)?,
+        })
+    }
+
+    fn name(&self) -> &[u8] {
+        &self.name[..self.name_len]
+    }
+}
+
+/// A resource record of a received message.
+struct ResourceRecord {
+    name: [u8; MAX_NAME_LEN],
+    name_len: usize,
+    rtype: u16,
+    class: u16,
+    ttl: u32,
+    /// Position of the data in the message.
+    rdata: core::ops::Range<usize>,
+}
+
+impl ResourceRecord {
+    fn parse(msg: &[u8], pos: &mut usize) -> Option<Self> {
+        let mut name = [0; MAX_NAME_LEN];
+        let name_len = read_name(msg, pos, &mut name)?;
+        let rtype = read_u16(msg, pos)?;
+        let class = read_u16(msg, pos)?;
+        let ttl = read_u32(msg, pos)?;
+        let len = read_u16(msg, pos)? as usize;
+        let rdata = *pos..*pos + len;
+        msg.get(rdata.clone())?;
+        *pos += len;
+        Some(Self {
+            name,
+            name_len,
+            rtype,
+            class,
+            ttl,
+            rdata,
+        })
+    }
+
+    fn name(&self) -> &[u8] {
+        &self.name[..self.name_len]
+    }
+}
+
+/// Walk over the records of a message.
+///
+/// Calls `f` with the section of each record, until it returns `Some`.
+fn for_each_record<R>(msg: &[u8], mut f: impl FnMut(usize, &ResourceRecord) -> Option<R>) -> Option<R> {
+    let header = Header::parse(msg)?;
+    let mut pos = 12;
+    for _ in 0..header.counts[QUESTIONS] {
+        Question::parse(msg, &mut pos)?;
+    }
+    for section in [ANSWERS, AUTHORITY, ADDITIONAL] {
+        for _ in 0..header.counts[section] {
+            let record = ResourceRecord::parse(msg, &mut pos)?;
+            if let Some(r) = f(section, &record) {
+                return Some(r);
+            }
+        }
+    }
+    None
+}
+
+/// Addresses of the interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct Addresses {
+    #[cfg(feature = "proto-ipv4")]
+    v4: Option<Ipv4Address>,
+    #[cfg(feature = "proto-ipv6")]
+    v6: Option<Ipv6Address>,
+}
+
+impl Addresses {
+    fn get(stack: Stack<'_>) -> Self {
+        Self {
+            #[cfg(feature = "proto-ipv4")]
+            v4: stack.config_v4().map(|c| c.address.address()),
+            #[cfg(feature = "proto-ipv6")]
+            v6: stack.config_v6().map(|c| c.address.address()),
+        }
+    }
+
+    fn v4(&self) -> Option<[u8; 4]> {
+        #[cfg(feature = "proto-ipv4")]
+        return self.v4.map(|a| a.octets());
+        #[cfg(not(feature = "proto-ipv4"))]
+        return None;
+    }
+
+    fn v6(&self) -> Option<[u8; 16]> {
+        #[cfg(feature = "proto-ipv6")]
+        return self.v6.map(|a| a.octets());
+        #[cfg(not(feature = "proto-ipv6"))]
+        return None;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.v4().is_none() && self.v6().is_none()
+    }
+
+    fn contains(&self, addr: IpAddress) -> bool {
+        match addr {
+            #[cfg(feature = "proto-ipv4")]
+            IpAddress::Ipv4(addr) => self.v4 == Some(addr),
+            #[cfg(feature = "proto-ipv6")]
+            IpAddress::Ipv6(addr) => self.v6 == Some(addr),
+        }
+    }
+
+    /// The mDNS groups of the address families in use.
+    fn groups(&self) -> impl Iterator<Item = IpEndpoint> {
+        #[cfg(feature = "proto-ipv4")]
+        let v4 = self.v4.map(|_| IpEndpoint::new(MDNS_GROUP_V4.into(), MDNS_PORT));
+        #[cfg(not(feature = "proto-ipv4"))]
+        let v4 = None;
+        #[cfg(feature = "proto-ipv6")]
+        let v6 = self.v6.map(|_| IpEndpoint::new(MDNS_GROUP_V6.into(), MDNS_PORT));
+        #[cfg(not(feature = "proto-ipv6"))]
+        let v6 = None;
+        v4.into_iter().chain(v6)
+    }
+}
+
+/// The mDNS group of the address family of `addr`.
+fn group_of(addr: IpAddress) -> IpEndpoint {
+    match addr {
+        #[cfg(feature = "proto-ipv4")]
+        IpAddress::Ipv4(_) => IpEndpoint::new(MDNS_GROUP_V4.into(), MDNS_PORT),
+        #[cfg(feature = "proto-ipv6")]
+        IpAddress::Ipv6(_) => IpEndpoint::new(MDNS_GROUP_V6.into(), MDNS_PORT),
+    }
+}
+
+/// Random delay up to `max`, to spread the messages of hosts starting at the same time.
+fn jitter(rng: &mut impl RngCore, max: Duration) -> Duration {
+    Duration::from_ticks(rng.next_u64() % max.as_ticks().max(1))
+}
+
+/// An mDNS responder, advertising a host name and DNS-SD services.
+pub struct Responder<'a, R: RngCore> {
+    stack: Stack<'a>,
+    socket: UdpSocket<'a>,
+    config: Config<'a>,
+    addresses: Addresses,
+    /// Suffix appended to the host name after conflicts, if not 0.
+    host_suffix: u16,
+    /// Suffix appended to the instance names after conflicts, if not 0.
+    service_suffixes: [u16; MAX_SERVICES],
+    rng: R,
+}
+
+impl<'a, R: RngCore> Responder<'a, R> {
+    /// Create a responder, binding `socket` to the mDNS port and joining the mDNS groups.
+    ///
+    /// `rng` picks the delays before probing, which must differ between hosts starting at the same time.
+    pub fn new(mut socket: UdpSocket<'a>, config: Config<'a>, rng: R) -> Result<Self, Error> {
+        if config.services.len() > MAX_SERVICES {
+            return Err(Error::TooManyServices);
+        }
+        let stack = socket.stack();
+        socket.bind(MDNS_PORT).map_err(Error::Bind)?;
+        socket.set_hop_limit(Some(255));
+        #[cfg(feature = "proto-ipv4")]
+        stack.join_multicast_group(MDNS_GROUP_V4).map_err(Error::Multicast)?;
+        #[cfg(feature = "proto-ipv6")]
+        stack.join_multicast_group(MDNS_GROUP_V6).map_err(Error::Multicast)?;
+        Ok(Self {
+            stack,
+            socket,
+            config,
+            addresses: Addresses::default(),
+            host_suffix: 0,
+            service_suffixes: [0; MAX_SERVICES],
+            rng,
+        })
+    }
+
+    /// Get the host name in use, without the `.local` domain.
+    ///
+    /// This is the configured one, unless it was changed after a conflict.
+    pub fn hostname(&self) -> String<63> {
+        self.host_label()
+    }
+
+    /// Get the instance name in use for the service at `index` in the configuration.
+    pub fn instance_name(&self, index: usize) -> String<63> {
+        self.instance_label(index)
+    }
+
+    /// Answer queries forever.
+    ///
+    /// Whenever the default interface gets an IP configuration, or its addresses change, the names
+    /// are probed and the records announced again.
+    pub async fn run(&mut self) -> ! {
+        let mut buf = [0; MAX_MESSAGE_LEN];
+        let mut tx = [0; MAX_MESSAGE_LEN];
+        loop {
+            self.stack.wait_config_up().await;
+            self.addresses = Addresses::get(self.stack);
+            if self.addresses.is_empty() {
+                // Configured, but the address isn't usable yet (e.g. during duplicate address detection).
+                let stack = self.stack;
+                let addresses = self.addresses;
+                stack.wait(|| Addresses::get(stack) != addresses).await;
+                continue;
+            }
+
+            self.probe(&mut buf).await;
+            debug!("mDNS: announcing {}", self.host_label().as_str());
+            self.serve(&mut buf, &mut tx).await;
+        }
+    }
+
+    /// Announce that the records are going away, so that they are removed from caches.
+    ///
+    /// Use it after cancelling [`run`](Self::run), e.g. before shutting down.
+    pub async fn goodbye(&mut self) {
+        let mut buf = [0; MAX_MESSAGE_LEN];
+        self.send_announcement(&mut buf, 0).await;
+    }
+
+    /// Probe until the names are unique on the network (RFC 6762 section 8.1).
+    async fn probe(&mut self, buf: &mut [u8; MAX_MESSAGE_LEN]) {
+        let mut delay = jitter(&mut self.rng, PROBE_INTERVAL);
+        'restart: loop {
+            Timer::after(delay).await;
+            delay = jitter(&mut self.rng, PROBE_INTERVAL);
+
+            for _ in 0..PROBE_COUNT {
+                let len = self.write_probe(buf);
+                self.send_all_groups(&buf[..len]).await;
+
+                let deadline = Instant::now() + PROBE_INTERVAL;
+                while let Ok(res) = with_deadline(deadline, self.socket.recv_from(buf)).await {
+                    let Ok((n, meta)) = res else {
+                        continue;
+                    };
+                    if self.addresses.contains(meta.endpoint.addr) {
+                        continue;
+                    }
+                    match self.check_probe(&buf[..n]) {
+                        ProbeCheck::None => {}
+                        ProbeCheck::Conflict(name) => {
+                            self.rename(name);
+                            continue 'restart;
+                        }
+                        ProbeCheck::Lost => {
+                            debug!("mDNS: lost probe tie-break");
+                            delay = TIEBREAK_DELAY;
+                            continue 'restart;
+                        }
+                    }
+                }
+            }
+            return;
+        }
+    }
+
+    /// Answer queries, until there's a conflict or the addresses change.
+    async fn serve(&mut self, buf: &mut [u8; MAX_MESSAGE_LEN], tx: &mut [u8; MAX_MESSAGE_LEN]) {
+        let mut announcements = 0;
+        let mut next_announcement = Instant::now();
+        let stack = self.stack;
+        let addresses = self.addresses;
+        loop {
+            let announce_at = match announcements < ANNOUNCE_COUNT {
+                true => next_announcement,
+                false => Instant::MAX,
+            };
+            let mut announcement = pin!(Timer::at(announce_at));
+            let mut changed = pin!(stack.wait(|| Addresses::get(stack) != addresses));
+            let event = poll_fn(|cx| {
+                if let Poll::Ready(res) = self.socket.poll_recv_from(buf, cx) {
+                    return Poll::Ready(Event::Received(res));
+                }
+                if announcement.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(Event::Announce);
+                }
+                if changed.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(Event::AddressesChanged);
+                }
+                Poll::Pending
+            })
+            .await;
+            let (n, meta) = match event {
+                Event::Received(Ok(received)) => received,
+                Event::Received(Err(RecvError::Truncated)) => {
+                    debug!("mDNS: message too long");
+                    continue;
+                }
+                Event::Announce => {
+                    self.send_announcement(buf, 1).await;
+                    announcements += 1;
+                    next_announcement = Instant::now() + ANNOUNCE_INTERVAL;
+                    continue;
+                }
+                Event::AddressesChanged => {
+                    debug!("mDNS: addresses changed");
+                    return;
+                }
+            };
+            if self.addresses.contains(meta.endpoint.addr) {
+                continue;
+            }
+            let Some(header) = Header::parse(&buf[..n]) else {
+                continue;
+            };
+
+            if header.is_response() {
+                // RFC 6762 section 9.
+                if let Some(name) = self.find_conflict(&buf[..n]) {
+                    self.rename(name);
+                    return;
+                }
+            } else if let Some((len, dst)) = self.answer(&buf[..n], meta.endpoint, tx) {
+                if let Err(e) = self.socket.send_to(&tx[..len], dst).await {
+                    warn!("mDNS: send error: {:?}", e);
+                }
+            }
+        }
+    }
+
+    async fn send_all_groups(&self, msg: &[u8]) {
+        for group in self.addresses.groups() {
+            if let Err(e) = self.socket.send_to(msg, group).await {
+                warn!("mDNS: send error: {:?}", e);
+            }
+        }
+    }
+
+    /// Send all the records, with a TTL multiplied by `ttl_factor`.
+    async fn send_announcement(&self, buf: &mut [u8; MAX_MESSAGE_LEN], ttl_factor: u32) {
+        let mut msg = Message::new(buf, 0, FLAG_RESPONSE | FLAG_AUTHORITATIVE);
+        for record in records(self.all_records()) {
+            msg.entry(ANSWERS, |w| {
+                self.write_record(w, record, record.ttl() * ttl_factor, true)
+            });
+        }
+        let len = msg.finish();
+        self.send_all_groups(&buf[..len]).await;
+    }
+
+    fn write_probe(&self, buf: &mut [u8; MAX_MESSAGE_LEN]) -> usize {
+        let mut msg = Message::new(buf, 0, 0);
+        let names = self.unique_names();
+        for name in names.clone() {
+            msg.entry(QUESTIONS, |w| {
+                self.write_name(w, name)?;
+                w.u16(TYPE_ANY)?;
+                w.u16(CLASS_IN | CLASS_FLAG)
+            });
+        }
+        for record in records(self.all_records()).filter(|r| r.name().is_unique()) {
+            msg.entry(AUTHORITY, |w| self.write_record(w, record, record.ttl(), false));
+        }
+        msg.finish()
+    }
+
+    fn rename(&mut self, name: Name) {
+        match name {
+            Name::Host => self.host_suffix = self.host_suffix.max(1) + 1,
+            Name::Instance(i) => self.service_suffixes[i] = self.service_suffixes[i].max(1) + 1,
+            _ => {}
+        }
+        match name {
+            Name::Host => info!("mDNS: host name conflict, renaming to {}", self.host_label().as_str()),
+            Name::Instance(i) => info!(
+                "mDNS: service name conflict, renaming to {}",
+                self.instance_label(i).as_str()
+            ),
+            _ => {}
+        }
+    }
+
+    /// Check a message received while probing (RFC 6762 sections 8.1 and 8.2).
+    fn check_probe(&self, msg: &[u8]) -> ProbeCheck {
+        let Some(header) = Header::parse(msg) else {
+            return ProbeCheck::None;
+        };
+        if header.is_response() {
+            return match self.find_conflict(msg) {
+                Some(name) => ProbeCheck::Conflict(name),
+                None => ProbeCheck::None,
+            };
+        }
+
+        // Simultaneous probe: compare the proposed records of the first name both probe for.
+        let probed = for_each_record(msg, |section, rr| {
+            (section == AUTHORITY)
+                .then(|| self.match_name(rr.name()))
+                .flatten()
+                .filter(|n| n.is_unique())
+        });
+        let Some(name) = probed else {
+            return ProbeCheck::None;
+        };
+        let ours = records(self.all_records()).filter(|r| r.name() == name);
+        let mut ours_sorted: heapless::Vec<Record, 2> = ours.collect();
+        ours_sorted.sort_unstable_by_key(|r| r.rtype());
+
+        let mut index = 0;
+        let mut ordering = Ordering::Equal;
+        for_each_record(msg, |section, rr| {
+            if section != AUTHORITY || self.match_name(rr.name()) != Some(name) {
+                return None;
+            }
+            let Some(&record) = ours_sorted.get(index) else {
+                // The other host has more records.
+                ordering = Ordering::Less;
+                return Some(());
+            };
+            index += 1;
+            ordering = self.compare(msg, rr, record);
+            (ordering != Ordering::Equal).then_some(())
+        });
+        if ordering == Ordering::Equal && index < ours_sorted.len() {
+            ordering = Ordering::Greater;
+        }
+        match ordering {
+            Ordering::Less => ProbeCheck::Lost,
+            _ => ProbeCheck::None,
+        }
+    }
+
+    /// Compare our `record` with a received one, lexicographically by class, type and data.
+    fn compare(&self, msg: &[u8], rr: &ResourceRecord, record: Record) -> Ordering {
+        let ours = (CLASS_IN, record.rtype());
+        let theirs = (rr.class & !CLASS_FLAG, rr.rtype);
+        if ours != theirs {
+            return ours.cmp(&theirs);
+        }
+        let mut rdata = [0; MAX_MESSAGE_LEN];
+        let mut w = Writer::new(&mut rdata);
+        if self.write_rdata(&mut w, record).is_err() {
+            return Ordering::Equal;
+        }
+        w.written().cmp(&msg[rr.rdata.clone()])
+    }
+
+    /// Find a record of a response conflicting with our unique records.
+    fn find_conflict(&self, msg: &[u8]) -> Option<Name> {
+        let all = self.all_records();
+        for_each_record(msg, |_, rr| {
+            // Goodbyes don't claim names.
+            if rr.ttl == 0 {
+                return None;
+            }
+            let name = self.match_name(rr.name()).filter(|n| n.is_unique())?;
+            let ours = records(all).find(|r| r.name() == name && r.rtype() == rr.rtype);
+            let conflict = match ours {
+                Some(record) => !self.rdata_eq(msg, rr, record),
+                // Another host has addresses of a family we don't.
+                None => matches!(rr.rtype, TYPE_A | TYPE_AAAA | TYPE_SRV | TYPE_TXT),
+            };
+            conflict.then_some(name)
+        })
+    }
+
+    /// Write the response to `query` in `buf`.
+    ///
+    /// Returns the length of the response and where to send it.
+    fn answer(&self, query: &[u8], src: IpEndpoint, buf: &mut [u8]) -> Option<(usize, IpEndpoint)> {
+        let header = Header::parse(query)?;
+        if header.flags & OPCODE_MASK != 0 {
+            return None;
+        }
+        // Queries not sent from the mDNS port come from simple resolvers (RFC 6762 section 6.7).
+        let legacy = src.port != MDNS_PORT;
+
+        let all = self.all_records();
+        let mut answers = 0;
+        let mut unicast = true;
+        let mut pos = 12;
+        for _ in 0..header.counts[QUESTIONS] {
+            let question = Question::parse(query, &mut pos)?;
+            if question.qclass & !CLASS_FLAG != CLASS_IN {
+                continue;
+            }
+            let Some(name) = self.match_name(question.name()) else {
+                continue;
+            };
+            let matched = records(all)
+                .filter(|r| r.name() == name && (question.qtype == TYPE_ANY || question.qtype == r.rtype()))
+                .fold(0, |set, r| set | r.bit());
+            if matched != 0 {
+                answers |= matched;
+                unicast &= question.qclass & CLASS_FLAG != 0;
+            }
+        }
+
+        // Known-answer suppression (RFC 6762 section 7.1).
+        for_each_record::<()>(query, |section, rr| {
+            if section == ANSWERS {
+                if let Some(record) = records(answers).find(|r| self.record_eq(query, rr, *r)) {
+                    if rr.ttl >= record.ttl() / 2 {
+                        answers &= !record.bit();
+                    }
+                }
+            }
+            None
+        });
+        if answers == 0 {
+            return None;
+        }
+
+        // RFC 6763 section 12.
+        let mut additional = 0;
+        for record in records(answers) {
+            additional |= match record {
+                Record::Ptr(i) => Record::Srv(i).bit() | Record::Txt(i).bit() | Record::A.bit() | Record::Aaaa.bit(),
+                Record::Srv(_) => Record::A.bit() | Record::Aaaa.bit(),
+                Record::A => Record::Aaaa.bit(),
+                Record::Aaaa => Record::A.bit(),
+                _ => 0,
+            };
+        }
+        additional &= all & !answers;
+
+        let id = if legacy { header.id } else { 0 };
+        let mut msg = Message::new(buf, id, FLAG_RESPONSE | FLAG_AUTHORITATIVE);
+        if legacy {
+            let mut pos = 12;
+            for _ in 0..header.counts[QUESTIONS] {
+                let question = Question::parse(query, &mut pos)?;
+                msg.entry(QUESTIONS, |w| {
+                    w.bytes(question.name())?;
+                    w.u16(question.qtype)?;
+                    w.u16(question.qclass & !CLASS_FLAG)
+                });
+            }
+        }
+        for (section, set) in [(ANSWERS, answers), (ADDITIONAL, additional)] {
+            for record in records(set) {
+                let ttl = match legacy {
+                    true => record.ttl().min(LEGACY_TTL),
+                    false => record.ttl(),
+                };
+                msg.entry(section, |w| self.write_record(w, record, ttl, !legacy));
+            }
+        }
+        let len = msg.finish();
+
+        let dst = if legacy {
+            src
+        } else if unicast {
+            IpEndpoint::new(src.addr, MDNS_PORT)
+        } else {
+            group_of(src.addr)
+        };
+        Some((len, dst))
+    }
+
+    /// Records we have, for the current addresses.
+    fn all_records(&self) -> u64 {
+        let mut set = 0;
+        if self.addresses.v4().is_some() {
+            set |= Record::A.bit();
+        }
+        if self.addresses.v6().is_some() {
+            set |= Record::Aaaa.bit();
+        }
+        for (i, service) in self.config.services.iter().enumerate() {
+            set |= Record::Ptr(i).bit() | Record::Srv(i).bit() | Record::Txt(i).bit();
+            // Only enumerate each service type once.
+            let first = self.config.services[..i]
+                .iter()
+                .all(|s| !s.service_type.eq_ignore_ascii_case(service.service_type));
+            if first {
+                set |= Record::Enumeration(i).bit();
+            }
+        }
+        set
+    }
+
+    /// Names we own, whose records must be unique.
+    fn unique_names(&self) -> impl Iterator<Item = Name> + Clone {
+        core::iter::once(Name::Host).chain((0..self.config.services.len()).map(Name::Instance))
+    }
+
+    fn names(&self) -> impl Iterator<Item = Name> {
+        let services = self.config.services.len();
+        self.unique_names()
+            .chain(core::iter::once(Name::Services))
+            .chain((0..services).map(Name::ServiceType))
+    }
+
+    /// Find which of our names `name`, in uncompressed wire format, is.
+    fn match_name(&self, name: &[u8]) -> Option<Name> {
+        self.names().find(|n| self.name_eq(name, *n))
+    }
+
+    fn name_eq(&self, wire: &[u8], name: Name) -> bool {
+        let mut buf = [0; MAX_NAME_LEN];
+        let mut w = Writer::new(&mut buf);
+        self.write_name(&mut w, name).is_ok() && w.written().eq_ignore_ascii_case(wire)
+    }
+
+    /// Whether a received record is the same as our `record`.
+    fn record_eq(&self, msg: &[u8], rr: &ResourceRecord, record: Record) -> bool {
+        rr.rtype == record.rtype() && self.name_eq(rr.name(), record.name()) && self.rdata_eq(msg, rr, record)
+    }
+
+    /// Whether the data of a received record is the same as the data of our `record`.
+    fn rdata_eq(&self, msg: &[u8], rr: &ResourceRecord, record: Record) -> bool {
+        let rdata = &msg[rr.rdata.clone()];
+        // Names in the data of PTR and SRV records may be compressed.
+        let (fixed, target) = match record {
+            Record::Ptr(i) => (0, Name::Instance(i)),
+            Record::Enumeration(i) => (0, Name::ServiceType(i)),
+            Record::Srv(i) => {
+                let mut ours = [0; 6];
+                ours[4..].copy_from_slice(&self.config.services[i].port.to_be_bytes());
+                if rdata.get(..6) != Some(&ours[..]) {
+                    return false;
+                }
+                (6, Name::Host)
+            }
+            _ => {
+                let mut ours = [0; MAX_MESSAGE_LEN];
+                let mut w = Writer::new(&mut ours);
+                return self.write_rdata(&mut w, record).is_ok() && w.written() == rdata;
+            }
+        };
+        let mut pos = rr.rdata.start + fixed;
+        let mut name = [0; MAX_NAME_LEN];
+        read_name(msg, &mut pos, &mut name).is_some_and(|len| self.name_eq(&name[..len], target))
+    }
+
+    fn host_label(&self) -> String<63> {
+        suffixed(self.config.hostname, self.host_suffix, "-", "")
+    }
+
+    fn instance_label(&self, index: usize) -> String<63> {
+        suffixed(
+            self.config.services[index].instance,
+            self.service_suffixes[index],
+            " (",
+            ")",
+        )
+    }
+
+    fn write_name(&self, w: &mut Writer<'_>, name: Name) -> Result<(), BufferFull> {
+        match name {
+            Name::Host => w.string(self.host_label().as_bytes())?,
+            Name::Services => w.labels("_services._dns-sd._udp")?,
+            Name::ServiceType(i) => w.labels(self.config.services[i].service_type)?,
+            Name::Instance(i) => {
+                w.string(self.instance_label(i).as_bytes())?;
+                w.labels(self.config.services[i].service_type)?;
+            }
+        }
+        w.labels("local")?;
+        w.bytes(&[0])
+    }
+
+    fn write_rdata(&self, w: &mut Writer<'_>, record: Record) -> Result<(), BufferFull> {
+        match record {
+            Record::A => w.bytes(&self.addresses.v4().ok_or(BufferFull)?),
+            Record::Aaaa => w.bytes(&self.addresses.v6().ok_or(BufferFull)?),
+            Record::Ptr(i) => self.write_name(w, Name::Instance(i)),
+            Record::Enumeration(i) => self.write_name(w, Name::ServiceType(i)),
+            Record::Srv(i) => {
+                // Priority and weight.
+                w.u16(0)?;
+                w.u16(0)?;
+                w.u16(self.config.services[i].port)?;
+                self.write_name(w, Name::Host)
+            }
+            Record::Txt(i) => {
+                let txt = self.config.services[i].txt;
+                if txt.is_empty() {
+                    // TXT records can't be empty (RFC 6763 section 6.1).
+                    return w.bytes(&[0]);
+                }
+                txt.iter().try_for_each(|entry| w.string(entry.as_bytes()))
+            }
+        }
+    }
+
+    fn write_record(&self, w: &mut Writer<'_>, record: Record, ttl: u32, cache_flush: bool) -> Result<(), BufferFull> {
+        self.write_name(w, record.name())?;
+        w.u16(record.rtype())?;
+        let flush = match cache_flush && record.name().is_unique() {
+            true => CLASS_FLAG,
+            false => 0,
+        };
+        w.u16(CLASS_IN | flush)?;
+        w.u32(ttl)?;
+        let len_pos = w.len;
+        w.u16(0)?;
+        self.write_rdata(w, record)?;
+        let len = (w.len - len_pos - 2) as u16;
+        w.buf[len_pos..len_pos + 2].copy_from_slice(&len.to_be_bytes());
+        Ok(())
+    }
+}
+
+/// `base` followed by `suffix` if it's not 0, e.g. `name-2` or `Name (2)`.
+fn suffixed(base: &str, suffix: u16, before: &str, after: &str) -> String<63> {
+    let mut label = String::new();
+    let mut number = String::<16>::new();
+    if suffix != 0 {
+        let _ = write!(number, "{}{}{}", before, suffix, after);
+    }
+    // Truncate the base name so that the suffix fits, on a character boundary.
+    let mut end = base.len().min(63 - number.len());
+    while !base.is_char_boundary(end) {
+        end -= 1;
+    }
+    let _ = label.push_str(&base[..end]);
+    let _ = label.push_str(&number);
+    label
+}
+
+#[cfg(all(test, feature = "proto-ipv4", feature = "medium-ip"))]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::udp::PacketMetadata;
+    use crate::StackResources;
+
+    const OURS: Ipv4Address = Ipv4Address::new(10, 0, 0, 1);
+    const PEER: Ipv4Address = Ipv4Address::new(10, 0, 0, 2);
+
+    static SERVICES: [Service<'static>; 1] = [Service::new("Dev", "_http._tcp", 80)];
+
+    /// xorshift, good enough for delays in tests.
+    struct Rng(u64);
+
+    impl RngCore for Rng {
+        fn next_u32(&mut self) -> u32 {
+            self.next_u64() as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            rand_core::impls::fill_bytes_via_next(self, dest)
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    /// A responder for `dev.local` at `OURS`, with an HTTP service.
+    fn responder() -> Responder<'static, Rng> {
+        let (stack, _) = crate::new(
+            crate::tests::device(),
+            crate::Config::default(),
+            Box::leak(Box::new(StackResources::<2>::new())),
+            0,
+        );
+        let meta = || &mut Box::leak(Box::new([PacketMetadata::EMPTY; 1]))[..];
+        let buf = || &mut Box::leak(Box::new([0u8; 16]))[..];
+        let socket = UdpSocket::new(stack, meta(), buf(), meta(), buf());
+        let mut config = Config::new("dev");
+        config.services = &SERVICES;
+        let mut responder = unwrap!(Responder::new(socket, config, Rng(1)).ok());
+        responder.addresses.v4 = Some(OURS);
+        responder
+    }
+
+    /// `name` in uncompressed wire format.
+    fn name(name: &str) -> Vec<u8> {
+        let mut wire = Vec::new();
+        for label in name.split('.') {
+            wire.push(label.len() as u8);
+            wire.extend_from_slice(label.as_bytes());
+        }
+        wire.push(0);
+        wire
+    }
+
+    /// The header of a message with ID 0x1234.
+    fn begin(flags: u16, counts: [u16; 4]) -> Vec<u8> {
+        let mut msg = Vec::from(0x1234u16.to_be_bytes());
+        msg.extend_from_slice(&flags.to_be_bytes());
+        for count in counts {
+            msg.extend_from_slice(&count.to_be_bytes());
+        }
+        msg
+    }
+
+    fn question(msg: &mut Vec<u8>, name: &[u8], qtype: u16, qclass: u16) {
+        msg.extend_from_slice(name);
+        msg.extend_from_slice(&qtype.to_be_bytes());
+        msg.extend_from_slice(&qclass.to_be_bytes());
+    }
+
+    fn record(msg: &mut Vec<u8>, name: &[u8], rtype: u16, ttl: u32, rdata: &[u8]) {
+        msg.extend_from_slice(name);
+        msg.extend_from_slice(&rtype.to_be_bytes());
+        msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+        msg.extend_from_slice(&ttl.to_be_bytes());
+        msg.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        msg.extend_from_slice(rdata);
+    }
+
+    fn read(msg: &[u8], mut pos: usize) -> Option<(Vec<u8>, usize)> {
+        let mut out = [0; MAX_NAME_LEN];
+        let len = read_name(msg, &mut pos, &mut out)?;
+        Some((out[..len].to_vec(), pos))
+    }
+
+    fn query(responder: &Responder<'static, Rng>, msg: &[u8], port: u16) -> Option<(Header, Vec<u8>, IpEndpoint)> {
+        let mut buf = [0; MAX_MESSAGE_LEN];
+        let (len, dst) = responder.answer(msg, IpEndpoint::new(PEER.into(), port), &mut buf)?;
+        Some((unwrap!(Header::parse(&buf[..len])), buf[..len].to_vec(), dst))
+    }
+
+    #[test]
+    fn names() {
+        core::assert_eq!(read(&name("dev.local"), 0), Some((name("dev.local"), 11)));
+
+        // `dev` followed by a pointer to `local`, which is read up to the pointer.
+        let mut msg = name("local");
+        msg.extend_from_slice(b"\x03dev\xc0\x00");
+        core::assert_eq!(read(&msg, 7), Some((name("dev.local"), msg.len())));
+
+        // Pointers to the pointer itself or further in the message.
+        core::assert_eq!(read(b"\xc0\x00", 0), None);
+        core::assert_eq!(read(b"\xc0\x02\x00", 0), None);
+        // A label pointing back to itself is followed until the name is too long.
+        core::assert_eq!(read(b"\x03abc\xc0\x00", 0), None);
+
+        // The longest name fits, one more byte doesn't.
+        let label = "a".repeat(63);
+        let longest = name(&[&label[..], &label, &label, &label[..61]].join("."));
+        assert_eq!(longest.len(), MAX_NAME_LEN);
+        core::assert_eq!(read(&longest, 0), Some((longest.clone(), MAX_NAME_LEN)));
+        let too_long = name(&[&label[..], &label, &label, &label[..62]].join("."));
+        core::assert_eq!(read(&too_long, 0), None);
+
+        // Reserved label types and truncated names.
+        core::assert_eq!(read(b"\x40\x00", 0), None);
+        core::assert_eq!(read(b"\x03de", 0), None);
+        core::assert_eq!(read(b"\x03dev", 0), None);
+    }
+
+    #[test]
+    fn answers() {
+        let responder = responder();
+
+        // Multicast answer to a query for our address.
+        let mut msg = begin(0, [1, 0, 0, 0]);
+        question(&mut msg, &name("dev.local"), TYPE_A, CLASS_IN);
+        let (header, response, dst) = unwrap!(query(&responder, &msg, MDNS_PORT));
+        assert_eq!((header.id, header.flags), (0, FLAG_RESPONSE | FLAG_AUTHORITATIVE));
+        assert_eq!(header.counts, [0, 1, 0, 0]);
+        let mut expected = response[..12].to_vec();
+        record(&mut expected, &name("dev.local"), TYPE_A, HOST_TTL, &OURS.octets());
+        expected[12 + 11 + 2] |= 0x80;
+        core::assert_eq!(response, expected);
+        assert_eq!(dst, IpEndpoint::new(MDNS_GROUP_V4.into(), MDNS_PORT));
+
+        // The unicast response bit.
+        let mut msg = begin(0, [1, 0, 0, 0]);
+        question(&mut msg, &name("DEV.local"), TYPE_ANY, CLASS_IN | CLASS_FLAG);
+        let (_, _, dst) = unwrap!(query(&responder, &msg, MDNS_PORT));
+        assert_eq!(dst, IpEndpoint::new(PEER.into(), MDNS_PORT));
+
+        // Browsing a service type gets the instance, with its records as additional records.
+        let mut msg = begin(0, [1, 0, 0, 0]);
+        question(&mut msg, &name("_http._tcp.local"), TYPE_PTR, CLASS_IN);
+        let (header, _, _) = unwrap!(query(&responder, &msg, MDNS_PORT));
+        assert_eq!(header.counts, [0, 1, 0, 3]);
+
+        // Legacy unicast queries get their ID and question back, with short TTLs.
+        let mut msg = begin(0, [1, 0, 0, 0]);
+        question(&mut msg, &name("dev.local"), TYPE_A, CLASS_IN);
+        let (header, response, dst) = unwrap!(query(&responder, &msg, 12345));
+        assert_eq!((header.id, header.counts), (0x1234, [1, 1, 0, 0]));
+        let mut expected = response[..12].to_vec();
+        question(&mut expected, &name("dev.local"), TYPE_A, CLASS_IN);
+        record(&mut expected, &name("dev.local"), TYPE_A, LEGACY_TTL, &OURS.octets());
+        core::assert_eq!(response, expected);
+        assert_eq!(dst, IpEndpoint::new(PEER.into(), 12345));
+
+        // Names and records we don't have, other classes and opcodes.
+        for (qname, qtype, qclass) in [
+            ("other.local", TYPE_A, CLASS_IN),
+            ("dev.local", TYPE_AAAA, CLASS_IN),
+            ("dev.local", TYPE_A, 3),
+        ] {
+            let mut msg = begin(0, [1, 0, 0, 0]);
+            question(&mut msg, &name(qname), qtype, qclass);
+            assert!(query(&responder, &msg, MDNS_PORT).is_none());
+        }
+        let mut msg = begin(0x2800, [1, 0, 0, 0]);
+        question(&mut msg, &name("dev.local"), TYPE_A, CLASS_IN);
+        assert!(query(&responder, &msg, MDNS_PORT).is_none());
+    }
+
+    #[test]
+    fn known_answers() {
+        let responder = responder();
+        let known = |ttl| {
+            let mut msg = begin(0, [1, 1, 0, 0]);
+            question(&mut msg, &name("_http._tcp.local"), TYPE_PTR, CLASS_IN);
+            // `Dev` followed by a pointer to the name of the question.
+            record(&mut msg, b"\xc0\x0c", TYPE_PTR, ttl, b"\x03Dev\xc0\x0c");
+            msg
+        };
+        // Not answered while the querier has more than half of the TTL left.
+        assert!(query(&responder, &known(OTHER_TTL / 2), MDNS_PORT).is_none());
+        let (header, _, _) = unwrap!(query(&responder, &known(OTHER_TTL / 2 - 1), MDNS_PORT));
+        assert_eq!(header.counts, [0, 1, 0, 3]);
+
+        // Records with other data don't suppress the answer.
+        let mut msg = begin(0, [1, 1, 0, 0]);
+        question(&mut msg, &name("dev.local"), TYPE_ANY, CLASS_IN);
+        record(&mut msg, b"\xc0\x0c", TYPE_A, HOST_TTL, &[10, 0, 0, 9]);
+        let (header, _, _) = unwrap!(query(&responder, &msg, MDNS_PORT));
+        assert_eq!(header.counts, [0, 1, 0, 0]);
+    }
+
+    fn response(records: impl FnOnce(&mut Vec<u8>), count: u16) -> Vec<u8> {
+        let mut msg = begin(FLAG_RESPONSE | FLAG_AUTHORITATIVE, [0, count, 0, 0]);
+        records(&mut msg);
+        msg
+    }
+
+    #[test]
+    fn conflicts() {
+        let responder = responder();
+        let host = name("dev.local");
+        let a = |ttl, address: Ipv4Address| response(|msg| record(msg, &host, TYPE_A, ttl, &address.octets()), 1);
+
+        core::assert_eq!(responder.find_conflict(&a(HOST_TTL, PEER)), Some(Name::Host));
+        // Our own records, and goodbyes.
+        core::assert_eq!(responder.find_conflict(&a(HOST_TTL, OURS)), None);
+        core::assert_eq!(responder.find_conflict(&a(0, PEER)), None);
+        // Addresses of a family we don't have.
+        let aaaa = response(|msg| record(msg, &host, TYPE_AAAA, HOST_TTL, &[0; 16]), 1);
+        core::assert_eq!(responder.find_conflict(&aaaa), Some(Name::Host));
+
+        // Another port for our instance.
+        let instance = name("Dev._http._tcp.local");
+        let srv = |port: u16| {
+            let mut rdata = Vec::from([0, 0, 0, 0]);
+            rdata.extend_from_slice(&port.to_be_bytes());
+            rdata.extend_from_slice(&host);
+            response(|msg| record(msg, &instance, TYPE_SRV, HOST_TTL, &rdata), 1)
+        };
+        core::assert_eq!(responder.find_conflict(&srv(80)), None);
+        core::assert_eq!(responder.find_conflict(&srv(8080)), Some(Name::Instance(0)));
+
+        // Shared records don't conflict.
+        let ptr = response(
+            |msg| {
+                record(
+                    msg,
+                    &name("_http._tcp.local"),
+                    TYPE_PTR,
+                    OTHER_TTL,
+                    &name("Other._http._tcp.local"),
+                )
+            },
+            1,
+        );
+        core::assert_eq!(responder.find_conflict(&ptr), None);
+    }
+
+    /// A probe for `dev.local` proposing `records`.
+    fn probe(records: &[(u16, &[u8])]) -> Vec<u8> {
+        let host = name("dev.local");
+        let mut msg = begin(0, [1, 0, records.len() as u16, 0]);
+        question(&mut msg, &host, TYPE_ANY, CLASS_IN | CLASS_FLAG);
+        for (rtype, rdata) in records {
+            record(&mut msg, &host, *rtype, HOST_TTL, rdata);
+        }
+        msg
+    }
+
+    #[test]
+    fn tie_break() {
+        let mut responder = responder();
+        let check = |msg: &[u8]| match responder.check_probe(msg) {
+            ProbeCheck::None => "none",
+            ProbeCheck::Conflict(_) => "conflict",
+            ProbeCheck::Lost => "lost",
+        };
+
+        // The lexicographically later data wins.
+        assert_eq!(check(&probe(&[(TYPE_A, &PEER.octets())])), "lost");
+        assert_eq!(check(&probe(&[(TYPE_A, &[10, 0, 0, 0])])), "none");
+        // So does the later type, then the host with more records.
+        assert_eq!(check(&probe(&[(TYPE_AAAA, &[0; 16])])), "lost");
+        assert_eq!(check(&probe(&[(TYPE_A, &OURS.octets())])), "none");
+        assert_eq!(
+            check(&probe(&[(TYPE_A, &OURS.octets()), (TYPE_AAAA, &[0; 16])])),
+            "lost"
+        );
+        // Probes for other names, and responses claiming ours.
+        let mut other = begin(0, [1, 0, 1, 0]);
+        question(&mut other, &name("other.local"), TYPE_ANY, CLASS_IN);
+        record(&mut other, &name("other.local"), TYPE_A, HOST_TTL, &PEER.octets());
+        assert_eq!(check(&other), "none");
+        let claim = response(
+            |msg| record(msg, &name("dev.local"), TYPE_A, HOST_TTL, &PEER.octets()),
+            1,
+        );
+        assert_eq!(check(&claim), "conflict");
+
+        // Conflicts pick the next free name.
+        responder.rename(Name::Host);
+        assert_eq!(responder.hostname(), "dev-2");
+        core::assert_eq!(responder.find_conflict(&claim), None);
+        responder.rename(Name::Instance(0));
+        responder.rename(Name::Instance(0));
+        assert_eq!(responder.instance_name(0), "Dev (3)");
+    }
+
+    #[test]
+    fn jitter_bounds() {
+        let mut rng = Rng(1);
+        for _ in 0..100 {
+            assert!(jitter(&mut rng, PROBE_INTERVAL) < PROBE_INTERVAL);
+        }
+        assert_eq!(jitter(&mut rng, Duration::from_ticks(0)), Duration::from_ticks(0));
+    }
+}
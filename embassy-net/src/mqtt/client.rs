@@ -0,0 +1,500 @@
+use core::convert::Infallible;
+use core::future::pending;
+
+use embassy_futures::select::{select, select3, Either, Either3};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::channel::{Channel, TrySendError};
+use embassy_sync::pubsub::{self, PubSubChannel, Publisher, Subscriber};
+use embassy_time::{with_timeout, Duration, Ticker, Timer};
+use heapless::Vec;
+
+use super::packet::*;
+use super::{Config, Error, Message, ProtocolVersion, QoS};
+use crate::tcp::TcpSocket;
+use crate::Stack;
+
+/// How long to wait for the broker to accept a connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Queues of messages shared between a [`MqttClient`] and the application.
+///
+/// Messages hold at most `N` bytes of topic and payload. At most `Q` messages are queued for
+/// publishing, or for delivery to each of the `SUBS` subscribers.
+pub struct State<M: RawMutex, const N: usize, const Q: usize, const SUBS: usize> {
+    outgoing: Channel<M, Message<N>, Q>,
+    incoming: PubSubChannel<M, Message<N>, Q, SUBS, 1>,
+}
+
+impl<M: RawMutex, const N: usize, const Q: usize, const SUBS: usize> State<M, N, Q, SUBS> {
+    /// Create a new `State`.
+    pub const fn new() -> Self {
+        Self {
+            outgoing: Channel::new(),
+            incoming: PubSubChannel::new(),
+        }
+    }
+
+    /// Queue a message for publishing, waiting until there is room in the queue.
+    ///
+    /// Messages are queued while the client is disconnected, and published once it connects.
+    pub async fn publish(&self, message: Message<N>) {
+        self.outgoing.send(message).await
+    }
+
+    /// Queue a message for publishing, if there is room in the queue.
+    pub fn try_publish(&self, message: Message<N>) -> Result<(), Message<N>> {
+        self.outgoing.try_send(message).map_err(|TrySendError::Full(m)| m)
+    }
+
+    /// Create a subscriber receiving all the messages received from the broker.
+    ///
+    /// Messages are received for all the topic filters in [`Config::subscriptions`]; use
+    /// [`topic_matches`](super::topic_matches) to tell them apart.
+    ///
+    /// The client doesn't wait for subscribers, so that it keeps answering the broker. When a
+    /// subscriber has `Q` messages queued, it loses the oldest one and gets
+    /// [`WaitResult::Lagged`](pubsub::WaitResult::Lagged) instead.
+    pub fn subscriber(&self) -> Result<Subscriber<'_, M, Message<N>, Q, SUBS, 1>, pubsub::Error> {
+        self.incoming.subscriber()
+    }
+}
+
+impl<M: RawMutex, const N: usize, const Q: usize, const SUBS: usize> Default for State<M, N, Q, SUBS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Step of the acknowledgement of a message sent with QoS 1 or 2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// Waiting for PUBACK or PUBREC.
+    Publish,
+    /// Waiting for PUBCOMP.
+    Release,
+}
+
+struct Inflight<const N: usize> {
+    id: u16,
+    phase: Phase,
+    message: Message<N>,
+}
+
+/// MQTT client.
+///
+/// The client must be kept running with [`run`](Self::run), and is used through its [`State`].
+pub struct MqttClient<'a, M: RawMutex, const N: usize, const Q: usize, const SUBS: usize> {
+    stack: Stack<'a>,
+    config: Config<'a>,
+    state: &'a State<M, N, Q, SUBS>,
+    publisher: Publisher<'a, M, Message<N>, Q, SUBS, 1>,
+    /// Messages sent with QoS 1 or 2 and not acknowledged yet, in the order they were sent.
+    inflight: Vec<Inflight<N>, Q>,
+    /// Packet identifiers of QoS 2 messages received and not released yet.
+    received: Vec<u16, Q>,
+    last_id: u16,
+}
+
+impl<'a, M: RawMutex, const N: usize, const Q: usize, const SUBS: usize> MqttClient<'a, M, N, Q, SUBS> {
+    /// Create a client.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `state` is already used by another client.
+    pub fn new(stack: Stack<'a>, config: Config<'a>, state: &'a State<M, N, Q, SUBS>) -> Self {
+        let publisher = unwrap!(state.incoming.publisher().ok(), "MQTT state is already in use");
+        Self {
+            stack,
+            config,
+            state,
+            publisher,
+            inflight: Vec::new(),
+            received: Vec::new(),
+            last_id: 0,
+        }
+    }
+
+    /// Run the client.
+    ///
+    /// Connects to the broker whenever the stack has a configuration, and connects again after
+    /// errors. `rx_buffer` and `tx_buffer` are used by the TCP socket.
+    pub async fn run(&mut self, rx_buffer: &mut [u8], tx_buffer: &mut [u8]) -> ! {
+        loop {
+            self.stack.wait_config_up().await;
+            let mut socket = TcpSocket::new(self.stack, rx_buffer, tx_buffer);
+            let stack = self.stack;
+            match select(self.session(&mut socket), stack.wait_config_down()).await {
+                Either::First(Err(e)) => {
+                    warn!("MQTT: error: {:?}", e);
+                    socket.abort();
+                    let _ = with_timeout(Duration::from_secs(1), socket.flush()).await;
+                    drop(socket);
+                    Timer::after(self.config.reconnect_delay).await;
+                }
+                Either::First(Ok(never)) => match never {},
+                Either::Second(()) => info!("MQTT: network down"),
+            }
+        }
+    }
+
+    async fn session(&mut self, socket: &mut TcpSocket<'_>) -> Result<Infallible, Error> {
+        let keep_alive = self.config.keep_alive;
+        if keep_alive != Duration::from_ticks(0) {
+            // The broker doesn't answer pings when it is gone.
+            socket.set_timeout(Some(keep_alive + keep_alive / 2));
+        }
+        let session_present = match with_timeout(CONNECT_TIMEOUT, self.connect(socket)).await {
+            Ok(r) => r?,
+            Err(_) => return Err(Error::Timeout),
+        };
+        info!("MQTT: connected, session present: {}", session_present);
+
+        if !session_present {
+            // The broker forgot about messages being sent in either direction. Messages it didn't
+            // receive yet are sent again as new ones.
+            self.received.clear();
+            self.inflight.retain(|i| i.phase == Phase::Publish);
+        }
+        for i in 0..self.inflight.len() {
+            let inflight = &self.inflight[i];
+            match inflight.phase {
+                Phase::Publish => {
+                    self.write_publish(socket, &inflight.message, Some(inflight.id), session_present)
+                        .await?
+                }
+                Phase::Release => Writer::start(socket, PUBREL, 0b0010, 2).await?.u16(inflight.id).await?,
+            }
+        }
+        self.subscribe(socket).await?;
+        socket.flush().await.map_err(Error::Io)?;
+
+        let mut ticker = match keep_alive != Duration::from_ticks(0) {
+            true => Some(Ticker::every(keep_alive)),
+            false => None,
+        };
+        let mut ping_pending = false;
+        let state = self.state;
+        loop {
+            let mut first = [0; 1];
+            let can_send = !self.inflight.is_full();
+            let tick = async {
+                match &mut ticker {
+                    Some(ticker) => ticker.next().await,
+                    None => pending().await,
+                }
+            };
+            let outgoing = async {
+                match can_send {
+                    true => state.outgoing.ready_to_receive().await,
+                    false => pending().await,
+                }
+            };
+            match select3(socket.read(&mut first), tick, outgoing).await {
+                Either3::First(Ok(0)) => return Err(Error::ConnectionClosed),
+                Either3::First(Ok(_)) => {
+                    if self.handle_packet(socket, first[0]).await? == PINGRESP {
+                        ping_pending = false;
+                    }
+                }
+                Either3::First(Err(e)) => return Err(Error::Io(e)),
+                Either3::Second(()) => {
+                    if ping_pending {
+                        return Err(Error::Timeout);
+                    }
+                    Writer::start(socket, PINGREQ, 0, 0).await?;
+                    ping_pending = true;
+                }
+                Either3::Third(()) => {
+                    if let Ok(message) = state.outgoing.try_receive() {
+                        self.publish(socket, message).await?;
+                    }
+                }
+            }
+            socket.flush().await.map_err(Error::Io)?;
+        }
+    }
+
+    /// Connect to the broker, returning whether it had a session for this client.
+    async fn connect(&mut self, socket: &mut TcpSocket<'_>) -> Result<bool, Error> {
+        let config = &self.config;
+        socket.connect(config.broker).await.map_err(Error::Connect)?;
+
+        let v5 = config.version == ProtocolVersion::V5;
+        // Session expiry interval, receive maximum, and maximum packet size: there is room for
+        // `N` bytes of topic and payload, and a few properties.
+        let properties_len = match config.clean_session {
+            true => 0,
+            false => 5,
+        } + 3
+            + 5;
+        let mut len = 6 + 1 + 1 + 2 + 2 + config.client_id.len();
+        if v5 {
+            len += varint_len(properties_len) + properties_len;
+        }
+        let mut flags = 0;
+        if config.clean_session {
+            flags |= 0x02;
+        }
+        if let Some(will) = &config.will {
+            flags |= 0x04 | ((will.qos as u8) << 3);
+            if will.retain {
+                flags |= 0x20;
+            }
+            len += usize::from(v5) + 2 + will.topic.len() + 2 + will.payload.len();
+        }
+        if let Some(username) = config.username {
+            flags |= 0x80;
+            len += 2 + username.len();
+        }
+        if let Some(password) = config.password {
+            flags |= 0x40;
+            len += 2 + password.len();
+        }
+
+        let mut w = Writer::start(socket, CONNECT, 0, len).await?;
+        w.string(b"MQTT").await?;
+        w.u8(if v5 { 5 } else { 4 }).await?;
+        w.u8(flags).await?;
+        w.u16(config.keep_alive.as_secs().min(u16::MAX.into()) as u16).await?;
+        if v5 {
+            w.varint(properties_len).await?;
+            if !config.clean_session {
+                w.u8(0x11).await?;
+                w.u32(config.session_expiry).await?;
+            }
+            w.u8(0x21).await?;
+            w.u16(Q.clamp(1, u16::MAX.into()) as u16).await?;
+            w.u8(0x27).await?;
+            w.u32((N + 16).min(u32::MAX as usize) as u32).await?;
+        }
+        w.string(config.client_id.as_bytes()).await?;
+        if let Some(will) = &config.will {
+            if v5 {
+                w.varint(0).await?;
+            }
+            w.string(will.topic.as_bytes()).await?;
+            w.string(will.payload).await?;
+        }
+        if let Some(username) = config.username {
+            w.string(username.as_bytes()).await?;
+        }
+        if let Some(password) = config.password {
+            w.string(password).await?;
+        }
+        socket.flush().await.map_err(Error::Io)?;
+
+        let mut first = [0; 1];
+        if socket.read(&mut first).await.map_err(Error::Io)? == 0 {
+            return Err(Error::ConnectionClosed);
+        }
+        if first[0] != CONNACK << 4 {
+            return Err(Error::Protocol);
+        }
+        let mut r = Reader::start(socket).await?;
+        let session_present = r.u8().await? & 0x01 != 0;
+        let code = r.u8().await?;
+        r.finish().await?;
+        match code {
+            0 => Ok(session_present),
+            code => Err(Error::Refused(code)),
+        }
+    }
+
+    async fn subscribe(&mut self, socket: &mut TcpSocket<'_>) -> Result<(), Error> {
+        let subscriptions = self.config.subscriptions;
+        if subscriptions.is_empty() {
+            return Ok(());
+        }
+        let v5 = self.config.version == ProtocolVersion::V5;
+        let len = 2 + usize::from(v5) + subscriptions.iter().map(|(f, _)| 2 + f.len() + 1).sum::<usize>();
+        let id = self.next_id();
+        let mut w = Writer::start(socket, SUBSCRIBE, 0b0010, len).await?;
+        w.u16(id).await?;
+        if v5 {
+            w.varint(0).await?;
+        }
+        for (filter, qos) in subscriptions {
+            w.string(filter.as_bytes()).await?;
+            w.u8(*qos as u8).await?;
+        }
+        Ok(())
+    }
+
+    /// Send a message queued by the application.
+    async fn publish(&mut self, socket: &mut TcpSocket<'_>, message: Message<N>) -> Result<(), Error> {
+        if message.qos == QoS::AtMostOnce {
+            return self.write_publish(socket, &message, None, false).await;
+        }
+        // The message is kept first, so that it is sent again if writing it fails. There is room
+        // for it, as messages are only taken from the queue then.
+        let id = self.next_id();
+        let inflight = Inflight {
+            id,
+            phase: Phase::Publish,
+            message,
+        };
+        unwrap!(self.inflight.push(inflight).ok());
+        let inflight = &self.inflight[self.inflight.len() - 1];
+        self.write_publish(socket, &inflight.message, Some(id), false).await
+    }
+
+    async fn write_publish(
+        &self,
+        socket: &mut TcpSocket<'_>,
+        message: &Message<N>,
+        id: Option<u16>,
+        dup: bool,
+    ) -> Result<(), Error> {
+        let v5 = self.config.version == ProtocolVersion::V5;
+        let topic = message.topic();
+        let len = 2 + topic.len() + id.map_or(0, |_| 2) + usize::from(v5) + message.payload().len();
+        let flags = (u8::from(dup) << 3) | ((message.qos as u8) << 1) | u8::from(message.retain);
+        let mut w = Writer::start(socket, PUBLISH, flags, len).await?;
+        w.string(topic.as_bytes()).await?;
+        if let Some(id) = id {
+            w.u16(id).await?;
+        }
+        if v5 {
+            w.varint(0).await?;
+        }
+        w.bytes(message.payload()).await
+    }
+
+    /// Handle a packet received from the broker, whose first byte is `first`, and return its
+    /// type.
+    async fn handle_packet(&mut self, socket: &mut TcpSocket<'_>, first: u8) -> Result<u8, Error> {
+        let v5 = self.config.version == ProtocolVersion::V5;
+        let kind = first >> 4;
+        if kind == PUBLISH {
+            return self.receive(socket, first).await.map(|_| kind);
+        }
+        let mut r = Reader::start(socket).await?;
+        match kind {
+            PUBACK | PUBREC | PUBCOMP => {
+                let id = r.u16().await?;
+                let code = r.reason_code().await?;
+                r.finish().await?;
+                let phase = match kind {
+                    PUBCOMP => Phase::Release,
+                    _ => Phase::Publish,
+                };
+                let Some(i) = self.inflight.iter().position(|i| i.id == id && i.phase == phase) else {
+                    debug!("MQTT: unexpected acknowledgement for {}", id);
+                    return Ok(kind);
+                };
+                if code >= 0x80 {
+                    warn!("MQTT: message {} rejected: {}", id, code);
+                    self.inflight.remove(i);
+                } else if kind == PUBREC {
+                    self.inflight[i].phase = Phase::Release;
+                    Writer::start(socket, PUBREL, 0b0010, 2).await?.u16(id).await?;
+                } else {
+                    self.inflight.remove(i);
+                }
+            }
+            PUBREL => {
+                let id = r.u16().await?;
+                r.finish().await?;
+                self.received.retain(|&r| r != id);
+                Writer::start(socket, PUBCOMP, 0, 2).await?.u16(id).await?;
+            }
+            SUBACK => {
+                let _id = r.u16().await?;
+                if v5 {
+                    r.skip_properties().await?;
+                }
+                for (filter, _) in self.config.subscriptions {
+                    if r.remaining() == 0 {
+                        break;
+                    }
+                    let code = r.u8().await?;
+                    if code >= 0x80 {
+                        warn!("MQTT: subscription to {} refused: {}", filter, code);
+                    }
+                }
+                r.finish().await?;
+            }
+            PINGRESP => r.finish().await?,
+            DISCONNECT if v5 => {
+                let code = r.reason_code().await?;
+                return Err(Error::Disconnected(code));
+            }
+            _ => return Err(Error::Protocol),
+        }
+        Ok(kind)
+    }
+
+    /// Receive a message published by the broker.
+    async fn receive(&mut self, socket: &mut TcpSocket<'_>, first: u8) -> Result<(), Error> {
+        let mut r = Reader::start(socket).await?;
+        let qos = QoS::from_bits((first >> 1) & 0b11).ok_or(Error::Protocol)?;
+        let retain = first & 1 != 0;
+        let topic_len = usize::from(r.u16().await?);
+        let mut data = Vec::<u8, N>::new();
+        let fits = data.resize(topic_len, 0).is_ok();
+        if fits {
+            r.bytes(&mut data).await?;
+            core::str::from_utf8(&data).map_err(|_| Error::Protocol)?;
+        } else {
+            r.skip(topic_len).await?;
+        }
+        let id = match qos {
+            QoS::AtMostOnce => None,
+            _ => Some(r.u16().await?),
+        };
+        if self.config.version == ProtocolVersion::V5 {
+            r.skip_properties().await?;
+        }
+        let payload_len = r.remaining();
+        let message = match fits && data.resize(topic_len + payload_len, 0).is_ok() {
+            true => {
+                r.bytes(&mut data[topic_len..]).await?;
+                Some(Message {
+                    data,
+                    topic_len,
+                    qos,
+                    retain,
+                })
+            }
+            false => {
+                warn!("MQTT: received message too large, dropped");
+                r.finish().await?;
+                None
+            }
+        };
+
+        match (qos, id) {
+            (QoS::ExactlyOnce, Some(id)) => {
+                // The broker sends the message again until it gets PUBREC, it must be delivered
+                // only once.
+                if !self.received.contains(&id) {
+                    if let Some(message) = message {
+                        self.publisher.publish_immediate(message);
+                    }
+                    if self.received.push(id).is_err() {
+                        warn!("MQTT: too many QoS 2 messages received, duplicates may be delivered");
+                    }
+                }
+                Writer::start(socket, PUBREC, 0, 2).await?.u16(id).await?;
+            }
+            (_, id) => {
+                if let Some(message) = message {
+                    self.publisher.publish_immediate(message);
+                }
+                if let Some(id) = id {
+                    Writer::start(socket, PUBACK, 0, 2).await?.u16(id).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn next_id(&mut self) -> u16 {
+        loop {
+            self.last_id = self.last_id.wrapping_add(1).max(1);
+            if !self.inflight.iter().any(|i| i.id == self.last_id) {
+                return self.last_id;
+            }
+        }
+    }
+}
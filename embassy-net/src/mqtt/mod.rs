@@ -0,0 +1,283 @@
+//! MQTT 3.1.1 and 5 client.
+//!
+//! [`MqttClient`] keeps a connection to a broker over a [`TcpSocket`](crate::tcp::TcpSocket),
+//! and reconnects when it is lost or when the stack gets a configuration again. The application
+//! talks to it through a [`State`]:
+//!
+//! - messages are queued for publishing with [`State::publish`], through a
+//!   [`Channel`](embassy_sync::channel::Channel);
+//! - received messages are delivered to every [`State::subscriber`], through a
+//!   [`PubSubChannel`](embassy_sync::pubsub::PubSubChannel). Subscribers that don't keep up
+//!   lose the oldest messages, instead of stalling the connection.
+//!
+//! QoS 1 and 2 messages are kept until the broker acknowledges them, and sent again after a
+//! reconnection. With [`Config::clean_session`] unset, the broker keeps the session too, so
+//! neither side loses messages while the connection is down.
+//!
+//! Everything is stored in fixed buffers: messages hold at most `N` bytes of topic and payload,
+//! and at most `Q` messages are queued or in flight in each direction.
+
+use embassy_time::Duration;
+use heapless::Vec;
+
+use crate::tcp::ConnectError;
+use crate::IpEndpoint;
+
+mod client;
+mod packet;
+
+pub use client::{MqttClient, State};
+
+/// MQTT protocol version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ProtocolVersion {
+    /// MQTT 3.1.1
+    V311,
+    /// MQTT 5
+    V5,
+}
+
+/// Quality of service of a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum QoS {
+    /// The message is delivered at most once, and may be lost.
+    AtMostOnce = 0,
+    /// The message is delivered at least once, and may be duplicated.
+    AtLeastOnce = 1,
+    /// The message is delivered exactly once.
+    ExactlyOnce = 2,
+}
+
+impl QoS {
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0 => Some(QoS::AtMostOnce),
+            1 => Some(QoS::AtLeastOnce),
+            2 => Some(QoS::ExactlyOnce),
+            _ => None,
+        }
+    }
+}
+
+/// MQTT error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The topic and payload don't fit in a [`Message`].
+    TooLarge,
+    /// The connection to the broker failed.
+    Connect(ConnectError),
+    /// The broker refused the connection, with this return code (MQTT 3.1.1) or reason code
+    /// (MQTT 5).
+    Refused(u8),
+    /// The broker closed the connection, with this reason code (MQTT 5).
+    Disconnected(u8),
+    /// Reading from or writing to the connection failed.
+    Io(crate::tcp::Error),
+    /// The connection was closed.
+    ConnectionClosed,
+    /// The broker sent an invalid packet.
+    Protocol,
+    /// The broker didn't answer in time.
+    Timeout,
+}
+
+/// A message, published or received.
+///
+/// The topic and payload are stored together, and their total length is limited to `N` bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Message<const N: usize> {
+    data: Vec<u8, N>,
+    topic_len: usize,
+    qos: QoS,
+    retain: bool,
+}
+
+impl<const N: usize> Message<N> {
+    /// Create a message.
+    ///
+    /// Returns [`Error::TooLarge`] if the topic and payload are longer than `N` bytes together.
+    pub fn new(topic: &str, payload: &[u8], qos: QoS, retain: bool) -> Result<Self, Error> {
+        let mut data = Vec::new();
+        data.extend_from_slice(topic.as_bytes()).map_err(|_| Error::TooLarge)?;
+        data.extend_from_slice(payload).map_err(|_| Error::TooLarge)?;
+        Ok(Self {
+            data,
+            topic_len: topic.len(),
+            qos,
+            retain,
+        })
+    }
+
+    /// Get the topic of the message.
+    pub fn topic(&self) -> &str {
+        // Topics are checked to be UTF-8 when received.
+        core::str::from_utf8(&self.data[..self.topic_len]).unwrap_or_default()
+    }
+
+    /// Get the payload of the message.
+    pub fn payload(&self) -> &[u8] {
+        &self.data[self.topic_len..]
+    }
+
+    /// Get the quality of service of the message.
+    pub fn qos(&self) -> QoS {
+        self.qos
+    }
+
+    /// Get whether the message is retained by the broker.
+    ///
+    /// For received messages, this is set when the message was retained before subscribing.
+    pub fn retain(&self) -> bool {
+        self.retain
+    }
+}
+
+/// Message published by the broker when the client disconnects unexpectedly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Will<'a> {
+    /// Topic of the message.
+    pub topic: &'a str,
+    /// Payload of the message.
+    pub payload: &'a [u8],
+    /// Quality of service of the message.
+    pub qos: QoS,
+    /// Whether the broker retains the message.
+    pub retain: bool,
+}
+
+/// MQTT client configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Config<'a> {
+    /// Address of the broker.
+    pub broker: IpEndpoint,
+    /// Protocol version to use.
+    pub version: ProtocolVersion,
+    /// Client identifier, identifying the session on the broker.
+    pub client_id: &'a str,
+    /// User name to authenticate with.
+    pub username: Option<&'a str>,
+    /// Password to authenticate with.
+    pub password: Option<&'a [u8]>,
+    /// Interval of keep-alive pings. Zero disables them.
+    ///
+    /// It is sent in seconds to the broker, which closes the connection when it receives nothing
+    /// for one and a half of it.
+    pub keep_alive: Duration,
+    /// Start a new session on every connection, instead of resuming the previous one.
+    pub clean_session: bool,
+    /// How long the broker keeps the session after a disconnection, in seconds (MQTT 5).
+    ///
+    /// `u32::MAX` keeps it forever, like MQTT 3.1.1 does. Only used when `clean_session` is
+    /// unset.
+    pub session_expiry: u32,
+    /// Message published by the broker if the client disconnects unexpectedly.
+    pub will: Option<Will<'a>>,
+    /// Topic filters subscribed to on every connection, with their maximum quality of service.
+    pub subscriptions: &'a [(&'a str, QoS)],
+    /// Delay before connecting again after an error.
+    pub reconnect_delay: Duration,
+}
+
+impl<'a> Config<'a> {
+    /// Create a configuration for connecting to `broker` as `client_id`.
+    pub fn new(broker: IpEndpoint, client_id: &'a str) -> Self {
+        Self {
+            broker,
+            version: ProtocolVersion::V311,
+            client_id,
+            username: None,
+            password: None,
+            keep_alive: Duration::from_secs(60),
+            clean_session: false,
+            session_expiry: u32::MAX,
+            will: None,
+            subscriptions: &[],
+            reconnect_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Check whether `topic` matches the topic filter `filter`, which may contain `+` and `#`
+/// wildcards.
+///
+/// Subscribers receive the messages of all subscriptions, this can be used to tell them apart.
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    // Topics starting with `$` are not matched by wildcards at their first level.
+    if topic.starts_with('$') && (filter.starts_with('+') || filter.starts_with('#')) {
+        return false;
+    }
+    let mut filter = filter.split('/');
+    let mut topic = topic.split('/');
+    loop {
+        match (filter.next(), topic.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => {}
+            (Some(f), Some(t)) if f == t => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topic_filters() {
+        assert!(topic_matches("sensors/temperature", "sensors/temperature"));
+        assert!(!topic_matches("sensors/temperature", "sensors/humidity"));
+        assert!(!topic_matches("sensors", "sensors/temperature"));
+        assert!(!topic_matches("sensors/temperature", "sensors"));
+
+        assert!(topic_matches("sensors/+/value", "sensors/kitchen/value"));
+        assert!(!topic_matches("sensors/+/value", "sensors/kitchen/raw"));
+        assert!(!topic_matches("sensors/+", "sensors/kitchen/value"));
+        assert!(topic_matches("sensors/+", "sensors/"));
+        assert!(topic_matches("+/+", "/finance"));
+
+        assert!(topic_matches("sensors/#", "sensors/kitchen/value"));
+        // `#` also matches the parent level.
+        assert!(topic_matches("sensors/#", "sensors"));
+        assert!(topic_matches("#", "sensors/kitchen"));
+        assert!(!topic_matches("sensors/#", "actuators/kitchen"));
+
+        // Wildcards at the first level don't match topics starting with `$`.
+        assert!(!topic_matches("#", "$SYS/uptime"));
+        assert!(!topic_matches("+/uptime", "$SYS/uptime"));
+        assert!(topic_matches("$SYS/#", "$SYS/uptime"));
+    }
+
+    #[test]
+    fn message() {
+        let message = Message::<16>::new("a/b", b"hello", QoS::AtLeastOnce, true).unwrap();
+        assert_eq!(message.topic(), "a/b");
+        assert_eq!(message.payload(), b"hello");
+        assert_eq!(message.qos(), QoS::AtLeastOnce);
+        assert!(message.retain());
+
+        assert_eq!(
+            Message::<8>::new("a/b", b"hello!", QoS::AtMostOnce, false),
+            Err(Error::TooLarge)
+        );
+        assert!(Message::<8>::new("a/b", b"hello", QoS::AtMostOnce, false).is_ok());
+    }
+
+    #[test]
+    fn varint_lengths() {
+        assert_eq!(packet::varint_len(0), 1);
+        assert_eq!(packet::varint_len(127), 1);
+        assert_eq!(packet::varint_len(128), 2);
+        assert_eq!(packet::varint_len(16_383), 2);
+        assert_eq!(packet::varint_len(16_384), 3);
+        assert_eq!(packet::varint_len(2_097_151), 3);
+        assert_eq!(packet::varint_len(2_097_152), 4);
+    }
+}
@@ -0,0 +1,162 @@
+//! Reading and writing MQTT packets on a TCP socket.
+
+use embedded_io_async::{Read, ReadExactError, Write};
+
+use super::Error;
+use crate::tcp::TcpSocket;
+
+pub(super) const CONNECT: u8 = 1;
+pub(super) const CONNACK: u8 = 2;
+pub(super) const PUBLISH: u8 = 3;
+pub(super) const PUBACK: u8 = 4;
+pub(super) const PUBREC: u8 = 5;
+pub(super) const PUBREL: u8 = 6;
+pub(super) const PUBCOMP: u8 = 7;
+pub(super) const SUBSCRIBE: u8 = 8;
+pub(super) const SUBACK: u8 = 9;
+pub(super) const PINGREQ: u8 = 12;
+pub(super) const PINGRESP: u8 = 13;
+pub(super) const DISCONNECT: u8 = 14;
+
+/// Get the length of a variable byte integer.
+pub(super) fn varint_len(value: usize) -> usize {
+    match value {
+        0..128 => 1,
+        128..16_384 => 2,
+        16_384..2_097_152 => 3,
+        _ => 4,
+    }
+}
+
+/// Writes the fields of a packet, after its length was computed.
+pub(super) struct Writer<'s, 'a> {
+    socket: &'s mut TcpSocket<'a>,
+}
+
+impl<'s, 'a> Writer<'s, 'a> {
+    /// Start a packet of type `kind` with `flags`, and a length of `len` after the fixed header.
+    pub async fn start(socket: &'s mut TcpSocket<'a>, kind: u8, flags: u8, len: usize) -> Result<Self, Error> {
+        let mut this = Self { socket };
+        this.u8((kind << 4) | flags).await?;
+        this.varint(len).await?;
+        Ok(this)
+    }
+
+    pub async fn bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.socket.write_all(data).await.map_err(Error::Io)
+    }
+
+    pub async fn u8(&mut self, value: u8) -> Result<(), Error> {
+        self.bytes(&[value]).await
+    }
+
+    pub async fn u16(&mut self, value: u16) -> Result<(), Error> {
+        self.bytes(&value.to_be_bytes()).await
+    }
+
+    pub async fn u32(&mut self, value: u32) -> Result<(), Error> {
+        self.bytes(&value.to_be_bytes()).await
+    }
+
+    /// Write a length-prefixed string or binary data.
+    pub async fn string(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.u16(data.len() as u16).await?;
+        self.bytes(data).await
+    }
+
+    pub async fn varint(&mut self, mut value: usize) -> Result<(), Error> {
+        loop {
+            let byte = (value % 128) as u8;
+            value /= 128;
+            if value == 0 {
+                return self.u8(byte).await;
+            }
+            self.u8(byte | 0x80).await?;
+        }
+    }
+}
+
+/// Reads the fields of a packet, without going past its end.
+pub(super) struct Reader<'s, 'a> {
+    socket: &'s mut TcpSocket<'a>,
+    remaining: usize,
+}
+
+impl<'s, 'a> Reader<'s, 'a> {
+    /// Read the length of a packet, whose first byte was already read.
+    pub async fn start(socket: &'s mut TcpSocket<'a>) -> Result<Self, Error> {
+        let mut this = Self { socket, remaining: 4 };
+        this.remaining = this.varint().await?;
+        Ok(this)
+    }
+
+    /// Get the number of bytes left in the packet.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    pub async fn bytes(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        if buf.len() > self.remaining {
+            return Err(Error::Protocol);
+        }
+        self.socket.read_exact(buf).await.map_err(|e| match e {
+            ReadExactError::UnexpectedEof => Error::ConnectionClosed,
+            ReadExactError::Other(e) => Error::Io(e),
+        })?;
+        self.remaining -= buf.len();
+        Ok(())
+    }
+
+    pub async fn u8(&mut self) -> Result<u8, Error> {
+        let mut buf = [0; 1];
+        self.bytes(&mut buf).await?;
+        Ok(buf[0])
+    }
+
+    pub async fn u16(&mut self) -> Result<u16, Error> {
+        let mut buf = [0; 2];
+        self.bytes(&mut buf).await?;
+        Ok(u16::from_be_bytes(buf))
+    }
+
+    pub async fn varint(&mut self) -> Result<usize, Error> {
+        let mut value = 0;
+        for i in 0..4 {
+            let byte = self.u8().await?;
+            value |= usize::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::Protocol)
+    }
+
+    /// Read the reason code of an acknowledgement, which is success when omitted (MQTT 5).
+    pub async fn reason_code(&mut self) -> Result<u8, Error> {
+        match self.remaining {
+            0 => Ok(0),
+            _ => self.u8().await,
+        }
+    }
+
+    /// Skip a length-prefixed property list (MQTT 5).
+    pub async fn skip_properties(&mut self) -> Result<(), Error> {
+        let len = self.varint().await?;
+        self.skip(len).await
+    }
+
+    pub async fn skip(&mut self, mut len: usize) -> Result<(), Error> {
+        let mut buf = [0; 32];
+        while len > 0 {
+            let n = len.min(buf.len());
+            self.bytes(&mut buf[..n]).await?;
+            len -= n;
+        }
+        Ok(())
+    }
+
+    /// Skip the rest of the packet.
+    pub async fn finish(mut self) -> Result<(), Error> {
+        self.skip(self.remaining).await
+    }
+}
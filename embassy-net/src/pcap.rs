@@ -0,0 +1,649 @@
+//! Packet capture in pcapng format.
+//!
+//! [`Capture`] wraps a [`Driver`] and records every frame it receives or transmits into a
+//! [`CaptureState`], as pcapng blocks which can be opened with Wireshark. Timestamps are taken
+//! from [`embassy_time::Instant`], so they count from boot.
+//!
+//! Frames are recorded synchronously while the stack processes them, into a buffer of `N` bytes.
+//! They are taken out of it in one of two ways, depending on the [`Mode`]:
+//!
+//! - [`Mode::Stream`]: [`CaptureState::run`] writes them continuously to an
+//!   [`embedded_io_async::Write`] sink, such as a [`Pipe`](embassy_sync::pipe::Pipe), a TCP
+//!   socket, or a file on std through `embedded-io-adapters`. Frames are dropped when the sink
+//!   can't keep up.
+//! - [`Mode::Ring`]: the buffer keeps the last frames, dropping the oldest ones. They are written
+//!   out on demand with [`CaptureState::dump`], or [`CaptureState::dump_blocking`] from contexts
+//!   that can't wait, like a fault handler.
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::task::{Context, Poll};
+
+use embassy_net_driver::{Capabilities, Driver, HardwareAddress, LinkState, RxToken, TxToken};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::waitqueue::WakerRegistration;
+use embassy_time::Instant;
+
+const BLOCK_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+const BLOCK_ENHANCED_PACKET: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_RAW: u16 = 101;
+const LINKTYPE_USER0: u16 = 147;
+const LINKTYPE_IEEE802_15_4_NOFCS: u16 = 230;
+
+const OPTION_END: u16 = 0;
+const OPTION_EPB_FLAGS: u16 = 2;
+
+/// Size of an Enhanced Packet Block, without the packet data.
+const PACKET_OVERHEAD: usize = 44;
+/// Size of the Section Header Block and Interface Description Block starting a capture.
+const HEADER_LEN: usize = 28 + 20;
+
+/// How captured frames are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Mode {
+    /// Keep frames until they are written out, dropping new ones when the buffer is full.
+    Stream,
+    /// Keep the last frames, dropping the oldest ones when the buffer is full.
+    Ring,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Inbound = 1,
+    Outbound = 2,
+}
+
+/// Byte ring buffer, holding whole pcapng blocks.
+///
+/// Blocks are only removed whole, so the start of the buffer is always the start of a block.
+/// The bytes of the oldest block already written out are counted in `sent`, and the block is
+/// removed once written completely.
+struct Ring<const N: usize> {
+    buf: [u8; N],
+    start: usize,
+    len: usize,
+    /// Bytes of the oldest block already written out.
+    sent: usize,
+}
+
+impl<const N: usize> Ring<N> {
+    const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            start: 0,
+            len: 0,
+            sent: 0,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        let end = (self.start + self.len) % N;
+        let first = data.len().min(N - end);
+        self.buf[end..end + first].copy_from_slice(&data[..first]);
+        self.buf[..data.len() - first].copy_from_slice(&data[first..]);
+        self.len += data.len();
+    }
+
+    /// Copy bytes from `offset` bytes after the start of the buffer into `buf`, without removing
+    /// them.
+    fn peek(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let n = buf.len().min(self.len - offset);
+        let start = (self.start + offset) % N;
+        let first = n.min(N - start);
+        buf[..first].copy_from_slice(&self.buf[start..start + first]);
+        buf[first..n].copy_from_slice(&self.buf[..n - first]);
+        n
+    }
+
+    /// Get the length of the oldest block.
+    fn block_len(&self) -> usize {
+        let mut header = [0; 8];
+        self.peek(0, &mut header);
+        u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize
+    }
+
+    /// Remove the oldest block.
+    fn pop_block(&mut self) {
+        let len = self.block_len();
+        self.start = (self.start + len) % N;
+        self.len -= len;
+        self.sent = self.sent.saturating_sub(len);
+    }
+
+    /// Mark `n` more bytes as written out, removing the blocks written completely.
+    fn consume(&mut self, n: usize) {
+        self.sent += n;
+        while self.len > 0 && self.sent >= self.block_len() {
+            self.pop_block();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+        self.sent = 0;
+    }
+}
+
+struct Inner<const N: usize> {
+    ring: Ring<N>,
+    mode: Mode,
+    snaplen: usize,
+    linktype: u16,
+    enabled: bool,
+    /// Whether frames are being written out, in which case no block is evicted.
+    writing: bool,
+    dropped: u32,
+    waker: WakerRegistration,
+}
+
+/// Buffer of captured frames, shared between a [`Capture`] and the code writing them out.
+///
+/// At most `N` bytes of pcapng blocks are kept. Each frame takes 44 bytes more than its length.
+pub struct CaptureState<M: RawMutex, const N: usize> {
+    inner: Mutex<M, RefCell<Inner<N>>>,
+}
+
+impl<M: RawMutex, const N: usize> CaptureState<M, N> {
+    /// Create a new `CaptureState`.
+    ///
+    /// Frames longer than `snaplen` bytes are truncated.
+    pub const fn new(mode: Mode, snaplen: usize) -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(Inner {
+                ring: Ring::new(),
+                mode,
+                snaplen,
+                linktype: LINKTYPE_ETHERNET,
+                enabled: true,
+                writing: false,
+                dropped: 0,
+                waker: WakerRegistration::new(),
+            })),
+        }
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut Inner<N>) -> R) -> R {
+        self.inner.lock(|inner| f(&mut inner.borrow_mut()))
+    }
+
+    /// Enable or disable capturing frames.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.with(|inner| inner.enabled = enabled)
+    }
+
+    /// Get the number of frames dropped because the buffer was full.
+    ///
+    /// In [`Mode::Ring`], frames are only dropped when larger than the whole buffer, or while
+    /// the buffer is full and being written out.
+    pub fn dropped(&self) -> u32 {
+        self.with(|inner| inner.dropped)
+    }
+
+    /// Remove all the captured frames.
+    pub fn clear(&self) {
+        self.with(|inner| inner.ring.clear())
+    }
+
+    fn record(&self, direction: Direction, frame: &[u8]) {
+        let micros = Instant::now().as_micros();
+        self.with(|inner| {
+            if !inner.enabled {
+                return;
+            }
+            let captured = frame.len().min(inner.snaplen);
+            let padded = captured.next_multiple_of(4);
+            let len = PACKET_OVERHEAD + padded;
+            if len > N {
+                inner.dropped += 1;
+                return;
+            }
+            match inner.mode {
+                Mode::Stream if N - inner.ring.len < len => {
+                    inner.dropped += 1;
+                    return;
+                }
+                Mode::Stream => {}
+                Mode::Ring => {
+                    while N - inner.ring.len < len {
+                        // Evicting a block being written out would corrupt the output.
+                        if inner.writing {
+                            inner.dropped += 1;
+                            return;
+                        }
+                        inner.ring.pop_block();
+                    }
+                }
+            }
+
+            let ring = &mut inner.ring;
+            ring.push(&BLOCK_ENHANCED_PACKET.to_le_bytes());
+            ring.push(&(len as u32).to_le_bytes());
+            // Interface ID.
+            ring.push(&0u32.to_le_bytes());
+            ring.push(&((micros >> 32) as u32).to_le_bytes());
+            ring.push(&(micros as u32).to_le_bytes());
+            ring.push(&(captured as u32).to_le_bytes());
+            ring.push(&(frame.len() as u32).to_le_bytes());
+            ring.push(&frame[..captured]);
+            ring.push(&[0; 3][..padded - captured]);
+            ring.push(&OPTION_EPB_FLAGS.to_le_bytes());
+            ring.push(&4u16.to_le_bytes());
+            ring.push(&(direction as u32).to_le_bytes());
+            ring.push(&OPTION_END.to_le_bytes());
+            ring.push(&0u16.to_le_bytes());
+            ring.push(&(len as u32).to_le_bytes());
+            inner.waker.wake();
+        })
+    }
+
+    /// Get the Section Header Block and Interface Description Block starting a capture.
+    fn header(&self) -> [u8; HEADER_LEN] {
+        let (linktype, snaplen) = self.with(|inner| (inner.linktype, inner.snaplen));
+        let mut header = [0; HEADER_LEN];
+        let fields: [&[u8]; 12] = [
+            &BLOCK_SECTION_HEADER.to_le_bytes(),
+            &28u32.to_le_bytes(),
+            &BYTE_ORDER_MAGIC.to_le_bytes(),
+            // Version 1.0.
+            &[1, 0, 0, 0],
+            // Unknown section length.
+            &u64::MAX.to_le_bytes(),
+            &28u32.to_le_bytes(),
+            &BLOCK_INTERFACE_DESCRIPTION.to_le_bytes(),
+            &20u32.to_le_bytes(),
+            &linktype.to_le_bytes(),
+            &[0, 0],
+            &(snaplen.min(u32::MAX as usize) as u32).to_le_bytes(),
+            &20u32.to_le_bytes(),
+        ];
+        let mut pos = 0;
+        for field in fields {
+            header[pos..pos + field.len()].copy_from_slice(field);
+            pos += field.len();
+        }
+        header
+    }
+
+    /// Copy the captured bytes not written out yet into `buf`, waiting for some if there are none.
+    async fn peek(&self, buf: &mut [u8]) -> usize {
+        poll_fn(|cx| {
+            self.with(|inner| match inner.ring.peek(inner.ring.sent, buf) {
+                0 => {
+                    inner.waker.register(cx.waker());
+                    Poll::Pending
+                }
+                n => Poll::Ready(n),
+            })
+        })
+        .await
+    }
+
+    /// Mark `n` bytes as written out.
+    fn consume(&self, n: usize) {
+        self.with(|inner| inner.ring.consume(n))
+    }
+
+    /// Start writing out frames from the oldest block, disabling capturing if `disable` is set.
+    fn start_writing(&self, disable: bool) -> WriteGuard<'_, M, N> {
+        let enabled = self.with(|inner| {
+            inner.writing = true;
+            inner.ring.sent = 0;
+            let enabled = inner.enabled;
+            inner.enabled &= !disable;
+            disable.then_some(enabled)
+        });
+        WriteGuard { state: self, enabled }
+    }
+
+    /// Write the captured frames to `sink` continuously, as a pcapng stream.
+    ///
+    /// This is meant for [`Mode::Stream`]. It only returns if writing fails.
+    pub async fn run<W: embedded_io_async::Write>(&self, mut sink: W) -> Result<(), W::Error> {
+        let _guard = self.start_writing(false);
+        sink.write_all(&self.header()).await?;
+        let mut buf = [0; 128];
+        loop {
+            let n = self.peek(&mut buf).await;
+            sink.write_all(&buf[..n]).await?;
+            self.consume(n);
+            if self.with(|inner| inner.ring.len == 0) {
+                sink.flush().await?;
+            }
+        }
+    }
+
+    /// Write the captured frames to `sink` as a pcapng file, and remove them from the buffer.
+    ///
+    /// Capturing is disabled while writing, so that the frames being written are not dropped,
+    /// and enabled again afterwards, even if writing fails or the future is dropped. The frames
+    /// not completely written are kept.
+    pub async fn dump<W: embedded_io_async::Write>(&self, mut sink: W) -> Result<(), W::Error> {
+        let _guard = self.start_writing(true);
+        sink.write_all(&self.header()).await?;
+        let mut buf = [0; 128];
+        while self.with(|inner| inner.ring.len) > 0 {
+            let n = self.peek(&mut buf).await;
+            sink.write_all(&buf[..n]).await?;
+            self.consume(n);
+        }
+        sink.flush().await
+    }
+
+    /// Write the captured frames to `sink` as a pcapng file, and remove them from the buffer,
+    /// blocking until done.
+    ///
+    /// Capturing is disabled while writing, and enabled again afterwards.
+    pub fn dump_blocking<W: embedded_io::Write>(&self, mut sink: W) -> Result<(), W::Error> {
+        let _guard = self.start_writing(true);
+        sink.write_all(&self.header())?;
+        let mut buf = [0; 128];
+        loop {
+            let n = self.with(|inner| inner.ring.peek(inner.ring.sent, &mut buf));
+            if n == 0 {
+                break;
+            }
+            sink.write_all(&buf[..n])?;
+            self.consume(n);
+        }
+        sink.flush()
+    }
+}
+
+/// Ends writing out frames when done, failed or cancelled, enabling capturing again if it was
+/// disabled.
+struct WriteGuard<'a, M: RawMutex, const N: usize> {
+    state: &'a CaptureState<M, N>,
+    enabled: Option<bool>,
+}
+
+impl<M: RawMutex, const N: usize> Drop for WriteGuard<'_, M, N> {
+    fn drop(&mut self) {
+        self.state.with(|inner| {
+            inner.writing = false;
+            // Write the block written partially again next time.
+            inner.ring.sent = 0;
+            if let Some(enabled) = self.enabled {
+                inner.enabled = enabled;
+            }
+        })
+    }
+}
+
+/// Driver wrapper capturing all frames into a [`CaptureState`].
+pub struct Capture<'d, D: Driver, M: RawMutex, const N: usize> {
+    inner: D,
+    state: &'d CaptureState<M, N>,
+}
+
+impl<'d, D: Driver, M: RawMutex, const N: usize> Capture<'d, D, M, N> {
+    /// Wrap `driver`, capturing its frames into `state`.
+    pub fn new(driver: D, state: &'d CaptureState<M, N>) -> Self {
+        let linktype = match driver.hardware_address() {
+            HardwareAddress::Ethernet(_) => LINKTYPE_ETHERNET,
+            HardwareAddress::Ip => LINKTYPE_RAW,
+            HardwareAddress::Ieee802154(_) => LINKTYPE_IEEE802_15_4_NOFCS,
+            _ => LINKTYPE_USER0,
+        };
+        state.with(|inner| inner.linktype = linktype);
+        Self { inner: driver, state }
+    }
+
+    /// Get the wrapped driver.
+    pub fn inner(&mut self) -> &mut D {
+        &mut self.inner
+    }
+}
+
+impl<D: Driver, M: RawMutex, const N: usize> Driver for Capture<'_, D, M, N> {
+    type RxToken<'a>
+        = CaptureRxToken<'a, D::RxToken<'a>, M, N>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = CaptureTxToken<'a, D::TxToken<'a>, M, N>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, cx: &mut Context) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let (rx, tx) = self.inner.receive(cx)?;
+        Some((
+            CaptureRxToken {
+                inner: rx,
+                state: self.state,
+            },
+            CaptureTxToken {
+                inner: tx,
+                state: self.state,
+            },
+        ))
+    }
+
+    fn transmit(&mut self, cx: &mut Context) -> Option<Self::TxToken<'_>> {
+        let tx = self.inner.transmit(cx)?;
+        Some(CaptureTxToken {
+            inner: tx,
+            state: self.state,
+        })
+    }
+
+    fn link_state(&mut self, cx: &mut Context) -> LinkState {
+        self.inner.link_state(cx)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        self.inner.hardware_address()
+    }
+}
+
+/// Receive token of a [`Capture`].
+pub struct CaptureRxToken<'a, T: RxToken, M: RawMutex, const N: usize> {
+    inner: T,
+    state: &'a CaptureState<M, N>,
+}
+
+impl<T: RxToken, M: RawMutex, const N: usize> RxToken for CaptureRxToken<'_, T, M, N> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        self.inner.consume(|buf| {
+            self.state.record(Direction::Inbound, buf);
+            f(buf)
+        })
+    }
+}
+
+/// Transmit token of a [`Capture`].
+pub struct CaptureTxToken<'a, T: TxToken, M: RawMutex, const N: usize> {
+    inner: T,
+    state: &'a CaptureState<M, N>,
+}
+
+impl<T: TxToken, M: RawMutex, const N: usize> TxToken for CaptureTxToken<'_, T, M, N> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        self.inner.consume(len, |buf| {
+            let r = f(buf);
+            self.state.record(Direction::Outbound, buf);
+            r
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+    use embassy_futures::select::select;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embedded_io::ErrorKind;
+
+    use super::*;
+
+    /// Sink accepting `limit` bytes, then failing or never completing.
+    struct Sink {
+        buf: Vec<u8>,
+        limit: usize,
+    }
+
+    impl Sink {
+        fn new(limit: usize) -> Self {
+            Self { buf: Vec::new(), limit }
+        }
+    }
+
+    impl embedded_io::ErrorType for Sink {
+        type Error = ErrorKind;
+    }
+
+    impl embedded_io::Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, ErrorKind> {
+            let n = buf.len().min(self.limit - self.buf.len());
+            if n == 0 {
+                return Err(ErrorKind::Other);
+            }
+            self.buf.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> Result<(), ErrorKind> {
+            Ok(())
+        }
+    }
+
+    impl embedded_io_async::Write for Sink {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, ErrorKind> {
+            let n = buf.len().min(self.limit - self.buf.len());
+            if n == 0 {
+                core::future::pending::<()>().await;
+            }
+            self.buf.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+    }
+
+    fn u32_at(buf: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap())
+    }
+
+    /// Get the frames of the Enhanced Packet Blocks following the header, checking their layout.
+    fn frames(capture: &[u8]) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+        let mut pos = HEADER_LEN;
+        while pos < capture.len() {
+            let block = &capture[pos..];
+            assert_eq!(u32_at(block, 0), BLOCK_ENHANCED_PACKET);
+            let len = u32_at(block, 4) as usize;
+            assert_eq!(u32_at(block, len - 4) as usize, len);
+            let captured = u32_at(block, 20) as usize;
+            assert_eq!(len, PACKET_OVERHEAD + captured.next_multiple_of(4));
+            frames.push(block[28..28 + captured].to_vec());
+            pos += len;
+        }
+        assert_eq!(pos, capture.len());
+        frames
+    }
+
+    fn dump<const N: usize>(state: &CaptureState<NoopRawMutex, N>) -> Vec<u8> {
+        let mut sink = Sink::new(usize::MAX);
+        state.dump_blocking(&mut sink).unwrap();
+        sink.buf
+    }
+
+    #[test]
+    fn layout() {
+        let state = CaptureState::<NoopRawMutex, 256>::new(Mode::Stream, 4);
+        state.record(Direction::Outbound, &[1, 2, 3, 4, 5]);
+        let capture = dump(&state);
+
+        #[rustfmt::skip]
+        let header = [
+            // Section Header Block.
+            0x0a, 0x0d, 0x0d, 0x0a, 28, 0, 0, 0,
+            0x4d, 0x3c, 0x2b, 0x1a, 1, 0, 0, 0,
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            28, 0, 0, 0,
+            // Interface Description Block, for Ethernet with a snaplen of 4.
+            1, 0, 0, 0, 20, 0, 0, 0,
+            1, 0, 0, 0, 4, 0, 0, 0,
+            20, 0, 0, 0,
+        ];
+        assert_eq!(capture[..HEADER_LEN], header);
+
+        let block = &capture[HEADER_LEN..];
+        assert_eq!(block.len(), 48);
+        // Type, length and interface ID.
+        assert_eq!(block[..12], [6, 0, 0, 0, 48, 0, 0, 0, 0, 0, 0, 0]);
+        // Captured and original lengths, and the truncated frame.
+        assert_eq!(block[20..32], [4, 0, 0, 0, 5, 0, 0, 0, 1, 2, 3, 4]);
+        // Outbound flags option, end of options and length.
+        #[rustfmt::skip]
+        assert_eq!(block[32..], [2, 0, 4, 0, 2, 0, 0, 0, 0, 0, 0, 0, 48, 0, 0, 0]);
+    }
+
+    #[test]
+    fn ring_eviction() {
+        // Room for two blocks of 4 byte frames, so that blocks wrap around the end.
+        let state = CaptureState::<NoopRawMutex, 100>::new(Mode::Ring, 1500);
+        for i in 1..=5 {
+            state.record(Direction::Inbound, &[i; 4]);
+        }
+        core::assert_eq!(frames(&dump(&state)), [[4; 4], [5; 4]]);
+
+        // A larger frame evicts both blocks.
+        state.record(Direction::Inbound, &[6; 4]);
+        state.record(Direction::Inbound, &[7; 4]);
+        state.record(Direction::Inbound, &[8; 40]);
+        core::assert_eq!(frames(&dump(&state)), [[8; 40]]);
+
+        // Frames larger than the buffer are dropped.
+        state.record(Direction::Inbound, &[9; 60]);
+        assert_eq!(state.dropped(), 1);
+        core::assert_eq!(frames(&dump(&state)), Vec::<Vec<u8>>::new());
+    }
+
+    #[test]
+    fn failed_dump() {
+        let state = CaptureState::<NoopRawMutex, 100>::new(Mode::Ring, 1500);
+        state.record(Direction::Inbound, &[1; 4]);
+        state.record(Direction::Inbound, &[2; 4]);
+
+        // Fail halfway through the first block.
+        let mut sink = Sink::new(HEADER_LEN + 20);
+        assert!(state.dump_blocking(&mut sink).is_err());
+
+        // Capturing goes on, evicting the block written partially.
+        state.record(Direction::Inbound, &[3; 4]);
+        core::assert_eq!(frames(&dump(&state)), [[2; 4], [3; 4]]);
+    }
+
+    #[test]
+    fn cancelled_dump() {
+        let state = CaptureState::<NoopRawMutex, 100>::new(Mode::Ring, 1500);
+        state.record(Direction::Inbound, &[1; 4]);
+        state.record(Direction::Inbound, &[2; 4]);
+
+        // Stop halfway through the second block.
+        let mut sink = Sink::new(HEADER_LEN + 48 + 20);
+        block_on(select(state.dump(&mut sink), async {}));
+        core::assert_eq!(frames(&sink.buf[..HEADER_LEN + 48]), [[1; 4]]);
+
+        // The second block is written again.
+        state.record(Direction::Inbound, &[3; 4]);
+        core::assert_eq!(frames(&dump(&state)), [[2; 4], [3; 4]]);
+    }
+}
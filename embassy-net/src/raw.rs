@@ -5,12 +5,12 @@ use core::mem;
 use core::task::{Context, Poll};
 
 use embassy_net_driver::Driver;
-use smoltcp::iface::{Interface, SocketHandle};
+use smoltcp::iface::Interface;
 use smoltcp::socket::raw;
 pub use smoltcp::socket::raw::PacketMetadata;
 pub use smoltcp::wire::{IpProtocol, IpVersion};
 
-use crate::Stack;
+use crate::{InterfaceBindError, InterfaceId, SocketId, Stack};
 
 /// Error returned by [`RawSocket::recv`] and [`RawSocket::send`].
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -23,7 +23,7 @@ pub enum RecvError {
 /// An Raw socket.
 pub struct RawSocket<'a> {
     stack: Stack<'a>,
-    handle: SocketHandle,
+    handle: SocketId,
 }
 
 impl<'a> RawSocket<'a> {
@@ -42,7 +42,7 @@ impl<'a> RawSocket<'a> {
             let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
             let tx_meta: &'static mut [PacketMetadata] = unsafe { mem::transmute(tx_meta) };
             let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
-            i.sockets.add(raw::Socket::new(
+            i.add_socket(raw::Socket::new(
                 ip_version,
                 ip_protocol,
                 raw::PacketBuffer::new(rx_meta, rx_buffer),
@@ -53,13 +53,16 @@ impl<'a> RawSocket<'a> {
         Self { stack, handle }
     }
 
+    /// Bind the socket to a network interface.
+    ///
+    /// Sockets are created on the default interface, and stay there unless bound to another one.
+    pub fn bind_to_interface(&mut self, id: InterfaceId) -> Result<(), InterfaceBindError> {
+        self.handle = self.stack.with_mut(|i| i.bind_socket(self.handle, id))?;
+        Ok(())
+    }
+
     fn with_mut<R>(&self, f: impl FnOnce(&mut raw::Socket, &mut Interface) -> R) -> R {
-        self.stack.with_mut(|i| {
-            let socket = i.sockets.get_mut::<raw::Socket>(self.handle);
-            let res = f(socket, &mut i.iface);
-            i.waker.wake();
-            res
-        })
+        self.stack.with_mut(|i| i.with_socket_mut(self.handle, f))
     }
 
     /// Wait until the socket becomes readable.
@@ -181,7 +184,7 @@ impl<'a> RawSocket<'a> {
 
 impl Drop for RawSocket<'_> {
     fn drop(&mut self) {
-        self.stack.with_mut(|i| i.sockets.remove(self.handle));
+        self.stack.with_mut(|i| i.remove_socket(self.handle));
     }
 }
 
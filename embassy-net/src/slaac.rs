@@ -0,0 +1,708 @@
+//! IPv6 stateless address autoconfiguration (RFC 4862).
+//!
+//! Router Advertisements are received through a raw ICMPv6 socket owned by the stack. The
+//! state machine solicits routers, runs Duplicate Address Detection on the address derived
+//! from an autonomous /64 prefix, and tracks the lifetimes of the address, the default router
+//! and the RDNSS (RFC 8106) DNS servers.
+
+use core::mem::MaybeUninit;
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use smoltcp::iface::{SocketHandle, SocketSet};
+use smoltcp::socket::raw;
+use smoltcp::wire::{
+    HardwareAddress, Icmpv6Packet, IpProtocol, IpVersion, Ipv6Address, Ipv6Cidr, Ipv6Packet, Ipv6Repr,
+};
+
+use crate::{SlaacConfig, StaticConfigV6};
+
+const ALL_ROUTERS: Ipv6Address = Ipv6Address::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+
+// smoltcp only provides Neighbor Discovery representations for the Ethernet and 802.15.4
+// mediums, so messages are encoded by hand to also work on IP mediums such as PPP.
+const ROUTER_SOLICIT: u8 = 133;
+const ROUTER_ADVERT: u8 = 134;
+const NEIGHBOR_SOLICIT: u8 = 135;
+const NEIGHBOR_ADVERT: u8 = 136;
+const PREFIX_INFORMATION_OPTION: u8 = 3;
+const RDNSS_OPTION: u8 = 25;
+const PREFIX_FLAG_AUTONOMOUS: u8 = 0x40;
+/// RFC 4861 `RETRANS_TIMER`, used between DAD probes.
+const RETRANS_TIMER: Duration = Duration::from_secs(1);
+/// RFC 7559 `MAX_RTR_SOLICITATION_INTERVAL`, the limit of the backoff between Router
+/// Solicitations once the initial ones went unanswered.
+const MAX_SOLICITATION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// RFC 4862 section 5.5.3 (e).
+const TWO_HOURS: Duration = Duration::from_secs(2 * 60 * 60);
+const PREFIX_LEN: u8 = 64;
+/// Number of addresses remembered as duplicates, so they aren't probed again.
+const MAX_DUPLICATES: usize = 4;
+
+const RX_META: usize = 4;
+const RX_BUF: usize = 1024;
+const TX_META: usize = 2;
+const TX_BUF: usize = 256;
+
+pub(crate) struct SlaacResources {
+    rx_meta: MaybeUninit<[raw::PacketMetadata; RX_META]>,
+    rx_buffer: MaybeUninit<[u8; RX_BUF]>,
+    tx_meta: MaybeUninit<[raw::PacketMetadata; TX_META]>,
+    tx_buffer: MaybeUninit<[u8; TX_BUF]>,
+}
+
+impl SlaacResources {
+    pub(crate) const fn new() -> Self {
+        Self {
+            rx_meta: MaybeUninit::uninit(),
+            rx_buffer: MaybeUninit::uninit(),
+            tx_meta: MaybeUninit::uninit(),
+            tx_buffer: MaybeUninit::uninit(),
+        }
+    }
+}
+
+/// Result of polling the state machine.
+pub(crate) enum Event {
+    /// A global address is available, or its configuration changed.
+    Configured(StaticConfigV6),
+    /// The address was lost.
+    Deconfigured,
+}
+
+enum Dad {
+    Tentative { sent: u8, next: Instant },
+    Done,
+}
+
+struct Prefix {
+    address: Ipv6Cidr,
+    expires_at: Instant,
+    dad: Dad,
+}
+
+pub(crate) struct Slaac {
+    handle: SocketHandle,
+    config: SlaacConfig,
+    autoconfigure: bool,
+    iid: [u8; 8],
+    solicitations: u8,
+    next_solicitation: Option<Instant>,
+    router: Option<(Ipv6Address, Instant)>,
+    prefix: Option<Prefix>,
+    dns_servers: Vec<(Ipv6Address, Instant), 3>,
+    duplicates: Vec<Ipv6Address, MAX_DUPLICATES>,
+    current: Option<StaticConfigV6>,
+}
+
+impl Slaac {
+    /// Create the state machine and its raw socket.
+    ///
+    /// When `autoconfigure` is false, prefixes are ignored and only the default router and
+    /// DNS servers are tracked. This is used when addresses are assigned by DHCPv6.
+    ///
+    /// # Safety
+    /// `resources` must outlive the socket set, and must not be in use by another socket.
+    pub(crate) unsafe fn new(
+        sockets: &mut SocketSet<'static>,
+        resources: *mut SlaacResources,
+        config: SlaacConfig,
+        autoconfigure: bool,
+        hardware_address: HardwareAddress,
+        random_seed: u64,
+    ) -> Self {
+        let r = &mut *resources;
+        let socket = raw::Socket::new(
+            IpVersion::Ipv6,
+            IpProtocol::Icmpv6,
+            raw::PacketBuffer::new(
+                &mut r.rx_meta.write([raw::PacketMetadata::EMPTY; RX_META])[..],
+                &mut r.rx_buffer.write([0; RX_BUF])[..],
+            ),
+            raw::PacketBuffer::new(
+                &mut r.tx_meta.write([raw::PacketMetadata::EMPTY; TX_META])[..],
+                &mut r.tx_buffer.write([0; TX_BUF])[..],
+            ),
+        );
+        let handle = sockets.add(socket);
+
+        let mut this = Self {
+            handle,
+            config,
+            autoconfigure,
+            iid: interface_identifier(hardware_address, random_seed),
+            solicitations: 0,
+            next_solicitation: None,
+            router: None,
+            prefix: None,
+            dns_servers: Vec::new(),
+            duplicates: Vec::new(),
+            current: None,
+        };
+        this.reset(Instant::now());
+        this
+    }
+
+    pub(crate) fn handle(&self) -> SocketHandle {
+        self.handle
+    }
+
+    pub(crate) fn set_config(&mut self, config: SlaacConfig, autoconfigure: bool) {
+        self.config = config;
+        self.autoconfigure = autoconfigure;
+    }
+
+    /// Modified EUI-64 interface identifier used to form addresses.
+    #[cfg(feature = "dhcpv6")]
+    pub(crate) fn interface_identifier(&self) -> [u8; 8] {
+        self.iid
+    }
+
+    /// Autoconfigured address, once Duplicate Address Detection has completed.
+    pub(crate) fn address(&self) -> Option<Ipv6Cidr> {
+        match &self.prefix {
+            Some(Prefix {
+                address,
+                dad: Dad::Done,
+                ..
+            }) => Some(*address),
+            _ => None,
+        }
+    }
+
+    /// Current default router.
+    pub(crate) fn router(&self) -> Option<Ipv6Address> {
+        self.router.map(|(addr, _)| addr)
+    }
+
+    /// DNS servers learned from RDNSS options.
+    pub(crate) fn dns_servers(&self) -> impl Iterator<Item = Ipv6Address> + '_ {
+        self.dns_servers.iter().map(|(addr, _)| *addr)
+    }
+
+    /// Link-local address (`fe80::/64` plus our interface identifier).
+    pub(crate) fn link_local(&self) -> Ipv6Cidr {
+        Ipv6Cidr::new(
+            self.address_in(Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 0)),
+            PREFIX_LEN,
+        )
+    }
+
+    /// Forget all learned state and restart router solicitation.
+    pub(crate) fn reset(&mut self, now: Instant) {
+        self.solicitations = 0;
+        self.next_solicitation = Some(now);
+        self.router = None;
+        self.prefix = None;
+        self.dns_servers.clear();
+        // The link may have changed, give the addresses another chance.
+        self.duplicates.clear();
+        self.current = None;
+    }
+
+    /// Process received packets, expire state and transmit pending solicitations.
+    ///
+    /// `link_local_assigned` tells whether the interface owns our link-local address, and
+    /// can therefore use it as the source of Router Solicitations.
+    pub(crate) fn poll(
+        &mut self,
+        sockets: &mut SocketSet<'static>,
+        link_local_assigned: bool,
+        now: Instant,
+    ) -> Option<Event> {
+        let socket = sockets.get_mut::<raw::Socket>(self.handle);
+        while let Ok(packet) = socket.recv() {
+            self.process(packet, now);
+        }
+
+        self.expire(now);
+
+        if let Some(at) = self.next_solicitation {
+            if at <= now {
+                let src = match link_local_assigned {
+                    true => self.link_local().address(),
+                    false => Ipv6Address::UNSPECIFIED,
+                };
+                debug!("SLAAC: sending router solicitation");
+                send(socket, src, ALL_ROUTERS, &[ROUTER_SOLICIT, 0, 0, 0, 0, 0, 0, 0]);
+                self.solicitations = self.solicitations.saturating_add(1);
+                self.next_solicitation = Some(now + self.solicitation_interval());
+            }
+        }
+
+        if let Some(prefix) = &mut self.prefix {
+            if let Dad::Tentative { sent, next } = &mut prefix.dad {
+                if *next <= now {
+                    if *sent < self.config.dad_transmits {
+                        let target = prefix.address.address();
+                        debug!("SLAAC: probing {:?}", target);
+                        let mut message = [0; 24];
+                        message[0] = NEIGHBOR_SOLICIT;
+                        message[8..].copy_from_slice(&target.octets());
+                        send(socket, Ipv6Address::UNSPECIFIED, solicited_node(target), &message);
+                        *sent += 1;
+                        *next = now + RETRANS_TIMER;
+                    } else {
+                        prefix.dad = Dad::Done;
+                    }
+                }
+            }
+        }
+
+        self.update()
+    }
+
+    /// Next instant at which [`poll`](Self::poll) has work to do.
+    pub(crate) fn poll_at(&self) -> Option<Instant> {
+        let mut at = self.next_solicitation;
+        let mut earliest = |t: Instant| {
+            if t != Instant::MAX {
+                at = Some(at.map_or(t, |at| at.min(t)));
+            }
+        };
+        if let Some((_, expires_at)) = self.router {
+            earliest(expires_at);
+        }
+        if let Some(prefix) = &self.prefix {
+            earliest(prefix.expires_at);
+            if let Dad::Tentative { next, .. } = prefix.dad {
+                earliest(next);
+            }
+        }
+        for (_, expires_at) in &self.dns_servers {
+            earliest(*expires_at);
+        }
+        at
+    }
+
+    fn update(&mut self) -> Option<Event> {
+        let config = self.address().map(|address| StaticConfigV6 {
+            address,
+            gateway: self.router(),
+            dns_servers: self.dns_servers().collect(),
+        });
+
+        if config == self.current {
+            return None;
+        }
+        self.current = config.clone();
+        match config {
+            Some(config) => Some(Event::Configured(config)),
+            None => Some(Event::Deconfigured),
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        if matches!(self.router, Some((_, expires_at)) if expires_at <= now) {
+            debug!("SLAAC: default router expired");
+            self.router = None;
+        }
+        if matches!(&self.prefix, Some(p) if p.expires_at <= now) {
+            debug!("SLAAC: address expired");
+            self.prefix = None;
+        }
+        self.dns_servers.retain(|(_, expires_at)| *expires_at > now);
+
+        // Without a router there is nobody to renew our state, go back to soliciting.
+        if self.router.is_none() && self.prefix.is_none() && self.next_solicitation.is_none() {
+            self.solicitations = 0;
+            self.next_solicitation = Some(now);
+        }
+    }
+
+    /// Delay before the next Router Solicitation.
+    ///
+    /// Once `max_router_solicitations` went unanswered, solicitations continue with an
+    /// exponential backoff (RFC 7559), so a router coming up later is still found.
+    fn solicitation_interval(&self) -> Duration {
+        let interval = self.config.router_solicitation_interval;
+        match self.solicitations.checked_sub(self.config.max_router_solicitations) {
+            None => interval,
+            Some(backoff) => interval
+                .checked_mul(1 << backoff.min(16))
+                .map_or(MAX_SOLICITATION_INTERVAL, |i| i.min(MAX_SOLICITATION_INTERVAL)),
+        }
+    }
+
+    /// Give up on the tentative address if it's `target`, after another node claimed it.
+    fn duplicate(&mut self, target: Ipv6Address) {
+        if !matches!(&self.prefix, Some(p) if matches!(p.dad, Dad::Tentative { .. }) && p.address.address() == target) {
+            return;
+        }
+        warn!("SLAAC: duplicate address {:?} detected", target);
+        self.prefix = None;
+        // RFC 4862 section 5.4.5: the address must not be used, remember it so the next
+        // advertisement of the prefix doesn't probe it again.
+        if self.duplicates.is_full() {
+            self.duplicates.remove(0);
+        }
+        let _ = self.duplicates.push(target);
+    }
+
+    fn process(&mut self, packet: &[u8], now: Instant) {
+        let Ok(packet) = Ipv6Packet::new_checked(packet) else {
+            return;
+        };
+        let Ok(ip_repr) = Ipv6Repr::parse(&packet) else {
+            return;
+        };
+        // Neighbor Discovery messages must not have been forwarded (RFC 4861 section 6.1).
+        if ip_repr.next_header != IpProtocol::Icmpv6 || ip_repr.hop_limit != 255 {
+            return;
+        }
+        let Ok(icmp) = Icmpv6Packet::new_checked(packet.payload()) else {
+            return;
+        };
+        if !icmp.verify_checksum(&ip_repr.src_addr, &ip_repr.dst_addr) {
+            return;
+        }
+
+        let message = packet.payload();
+        match message[0] {
+            // Type, code, checksum, hop limit, flags, router lifetime, reachable time, retrans timer.
+            ROUTER_ADVERT if message.len() >= 16 && ip_repr.src_addr.is_unicast_link_local() => {
+                let router_lifetime = u16::from_be_bytes([message[6], message[7]]);
+                self.process_router_advert(ip_repr.src_addr, router_lifetime, &message[16..], now)
+            }
+            // Type, code, checksum, flags, target address.
+            NEIGHBOR_ADVERT if message.len() >= 24 => {
+                self.duplicate(Ipv6Address::from(<[u8; 16]>::try_from(&message[8..24]).unwrap()))
+            }
+            // Type, code, checksum, reserved, target address. A solicitation from the
+            // unspecified address is another node probing the same tentative address
+            // (RFC 4862 section 5.4.3).
+            NEIGHBOR_SOLICIT if message.len() >= 24 && ip_repr.src_addr.is_unspecified() => {
+                self.duplicate(Ipv6Address::from(<[u8; 16]>::try_from(&message[8..24]).unwrap()))
+            }
+            _ => {}
+        }
+    }
+
+    fn process_router_advert(&mut self, src: Ipv6Address, router_lifetime: u16, mut options: &[u8], now: Instant) {
+        debug!("SLAAC: router advertisement from {:?}", src);
+
+        // Any advertisement ends the solicitation phase.
+        self.solicitations = 0;
+        self.next_solicitation = None;
+
+        if router_lifetime == 0 {
+            if matches!(self.router, Some((addr, _)) if addr == src) {
+                self.router = None;
+            }
+        } else {
+            self.router = Some((src, now + Duration::from_secs(router_lifetime as u64)));
+        }
+
+        // Options are type, length in units of 8 octets, data.
+        while options.len() >= 2 {
+            let len = options[1] as usize * 8;
+            if len == 0 || len > options.len() {
+                return;
+            }
+            match options[0] {
+                PREFIX_INFORMATION_OPTION if len == 32 => self.process_prefix(&options[2..len], now),
+                RDNSS_OPTION => self.process_rdnss(&options[2..len], now),
+                _ => {}
+            }
+            options = &options[len..];
+        }
+    }
+
+    fn process_prefix(&mut self, data: &[u8], now: Instant) {
+        // Prefix length, flags, valid lifetime, preferred lifetime, reserved, prefix.
+        let prefix_len = data[0];
+        let flags = data[1];
+        let valid = u32::from_be_bytes([data[2], data[3], data[4], data[5]]);
+        let preferred = u32::from_be_bytes([data[6], data[7], data[8], data[9]]);
+        let prefix = Ipv6Address::from(<[u8; 16]>::try_from(&data[14..30]).unwrap());
+
+        if !self.autoconfigure
+            || flags & PREFIX_FLAG_AUTONOMOUS == 0
+            || prefix_len != PREFIX_LEN
+            || prefix.is_unicast_link_local()
+            || preferred > valid
+        {
+            return;
+        }
+
+        let address = self.address_in(prefix);
+        if self.duplicates.contains(&address) {
+            return;
+        }
+        match &mut self.prefix {
+            Some(current) if current.address.address() == address => {
+                // RFC 4862 section 5.5.3 (e): don't let an unauthenticated advertisement
+                // shorten the lifetime below two hours.
+                let remaining = current.expires_at.saturating_duration_since(now);
+                let valid = lifetime(valid);
+                if valid > TWO_HOURS || valid > remaining {
+                    current.expires_at = expiry(now, valid);
+                } else if remaining > TWO_HOURS {
+                    current.expires_at = now + TWO_HOURS;
+                }
+            }
+            // Only one autoconfigured address is supported.
+            Some(_) => {}
+            None if valid == 0 => {}
+            None => {
+                debug!("SLAAC: new prefix {:?}/{}", prefix, PREFIX_LEN);
+                self.prefix = Some(Prefix {
+                    address: Ipv6Cidr::new(address, PREFIX_LEN),
+                    expires_at: expiry(now, lifetime(valid)),
+                    dad: Dad::Tentative { sent: 0, next: now },
+                });
+            }
+        }
+    }
+
+    fn process_rdnss(&mut self, data: &[u8], now: Instant) {
+        // Reserved (2), lifetime (4), addresses (16 each).
+        if data.len() < 6 + 16 {
+            return;
+        }
+        let lifetime_secs = u32::from_be_bytes([data[2], data[3], data[4], data[5]]);
+        for chunk in data[6..].chunks_exact(16) {
+            let addr = Ipv6Address::from(<[u8; 16]>::try_from(chunk).unwrap());
+            let existing = self.dns_servers.iter().position(|(a, _)| *a == addr);
+            match (existing, lifetime_secs) {
+                (Some(i), 0) => {
+                    self.dns_servers.remove(i);
+                }
+                (Some(i), secs) => self.dns_servers[i].1 = expiry(now, lifetime(secs)),
+                (None, 0) => {}
+                (None, secs) => {
+                    if self.dns_servers.push((addr, expiry(now, lifetime(secs)))).is_err() {
+                        debug!("SLAAC: ignoring DNS server {:?}, list full", addr);
+                    }
+                }
+            }
+        }
+    }
+
+    fn address_in(&self, prefix: Ipv6Address) -> Ipv6Address {
+        let mut octets = prefix.octets();
+        octets[8..].copy_from_slice(&self.iid);
+        Ipv6Address::from(octets)
+    }
+}
+
+/// Lifetime in seconds, where all-ones means infinity.
+pub(crate) fn lifetime(secs: u32) -> Duration {
+    match secs {
+        u32::MAX => Duration::MAX,
+        secs => Duration::from_secs(secs as u64),
+    }
+}
+
+pub(crate) fn expiry(now: Instant, lifetime: Duration) -> Instant {
+    now.checked_add(lifetime).unwrap_or(Instant::MAX)
+}
+
+fn solicited_node(addr: Ipv6Address) -> Ipv6Address {
+    let o = addr.octets();
+    Ipv6Address::from([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xff, o[13], o[14], o[15]])
+}
+
+/// Modified EUI-64 interface identifier (RFC 4291 appendix A).
+///
+/// Mediums without a link-layer address get a random identifier derived from the stack seed.
+fn interface_identifier(hardware_address: HardwareAddress, random_seed: u64) -> [u8; 8] {
+    let mut iid = match hardware_address {
+        #[cfg(feature = "medium-ethernet")]
+        HardwareAddress::Ethernet(addr) => {
+            let m = addr.0;
+            [m[0], m[1], m[2], 0xff, 0xfe, m[3], m[4], m[5]]
+        }
+        #[cfg(feature = "medium-ieee802154")]
+        HardwareAddress::Ieee802154(addr) => match addr.as_bytes().try_into() {
+            Ok(extended) => extended,
+            Err(_) => random_seed.to_be_bytes(),
+        },
+        #[allow(unreachable_patterns)]
+        _ => random_seed.to_be_bytes(),
+    };
+    iid[0] ^= 0x02;
+    iid
+}
+
+fn send(socket: &mut raw::Socket, src: Ipv6Address, dst: Ipv6Address, message: &[u8]) {
+    let ip_repr = Ipv6Repr {
+        src_addr: src,
+        dst_addr: dst,
+        next_header: IpProtocol::Icmpv6,
+        payload_len: message.len(),
+        hop_limit: 255,
+    };
+    match socket.send(ip_repr.buffer_len() + message.len()) {
+        Ok(buf) => {
+            let mut packet = Ipv6Packet::new_unchecked(buf);
+            ip_repr.emit(&mut packet);
+            packet.payload_mut().copy_from_slice(message);
+            Icmpv6Packet::new_unchecked(packet.payload_mut()).fill_checksum(&src, &dst);
+        }
+        Err(_) => warn!("SLAAC: transmit buffer full"),
+    }
+}
+
+#[cfg(all(test, feature = "medium-ethernet"))]
+mod tests {
+    use std::vec::Vec;
+
+    use smoltcp::iface::SocketStorage;
+    use smoltcp::wire::EthernetAddress;
+
+    use super::*;
+
+    const T0: Instant = Instant::from_secs(1000);
+    const ROUTER: Ipv6Address = Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+    const PREFIX_A: Ipv6Address = Ipv6Address::new(0x2001, 0xdb8, 0, 0xa, 0, 0, 0, 0);
+    const PREFIX_B: Ipv6Address = Ipv6Address::new(0x2001, 0xdb8, 0, 0xb, 0, 0, 0, 0);
+
+    fn slaac() -> (Slaac, SocketSet<'static>) {
+        let storage = Box::leak(Box::new([SocketStorage::EMPTY; 1]));
+        let mut sockets = SocketSet::new(&mut storage[..]);
+        let resources = Box::leak(Box::new(SlaacResources::new()));
+        let hardware_address = HardwareAddress::Ethernet(EthernetAddress([0x02, 0, 0, 0, 0, 1]));
+        // safety: the resources are leaked, and only used by this socket.
+        let mut slaac = unsafe {
+            Slaac::new(
+                &mut sockets,
+                resources,
+                SlaacConfig::default(),
+                true,
+                hardware_address,
+                0,
+            )
+        };
+        slaac.reset(T0);
+        (slaac, sockets)
+    }
+
+    fn packet(src: Ipv6Address, dst: Ipv6Address, message: &[u8]) -> Vec<u8> {
+        let ip_repr = Ipv6Repr {
+            src_addr: src,
+            dst_addr: dst,
+            next_header: IpProtocol::Icmpv6,
+            payload_len: message.len(),
+            hop_limit: 255,
+        };
+        let mut buf = vec![0; ip_repr.buffer_len() + message.len()];
+        let mut packet = Ipv6Packet::new_unchecked(&mut buf[..]);
+        ip_repr.emit(&mut packet);
+        packet.payload_mut().copy_from_slice(message);
+        Icmpv6Packet::new_unchecked(packet.payload_mut()).fill_checksum(&src, &dst);
+        buf
+    }
+
+    /// A Router Advertisement from `ROUTER`, with an autonomous prefix valid for an hour.
+    fn router_advert(router_lifetime: u16, prefix: Ipv6Address) -> Vec<u8> {
+        let mut message = vec![ROUTER_ADVERT, 0, 0, 0, 64, 0];
+        message.extend_from_slice(&router_lifetime.to_be_bytes());
+        message.extend_from_slice(&[0; 8]);
+        message.extend_from_slice(&[PREFIX_INFORMATION_OPTION, 4, PREFIX_LEN, PREFIX_FLAG_AUTONOMOUS]);
+        message.extend_from_slice(&3600u32.to_be_bytes());
+        message.extend_from_slice(&3600u32.to_be_bytes());
+        message.extend_from_slice(&[0; 4]);
+        message.extend_from_slice(&prefix.octets());
+        packet(ROUTER, Ipv6Address::new(0xff02, 0, 0, 0, 0, 0, 0, 1), &message)
+    }
+
+    fn neighbor_message(kind: u8, src: Ipv6Address, dst: Ipv6Address, target: Ipv6Address) -> Vec<u8> {
+        let mut message = vec![kind, 0, 0, 0, 0, 0, 0, 0];
+        message.extend_from_slice(&target.octets());
+        packet(src, dst, &message)
+    }
+
+    fn tentative(slaac: &Slaac) -> Option<Ipv6Address> {
+        match &slaac.prefix {
+            Some(Prefix {
+                address,
+                dad: Dad::Tentative { .. },
+                ..
+            }) => Some(address.address()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn duplicate_address_is_not_probed_again() {
+        let (mut slaac, mut sockets) = slaac();
+        let address_a = slaac.address_in(PREFIX_A);
+        slaac.process(&router_advert(1800, PREFIX_A), T0);
+        assert_eq!(tentative(&slaac), Some(address_a));
+        assert!(slaac.poll(&mut sockets, true, T0).is_none());
+
+        let advert = neighbor_message(NEIGHBOR_ADVERT, ROUTER, ALL_ROUTERS, address_a);
+        slaac.process(&advert, T0);
+        assert!(slaac.prefix.is_none());
+
+        // The next advertisement of the prefix doesn't start over, another prefix is used.
+        slaac.process(&router_advert(1800, PREFIX_A), T0);
+        assert!(slaac.prefix.is_none());
+        slaac.process(&router_advert(1800, PREFIX_B), T0);
+        let address_b = slaac.address_in(PREFIX_B);
+        assert_eq!(tentative(&slaac), Some(address_b));
+
+        assert!(slaac.poll(&mut sockets, true, T0).is_none());
+        match slaac.poll(&mut sockets, true, T0 + RETRANS_TIMER) {
+            Some(Event::Configured(config)) => {
+                assert_eq!(config.address, Ipv6Cidr::new(address_b, PREFIX_LEN));
+                assert_eq!(config.gateway, Some(ROUTER));
+            }
+            _ => panic!("not configured"),
+        }
+
+        // Duplicates are forgotten when the link changes.
+        slaac.reset(T0);
+        slaac.process(&router_advert(1800, PREFIX_A), T0);
+        assert_eq!(tentative(&slaac), Some(address_a));
+    }
+
+    #[test]
+    fn probe_from_another_node() {
+        let (mut slaac, _sockets) = slaac();
+        let address = slaac.address_in(PREFIX_A);
+        slaac.process(&router_advert(1800, PREFIX_A), T0);
+
+        // Address resolution from a node with an address is not a probe.
+        let solicit = neighbor_message(NEIGHBOR_SOLICIT, ROUTER, solicited_node(address), address);
+        slaac.process(&solicit, T0);
+        assert_eq!(tentative(&slaac), Some(address));
+
+        let probe = neighbor_message(
+            NEIGHBOR_SOLICIT,
+            Ipv6Address::UNSPECIFIED,
+            solicited_node(address),
+            address,
+        );
+        slaac.process(&probe, T0);
+        assert!(slaac.prefix.is_none());
+        slaac.process(&router_advert(1800, PREFIX_A), T0);
+        assert!(slaac.prefix.is_none());
+    }
+
+    #[test]
+    fn solicitation_backoff() {
+        let (mut slaac, mut sockets) = slaac();
+        let mut intervals = Vec::new();
+        let mut now = T0;
+        for _ in 0..16 {
+            assert_eq!(slaac.poll_at(), Some(now));
+            slaac.poll(&mut sockets, false, now);
+            let next = slaac.next_solicitation.unwrap();
+            intervals.push((next - now).as_secs());
+            now = next;
+        }
+        assert_eq!(intervals[..6], [4, 4, 4, 8, 16, 32]);
+        assert_eq!(intervals[15], MAX_SOLICITATION_INTERVAL.as_secs());
+
+        // An advertisement stops the solicitations, and losing the router starts them over.
+        slaac.process(&router_advert(1, PREFIX_A), now);
+        assert!(slaac.next_solicitation.is_none());
+        let address = slaac.address_in(PREFIX_A);
+        slaac.process(&neighbor_message(NEIGHBOR_ADVERT, ROUTER, ALL_ROUTERS, address), now);
+        now += Duration::from_secs(1);
+        slaac.poll(&mut sockets, true, now);
+        assert_eq!(slaac.next_solicitation, Some(now + Duration::from_secs(4)));
+    }
+}
@@ -0,0 +1,571 @@
+//! SNTP client (RFC 4330), providing wall-clock time.
+//!
+//! [`embassy_time::Instant`] is monotonic and starts at boot. The [`SntpClient`] periodically asks
+//! NTP servers for the current time and keeps a [`WallClock`] synchronized, which maps instants to
+//! Unix time.
+//!
+//! Small errors are corrected gradually (slewed), so that the wall clock never jumps nor goes
+//! backwards. Errors above [`Config::step_threshold`] are corrected at once (stepped).
+//!
+//! ```ignore
+//! use embassy_net::sntp::{Config, SntpClient, WallClock};
+//! use embassy_net::udp::{PacketMetadata, UdpSocket};
+//! use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+//!
+//! static CLOCK: WallClock<CriticalSectionRawMutex> = WallClock::new();
+//!
+//! let mut rx_meta = [PacketMetadata::EMPTY; 2];
+//! let mut rx_buffer = [0; 256];
+//! let mut tx_meta = [PacketMetadata::EMPTY; 2];
+//! let mut tx_buffer = [0; 256];
+//! let socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+//!
+//! let mut config = Config::new(&["pool.ntp.org"]);
+//! config.poll_interval = Duration::from_secs(3600);
+//! let mut client = SntpClient::new(socket, config, &CLOCK).unwrap();
+//! client.run().await;
+//!
+//! // Elsewhere, in any task:
+//! if let Some(now) = CLOCK.now() {
+//!     info!("Unix time: {}", now.as_secs());
+//! }
+//! ```
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{with_deadline, Duration, Instant, Timer};
+
+use crate::dns::{self, DnsQueryType};
+use crate::udp::{BindError, SendError, UdpSocket};
+use crate::{IpAddress, IpEndpoint, Stack};
+
+const NTP_PORT: u16 = 123;
+const PACKET_LEN: usize = 48;
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970).
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+const VERSION: u8 = 4;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+const LEAP_UNSYNCHRONIZED: u8 = 3;
+
+/// How the server used for each synchronization is chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ServerSelection {
+    /// Use the first server that answers.
+    ///
+    /// Servers are tried in order, starting with the last one that answered.
+    FirstResponding,
+    /// Query all the servers and use the answer with the lowest round-trip delay.
+    LowestDelay,
+}
+
+/// SNTP client configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Config<'a> {
+    /// Server host names or IP addresses.
+    pub servers: &'a [&'a str],
+    /// How to choose between servers.
+    pub server_selection: ServerSelection,
+    /// Time between synchronizations.
+    pub poll_interval: Duration,
+    /// Time before trying again after a failed synchronization.
+    ///
+    /// RFC 4330 asks clients not to query servers more than once every 15 seconds.
+    pub retry_interval: Duration,
+    /// How long to wait for an answer from a server.
+    pub timeout: Duration,
+    /// Clock errors of this size and above are corrected at once instead of being slewed.
+    pub step_threshold: Duration,
+    /// Largest rate of slewing, in parts per million.
+    ///
+    /// For example, with 500 ppm, correcting 100 ms takes 200 seconds.
+    pub max_slew_ppm: u32,
+}
+
+impl<'a> Config<'a> {
+    /// Create a configuration using `servers`.
+    pub const fn new(servers: &'a [&'a str]) -> Self {
+        Self {
+            servers,
+            server_selection: ServerSelection::FirstResponding,
+            poll_interval: Duration::from_secs(1024),
+            retry_interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(5),
+            step_threshold: Duration::from_millis(128),
+            max_slew_ppm: 500,
+        }
+    }
+}
+
+/// SNTP error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// There are no servers in the configuration, or all of them refused access.
+    NoServers,
+    /// The server name couldn't be resolved.
+    Dns(dns::Error),
+    /// The request couldn't be sent.
+    Send(SendError),
+    /// The server didn't answer in time.
+    Timeout,
+    /// The server isn't synchronized.
+    Unsynchronized,
+    /// The server sent a kiss-o'-death packet with this code, e.g. `RATE` to ask for less
+    /// frequent queries.
+    KissOfDeath([u8; 4]),
+}
+
+/// Result of a synchronization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Sample {
+    /// Server which answered.
+    pub server: IpAddress,
+    /// Stratum of the server, 1 for primary servers.
+    pub stratum: u8,
+    /// Time since the Unix epoch at `instant`, according to the server.
+    pub time: Duration,
+    /// When the answer was received.
+    pub instant: Instant,
+    /// Round-trip delay to the server, not counting its processing time.
+    pub delay: Duration,
+    /// Error of the wall clock before the synchronization, in microseconds.
+    ///
+    /// Positive if the wall clock was late. `None` if it wasn't synchronized yet.
+    pub offset_micros: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ClockState {
+    /// Instant of the last correction.
+    base_instant: Instant,
+    /// Unix time at `base_instant`, in microseconds.
+    base_micros: i64,
+    /// Error left to slew away from `base_instant`, in microseconds.
+    slew_micros: i64,
+    /// Slewing rate, in parts per million.
+    slew_ppm: u32,
+}
+
+impl ClockState {
+    fn micros_at(&self, instant: Instant) -> i64 {
+        let elapsed = instant.as_micros() as i64 - self.base_instant.as_micros() as i64;
+        let max_slew = (elapsed.max(0) as i128 * self.slew_ppm as i128 / 1_000_000) as i64;
+        self.base_micros + elapsed + self.slew_micros.clamp(-max_slew, max_slew)
+    }
+}
+
+/// Wall-clock time, kept synchronized by a [`SntpClient`].
+///
+/// With a `M` such as `CriticalSectionRawMutex`, it can be a `static` read from any task.
+pub struct WallClock<M: RawMutex> {
+    state: Mutex<M, Cell<Option<ClockState>>>,
+}
+
+impl<M: RawMutex> WallClock<M> {
+    /// Create an unsynchronized clock.
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(Cell::new(None)),
+        }
+    }
+
+    fn state(&self) -> Option<ClockState> {
+        self.state.lock(|s| s.get())
+    }
+
+    fn set_state(&self, state: ClockState) {
+        self.state.lock(|s| s.set(Some(state)))
+    }
+
+    /// Return whether the clock was synchronized at least once.
+    pub fn is_synchronized(&self) -> bool {
+        self.state().is_some()
+    }
+
+    /// Get the current time, since the Unix epoch.
+    ///
+    /// Returns `None` if the clock was never synchronized.
+    pub fn now(&self) -> Option<Duration> {
+        self.to_unix(Instant::now())
+    }
+
+    /// Get the time at `instant`, since the Unix epoch.
+    ///
+    /// Returns `None` if the clock was never synchronized.
+    pub fn to_unix(&self, instant: Instant) -> Option<Duration> {
+        let micros = self.state()?.micros_at(instant);
+        Some(Duration::from_micros(micros.max(0) as u64))
+    }
+
+    /// Get the instant of the last synchronization, or [`set`](Self::set).
+    pub fn last_update(&self) -> Option<Instant> {
+        self.state().map(|s| s.base_instant)
+    }
+
+    /// Set the time at `instant`, since the Unix epoch, e.g. from a real-time clock.
+    pub fn set(&self, instant: Instant, time: Duration) {
+        self.set_state(ClockState {
+            base_instant: instant,
+            base_micros: time.as_micros() as i64,
+            slew_micros: 0,
+            slew_ppm: 0,
+        });
+    }
+
+    /// Correct the clock with a measurement, returning its error.
+    fn update(&self, instant: Instant, time: Duration, step_threshold: Duration, slew_ppm: u32) -> Option<i64> {
+        let time = time.as_micros() as i64;
+        let Some(state) = self.state() else {
+            debug!("SNTP: clock set");
+            self.set(instant, Duration::from_micros(time as u64));
+            return None;
+        };
+
+        let current = state.micros_at(instant);
+        let offset = time - current;
+        if offset.unsigned_abs() >= step_threshold.as_micros() {
+            debug!("SNTP: stepping clock by {} us", offset);
+            self.set(instant, Duration::from_micros(time as u64));
+        } else {
+            // The measured offset includes what wasn't slewed yet.
+            self.set_state(ClockState {
+                base_instant: instant,
+                base_micros: current,
+                slew_micros: offset,
+                slew_ppm,
+            });
+        }
+        Some(offset)
+    }
+}
+
+impl<M: RawMutex> Default for WallClock<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Read an NTP timestamp as Unix time in microseconds.
+fn read_timestamp(b: &[u8]) -> Option<i64> {
+    let secs = u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as u64;
+    let frac = u32::from_be_bytes([b[4], b[5], b[6], b[7]]) as u64;
+    if secs == 0 && frac == 0 {
+        return None;
+    }
+    // Timestamps with the high bit clear are after the 2036 rollover (RFC 4330 section 3).
+    let secs = match secs & 0x8000_0000 {
+        0 => secs + (1 << 32),
+        _ => secs,
+    };
+    let micros = (frac * 1_000_000) >> 32;
+    Some((secs - NTP_UNIX_OFFSET) as i64 * 1_000_000 + micros as i64)
+}
+
+/// Compute the server time when its answer is received, and the round-trip delay.
+///
+/// `round_trip` is the time between sending the request and receiving the answer, `receive` and
+/// `transmit` are the server timestamps, all in microseconds. The return trip is assumed to take
+/// half of the delay.
+fn server_time(round_trip: i64, receive: i64, transmit: i64) -> Option<(Duration, Duration)> {
+    let processing = transmit - receive;
+    let delay = (round_trip - processing).max(0);
+    let time = (receive + transmit + round_trip) / 2;
+    if time < 0 {
+        return None;
+    }
+    Some((Duration::from_micros(time as u64), Duration::from_micros(delay as u64)))
+}
+
+/// An SNTP client.
+pub struct SntpClient<'a, M: RawMutex> {
+    stack: Stack<'a>,
+    socket: UdpSocket<'a>,
+    config: Config<'a>,
+    clock: &'a WallClock<M>,
+    /// Index of the server that answered last.
+    preferred: usize,
+    /// Servers which refused access, by index.
+    denied: u32,
+}
+
+impl<'a, M: RawMutex> SntpClient<'a, M> {
+    /// Create a client keeping `clock` synchronized, binding `socket` to a local port.
+    pub fn new(mut socket: UdpSocket<'a>, config: Config<'a>, clock: &'a WallClock<M>) -> Result<Self, BindError> {
+        let stack = socket.stack();
+        socket.bind(0)?;
+        Ok(Self {
+            stack,
+            socket,
+            config,
+            clock,
+            preferred: 0,
+            denied: 0,
+        })
+    }
+
+    /// Get the clock kept synchronized.
+    pub fn clock(&self) -> &'a WallClock<M> {
+        self.clock
+    }
+
+    /// Synchronize the clock forever.
+    ///
+    /// Waits for the network to be up, then synchronizes every [`Config::poll_interval`], or
+    /// every [`Config::retry_interval`] while synchronizations fail.
+    pub async fn run(&mut self) -> ! {
+        loop {
+            self.stack.wait_config_up().await;
+            let delay = match self.synchronize().await {
+                Ok(_) => self.config.poll_interval,
+                // Back off when asked to.
+                Err(Error::KissOfDeath(_)) => self.config.poll_interval,
+                Err(e) => {
+                    warn!("SNTP: synchronization failed: {:?}", e);
+                    self.config.retry_interval
+                }
+            };
+            Timer::after(delay).await;
+        }
+    }
+
+    /// Query the servers once and correct the clock.
+    pub async fn synchronize(&mut self) -> Result<Sample, Error> {
+        let count = self.config.servers.len();
+        let mut best: Option<(usize, Sample)> = None;
+        let mut error = Error::NoServers;
+        for i in 0..count {
+            let index = match self.config.server_selection {
+                ServerSelection::FirstResponding => (self.preferred + i) % count,
+                ServerSelection::LowestDelay => i,
+            };
+            if self.is_denied(index) {
+                continue;
+            }
+            match self.query(self.config.servers[index]).await {
+                Ok(sample) => {
+                    if !matches!(&best, Some((_, b)) if b.delay <= sample.delay) {
+                        best = Some((index, sample));
+                    }
+                    if self.config.server_selection == ServerSelection::FirstResponding {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    debug!("SNTP: server {} failed: {:?}", self.config.servers[index], e);
+                    if let Error::KissOfDeath(code) = e {
+                        if &code == b"DENY" || &code == b"RSTR" {
+                            self.deny(index);
+                        }
+                    }
+                    error = e;
+                }
+            }
+        }
+
+        let (index, mut sample) = best.ok_or(error)?;
+        self.preferred = index;
+        sample.offset_micros = self.clock.update(
+            sample.instant,
+            sample.time,
+            self.config.step_threshold,
+            self.config.max_slew_ppm,
+        );
+        debug!(
+            "SNTP: synchronized with {}, delay {} us, offset {:?} us",
+            sample.server,
+            sample.delay.as_micros(),
+            sample.offset_micros
+        );
+        Ok(sample)
+    }
+
+    fn is_denied(&self, index: usize) -> bool {
+        index < 32 && self.denied & (1 << index) != 0
+    }
+
+    fn deny(&mut self, index: usize) {
+        if index < 32 {
+            self.denied |= 1 << index;
+        }
+    }
+
+    async fn resolve(&self, server: &str) -> Result<IpAddress, Error> {
+        let mut error = dns::Error::Failed;
+        #[cfg(feature = "proto-ipv4")]
+        match self.stack.dns_query(server, DnsQueryType::A).await {
+            Ok(addrs) if !addrs.is_empty() => return Ok(addrs[0]),
+            Ok(_) => {}
+            Err(e) => error = e,
+        }
+        #[cfg(feature = "proto-ipv6")]
+        match self.stack.dns_query(server, DnsQueryType::Aaaa).await {
+            Ok(addrs) if !addrs.is_empty() => return Ok(addrs[0]),
+            Ok(_) => {}
+            Err(e) => error = e,
+        }
+        Err(Error::Dns(error))
+    }
+
+    /// Query a server, without correcting the clock.
+    async fn query(&mut self, server: &str) -> Result<Sample, Error> {
+        let addr = self.resolve(server).await?;
+        let endpoint = IpEndpoint::new(addr, NTP_PORT);
+
+        // The transmit timestamp is only used to match the answer, so it doesn't need to be the
+        // current time (RFC 4330 section 5).
+        let mut request = [0; PACKET_LEN];
+        request[0] = (VERSION << 3) | MODE_CLIENT;
+        let cookie = Instant::now().as_ticks() | 1;
+        request[40..48].copy_from_slice(&cookie.to_be_bytes());
+
+        let sent = Instant::now();
+        self.socket.send_to(&request, endpoint).await.map_err(Error::Send)?;
+
+        let deadline = sent + self.config.timeout;
+        let mut buf = [0; PACKET_LEN];
+        loop {
+            let Ok(res) = with_deadline(deadline, self.socket.recv_from(&mut buf)).await else {
+                return Err(Error::Timeout);
+            };
+            let received = Instant::now();
+            let Ok((n, meta)) = res else {
+                continue;
+            };
+            if n < PACKET_LEN || meta.endpoint != endpoint || buf[24..32] != request[40..48] {
+                continue;
+            }
+            if buf[0] & 0x07 != MODE_SERVER {
+                continue;
+            }
+
+            let stratum = buf[1];
+            if stratum == 0 {
+                return Err(Error::KissOfDeath([buf[12], buf[13], buf[14], buf[15]]));
+            }
+            if buf[0] >> 6 == LEAP_UNSYNCHRONIZED {
+                return Err(Error::Unsynchronized);
+            }
+            let (Some(receive), Some(transmit)) = (read_timestamp(&buf[32..40]), read_timestamp(&buf[40..48])) else {
+                continue;
+            };
+
+            let round_trip = (received - sent).as_micros() as i64;
+            let Some((time, delay)) = server_time(round_trip, receive, transmit) else {
+                continue;
+            };
+            return Ok(Sample {
+                server: addr,
+                stratum,
+                time,
+                instant: received,
+                delay,
+                offset_micros: None,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+    use super::*;
+
+    /// Shared by all tasks, so it must be `Sync`.
+    static CLOCK: WallClock<CriticalSectionRawMutex> = WallClock::new();
+
+    const STEP: Duration = Duration::from_millis(128);
+
+    fn ntp_timestamp(secs: u32, frac: u32) -> [u8; 8] {
+        let mut b = [0; 8];
+        b[..4].copy_from_slice(&secs.to_be_bytes());
+        b[4..].copy_from_slice(&frac.to_be_bytes());
+        b
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(read_timestamp(&ntp_timestamp(0, 0)), None);
+        assert_eq!(read_timestamp(&ntp_timestamp(NTP_UNIX_OFFSET as u32, 0)), Some(0));
+        assert_eq!(
+            read_timestamp(&ntp_timestamp(NTP_UNIX_OFFSET as u32 + 2, 0x4000_0000)),
+            Some(2_250_000)
+        );
+        // 2036-02-07T06:28:16Z is where NTP era 1 starts.
+        let era1 = ((1u64 << 32) - NTP_UNIX_OFFSET) as i64 * 1_000_000;
+        assert_eq!(read_timestamp(&ntp_timestamp(1, 0)), Some(era1 + 1_000_000));
+    }
+
+    #[test]
+    fn offset_and_delay() {
+        // Request sent at client time 0, received by the server at 1000 s and answered 500 us
+        // later, answer received 20.5 ms after sending.
+        let receive = 1_000_000_000;
+        let (time, delay) = server_time(20_500, receive, receive + 500).unwrap();
+        assert_eq!(delay, Duration::from_millis(20));
+        // The answer took half of the delay to come back.
+        assert_eq!(time, Duration::from_micros(receive as u64 + 500 + 10_000));
+
+        // Processing time longer than the round trip, with a server clock going backwards.
+        let (_, delay) = server_time(100, receive, receive + 1000).unwrap();
+        assert_eq!(delay, Duration::from_ticks(0));
+        assert_eq!(server_time(10, -100, -50), None);
+    }
+
+    #[test]
+    fn set_step_and_slew() {
+        let clock: WallClock<CriticalSectionRawMutex> = WallClock::new();
+        let t0 = Instant::from_secs(10);
+        let unix = Duration::from_secs(1_700_000_000);
+        assert!(!clock.is_synchronized());
+        assert_eq!(clock.to_unix(t0), None);
+
+        // First measurement sets the clock.
+        assert_eq!(clock.update(t0, unix, STEP, 500), None);
+        assert_eq!(
+            clock.to_unix(t0 + Duration::from_secs(1)),
+            Some(unix + Duration::from_secs(1))
+        );
+        assert_eq!(clock.last_update(), Some(t0));
+
+        // 50 ms late: slewed at 500 ppm, so over 100 s.
+        let t1 = t0 + Duration::from_secs(100);
+        let late = unix + Duration::from_secs(100) + Duration::from_millis(50);
+        assert_eq!(clock.update(t1, late, STEP, 500), Some(50_000));
+        assert_eq!(clock.to_unix(t1), Some(late - Duration::from_millis(50)));
+        assert_eq!(
+            clock.to_unix(t1 + Duration::from_secs(10)),
+            Some(late + Duration::from_secs(10) - Duration::from_millis(45))
+        );
+        assert_eq!(
+            clock.to_unix(t1 + Duration::from_secs(200)),
+            Some(late + Duration::from_secs(200))
+        );
+
+        // Half-way through, the error left is measured again.
+        let t2 = t1 + Duration::from_secs(50);
+        let exact = late + Duration::from_secs(50);
+        assert_eq!(clock.update(t2, exact, STEP, 500), Some(25_000));
+
+        // About 1 s early: stepped. 0.5 ms were slewed since the last measurement.
+        let t3 = t2 + Duration::from_secs(1);
+        let early = exact;
+        assert_eq!(clock.update(t3, early, STEP, 500), Some(-975_500));
+        assert_eq!(clock.to_unix(t3), Some(early));
+        assert_eq!(clock.last_update(), Some(t3));
+    }
+
+    #[test]
+    fn static_clock() {
+        CLOCK.set(Instant::from_secs(1), Duration::from_secs(100));
+        assert!(CLOCK.is_synchronized());
+        assert_eq!(CLOCK.to_unix(Instant::from_secs(3)), Some(Duration::from_secs(102)));
+    }
+}
@@ -0,0 +1,762 @@
+//! Traffic statistics.
+//!
+//! Each interface counts the frames its driver receives and transmits, and some of the reasons packets
+//! are lost, see [`InterfaceStats`]. They're read with [`InterfaceHandle::stats`](crate::InterfaceHandle::stats),
+//! or [`Stack::stats`](crate::Stack::stats) for the default interface.
+//!
+//! smoltcp doesn't keep statistics, so the rest comes from looking into the frames once more, with the
+//! `statistics-inspect` feature. This costs parsing the headers of each frame a second time, and
+//! verifying its checksums in software when the driver doesn't. It adds the checksum errors, packets no
+//! socket accepted and neighbor misses to [`InterfaceStats`].
+//!
+//! It also follows TCP connections on the wire, to estimate their round-trip time, count retransmissions
+//! and keep the window advertised by the peer, see [`TcpStats`]. They're read with
+//! [`TcpSocket::stats`](crate::tcp::TcpSocket::stats). An interface follows as many connections as it
+//! has socket slots. When it runs out, it forgets the connection seen the least recently.
+//!
+//! Counters wrap around on overflow, and are never reset. Compute differences between two readings
+//! with `wrapping_sub` to get rates.
+
+use core::cell::RefCell;
+
+#[cfg(feature = "statistics-inspect")]
+use embassy_net_driver::{Checksum, ChecksumCapabilities};
+#[cfg(feature = "statistics-inspect")]
+use embassy_time::{Duration, Instant};
+#[cfg(feature = "statistics-inspect")]
+use smoltcp::phy::Medium;
+#[cfg(all(feature = "statistics-inspect", feature = "medium-ethernet", feature = "proto-ipv4"))]
+use smoltcp::wire::{ArpOperation, ArpPacket};
+#[cfg(all(feature = "statistics-inspect", feature = "medium-ethernet"))]
+use smoltcp::wire::{EthernetFrame, EthernetProtocol};
+#[cfg(all(feature = "statistics-inspect", feature = "proto-ipv4"))]
+use smoltcp::wire::{Icmpv4Message, Icmpv4Packet, Ipv4Packet};
+#[cfg(all(feature = "statistics-inspect", feature = "proto-ipv6"))]
+use smoltcp::wire::{Icmpv6Message, Icmpv6Packet, Ipv6Packet};
+#[cfg(feature = "statistics-inspect")]
+use smoltcp::wire::{IpAddress, IpEndpoint, IpProtocol, TcpOption, TcpPacket, UdpPacket};
+
+/// ICMP code of port unreachable errors, sent by smoltcp for UDP datagrams no socket accepts.
+#[cfg(all(feature = "statistics-inspect", feature = "proto-ipv4"))]
+const ICMPV4_PORT_UNREACHABLE: u8 = 3;
+#[cfg(all(feature = "statistics-inspect", feature = "proto-ipv6"))]
+const ICMPV6_PORT_UNREACHABLE: u8 = 4;
+
+/// Statistics of a network interface.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct InterfaceStats {
+    /// Frames received from the driver.
+    pub rx_packets: u64,
+    /// Bytes received from the driver.
+    pub rx_bytes: u64,
+    /// Frames transmitted by the driver.
+    pub tx_packets: u64,
+    /// Bytes transmitted by the driver.
+    pub tx_bytes: u64,
+    /// Packets dropped because the socket set of an interface had no free slot to take them.
+    ///
+    /// Received packets only need a socket slot when they're forwarded: they're queued on the
+    /// interface they leave through. They're counted on the interface they were received on.
+    pub rx_socket_set_full: u32,
+    /// Packets dropped because of an invalid IPv4 header, TCP, UDP or ICMP checksum.
+    ///
+    /// Checksums are only verified when the driver doesn't do it, as reported in its
+    /// [`Capabilities`](crate::driver::Capabilities).
+    #[cfg(feature = "statistics-inspect")]
+    pub rx_checksum_errors: u32,
+    /// TCP segments and UDP datagrams dropped because no socket accepted them.
+    ///
+    /// These are the ones the stack answered with a TCP reset or an ICMP port unreachable error.
+    /// Datagrams dropped because the receive buffer of their UDP socket is full aren't counted.
+    #[cfg(feature = "statistics-inspect")]
+    pub rx_no_socket: u32,
+    /// Packets that couldn't be sent right away because the hardware address of the next hop wasn't
+    /// known, counted as the ARP requests and NDP neighbor solicitations sent.
+    #[cfg(feature = "statistics-inspect")]
+    pub neighbor_misses: u32,
+}
+
+/// Statistics of a TCP connection.
+#[cfg(feature = "statistics-inspect")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct TcpStats {
+    /// Smoothed round-trip time, as defined by RFC 6298.
+    ///
+    /// `None` until a segment is acknowledged.
+    pub rtt: Option<Duration>,
+    /// Variation of the round-trip time, as defined by RFC 6298.
+    pub rtt_variation: Duration,
+    /// Segments sent again, because they were lost or acknowledged late.
+    pub retransmits: u32,
+    /// Bytes the peer can receive, as last advertised by it.
+    pub remote_window: u32,
+    /// Bytes the socket can receive, i.e. free space in its receive buffer.
+    pub local_window: u32,
+}
+
+/// A TCP connection followed by an interface.
+#[cfg(feature = "statistics-inspect")]
+pub(crate) struct Connection {
+    local: IpEndpoint,
+    remote: IpEndpoint,
+    last_seen: Instant,
+    /// Our initial sequence number, once a socket sent a SYN for the connection.
+    iss: Option<u32>,
+    /// Initial sequence number of the peer.
+    irs: Option<u32>,
+    local_scale: Option<u8>,
+    remote_scale: Option<u8>,
+    /// End of the highest segment sent.
+    snd_max: u32,
+    /// Segment being timed: acknowledgment number covering it, and when it was sent.
+    timing: Option<(u32, Instant)>,
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    retransmits: u32,
+    remote_window: u32,
+}
+
+#[cfg(feature = "statistics-inspect")]
+impl Connection {
+    fn new(local: IpEndpoint, remote: IpEndpoint, now: Instant) -> Self {
+        Self {
+            local,
+            remote,
+            last_seen: now,
+            iss: None,
+            irs: None,
+            local_scale: None,
+            remote_scale: None,
+            snd_max: 0,
+            timing: None,
+            srtt: None,
+            rttvar: Duration::from_ticks(0),
+            retransmits: 0,
+            remote_window: 0,
+        }
+    }
+
+    fn sent(&mut self, seq: u32, len: u32, now: Instant) {
+        if len == 0 {
+            return;
+        }
+        let end = seq.wrapping_add(len);
+        if (seq.wrapping_sub(self.snd_max) as i32) < 0 {
+            self.retransmits = self.retransmits.wrapping_add(1);
+            // Karn's algorithm: acknowledgments of retransmitted segments are ambiguous.
+            self.timing = None;
+        } else if self.timing.is_none() {
+            self.timing = Some((end, now));
+        }
+        if (end.wrapping_sub(self.snd_max) as i32) > 0 {
+            self.snd_max = end;
+        }
+    }
+
+    fn acked(&mut self, ack: u32, now: Instant) {
+        let Some((end, sent_at)) = self.timing else {
+            return;
+        };
+        if (ack.wrapping_sub(end) as i32) < 0 {
+            return;
+        }
+        self.timing = None;
+
+        let sample = now - sent_at;
+        match self.srtt {
+            None => {
+                self.srtt = Some(sample);
+                self.rttvar = sample / 2;
+            }
+            Some(srtt) => {
+                let error = if srtt > sample { srtt - sample } else { sample - srtt };
+                self.rttvar = (self.rttvar * 3 + error) / 4;
+                self.srtt = Some((srtt * 7 + sample) / 8);
+            }
+        }
+    }
+
+    fn window_shift(&self) -> u8 {
+        // Windows are only scaled when both sides sent the option.
+        match (self.local_scale, self.remote_scale) {
+            (Some(_), Some(shift)) => shift.min(14),
+            _ => 0,
+        }
+    }
+}
+
+/// Statistics of an interface, and the TCP connections going through it.
+pub(crate) struct Recorder {
+    stats: InterfaceStats,
+    #[cfg(feature = "statistics-inspect")]
+    connections: &'static mut [Option<Connection>],
+}
+
+impl Recorder {
+    pub(crate) fn new(#[cfg(feature = "statistics-inspect")] connections: &'static mut [Option<Connection>]) -> Self {
+        Self {
+            stats: InterfaceStats::default(),
+            #[cfg(feature = "statistics-inspect")]
+            connections,
+        }
+    }
+
+    pub(crate) fn stats(&self) -> InterfaceStats {
+        self.stats
+    }
+
+    /// Count `n` packets dropped because an interface had no free socket slot for them.
+    #[cfg(feature = "forwarding")]
+    pub(crate) fn socket_set_full(&mut self, n: u32) {
+        self.stats.rx_socket_set_full = self.stats.rx_socket_set_full.wrapping_add(n);
+    }
+
+    /// Get the statistics of the TCP connection between `local` and `remote`, if it's followed.
+    ///
+    /// `local_window` is left to the caller.
+    #[cfg(all(feature = "statistics-inspect", feature = "tcp"))]
+    pub(crate) fn tcp(&self, local: IpEndpoint, remote: IpEndpoint) -> Option<TcpStats> {
+        let c = self
+            .connections
+            .iter()
+            .flatten()
+            .find(|c| c.local == local && c.remote == remote && c.iss.is_some())?;
+        Some(TcpStats {
+            rtt: c.srtt,
+            rtt_variation: c.rttvar,
+            retransmits: c.retransmits,
+            remote_window: c.remote_window,
+            local_window: 0,
+        })
+    }
+
+    #[cfg(feature = "statistics-inspect")]
+    fn find(&mut self, local: IpEndpoint, remote: IpEndpoint) -> Option<&mut Connection> {
+        self.connections
+            .iter_mut()
+            .flatten()
+            .find(|c| c.local == local && c.remote == remote)
+    }
+
+    /// Start following a connection, replacing the one seen the least recently if needed.
+    #[cfg(feature = "statistics-inspect")]
+    fn open(&mut self, local: IpEndpoint, remote: IpEndpoint, now: Instant) -> &mut Connection {
+        let n = match self.connections.iter().position(|c| match c {
+            None => true,
+            Some(c) => c.local == local && c.remote == remote,
+        }) {
+            Some(n) => n,
+            // Connections no socket took are replaced first.
+            None => unwrap!(self
+                .connections
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, c)| c.as_ref().map(|c| (c.iss.is_some(), c.last_seen)))
+                .map(|(n, _)| n)),
+        };
+        self.connections[n].insert(Connection::new(local, remote, now))
+    }
+
+    #[cfg(feature = "statistics-inspect")]
+    fn close(&mut self, local: IpEndpoint, remote: IpEndpoint) {
+        for c in self.connections.iter_mut() {
+            if c.as_ref().is_some_and(|c| c.local == local && c.remote == remote) {
+                *c = None;
+            }
+        }
+    }
+
+    #[cfg(feature = "statistics-inspect")]
+    fn tcp_received(&mut self, src: IpAddress, dst: IpAddress, segment: &TcpPacket<&[u8]>, now: Instant) {
+        let local = IpEndpoint::new(dst, segment.dst_port());
+        let remote = IpEndpoint::new(src, segment.src_port());
+        let seq = segment.seq_number().0 as u32;
+
+        let c = if segment.syn() && !segment.ack() {
+            let c = match self.find(local, remote) {
+                // Retransmitted SYN.
+                Some(c) if c.irs == Some(seq) => c,
+                _ => self.open(local, remote, now),
+            };
+            c.irs = Some(seq);
+            c.remote_scale = window_scale(segment);
+            c
+        } else {
+            let Some(c) = self.find(local, remote) else {
+                return;
+            };
+            if segment.syn() {
+                c.irs = Some(seq);
+                c.remote_scale = window_scale(segment);
+            }
+            c
+        };
+        c.last_seen = now;
+
+        if segment.ack() {
+            c.acked(segment.ack_number().0 as u32, now);
+            let shift = if segment.syn() { 0 } else { c.window_shift() };
+            c.remote_window = u32::from(segment.window_len()) << shift;
+        }
+    }
+
+    #[cfg(feature = "statistics-inspect")]
+    fn tcp_sent(&mut self, src: IpAddress, dst: IpAddress, segment: &TcpPacket<&[u8]>, now: Instant) {
+        let local = IpEndpoint::new(src, segment.src_port());
+        let remote = IpEndpoint::new(dst, segment.dst_port());
+        let seq = segment.seq_number().0 as u32;
+
+        if segment.rst() {
+            // smoltcp resets segments no socket accepted. Resets of connections a socket took are aborts.
+            if self.find(local, remote).is_none_or(|c| c.iss.is_none()) {
+                self.stats.rx_no_socket = self.stats.rx_no_socket.wrapping_add(1);
+                self.close(local, remote);
+            }
+            return;
+        }
+
+        let c = if segment.syn() {
+            let c = match self.find(local, remote) {
+                Some(c) if c.iss.is_none_or(|iss| iss == seq) => c,
+                _ => self.open(local, remote, now),
+            };
+            if c.iss.is_none() {
+                c.iss = Some(seq);
+                c.snd_max = seq;
+                c.local_scale = window_scale(segment);
+            }
+            c
+        } else {
+            match self.find(local, remote) {
+                Some(c) => c,
+                None => return,
+            }
+        };
+        c.last_seen = now;
+        c.sent(seq, segment.segment_len() as u32, now);
+    }
+}
+
+#[cfg(feature = "statistics-inspect")]
+fn window_scale(segment: &TcpPacket<&[u8]>) -> Option<u8> {
+    let mut options = segment.options();
+    while !options.is_empty() {
+        match TcpOption::parse(options) {
+            Ok((_, TcpOption::EndOfList)) | Err(_) => break,
+            Ok((_, TcpOption::WindowScale(shift))) => return Some(shift),
+            Ok((rest, _)) => options = rest,
+        }
+    }
+    None
+}
+
+/// Checksums verified in software.
+#[cfg(feature = "statistics-inspect")]
+#[derive(Clone, Copy)]
+struct Verify {
+    #[cfg(feature = "proto-ipv4")]
+    ipv4: bool,
+    tcp: bool,
+    udp: bool,
+    #[cfg(feature = "proto-ipv4")]
+    icmpv4: bool,
+    #[cfg(feature = "proto-ipv6")]
+    icmpv6: bool,
+}
+
+/// The part of an IP packet statistics look at.
+#[cfg(feature = "statistics-inspect")]
+struct IpPacket<'a> {
+    src: IpAddress,
+    dst: IpAddress,
+    protocol: IpProtocol,
+    payload: &'a [u8],
+    /// Whether the payload is only a fragment of the transport layer packet.
+    fragment: bool,
+}
+
+/// How frames are looked into, with the `statistics-inspect` feature.
+#[cfg(feature = "statistics-inspect")]
+#[derive(Clone, Copy)]
+pub(crate) struct Inspect {
+    medium: Medium,
+    verify: Verify,
+    now: Instant,
+}
+
+#[cfg(feature = "statistics-inspect")]
+impl Inspect {
+    pub(crate) fn new(medium: Medium, checksum: &ChecksumCapabilities, now: Instant) -> Self {
+        let rx = |c: Checksum| matches!(c, Checksum::Both | Checksum::Rx);
+        Self {
+            medium,
+            verify: Verify {
+                #[cfg(feature = "proto-ipv4")]
+                ipv4: rx(checksum.ipv4),
+                tcp: rx(checksum.tcp),
+                udp: rx(checksum.udp),
+                #[cfg(feature = "proto-ipv4")]
+                icmpv4: rx(checksum.icmpv4),
+                #[cfg(feature = "proto-ipv6")]
+                icmpv6: rx(checksum.icmpv6),
+            },
+            now,
+        }
+    }
+}
+
+/// Records the frames of a driver, while the stack polls it.
+#[derive(Clone, Copy)]
+pub(crate) struct Tap<'a> {
+    recorder: &'a RefCell<Recorder>,
+    #[cfg(feature = "statistics-inspect")]
+    inspect: Inspect,
+}
+
+impl<'a> Tap<'a> {
+    pub(crate) fn new(
+        recorder: &'a RefCell<Recorder>,
+        #[cfg(feature = "statistics-inspect")] inspect: Inspect,
+    ) -> Self {
+        Self {
+            recorder,
+            #[cfg(feature = "statistics-inspect")]
+            inspect,
+        }
+    }
+
+    pub(crate) fn received(&self, frame: &[u8]) {
+        let mut r = self.recorder.borrow_mut();
+        r.stats.rx_packets = r.stats.rx_packets.wrapping_add(1);
+        r.stats.rx_bytes = r.stats.rx_bytes.wrapping_add(frame.len() as u64);
+        #[cfg(feature = "statistics-inspect")]
+        self.inspect_received(&mut r, frame);
+    }
+
+    /// Count a frame forwarded to another interface, without looking into it.
+    #[cfg(feature = "forwarding")]
+    pub(crate) fn forwarded(&self, frame: &[u8]) {
+        let mut r = self.recorder.borrow_mut();
+        r.stats.rx_packets = r.stats.rx_packets.wrapping_add(1);
+        r.stats.rx_bytes = r.stats.rx_bytes.wrapping_add(frame.len() as u64);
+    }
+
+    pub(crate) fn transmitted(&self, frame: &[u8]) {
+        let mut r = self.recorder.borrow_mut();
+        r.stats.tx_packets = r.stats.tx_packets.wrapping_add(1);
+        r.stats.tx_bytes = r.stats.tx_bytes.wrapping_add(frame.len() as u64);
+        #[cfg(feature = "statistics-inspect")]
+        self.inspect_transmitted(&mut r, frame);
+    }
+}
+
+#[cfg(feature = "statistics-inspect")]
+impl Tap<'_> {
+    fn inspect_received(&self, r: &mut Recorder, frame: &[u8]) {
+        let Some((packet, header_ok)) = self.ip_packet(frame) else {
+            return;
+        };
+        if !header_ok || !self.verify_checksum(&packet) {
+            r.stats.rx_checksum_errors = r.stats.rx_checksum_errors.wrapping_add(1);
+            return;
+        }
+        if let (IpProtocol::Tcp, false) = (packet.protocol, packet.fragment) {
+            if let Ok(segment) = TcpPacket::new_checked(packet.payload) {
+                r.tcp_received(packet.src, packet.dst, &segment, self.inspect.now);
+            }
+        }
+    }
+
+    fn inspect_transmitted(&self, r: &mut Recorder, frame: &[u8]) {
+        #[cfg(all(feature = "medium-ethernet", feature = "proto-ipv4"))]
+        if self.inspect.medium == Medium::Ethernet {
+            if let Ok(eth) = EthernetFrame::new_checked(frame) {
+                if eth.ethertype() == EthernetProtocol::Arp
+                    && ArpPacket::new_checked(eth.payload()).is_ok_and(|arp| arp.operation() == ArpOperation::Request)
+                {
+                    r.stats.neighbor_misses = r.stats.neighbor_misses.wrapping_add(1);
+                    return;
+                }
+            }
+        }
+
+        let Some((packet, _)) = self.ip_packet(frame) else {
+            return;
+        };
+        match (packet.protocol, packet.fragment) {
+            (IpProtocol::Tcp, false) => {
+                if let Ok(segment) = TcpPacket::new_checked(packet.payload) {
+                    r.tcp_sent(packet.src, packet.dst, &segment, self.inspect.now);
+                }
+            }
+            #[cfg(feature = "proto-ipv4")]
+            (IpProtocol::Icmp, false) => {
+                if Icmpv4Packet::new_checked(packet.payload).is_ok_and(|icmp| {
+                    icmp.msg_type() == Icmpv4Message::DstUnreachable && icmp.msg_code() == ICMPV4_PORT_UNREACHABLE
+                }) {
+                    r.stats.rx_no_socket = r.stats.rx_no_socket.wrapping_add(1);
+                }
+            }
+            #[cfg(feature = "proto-ipv6")]
+            (IpProtocol::Icmpv6, false) => match Icmpv6Packet::new_checked(packet.payload) {
+                Ok(icmp)
+                    if icmp.msg_type() == Icmpv6Message::DstUnreachable
+                        && icmp.msg_code() == ICMPV6_PORT_UNREACHABLE =>
+                {
+                    r.stats.rx_no_socket = r.stats.rx_no_socket.wrapping_add(1);
+                }
+                // Solicitations from the unspecified address are for duplicate address detection.
+                Ok(icmp) if icmp.msg_type() == Icmpv6Message::NeighborSolicit && !packet.src.is_unspecified() => {
+                    r.stats.neighbor_misses = r.stats.neighbor_misses.wrapping_add(1);
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    /// Get the IP packet in `frame`, and whether its header is valid.
+    #[cfg_attr(
+        not(any(feature = "medium-ethernet", feature = "medium-ip")),
+        allow(unreachable_code, unused_variables)
+    )]
+    fn ip_packet<'f>(&self, frame: &'f [u8]) -> Option<(IpPacket<'f>, bool)> {
+        let packet: &[u8] = match self.inspect.medium {
+            #[cfg(feature = "medium-ethernet")]
+            Medium::Ethernet => {
+                let eth = EthernetFrame::new_checked(frame).ok()?;
+                match eth.ethertype() {
+                    EthernetProtocol::Ipv4 | EthernetProtocol::Ipv6 => eth.payload(),
+                    _ => return None,
+                }
+            }
+            #[cfg(feature = "medium-ip")]
+            Medium::Ip => frame,
+            // 6LoWPAN compressed packets aren't looked into.
+            #[allow(unreachable_patterns)]
+            _ => return None,
+        };
+
+        match packet.first()? >> 4 {
+            #[cfg(feature = "proto-ipv4")]
+            4 => {
+                let ip = Ipv4Packet::new_checked(packet).ok()?;
+                let header_ok = !self.inspect.verify.ipv4 || ip.verify_checksum();
+                let packet = IpPacket {
+                    src: ip.src_addr().into(),
+                    dst: ip.dst_addr().into(),
+                    protocol: ip.next_header(),
+                    payload: ip.payload(),
+                    fragment: ip.more_frags() || ip.frag_offset() != 0,
+                };
+                Some((packet, header_ok))
+            }
+            #[cfg(feature = "proto-ipv6")]
+            6 => {
+                let ip = Ipv6Packet::new_checked(packet).ok()?;
+                let packet = IpPacket {
+                    src: ip.src_addr().into(),
+                    dst: ip.dst_addr().into(),
+                    protocol: ip.next_header(),
+                    payload: ip.payload(),
+                    fragment: false,
+                };
+                Some((packet, true))
+            }
+            _ => None,
+        }
+    }
+
+    /// Check the transport layer checksum of `packet`. Packets too short to have one pass.
+    fn verify_checksum(&self, packet: &IpPacket) -> bool {
+        if packet.fragment {
+            return true;
+        }
+        let (src, dst, payload) = (&packet.src, &packet.dst, packet.payload);
+        match packet.protocol {
+            IpProtocol::Tcp if self.inspect.verify.tcp => {
+                TcpPacket::new_checked(payload).map_or(true, |p| p.verify_checksum(src, dst))
+            }
+            IpProtocol::Udp if self.inspect.verify.udp => match UdpPacket::new_checked(payload) {
+                // The checksum is optional over IPv4.
+                #[cfg(feature = "proto-ipv4")]
+                Ok(p) if p.checksum() == 0 && matches!(packet.src, IpAddress::Ipv4(_)) => true,
+                Ok(p) => p.verify_checksum(src, dst),
+                Err(_) => true,
+            },
+            #[cfg(feature = "proto-ipv4")]
+            IpProtocol::Icmp if self.inspect.verify.icmpv4 => {
+                Icmpv4Packet::new_checked(payload).map_or(true, |p| p.verify_checksum())
+            }
+            #[cfg(feature = "proto-ipv6")]
+            IpProtocol::Icmpv6 if self.inspect.verify.icmpv6 => match (src, dst) {
+                (IpAddress::Ipv6(src), IpAddress::Ipv6(dst)) => {
+                    Icmpv6Packet::new_checked(payload).map_or(true, |p| p.verify_checksum(src, dst))
+                }
+                #[allow(unreachable_patterns)]
+                _ => true,
+            },
+            _ => true,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "proto-ipv4", feature = "medium-ip"))]
+mod tests {
+    use std::vec::Vec;
+
+    use smoltcp::wire::{IpProtocol, Ipv4Address, Ipv4Packet, Ipv4Repr};
+
+    use super::*;
+
+    const LOCAL: Ipv4Address = Ipv4Address::new(10, 0, 0, 1);
+    const REMOTE: Ipv4Address = Ipv4Address::new(10, 0, 0, 2);
+
+    fn recorder() -> RefCell<Recorder> {
+        RefCell::new(Recorder::new(
+            #[cfg(feature = "statistics-inspect")]
+            Vec::leak((0..4).map(|_| None).collect()),
+        ))
+    }
+
+    #[cfg(feature = "statistics-inspect")]
+    fn tap(recorder: &RefCell<Recorder>, checksum: Checksum, now: Instant) -> Tap<'_> {
+        let mut caps = ChecksumCapabilities::default();
+        caps.ipv4 = checksum;
+        caps.tcp = checksum;
+        caps.udp = checksum;
+        Tap::new(recorder, Inspect::new(Medium::Ip, &caps, now))
+    }
+
+    #[cfg(not(feature = "statistics-inspect"))]
+    fn tap(recorder: &RefCell<Recorder>) -> Tap<'_> {
+        Tap::new(recorder)
+    }
+
+    /// An IPv4 packet, with a valid header checksum.
+    fn ipv4(src: Ipv4Address, dst: Ipv4Address, protocol: IpProtocol, payload: &[u8]) -> Vec<u8> {
+        let repr = Ipv4Repr {
+            src_addr: src,
+            dst_addr: dst,
+            next_header: protocol,
+            payload_len: payload.len(),
+            hop_limit: 64,
+        };
+        let mut buf = vec![0; repr.buffer_len() + payload.len()];
+        let mut packet = Ipv4Packet::new_unchecked(&mut buf[..]);
+        repr.emit(&mut packet, &smoltcp::phy::ChecksumCapabilities::default());
+        packet.payload_mut().copy_from_slice(payload);
+        buf
+    }
+
+    #[test]
+    fn counters() {
+        let recorder = recorder();
+        #[cfg(feature = "statistics-inspect")]
+        let tap = tap(&recorder, Checksum::Both, Instant::from_secs(0));
+        #[cfg(not(feature = "statistics-inspect"))]
+        let tap = tap(&recorder);
+
+        let udp = [0x12, 0x34, 0x00, 0x07, 0x00, 0x08, 0x00, 0x00];
+        tap.received(&ipv4(REMOTE, LOCAL, IpProtocol::Udp, &udp));
+        tap.transmitted(&ipv4(LOCAL, REMOTE, IpProtocol::Udp, &udp));
+        tap.transmitted(&ipv4(LOCAL, REMOTE, IpProtocol::Udp, &[0; 100]));
+        #[cfg(feature = "forwarding")]
+        {
+            tap.forwarded(&[0; 60]);
+            recorder.borrow_mut().socket_set_full(2);
+        }
+
+        let stats = recorder.borrow().stats();
+        #[cfg(not(feature = "forwarding"))]
+        assert_eq!((stats.rx_packets, stats.rx_bytes), (1, 28));
+        #[cfg(feature = "forwarding")]
+        assert_eq!((stats.rx_packets, stats.rx_bytes, stats.rx_socket_set_full), (2, 88, 2));
+        assert_eq!((stats.tx_packets, stats.tx_bytes), (2, 28 + 120));
+    }
+
+    #[cfg(feature = "statistics-inspect")]
+    #[test]
+    fn checksum_errors() {
+        // A UDP datagram to port 7, with a wrong checksum.
+        let packet = ipv4(
+            REMOTE,
+            LOCAL,
+            IpProtocol::Udp,
+            &[0x12, 0x34, 0x00, 0x07, 0x00, 0x08, 0xde, 0xad],
+        );
+
+        let recorder = recorder();
+        tap(&recorder, Checksum::Both, Instant::from_secs(0)).received(&packet);
+        assert_eq!(recorder.borrow().stats().rx_checksum_errors, 1);
+
+        // Drivers verifying checksums drop these packets themselves.
+        let recorder = self::recorder();
+        tap(&recorder, Checksum::None, Instant::from_secs(0)).received(&packet);
+        assert_eq!(recorder.borrow().stats().rx_checksum_errors, 0);
+    }
+
+    #[cfg(all(feature = "statistics-inspect", feature = "tcp"))]
+    #[test]
+    fn tcp_connection() {
+        use smoltcp::wire::{TcpControl, TcpRepr, TcpSeqNumber};
+
+        fn segment(
+            src: (Ipv4Address, u16),
+            dst: (Ipv4Address, u16),
+            control: TcpControl,
+            seq: i32,
+            ack: Option<i32>,
+            payload: &[u8],
+        ) -> Vec<u8> {
+            let repr = TcpRepr {
+                src_port: src.1,
+                dst_port: dst.1,
+                control,
+                seq_number: TcpSeqNumber(seq),
+                ack_number: ack.map(TcpSeqNumber),
+                window_len: 1000,
+                window_scale: None,
+                max_seg_size: None,
+                sack_permitted: false,
+                sack_ranges: [None; 3],
+                timestamp: None,
+                payload,
+            };
+            let mut tcp = vec![0; repr.buffer_len()];
+            repr.emit(
+                &mut TcpPacket::new_unchecked(&mut tcp[..]),
+                &src.0.into(),
+                &dst.0.into(),
+                &smoltcp::phy::ChecksumCapabilities::default(),
+            );
+            ipv4(src.0, dst.0, IpProtocol::Tcp, &tcp)
+        }
+
+        let (local, remote) = ((LOCAL, 1000), (REMOTE, 80));
+        let recorder = recorder();
+        let at = |ms| tap(&recorder, Checksum::Both, Instant::from_millis(ms));
+
+        at(0).transmitted(&segment(local, remote, TcpControl::Syn, 100, None, &[]));
+        at(20).received(&segment(remote, local, TcpControl::Syn, 500, Some(101), &[]));
+        at(20).transmitted(&segment(local, remote, TcpControl::None, 101, Some(501), b"hello"));
+        // Retransmitted, its acknowledgment isn't used to estimate the round-trip time.
+        at(200).transmitted(&segment(local, remote, TcpControl::None, 101, Some(501), b"hello"));
+        at(300).received(&segment(remote, local, TcpControl::None, 501, Some(106), &[]));
+
+        let endpoints = (IpEndpoint::new(LOCAL.into(), 1000), IpEndpoint::new(REMOTE.into(), 80));
+        let stats = recorder.borrow().tcp(endpoints.0, endpoints.1).unwrap();
+        assert_eq!(stats.rtt, Some(Duration::from_millis(20)));
+        assert_eq!(stats.rtt_variation, Duration::from_millis(10));
+        assert_eq!(stats.retransmits, 1);
+        assert_eq!(stats.remote_window, 1000);
+
+        // smoltcp resets connections no socket accepted.
+        at(400).transmitted(&segment(local, (REMOTE, 81), TcpControl::Rst, 0, Some(1), &[]));
+        assert_eq!(recorder.borrow().stats().rx_no_socket, 1);
+    }
+}
@@ -2,24 +2,26 @@
 //!
 //! # Listening
 //!
-//! `embassy-net` does not have a `TcpListener`. Instead, individual `TcpSocket`s can be put into
-//! listening mode by calling [`TcpSocket::accept`].
+//! Individual `TcpSocket`s can be put into listening mode by calling [`TcpSocket::accept`].
 //!
 //! Incoming connections when no socket is listening are rejected. To accept many incoming
-//! connections, create many sockets and put them all into listening mode.
+//! connections, create many sockets and put them all into listening mode, or use a
+//! [`TcpListener`](server::TcpListener), which keeps a backlog of listening sockets.
 
 use core::future::{poll_fn, Future};
 use core::mem;
 use core::task::{Context, Poll};
 
 use embassy_time::Duration;
-use smoltcp::iface::{Interface, SocketHandle};
+use smoltcp::iface::Interface;
 use smoltcp::socket::tcp;
 pub use smoltcp::socket::tcp::State;
 use smoltcp::wire::{IpEndpoint, IpListenEndpoint};
 
+#[cfg(feature = "statistics-inspect")]
+use crate::stats::TcpStats;
 use crate::time::duration_to_smoltcp;
-use crate::Stack;
+use crate::{InterfaceBindError, InterfaceId, SocketId, Stack};
 
 /// Error returned by TcpSocket read/write functions.
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -29,6 +31,9 @@ pub enum Error {
     ///
     /// This can happen on receiving a RST packet, or on timeout.
     ConnectionReset,
+    /// All the connections of a [`TcpClient`](client::TcpClient) are in use, or the interface
+    /// has no free socket slot.
+    NoFreeSocket,
 }
 
 /// Error returned by [`TcpSocket::connect`].
@@ -43,6 +48,8 @@ pub enum ConnectError {
     TimedOut,
     /// No route to host.
     NoRoute,
+    /// The interface that routes to the remote host has no free socket slot.
+    NoFreeSocket,
 }
 
 /// Error returned by [`TcpSocket::accept`].
@@ -55,11 +62,14 @@ pub enum AcceptError {
     InvalidPort,
     /// The remote host rejected the connection with a RST packet.
     ConnectionReset,
+    /// The interface that has the address to listen on has no free socket slot.
+    NoFreeSocket,
 }
 
 /// A TCP socket.
 pub struct TcpSocket<'a> {
     io: TcpIo<'a>,
+    bound_to_interface: bool,
 }
 
 /// The reader half of a TCP socket.
@@ -179,7 +189,7 @@ impl<'a> TcpSocket<'a> {
         let handle = stack.with_mut(|i| {
             let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
             let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
-            i.sockets.add(tcp::Socket::new(
+            i.add_socket(tcp::Socket::new(
                 tcp::SocketBuffer::new(rx_buffer),
                 tcp::SocketBuffer::new(tx_buffer),
             ))
@@ -187,9 +197,21 @@ impl<'a> TcpSocket<'a> {
 
         Self {
             io: TcpIo { stack, handle },
+            bound_to_interface: false,
         }
     }
 
+    /// Bind the socket to a network interface.
+    ///
+    /// Connections and listening then only use that interface. Otherwise, [`connect`](Self::connect)
+    /// uses the interface selected by the routing table, and [`accept`](Self::accept) only accepts
+    /// connections on the default interface.
+    pub fn bind_to_interface(&mut self, id: InterfaceId) -> Result<(), InterfaceBindError> {
+        self.io.handle = self.io.stack.with_mut(|i| i.bind_socket(self.io.handle, id))?;
+        self.bound_to_interface = true;
+        Ok(())
+    }
+
     /// Return the maximum number of bytes inside the recv buffer.
     pub fn recv_capacity(&self) -> usize {
         self.io.recv_capacity()
@@ -240,11 +262,22 @@ impl<'a> TcpSocket<'a> {
     }
 
     /// Connect to a remote host.
+    ///
+    /// Unless the socket is bound to an interface, it's moved to the interface that routes to
+    /// `remote_endpoint`. This fails with [`ConnectError::NoFreeSocket`] if that interface has no free socket slot.
     pub async fn connect<T>(&mut self, remote_endpoint: T) -> Result<(), ConnectError>
     where
         T: Into<IpEndpoint>,
     {
-        let local_port = self.io.stack.with_mut(|i| i.get_local_port());
+        let remote_endpoint = remote_endpoint.into();
+        let local_port = self.io.stack.with_mut(|i| {
+            if !self.bound_to_interface {
+                if let Some(id) = i.route(remote_endpoint.addr) {
+                    self.io.handle = i.move_socket(self.io.handle, id).ok_or(ConnectError::NoFreeSocket)?;
+                }
+            }
+            Ok(i.get_local_port())
+        })?;
 
         match {
             self.io
@@ -272,15 +305,15 @@ impl<'a> TcpSocket<'a> {
     /// Accept a connection from a remote host.
     ///
     /// This function puts the socket in listening mode, and waits until a connection is received.
+    ///
+    /// Only connections on the interface the socket is on are accepted: the default interface, the
+    /// one set with [`bind_to_interface`](Self::bind_to_interface), or the one that has the address
+    /// of `local_endpoint`.
     pub async fn accept<T>(&mut self, local_endpoint: T) -> Result<(), AcceptError>
     where
         T: Into<IpListenEndpoint>,
     {
-        match self.io.with_mut(|s, _| s.listen(local_endpoint)) {
-            Ok(()) => {}
-            Err(tcp::ListenError::InvalidState) => return Err(AcceptError::InvalidState),
-            Err(tcp::ListenError::Unaddressable) => return Err(AcceptError::InvalidPort),
-        }
+        self.listen(local_endpoint.into())?;
 
         poll_fn(|cx| {
             self.io.with_mut(|s, _| match s.state() {
@@ -294,6 +327,28 @@ impl<'a> TcpSocket<'a> {
         .await
     }
 
+    /// Put the socket in listening mode.
+    ///
+    /// Unless the socket is bound to an interface, it's moved to the interface that has the address
+    /// of `local_endpoint`, if any.
+    fn listen(&mut self, local_endpoint: IpListenEndpoint) -> Result<(), AcceptError> {
+        if let Some(addr) = local_endpoint.addr {
+            if !self.bound_to_interface {
+                self.io.handle = self.io.stack.with_mut(|i| match i.interface_with_addr(addr) {
+                    Some(id) => i.move_socket(self.io.handle, id).ok_or(AcceptError::NoFreeSocket),
+                    None => Ok(self.io.handle),
+                })?;
+                self.bound_to_interface = true;
+            }
+        }
+
+        match self.io.with_mut(|s, _| s.listen(local_endpoint)) {
+            Ok(()) => Ok(()),
+            Err(tcp::ListenError::InvalidState) => Err(AcceptError::InvalidState),
+            Err(tcp::ListenError::Unaddressable) => Err(AcceptError::InvalidPort),
+        }
+    }
+
     /// Wait until the socket becomes readable.
     ///
     /// A socket becomes readable when the receive half of the full-duplex connection is open
@@ -392,6 +447,26 @@ impl<'a> TcpSocket<'a> {
         self.io.with(|s, _| s.state())
     }
 
+    /// Get the statistics of the connection.
+    ///
+    /// Returns `None` if the socket is not connected, or if its interface doesn't follow the
+    /// connection. See [`stats`](crate::stats).
+    #[cfg(feature = "statistics-inspect")]
+    pub fn stats(&self) -> Option<TcpStats> {
+        self.io.stack.with(|i| {
+            let (local, remote, local_window) = i.with_socket(self.io.handle, |s: &tcp::Socket, _| {
+                Some((
+                    s.local_endpoint()?,
+                    s.remote_endpoint()?,
+                    s.recv_capacity() - s.recv_queue(),
+                ))
+            })?;
+            let mut stats = i.iface(self.io.handle.iface).stats.borrow().tcp(local, remote)?;
+            stats.local_window = local_window as u32;
+            Some(stats)
+        })
+    }
+
     /// Close the write half of the socket.
     ///
     /// This closes only the write half of the socket. The read half side remains open, the
@@ -451,7 +526,7 @@ impl<'a> TcpSocket<'a> {
 
 impl<'a> Drop for TcpSocket<'a> {
     fn drop(&mut self) {
-        self.io.stack.with_mut(|i| i.sockets.remove(self.io.handle));
+        self.io.stack.with_mut(|i| i.remove_socket(self.io.handle));
     }
 }
 
@@ -470,24 +545,16 @@ fn _assert_covariant_writer<'a, 'b: 'a>(x: TcpWriter<'b>) -> TcpWriter<'a> {
 #[derive(Copy, Clone)]
 struct TcpIo<'a> {
     stack: Stack<'a>,
-    handle: SocketHandle,
+    handle: SocketId,
 }
 
 impl<'d> TcpIo<'d> {
     fn with<R>(&self, f: impl FnOnce(&tcp::Socket, &Interface) -> R) -> R {
-        self.stack.with(|i| {
-            let socket = i.sockets.get::<tcp::Socket>(self.handle);
-            f(socket, &i.iface)
-        })
+        self.stack.with(|i| i.with_socket(self.handle, f))
     }
 
     fn with_mut<R>(&self, f: impl FnOnce(&mut tcp::Socket, &mut Interface) -> R) -> R {
-        self.stack.with_mut(|i| {
-            let socket = i.sockets.get_mut::<tcp::Socket>(self.handle);
-            let res = f(socket, &mut i.iface);
-            i.waker.wake();
-            res
-        })
+        self.stack.with_mut(|i| i.with_socket_mut(self.handle, f))
     }
 
     fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
@@ -662,6 +729,7 @@ mod embedded_io_impls {
                 ConnectError::ConnectionReset => embedded_io_async::ErrorKind::ConnectionReset,
                 ConnectError::TimedOut => embedded_io_async::ErrorKind::TimedOut,
                 ConnectError::NoRoute => embedded_io_async::ErrorKind::NotConnected,
+                ConnectError::NoFreeSocket => embedded_io_async::ErrorKind::OutOfMemory,
                 ConnectError::InvalidState => embedded_io_async::ErrorKind::Other,
             }
         }
@@ -671,6 +739,7 @@ mod embedded_io_impls {
         fn kind(&self) -> embedded_io_async::ErrorKind {
             match self {
                 Error::ConnectionReset => embedded_io_async::ErrorKind::ConnectionReset,
+                Error::NoFreeSocket => embedded_io_async::ErrorKind::OutOfMemory,
             }
         }
     }
@@ -802,40 +871,46 @@ pub mod client {
                 IpAddr::V6(_) => panic!("ipv6 support not enabled"),
             };
             let remote_endpoint = (addr, remote.port());
-            let mut socket = TcpConnection::new(self.stack, self.state)?;
+            let mut socket = TcpConnection::new(self.stack, &self.state.pool)?;
             socket.socket.set_timeout(self.socket_timeout);
-            socket
-                .socket
-                .connect(remote_endpoint)
-                .await
-                .map_err(|_| Error::ConnectionReset)?;
+            socket.socket.connect(remote_endpoint).await.map_err(|e| match e {
+                ConnectError::NoFreeSocket => Error::NoFreeSocket,
+                _ => Error::ConnectionReset,
+            })?;
             Ok(socket)
         }
     }
 
-    /// Opened TCP connection in a [`TcpClient`].
+    /// Opened TCP connection in a [`TcpClient`], or accepted by a [`TcpListener`](super::server::TcpListener).
     pub struct TcpConnection<'d, const N: usize, const TX_SZ: usize, const RX_SZ: usize> {
-        socket: TcpSocket<'d>,
-        state: &'d TcpClientState<N, TX_SZ, RX_SZ>,
+        pub(super) socket: TcpSocket<'d>,
+        pool: &'d Pool<([u8; TX_SZ], [u8; RX_SZ]), N>,
         bufs: NonNull<([u8; TX_SZ], [u8; RX_SZ])>,
     }
 
     impl<'d, const N: usize, const TX_SZ: usize, const RX_SZ: usize> TcpConnection<'d, N, TX_SZ, RX_SZ> {
-        fn new(stack: Stack<'d>, state: &'d TcpClientState<N, TX_SZ, RX_SZ>) -> Result<Self, Error> {
-            let mut bufs = state.pool.alloc().ok_or(Error::ConnectionReset)?;
+        pub(super) fn new(stack: Stack<'d>, pool: &'d Pool<([u8; TX_SZ], [u8; RX_SZ]), N>) -> Result<Self, Error> {
+            let mut bufs = pool.alloc().ok_or(Error::NoFreeSocket)?;
             Ok(Self {
                 socket: unsafe { TcpSocket::new(stack, &mut bufs.as_mut().1, &mut bufs.as_mut().0) },
-                state,
+                pool,
                 bufs,
             })
         }
+
+        /// Get the remote endpoint of the connection.
+        ///
+        /// Returns `None` if the connection is closed.
+        pub fn remote_endpoint(&self) -> Option<IpEndpoint> {
+            self.socket.remote_endpoint()
+        }
     }
 
     impl<'d, const N: usize, const TX_SZ: usize, const RX_SZ: usize> Drop for TcpConnection<'d, N, TX_SZ, RX_SZ> {
         fn drop(&mut self) {
             unsafe {
                 self.socket.close();
-                self.state.pool.free(self.bufs);
+                self.pool.free(self.bufs);
             }
         }
     }
@@ -878,7 +953,7 @@ pub mod client {
         }
     }
 
-    struct Pool<T, const N: usize> {
+    pub(crate) struct Pool<T, const N: usize> {
         used: [Cell<bool>; N],
         data: [UnsafeCell<MaybeUninit<T>>; N],
     }
@@ -887,7 +962,7 @@ pub mod client {
         const VALUE: Cell<bool> = Cell::new(false);
         const UNINIT: UnsafeCell<MaybeUninit<T>> = UnsafeCell::new(MaybeUninit::uninit());
 
-        const fn new() -> Self {
+        pub(crate) const fn new() -> Self {
             Self {
                 used: [Self::VALUE; N],
                 data: [Self::UNINIT; N],
@@ -896,7 +971,7 @@ pub mod client {
     }
 
     impl<T, const N: usize> Pool<T, N> {
-        fn alloc(&self) -> Option<NonNull<T>> {
+        pub(crate) fn alloc(&self) -> Option<NonNull<T>> {
             for n in 0..N {
                 // this can't race because Pool is not Sync.
                 if !self.used[n].get() {
@@ -909,7 +984,7 @@ pub mod client {
         }
 
         /// safety: p must be a pointer obtained from self.alloc that hasn't been freed yet.
-        unsafe fn free(&self, p: NonNull<T>) {
+        pub(crate) unsafe fn free(&self, p: NonNull<T>) {
             let origin = self.data.as_ptr() as *mut T;
             let n = p.as_ptr().offset_from(origin);
             assert!(n >= 0);
@@ -918,3 +993,189 @@ pub mod client {
         }
     }
 }
+
+/// TCP server accepting connections with a backlog of listening sockets.
+pub mod server {
+    use core::cell::RefCell;
+    use core::net::SocketAddr;
+
+    use super::client::{Pool, TcpConnection};
+    use super::*;
+
+    /// TCP listener with a backlog of up to `N` listening sockets.
+    ///
+    /// Connections arriving while the previous one is being handled complete their handshake on
+    /// another listening socket, instead of being rejected. Accepted connections keep their socket
+    /// until dropped, so `N` is the maximum of accepted connections and listening sockets together.
+    pub struct TcpListener<'d, const N: usize, const TX_SZ: usize = 1024, const RX_SZ: usize = 1024> {
+        stack: Stack<'d>,
+        state: &'d TcpListenerState<N, TX_SZ, RX_SZ>,
+        local_endpoint: IpListenEndpoint,
+        interface: Option<InterfaceId>,
+        socket_timeout: Option<Duration>,
+        backlog: RefCell<[Option<TcpConnection<'d, N, TX_SZ, RX_SZ>>; N]>,
+    }
+
+    impl<'d, const N: usize, const TX_SZ: usize, const RX_SZ: usize> TcpListener<'d, N, TX_SZ, RX_SZ> {
+        /// Create a new `TcpListener`, for connections to `local_endpoint`.
+        ///
+        /// Sockets start listening on the first call to [`accept`](Self::accept).
+        pub fn new<T>(stack: Stack<'d>, state: &'d TcpListenerState<N, TX_SZ, RX_SZ>, local_endpoint: T) -> Self
+        where
+            T: Into<IpListenEndpoint>,
+        {
+            Self {
+                stack,
+                state,
+                local_endpoint: local_endpoint.into(),
+                interface: None,
+                socket_timeout: None,
+                backlog: RefCell::new([const { None }; N]),
+            }
+        }
+
+        /// Only accept connections on a network interface.
+        ///
+        /// Otherwise, like [`TcpSocket::accept`], connections are accepted on the interface that has
+        /// the address of the local endpoint, or on the default interface. Sockets that are already
+        /// listening keep their interface.
+        pub fn bind_to_interface(&mut self, id: InterfaceId) -> Result<(), InterfaceBindError> {
+            if !self.stack.with(|i| (id.0 as usize) < i.ifaces.len()) {
+                return Err(InterfaceBindError::InvalidInterface);
+            }
+            self.interface = Some(id);
+            Ok(())
+        }
+
+        /// Set the timeout for each connection accepted by this `TcpListener`.
+        ///
+        /// If the timeout is set, the socket will be closed if no data is received for the
+        /// specified duration.
+        pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+            self.socket_timeout = timeout;
+        }
+
+        /// Accept a connection.
+        ///
+        /// This puts all free sockets in listening mode, and waits until one of them is connected.
+        /// Connections that completed their handshake in the meantime are returned immediately.
+        pub async fn accept(&self) -> Result<(SocketAddr, TcpConnection<'d, N, TX_SZ, RX_SZ>), AcceptError> {
+            poll_fn(|cx| {
+                if let Err(e) = self.listen() {
+                    return Poll::Ready(Err(e));
+                }
+
+                let mut backlog = self.backlog.borrow_mut();
+                for slot in backlog.iter_mut() {
+                    let Some(conn) = slot else { continue };
+                    let state = conn.socket.io.with_mut(|s, _| {
+                        s.register_send_waker(cx.waker());
+                        s.state()
+                    });
+                    match (state, conn.remote_endpoint()) {
+                        (tcp::State::Listen | tcp::State::SynReceived, _) => {}
+                        // Timed out during the handshake, free the socket to listen again.
+                        (tcp::State::Closed, _) => {
+                            *slot = None;
+                            cx.waker().wake_by_ref();
+                        }
+                        (_, Some(remote)) => {
+                            let conn = unwrap!(slot.take());
+                            return Poll::Ready(Ok((SocketAddr::new(remote.addr.into(), remote.port), conn)));
+                        }
+                        (_, None) => {}
+                    }
+                }
+                Poll::Pending
+            })
+            .await
+        }
+
+        /// Put all free sockets in listening mode.
+        fn listen(&self) -> Result<(), AcceptError> {
+            let mut backlog = self.backlog.borrow_mut();
+            let listening = backlog.iter().any(Option::is_some);
+            for slot in backlog.iter_mut().filter(|slot| slot.is_none()) {
+                // All sockets are in use by accepted connections.
+                let Ok(mut conn) = TcpConnection::new(self.stack, &self.state.pool) else {
+                    break;
+                };
+                conn.socket.set_timeout(self.socket_timeout);
+                let bound = match self.interface {
+                    Some(id) => conn.socket.bind_to_interface(id).map_err(|_| AcceptError::NoFreeSocket),
+                    None => Ok(()),
+                };
+                match bound.and_then(|()| conn.socket.listen(self.local_endpoint)) {
+                    Ok(()) => *slot = Some(conn),
+                    // Keep the backlog we have when the interface runs out of socket slots.
+                    Err(AcceptError::NoFreeSocket) if listening => break,
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl<const N: usize, const TX_SZ: usize, const RX_SZ: usize> TcpAccept for TcpListener<'_, N, TX_SZ, RX_SZ> {
+        type Error = Error;
+        type Connection<'m>
+            = TcpConnection<'m, N, TX_SZ, RX_SZ>
+        where
+            Self: 'm;
+
+        async fn accept(&self) -> Result<(SocketAddr, Self::Connection<'_>), Self::Error> {
+            TcpListener::accept(self).await.map_err(|e| match e {
+                AcceptError::NoFreeSocket => Error::NoFreeSocket,
+                _ => Error::ConnectionReset,
+            })
+        }
+    }
+
+    /// Server counterpart of the `embedded-nal-async` [`TcpConnect`](embedded_nal_async::TcpConnect)
+    /// trait, which has none.
+    pub trait TcpAccept {
+        /// Error type returned on accept failure.
+        type Error: embedded_io_async::Error;
+
+        /// Type holding state of an accepted TCP connection. Should close the connection when dropped.
+        type Connection<'a>: embedded_io_async::Read<Error = Self::Error>
+            + embedded_io_async::Write<Error = Self::Error>
+        where
+            Self: 'a;
+
+        /// Wait for a connection from a remote host.
+        ///
+        /// Returns the connection along with the address of the remote host.
+        async fn accept(&self) -> Result<(SocketAddr, Self::Connection<'_>), Self::Error>;
+    }
+
+    impl<T: TcpAccept> TcpAccept for &T {
+        type Error = T::Error;
+        type Connection<'a>
+            = T::Connection<'a>
+        where
+            Self: 'a;
+
+        async fn accept(&self) -> Result<(SocketAddr, Self::Connection<'_>), Self::Error> {
+            T::accept(self).await
+        }
+    }
+
+    /// State for TcpListener
+    pub struct TcpListenerState<const N: usize, const TX_SZ: usize, const RX_SZ: usize> {
+        pool: Pool<([u8; TX_SZ], [u8; RX_SZ]), N>,
+    }
+
+    impl<const N: usize, const TX_SZ: usize, const RX_SZ: usize> TcpListenerState<N, TX_SZ, RX_SZ> {
+        /// Create a new `TcpListenerState`.
+        pub const fn new() -> Self {
+            Self { pool: Pool::new() }
+        }
+    }
+
+    impl<const N: usize, const TX_SZ: usize, const RX_SZ: usize> Default for TcpListenerState<N, TX_SZ, RX_SZ> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+}
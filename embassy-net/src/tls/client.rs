@@ -0,0 +1,140 @@
+//! TLS client compatible with `embedded-nal-async` traits.
+
+use core::cell::RefCell;
+use core::ptr::NonNull;
+
+use embedded_io_async::{ErrorKind, Read, Write};
+use embedded_nal_async::TcpConnect;
+
+use rand_core::{CryptoRng, RngCore};
+
+use super::{io_error, ClientConfig, Error, TlsConnection};
+use crate::tcp::client::Pool;
+
+/// TLS client connection pool compatible with `embedded-nal-async` traits.
+///
+/// Connections are opened with a `TcpConnect` implementation, usually a
+/// [`TcpClient`](crate::tcp::client::TcpClient), and secured with `config`. The pool is capable of
+/// managing up to N concurrent connections with record buffers according to TX_SZ and RX_SZ.
+/// See the [module documentation](super) for the sizes needed.
+pub struct TlsClient<'d, T, R, const N: usize, const TX_SZ: usize = 4096, const RX_SZ: usize = 16640> {
+    tcp: T,
+    state: &'d TlsClientState<N, TX_SZ, RX_SZ>,
+    config: ClientConfig<'d>,
+    rng: RefCell<R>,
+}
+
+impl<'d, T: TcpConnect, R: RngCore + CryptoRng, const N: usize, const TX_SZ: usize, const RX_SZ: usize>
+    TlsClient<'d, T, R, N, TX_SZ, RX_SZ>
+{
+    /// Create a new `TlsClient`, with `rng` giving the randoms of the handshakes.
+    pub fn new(tcp: T, state: &'d TlsClientState<N, TX_SZ, RX_SZ>, config: ClientConfig<'d>, rng: R) -> Self {
+        Self {
+            tcp,
+            state,
+            config,
+            rng: RefCell::new(rng),
+        }
+    }
+}
+
+impl<T: TcpConnect, R: RngCore + CryptoRng, const N: usize, const TX_SZ: usize, const RX_SZ: usize> TcpConnect
+    for TlsClient<'_, T, R, N, TX_SZ, RX_SZ>
+{
+    type Error = Error;
+    type Connection<'m>
+        = TlsClientConnection<'m, T::Connection<'m>, N, TX_SZ, RX_SZ>
+    where
+        Self: 'm;
+
+    async fn connect(&self, remote: core::net::SocketAddr) -> Result<Self::Connection<'_>, Self::Error> {
+        let mut random = [0; 64];
+        self.rng.borrow_mut().fill_bytes(&mut random);
+        let tcp = self.tcp.connect(remote).await.map_err(io_error)?;
+        let mut connection = TlsClientConnection::new(tcp, self.state)?;
+        connection.tls.connect_with_random(&self.config, &random).await?;
+        Ok(connection)
+    }
+}
+
+/// Opened TLS connection in a [`TlsClient`].
+///
+/// Dropping it closes the TCP connection without a `close_notify` alert, call
+/// [`close`](Self::close) first to send one.
+pub struct TlsClientConnection<'d, C: Read + Write, const N: usize, const TX_SZ: usize, const RX_SZ: usize> {
+    tls: TlsConnection<'d, C>,
+    state: &'d TlsClientState<N, TX_SZ, RX_SZ>,
+    bufs: NonNull<([u8; TX_SZ], [u8; RX_SZ])>,
+}
+
+impl<'d, C: Read + Write, const N: usize, const TX_SZ: usize, const RX_SZ: usize>
+    TlsClientConnection<'d, C, N, TX_SZ, RX_SZ>
+{
+    fn new(tcp: C, state: &'d TlsClientState<N, TX_SZ, RX_SZ>) -> Result<Self, Error> {
+        let mut bufs = state.pool.alloc().ok_or(Error::Io(ErrorKind::OutOfMemory))?;
+        Ok(Self {
+            tls: unsafe { TlsConnection::new(tcp, &mut bufs.as_mut().1, &mut bufs.as_mut().0) },
+            state,
+            bufs,
+        })
+    }
+
+    /// Close the connection, by sending a `close_notify` alert.
+    pub async fn close(&mut self) -> Result<(), Error> {
+        self.tls.close().await
+    }
+}
+
+impl<C: Read + Write, const N: usize, const TX_SZ: usize, const RX_SZ: usize> Drop
+    for TlsClientConnection<'_, C, N, TX_SZ, RX_SZ>
+{
+    fn drop(&mut self) {
+        unsafe {
+            self.state.pool.free(self.bufs);
+        }
+    }
+}
+
+impl<C: Read + Write, const N: usize, const TX_SZ: usize, const RX_SZ: usize> embedded_io_async::ErrorType
+    for TlsClientConnection<'_, C, N, TX_SZ, RX_SZ>
+{
+    type Error = Error;
+}
+
+impl<C: Read + Write, const N: usize, const TX_SZ: usize, const RX_SZ: usize> Read
+    for TlsClientConnection<'_, C, N, TX_SZ, RX_SZ>
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.tls.read(buf).await
+    }
+}
+
+impl<C: Read + Write, const N: usize, const TX_SZ: usize, const RX_SZ: usize> Write
+    for TlsClientConnection<'_, C, N, TX_SZ, RX_SZ>
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.tls.write(buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.tls.flush().await
+    }
+}
+
+/// State for TlsClient
+pub struct TlsClientState<const N: usize, const TX_SZ: usize, const RX_SZ: usize> {
+    pool: Pool<([u8; TX_SZ], [u8; RX_SZ]), N>,
+}
+
+impl<const N: usize, const TX_SZ: usize, const RX_SZ: usize> TlsClientState<N, TX_SZ, RX_SZ> {
+    /// Create a new `TlsClientState`.
+    pub const fn new() -> Self {
+        Self { pool: Pool::new() }
+    }
+}
+
+impl<const N: usize, const TX_SZ: usize, const RX_SZ: usize> Default for TlsClientState<N, TX_SZ, RX_SZ> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,147 @@
+//! Encoding and decoding of TLS wire structures.
+
+use super::Error;
+
+/// Reads big-endian integers and length-prefixed vectors from a buffer.
+#[derive(Clone)]
+pub(crate) struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub(crate) fn rest(&self) -> &'a [u8] {
+        self.buf
+    }
+
+    pub(crate) fn bytes(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if self.buf.len() < n {
+            return Err(Error::Decode);
+        }
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        Ok(unwrap!(self.bytes(N)?.try_into()))
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn u24(&mut self) -> Result<usize, Error> {
+        let b = self.bytes(3)?;
+        Ok(((b[0] as usize) << 16) | ((b[1] as usize) << 8) | b[2] as usize)
+    }
+
+    pub(crate) fn vec8(&mut self) -> Result<Reader<'a>, Error> {
+        let n = self.u8()? as usize;
+        Ok(Reader::new(self.bytes(n)?))
+    }
+
+    pub(crate) fn vec16(&mut self) -> Result<Reader<'a>, Error> {
+        let n = self.u16()? as usize;
+        Ok(Reader::new(self.bytes(n)?))
+    }
+
+    pub(crate) fn vec24(&mut self) -> Result<Reader<'a>, Error> {
+        let n = self.u24()?;
+        Ok(Reader::new(self.bytes(n)?))
+    }
+
+    /// Fail if there are bytes left.
+    pub(crate) fn finish(&self) -> Result<(), Error> {
+        match self.buf.is_empty() {
+            true => Ok(()),
+            false => Err(Error::Decode),
+        }
+    }
+}
+
+/// Writes big-endian integers and length-prefixed vectors to a buffer.
+pub(crate) struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub(crate) fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.pos
+    }
+
+    pub(crate) fn written(&self) -> &[u8] {
+        &self.buf[..self.pos]
+    }
+
+    pub(crate) fn bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+        let dst = self
+            .buf
+            .get_mut(self.pos..self.pos + data.len())
+            .ok_or(Error::BufferTooSmall)?;
+        dst.copy_from_slice(data);
+        self.pos += data.len();
+        Ok(())
+    }
+
+    /// Let `f` write into the rest of the buffer, and return how many bytes it wrote.
+    pub(crate) fn fill(&mut self, f: impl FnOnce(&mut [u8]) -> Result<usize, Error>) -> Result<(), Error> {
+        let n = f(&mut self.buf[self.pos..])?;
+        self.pos += n;
+        Ok(())
+    }
+
+    pub(crate) fn u8(&mut self, v: u8) -> Result<(), Error> {
+        self.bytes(&[v])
+    }
+
+    pub(crate) fn u16(&mut self, v: u16) -> Result<(), Error> {
+        self.bytes(&v.to_be_bytes())
+    }
+
+    pub(crate) fn u24(&mut self, v: usize) -> Result<(), Error> {
+        self.bytes(&(v as u32).to_be_bytes()[1..])
+    }
+
+    /// Write a vector with a length prefix of `N` bytes, filled by `f`.
+    pub(crate) fn vec<const N: usize>(&mut self, f: impl FnOnce(&mut Self) -> Result<(), Error>) -> Result<(), Error> {
+        let start = self.pos;
+        self.bytes(&[0; N])?;
+        f(self)?;
+        let len = (self.pos - start - N) as u32;
+        self.buf[start..start + N].copy_from_slice(&len.to_be_bytes()[4 - N..]);
+        Ok(())
+    }
+
+    /// Write a handshake message header, and the body filled by `f`.
+    pub(crate) fn handshake(
+        &mut self,
+        msg_type: u8,
+        f: impl FnOnce(&mut Self) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.u8(msg_type)?;
+        self.vec::<3>(f)
+    }
+
+    /// Write an extension, with the data filled by `f`.
+    pub(crate) fn extension(&mut self, ty: u16, f: impl FnOnce(&mut Self) -> Result<(), Error>) -> Result<(), Error> {
+        self.u16(ty)?;
+        self.vec::<2>(f)
+    }
+}
@@ -0,0 +1,26 @@
+//! ChaCha20-Poly1305 AEAD (RFC 8439).
+
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Tag};
+
+pub(crate) const KEY_LEN: usize = 32;
+pub(crate) const NONCE_LEN: usize = 12;
+pub(crate) const TAG_LEN: usize = 16;
+
+/// Encrypt `data` in place, and return the tag.
+pub(crate) fn seal(key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], aad: &[u8], data: &mut [u8]) -> [u8; TAG_LEN] {
+    let tag = unwrap!(ChaCha20Poly1305::new(key.into())
+        .encrypt_in_place_detached(nonce.into(), aad, data)
+        .ok());
+    tag.into()
+}
+
+/// Check `tag` and decrypt `data` in place. Returns `false`, leaving `data` unchanged, if the tag is wrong.
+pub(crate) fn open(key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], aad: &[u8], data: &mut [u8], tag: &[u8]) -> bool {
+    let Ok(tag) = <[u8; TAG_LEN]>::try_from(tag) else {
+        return false;
+    };
+    ChaCha20Poly1305::new(key.into())
+        .decrypt_in_place_detached(nonce.into(), aad, data, &Tag::from(tag))
+        .is_ok()
+}
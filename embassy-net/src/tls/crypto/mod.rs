@@ -0,0 +1,71 @@
+//! The cryptographic primitives of the `TLS_CHACHA20_POLY1305_SHA256` cipher suite and X25519 key exchange,
+//! from the RustCrypto and dalek crates.
+
+pub(crate) mod chacha20poly1305;
+pub(crate) mod sha256;
+pub(crate) mod x25519;
+
+/// Compare two byte strings in constant time.
+pub(crate) fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::chacha20poly1305::{open, seal};
+    use super::x25519::{x25519, BASE_POINT};
+
+    pub(crate) fn hex<const N: usize>(s: &str) -> [u8; N] {
+        let bytes: Vec<u8> = (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect();
+        bytes.try_into().unwrap()
+    }
+
+    #[test]
+    fn chacha20poly1305() {
+        // RFC 8439 section 2.8.2.
+        let key = core::array::from_fn(|i| 0x80 + i as u8);
+        let nonce = hex("070000004041424344454647");
+        let aad: [u8; 12] = hex("50515253c0c1c2c3c4c5c6c7");
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+
+        let mut data = *plaintext;
+        let tag = seal(&key, &nonce, &aad, &mut data);
+        assert_eq!(tag, hex("1ae10b594f09e26a7e902ecbd0600691"));
+        assert_eq!(data[..16], hex::<16>("d31a8d34648e60db7b86afbc53ef7ec2"));
+
+        // Tampered data or header are detected, and leave the data as is.
+        data[0] ^= 1;
+        assert!(!open(&key, &nonce, &aad, &mut data, &tag));
+        data[0] ^= 1;
+        assert!(!open(&key, &nonce, &aad[1..], &mut data, &tag));
+        assert!(!open(&key, &nonce, &aad, &mut data, &tag[1..]));
+        assert!(open(&key, &nonce, &aad, &mut data, &tag));
+        assert_eq!(&data, plaintext);
+    }
+
+    #[test]
+    fn x25519_key_agreement() {
+        // RFC 7748 section 6.1.
+        let alice = hex("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+        let bob = hex("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb");
+        let alice_public = x25519(&alice, &BASE_POINT);
+        let bob_public = x25519(&bob, &BASE_POINT);
+        assert_eq!(
+            alice_public,
+            hex("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a")
+        );
+        assert_eq!(
+            bob_public,
+            hex("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f")
+        );
+        let shared = hex("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742");
+        assert_eq!(x25519(&alice, &bob_public), shared);
+        assert_eq!(x25519(&bob, &alice_public), shared);
+    }
+}
@@ -0,0 +1,51 @@
+//! SHA-256 (FIPS 180-4), HMAC (RFC 2104) and HKDF (RFC 5869).
+
+use hkdf::Hkdf;
+use hmac::Mac;
+use sha2::Digest;
+
+pub(crate) const HASH_LEN: usize = 32;
+
+/// Incremental SHA-256 hash. Cloning it snapshots the data hashed so far.
+#[derive(Clone)]
+pub(crate) struct Sha256(sha2::Sha256);
+
+impl Sha256 {
+    pub(crate) fn new() -> Self {
+        Self(sha2::Sha256::new())
+    }
+
+    pub(crate) fn digest(data: &[u8]) -> [u8; HASH_LEN] {
+        sha2::Sha256::digest(data).into()
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub(crate) fn finalize(self) -> [u8; HASH_LEN] {
+        self.0.finalize().into()
+    }
+}
+
+/// HMAC-SHA-256.
+pub(crate) struct Hmac;
+
+impl Hmac {
+    pub(crate) fn mac(key: &[u8], data: &[u8]) -> [u8; HASH_LEN] {
+        let mut h = unwrap!(hmac::Hmac::<sha2::Sha256>::new_from_slice(key).ok());
+        h.update(data);
+        h.finalize().into_bytes().into()
+    }
+}
+
+/// HKDF-Extract.
+pub(crate) fn hkdf_extract(salt: &[u8], ikm: &[u8]) -> [u8; HASH_LEN] {
+    Hkdf::<sha2::Sha256>::extract(Some(salt), ikm).0.into()
+}
+
+/// HKDF-Expand, with the info given in parts.
+pub(crate) fn hkdf_expand(prk: &[u8], info: &[&[u8]], out: &mut [u8]) {
+    let hkdf = unwrap!(Hkdf::<sha2::Sha256>::from_prk(prk).ok());
+    unwrap!(hkdf.expand_multi_info(info, out).ok());
+}
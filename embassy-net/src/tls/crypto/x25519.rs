@@ -0,0 +1,9 @@
+//! X25519 key agreement (RFC 7748).
+
+/// The u-coordinate of the base point.
+pub(crate) const BASE_POINT: [u8; 32] = x25519_dalek::X25519_BASEPOINT_BYTES;
+
+/// Multiply the point `point` by the scalar `scalar`.
+pub(crate) fn x25519(scalar: &[u8; 32], point: &[u8; 32]) -> [u8; 32] {
+    x25519_dalek::x25519(*scalar, *point)
+}
@@ -0,0 +1,636 @@
+//! Client and server handshakes (RFC 8446 section 4).
+
+use embedded_io_async::{Read, Write};
+
+use super::codec::{Reader, Writer};
+use super::crypto::ct_eq;
+use super::crypto::sha256::{Sha256, HASH_LEN};
+use super::crypto::x25519::{x25519, BASE_POINT};
+use super::keys::{self, TrafficKeys};
+use super::*;
+
+pub(super) const CLIENT_HELLO: u8 = 1;
+pub(super) const SERVER_HELLO: u8 = 2;
+pub(super) const NEW_SESSION_TICKET: u8 = 4;
+pub(super) const ENCRYPTED_EXTENSIONS: u8 = 8;
+pub(super) const CERTIFICATE: u8 = 11;
+pub(super) const CERTIFICATE_REQUEST: u8 = 13;
+pub(super) const CERTIFICATE_VERIFY: u8 = 15;
+pub(super) const FINISHED: u8 = 20;
+pub(super) const KEY_UPDATE: u8 = 24;
+
+const EXT_SERVER_NAME: u16 = 0;
+const EXT_MAX_FRAGMENT_LENGTH: u16 = 1;
+const EXT_SUPPORTED_GROUPS: u16 = 10;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 13;
+const EXT_PRE_SHARED_KEY: u16 = 41;
+const EXT_SUPPORTED_VERSIONS: u16 = 43;
+const EXT_PSK_KEY_EXCHANGE_MODES: u16 = 45;
+const EXT_KEY_SHARE: u16 = 51;
+
+const LEGACY_VERSION: u16 = 0x0303;
+const TLS13: u16 = 0x0304;
+const TLS_CHACHA20_POLY1305_SHA256: u16 = 0x1303;
+const GROUP_X25519: u16 = 0x001d;
+const PSK_DHE_KE: u8 = 1;
+
+/// Random of a ServerHello that is actually a HelloRetryRequest.
+const HELLO_RETRY_REQUEST: [u8; 32] = [
+    0xcf, 0x21, 0xad, 0x74, 0xe5, 0x9a, 0x61, 0x11, 0xbe, 0x1d, 0x8c, 0x02, 0x1e, 0x65, 0xb8, 0x91, 0xc2, 0xa2, 0x11,
+    0x16, 0x7a, 0xbb, 0x8c, 0x5e, 0x07, 0x9e, 0x09, 0xe2, 0xc8, 0xa8, 0x33, 0x9c,
+];
+
+/// Length of the binders list of a pre_shared_key extension with one SHA-256 binder.
+const BINDERS_LEN: usize = 2 + 1 + HASH_LEN;
+
+const SERVER_SIGNATURE_CONTEXT: &[u8; 33] = b"TLS 1.3, server CertificateVerify";
+
+/// Content signed in a CertificateVerify message.
+fn signed_content(context: &[u8; 33], transcript_hash: &[u8; HASH_LEN]) -> [u8; 64 + 33 + 1 + HASH_LEN] {
+    let mut content = [0x20; 64 + 33 + 1 + HASH_LEN];
+    content[64..97].copy_from_slice(context);
+    content[97] = 0;
+    content[98..].copy_from_slice(transcript_hash);
+    content
+}
+
+/// Code of the max_fragment_length extension for a read buffer, if records must be smaller than
+/// the maximum.
+fn max_fragment_length(read_buffer_len: usize) -> Option<u8> {
+    if read_buffer_len >= MAX_PLAINTEXT_LEN + RECORD_OVERHEAD {
+        return None;
+    }
+    // Codes 1 to 4 ask for 512 to 4096 bytes.
+    let code = (1..=4)
+        .rev()
+        .find(|code| (256 << code) + RECORD_OVERHEAD <= read_buffer_len);
+    Some(code.unwrap_or(1))
+}
+
+fn parse_server_hello(body: &[u8], psk_offered: bool) -> Result<([u8; 32], bool), Error> {
+    let mut r = Reader::new(body);
+    r.u16()?;
+    if r.array::<32>()? == HELLO_RETRY_REQUEST {
+        // Only one key share is offered, and cookies aren't supported.
+        return Err(Error::HandshakeFailure);
+    }
+    if !r.vec8()?.is_empty() {
+        return Err(Error::IllegalParameter);
+    }
+    if r.u16()? != TLS_CHACHA20_POLY1305_SHA256 || r.u8()? != 0 {
+        return Err(Error::IllegalParameter);
+    }
+    let mut extensions = r.vec16()?;
+    r.finish()?;
+
+    let mut version = None;
+    let mut key_share = None;
+    let mut psk_selected = false;
+    while !extensions.is_empty() {
+        let ty = extensions.u16()?;
+        let mut data = extensions.vec16()?;
+        match ty {
+            EXT_SUPPORTED_VERSIONS => version = Some(data.u16()?),
+            EXT_KEY_SHARE => {
+                if data.u16()? != GROUP_X25519 {
+                    return Err(Error::IllegalParameter);
+                }
+                let mut key = data.vec16()?;
+                key_share = Some(key.array()?);
+                key.finish()?;
+            }
+            EXT_PRE_SHARED_KEY if psk_offered => {
+                // Only one identity is offered.
+                if data.u16()? != 0 {
+                    return Err(Error::IllegalParameter);
+                }
+                psk_selected = true;
+            }
+            _ => return Err(Error::IllegalParameter),
+        }
+        data.finish()?;
+    }
+
+    if version != Some(TLS13) {
+        return Err(Error::HandshakeFailure);
+    }
+    Ok((key_share.ok_or(Error::HandshakeFailure)?, psk_selected))
+}
+
+/// Pre-shared keys offered in a ClientHello.
+struct PskOffer<'a> {
+    identities: Reader<'a>,
+    binders: Reader<'a>,
+    /// Offset of the binders in the ClientHello, which is hashed up to there.
+    binders_offset: usize,
+}
+
+/// The parts of a ClientHello a server needs.
+struct ClientHello<'a> {
+    session_id: &'a [u8],
+    cipher_suite: bool,
+    tls13: bool,
+    server_name: Option<&'a [u8]>,
+    signature_schemes: &'a [u8],
+    key_share: Option<[u8; 32]>,
+    psk_dhe_ke: bool,
+    psk: Option<PskOffer<'a>>,
+}
+
+impl<'a> ClientHello<'a> {
+    /// Parse a ClientHello message, including its header.
+    fn parse(msg: &'a [u8]) -> Result<Self, Error> {
+        let mut r = Reader::new(&msg[4..]);
+        r.u16()?;
+        r.bytes(32)?;
+        let session_id = r.vec8()?.rest();
+        if session_id.len() > 32 {
+            return Err(Error::Decode);
+        }
+        let mut suites = r.vec16()?;
+        let mut cipher_suite = false;
+        while !suites.is_empty() {
+            cipher_suite |= suites.u16()? == TLS_CHACHA20_POLY1305_SHA256;
+        }
+        // Legacy compression methods.
+        r.vec8()?;
+        let mut extensions = r.vec16()?;
+        r.finish()?;
+
+        let mut hello = Self {
+            session_id,
+            cipher_suite,
+            tls13: false,
+            server_name: None,
+            signature_schemes: &[],
+            key_share: None,
+            psk_dhe_ke: false,
+            psk: None,
+        };
+        while !extensions.is_empty() {
+            let ty = extensions.u16()?;
+            let mut data = extensions.vec16()?;
+            match ty {
+                EXT_SERVER_NAME => {
+                    let mut names = data.vec16()?;
+                    if names.u8()? == 0 {
+                        hello.server_name = Some(names.vec16()?.rest());
+                    }
+                }
+                EXT_SUPPORTED_VERSIONS => {
+                    let mut versions = data.vec8()?;
+                    while !versions.is_empty() {
+                        hello.tls13 |= versions.u16()? == TLS13;
+                    }
+                }
+                EXT_SIGNATURE_ALGORITHMS => hello.signature_schemes = data.vec16()?.rest(),
+                EXT_KEY_SHARE => {
+                    let mut shares = data.vec16()?;
+                    while !shares.is_empty() {
+                        let group = shares.u16()?;
+                        let key = shares.vec16()?.rest();
+                        if group == GROUP_X25519 {
+                            hello.key_share = Some(key.try_into().map_err(|_| Error::IllegalParameter)?);
+                        }
+                    }
+                }
+                EXT_PSK_KEY_EXCHANGE_MODES => hello.psk_dhe_ke = data.vec8()?.rest().contains(&PSK_DHE_KE),
+                EXT_PRE_SHARED_KEY => {
+                    // The pre_shared_key extension must be the last one.
+                    if !extensions.is_empty() {
+                        return Err(Error::IllegalParameter);
+                    }
+                    let identities = data.vec16()?;
+                    let binders = data.vec16()?;
+                    hello.psk = Some(PskOffer {
+                        identities,
+                        binders_offset: msg.len() - 2 - binders.rest().len(),
+                        binders,
+                    });
+                }
+                _ => continue,
+            }
+            data.finish()?;
+        }
+        Ok(hello)
+    }
+
+    fn supports_signature_scheme(&self, scheme: SignatureScheme) -> bool {
+        self.signature_schemes
+            .chunks_exact(2)
+            .any(|s| u16::from_be_bytes([s[0], s[1]]) == scheme.0)
+    }
+}
+
+/// Find the first offered pre-shared key known by `provider`, and check its binder.
+///
+/// Returns the index of the key, and the key.
+fn select_psk<'k>(
+    provider: &'k dyn PskProvider,
+    offer: &PskOffer<'_>,
+    client_hello: &[u8],
+) -> Result<Option<(u16, &'k [u8])>, Error> {
+    let mut identities = offer.identities.clone();
+    let mut binders = offer.binders.clone();
+    let mut index = 0;
+    while !identities.is_empty() {
+        let identity = identities.vec16()?.rest();
+        // Obfuscated ticket age, meaningless for external keys.
+        identities.bytes(4)?;
+        let binder = binders.vec8()?.rest();
+        if let Some(key) = provider.key(identity) {
+            let binder_key = keys::derive_secret(&keys::early_secret(Some(key)), b"ext binder", &keys::empty_hash());
+            let expected = keys::finished(&binder_key, &Sha256::digest(&client_hello[..offer.binders_offset]));
+            if !ct_eq(binder, &expected) {
+                return Err(Error::DecryptError);
+            }
+            return Ok(Some((index, key)));
+        }
+        index += 1;
+    }
+    Ok(None)
+}
+
+impl<T: Read + Write> TlsConnection<'_, T> {
+    /// Write a handshake message to the next record, starting a new record if it doesn't fit.
+    async fn write_handshake(
+        &mut self,
+        transcript: &mut Sha256,
+        f: impl Fn(&mut Writer<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        loop {
+            let mut w = Writer::new(self.record_buffer());
+            match f(&mut w) {
+                Ok(()) => {
+                    transcript.update(w.written());
+                    self.tx.len += w.len();
+                    return Ok(());
+                }
+                Err(Error::BufferTooSmall) if self.tx.len > 0 => self.flush_record(CONTENT_HANDSHAKE).await?,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Write part of a handshake message, split over as many records as needed.
+    async fn push_handshake(&mut self, transcript: &mut Sha256, mut data: &[u8]) -> Result<(), Error> {
+        transcript.update(data);
+        while !data.is_empty() {
+            if self.record_space() == 0 {
+                self.flush_record(CONTENT_HANDSHAKE).await?;
+            }
+            let n = data.len().min(self.record_space());
+            self.record_buffer()[..n].copy_from_slice(&data[..n]);
+            self.tx.len += n;
+            data = &data[n..];
+        }
+        Ok(())
+    }
+
+    /// Send the pending handshake messages.
+    async fn flush_handshake(&mut self) -> Result<(), Error> {
+        self.flush_record(CONTENT_HANDSHAKE).await?;
+        self.transport.flush().await.map_err(io_error)
+    }
+
+    /// Fail if a key change doesn't happen at a record boundary.
+    fn check_record_boundary(&self) -> Result<(), Error> {
+        match self.rx.handshake_len {
+            0 => Ok(()),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    /// Receive the next handshake message, which must be of type `expected`.
+    async fn expect_handshake(&mut self, expected: u8) -> Result<usize, Error> {
+        match self.read_handshake(0).await? {
+            (ty, len) if ty == expected => Ok(len),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    /// Receive a Finished message, and check it against the transcript.
+    async fn read_finished(&mut self, transcript: &mut Sha256, base_key: &keys::Secret) -> Result<(), Error> {
+        let len = self.expect_handshake(FINISHED).await?;
+        let expected = keys::finished(base_key, &transcript.clone().finalize());
+        if !ct_eq(&self.rx.buf[4..len], &expected) {
+            return Err(Error::DecryptError);
+        }
+        transcript.update(&self.rx.buf[..len]);
+        self.consume_handshake(len);
+        self.check_record_boundary()
+    }
+
+    pub(super) async fn client_handshake(&mut self, config: &ClientConfig<'_>, random: &[u8; 64]) -> Result<(), Error> {
+        if self.tx.max_plaintext == 0 {
+            return Err(Error::BufferTooSmall);
+        }
+        let private_key = unwrap!(random[32..].try_into());
+        let public_key = x25519(&private_key, &BASE_POINT);
+        let schemes = config
+            .verifier
+            .map_or(DEFAULT_SIGNATURE_SCHEMES, |v| v.signature_schemes());
+        let max_fragment_length = max_fragment_length(self.rx.buf.len());
+
+        // ClientHello
+        let mut w = Writer::new(self.record_buffer());
+        w.handshake(CLIENT_HELLO, |w| {
+            w.u16(LEGACY_VERSION)?;
+            w.bytes(&random[..32])?;
+            w.vec::<1>(|_| Ok(()))?;
+            w.vec::<2>(|w| w.u16(TLS_CHACHA20_POLY1305_SHA256))?;
+            w.vec::<1>(|w| w.u8(0))?;
+            w.vec::<2>(|w| {
+                if let Some(name) = config.server_name {
+                    w.extension(EXT_SERVER_NAME, |w| {
+                        w.vec::<2>(|w| {
+                            w.u8(0)?;
+                            w.vec::<2>(|w| w.bytes(name.as_bytes()))
+                        })
+                    })?;
+                }
+                if let Some(code) = max_fragment_length {
+                    w.extension(EXT_MAX_FRAGMENT_LENGTH, |w| w.u8(code))?;
+                }
+                w.extension(EXT_SUPPORTED_GROUPS, |w| w.vec::<2>(|w| w.u16(GROUP_X25519)))?;
+                w.extension(EXT_SIGNATURE_ALGORITHMS, |w| {
+                    w.vec::<2>(|w| schemes.iter().try_for_each(|s| w.u16(s.0)))
+                })?;
+                w.extension(EXT_SUPPORTED_VERSIONS, |w| w.vec::<1>(|w| w.u16(TLS13)))?;
+                w.extension(EXT_KEY_SHARE, |w| {
+                    w.vec::<2>(|w| {
+                        w.u16(GROUP_X25519)?;
+                        w.vec::<2>(|w| w.bytes(&public_key))
+                    })
+                })?;
+                if let Some(psk) = &config.psk {
+                    w.extension(EXT_PSK_KEY_EXCHANGE_MODES, |w| w.vec::<1>(|w| w.u8(PSK_DHE_KE)))?;
+                    // The binder is filled in once the rest of the message is known.
+                    w.extension(EXT_PRE_SHARED_KEY, |w| {
+                        w.vec::<2>(|w| {
+                            w.vec::<2>(|w| w.bytes(psk.identity))?;
+                            w.bytes(&[0; 4])
+                        })?;
+                        w.vec::<2>(|w| w.vec::<1>(|w| w.bytes(&[0; HASH_LEN])))
+                    })?;
+                }
+                Ok(())
+            })
+        })?;
+        let len = w.len();
+        if let Some(psk) = &config.psk {
+            let hello = &mut self.record_buffer()[..len];
+            let binder_key =
+                keys::derive_secret(&keys::early_secret(Some(psk.key)), b"ext binder", &keys::empty_hash());
+            let binder = keys::finished(&binder_key, &Sha256::digest(&hello[..len - BINDERS_LEN]));
+            hello[len - HASH_LEN..].copy_from_slice(&binder);
+        }
+        let mut transcript = Sha256::new();
+        transcript.update(&self.record_buffer()[..len]);
+        self.tx.len = len;
+        self.flush_handshake().await?;
+
+        // ServerHello
+        let len = self.expect_handshake(SERVER_HELLO).await?;
+        let (server_key, psk_selected) = parse_server_hello(&self.rx.buf[4..len], config.psk.is_some())?;
+        transcript.update(&self.rx.buf[..len]);
+        self.consume_handshake(len);
+        self.check_record_boundary()?;
+
+        let shared_secret = x25519(&private_key, &server_key);
+        if shared_secret == [0; 32] {
+            return Err(Error::IllegalParameter);
+        }
+        let psk = config.psk.filter(|_| psk_selected).map(|psk| psk.key);
+        let (handshake_secret, client_secret, server_secret) =
+            keys::handshake_secrets(&keys::early_secret(psk), &shared_secret, &transcript.clone().finalize());
+        self.rx.keys = Some(TrafficKeys::new(server_secret));
+
+        // EncryptedExtensions
+        let len = self.expect_handshake(ENCRYPTED_EXTENSIONS).await?;
+        let mut r = Reader::new(&self.rx.buf[4..len]);
+        let mut extensions = r.vec16()?;
+        r.finish()?;
+        while !extensions.is_empty() {
+            let ty = extensions.u16()?;
+            let mut data = extensions.vec16()?;
+            if ty == EXT_MAX_FRAGMENT_LENGTH {
+                let code = data.u8()?;
+                if Some(code) != max_fragment_length {
+                    return Err(Error::IllegalParameter);
+                }
+                self.tx.max_plaintext = self.tx.max_plaintext.min(256 << code);
+            }
+        }
+        transcript.update(&self.rx.buf[..len]);
+        self.consume_handshake(len);
+
+        // CertificateRequest, Certificate, CertificateVerify
+        let mut certificate_request = None;
+        if !psk_selected {
+            let (mut ty, mut len) = self.read_handshake(0).await?;
+            if ty == CERTIFICATE_REQUEST {
+                let mut r = Reader::new(&self.rx.buf[4..len]);
+                let context = r.vec8()?.rest();
+                certificate_request = Some(unwrap!(heapless::Vec::<u8, 255>::from_slice(context)));
+                transcript.update(&self.rx.buf[..len]);
+                self.consume_handshake(len);
+                (ty, len) = self.read_handshake(0).await?;
+            }
+            if ty != CERTIFICATE {
+                return Err(Error::UnexpectedMessage);
+            }
+            let verifier = config.verifier.ok_or(Error::BadCertificate)?;
+
+            let mut r = Reader::new(&self.rx.buf[4..len]);
+            if !r.vec8()?.is_empty() {
+                return Err(Error::IllegalParameter);
+            }
+            let certificates = Certificates::new(r.vec24()?.rest())?;
+            r.finish()?;
+            // With an empty context, the first certificate starts after the message header, the
+            // context length and the list and certificate lengths.
+            let end_entity = 4 + 1 + 3 + 3;
+            let end_entity = end_entity..end_entity + certificates.clone().next().ok_or(Error::Decode)?.len();
+            verifier.verify_certificate(config.server_name, certificates)?;
+            transcript.update(&self.rx.buf[..len]);
+            let signed = signed_content(SERVER_SIGNATURE_CONTEXT, &transcript.clone().finalize());
+
+            // Keep the Certificate message in the buffer, to check the signature with it.
+            let certificate_len = len;
+            let (ty, len) = self.read_handshake(certificate_len).await?;
+            if ty != CERTIFICATE_VERIFY {
+                return Err(Error::UnexpectedMessage);
+            }
+            let message = &self.rx.buf[certificate_len..certificate_len + len];
+            let mut r = Reader::new(&message[4..]);
+            let scheme = SignatureScheme(r.u16()?);
+            let signature = r.vec16()?.rest();
+            r.finish()?;
+            if !verifier.signature_schemes().contains(&scheme) {
+                return Err(Error::IllegalParameter);
+            }
+            verifier.verify_signature(&self.rx.buf[end_entity], scheme, &signed, signature)?;
+            transcript.update(message);
+            self.consume_handshake(certificate_len + len);
+        }
+
+        // Finished
+        self.read_finished(&mut transcript, &server_secret).await?;
+        let (client_app_secret, server_app_secret) =
+            keys::application_secrets(&handshake_secret, &transcript.clone().finalize());
+        self.rx.keys = Some(TrafficKeys::new(server_app_secret));
+
+        self.tx.keys = Some(TrafficKeys::new(client_secret));
+        if let Some(context) = certificate_request {
+            // Client certificates aren't supported, send an empty one.
+            self.write_handshake(&mut transcript, |w| {
+                w.handshake(CERTIFICATE, |w| {
+                    w.vec::<1>(|w| w.bytes(&context))?;
+                    w.vec::<3>(|_| Ok(()))
+                })
+            })
+            .await?;
+        }
+        let verify_data = keys::finished(&client_secret, &transcript.clone().finalize());
+        self.write_handshake(&mut transcript, |w| w.handshake(FINISHED, |w| w.bytes(&verify_data)))
+            .await?;
+        self.flush_handshake().await?;
+        self.tx.keys = Some(TrafficKeys::new(client_app_secret));
+        Ok(())
+    }
+
+    pub(super) async fn server_handshake(&mut self, config: &ServerConfig<'_>, random: &[u8; 64]) -> Result<(), Error> {
+        if self.tx.max_plaintext == 0 {
+            return Err(Error::BufferTooSmall);
+        }
+
+        // ClientHello
+        let len = self.expect_handshake(CLIENT_HELLO).await?;
+        let hello = ClientHello::parse(&self.rx.buf[..len])?;
+        if !hello.tls13 || !hello.cipher_suite {
+            return Err(Error::HandshakeFailure);
+        }
+        // Without a X25519 key share, the server should ask for one with a HelloRetryRequest,
+        // which isn't supported.
+        let client_key = hello.key_share.ok_or(Error::HandshakeFailure)?;
+        let psk = match (config.psk, &hello.psk) {
+            (Some(provider), Some(offer)) if hello.psk_dhe_ke => select_psk(provider, offer, &self.rx.buf[..len])?,
+            _ => None,
+        };
+        let signer = match psk {
+            Some(_) => None,
+            None => match config.signer {
+                Some(signer) if hello.supports_signature_scheme(signer.signature_scheme()) => Some(signer),
+                _ => return Err(Error::HandshakeFailure),
+            },
+        };
+        let server_name_received = hello.server_name.is_some();
+        if let Some(name) = hello.server_name {
+            let name = core::str::from_utf8(name).map_err(|_| Error::IllegalParameter)?;
+            self.server_name = name.try_into().map_err(|_| Error::IllegalParameter)?;
+        }
+        let session_id = unwrap!(heapless::Vec::<u8, 32>::from_slice(hello.session_id));
+        let mut transcript = Sha256::new();
+        transcript.update(&self.rx.buf[..len]);
+        self.consume_handshake(len);
+        self.check_record_boundary()?;
+
+        // ServerHello
+        let private_key = unwrap!(random[32..].try_into());
+        let public_key = x25519(&private_key, &BASE_POINT);
+        self.write_handshake(&mut transcript, |w| {
+            w.handshake(SERVER_HELLO, |w| {
+                w.u16(LEGACY_VERSION)?;
+                w.bytes(&random[..32])?;
+                w.vec::<1>(|w| w.bytes(&session_id))?;
+                w.u16(TLS_CHACHA20_POLY1305_SHA256)?;
+                w.u8(0)?;
+                w.vec::<2>(|w| {
+                    w.extension(EXT_SUPPORTED_VERSIONS, |w| w.u16(TLS13))?;
+                    w.extension(EXT_KEY_SHARE, |w| {
+                        w.u16(GROUP_X25519)?;
+                        w.vec::<2>(|w| w.bytes(&public_key))
+                    })?;
+                    if let Some((index, _)) = psk {
+                        w.extension(EXT_PRE_SHARED_KEY, |w| w.u16(index))?;
+                    }
+                    Ok(())
+                })
+            })
+        })
+        .await?;
+        self.flush_record(CONTENT_HANDSHAKE).await?;
+        // Clients in middlebox compatibility mode, sending a session ID, expect a ChangeCipherSpec.
+        if !session_id.is_empty() {
+            self.tx.buf[HEADER_LEN] = 1;
+            self.tx.len = 1;
+            self.flush_record(CONTENT_CHANGE_CIPHER_SPEC).await?;
+        }
+
+        let shared_secret = x25519(&private_key, &client_key);
+        if shared_secret == [0; 32] {
+            return Err(Error::IllegalParameter);
+        }
+        let (handshake_secret, client_secret, server_secret) = keys::handshake_secrets(
+            &keys::early_secret(psk.map(|(_, key)| key)),
+            &shared_secret,
+            &transcript.clone().finalize(),
+        );
+        self.tx.keys = Some(TrafficKeys::new(server_secret));
+        self.rx.keys = Some(TrafficKeys::new(client_secret));
+
+        // EncryptedExtensions
+        self.write_handshake(&mut transcript, |w| {
+            w.handshake(ENCRYPTED_EXTENSIONS, |w| {
+                w.vec::<2>(|w| match server_name_received {
+                    true => w.extension(EXT_SERVER_NAME, |_| Ok(())),
+                    false => Ok(()),
+                })
+            })
+        })
+        .await?;
+
+        // Certificate, CertificateVerify
+        if let Some(signer) = signer {
+            let certificates = signer.certificates();
+            let list_len: usize = certificates.iter().map(|c| 3 + c.len() + 2).sum();
+            let mut header = [0; 8];
+            let mut w = Writer::new(&mut header);
+            w.u8(CERTIFICATE)?;
+            w.u24(1 + 3 + list_len)?;
+            w.u8(0)?;
+            w.u24(list_len)?;
+            self.push_handshake(&mut transcript, &header).await?;
+            for certificate in certificates {
+                let mut len = [0; 3];
+                Writer::new(&mut len).u24(certificate.len())?;
+                self.push_handshake(&mut transcript, &len).await?;
+                self.push_handshake(&mut transcript, certificate).await?;
+                self.push_handshake(&mut transcript, &[0, 0]).await?;
+            }
+
+            let signed = signed_content(SERVER_SIGNATURE_CONTEXT, &transcript.clone().finalize());
+            self.write_handshake(&mut transcript, |w| {
+                w.handshake(CERTIFICATE_VERIFY, |w| {
+                    w.u16(signer.signature_scheme().0)?;
+                    w.vec::<2>(|w| w.fill(|signature| signer.sign(&signed, signature)))
+                })
+            })
+            .await?;
+        }
+
+        // Finished
+        let verify_data = keys::finished(&server_secret, &transcript.clone().finalize());
+        self.write_handshake(&mut transcript, |w| w.handshake(FINISHED, |w| w.bytes(&verify_data)))
+            .await?;
+        self.flush_handshake().await?;
+        let (client_app_secret, server_app_secret) =
+            keys::application_secrets(&handshake_secret, &transcript.clone().finalize());
+        self.tx.keys = Some(TrafficKeys::new(server_app_secret));
+
+        self.read_finished(&mut transcript, &client_secret).await?;
+        self.rx.keys = Some(TrafficKeys::new(client_app_secret));
+        Ok(())
+    }
+}
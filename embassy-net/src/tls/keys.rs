@@ -0,0 +1,197 @@
+//! TLS 1.3 key schedule (RFC 8446 section 7), for SHA-256.
+
+use super::crypto::chacha20poly1305::{self, KEY_LEN, NONCE_LEN, TAG_LEN};
+use super::crypto::sha256::{hkdf_expand, hkdf_extract, Hmac, Sha256, HASH_LEN};
+
+pub(crate) type Secret = [u8; HASH_LEN];
+
+/// HKDF-Expand-Label.
+pub(crate) fn expand_label(secret: &[u8], label: &[u8], context: &[u8], out: &mut [u8]) {
+    let len = (out.len() as u16).to_be_bytes();
+    let label_len = [(6 + label.len()) as u8];
+    let context_len = [context.len() as u8];
+    hkdf_expand(
+        secret,
+        &[&len, &label_len, b"tls13 ", label, &context_len, context],
+        out,
+    );
+}
+
+/// Derive-Secret, with the hash of the transcript.
+pub(crate) fn derive_secret(secret: &Secret, label: &[u8], transcript_hash: &[u8; HASH_LEN]) -> Secret {
+    let mut out = [0; HASH_LEN];
+    expand_label(secret, label, transcript_hash, &mut out);
+    out
+}
+
+pub(crate) fn empty_hash() -> [u8; HASH_LEN] {
+    Sha256::digest(&[])
+}
+
+pub(crate) fn early_secret(psk: Option<&[u8]>) -> Secret {
+    hkdf_extract(&[0; HASH_LEN], psk.unwrap_or(&[0; HASH_LEN]))
+}
+
+/// Secret of the next stage of the key schedule, from the secret of the previous one.
+pub(crate) fn next_secret(secret: &Secret, ikm: &[u8]) -> Secret {
+    hkdf_extract(&derive_secret(secret, b"derived", &empty_hash()), ikm)
+}
+
+/// The verify data of a Finished message, or of a PSK binder.
+pub(crate) fn finished(base_key: &Secret, transcript_hash: &[u8; HASH_LEN]) -> [u8; HASH_LEN] {
+    let mut key = [0; HASH_LEN];
+    expand_label(base_key, b"finished", &[], &mut key);
+    Hmac::mac(&key, transcript_hash)
+}
+
+/// Keys protecting the records sent in one direction.
+pub(crate) struct TrafficKeys {
+    secret: Secret,
+    key: [u8; KEY_LEN],
+    iv: [u8; NONCE_LEN],
+    seq: u64,
+}
+
+impl TrafficKeys {
+    pub(crate) fn new(secret: Secret) -> Self {
+        let mut key = [0; KEY_LEN];
+        let mut iv = [0; NONCE_LEN];
+        expand_label(&secret, b"key", &[], &mut key);
+        expand_label(&secret, b"iv", &[], &mut iv);
+        Self {
+            secret,
+            key,
+            iv,
+            seq: 0,
+        }
+    }
+
+    /// Switch to the next generation of keys, after a KeyUpdate.
+    pub(crate) fn update(&mut self) {
+        let mut next = [0; HASH_LEN];
+        expand_label(&self.secret, b"traffic upd", &[], &mut next);
+        *self = Self::new(next);
+    }
+
+    fn nonce(&mut self) -> [u8; NONCE_LEN] {
+        let mut nonce = self.iv;
+        for (n, s) in nonce[4..].iter_mut().zip(self.seq.to_be_bytes()) {
+            *n ^= s;
+        }
+        self.seq += 1;
+        nonce
+    }
+
+    /// Encrypt `data` in place, and return the tag.
+    pub(crate) fn seal(&mut self, header: &[u8], data: &mut [u8]) -> [u8; TAG_LEN] {
+        let nonce = self.nonce();
+        chacha20poly1305::seal(&self.key, &nonce, header, data)
+    }
+
+    /// Decrypt `data` in place. Returns `false` if the record isn't authentic.
+    pub(crate) fn open(&mut self, header: &[u8], data: &mut [u8], tag: &[u8]) -> bool {
+        let nonce = self.nonce();
+        chacha20poly1305::open(&self.key, &nonce, header, data, tag)
+    }
+}
+
+/// The handshake secret, and the client and server handshake traffic secrets.
+pub(crate) fn handshake_secrets(
+    early_secret: &Secret,
+    shared_secret: &[u8],
+    transcript_hash: &[u8; HASH_LEN],
+) -> (Secret, Secret, Secret) {
+    let secret = next_secret(early_secret, shared_secret);
+    let client = derive_secret(&secret, b"c hs traffic", transcript_hash);
+    let server = derive_secret(&secret, b"s hs traffic", transcript_hash);
+    (secret, client, server)
+}
+
+/// The client and server application traffic secrets.
+pub(crate) fn application_secrets(handshake_secret: &Secret, transcript_hash: &[u8; HASH_LEN]) -> (Secret, Secret) {
+    let secret = next_secret(handshake_secret, &[0; HASH_LEN]);
+    let client = derive_secret(&secret, b"c ap traffic", transcript_hash);
+    let server = derive_secret(&secret, b"s ap traffic", transcript_hash);
+    (client, server)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::crypto::tests::hex;
+
+    // The simple 1-RTT handshake of RFC 8448 section 3.
+    const SHARED_SECRET: &str = "8bd4054fb55b9d63fdfbacf9f04b9f0d35e6d63f537563efd46272900f89492d";
+    const HELLO_HASH: &str = "860c06edc07858ee8e78f0e7428c58edd6b43f2ca3e6e95f02ed063cf0e1cad8";
+
+    #[test]
+    fn key_schedule() {
+        let early = early_secret(None);
+        assert_eq!(
+            early,
+            hex("33ad0a1c607ec03b09e6cd9893680ce210adf300aa1f2660e1b22e10f170f92a")
+        );
+        assert_eq!(
+            derive_secret(&early, b"derived", &empty_hash()),
+            hex("6f2615a108c702c5678f54fc9dbab69716c076189c48250cebeac3576c3611ba")
+        );
+
+        let (handshake, client, server) = handshake_secrets(&early, &hex::<32>(SHARED_SECRET), &hex(HELLO_HASH));
+        assert_eq!(
+            handshake,
+            hex("1dc826e93606aa6fdc0aadc12f741b01046aa6b99f691ed221a9f0ca043fbeac")
+        );
+        assert_eq!(
+            client,
+            hex("b3eddb126e067f35a780b3abf45e2d8f3b1a950738f52e9600746a0e27a55a21")
+        );
+        assert_eq!(
+            server,
+            hex("b67b7d690cc16c4e75e54213cb2d37b4e9c912bcded9105d42befd59d391ad38")
+        );
+
+        assert_eq!(
+            next_secret(&handshake, &[0; HASH_LEN]),
+            hex("18df06843d13a08bf2a449844c5f8a478001bc4d4c627984d5a41da8d0402919")
+        );
+    }
+
+    #[test]
+    fn traffic_keys() {
+        let server = hex("b67b7d690cc16c4e75e54213cb2d37b4e9c912bcded9105d42befd59d391ad38");
+        let mut finished_key = [0; HASH_LEN];
+        expand_label(&server, b"finished", &[], &mut finished_key);
+        assert_eq!(
+            finished_key,
+            hex("008d3b66f816ea559f96b537e885c31fc068bf492c652f01f288a1d8cdc19fc8")
+        );
+        let keys = TrafficKeys::new(server);
+        assert_eq!(keys.iv, hex("5d313eb2671276ee13000b30"));
+    }
+
+    #[test]
+    fn seal_and_open() {
+        let secret = [7; HASH_LEN];
+        let (mut tx, mut rx) = (TrafficKeys::new(secret), TrafficKeys::new(secret));
+        for _ in 0..3 {
+            let mut data = *b"hello";
+            let tag = tx.seal(b"header", &mut data);
+            assert!(rx.open(b"header", &mut data, &tag));
+            assert_eq!(&data, b"hello");
+        }
+
+        // Records out of sequence don't decrypt.
+        let mut data = *b"hello";
+        tx.seal(b"header", &mut data);
+        let tag = tx.seal(b"header", &mut data);
+        assert!(!rx.open(b"header", &mut data, &tag));
+
+        // Both sides switch to the same keys after a KeyUpdate.
+        let (mut tx, mut rx) = (TrafficKeys::new(secret), TrafficKeys::new(secret));
+        tx.update();
+        rx.update();
+        assert_eq!(tx.seq, 0);
+        let tag = tx.seal(&[], &mut data);
+        assert!(rx.open(&[], &mut data, &tag));
+    }
+}
@@ -0,0 +1,899 @@
+//! TLS 1.3 client and server connections.
+//!
+//! [`TlsConnection`] secures any transport implementing the `embedded-io-async` traits, usually a
+//! [`TcpSocket`](crate::tcp::TcpSocket) or a [`TcpConnection`](crate::tcp::client::TcpConnection).
+//! [`TlsClient`] wraps a [`TcpClient`](crate::tcp::client::TcpClient) into an `embedded-nal-async`
+//! `TcpConnect` implementation, so existing clients can use TLS unchanged.
+//!
+//! The only cipher suite is `TLS_CHACHA20_POLY1305_SHA256`, with X25519 key exchange, optionally
+//! combined with an external pre-shared key. There's no session resumption, 0-RTT data, or
+//! client authentication with certificates.
+//!
+//! Certificates aren't parsed here: checking the peer's certificate chain and verifying its
+//! signature is up to a [`CertificateVerifier`], and signing for a server certificate is up to
+//! a [`CertificateSigner`].
+//!
+//! Records are decrypted and encrypted in place, in buffers provided by the caller. The read
+//! buffer must fit a whole record, and the largest handshake message (usually the server's
+//! certificate chain). Servers send records of up to 16 KiB, plus 22 bytes of overhead, unless
+//! the read buffer is smaller, in which case the client asks for smaller records with the
+//! `max_fragment_length` extension. The write buffer sets the size of the records sent.
+
+mod client;
+mod codec;
+mod crypto;
+mod handshake;
+mod keys;
+
+pub use client::{TlsClient, TlsClientConnection, TlsClientState};
+use codec::Reader;
+use embedded_io_async::{ErrorKind, Read, Write};
+use keys::TrafficKeys;
+use rand_core::{CryptoRng, RngCore};
+
+/// Size of a record header.
+const HEADER_LEN: usize = 5;
+/// Bytes added to the plaintext of a record: the header, the content type and the AEAD tag.
+const RECORD_OVERHEAD: usize = HEADER_LEN + 1 + crypto::chacha20poly1305::TAG_LEN;
+/// Largest record plaintext allowed.
+const MAX_PLAINTEXT_LEN: usize = 1 << 14;
+/// Largest record ciphertext allowed.
+const MAX_CIPHERTEXT_LEN: usize = MAX_PLAINTEXT_LEN + 256;
+
+const CONTENT_CHANGE_CIPHER_SPEC: u8 = 20;
+const CONTENT_ALERT: u8 = 21;
+const CONTENT_HANDSHAKE: u8 = 22;
+const CONTENT_APPLICATION_DATA: u8 = 23;
+
+const ALERT_CLOSE_NOTIFY: u8 = 0;
+
+/// Error returned by TLS connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The transport failed.
+    Io(ErrorKind),
+    /// The transport was closed before the handshake completed, or without a `close_notify` alert.
+    ConnectionClosed,
+    /// The connection isn't established, or was already closed.
+    InvalidState,
+    /// A record or handshake message doesn't fit in the buffers.
+    BufferTooSmall,
+    /// A message couldn't be decoded.
+    Decode,
+    /// A message was received out of order.
+    UnexpectedMessage,
+    /// No acceptable set of parameters could be negotiated with the peer.
+    HandshakeFailure,
+    /// The peer sent a value that isn't allowed.
+    IllegalParameter,
+    /// A record failed authentication.
+    BadRecordMac,
+    /// A Finished message or PSK binder didn't match.
+    DecryptError,
+    /// The peer's certificate was rejected, or there's no verifier for it.
+    BadCertificate,
+    /// The peer's certificate signature was rejected, or signing failed.
+    BadSignature,
+    /// The peer sent a fatal alert, with this description.
+    Alert(u8),
+}
+
+impl Error {
+    /// Description of the alert sent to the peer for this error.
+    fn alert(&self) -> Option<u8> {
+        match self {
+            Error::Io(_) | Error::ConnectionClosed | Error::InvalidState | Error::Alert(_) => None,
+            Error::UnexpectedMessage => Some(10),
+            Error::BadRecordMac => Some(20),
+            Error::HandshakeFailure => Some(40),
+            Error::BadCertificate => Some(42),
+            Error::IllegalParameter => Some(47),
+            Error::Decode => Some(50),
+            Error::DecryptError | Error::BadSignature => Some(51),
+            Error::BufferTooSmall => Some(80),
+        }
+    }
+}
+
+impl embedded_io_async::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Io(kind) => *kind,
+            Error::ConnectionClosed => ErrorKind::ConnectionAborted,
+            Error::InvalidState => ErrorKind::NotConnected,
+            Error::BufferTooSmall => ErrorKind::OutOfMemory,
+            _ => ErrorKind::InvalidData,
+        }
+    }
+}
+
+fn io_error<E: embedded_io_async::Error>(e: E) -> Error {
+    Error::Io(e.kind())
+}
+
+/// TLS signature scheme, as registered by IANA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SignatureScheme(pub u16);
+
+impl SignatureScheme {
+    /// RSASSA-PKCS1-v1_5 with SHA-256. Only for signatures in certificates.
+    pub const RSA_PKCS1_SHA256: Self = Self(0x0401);
+    /// RSASSA-PKCS1-v1_5 with SHA-384. Only for signatures in certificates.
+    pub const RSA_PKCS1_SHA384: Self = Self(0x0501);
+    /// ECDSA with P-256 and SHA-256.
+    pub const ECDSA_SECP256R1_SHA256: Self = Self(0x0403);
+    /// ECDSA with P-384 and SHA-384.
+    pub const ECDSA_SECP384R1_SHA384: Self = Self(0x0503);
+    /// RSASSA-PSS with SHA-256, for RSA keys.
+    pub const RSA_PSS_RSAE_SHA256: Self = Self(0x0804);
+    /// RSASSA-PSS with SHA-384, for RSA keys.
+    pub const RSA_PSS_RSAE_SHA384: Self = Self(0x0805);
+    /// RSASSA-PSS with SHA-512, for RSA keys.
+    pub const RSA_PSS_RSAE_SHA512: Self = Self(0x0806);
+    /// Ed25519.
+    pub const ED25519: Self = Self(0x0807);
+}
+
+const DEFAULT_SIGNATURE_SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::ECDSA_SECP256R1_SHA256,
+    SignatureScheme::ECDSA_SECP384R1_SHA384,
+    SignatureScheme::ED25519,
+    SignatureScheme::RSA_PSS_RSAE_SHA256,
+    SignatureScheme::RSA_PSS_RSAE_SHA384,
+    SignatureScheme::RSA_PSS_RSAE_SHA512,
+    SignatureScheme::RSA_PKCS1_SHA256,
+    SignatureScheme::RSA_PKCS1_SHA384,
+];
+
+/// Certificate chain sent by the peer.
+///
+/// Iterates over the DER encoded certificates, starting with the peer's own.
+#[derive(Clone)]
+pub struct Certificates<'a> {
+    reader: Reader<'a>,
+}
+
+impl<'a> Certificates<'a> {
+    fn new(list: &'a [u8]) -> Result<Self, Error> {
+        // Check the encoding up front, so that iterating can't fail.
+        let mut reader = Reader::new(list);
+        while !reader.is_empty() {
+            reader.vec24()?;
+            reader.vec16()?;
+        }
+        Ok(Self {
+            reader: Reader::new(list),
+        })
+    }
+}
+
+impl<'a> Iterator for Certificates<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.reader.is_empty() {
+            return None;
+        }
+        let cert = unwrap!(self.reader.vec24()).rest();
+        // Certificate extensions (OCSP, SCT) are ignored.
+        unwrap!(self.reader.vec16());
+        Some(cert)
+    }
+}
+
+/// Verifies the certificate of a TLS server.
+pub trait CertificateVerifier {
+    /// Signature schemes offered to the server, for [`verify_signature`](Self::verify_signature)
+    /// and for the signatures in certificates.
+    fn signature_schemes(&self) -> &[SignatureScheme] {
+        DEFAULT_SIGNATURE_SCHEMES
+    }
+
+    /// Check the certificate chain of the server, e.g. against trusted roots, validity dates, and
+    /// the name the client asked for.
+    fn verify_certificate(&self, server_name: Option<&str>, certificates: Certificates<'_>) -> Result<(), Error>;
+
+    /// Check that `signature` is a valid signature of `message` by the key of `certificate`, the
+    /// server's own certificate.
+    fn verify_signature(
+        &self,
+        certificate: &[u8],
+        scheme: SignatureScheme,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), Error>;
+}
+
+/// Verifier accepting any certificate and signature.
+///
+/// This provides no security against an active attacker, only use it for testing.
+pub struct NoVerification;
+
+impl CertificateVerifier for NoVerification {
+    fn verify_certificate(&self, _server_name: Option<&str>, _certificates: Certificates<'_>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn verify_signature(
+        &self,
+        _certificate: &[u8],
+        _scheme: SignatureScheme,
+        _message: &[u8],
+        _signature: &[u8],
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Certificate and private key of a TLS server.
+pub trait CertificateSigner {
+    /// DER encoded certificate chain, starting with the server's own certificate.
+    fn certificates(&self) -> &[&[u8]];
+
+    /// Signature scheme of [`sign`](Self::sign). Clients that don't support it are rejected.
+    fn signature_scheme(&self) -> SignatureScheme;
+
+    /// Sign `message` with the private key of the certificate, into `signature`.
+    ///
+    /// Returns the length of the signature.
+    fn sign(&self, message: &[u8], signature: &mut [u8]) -> Result<usize, Error>;
+}
+
+/// External pre-shared key, for use with SHA-256.
+#[derive(Debug, Clone, Copy)]
+pub struct Psk<'a> {
+    /// Identity of the key.
+    pub identity: &'a [u8],
+    /// Secret key.
+    pub key: &'a [u8],
+}
+
+/// Looks up the pre-shared keys of a TLS server.
+pub trait PskProvider {
+    /// Get the key with this identity, if it's known.
+    fn key(&self, identity: &[u8]) -> Option<&[u8]>;
+}
+
+impl PskProvider for Psk<'_> {
+    fn key(&self, identity: &[u8]) -> Option<&[u8]> {
+        (identity == self.identity).then_some(self.key)
+    }
+}
+
+/// TLS client configuration.
+#[derive(Clone, Copy, Default)]
+#[non_exhaustive]
+pub struct ClientConfig<'a> {
+    /// Server name sent with the Server Name Indication extension, and passed to the verifier.
+    pub server_name: Option<&'a str>,
+    /// Verifier of the server certificate. Handshakes with certificates fail if it's `None`.
+    pub verifier: Option<&'a dyn CertificateVerifier>,
+    /// Pre-shared key authenticating the server instead of a certificate.
+    pub psk: Option<Psk<'a>>,
+}
+
+/// TLS server configuration.
+#[derive(Clone, Copy, Default)]
+#[non_exhaustive]
+pub struct ServerConfig<'a> {
+    /// Certificate of the server.
+    pub signer: Option<&'a dyn CertificateSigner>,
+    /// Pre-shared keys of the server. Clients offering one of them don't need a certificate.
+    pub psk: Option<&'a dyn PskProvider>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Before the handshake.
+    Idle,
+    /// Handshake complete.
+    Open,
+    /// The handshake failed, or a fatal error happened.
+    Failed,
+    /// Closed with [`TlsConnection::close`].
+    Closed,
+}
+
+/// Records received from the transport.
+struct RecordReader<'a> {
+    buf: &'a mut [u8],
+    /// Handshake data not processed yet, in `buf[..handshake_len]`.
+    handshake_len: usize,
+    /// Application data not read yet, in `buf[app_start..app_end]`.
+    app_start: usize,
+    app_end: usize,
+    /// Data received from the transport not processed yet, in `buf[pos..end]`.
+    pos: usize,
+    end: usize,
+    keys: Option<TrafficKeys>,
+    /// The peer sent `close_notify`.
+    closed: bool,
+}
+
+/// Records sent to the transport.
+struct RecordWriter<'a> {
+    buf: &'a mut [u8],
+    /// Plaintext of the next record, in `buf[HEADER_LEN..HEADER_LEN + len]`.
+    len: usize,
+    /// Largest record plaintext the peer accepts.
+    max_plaintext: usize,
+    keys: Option<TrafficKeys>,
+}
+
+/// A TLS 1.3 connection over a transport, usually a [`TcpSocket`](crate::tcp::TcpSocket).
+///
+/// Create it with [`new`](Self::new), then do the handshake with [`connect`](Self::connect) or
+/// [`accept`](Self::accept). The connection then implements the `embedded-io-async` traits.
+/// Written data is sent when a record is full, or on `flush`.
+pub struct TlsConnection<'a, T> {
+    transport: T,
+    rx: RecordReader<'a>,
+    tx: RecordWriter<'a>,
+    state: State,
+    server_name: heapless::String<MAX_SERVER_NAME_LEN>,
+}
+
+/// Longest server name kept by servers.
+const MAX_SERVER_NAME_LEN: usize = 253;
+
+impl<'a, T: Read + Write> TlsConnection<'a, T> {
+    /// Create a TLS connection over `transport`, with record buffers.
+    pub fn new(transport: T, read_buffer: &'a mut [u8], write_buffer: &'a mut [u8]) -> Self {
+        let max_plaintext = write_buffer
+            .len()
+            .saturating_sub(RECORD_OVERHEAD)
+            .min(MAX_PLAINTEXT_LEN);
+        Self {
+            transport,
+            rx: RecordReader {
+                buf: read_buffer,
+                handshake_len: 0,
+                app_start: 0,
+                app_end: 0,
+                pos: 0,
+                end: 0,
+                keys: None,
+                closed: false,
+            },
+            tx: RecordWriter {
+                buf: write_buffer,
+                len: 0,
+                max_plaintext,
+                keys: None,
+            },
+            state: State::Idle,
+            server_name: heapless::String::new(),
+        }
+    }
+
+    /// Do the handshake as a client.
+    pub async fn connect(
+        &mut self,
+        config: &ClientConfig<'_>,
+        rng: &mut (impl RngCore + CryptoRng),
+    ) -> Result<(), Error> {
+        let mut random = [0; 64];
+        rng.fill_bytes(&mut random);
+        self.connect_with_random(config, &random).await
+    }
+
+    async fn connect_with_random(&mut self, config: &ClientConfig<'_>, random: &[u8; 64]) -> Result<(), Error> {
+        if self.state != State::Idle {
+            return Err(Error::InvalidState);
+        }
+        let res = self.client_handshake(config, random).await;
+        self.finish_handshake(res).await
+    }
+
+    /// Do the handshake as a server.
+    pub async fn accept(
+        &mut self,
+        config: &ServerConfig<'_>,
+        rng: &mut (impl RngCore + CryptoRng),
+    ) -> Result<(), Error> {
+        if self.state != State::Idle {
+            return Err(Error::InvalidState);
+        }
+        let mut random = [0; 64];
+        rng.fill_bytes(&mut random);
+        let res = self.server_handshake(config, &random).await;
+        self.finish_handshake(res).await
+    }
+
+    async fn finish_handshake(&mut self, res: Result<(), Error>) -> Result<(), Error> {
+        match res {
+            Ok(()) => {
+                self.state = State::Open;
+                Ok(())
+            }
+            Err(e) => {
+                self.fail(e).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Server name the client asked for, on servers.
+    pub fn server_name(&self) -> Option<&str> {
+        (!self.server_name.is_empty()).then_some(self.server_name.as_str())
+    }
+
+    /// Get a reference to the transport.
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Get a mutable reference to the transport.
+    ///
+    /// Reading from or writing to it directly breaks the TLS connection.
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Get back the transport.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Close the connection, by sending a `close_notify` alert.
+    ///
+    /// Data can still be read, until the peer closes its side.
+    pub async fn close(&mut self) -> Result<(), Error> {
+        self.check_open()?;
+        self.state = State::Closed;
+        self.flush_record(CONTENT_APPLICATION_DATA).await?;
+        self.send_alert(ALERT_CLOSE_NOTIFY).await?;
+        self.transport.flush().await.map_err(io_error)
+    }
+
+    fn check_open(&self) -> Result<(), Error> {
+        match self.state {
+            State::Open => Ok(()),
+            _ => Err(Error::InvalidState),
+        }
+    }
+
+    /// Mark the connection failed, sending the alert for `error` if any.
+    async fn fail(&mut self, error: Error) {
+        self.state = State::Failed;
+        if let Some(alert) = error.alert() {
+            debug!("tls: sending alert {} for {:?}", alert, error);
+            // The connection is broken anyway, a failure to send the alert doesn't matter.
+            self.tx.len = 0;
+            if self.send_alert(alert).await.is_ok() {
+                let _ = self.transport.flush().await;
+            }
+        }
+    }
+
+    async fn send_alert(&mut self, description: u8) -> Result<(), Error> {
+        let level = match description {
+            ALERT_CLOSE_NOTIFY => 1,
+            _ => 2,
+        };
+        self.tx.buf[HEADER_LEN..HEADER_LEN + 2].copy_from_slice(&[level, description]);
+        self.tx.len = 2;
+        self.flush_record(CONTENT_ALERT).await
+    }
+
+    /// Space left for plaintext in the next record.
+    fn record_space(&self) -> usize {
+        self.tx.max_plaintext - self.tx.len
+    }
+
+    /// Plaintext buffer of the next record, after the data already in it.
+    fn record_buffer(&mut self) -> &mut [u8] {
+        let end = HEADER_LEN + self.tx.max_plaintext;
+        &mut self.tx.buf[HEADER_LEN + self.tx.len..end]
+    }
+
+    /// Send the pending plaintext as a record of `content_type`, encrypted if there are keys.
+    async fn flush_record(&mut self, content_type: u8) -> Result<(), Error> {
+        if self.tx.len == 0 {
+            return Ok(());
+        }
+        let tx = &mut self.tx;
+        let mut len = tx.len;
+        let outer_type = match &mut tx.keys {
+            None => content_type,
+            Some(_) => {
+                tx.buf[HEADER_LEN + len] = content_type;
+                len += 1 + crypto::chacha20poly1305::TAG_LEN;
+                CONTENT_APPLICATION_DATA
+            }
+        };
+        let (header, body) = tx.buf.split_at_mut(HEADER_LEN);
+        header[0] = outer_type;
+        header[1..3].copy_from_slice(&[3, 3]);
+        header[3..5].copy_from_slice(&(len as u16).to_be_bytes());
+        if let Some(keys) = &mut tx.keys {
+            let (data, tag) = body[..len].split_at_mut(len - crypto::chacha20poly1305::TAG_LEN);
+            tag.copy_from_slice(&keys.seal(header, data));
+        }
+        tx.len = 0;
+        self.transport
+            .write_all(&self.tx.buf[..HEADER_LEN + len])
+            .await
+            .map_err(io_error)
+    }
+
+    /// Receive a record. Handshake data is appended to the handshake buffer, application data is
+    /// made available to read, alerts are handled.
+    ///
+    /// Returns the content type of the record.
+    async fn read_record(&mut self) -> Result<u8, Error> {
+        let rx = &mut self.rx;
+        let record_len = loop {
+            let available = rx.end - rx.pos;
+            let mut needed = HEADER_LEN;
+            if available >= HEADER_LEN {
+                let len = u16::from_be_bytes([rx.buf[rx.pos + 3], rx.buf[rx.pos + 4]]) as usize;
+                if len > MAX_CIPHERTEXT_LEN {
+                    return Err(Error::Decode);
+                }
+                needed += len;
+                if available >= needed {
+                    break len;
+                }
+            }
+
+            // Make room after the pending handshake data.
+            if rx.pos + needed > rx.buf.len() {
+                if rx.handshake_len + needed > rx.buf.len() {
+                    return Err(Error::BufferTooSmall);
+                }
+                rx.buf.copy_within(rx.pos..rx.end, rx.handshake_len);
+                rx.end -= rx.pos - rx.handshake_len;
+                rx.pos = rx.handshake_len;
+            }
+
+            let n = self.transport.read(&mut rx.buf[rx.end..]).await.map_err(io_error)?;
+            if n == 0 {
+                return Err(Error::ConnectionClosed);
+            }
+            rx.end += n;
+        };
+
+        let start = rx.pos + HEADER_LEN;
+        let mut end = start + record_len;
+        rx.pos = end;
+        let mut content_type = rx.buf[start - HEADER_LEN];
+
+        match (&mut rx.keys, content_type) {
+            // Compatibility ChangeCipherSpec, ignored.
+            (_, CONTENT_CHANGE_CIPHER_SPEC) if self.state == State::Idle => {
+                if rx.buf[start..end] != [1] {
+                    return Err(Error::UnexpectedMessage);
+                }
+                return Ok(CONTENT_CHANGE_CIPHER_SPEC);
+            }
+            (Some(keys), CONTENT_APPLICATION_DATA) => {
+                if record_len < crypto::chacha20poly1305::TAG_LEN {
+                    return Err(Error::BadRecordMac);
+                }
+                let (header, body) = rx.buf[start - HEADER_LEN..end].split_at_mut(HEADER_LEN);
+                let (data, tag) = body.split_at_mut(record_len - crypto::chacha20poly1305::TAG_LEN);
+                if !keys.open(header, data, tag) {
+                    return Err(Error::BadRecordMac);
+                }
+                // Strip the padding, the content type is the last non-zero byte.
+                end = start + data.iter().rposition(|b| *b != 0).ok_or(Error::UnexpectedMessage)?;
+                content_type = rx.buf[end];
+            }
+            (None, CONTENT_HANDSHAKE | CONTENT_ALERT) => {}
+            _ => return Err(Error::UnexpectedMessage),
+        }
+
+        match content_type {
+            CONTENT_HANDSHAKE => {
+                if end == start {
+                    return Err(Error::UnexpectedMessage);
+                }
+                rx.buf.copy_within(start..end, rx.handshake_len);
+                rx.handshake_len += end - start;
+            }
+            CONTENT_APPLICATION_DATA => {
+                rx.app_start = start;
+                rx.app_end = end;
+            }
+            CONTENT_ALERT => {
+                let alert = &rx.buf[start..end];
+                if alert.len() != 2 {
+                    return Err(Error::Decode);
+                }
+                if alert[1] != ALERT_CLOSE_NOTIFY {
+                    debug!("tls: received alert {}", alert[1]);
+                    return Err(Error::Alert(alert[1]));
+                }
+                rx.closed = true;
+            }
+            _ => return Err(Error::UnexpectedMessage),
+        }
+        Ok(content_type)
+    }
+
+    /// Length of the complete handshake message at `offset` in the handshake buffer, if there's one.
+    fn handshake_message(&self, offset: usize) -> Option<usize> {
+        let data = &self.rx.buf[offset..self.rx.handshake_len];
+        if data.len() < 4 {
+            return None;
+        }
+        let len = 4 + (((data[1] as usize) << 16) | ((data[2] as usize) << 8) | data[3] as usize);
+        (data.len() >= len).then_some(len)
+    }
+
+    /// Receive the next handshake message, after `offset` bytes of the handshake buffer.
+    ///
+    /// Returns its type and length. The message is in `self.rx.buf[offset..offset + len]`.
+    async fn read_handshake(&mut self, offset: usize) -> Result<(u8, usize), Error> {
+        loop {
+            if let Some(len) = self.handshake_message(offset) {
+                return Ok((self.rx.buf[offset], len));
+            }
+            match self.read_record().await? {
+                CONTENT_HANDSHAKE | CONTENT_CHANGE_CIPHER_SPEC => {}
+                _ => return Err(Error::UnexpectedMessage),
+            }
+        }
+    }
+
+    /// Remove the first `len` bytes of the handshake buffer.
+    fn consume_handshake(&mut self, len: usize) {
+        let rx = &mut self.rx;
+        rx.buf.copy_within(len..rx.handshake_len, 0);
+        rx.handshake_len -= len;
+    }
+
+    /// Handle the handshake messages received after the handshake.
+    async fn post_handshake(&mut self) -> Result<(), Error> {
+        while let Some(len) = self.handshake_message(0) {
+            let mut reader = Reader::new(&self.rx.buf[4..len]);
+            match self.rx.buf[0] {
+                // Session tickets aren't supported.
+                handshake::NEW_SESSION_TICKET if self.rx.keys.is_some() => {}
+                handshake::KEY_UPDATE => {
+                    let update_requested = reader.u8()?;
+                    reader.finish()?;
+                    unwrap!(self.rx.keys.as_mut()).update();
+                    if update_requested == 1 {
+                        self.flush_record(CONTENT_APPLICATION_DATA).await?;
+                        self.tx.buf[HEADER_LEN..HEADER_LEN + 5].copy_from_slice(&[handshake::KEY_UPDATE, 0, 0, 1, 0]);
+                        self.tx.len = 5;
+                        self.flush_record(CONTENT_HANDSHAKE).await?;
+                        unwrap!(self.tx.keys.as_mut()).update();
+                    }
+                }
+                _ => return Err(Error::UnexpectedMessage),
+            }
+            self.consume_handshake(len);
+        }
+        Ok(())
+    }
+
+    async fn read_inner(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        loop {
+            let rx = &mut self.rx;
+            if rx.app_start < rx.app_end {
+                let n = buf.len().min(rx.app_end - rx.app_start);
+                buf[..n].copy_from_slice(&rx.buf[rx.app_start..rx.app_start + n]);
+                rx.app_start += n;
+                return Ok(n);
+            }
+            if rx.closed {
+                return Ok(0);
+            }
+            if self.read_record().await? == CONTENT_HANDSHAKE {
+                self.post_handshake().await?;
+            }
+        }
+    }
+
+    async fn write_inner(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if self.record_space() == 0 {
+            self.flush_record(CONTENT_APPLICATION_DATA).await?;
+        }
+        let n = buf.len().min(self.record_space());
+        self.record_buffer()[..n].copy_from_slice(&buf[..n]);
+        self.tx.len += n;
+        Ok(n)
+    }
+}
+
+impl<T> embedded_io_async::ErrorType for TlsConnection<'_, T> {
+    type Error = Error;
+}
+
+impl<T: Read + Write> Read for TlsConnection<'_, T> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.state == State::Idle || self.state == State::Failed {
+            return Err(Error::InvalidState);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.read_inner(buf).await {
+            Ok(n) => Ok(n),
+            Err(e) => {
+                self.fail(e).await;
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<T: Read + Write> Write for TlsConnection<'_, T> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.check_open()?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.write_inner(buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.check_open()?;
+        self.flush_record(CONTENT_APPLICATION_DATA).await?;
+        self.transport.flush().await.map_err(io_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::Infallible;
+
+    use embassy_futures::block_on;
+    use embassy_futures::join::join;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embassy_sync::pipe::Pipe;
+
+    use super::*;
+
+    type Half = Pipe<NoopRawMutex, 4096>;
+
+    /// One end of an in-memory connection.
+    struct Duplex<'p> {
+        rx: &'p Half,
+        tx: &'p Half,
+    }
+
+    impl embedded_io_async::ErrorType for Duplex<'_> {
+        type Error = Infallible;
+    }
+
+    impl Read for Duplex<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+            Ok(self.rx.read(buf).await)
+        }
+    }
+
+    impl Write for Duplex<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+            Ok(self.tx.write(buf).await)
+        }
+    }
+
+    struct CountingRng(u8);
+
+    impl RngCore for CountingRng {
+        fn next_u32(&mut self) -> u32 {
+            rand_core::impls::next_u32_via_fill(self)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            rand_core::impls::next_u64_via_fill(self)
+        }
+
+        fn fill_bytes(&mut self, buf: &mut [u8]) {
+            for b in buf {
+                self.0 = self.0.wrapping_add(1);
+                *b = self.0;
+            }
+        }
+
+        fn try_fill_bytes(&mut self, buf: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(buf);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for CountingRng {}
+
+    struct Signer;
+
+    impl CertificateSigner for Signer {
+        fn certificates(&self) -> &[&[u8]] {
+            &[b"certificate"]
+        }
+
+        fn signature_scheme(&self) -> SignatureScheme {
+            SignatureScheme::ED25519
+        }
+
+        fn sign(&self, _message: &[u8], signature: &mut [u8]) -> Result<usize, Error> {
+            signature[..64].fill(0x55);
+            Ok(64)
+        }
+    }
+
+    /// Run a handshake between a client and a server, then send data both ways.
+    fn handshake(client_config: &ClientConfig, server_config: &ServerConfig) -> Result<(), Error> {
+        let (a, b) = (Half::new(), Half::new());
+        let (mut client_rx, mut client_tx) = ([0; 4096], [0; 1024]);
+        let (mut server_rx, mut server_tx) = ([0; 4096], [0; 1024]);
+        let mut client = TlsConnection::new(Duplex { rx: &a, tx: &b }, &mut client_rx, &mut client_tx);
+        let mut server = TlsConnection::new(Duplex { rx: &b, tx: &a }, &mut server_rx, &mut server_tx);
+
+        block_on(async {
+            let (client_result, server_result) = join(
+                client.connect(client_config, &mut CountingRng(0)),
+                server.accept(server_config, &mut CountingRng(100)),
+            )
+            .await;
+            client_result?;
+            server_result?;
+            assert_eq!(server.server_name(), client_config.server_name);
+
+            let mut buf = [0; 16];
+            client.write_all(b"ping").await?;
+            client.flush().await?;
+            server.read_exact(&mut buf[..4]).await.unwrap();
+            assert_eq!(&buf[..4], b"ping");
+            server.write_all(b"pong").await?;
+            server.flush().await?;
+            client.read_exact(&mut buf[..4]).await.unwrap();
+            assert_eq!(&buf[..4], b"pong");
+
+            // close_notify is seen as the end of the data.
+            client.close().await?;
+            assert_eq!(server.read(&mut buf).await?, 0);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn certificate_handshake() {
+        let client = ClientConfig {
+            server_name: Some("example.com"),
+            verifier: Some(&NoVerification),
+            psk: None,
+        };
+        let server = ServerConfig {
+            signer: Some(&Signer),
+            psk: None,
+        };
+        handshake(&client, &server).unwrap();
+
+        // Certificates aren't accepted without a verifier.
+        let client = ClientConfig::default();
+        assert!(handshake(&client, &server).is_err());
+    }
+
+    #[test]
+    fn psk_handshake() {
+        let psk = Psk {
+            identity: b"client",
+            key: b"secret",
+        };
+        let client = ClientConfig {
+            psk: Some(psk),
+            ..Default::default()
+        };
+        let server = ServerConfig {
+            signer: None,
+            psk: Some(&psk),
+        };
+        handshake(&client, &server).unwrap();
+
+        let wrong = Psk {
+            identity: b"client",
+            key: b"wrong",
+        };
+        let server = ServerConfig {
+            signer: None,
+            psk: Some(&wrong),
+        };
+        assert!(handshake(&client, &server).is_err());
+    }
+}
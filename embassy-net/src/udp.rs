@@ -4,12 +4,12 @@ use core::future::{poll_fn, Future};
 use core::mem;
 use core::task::{Context, Poll};
 
-use smoltcp::iface::{Interface, SocketHandle};
+use smoltcp::iface::Interface;
 use smoltcp::socket::udp;
 pub use smoltcp::socket::udp::{PacketMetadata, UdpMetadata};
 use smoltcp::wire::IpListenEndpoint;
 
-use crate::Stack;
+use crate::{InterfaceBindError, InterfaceId, SocketId, Stack};
 
 /// Error returned by [`UdpSocket::bind`].
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -19,6 +19,8 @@ pub enum BindError {
     InvalidState,
     /// No route to host.
     NoRoute,
+    /// The interface that has the local address has no free socket slot.
+    NoFreeSocket,
 }
 
 /// Error returned by [`UdpSocket::send_to`].
@@ -42,9 +44,14 @@ pub enum RecvError {
 }
 
 /// An UDP socket.
+///
+/// The socket sends and receives on a single interface: the one it's bound to with
+/// [`UdpSocket::bind_to_interface`], the one that has the address it's bound to with
+/// [`UdpSocket::bind`], or else the default interface.
 pub struct UdpSocket<'a> {
     stack: Stack<'a>,
-    handle: SocketHandle,
+    handle: SocketId,
+    bound_to_interface: bool,
 }
 
 impl<'a> UdpSocket<'a> {
@@ -61,16 +68,31 @@ impl<'a> UdpSocket<'a> {
             let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
             let tx_meta: &'static mut [PacketMetadata] = unsafe { mem::transmute(tx_meta) };
             let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
-            i.sockets.add(udp::Socket::new(
+            i.add_socket(udp::Socket::new(
                 udp::PacketBuffer::new(rx_meta, rx_buffer),
                 udp::PacketBuffer::new(tx_meta, tx_buffer),
             ))
         });
 
-        Self { stack, handle }
+        Self {
+            stack,
+            handle,
+            bound_to_interface: false,
+        }
+    }
+
+    /// Bind the socket to a network interface.
+    ///
+    /// The socket then only sends and receives on that interface.
+    pub fn bind_to_interface(&mut self, id: InterfaceId) -> Result<(), InterfaceBindError> {
+        self.handle = self.stack.with_mut(|i| i.bind_socket(self.handle, id))?;
+        self.bound_to_interface = true;
+        Ok(())
     }
 
     /// Bind the socket to a local endpoint.
+    ///
+    /// If the endpoint has an address, the socket is moved to the interface that has it.
     pub fn bind<T>(&mut self, endpoint: T) -> Result<(), BindError>
     where
         T: Into<IpListenEndpoint>,
@@ -82,6 +104,16 @@ impl<'a> UdpSocket<'a> {
             endpoint.port = self.stack.with_mut(|i| i.get_local_port());
         }
 
+        if let Some(addr) = endpoint.addr {
+            if !self.bound_to_interface {
+                self.handle = self.stack.with_mut(|i| match i.interface_with_addr(addr) {
+                    Some(id) => i.move_socket(self.handle, id).ok_or(BindError::NoFreeSocket),
+                    None => Ok(self.handle),
+                })?;
+                self.bound_to_interface = true;
+            }
+        }
+
         match self.with_mut(|s, _| s.bind(endpoint)) {
             Ok(()) => Ok(()),
             Err(udp::BindError::InvalidState) => Err(BindError::InvalidState),
@@ -90,19 +122,11 @@ impl<'a> UdpSocket<'a> {
     }
 
     fn with<R>(&self, f: impl FnOnce(&udp::Socket, &Interface) -> R) -> R {
-        self.stack.with(|i| {
-            let socket = i.sockets.get::<udp::Socket>(self.handle);
-            f(socket, &i.iface)
-        })
+        self.stack.with(|i| i.with_socket(self.handle, f))
     }
 
     fn with_mut<R>(&self, f: impl FnOnce(&mut udp::Socket, &mut Interface) -> R) -> R {
-        self.stack.with_mut(|i| {
-            let socket = i.sockets.get_mut::<udp::Socket>(self.handle);
-            let res = f(socket, &mut i.iface);
-            i.waker.wake();
-            res
-        })
+        self.stack.with_mut(|i| i.with_socket_mut(self.handle, f))
     }
 
     /// Wait until the socket becomes readable.
@@ -383,11 +407,16 @@ impl<'a> UdpSocket<'a> {
     pub fn set_hop_limit(&mut self, hop_limit: Option<u8>) {
         self.with_mut(|s, _| s.set_hop_limit(hop_limit))
     }
+
+    #[cfg(any(feature = "mdns-responder", feature = "sntp"))]
+    pub(crate) fn stack(&self) -> Stack<'a> {
+        self.stack
+    }
 }
 
 impl Drop for UdpSocket<'_> {
     fn drop(&mut self) {
-        self.stack.with_mut(|i| i.sockets.remove(self.handle));
+        self.stack.with_mut(|i| i.remove_socket(self.handle));
     }
 }
 
@@ -0,0 +1,1088 @@
+//! WebSocket client and server connections (RFC 6455).
+//!
+//! [`WebSocket`] works over any transport implementing the `embedded-io-async` traits, usually a
+//! [`TcpSocket`](crate::tcp::TcpSocket), or a [`TlsConnection`](crate::tls::TlsConnection) for
+//! `wss://` when the `tls` feature is enabled. [`WebSocket::connect`] sends the opening handshake
+//! of a client, and [`WebSocket::accept`] answers the one of a client.
+//!
+//! ```ignore
+//! use embassy_net::tcp::TcpSocket;
+//! use embassy_net::websocket::{CloseCode, Config, Message, WebSocket};
+//!
+//! let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+//! socket.connect(endpoint).await?;
+//! let mut buf = [0; 1024];
+//! let config = Config::new();
+//! let mut ws = WebSocket::connect(&mut socket, &mut buf, "example.com", "/telemetry", &config, &mut rng).await?;
+//! ws.send_text("{\"temperature\":21.5}").await?;
+//! match ws.read().await? {
+//!     Message::Text(text) => info!("received {}", text),
+//!     Message::Binary(data) => info!("received {} bytes", data.len()),
+//!     Message::Close(code, reason) => info!("closed: {:?} {}", code, reason),
+//! }
+//! ws.close(CloseCode::NORMAL, "").await?;
+//! ```
+//!
+//! Messages are received whole in the buffer given when opening the connection, which must fit
+//! the largest message, plus the header of a frame. Fragmented messages are reassembled there.
+//! Messages are sent in a single frame, or in several with [`WebSocket::send_fragment`], and
+//! masked when sent by a client, with keys from the [`RngCore`] given to [`WebSocket::connect`].
+//!
+//! Pings are answered, and closing handshakes started by the peer are completed, while reading
+//! with [`WebSocket::read`]. With [`Config::ping_interval`], pings are sent when nothing is
+//! received for that long, and the connection fails if no pong comes back. `read` can be
+//! cancelled, e.g. with `select`, to send messages in between, without losing what was received.
+//!
+//! Extensions, such as `permessage-deflate`, aren't supported.
+
+use embassy_futures::select::{select, Either};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use embedded_io_async::{ErrorKind, Read, Write};
+use heapless::{String, Vec};
+use rand_core::RngCore;
+
+use crate::http::{self, decimal, read_head, write_head, Head, Status};
+
+mod sha1;
+
+/// Appended to the key of the client to compute the `Sec-WebSocket-Accept` header.
+const GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+/// Largest payload of a control frame.
+const MAX_CONTROL_LEN: usize = 125;
+/// Largest frame header: 2 bytes, 8 bytes of extended payload length and the masking key.
+const MAX_HEADER_LEN: usize = 14;
+/// How long to wait for the peer to answer a close frame.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// WebSocket error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Reading from or writing to the transport failed.
+    Io(ErrorKind),
+    /// The transport was closed without a closing handshake.
+    ConnectionClosed,
+    /// The opening handshake was refused, or is malformed.
+    Handshake,
+    /// The peer sent an invalid frame. The connection was closed.
+    Protocol,
+    /// The handshake or a received message doesn't fit in the buffer. The connection was closed
+    /// if it was open.
+    BufferTooSmall,
+    /// A message is being sent in fragments, and must be finished first.
+    InvalidState,
+    /// The connection was closed with a closing handshake.
+    Closed,
+    /// The peer didn't answer a ping, or a close frame, in time. The connection was closed.
+    Timeout,
+}
+
+impl embedded_io_async::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Io(kind) => *kind,
+            Error::ConnectionClosed => ErrorKind::ConnectionReset,
+            Error::Handshake | Error::Protocol => ErrorKind::InvalidData,
+            Error::BufferTooSmall => ErrorKind::OutOfMemory,
+            Error::Closed => ErrorKind::NotConnected,
+            Error::Timeout => ErrorKind::TimedOut,
+            Error::InvalidState => ErrorKind::Other,
+        }
+    }
+}
+
+fn io_error<E: embedded_io_async::Error>(e: E) -> Error {
+    Error::Io(e.kind())
+}
+
+fn http_error(e: http::Error) -> Error {
+    match e {
+        http::Error::Io(kind) => Error::Io(kind),
+        http::Error::ConnectionClosed => Error::ConnectionClosed,
+        http::Error::BufferTooSmall => Error::BufferTooSmall,
+        _ => Error::Handshake,
+    }
+}
+
+/// Status code of a close frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CloseCode(pub u16);
+
+impl CloseCode {
+    /// 1000 Normal Closure
+    pub const NORMAL: Self = Self(1000);
+    /// 1001 Going Away, e.g. a server shutting down.
+    pub const GOING_AWAY: Self = Self(1001);
+    /// 1002 Protocol Error
+    pub const PROTOCOL_ERROR: Self = Self(1002);
+    /// 1003 Unsupported Data, for a type of message that can't be handled.
+    pub const UNSUPPORTED_DATA: Self = Self(1003);
+    /// 1007 Invalid Payload Data, e.g. a text message that isn't UTF-8.
+    pub const INVALID_PAYLOAD: Self = Self(1007);
+    /// 1008 Policy Violation
+    pub const POLICY_VIOLATION: Self = Self(1008);
+    /// 1009 Message Too Big
+    pub const MESSAGE_TOO_BIG: Self = Self(1009);
+    /// 1011 Internal Error
+    pub const INTERNAL_ERROR: Self = Self(1011);
+
+    /// Whether the code can be sent in a close frame (RFC 6455 section 7.4).
+    fn is_valid(self) -> bool {
+        matches!(self.0, 1000..=1003 | 1007..=1011 | 3000..=4999)
+    }
+}
+
+/// A received message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Message<'m> {
+    /// Text message.
+    Text(&'m str),
+    /// Binary message.
+    Binary(&'m [u8]),
+    /// The peer closed the connection, with an optional status code and a reason.
+    ///
+    /// The closing handshake is completed, and the transport should be closed: right away by a
+    /// server, after the server does by a client.
+    Close(Option<CloseCode>, &'m str),
+}
+
+/// Type of a message sent in fragments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MessageType {
+    /// Text message, which must be UTF-8 once reassembled.
+    Text,
+    /// Binary message.
+    Binary,
+}
+
+/// WebSocket configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Config<'a> {
+    /// Subprotocols, in order of preference.
+    ///
+    /// A client offers them all, and a server picks the first of them that the client offers. The
+    /// one agreed on is given by [`WebSocket::protocol`].
+    pub protocols: &'a [&'a str],
+    /// Headers sent with the handshake request or response, e.g. for authentication.
+    pub headers: &'a [(&'a str, &'a str)],
+    /// A ping is sent when nothing is received for this long. `None` disables pings.
+    pub ping_interval: Option<Duration>,
+    /// How long to wait for the pong answering a ping, before failing with [`Error::Timeout`].
+    pub pong_timeout: Duration,
+}
+
+impl Config<'_> {
+    /// Create a default configuration, without subprotocols, pinging every 30 seconds.
+    pub const fn new() -> Self {
+        Self {
+            protocols: &[],
+            headers: &[],
+            ping_interval: Some(Duration::from_secs(30)),
+            pong_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Default for Config<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Random number generator of server connections, which don't mask their frames.
+pub enum NoRng {}
+
+impl RngCore for NoRng {
+    fn next_u32(&mut self) -> u32 {
+        match *self {}
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match *self {}
+    }
+
+    fn fill_bytes(&mut self, _dest: &mut [u8]) {
+        match *self {}
+    }
+
+    fn try_fill_bytes(&mut self, _dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        match *self {}
+    }
+}
+
+/// A WebSocket connection, over a transport `C`.
+///
+/// Received messages are stored in the buffer given when opening the connection. Clients mask
+/// the frames they send with keys from `R`.
+pub struct WebSocket<'b, C: Read + Write, R: RngCore = NoRng> {
+    conn: C,
+    buf: &'b mut [u8],
+    /// Length of the message being received, at the start of `buf`.
+    msg_len: usize,
+    /// Opcode of the first frame of a fragmented message being received.
+    msg_opcode: Option<u8>,
+    /// Whether the message at the start of `buf` was returned, and must be dropped.
+    msg_returned: bool,
+    /// End of the received bytes in `buf`. Those after the message aren't processed yet.
+    filled: usize,
+    /// Generator of masking keys, for clients.
+    rng: Option<R>,
+    ping_interval: Option<Duration>,
+    pong_timeout: Duration,
+    last_rx: Instant,
+    /// When the ping waiting for a pong was sent.
+    ping_sent: Option<Instant>,
+    /// Whether a message is being sent in fragments.
+    sending: bool,
+    close_sent: bool,
+    close_received: bool,
+    protocol: Option<usize>,
+}
+
+impl<'b, C: Read + Write, R: RngCore> WebSocket<'b, C, R> {
+    fn new(
+        conn: C,
+        buf: &'b mut [u8],
+        filled: usize,
+        config: &Config<'_>,
+        rng: Option<R>,
+        protocol: Option<usize>,
+    ) -> Self {
+        Self {
+            conn,
+            buf,
+            msg_len: 0,
+            msg_opcode: None,
+            msg_returned: false,
+            filled,
+            rng,
+            ping_interval: config.ping_interval,
+            pong_timeout: config.pong_timeout,
+            last_rx: Instant::now(),
+            ping_sent: None,
+            sending: false,
+            close_sent: false,
+            close_received: false,
+            protocol,
+        }
+    }
+
+    /// Open a connection as a client, sending a handshake request for `path` on `host`.
+    ///
+    /// `host` is sent in the `Host` header, with the port if it isn't the default one. `buf`
+    /// must fit the handshake response, then stores received messages.
+    ///
+    /// `rng` gives the handshake key and the masking keys. Masking protects intermediaries from
+    /// attacks by scripts in browsers, and needs keys that can't be predicted: `rng` should be a
+    /// hardware random number generator, or seeded from one.
+    pub async fn connect(
+        mut conn: C,
+        buf: &'b mut [u8],
+        host: &str,
+        path: &str,
+        config: &Config<'_>,
+        mut rng: R,
+    ) -> Result<Self, Error> {
+        let mut nonce = [0; 16];
+        rng.fill_bytes(&mut nonce);
+        let key: String<24> = base64(&nonce);
+
+        // The offered protocols are joined in the buffer, unused until the response is read.
+        let mut len = 0;
+        for (i, protocol) in config.protocols.iter().enumerate() {
+            for part in [if i > 0 { ", " } else { "" }, protocol] {
+                let end = len + part.len();
+                buf.get_mut(len..end)
+                    .ok_or(Error::BufferTooSmall)?
+                    .copy_from_slice(part.as_bytes());
+                len = end;
+            }
+        }
+        let protocols = core::str::from_utf8(&buf[..len]).unwrap_or_default();
+
+        let mut headers: Vec<(&str, &str), 6> = Vec::new();
+        let _ = headers.extend_from_slice(&[
+            ("Host", host),
+            ("Upgrade", "websocket"),
+            ("Connection", "Upgrade"),
+            ("Sec-WebSocket-Key", key.as_str()),
+            ("Sec-WebSocket-Version", "13"),
+        ]);
+        if !protocols.is_empty() {
+            let _ = headers.push(("Sec-WebSocket-Protocol", protocols));
+        }
+        write_head(&mut conn, &["GET ", path, " HTTP/1.1"], &headers, config.headers)
+            .await
+            .map_err(http_error)?;
+        drop(headers);
+        conn.flush().await.map_err(io_error)?;
+
+        let (head_len, len) = read_head(&mut conn, buf, 0)
+            .await
+            .map_err(http_error)?
+            .ok_or(Error::ConnectionClosed)?;
+        let head = Head::parse(&buf[..head_len]).map_err(http_error)?;
+        let status = head.start.split(' ').nth(1);
+        let headers = head.headers;
+        let accept = accept_key(key.as_bytes());
+        if status != Some("101")
+            || !headers.contains_token("upgrade", "websocket")
+            || !headers.contains_token("connection", "upgrade")
+            || headers.get("sec-websocket-accept") != Some(accept.as_str())
+            || headers.get("sec-websocket-extensions").is_some()
+        {
+            debug!("WebSocket: handshake refused: {}", head.start);
+            return Err(Error::Handshake);
+        }
+        let protocol = match headers.get("sec-websocket-protocol") {
+            Some(protocol) => Some(
+                config
+                    .protocols
+                    .iter()
+                    .position(|p| *p == protocol)
+                    .ok_or(Error::Handshake)?,
+            ),
+            None => None,
+        };
+
+        buf.copy_within(head_len..len, 0);
+        Ok(Self::new(conn, buf, len - head_len, config, Some(rng), protocol))
+    }
+
+    /// Get the index in [`Config::protocols`] of the subprotocol agreed on, if any.
+    pub fn protocol(&self) -> Option<usize> {
+        self.protocol
+    }
+
+    /// Get the transport back.
+    pub fn into_inner(self) -> C {
+        self.conn
+    }
+
+    /// Read a message.
+    ///
+    /// Pings are answered, and pings are sent as configured, while waiting for a message. The
+    /// message is valid until the next call. After a [`Message::Close`], or an error, nothing
+    /// more can be read.
+    pub async fn read(&mut self) -> Result<Message<'_>, Error> {
+        if self.close_received {
+            return Err(Error::Closed);
+        }
+        if self.msg_returned {
+            self.buf.copy_within(self.msg_len..self.filled, 0);
+            self.filled -= self.msg_len;
+            self.msg_len = 0;
+            self.msg_returned = false;
+        }
+
+        let opcode = loop {
+            if let Some(opcode) = self.process_frame().await? {
+                break opcode;
+            }
+        };
+        if opcode == OP_TEXT && core::str::from_utf8(&self.buf[..self.msg_len]).is_err() {
+            return self.fail(CloseCode::INVALID_PAYLOAD, Error::Protocol).await;
+        }
+
+        self.msg_returned = true;
+        let data = &self.buf[..self.msg_len];
+        Ok(match opcode {
+            // Checked above.
+            OP_TEXT => Message::Text(core::str::from_utf8(data).unwrap_or_default()),
+            OP_BINARY => Message::Binary(data),
+            _ => {
+                let code = data.get(..2).map(|c| CloseCode(u16::from_be_bytes([c[0], c[1]])));
+                let reason = data.get(2..).unwrap_or_default();
+                // Checked when received.
+                Message::Close(code, core::str::from_utf8(reason).unwrap_or_default())
+            }
+        })
+    }
+
+    /// Process the next frame, once it is received whole.
+    ///
+    /// Returns the opcode of the message once its last frame is received, or of a close frame.
+    async fn process_frame(&mut self) -> Result<Option<u8>, Error> {
+        let start = self.msg_len;
+        let Some(header) = FrameHeader::parse(&self.buf[start..self.filled]) else {
+            if self.filled == self.buf.len() {
+                return self.fail(CloseCode::MESSAGE_TOO_BIG, Error::BufferTooSmall).await;
+            }
+            self.fill().await?;
+            return Ok(None);
+        };
+
+        // Clients mask their frames, servers don't.
+        let valid = header.rsv == 0
+            && header.mask.is_some() == self.rng.is_none()
+            && match header.opcode {
+                OP_CONTINUATION => self.msg_opcode.is_some(),
+                OP_TEXT | OP_BINARY => self.msg_opcode.is_none(),
+                OP_CLOSE | OP_PING | OP_PONG => header.fin && header.payload_len <= MAX_CONTROL_LEN as u64,
+                _ => false,
+            };
+        if !valid {
+            return self.fail(CloseCode::PROTOCOL_ERROR, Error::Protocol).await;
+        }
+        let frame_len = header.len as u64 + header.payload_len;
+        if (start as u64).saturating_add(frame_len) > self.buf.len() as u64 {
+            return self.fail(CloseCode::MESSAGE_TOO_BIG, Error::BufferTooSmall).await;
+        }
+        let end = start + frame_len as usize;
+        if end > self.filled {
+            self.fill().await?;
+            return Ok(None);
+        }
+
+        let payload = &mut self.buf[start + header.len..end];
+        if let Some(mask) = header.mask {
+            apply_mask(payload, mask);
+        }
+        let payload_len = payload.len();
+
+        if header.opcode & 0x8 == 0 {
+            // Append the payload to the message, dropping the header.
+            self.buf.copy_within(start + header.len..self.filled, start);
+            self.filled -= header.len;
+            self.msg_len += payload_len;
+            if header.opcode != OP_CONTINUATION {
+                self.msg_opcode = Some(header.opcode);
+            }
+            return Ok(match header.fin {
+                true => self.msg_opcode.take(),
+                false => None,
+            });
+        }
+
+        // Control frames can come between the fragments of a message, take them out.
+        let mut control = [0; MAX_CONTROL_LEN];
+        let control = &mut control[..payload_len];
+        control.copy_from_slice(payload);
+        self.buf.copy_within(end..self.filled, start);
+        self.filled -= end - start;
+
+        match header.opcode {
+            OP_PING => {
+                if !self.close_sent {
+                    self.send_frame(OP_PONG, true, control).await?;
+                }
+                Ok(None)
+            }
+            OP_PONG => {
+                self.ping_sent = None;
+                Ok(None)
+            }
+            _ => {
+                let code = control.get(..2).map(|c| CloseCode(u16::from_be_bytes([c[0], c[1]])));
+                if payload_len == 1 || code.is_some_and(|c| !c.is_valid()) {
+                    return self.fail(CloseCode::PROTOCOL_ERROR, Error::Protocol).await;
+                }
+                if core::str::from_utf8(control.get(2..).unwrap_or_default()).is_err() {
+                    return self.fail(CloseCode::INVALID_PAYLOAD, Error::Protocol).await;
+                }
+                if !self.close_sent {
+                    // Echo the status code.
+                    self.send_frame(OP_CLOSE, true, control.get(..2).unwrap_or_default())
+                        .await?;
+                }
+                self.close_received = true;
+
+                // The message being received is dropped.
+                self.buf[..payload_len].copy_from_slice(control);
+                self.msg_len = payload_len;
+                self.msg_opcode = None;
+                Ok(Some(OP_CLOSE))
+            }
+        }
+    }
+
+    /// Receive more bytes, sending pings while waiting.
+    async fn fill(&mut self) -> Result<(), Error> {
+        loop {
+            let deadline = self.ping_deadline();
+            let read = self.conn.read(&mut self.buf[self.filled..]);
+            let result = match deadline {
+                Some(at) => select(read, Timer::at(at)).await,
+                None => Either::First(read.await),
+            };
+            match result {
+                Either::First(Ok(0)) => return Err(Error::ConnectionClosed),
+                Either::First(Ok(n)) => {
+                    self.filled += n;
+                    self.last_rx = Instant::now();
+                    return Ok(());
+                }
+                Either::First(Err(e)) => return Err(io_error(e)),
+                Either::Second(()) => {
+                    if self.ping_sent.is_some() {
+                        warn!("WebSocket: no answer to ping");
+                        self.close_sent = true;
+                        self.close_received = true;
+                        return Err(Error::Timeout);
+                    }
+                    self.ping_sent = Some(Instant::now());
+                    self.send_frame(OP_PING, true, &[]).await?;
+                }
+            }
+        }
+    }
+
+    /// When to send a ping, or to give up waiting for a pong.
+    fn ping_deadline(&self) -> Option<Instant> {
+        let interval = self.ping_interval?;
+        if self.close_sent {
+            return None;
+        }
+        Some(match self.ping_sent {
+            Some(at) => at + self.pong_timeout,
+            None => self.last_rx + interval,
+        })
+    }
+
+    /// Close the connection after an error of the peer.
+    async fn fail<T>(&mut self, code: CloseCode, error: Error) -> Result<T, Error> {
+        warn!("WebSocket: closing with {}: {:?}", code.0, error);
+        if !self.close_sent {
+            let _ = self.send_frame(OP_CLOSE, true, &code.0.to_be_bytes()).await;
+        }
+        self.close_received = true;
+        Err(error)
+    }
+
+    /// Send a text message.
+    pub async fn send_text(&mut self, text: &str) -> Result<(), Error> {
+        self.send_message(OP_TEXT, text.as_bytes()).await
+    }
+
+    /// Send a binary message.
+    pub async fn send_binary(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.send_message(OP_BINARY, data).await
+    }
+
+    async fn send_message(&mut self, opcode: u8, data: &[u8]) -> Result<(), Error> {
+        if self.sending {
+            return Err(Error::InvalidState);
+        }
+        self.send_frame(opcode, true, data).await
+    }
+
+    /// Send a fragment of a message, of type `ty`, which is ignored after the first fragment.
+    ///
+    /// The message ends with the fragment sent with `last` set. No other message can be sent
+    /// in the meantime.
+    pub async fn send_fragment(&mut self, ty: MessageType, data: &[u8], last: bool) -> Result<(), Error> {
+        let opcode = match (self.sending, ty) {
+            (true, _) => OP_CONTINUATION,
+            (false, MessageType::Text) => OP_TEXT,
+            (false, MessageType::Binary) => OP_BINARY,
+        };
+        self.send_frame(opcode, last, data).await?;
+        self.sending = !last;
+        Ok(())
+    }
+
+    /// Start the closing handshake, and wait for the peer to answer it.
+    ///
+    /// Messages received in the meantime are dropped. `reason` is truncated to 123 bytes. The
+    /// transport should be closed afterwards: right away by a server, after the server does by
+    /// a client.
+    pub async fn close(&mut self, code: CloseCode, reason: &str) -> Result<(), Error> {
+        if self.close_received {
+            return Ok(());
+        }
+        let mut len = reason.len().min(MAX_CONTROL_LEN - 2);
+        while !reason.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut payload = [0; MAX_CONTROL_LEN];
+        payload[..2].copy_from_slice(&code.0.to_be_bytes());
+        payload[2..][..len].copy_from_slice(&reason.as_bytes()[..len]);
+        self.send_frame(OP_CLOSE, true, &payload[..2 + len]).await?;
+
+        let answer = async {
+            loop {
+                match self.read().await {
+                    Ok(Message::Close(..)) | Err(Error::ConnectionClosed) => return Ok(()),
+                    Ok(_) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        };
+        with_timeout(CLOSE_TIMEOUT, answer).await.map_err(|_| Error::Timeout)?
+    }
+
+    async fn send_frame(&mut self, opcode: u8, fin: bool, payload: &[u8]) -> Result<(), Error> {
+        if self.close_sent {
+            return Err(Error::Closed);
+        }
+        if opcode == OP_CLOSE {
+            self.close_sent = true;
+        }
+        let mask = self.rng.as_mut().map(|rng| rng.next_u32().to_ne_bytes());
+
+        let mut header = [0; MAX_HEADER_LEN];
+        let len = FrameHeader::emit(&mut header, fin, opcode, mask, payload.len());
+        self.conn.write_all(&header[..len]).await.map_err(io_error)?;
+        match mask {
+            Some(mask) => {
+                // The chunks are a multiple of 4 bytes long, so the mask restarts with each.
+                let mut chunk = [0; 128];
+                for part in payload.chunks(chunk.len()) {
+                    let chunk = &mut chunk[..part.len()];
+                    chunk.copy_from_slice(part);
+                    apply_mask(chunk, mask);
+                    self.conn.write_all(chunk).await.map_err(io_error)?;
+                }
+            }
+            None => self.conn.write_all(payload).await.map_err(io_error)?,
+        }
+        self.conn.flush().await.map_err(io_error)
+    }
+}
+
+impl<'b, C: Read + Write> WebSocket<'b, C> {
+    /// Open a connection as a server, reading the handshake request of a client and answering it.
+    ///
+    /// Requests for any path are accepted. Invalid requests are answered with an error status,
+    /// and the transport should then be closed. `buf` must fit the handshake request, then
+    /// stores received messages.
+    pub async fn accept(mut conn: C, buf: &'b mut [u8], config: &Config<'_>) -> Result<Self, Error> {
+        let (head_len, len) = match read_head(&mut conn, buf, 0).await {
+            Ok(Some(head)) => head,
+            Ok(None) => return Err(Error::ConnectionClosed),
+            Err(http::Error::BufferTooSmall) => {
+                refuse(&mut conn, Status::REQUEST_HEADER_FIELDS_TOO_LARGE).await?;
+                return Err(Error::BufferTooSmall);
+            }
+            Err(e) => return Err(http_error(e)),
+        };
+        let (key, protocol) = match check_request(&buf[..head_len], config.protocols) {
+            Ok(request) => request,
+            Err(status) => {
+                debug!("WebSocket: invalid handshake request, answering {}", status.0);
+                refuse(&mut conn, status).await?;
+                return Err(Error::Handshake);
+            }
+        };
+
+        let accept = accept_key(&key);
+        let mut headers: Vec<(&str, &str), 4> = Vec::new();
+        let _ = headers.extend_from_slice(&[
+            ("Upgrade", "websocket"),
+            ("Connection", "Upgrade"),
+            ("Sec-WebSocket-Accept", accept.as_str()),
+        ]);
+        if let Some(i) = protocol {
+            let _ = headers.push(("Sec-WebSocket-Protocol", config.protocols[i]));
+        }
+        write_head(
+            &mut conn,
+            &["HTTP/1.1 101 Switching Protocols"],
+            &headers,
+            config.headers,
+        )
+        .await
+        .map_err(http_error)?;
+        conn.flush().await.map_err(io_error)?;
+
+        buf.copy_within(head_len..len, 0);
+        Ok(Self::new(conn, buf, len - head_len, config, None, protocol))
+    }
+}
+
+/// Check a handshake request, returning the key of the client and the subprotocol picked, or
+/// the status of the response refusing it.
+fn check_request(head: &[u8], protocols: &[&str]) -> Result<([u8; 24], Option<usize>), Status> {
+    let head = Head::parse(head).map_err(|_| Status::BAD_REQUEST)?;
+    let mut parts = head.start.split(' ');
+    let (Some(method), Some(_), Some(version), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return Err(Status::BAD_REQUEST);
+    };
+    if method != "GET" {
+        return Err(Status::METHOD_NOT_ALLOWED);
+    }
+    let headers = head.headers;
+    if version != "HTTP/1.1"
+        || !headers.contains_token("upgrade", "websocket")
+        || !headers.contains_token("connection", "upgrade")
+    {
+        return Err(Status::BAD_REQUEST);
+    }
+    if headers.get("sec-websocket-version") != Some("13") {
+        return Err(Status::UPGRADE_REQUIRED);
+    }
+    // The key is 16 bytes, in base64.
+    let key = headers
+        .get("sec-websocket-key")
+        .and_then(|key| key.as_bytes().try_into().ok())
+        .ok_or(Status::BAD_REQUEST)?;
+    let protocol = protocols
+        .iter()
+        .position(|p| headers.contains_token("sec-websocket-protocol", p));
+    Ok((key, protocol))
+}
+
+/// Answer an invalid handshake request.
+async fn refuse<C: Write>(conn: &mut C, status: Status) -> Result<(), Error> {
+    let code = decimal(status.0 as usize);
+    let mut headers: Vec<(&str, &str), 3> = Vec::new();
+    let _ = headers.extend_from_slice(&[("Content-Length", "0"), ("Connection", "close")]);
+    if status == Status::UPGRADE_REQUIRED {
+        let _ = headers.push(("Sec-WebSocket-Version", "13"));
+    }
+    write_head(conn, &["HTTP/1.1 ", code.as_str(), " ", status.reason()], &headers, &[])
+        .await
+        .map_err(http_error)?;
+    conn.flush().await.map_err(io_error)
+}
+
+/// Header of a frame.
+struct FrameHeader {
+    fin: bool,
+    rsv: u8,
+    opcode: u8,
+    mask: Option<[u8; 4]>,
+    /// Length of the header.
+    len: usize,
+    payload_len: u64,
+}
+
+impl FrameHeader {
+    /// Parse a header, returning `None` if it isn't received whole yet.
+    fn parse(buf: &[u8]) -> Option<Self> {
+        let [b0, b1] = *buf.first_chunk()?;
+        let (payload_len, mut len) = match b1 & 0x7f {
+            126 => (u16::from_be_bytes(*buf.get(2..)?.first_chunk()?) as u64, 4),
+            127 => (u64::from_be_bytes(*buf.get(2..)?.first_chunk()?), 10),
+            n => (n as u64, 2),
+        };
+        let mask = match b1 & 0x80 {
+            0 => None,
+            _ => {
+                let mask = *buf.get(len..)?.first_chunk()?;
+                len += 4;
+                Some(mask)
+            }
+        };
+        Some(Self {
+            fin: b0 & 0x80 != 0,
+            rsv: b0 & 0x70,
+            opcode: b0 & 0x0f,
+            mask,
+            len,
+            payload_len,
+        })
+    }
+
+    /// Write a header in `buf`, returning its length.
+    fn emit(buf: &mut [u8; MAX_HEADER_LEN], fin: bool, opcode: u8, mask: Option<[u8; 4]>, payload_len: usize) -> usize {
+        buf[0] = ((fin as u8) << 7) | opcode;
+        let mask_bit = (mask.is_some() as u8) << 7;
+        let mut len = match payload_len {
+            0..=125 => {
+                buf[1] = mask_bit | payload_len as u8;
+                2
+            }
+            126..=0xffff => {
+                buf[1] = mask_bit | 126;
+                buf[2..4].copy_from_slice(&(payload_len as u16).to_be_bytes());
+                4
+            }
+            _ => {
+                buf[1] = mask_bit | 127;
+                buf[2..10].copy_from_slice(&(payload_len as u64).to_be_bytes());
+                10
+            }
+        };
+        if let Some(mask) = mask {
+            buf[len..len + 4].copy_from_slice(&mask);
+            len += 4;
+        }
+        len
+    }
+}
+
+/// Mask or unmask data, starting at the first byte of the mask.
+fn apply_mask(data: &mut [u8], mask: [u8; 4]) {
+    for (b, m) in data.iter_mut().zip(mask.iter().cycle()) {
+        *b ^= m;
+    }
+}
+
+/// Compute the `Sec-WebSocket-Accept` header answering the key of a client.
+fn accept_key(key: &[u8]) -> String<28> {
+    let mut h = sha1::Sha1::new();
+    h.update(key);
+    h.update(GUID);
+    base64(&h.finalize())
+}
+
+/// Encode `data` in base64, with padding.
+fn base64<const N: usize>(data: &[u8]) -> String<N> {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let mut b = [0; 3];
+        b[..chunk.len()].copy_from_slice(chunk);
+        let n = u32::from_be_bytes([0, b[0], b[1], b[2]]);
+        for i in 0..4 {
+            let c = match i <= chunk.len() {
+                true => ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f] as char,
+                false => '=',
+            };
+            let _ = out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::Infallible;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    /// Transport reading from a slice, and recording what is written.
+    struct Io<'a> {
+        input: &'a [u8],
+        output: std::vec::Vec<u8>,
+    }
+
+    impl embedded_io_async::ErrorType for Io<'_> {
+        type Error = Infallible;
+    }
+
+    impl Read for Io<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+            let n = buf.len().min(self.input.len());
+            buf[..n].copy_from_slice(&self.input[..n]);
+            self.input = &self.input[n..];
+            Ok(n)
+        }
+    }
+
+    impl Write for Io<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    /// Gives the nonce of the example handshake of RFC 6455 section 1.3, then masking keys.
+    struct SampleRng;
+
+    impl RngCore for SampleRng {
+        fn next_u32(&mut self) -> u32 {
+            0x37fa213d
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            unimplemented!()
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            dest.copy_from_slice(b"the sample nonce");
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    const REQUEST: &[u8] = b"GET /chat HTTP/1.1\r\nHost: server.example.com\r\nUpgrade: websocket\r\n\
+        Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+        Sec-WebSocket-Protocol: chat, superchat\r\nSec-WebSocket-Version: 13\r\n\r\n";
+    const RESPONSE: &[u8] = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+        Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+
+    /// Split what was written into the HTTP head and the frames following it.
+    fn split_head(output: &[u8]) -> (&str, &[u8]) {
+        let end = output.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        (core::str::from_utf8(&output[..end]).unwrap(), &output[end..])
+    }
+
+    /// Split the frames in `data` into their header and unmasked payload.
+    fn frames(mut data: &[u8]) -> std::vec::Vec<(FrameHeader, std::vec::Vec<u8>)> {
+        let mut frames = std::vec::Vec::new();
+        while !data.is_empty() {
+            let header = FrameHeader::parse(data).unwrap();
+            let end = header.len + header.payload_len as usize;
+            let mut payload = data[header.len..end].to_vec();
+            if let Some(mask) = header.mask {
+                apply_mask(&mut payload, mask);
+            }
+            data = &data[end..];
+            frames.push((header, payload));
+        }
+        frames
+    }
+
+    #[test]
+    fn handshake_keys() {
+        // RFC 6455 section 1.3.
+        assert_eq!(
+            accept_key(b"dGhlIHNhbXBsZSBub25jZQ==").as_str(),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(base64::<24>(b"the sample nonce").as_str(), "dGhlIHNhbXBsZSBub25jZQ==");
+        // RFC 4648 section 10.
+        for (data, encoded) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(base64::<8>(data.as_bytes()).as_str(), encoded);
+        }
+    }
+
+    #[test]
+    fn frame_headers() {
+        // Examples of RFC 6455 section 5.7.
+        let header = FrameHeader::parse(&[0x81, 0x05, b'H']).unwrap();
+        assert!(header.fin && header.mask.is_none());
+        assert_eq!((header.opcode, header.len, header.payload_len), (OP_TEXT, 2, 5));
+
+        let mut masked = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let header = FrameHeader::parse(&masked).unwrap();
+        assert_eq!((header.len, header.mask), (6, Some([0x37, 0xfa, 0x21, 0x3d])));
+        apply_mask(&mut masked[6..], header.mask.unwrap());
+        assert_eq!(&masked[6..], b"Hello");
+
+        let header = FrameHeader::parse(&[0x01, 0x03]).unwrap();
+        assert!(!header.fin);
+        assert_eq!(header.opcode, OP_TEXT);
+        let header = FrameHeader::parse(&[0x80, 0x02]).unwrap();
+        assert!(header.fin);
+        assert_eq!(header.opcode, OP_CONTINUATION);
+
+        let mut buf = [0; MAX_HEADER_LEN];
+        assert_eq!(FrameHeader::emit(&mut buf, true, OP_BINARY, None, 256), 4);
+        assert_eq!(&buf[..4], &[0x82, 0x7e, 0x01, 0x00]);
+        assert_eq!(FrameHeader::emit(&mut buf, true, OP_BINARY, None, 65536), 10);
+        assert_eq!(&buf[..10], &[0x82, 0x7f, 0, 0, 0, 0, 0, 1, 0, 0]);
+
+        for payload_len in [0, 125, 126, 0xffff, 0x10000] {
+            for mask in [None, Some([1, 2, 3, 4])] {
+                let len = FrameHeader::emit(&mut buf, false, OP_PING, mask, payload_len);
+                // Headers received partially aren't parsed.
+                assert!(FrameHeader::parse(&buf[..len - 1]).is_none());
+                let header = FrameHeader::parse(&buf[..len]).unwrap();
+                assert!(!header.fin);
+                assert_eq!((header.opcode, header.rsv, header.mask), (OP_PING, 0, mask));
+                assert_eq!((header.len, header.payload_len), (len, payload_len as u64));
+            }
+        }
+    }
+
+    #[test]
+    fn server() {
+        let mut input = REQUEST.to_vec();
+        // A masked text message in two fragments, with a masked ping in between.
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        for (b0, payload) in [(0x01, &b"Hel"[..]), (0x89, b""), (0x80, b"lo")] {
+            input.extend_from_slice(&[b0, 0x80 | payload.len() as u8]);
+            input.extend_from_slice(&mask);
+            let start = input.len();
+            input.extend_from_slice(payload);
+            apply_mask(&mut input[start..], mask);
+        }
+        // An unmasked frame, which clients must not send.
+        input.extend_from_slice(&[0x82, 0x00]);
+
+        let mut io = Io {
+            input: &input,
+            output: std::vec::Vec::new(),
+        };
+        let mut buf = [0; 512];
+        let config = Config {
+            protocols: &["superchat"],
+            ..Config::new()
+        };
+        block_on(async {
+            let mut ws = WebSocket::accept(&mut io, &mut buf, &config).await.unwrap();
+            assert_eq!(ws.protocol(), Some(0));
+            assert_eq!(ws.read().await, Ok(Message::Text("Hello")));
+            assert_eq!(ws.read().await, Err(Error::Protocol));
+        });
+
+        let (head, frames_data) = split_head(&io.output);
+        assert!(head.starts_with("HTTP/1.1 101 "));
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(head.contains("Sec-WebSocket-Protocol: superchat\r\n"));
+        // The pong, then the close frame for the protocol error, both unmasked.
+        let frames = frames(frames_data);
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].0.opcode, frames[0].0.mask), (OP_PONG, None));
+        assert_eq!(frames[1].0.opcode, OP_CLOSE);
+        core::assert_eq!(frames[1].1, CloseCode::PROTOCOL_ERROR.0.to_be_bytes());
+    }
+
+    #[test]
+    fn client() {
+        let mut input = RESPONSE.to_vec();
+        input.extend_from_slice(&[0x81, 0x05]);
+        input.extend_from_slice(b"Hello");
+        // Server close frame, answered by the client.
+        input.extend_from_slice(&[0x88, 0x02, 0x03, 0xe8]);
+
+        let mut io = Io {
+            input: &input,
+            output: std::vec::Vec::new(),
+        };
+        let mut buf = [0; 512];
+        block_on(async {
+            let mut ws = WebSocket::connect(
+                &mut io,
+                &mut buf,
+                "server.example.com",
+                "/chat",
+                &Config::new(),
+                SampleRng,
+            )
+            .await
+            .unwrap();
+            ws.send_fragment(MessageType::Binary, &[1, 2, 3], false).await.unwrap();
+            assert_eq!(ws.send_text("no").await, Err(Error::InvalidState));
+            ws.send_fragment(MessageType::Text, &[4, 5], true).await.unwrap();
+            assert_eq!(ws.read().await, Ok(Message::Text("Hello")));
+            assert_eq!(ws.read().await, Ok(Message::Close(Some(CloseCode::NORMAL), "")));
+            assert_eq!(ws.send_text("late").await, Err(Error::Closed));
+        });
+
+        let (head, frames_data) = split_head(&io.output);
+        assert!(head.starts_with("GET /chat HTTP/1.1\r\n"));
+        assert!(head.contains("Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n"));
+        let frames = frames(frames_data);
+        let summary: std::vec::Vec<_> = frames
+            .iter()
+            .map(|(h, payload)| (h.fin, h.opcode, h.mask, payload.as_slice()))
+            .collect();
+        let mask = Some(0x37fa213du32.to_ne_bytes());
+        core::assert_eq!(
+            summary,
+            [
+                (false, OP_BINARY, mask, &[1, 2, 3][..]),
+                (true, OP_CONTINUATION, mask, &[4, 5]),
+                (true, OP_CLOSE, mask, &[0x03, 0xe8]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! SHA-1 (FIPS 180-4), only used to compute the `Sec-WebSocket-Accept` handshake header.
+
+pub(crate) const HASH_LEN: usize = 20;
+
+const BLOCK_LEN: usize = 64;
+
+const INIT: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+/// Incremental SHA-1 hash.
+pub(crate) struct Sha1 {
+    state: [u32; 5],
+    block: [u8; BLOCK_LEN],
+    block_len: usize,
+    len: u64,
+}
+
+impl Sha1 {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: INIT,
+            block: [0; BLOCK_LEN],
+            block_len: 0,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        while !data.is_empty() {
+            let n = data.len().min(BLOCK_LEN - self.block_len);
+            self.block[self.block_len..][..n].copy_from_slice(&data[..n]);
+            self.block_len += n;
+            data = &data[n..];
+            if self.block_len == BLOCK_LEN {
+                compress(&mut self.state, &self.block);
+                self.block_len = 0;
+            }
+        }
+    }
+
+    pub(crate) fn finalize(mut self) -> [u8; HASH_LEN] {
+        let bits = self.len * 8;
+        self.block[self.block_len] = 0x80;
+        self.block[self.block_len + 1..].fill(0);
+        if self.block_len >= BLOCK_LEN - 8 {
+            compress(&mut self.state, &self.block);
+            self.block.fill(0);
+        }
+        self.block[BLOCK_LEN - 8..].copy_from_slice(&bits.to_be_bytes());
+        compress(&mut self.state, &self.block);
+
+        let mut out = [0; HASH_LEN];
+        for (o, s) in out.chunks_exact_mut(4).zip(self.state) {
+            o.copy_from_slice(&s.to_be_bytes());
+        }
+        out
+    }
+}
+
+fn compress(state: &mut [u32; 5], block: &[u8; BLOCK_LEN]) {
+    let mut w = [0u32; 80];
+    for (w, b) in w.iter_mut().zip(block.chunks_exact(4)) {
+        *w = u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+    }
+    for i in 16..80 {
+        w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e] = *state;
+    for (i, w) in w.into_iter().enumerate() {
+        let (f, k) = match i {
+            0..=19 => ((b & c) | (!b & d), 0x5a827999),
+            20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+            40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+            _ => (b ^ c ^ d, 0xca62c1d6),
+        };
+        let t = a
+            .rotate_left(5)
+            .wrapping_add(f)
+            .wrapping_add(e)
+            .wrapping_add(k)
+            .wrapping_add(w);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = t;
+    }
+    for (s, v) in state.iter_mut().zip([a, b, c, d, e]) {
+        *s = s.wrapping_add(v);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(data: &[u8]) -> [u8; HASH_LEN] {
+        let mut h = Sha1::new();
+        h.update(data);
+        h.finalize()
+    }
+
+    fn hex(s: &str) -> [u8; HASH_LEN] {
+        core::array::from_fn(|i| u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap())
+    }
+
+    #[test]
+    fn vectors() {
+        // FIPS 180 examples.
+        assert_eq!(digest(b"abc"), hex("a9993e364706816aba3e25717850c26c9cd0d89d"));
+        assert_eq!(digest(b""), hex("da39a3ee5e6b4b0d3255bfef95601890afd80709"));
+        assert_eq!(
+            digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            hex("84983e441c3bd26ebaae4aa1f95129e5e54670f1")
+        );
+    }
+
+    #[test]
+    fn incremental() {
+        // A million 'a', hashed in chunks which don't line up with blocks.
+        let mut h = Sha1::new();
+        for _ in 0..10_000 {
+            h.update(&[b'a'; 73][..]);
+        }
+        h.update(&[b'a'; 1_000_000 - 730_000][..]);
+        assert_eq!(h.finalize(), hex("34aa973cd4c4daa4f61eeb2bdbad27316534016f"));
+    }
+}
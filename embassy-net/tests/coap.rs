@@ -0,0 +1,303 @@
+//! A CoAP client and server talking over an `embassy-net-sim` link.
+#![cfg(all(feature = "coap", feature = "proto-ipv4", feature = "medium-ip"))]
+
+use core::fmt::Write;
+use core::future::Future;
+use core::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use embassy_futures::block_on;
+use embassy_futures::join::join;
+use embassy_futures::select::{select, select3, Either};
+use embassy_net::coap::client::{self, Client};
+use embassy_net::coap::server::{Handler, Request, Response, Route, Server, ServerState};
+use embassy_net::coap::{Code, Method, Type};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{Config, IpEndpoint, Ipv4Address, Ipv4Cidr, Runner, Stack, StackResources, StaticConfigV4};
+use embassy_net_sim::driver::HardwareAddress;
+use embassy_net_sim::{Control, Direction};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_time::{with_timeout, Duration, Timer};
+use rand_core::RngCore;
+
+type Device = embassy_net_sim::Device<'static, 1500, 16>;
+
+const SERVER: Ipv4Address = Ipv4Address::new(10, 0, 0, 1);
+const CLIENT: Ipv4Address = Ipv4Address::new(10, 0, 0, 2);
+
+/// Losing all the packets, for a while.
+const LOSSY: embassy_net_sim::Config = embassy_net_sim::Config {
+    loss: 1.0,
+    duplicate: 0.0,
+    reorder: 0.0,
+    latency: Duration::from_ticks(0),
+    jitter: Duration::from_ticks(0),
+    bandwidth: None,
+};
+
+static COUNTER: AtomicU32 = AtomicU32::new(0);
+static CALLS: AtomicU32 = AtomicU32::new(0);
+static UPLOAD: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+enum Api {
+    Counter,
+    Upload,
+    Large,
+    Count,
+}
+
+impl Handler for Api {
+    async fn handle(&self, request: &Request<'_>, response: &mut Response<'_>) {
+        match self {
+            Api::Counter => write!(response, "{}", COUNTER.load(Ordering::Relaxed)).unwrap(),
+            Api::Upload => {
+                let mut upload = UPLOAD.lock().unwrap();
+                let block = request.block1();
+                if block.map_or(0, |b| b.offset()) != upload.len() {
+                    response.set_code(Code::REQUEST_ENTITY_INCOMPLETE);
+                    return;
+                }
+                upload.extend_from_slice(request.payload());
+                if block.is_some_and(|b| b.more) {
+                    response.set_code(Code::CONTINUE);
+                }
+            }
+            Api::Large => response.write(&large()),
+            Api::Count => write!(response, "{}", CALLS.fetch_add(1, Ordering::Relaxed) + 1).unwrap(),
+        }
+    }
+}
+
+static ROUTES: [Route<'static, Api>; 4] = [
+    Route::new(Method::Get, "counter", Api::Counter),
+    Route::new(Method::Put, "upload", Api::Upload),
+    Route::new(Method::Get, "large", Api::Large),
+    Route::new(Method::Post, "count", Api::Count),
+];
+
+/// A representation of several blocks.
+fn large() -> Vec<u8> {
+    (0..500).map(|i| (i % 251) as u8).collect()
+}
+
+/// xorshift, good enough for message IDs and tokens in tests.
+struct Rng(u64);
+
+impl RngCore for Rng {
+    fn next_u32(&mut self) -> u32 {
+        self.next_u64() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        rand_core::impls::fill_bytes_via_next(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+fn config(address: Ipv4Address) -> Config {
+    Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(address, 24),
+        gateway: None,
+        dns_servers: Default::default(),
+    })
+}
+
+fn coap_config() -> embassy_net::coap::Config {
+    let mut config = embassy_net::coap::Config::new();
+    config.ack_timeout = Duration::from_millis(200);
+    config.block_size = 64;
+    config
+}
+
+fn socket(stack: Stack<'static>) -> UdpSocket<'static> {
+    UdpSocket::new(
+        stack,
+        Box::leak(Box::new([PacketMetadata::EMPTY; 4])),
+        Box::leak(Box::new([0; 2048])),
+        Box::leak(Box::new([PacketMetadata::EMPTY; 4])),
+        Box::leak(Box::new([0; 2048])),
+    )
+}
+
+struct Setup {
+    server: Server<'static, NoopRawMutex, Api, Rng>,
+    server_runner: Runner<'static, Device>,
+    client: Stack<'static>,
+    client_runner: Runner<'static, Device>,
+    control: Control<'static, 1500, 16>,
+}
+
+/// Create a server at `SERVER`, on device A, and the stack of a client at `CLIENT`, on device B.
+fn setup() -> Setup {
+    let state = Box::leak(Box::new(embassy_net_sim::State::new()));
+    let (server_device, client_device, control) = embassy_net_sim::new(state, HardwareAddress::Ip, HardwareAddress::Ip);
+    let (server, server_runner) = embassy_net::new(
+        server_device,
+        config(SERVER),
+        Box::leak(Box::new(StackResources::<2>::new())),
+        1,
+    );
+    let (client, client_runner) = embassy_net::new(
+        client_device,
+        config(CLIENT),
+        Box::leak(Box::new(StackResources::<2>::new())),
+        2,
+    );
+    Setup {
+        server: Server::new(socket(server), coap_config(), &ROUTES, Rng(1)).unwrap(),
+        server_runner,
+        client,
+        client_runner,
+        control,
+    }
+}
+
+/// Run `test` while serving requests.
+fn run<T>(
+    server: &Server<'static, NoopRawMutex, Api, Rng>,
+    mut server_runner: Runner<'static, Device>,
+    mut client_runner: Runner<'static, Device>,
+    test: impl Future<Output = T>,
+) -> T {
+    let state = Box::leak(Box::new(ServerState::<4, 2>::new()));
+    let serve = select3(server_runner.run(), client_runner.run(), server.run(state));
+    match block_on(select(serve, with_timeout(Duration::from_secs(10), test))) {
+        Either::Second(result) => result.expect("test timed out"),
+        Either::First(_) => unreachable!(),
+    }
+}
+
+fn endpoint() -> IpEndpoint {
+    IpEndpoint::new(SERVER.into(), embassy_net::coap::PORT)
+}
+
+#[test]
+fn retransmit_and_observe() {
+    let Setup {
+        server,
+        server_runner,
+        client,
+        client_runner,
+        control,
+    } = setup();
+    let mut client = Client::new(socket(client), coap_config(), Rng(2)).unwrap();
+
+    let test = async {
+        let mut buf = [0; 512];
+        // The first transmission of the request is lost.
+        control.set_config(Direction::BToA, LOSSY);
+        let (response, ()) = join(
+            client.observe(endpoint(), &client::Request::get("counter"), &mut buf),
+            async {
+                Timer::after_millis(100).await;
+                control.set_config(Direction::BToA, Default::default());
+            },
+        )
+        .await;
+        let response = response.unwrap();
+        assert_eq!(response.code(), Code::CONTENT);
+        assert_eq!(response.options().observe(), Some(0));
+        assert_eq!(response.payload(), b"0");
+        assert_eq!(control.stats(Direction::BToA).lost, 1);
+
+        // The first transmission of the notification is lost.
+        control.set_config(Direction::AToB, LOSSY);
+        COUNTER.store(1, Ordering::Relaxed);
+        server.notify("counter");
+        Timer::after_millis(100).await;
+        control.set_config(Direction::AToB, Default::default());
+        let notification = client.notification(&mut buf).await.unwrap();
+        assert_eq!(notification.ty(), Type::Confirmable);
+        // The retransmission is a new notification, with the next sequence number.
+        assert_eq!(notification.options().observe(), Some(2));
+        assert_eq!(notification.payload(), b"1");
+        assert_eq!(control.stats(Direction::AToB).lost, 1);
+
+        COUNTER.store(2, Ordering::Relaxed);
+        server.notify("counter");
+        let notification = client.notification(&mut buf).await.unwrap();
+        assert_eq!(notification.payload(), b"2");
+    };
+    run(&server, server_runner, client_runner, test);
+}
+
+#[test]
+fn blockwise() {
+    let Setup {
+        server,
+        server_runner,
+        client,
+        client_runner,
+        ..
+    } = setup();
+    let mut client = Client::new(socket(client), coap_config(), Rng(2)).unwrap();
+
+    let test = async {
+        let mut buf = [0; 512];
+        // Block1: the payload is sent in blocks of 64 bytes.
+        let payload: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let response = client
+            .send(endpoint(), &client::Request::put("upload", &payload), &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(response.code(), Code::CHANGED);
+        assert_eq!(*UPLOAD.lock().unwrap(), payload);
+
+        // Block2: the representation is received in blocks of 64 bytes.
+        let mut downloaded = Vec::new();
+        let response = client
+            .download(endpoint(), &client::Request::get("large"), &mut buf, |offset, data| {
+                assert_eq!(offset, downloaded.len());
+                downloaded.extend_from_slice(data);
+            })
+            .await
+            .unwrap();
+        assert_eq!(response.code(), Code::CONTENT);
+        assert_eq!(response.options().block2().map(|b| (b.num, b.more)), Some((7, false)));
+        assert_eq!(downloaded, large());
+    };
+    run(&server, server_runner, client_runner, test);
+}
+
+#[test]
+fn duplicates() {
+    let Setup {
+        server,
+        server_runner,
+        client,
+        client_runner,
+        ..
+    } = setup();
+    let mut socket = socket(client);
+    socket.bind(1234).unwrap();
+
+    let test = async {
+        let mut buf = [0; 64];
+        let mut request = *b"\x41\x02\x12\x34\xaa\xb5count";
+        // The response is sent again to a duplicate, without calling the handler again.
+        for _ in 0..2 {
+            socket.send_to(&request, endpoint()).await.unwrap();
+            let (n, _) = socket.recv_from(&mut buf).await.unwrap();
+            assert_eq!(buf[..n], *b"\x61\x44\x12\x34\xaa\xff1");
+        }
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+
+        // Another message ID is another request.
+        request[3] = 0x35;
+        socket.send_to(&request, endpoint()).await.unwrap();
+        let (n, _) = socket.recv_from(&mut buf).await.unwrap();
+        assert_eq!(buf[..n], *b"\x61\x44\x12\x35\xaa\xff2");
+    };
+    run(&server, server_runner, client_runner, test);
+}
@@ -0,0 +1,246 @@
+//! A host on an Ethernet link, reaching a server through a router translating its connections to the
+//! address of its uplink.
+#![cfg(all(
+    feature = "forwarding",
+    feature = "tcp",
+    feature = "udp",
+    feature = "raw",
+    feature = "medium-ip",
+    feature = "medium-ethernet"
+))]
+
+use embassy_futures::block_on;
+use embassy_futures::join::join;
+use embassy_futures::select::{select, select4, Either};
+use embassy_net::forward;
+use embassy_net::raw::{IpProtocol, IpVersion, RawSocket};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{
+    Config, InterfaceId, InterfaceResources, IpEndpoint, Ipv4Address, Ipv4Cidr, Stack, StackResources, StaticConfigV4,
+};
+use embassy_net_sim::driver::HardwareAddress;
+use embassy_time::{with_timeout, Duration};
+use embedded_io_async::{Read, Write};
+
+type Device = embassy_net_sim::Device<'static, 1514, 16>;
+
+const SERVER: Ipv4Address = Ipv4Address::new(10, 0, 0, 2);
+const UPLINK: Ipv4Address = Ipv4Address::new(10, 0, 0, 1);
+const LAN: Ipv4Address = Ipv4Address::new(192, 168, 1, 1);
+const HOST: Ipv4Address = Ipv4Address::new(192, 168, 1, 2);
+const PORT: u16 = 7;
+const LEN: usize = 4096;
+
+fn config(address: Ipv4Address, gateway: Option<Ipv4Address>) -> Config {
+    Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(address, 24),
+        gateway,
+        dns_servers: Default::default(),
+    })
+}
+
+fn link(a: HardwareAddress, b: HardwareAddress) -> (Device, Device) {
+    let state = Box::leak(Box::new(embassy_net_sim::State::new()));
+    let (a, b, _) = embassy_net_sim::new(state, a, b);
+    (a, b)
+}
+
+fn data() -> impl Iterator<Item = u8> {
+    (0..LEN).map(|i| (i % 251) as u8)
+}
+
+/// Echo a UDP datagram and a TCP connection, returning the endpoints they came from.
+async fn server(stack: Stack<'_>) -> (IpEndpoint, IpEndpoint) {
+    let (mut rx_meta, mut tx_meta) = ([PacketMetadata::EMPTY; 2], [PacketMetadata::EMPTY; 2]);
+    let (mut rx, mut tx) = ([0; 64], [0; 64]);
+    let mut udp = UdpSocket::new(stack, &mut rx_meta, &mut rx, &mut tx_meta, &mut tx);
+    udp.bind(PORT).unwrap();
+    let mut buf = [0; 64];
+    let (n, meta) = udp.recv_from(&mut buf).await.unwrap();
+    udp.send_to(&buf[..n], meta.endpoint).await.unwrap();
+
+    let (mut rx, mut tx) = ([0; LEN], [0; LEN]);
+    let mut tcp = TcpSocket::new(stack, &mut rx, &mut tx);
+    tcp.accept(PORT).await.unwrap();
+    let remote = tcp.remote_endpoint().unwrap();
+    let mut received = [0; LEN];
+    tcp.read_exact(&mut received).await.unwrap();
+    tcp.write_all(&received).await.unwrap();
+    tcp.flush().await.unwrap();
+    (meta.endpoint, remote)
+}
+
+async fn host(stack: Stack<'_>) {
+    let (mut rx_meta, mut tx_meta) = ([PacketMetadata::EMPTY; 2], [PacketMetadata::EMPTY; 2]);
+    let (mut rx, mut tx) = ([0; 64], [0; 64]);
+    let mut udp = UdpSocket::new(stack, &mut rx_meta, &mut rx, &mut tx_meta, &mut tx);
+    udp.bind(1000).unwrap();
+    udp.send_to(b"ping", (SERVER, PORT)).await.unwrap();
+    let mut buf = [0; 64];
+    let (n, meta) = udp.recv_from(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"ping");
+    assert_eq!(meta.endpoint, (SERVER, PORT).into());
+
+    let (mut rx, mut tx) = ([0; LEN], [0; LEN]);
+    let mut tcp = TcpSocket::new(stack, &mut rx, &mut tx);
+    tcp.connect((SERVER, PORT)).await.unwrap();
+    let data: Vec<u8> = data().collect();
+    tcp.write_all(&data).await.unwrap();
+    let mut received = [0; LEN];
+    tcp.read_exact(&mut received).await.unwrap();
+    assert_eq!(received[..], data[..]);
+
+    // The router still answers datagrams for closed ports of its own.
+    let (mut rx_meta, mut tx_meta) = ([embassy_net::raw::PacketMetadata::EMPTY; 2], []);
+    let (mut rx, mut tx) = ([0; 128], []);
+    let icmp = RawSocket::new::<Device>(
+        stack,
+        IpVersion::Ipv4,
+        IpProtocol::Icmp,
+        &mut rx_meta,
+        &mut rx,
+        &mut tx_meta,
+        &mut tx,
+    );
+    udp.send_to(b"closed", (LAN, 9)).await.unwrap();
+    let n = icmp.recv(&mut buf).await.unwrap();
+    // Destination unreachable, port unreachable, after a 20 byte IPv4 header.
+    assert!(n > 22);
+    assert_eq!(&buf[20..22], &[3, 3]);
+}
+
+#[test]
+fn masquerade() {
+    let (uplink, server_device) = link(HardwareAddress::Ip, HardwareAddress::Ip);
+    let (lan, host_device) = link(
+        HardwareAddress::Ethernet([2, 0, 0, 0, 0, 1]),
+        HardwareAddress::Ethernet([2, 0, 0, 0, 0, 2]),
+    );
+
+    let (server_stack, mut server_runner) = embassy_net::new(
+        server_device,
+        config(SERVER, None),
+        Box::leak(Box::new(StackResources::<3>::new())),
+        1,
+    );
+    let (router, mut uplink_runner) = embassy_net::new(
+        uplink,
+        config(UPLINK, Some(SERVER)),
+        Box::leak(Box::new(StackResources::<3>::new())),
+        2,
+    );
+    let (_, mut lan_runner) = router
+        .add_interface(
+            lan,
+            config(LAN, None),
+            Box::leak(Box::new(InterfaceResources::<3>::new())),
+        )
+        .unwrap();
+    router.enable_forwarding(
+        forward::Config::masquerade(InterfaceId::DEFAULT),
+        Box::leak(Box::new(forward::Resources::<4>::new())),
+    );
+    let (host_stack, mut host_runner) = embassy_net::new(
+        host_device,
+        config(HOST, Some(LAN)),
+        Box::leak(Box::new(StackResources::<4>::new())),
+        3,
+    );
+
+    let runners = select4(
+        server_runner.run(),
+        uplink_runner.run(),
+        lan_runner.run(),
+        host_runner.run(),
+    );
+    let test = with_timeout(Duration::from_secs(10), join(server(server_stack), host(host_stack)));
+    let (udp, tcp) = match block_on(select(runners, test)) {
+        Either::Second(result) => result.expect("test timed out").0,
+        Either::First(_) => unreachable!(),
+    };
+
+    // The server only sees the address of the uplink, and ports of the translation table.
+    assert_eq!(udp.addr, UPLINK.into());
+    assert_eq!(tcp.addr, UPLINK.into());
+    assert!(udp.port >= 49152 && tcp.port >= 49152);
+    assert_ne!(udp.port, tcp.port);
+}
+
+/// Forwarded packets are dropped, and counted, when the uplink has no socket slot to queue them.
+#[cfg(feature = "statistics")]
+#[test]
+fn socket_set_full() {
+    let (uplink, server_device) = link(HardwareAddress::Ip, HardwareAddress::Ip);
+    let (lan, host_device) = link(HardwareAddress::Ip, HardwareAddress::Ip);
+
+    let (server_stack, mut server_runner) = embassy_net::new(
+        server_device,
+        config(SERVER, None),
+        Box::leak(Box::new(StackResources::<3>::new())),
+        1,
+    );
+    let (router, mut lan_runner) = embassy_net::new(
+        lan,
+        config(LAN, None),
+        Box::leak(Box::new(StackResources::<3>::new())),
+        2,
+    );
+    let (uplink_id, mut uplink_runner) = router
+        .add_interface(
+            uplink,
+            config(UPLINK, Some(SERVER)),
+            Box::leak(Box::new(InterfaceResources::<1>::new())),
+        )
+        .unwrap();
+    router.enable_forwarding(
+        forward::Config::masquerade(uplink_id),
+        Box::leak(Box::new(forward::Resources::<4>::new())),
+    );
+    let (host_stack, mut host_runner) = embassy_net::new(
+        host_device,
+        config(HOST, Some(LAN)),
+        Box::leak(Box::new(StackResources::<3>::new())),
+        3,
+    );
+
+    let test = async {
+        let (mut rx_meta, mut tx_meta) = ([PacketMetadata::EMPTY; 2], [PacketMetadata::EMPTY; 2]);
+        let (mut rx, mut tx) = ([0; 64], [0; 64]);
+        let mut server = UdpSocket::new(server_stack, &mut rx_meta, &mut rx, &mut tx_meta, &mut tx);
+        server.bind(PORT).unwrap();
+        let (mut rx_meta, mut tx_meta) = ([PacketMetadata::EMPTY; 2], [PacketMetadata::EMPTY; 2]);
+        let (mut rx, mut tx) = ([0; 64], [0; 64]);
+        let mut host = UdpSocket::new(host_stack, &mut rx_meta, &mut rx, &mut tx_meta, &mut tx);
+        host.bind(1000).unwrap();
+
+        // A socket of the router takes the only slot of the uplink.
+        let (mut rx_meta, mut tx_meta) = ([PacketMetadata::EMPTY; 1], [PacketMetadata::EMPTY; 1]);
+        let (mut rx, mut tx) = ([0; 16], [0; 16]);
+        let mut filler = UdpSocket::new(router, &mut rx_meta, &mut rx, &mut tx_meta, &mut tx);
+        filler.bind_to_interface(uplink_id).unwrap();
+
+        host.send_to(b"dropped", (SERVER, PORT)).await.unwrap();
+        while router.stats().rx_socket_set_full == 0 {
+            embassy_time::Timer::after_millis(1).await;
+        }
+        drop(filler);
+
+        host.send_to(b"forwarded", (SERVER, PORT)).await.unwrap();
+        let mut buf = [0; 64];
+        let (n, _) = server.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"forwarded");
+        assert_eq!(router.stats().rx_socket_set_full, 1);
+    };
+
+    let runners = select4(
+        server_runner.run(),
+        uplink_runner.run(),
+        lan_runner.run(),
+        host_runner.run(),
+    );
+    match block_on(select(runners, with_timeout(Duration::from_secs(10), test))) {
+        Either::Second(result) => result.expect("test timed out"),
+        Either::First(_) => unreachable!(),
+    }
+}
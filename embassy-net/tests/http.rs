@@ -0,0 +1,193 @@
+//! An HTTP client and server talking over an `embassy-net-sim` link.
+#![cfg(all(feature = "http", feature = "proto-ipv4", feature = "medium-ip"))]
+
+use core::convert::Infallible;
+use core::fmt::Write as _;
+use core::net::IpAddr;
+
+use embassy_futures::block_on;
+use embassy_futures::select::{select, select3, Either};
+use embassy_net::http::client::{HttpClient, Request as ClientRequest};
+use embassy_net::http::server::{Config as ServerConfig, Handler, Request, Response, Route, Server, ServerState};
+use embassy_net::http::{Error, Method, Status};
+use embassy_net::tcp::client::{TcpClient, TcpClientState};
+use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+use embassy_net_sim::driver::HardwareAddress;
+use embassy_time::{with_timeout, Duration};
+use embedded_io_async::{Read, Write};
+use embedded_nal_async::{AddrType, Dns};
+
+type Device = embassy_net_sim::Device<'static, 1500, 16>;
+
+const SERVER: Ipv4Address = Ipv4Address::new(10, 0, 0, 1);
+const CLIENT: Ipv4Address = Ipv4Address::new(10, 0, 0, 2);
+
+enum Api {
+    Peer,
+    Chunked,
+    Files,
+    Echo,
+}
+
+impl Handler for Api {
+    async fn handle(&self, request: &mut Request<'_, '_>, response: &mut Response<'_, '_>) -> Result<(), Error> {
+        match self {
+            // The port of the client tells connections apart.
+            Api::Peer => {
+                let mut port = heapless::String::<8>::new();
+                write!(port, "{}", request.remote_endpoint().unwrap().port).unwrap();
+                response.send(Status::OK, &[], port.as_bytes()).await
+            }
+            Api::Chunked => {
+                response
+                    .start(Status::OK, &[("Content-Type", "text/plain")], None)
+                    .await?;
+                for chunk in large().chunks(50) {
+                    response.write_all(chunk).await?;
+                }
+                Ok(())
+            }
+            Api::Files => response.send(Status::OK, &[], request.path().as_bytes()).await,
+            Api::Echo => {
+                let mut buf = [0; 64];
+                let len = request.read_to_end(&mut buf).await?;
+                response.send(Status::CREATED, &[], &buf[..len]).await
+            }
+        }
+    }
+}
+
+static ROUTES: [Route<'static, Api>; 4] = [
+    Route::new(Method::Get, "/peer", Api::Peer),
+    Route::new(Method::Get, "/chunked", Api::Chunked),
+    Route::new(Method::Get, "/files/*", Api::Files),
+    Route::new(Method::Post, "/echo", Api::Echo),
+];
+
+/// A body of several chunks.
+fn large() -> Vec<u8> {
+    (0..500).map(|i| (i % 251) as u8).collect()
+}
+
+/// Only IP addresses are used in the tests.
+struct NoDns;
+
+impl Dns for NoDns {
+    type Error = Infallible;
+
+    async fn get_host_by_name(&self, _host: &str, _addr_type: AddrType) -> Result<IpAddr, Infallible> {
+        unreachable!()
+    }
+
+    async fn get_host_by_address(&self, _addr: IpAddr, _result: &mut [u8]) -> Result<usize, Infallible> {
+        unreachable!()
+    }
+}
+
+fn config(address: Ipv4Address) -> Config {
+    Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(address, 24),
+        gateway: None,
+        dns_servers: Default::default(),
+    })
+}
+
+#[test]
+fn client_and_server() {
+    let state = Box::leak(Box::new(embassy_net_sim::State::new()));
+    let (server_device, client_device, _): (Device, Device, _) =
+        embassy_net_sim::new(state, HardwareAddress::Ip, HardwareAddress::Ip);
+    let (server, mut server_runner) = embassy_net::new(
+        server_device,
+        config(SERVER),
+        Box::leak(Box::new(StackResources::<3>::new())),
+        1,
+    );
+    let (client, mut client_runner) = embassy_net::new(
+        client_device,
+        config(CLIENT),
+        Box::leak(Box::new(StackResources::<3>::new())),
+        2,
+    );
+
+    let server = Server::new(server, ServerConfig::new(80), &ROUTES);
+    let mut server_state = ServerState::<2, 1024>::new();
+    let tcp_state = TcpClientState::<2, 1024, 1024>::new();
+    let tcp = TcpClient::new(client, &tcp_state);
+    let client: HttpClient<_, _, 1> = HttpClient::new(&tcp, &NoDns);
+
+    let test = async {
+        let request = async |method, url| {
+            let mut buf = [0; 256];
+            let mut response = client.send(&ClientRequest::new(method, url), &mut buf).await.unwrap();
+            let mut body = [0; 64];
+            let len = response.read_to_end(&mut body).await.unwrap();
+            (response.status(), String::from_utf8(body[..len].to_vec()).unwrap())
+        };
+        let get = async |url| request(Method::Get, url).await;
+
+        // The connection is kept for the next request, until the idle connections are closed.
+        let (status, port) = get("http://10.0.0.1/peer").await;
+        assert_eq!(status, Status::OK);
+        assert_eq!(get("http://10.0.0.1/peer").await.1, port);
+        client.close_idle();
+        let (_, other_port) = get("http://10.0.0.1/peer").await;
+        assert_ne!(other_port, port);
+
+        // Routes by prefix, unknown paths and methods.
+        assert_eq!(
+            get("http://10.0.0.1/files/a/b.txt?x=1").await,
+            (Status::OK, "/files/a/b.txt".into())
+        );
+        assert_eq!(get("http://10.0.0.1/missing").await, (Status::NOT_FOUND, "".into()));
+        assert_eq!(get("http://10.0.0.1/echo").await.0, Status::METHOD_NOT_ALLOWED);
+        // GET routes answer HEAD requests, without the body.
+        assert_eq!(
+            request(Method::Head, "http://10.0.0.1/files/x").await,
+            (Status::OK, "".into())
+        );
+        // Neither the error responses nor HEAD closed the connection.
+        assert_eq!(get("http://10.0.0.1/peer").await.1, other_port);
+
+        let mut buf = [0; 128];
+        let post = ClientRequest::post("http://10.0.0.1/echo", b"hello");
+        let mut response = client.send(&post, &mut buf).await.unwrap();
+        assert_eq!(response.status(), Status::CREATED);
+        let mut body = [0; 64];
+        let len = response.read_to_end(&mut body).await.unwrap();
+        assert_eq!(body[..len], *b"hello");
+        drop(response);
+
+        // A chunked body, larger than the buffer of the client and read in small pieces.
+        let mut response = client
+            .send(&ClientRequest::get("http://10.0.0.1/chunked"), &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(response.headers().get("transfer-encoding"), Some("chunked"));
+        let mut body = Vec::new();
+        loop {
+            let mut piece = [0; 16];
+            match response.read(&mut piece).await.unwrap() {
+                0 => break,
+                n => body.extend_from_slice(&piece[..n]),
+            }
+        }
+        assert_eq!(body, large());
+        drop(response);
+        assert_eq!(get("http://10.0.0.1/peer").await.1, other_port);
+
+        // A chunked body that doesn't fit the buffer given to `read_to_end`.
+        let mut response = client
+            .send(&ClientRequest::get("http://10.0.0.1/chunked"), &mut buf)
+            .await
+            .unwrap();
+        let mut body = [0; 100];
+        assert_eq!(response.read_to_end(&mut body).await, Err(Error::BufferTooSmall));
+    };
+
+    let runners = select3(server_runner.run(), client_runner.run(), server.run(&mut server_state));
+    match block_on(select(runners, with_timeout(Duration::from_secs(10), test))) {
+        Either::Second(result) => result.expect("test timed out"),
+        Either::First(_) => unreachable!(),
+    }
+}
@@ -0,0 +1,286 @@
+//! An MQTT client talking to a scripted broker over an `embassy-net-sim` link.
+#![cfg(all(feature = "mqtt", feature = "proto-ipv4", feature = "medium-ip"))]
+
+use core::future::Future;
+
+use embassy_futures::block_on;
+use embassy_futures::select::{select, select3, Either};
+use embassy_net::mqtt::{self, Message, MqttClient, ProtocolVersion, QoS, State, Will};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{Config, IpEndpoint, Ipv4Address, Ipv4Cidr, Stack, StackResources, StaticConfigV4};
+use embassy_net_sim::driver::HardwareAddress;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_time::{with_timeout, Duration, Instant};
+use embedded_io_async::{Read, Write};
+
+type Device = embassy_net_sim::Device<'static, 1500, 16>;
+type ClientState = State<NoopRawMutex, 64, 4, 1>;
+
+const BROKER: Ipv4Address = Ipv4Address::new(10, 0, 0, 1);
+const CLIENT: Ipv4Address = Ipv4Address::new(10, 0, 0, 2);
+const PORT: u16 = 1883;
+
+fn config(address: Ipv4Address) -> Config {
+    Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(address, 24),
+        gateway: None,
+        dns_servers: Default::default(),
+    })
+}
+
+/// A client configuration reconnecting quickly, without keep-alive pings.
+fn mqtt_config() -> mqtt::Config<'static> {
+    let mut config = mqtt::Config::new(IpEndpoint::new(BROKER.into(), PORT), "dev");
+    config.keep_alive = Duration::from_ticks(0);
+    config.reconnect_delay = Duration::from_millis(100);
+    config
+}
+
+/// The broker side of a connection, checking the packets the client sends.
+struct Broker {
+    socket: TcpSocket<'static>,
+}
+
+impl Broker {
+    async fn accept(stack: Stack<'static>) -> Self {
+        let mut socket = TcpSocket::new(stack, Box::leak(Box::new([0; 1024])), Box::leak(Box::new([0; 1024])));
+        socket.accept(PORT).await.unwrap();
+        Self { socket }
+    }
+
+    /// Accept a connection, answering its CONNECT packet.
+    async fn connect(stack: Stack<'static>, session_present: bool) -> Self {
+        let mut broker = Self::accept(stack).await;
+        let connect = broker.read().await;
+        assert_eq!(connect[0], 0x10);
+        broker.write(&[0x20, 0x02, session_present.into(), 0x00]).await;
+        broker
+    }
+
+    /// Read a packet, with its fixed header.
+    async fn read(&mut self) -> Vec<u8> {
+        let mut packet = vec![0; 2];
+        self.socket.read_exact(&mut packet).await.unwrap();
+        while packet[packet.len() - 1] & 0x80 != 0 {
+            let mut byte = [0; 1];
+            self.socket.read_exact(&mut byte).await.unwrap();
+            packet.push(byte[0]);
+        }
+        let len = packet[1..]
+            .iter()
+            .enumerate()
+            .fold(0, |len, (i, byte)| len | (usize::from(byte & 0x7f) << (7 * i)));
+        let start = packet.len();
+        packet.resize(start + len, 0);
+        self.socket.read_exact(&mut packet[start..]).await.unwrap();
+        packet
+    }
+
+    async fn expect(&mut self, packet: &[u8]) {
+        assert_eq!(self.read().await, packet);
+    }
+
+    async fn write(&mut self, packet: &[u8]) {
+        self.socket.write_all(packet).await.unwrap();
+        self.socket.flush().await.unwrap();
+    }
+
+    /// Drop the connection, with a reset.
+    async fn abort(mut self) {
+        self.socket.abort();
+        let _ = self.socket.flush().await;
+    }
+}
+
+/// Run a client configured with `mqtt` at `CLIENT`, and `test` with the stack of the broker at `BROKER`.
+fn run<F: Future>(mqtt: mqtt::Config<'static>, state: &'static ClientState, test: impl FnOnce(Stack<'static>) -> F) {
+    let sim = Box::leak(Box::new(embassy_net_sim::State::new()));
+    let (broker_device, client_device, _): (Device, Device, _) =
+        embassy_net_sim::new(sim, HardwareAddress::Ip, HardwareAddress::Ip);
+    let (broker, mut broker_runner) = embassy_net::new(
+        broker_device,
+        config(BROKER),
+        Box::leak(Box::new(StackResources::<4>::new())),
+        1,
+    );
+    let (stack, mut client_runner) = embassy_net::new(
+        client_device,
+        config(CLIENT),
+        Box::leak(Box::new(StackResources::<2>::new())),
+        2,
+    );
+    let mut client = MqttClient::new(stack, mqtt, state);
+    let (mut rx, mut tx) = ([0; 1024], [0; 1024]);
+
+    let runners = select3(broker_runner.run(), client_runner.run(), client.run(&mut rx, &mut tx));
+    match block_on(select(runners, with_timeout(Duration::from_secs(10), test(broker)))) {
+        Either::Second(result) => {
+            result.expect("test timed out");
+        }
+        Either::First(_) => unreachable!(),
+    }
+}
+
+fn state() -> &'static ClientState {
+    Box::leak(Box::new(State::new()))
+}
+
+#[test]
+fn connect_v311() {
+    let mut config = mqtt_config();
+    config.keep_alive = Duration::from_secs(60);
+    config.username = Some("u");
+    config.password = Some(b"p");
+    run(config, state(), |broker| async move {
+        let mut broker = Broker::accept(broker).await;
+        // User name and password flags, keep-alive in seconds, then the client ID and credentials.
+        broker
+            .expect(b"\x10\x15\x00\x04MQTT\x04\xc0\x00\x3c\x00\x03dev\x00\x01u\x00\x01p")
+            .await;
+    });
+}
+
+#[test]
+fn connect_v5() {
+    let mut config = mqtt_config();
+    config.version = ProtocolVersion::V5;
+    config.clean_session = true;
+    config.keep_alive = Duration::from_secs(30);
+    config.will = Some(Will {
+        topic: "w",
+        payload: b"x",
+        qos: QoS::AtLeastOnce,
+        retain: true,
+    });
+    config.subscriptions = &[("a/#", QoS::ExactlyOnce)];
+    run(config, state(), |broker| async move {
+        let mut broker = Broker::accept(broker).await;
+        // Clean start and will flags, then properties: receive maximum 4, maximum packet size
+        // 64 + 16. The will has empty properties.
+        broker
+            .expect(b"\x10\x1f\x00\x04MQTT\x05\x2e\x00\x1e\x08\x21\x00\x04\x27\x00\x00\x00\x50\x00\x03dev\x00\x00\x01w\x00\x01x")
+            .await;
+        broker.write(b"\x20\x03\x00\x00\x00").await;
+        // The subscription, with empty properties.
+        broker.expect(b"\x82\x09\x00\x01\x00\x00\x03a/#\x02").await;
+    });
+}
+
+#[test]
+fn redelivery() {
+    let state = state();
+    run(mqtt_config(), state, |stack| async move {
+        let mut broker = Broker::connect(stack, false).await;
+        state
+            .publish(Message::new("t", b"1", QoS::AtLeastOnce, false).unwrap())
+            .await;
+        state
+            .publish(Message::new("t", b"2", QoS::ExactlyOnce, false).unwrap())
+            .await;
+        broker.expect(b"\x32\x06\x00\x01t\x00\x011").await;
+        broker.expect(b"\x34\x06\x00\x01t\x00\x022").await;
+        // The second message is received, the first one isn't acknowledged.
+        broker.write(b"\x50\x02\x00\x02").await;
+        broker.expect(b"\x62\x02\x00\x02").await;
+        broker.abort().await;
+
+        // The broker kept the session: the first message is sent again as a duplicate, and the
+        // second one released again.
+        let mut broker = Broker::connect(stack, true).await;
+        broker.expect(b"\x3a\x06\x00\x01t\x00\x011").await;
+        broker.expect(b"\x62\x02\x00\x02").await;
+        broker.abort().await;
+
+        // The broker lost the session: the first message is sent again as a new one, and the
+        // second one was delivered already.
+        let mut broker = Broker::connect(stack, false).await;
+        broker.expect(b"\x32\x06\x00\x01t\x00\x011").await;
+        broker.write(b"\x40\x02\x00\x01").await;
+        broker.abort().await;
+
+        // Nothing is left in flight.
+        let mut broker = Broker::connect(stack, true).await;
+        state
+            .publish(Message::new("t", b"3", QoS::AtMostOnce, false).unwrap())
+            .await;
+        broker.expect(b"\x30\x04\x00\x01t3").await;
+    });
+}
+
+#[test]
+fn qos2_exchange() {
+    let state = state();
+    run(mqtt_config(), state, |stack| async move {
+        let mut broker = Broker::connect(stack, false).await;
+        state
+            .publish(Message::new("t", b"2", QoS::ExactlyOnce, false).unwrap())
+            .await;
+        broker.expect(b"\x34\x06\x00\x01t\x00\x012").await;
+        // PUBCOMP is only expected after PUBREL.
+        broker.write(b"\x70\x02\x00\x01").await;
+        broker.write(b"\x50\x02\x00\x01").await;
+        broker.expect(b"\x62\x02\x00\x01").await;
+        broker.write(b"\x70\x02\x00\x01").await;
+
+        // The identifier is free again once the exchange completes.
+        state
+            .publish(Message::new("t", b"1", QoS::AtLeastOnce, false).unwrap())
+            .await;
+        broker.expect(b"\x32\x06\x00\x01t\x00\x021").await;
+        broker.write(b"\x40\x02\x00\x02").await;
+        broker.abort().await;
+        let mut broker = Broker::connect(stack, true).await;
+        state
+            .publish(Message::new("t", b"3", QoS::AtMostOnce, false).unwrap())
+            .await;
+        broker.expect(b"\x30\x04\x00\x01t3").await;
+    });
+}
+
+#[test]
+fn receive_duplicates() {
+    let state = state();
+    let mut subscriber = state.subscriber().unwrap();
+    run(mqtt_config(), state, |stack| async move {
+        let mut broker = Broker::connect(stack, false).await;
+        let publish = b"\x34\x09\x00\x03a/b\x00\x07hi";
+        broker.write(publish).await;
+        broker.expect(b"\x50\x02\x00\x07").await;
+        // The broker didn't get PUBREC, and sends the message again.
+        let mut duplicate = *publish;
+        duplicate[0] |= 0x08;
+        broker.write(&duplicate).await;
+        broker.expect(b"\x50\x02\x00\x07").await;
+        broker.write(b"\x62\x02\x00\x07").await;
+        broker.expect(b"\x70\x02\x00\x07").await;
+
+        broker.write(b"\x32\x09\x00\x03a/b\x00\x08q1").await;
+        broker.expect(b"\x40\x02\x00\x08").await;
+
+        // The QoS 2 message was delivered once.
+        let message = subscriber.next_message_pure().await;
+        assert_eq!((message.topic(), message.payload()), ("a/b", &b"hi"[..]));
+        assert_eq!(message.qos(), QoS::ExactlyOnce);
+        let message = subscriber.next_message_pure().await;
+        assert_eq!(message.payload(), b"q1");
+        assert!(subscriber.try_next_message_pure().is_none());
+    });
+}
+
+#[test]
+fn keep_alive() {
+    let mut config = mqtt_config();
+    config.keep_alive = Duration::from_secs(1);
+    run(config, state(), |stack| async move {
+        let mut broker = Broker::connect(stack, false).await;
+        broker.expect(b"\xc0\x00").await;
+        broker.write(b"\xd0\x00").await;
+        broker.expect(b"\xc0\x00").await;
+        let unanswered = Instant::now();
+
+        // The client gives up on the broker at the next ping, and connects again.
+        let mut broker = Broker::accept(stack).await;
+        assert_eq!(broker.read().await[0], 0x10);
+        assert!(unanswered.elapsed() >= Duration::from_millis(900));
+    });
+}
@@ -0,0 +1,86 @@
+//! Two stacks talking over an impaired `embassy-net-sim` link.
+#![cfg(all(feature = "tcp", feature = "proto-ipv4", feature = "medium-ip"))]
+
+use embassy_futures::block_on;
+use embassy_futures::join::join;
+use embassy_futures::select::{select3, Either3};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{Config, Ipv4Address, Ipv4Cidr, Stack, StackResources, StaticConfigV4};
+use embassy_net_sim::driver::HardwareAddress;
+use embassy_net_sim::Direction;
+use embassy_time::{with_timeout, Duration};
+
+const PORT: u16 = 1234;
+const LEN: usize = 16 * 1024;
+
+fn config(last: u8) -> Config {
+    Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, last), 24),
+        gateway: None,
+        dns_servers: Default::default(),
+    })
+}
+
+fn data() -> impl Iterator<Item = u8> {
+    (0..LEN).map(|i| (i % 251) as u8)
+}
+
+async fn send(stack: Stack<'_>) {
+    let (mut rx, mut tx) = ([0; 2048], [0; 2048]);
+    let mut socket = TcpSocket::new(stack, &mut rx, &mut tx);
+    socket.connect((Ipv4Address::new(10, 0, 0, 2), PORT)).await.unwrap();
+    let data: Vec<u8> = data().collect();
+    let mut written = 0;
+    while written < LEN {
+        written += socket.write(&data[written..]).await.unwrap();
+    }
+    socket.close();
+    // Wait for the FIN to be sent before the socket is dropped.
+    socket.flush().await.unwrap();
+}
+
+async fn receive(stack: Stack<'_>) -> Vec<u8> {
+    let (mut rx, mut tx) = ([0; 2048], [0; 2048]);
+    let mut socket = TcpSocket::new(stack, &mut rx, &mut tx);
+    socket.accept(PORT).await.unwrap();
+    let mut received = Vec::new();
+    let mut buf = [0; 512];
+    loop {
+        match socket.read(&mut buf).await.unwrap() {
+            0 => return received,
+            n => received.extend_from_slice(&buf[..n]),
+        }
+    }
+}
+
+#[test]
+fn tcp_over_impaired_link() {
+    let state = Box::leak(Box::new(embassy_net_sim::State::<1500, 16>::new()));
+    let (device_a, device_b, control) = embassy_net_sim::new(state, HardwareAddress::Ip, HardwareAddress::Ip);
+    let impairments = embassy_net_sim::Config {
+        loss: 0.1,
+        duplicate: 0.05,
+        reorder: 0.05,
+        latency: Duration::from_millis(5),
+        jitter: Duration::from_millis(2),
+        ..Default::default()
+    };
+    control.set_config(Direction::AToB, impairments);
+    control.set_config(Direction::BToA, impairments);
+    control.set_seed(7);
+
+    let (stack_a, mut runner_a) =
+        embassy_net::new(device_a, config(1), Box::leak(Box::new(StackResources::<2>::new())), 1);
+    let (stack_b, mut runner_b) =
+        embassy_net::new(device_b, config(2), Box::leak(Box::new(StackResources::<2>::new())), 2);
+
+    let transfer = with_timeout(Duration::from_secs(30), join(send(stack_a), receive(stack_b)));
+    let received = match block_on(select3(runner_a.run(), runner_b.run(), transfer)) {
+        Either3::Third(result) => result.expect("transfer timed out").1,
+        _ => unreachable!(),
+    };
+    assert!(received.iter().copied().eq(data()));
+
+    let stats = control.stats(Direction::AToB);
+    assert!(stats.lost > 0 && stats.duplicated > 0 && stats.reordered > 0);
+}
@@ -0,0 +1,164 @@
+//! A `TcpListener` accepting connections that arrive while the previous one is being handled.
+#![cfg(all(feature = "tcp", feature = "proto-ipv4", feature = "medium-ip"))]
+
+use core::net::SocketAddr;
+
+use embassy_futures::block_on;
+use embassy_futures::join::{join, join3};
+use embassy_futures::select::{select, select3, Either, Either3};
+use embassy_net::tcp::client::{TcpClient, TcpClientState};
+use embassy_net::tcp::server::{TcpAccept, TcpListener, TcpListenerState};
+use embassy_net::{Config, InterfaceResources, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+use embassy_net_sim::driver::HardwareAddress;
+use embassy_time::{with_timeout, Duration, Timer};
+use embedded_io_async::{Read, Write};
+use embedded_nal_async::TcpConnect;
+
+type Device = embassy_net_sim::Device<'static, 1500, 16>;
+
+const SERVER: Ipv4Address = Ipv4Address::new(10, 0, 0, 1);
+const CLIENT: Ipv4Address = Ipv4Address::new(10, 0, 0, 2);
+const PORT: u16 = 1234;
+
+fn config(address: Ipv4Address) -> Config {
+    Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(address, 24),
+        gateway: None,
+        dns_servers: Default::default(),
+    })
+}
+
+fn link() -> (Device, Device) {
+    let state = Box::leak(Box::new(embassy_net_sim::State::new()));
+    let (a, b, _) = embassy_net_sim::new(state, HardwareAddress::Ip, HardwareAddress::Ip);
+    (a, b)
+}
+
+/// Echo one byte on each of `n` connections, handling them one after the other.
+async fn serve(listener: impl TcpAccept, n: usize) -> Vec<SocketAddr> {
+    let mut connections = Vec::new();
+    let mut remotes = Vec::new();
+    for _ in 0..n {
+        let (remote, mut conn) = listener.accept().await.unwrap();
+        // Keep the others waiting in the backlog for a while.
+        Timer::after_millis(100).await;
+        let mut buf = [0; 1];
+        conn.read_exact(&mut buf).await.unwrap();
+        conn.write_all(&buf).await.unwrap();
+        conn.flush().await.unwrap();
+        connections.push(conn);
+        remotes.push(remote);
+    }
+    remotes
+}
+
+/// Echo a byte, returning the connection so it's not dropped before acknowledging the echo.
+async fn echo<C: TcpConnect>(client: &C, server: Ipv4Address, port: u16, byte: u8) -> C::Connection<'_> {
+    let mut conn = client.connect(SocketAddr::new(server.into(), port)).await.unwrap();
+    conn.write_all(&[byte]).await.unwrap();
+    let mut buf = [0; 1];
+    conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf, [byte]);
+    conn
+}
+
+#[test]
+fn backlog() {
+    let (server_device, client_device) = link();
+    let (server, mut server_runner) = embassy_net::new(
+        server_device,
+        config(SERVER),
+        Box::leak(Box::new(StackResources::<4>::new())),
+        1,
+    );
+    let (client, mut client_runner) = embassy_net::new(
+        client_device,
+        config(CLIENT),
+        Box::leak(Box::new(StackResources::<4>::new())),
+        2,
+    );
+
+    let state = TcpListenerState::<3, 64, 64>::new();
+    let listener = TcpListener::new(server, &state, PORT);
+    let client_state = TcpClientState::<3, 64, 64>::new();
+    let client = TcpClient::new(client, &client_state);
+
+    let test = with_timeout(
+        Duration::from_secs(10),
+        join(
+            serve(&listener, 3),
+            join3(
+                echo(&client, SERVER, PORT, 1),
+                echo(&client, SERVER, PORT, 2),
+                echo(&client, SERVER, PORT, 3),
+            ),
+        ),
+    );
+    let remotes = match block_on(select3(server_runner.run(), client_runner.run(), test)) {
+        Either3::Third(result) => result.map(|(remotes, _)| remotes).expect("test timed out"),
+        _ => unreachable!(),
+    };
+    assert!(remotes.iter().all(|remote| remote.ip() == CLIENT));
+}
+
+#[test]
+fn listen_on_interface() {
+    const LAN: Ipv4Address = Ipv4Address::new(192, 168, 1, 1);
+    const HOST: Ipv4Address = Ipv4Address::new(192, 168, 1, 2);
+
+    let (server_device, client_device) = link();
+    let (lan_device, host_device) = link();
+    let (server, mut server_runner) = embassy_net::new(
+        server_device,
+        config(SERVER),
+        Box::leak(Box::new(StackResources::<2>::new())),
+        1,
+    );
+    let (lan, mut lan_runner) = server
+        .add_interface(
+            lan_device,
+            config(LAN),
+            Box::leak(Box::new(InterfaceResources::<2>::new())),
+        )
+        .unwrap();
+    let (_, mut client_runner) = embassy_net::new(
+        client_device,
+        config(CLIENT),
+        Box::leak(Box::new(StackResources::<1>::new())),
+        2,
+    );
+    let (host, mut host_runner) = embassy_net::new(
+        host_device,
+        config(HOST),
+        Box::leak(Box::new(StackResources::<3>::new())),
+        3,
+    );
+
+    // Listening on the address of the second interface.
+    let state = TcpListenerState::<1, 64, 64>::new();
+    let by_address = TcpListener::new(server, &state, (LAN, PORT));
+    // Listening on any address of the second interface.
+    let bound_state = TcpListenerState::<1, 64, 64>::new();
+    let mut bound = TcpListener::new(server, &bound_state, PORT + 1);
+    bound.bind_to_interface(lan).unwrap();
+
+    let host_state = TcpClientState::<2, 64, 64>::new();
+    let host = TcpClient::new(host, &host_state);
+    let test = with_timeout(
+        Duration::from_secs(10),
+        join(
+            join(serve(&by_address, 1), serve(&bound, 1)),
+            join(echo(&host, LAN, PORT, 1), echo(&host, LAN, PORT + 1, 2)),
+        ),
+    );
+    let runners = join(
+        select(server_runner.run(), lan_runner.run()),
+        select(client_runner.run(), host_runner.run()),
+    );
+    let (by_address, bound) = match block_on(select(runners, test)) {
+        Either::Second(result) => result.map(|(remotes, _)| remotes).expect("test timed out"),
+        Either::First(_) => unreachable!(),
+    };
+    assert_eq!(by_address[0].ip(), HOST);
+    assert_eq!(bound[0].ip(), HOST);
+}
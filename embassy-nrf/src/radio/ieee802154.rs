@@ -358,6 +358,21 @@ impl<'d, T: Instance> Radio<'d, T> {
     // NOTE we do NOT check the address of `packet` because the mutable reference ensures it's
     // allocated in RAM
     pub async fn try_send(&mut self, packet: &mut Packet) -> Result<(), Error> {
+        self.send_packet(packet, true).await
+    }
+
+    /// Sends the given `packet` right away, without Clear Channel Assessment (CCA)
+    ///
+    /// This is meant for acknowledgment frames, which are sent during the turnaround time after a
+    /// reception.
+    ///
+    /// NOTE this method will *not* modify the `packet` argument. The mutable reference is used to
+    /// ensure the `packet` buffer is allocated in RAM, which is required by the RADIO peripheral
+    pub async fn send(&mut self, packet: &mut Packet) -> Result<(), Error> {
+        self.send_packet(packet, false).await
+    }
+
+    async fn send_packet(&mut self, packet: &mut Packet, cca: bool) -> Result<(), Error> {
         let s = T::state();
         let r = T::regs();
 
@@ -382,11 +397,13 @@ impl<'d, T: Instance> Radio<'d, T> {
         // CCA idle → enable TX → start TX → TX → end (PHYEND) → disabled
         //
         // CCA might end up in the event CCABUSY in which there will be no transmission
+        //
+        // Without CCA, the radio goes directly from TX enable to TX
         r.shorts().write(|w| {
-            w.set_rxready_ccastart(true);
-            w.set_ccaidle_txen(true);
+            w.set_rxready_ccastart(cca);
+            w.set_ccaidle_txen(cca);
             w.set_txready_start(true);
-            w.set_ccabusy_disable(true);
+            w.set_ccabusy_disable(cca);
             w.set_phyend_disable(true);
         });
 
@@ -398,11 +415,16 @@ impl<'d, T: Instance> Radio<'d, T> {
         dma_start_fence();
         // start CCA. In case the channel is clear, the data at packetptr will be sent automatically
 
-        match self.state() {
+        match (cca, self.state()) {
+            // Enable transmitter, which is only possible from the disabled state
+            (false, _) => {
+                self.disable();
+                r.tasks_txen().write_value(1)
+            }
             // Re-start receiver
-            RadioState::RX_IDLE => r.tasks_ccastart().write_value(1),
+            (true, RadioState::RX_IDLE) => r.tasks_ccastart().write_value(1),
             // Enable receiver
-            _ => r.tasks_rxen().write_value(1),
+            (true, _) => r.tasks_rxen().write_value(1),
         }
 
         self.clear_all_interrupts();
@@ -436,6 +458,27 @@ impl<'d, T: Instance> Radio<'d, T> {
     }
 }
 
+#[cfg(feature = "embassy-net-ieee802154")]
+impl<'d, T: Instance> embassy_net_ieee802154::Radio for Radio<'d, T> {
+    async fn receive(&mut self, buf: &mut [u8]) -> usize {
+        let mut packet = Packet::new();
+        // Frames failing the CRC check are skipped
+        while Radio::receive(self, &mut packet).await.is_err() {}
+        buf[..packet.len() as usize].copy_from_slice(&packet);
+        packet.len() as usize
+    }
+
+    async fn transmit(&mut self, frame: &[u8], cca: bool) -> Result<(), embassy_net_ieee802154::ChannelBusy> {
+        let mut packet = Packet::new();
+        packet.copy_from_slice(frame);
+        match cca {
+            true => self.try_send(&mut packet).await,
+            false => self.send(&mut packet).await,
+        }
+        .map_err(|_| embassy_net_ieee802154::ChannelBusy)
+    }
+}
+
 /// An IEEE 802.15.4 packet
 ///
 /// This `Packet` is a PHY layer packet. It's made up of the physical header (PHR) and the PSDU
@@ -3,11 +3,13 @@
 use core::task::Context;
 
 use embassy_net_driver::{Capabilities, HardwareAddress, LinkState};
+use embassy_net_ieee802154::frame::{Address, FrameType, Header};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::Channel;
 
 use crate::mac::event::MacEvent;
 use crate::mac::runner::Runner;
+use crate::mac::typedefs::{AddressMode, MacAddress, PanId};
 use crate::mac::MTU;
 
 pub struct Driver<'d> {
@@ -69,12 +71,11 @@ impl<'d> embassy_net_driver::Driver for Driver<'d> {
     }
 
     fn link_state(&mut self, _cx: &mut Context) -> LinkState {
-        LinkState::Down
+        LinkState::Up
     }
 
     fn hardware_address(&self) -> HardwareAddress {
-        // self.mac_addr
-        HardwareAddress::Ieee802154([0; 8])
+        HardwareAddress::Ieee802154(self.runner.extended_address.to_be_bytes())
     }
 }
 
@@ -88,13 +89,47 @@ impl<'d> embassy_net_driver::RxToken for RxToken<'d> {
         F: FnOnce(&mut [u8]) -> R,
     {
         // Only valid data events should be put into the queue
+        let event = self.rx.try_receive().unwrap();
+        let MacEvent::McpsDataInd(data_event) = &event else {
+            unreachable!()
+        };
 
-        let data_event = match self.rx.try_receive().unwrap() {
-            MacEvent::McpsDataInd(data_event) => data_event,
-            _ => unreachable!(),
+        // The MAC only indicates the MSDU, rebuild the header smoltcp expects
+        let header = Header {
+            frame_type: FrameType::Data,
+            frame_pending: false,
+            ack_request: false,
+            version: 1,
+            sequence_number: data_event.dsn,
+            dst_pan_id: Some(from_pan_id(data_event.dst_pan_id)),
+            dst_addr: from_mac_address(data_event.dst_addr_mode, &data_event.dst_address),
+            src_pan_id: Some(from_pan_id(data_event.src_pan_id)),
+            src_addr: from_mac_address(data_event.src_addr_mode, &data_event.src_address),
         };
+        let payload = data_event.payload();
+
+        let mut buf = [0; MTU];
+        let header_len = header.emit(&mut buf);
+        let len = (header_len + payload.len()).min(MTU);
+        buf[header_len..len].copy_from_slice(&payload[..len - header_len]);
+
+        // The event is dropped after the closure, releasing its buffer
+        f(&mut buf[..len])
+    }
+}
+
+fn from_pan_id(pan_id: PanId) -> u16 {
+    u16::from_le_bytes(pan_id.0)
+}
 
-        f(&mut data_event.payload())
+fn from_mac_address(mode: AddressMode, addr: &MacAddress) -> Option<Address> {
+    // Safety: the address mode tells which field of the union is valid
+    match mode {
+        AddressMode::Short => Some(Address::Short(u16::from_le_bytes(unsafe { addr.short }))),
+        AddressMode::Extended => Some(Address::Extended(
+            u64::from_le_bytes(unsafe { addr.extended }).to_be_bytes(),
+        )),
+        _ => None,
     }
 }
 
@@ -1,6 +1,7 @@
 use core::cell::RefCell;
 
 use embassy_futures::join;
+use embassy_net_ieee802154::frame::{Address, Header};
 use embassy_sync::blocking_mutex;
 use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex};
 use embassy_sync::channel::Channel;
@@ -25,10 +26,16 @@ pub struct Runner<'a> {
     pub(crate) rx_channel: Channel<CriticalSectionRawMutex, MacEvent<'a>, 1>,
     pub(crate) tx_channel: Channel<CriticalSectionRawMutex, (&'a mut [u8; MTU], usize), 5>,
     pub(crate) tx_buf_channel: Channel<CriticalSectionRawMutex, &'a mut [u8; MTU], 5>,
+    pub(crate) pan_id: PanId,
+    pub(crate) extended_address: u64,
 }
 
 impl<'a> Runner<'a> {
-    pub fn new(mac: Mac, tx_buf_queue: [&'a mut [u8; MTU]; 5]) -> Self {
+    /// Create a runner for a device in the PAN `pan_id`, with the extended address `extended_address`.
+    ///
+    /// They must be the ones set in the MAC PIB, and are used to build the frames given to and
+    /// expected from `embassy-net`.
+    pub fn new(mac: Mac, tx_buf_queue: [&'a mut [u8; MTU]; 5], pan_id: PanId, extended_address: u64) -> Self {
         let this = Self {
             mac_subsystem: mac,
             rx_event_channel: blocking_mutex::Mutex::new(RefCell::new(None)),
@@ -37,6 +44,8 @@ impl<'a> Runner<'a> {
             rx_channel: Channel::new(),
             tx_channel: Channel::new(),
             tx_buf_channel: Channel::new(),
+            pan_id,
+            extended_address,
         };
 
         for buf in tx_buf_queue {
@@ -76,24 +85,34 @@ impl<'a> Runner<'a> {
                     let (buf, len) = self.tx_channel.receive().await;
                     let _wm = self.write_mutex.lock().await;
 
-                    // The mutex should be dropped on the next loop iteration
-                    self.mac_subsystem
-                        .send_command(
-                            DataRequest {
-                                src_addr_mode: AddressMode::Short,
-                                dst_addr_mode: AddressMode::Short,
-                                dst_pan_id: PanId([0x1A, 0xAA]),
-                                dst_address: MacAddress::BROADCAST,
-                                msdu_handle: msdu_handle,
-                                ack_tx: 0x00,
-                                gts_tx: false,
-                                security_level: SecurityLevel::Unsecure,
-                                ..Default::default()
-                            }
-                            .set_buffer(&buf[..len]),
-                        )
-                        .await
-                        .unwrap();
+                    // smoltcp gives a whole MAC frame, but the MAC builds the header from the request
+                    match Header::parse(&buf[..len]) {
+                        Some((header, header_len)) => {
+                            let (dst_addr_mode, dst_address) = to_mac_address(header.dst_addr);
+                            let (src_addr_mode, _) = to_mac_address(header.src_addr);
+                            let unicast = matches!(header.dst_addr, Some(addr) if !addr.is_broadcast());
+
+                            // The mutex should be dropped on the next loop iteration
+                            self.mac_subsystem
+                                .send_command(
+                                    DataRequest {
+                                        src_addr_mode,
+                                        dst_addr_mode,
+                                        dst_pan_id: self.pan_id,
+                                        dst_address,
+                                        msdu_handle: msdu_handle,
+                                        ack_tx: unicast as u8,
+                                        gts_tx: false,
+                                        security_level: SecurityLevel::Unsecure,
+                                        ..Default::default()
+                                    }
+                                    .set_buffer(&buf[header_len..len]),
+                                )
+                                .await
+                                .unwrap();
+                        }
+                        None => warn!("dropping malformed frame"),
+                    }
 
                     msdu_handle = msdu_handle.wrapping_add(1);
 
@@ -107,3 +126,21 @@ impl<'a> Runner<'a> {
         loop {}
     }
 }
+
+fn to_mac_address(addr: Option<Address>) -> (AddressMode, MacAddress) {
+    match addr {
+        None => (AddressMode::NoAddress, MacAddress::default()),
+        Some(Address::Short(addr)) => (
+            AddressMode::Short,
+            MacAddress {
+                short: addr.to_le_bytes(),
+            },
+        ),
+        Some(Address::Extended(addr)) => (
+            AddressMode::Extended,
+            MacAddress {
+                extended: u64::from_be_bytes(addr).to_le_bytes(),
+            },
+        ),
+    }
+}
@@ -0,0 +1,77 @@
+//! UDP echo server over 6LoWPAN, with the IEEE 802.15.4 radio.
+//!
+//! Another board running this example, or a Linux host with a `lowpan` interface on PAN 0xbeef, channel 15,
+//! can reach it at its IPv6 link-local address, printed at startup.
+
+#![no_std]
+#![no_main]
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::StackResources;
+use embassy_net_ieee802154::{Config, Device, Runner, State};
+use embassy_nrf::radio::ieee802154::Radio;
+use embassy_nrf::rng::Rng;
+use embassy_nrf::{bind_interrupts, peripherals, radio, rng};
+use static_cell::StaticCell;
+use {defmt_rtt as _, panic_probe as _};
+
+bind_interrupts!(struct Irqs {
+    RADIO => radio::InterruptHandler<peripherals::RADIO>;
+    RNG => rng::InterruptHandler<peripherals::RNG>;
+});
+
+#[embassy_executor::task]
+async fn mac_task(mut runner: Runner<'static>, radio: Radio<'static, peripherals::RADIO>) -> ! {
+    runner.run(radio).await
+}
+
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, Device<'static>>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+
+    let mut radio = Radio::new(p.RADIO, Irqs);
+    radio.set_channel(15);
+
+    // Random extended address, locally administered, and random seed.
+    let mut rng = Rng::new(p.RNG, Irqs);
+    let mut extended_address = [0; 8];
+    rng.blocking_fill_bytes(&mut extended_address);
+    extended_address[0] = (extended_address[0] & !0x01) | 0x02;
+    let mut seed = [0; 8];
+    rng.blocking_fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    static STATE: StaticCell<State<4, 4>> = StaticCell::new();
+    let (device, runner) = embassy_net_ieee802154::new(STATE.init(State::new()), Config::new(0xbeef, extended_address));
+    unwrap!(spawner.spawn(mac_task(runner, radio)));
+
+    // The link-local address is derived from the extended address, global ones come from router advertisements.
+    let config = embassy_net::Config::slaac(Default::default());
+    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+    let (stack, runner) = embassy_net::new(device, config, RESOURCES.init(StackResources::new()), seed);
+    unwrap!(spawner.spawn(net_task(runner)));
+
+    stack.wait_config_up().await;
+    info!("IPv6 config: {:?}", Debug2Format(&stack.config_v6()));
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 512];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 512];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    unwrap!(socket.bind(1234));
+
+    let mut buf = [0; 128];
+    loop {
+        let (n, meta) = unwrap!(socket.recv_from(&mut buf).await);
+        info!("received {} bytes from {}", n, meta.endpoint);
+        unwrap!(socket.send_to(&buf[..n], meta.endpoint).await);
+    }
+}
@@ -0,0 +1,91 @@
+//! This example implements a TCP client that attempts to connect to a host on port 1234 and send it some data once per second,
+//! using the W5500's hardware sockets instead of `embassy-net`.
+//!
+//! Example written for the [`WIZnet W5500-EVB-Pico`](https://docs.wiznet.io/Product/iEthernet/W5500/w5500-evb-pico) board.
+
+#![no_std]
+#![no_main]
+
+use core::net::{Ipv4Addr, SocketAddr};
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_net_wiznet::chip::W5500;
+use embassy_net_wiznet::offload;
+use embassy_rp::gpio::{Input, Level, Output, Pull};
+use embassy_rp::peripherals::SPI0;
+use embassy_rp::spi::{Async, Config as SpiConfig, Spi};
+use embassy_time::{Delay, Timer};
+use embedded_hal_bus::spi::ExclusiveDevice;
+use embedded_io_async::Write;
+use embedded_nal_async::TcpConnect;
+use static_cell::StaticCell;
+use {defmt_rtt as _, panic_probe as _};
+
+type SpiDevice = ExclusiveDevice<Spi<'static, SPI0, Async>, Output<'static>, Delay>;
+
+#[embassy_executor::task]
+async fn ethernet_task(runner: offload::Runner<'static, W5500, SpiDevice, Input<'static>, Output<'static>>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let p = embassy_rp::init(Default::default());
+    let mut led = Output::new(p.PIN_25, Level::Low);
+
+    let mut spi_cfg = SpiConfig::default();
+    spi_cfg.frequency = 50_000_000;
+    let (miso, mosi, clk) = (p.PIN_16, p.PIN_19, p.PIN_18);
+    let spi = Spi::new(p.SPI0, clk, mosi, miso, p.DMA_CH0, p.DMA_CH1, spi_cfg);
+    let cs = Output::new(p.PIN_17, Level::High);
+    let w5500_int = Input::new(p.PIN_21, Pull::Up);
+    let w5500_reset = Output::new(p.PIN_20, Level::High);
+
+    let mac_addr = [0x02, 0x00, 0x00, 0x00, 0x00, 0x00];
+    let config = offload::Config::new(Ipv4Addr::new(192, 168, 1, 2), 24, Some(Ipv4Addr::new(192, 168, 1, 1)));
+    static STATE: StaticCell<offload::State<W5500, SpiDevice>> = StaticCell::new();
+    let state = STATE.init(offload::State::new());
+    let (stack, runner) = offload::new(
+        mac_addr,
+        config,
+        state,
+        ExclusiveDevice::new(spi, cs, Delay),
+        w5500_int,
+        w5500_reset,
+    )
+    .await
+    .unwrap();
+    unwrap!(spawner.spawn(ethernet_task(runner)));
+
+    info!("Waiting for link...");
+    while !stack.is_link_up().await {
+        Timer::after_millis(500).await;
+    }
+
+    let host_addr = SocketAddr::new(Ipv4Addr::new(192, 168, 1, 110).into(), 1234);
+    loop {
+        led.set_low();
+        info!("Connecting...");
+        let mut connection = match stack.connect(host_addr).await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("connect error: {:?}", Debug2Format(&e));
+                Timer::after_secs(1).await;
+                continue;
+            }
+        };
+        info!("Connected");
+        led.set_high();
+
+        let msg = b"Hello world!\n";
+        loop {
+            if let Err(e) = connection.write_all(msg).await {
+                warn!("write error: {:?}", Debug2Format(&e));
+                break;
+            }
+            info!("txd: {}", core::str::from_utf8(msg).unwrap());
+            Timer::after_secs(1).await;
+        }
+    }
+}
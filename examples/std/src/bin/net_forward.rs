@@ -0,0 +1,89 @@
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::{forward, Config, InterfaceResources, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+use embassy_net_tuntap::TunTapDevice;
+use heapless::Vec;
+use log::*;
+use rand_core::{OsRng, RngCore};
+use static_cell::StaticCell;
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// TAP device of the uplink
+    #[clap(long, default_value = "tap99")]
+    uplink: String,
+    /// TAP device of the local network
+    #[clap(long, default_value = "tap98")]
+    local: String,
+}
+
+#[embassy_executor::task(pool_size = 2)]
+async fn net_task(mut runner: embassy_net::Runner<'static, TunTapDevice>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network devices
+    let uplink = TunTapDevice::new(&opts.uplink).unwrap();
+    let local = TunTapDevice::new(&opts.local).unwrap();
+
+    // Generate random seed
+    let mut seed = [0; 8];
+    OsRng.fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    // Init network stack, with the uplink as the default interface
+    static RESOURCES: StaticCell<StackResources<4>> = StaticCell::new();
+    let (stack, runner) = embassy_net::new(
+        uplink,
+        Config::ipv4_static(StaticConfigV4 {
+            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+            dns_servers: Vec::new(),
+            gateway: Some(Ipv4Address::new(192, 168, 69, 100)),
+        }),
+        RESOURCES.init(StackResources::new()),
+        seed,
+    );
+    spawner.spawn(net_task(runner)).unwrap();
+
+    static LOCAL_RESOURCES: StaticCell<InterfaceResources<4>> = StaticCell::new();
+    let (_, runner) = stack.add_interface(
+        local,
+        Config::ipv4_static(StaticConfigV4 {
+            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 70, 1), 24),
+            dns_servers: Vec::new(),
+            gateway: None,
+        }),
+        LOCAL_RESOURCES.init(InterfaceResources::new()),
+    )
+    .unwrap();
+    spawner.spawn(net_task(runner)).unwrap();
+
+    // Share the uplink address with the hosts of the local network
+    static FORWARD_RESOURCES: StaticCell<forward::Resources<16>> = StaticCell::new();
+    stack.enable_forwarding(
+        forward::Config::masquerade(embassy_net::InterfaceId::DEFAULT),
+        FORWARD_RESOURCES.init(forward::Resources::new()),
+    );
+
+    info!("forwarding from {} to {}", opts.local, opts.uplink);
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
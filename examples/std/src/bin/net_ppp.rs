@@ -16,7 +16,7 @@ use async_io::Async;
 use clap::Parser;
 use embassy_executor::{Executor, Spawner};
 use embassy_net::tcp::TcpSocket;
-use embassy_net::{Config, ConfigV4, Ipv4Cidr, Stack, StackResources};
+use embassy_net::{Config, ConfigV4, ConfigV6, Ipv4Cidr, Ipv6Cidr, Stack, StackResources};
 use embassy_net_ppp::Runner;
 use embedded_io_async::Write;
 use futures::io::BufReader;
@@ -47,28 +47,41 @@ async fn ppp_task(stack: Stack<'static>, mut runner: Runner<'static>, port: Seri
     let port = BufReader::new(port);
     let port = embedded_io_adapters::futures_03::FromFutures::new(port);
 
-    let config = embassy_net_ppp::Config {
-        username: b"myuser",
-        password: b"mypass",
-    };
+    let mut config = embassy_net_ppp::Config::default();
+    config.username = b"myuser";
+    config.password = b"mypass";
+    config.ipv6 = true;
 
     runner
-        .run(port, config, |ipv4| {
-            let Some(addr) = ipv4.address else {
-                warn!("PPP did not provide an IP address.");
-                return;
-            };
-            let mut dns_servers = Vec::new();
-            for s in ipv4.dns_servers.iter().flatten() {
-                let _ = dns_servers.push(*s);
-            }
-            let config = ConfigV4::Static(embassy_net::StaticConfigV4 {
-                address: Ipv4Cidr::new(addr, 0),
-                gateway: None,
-                dns_servers,
-            });
-            stack.set_config_v4(config);
-        })
+        .run(
+            port,
+            config,
+            &mut OsRng,
+            |ipv4| {
+                let Some(addr) = ipv4.address else {
+                    warn!("PPP did not provide an IP address.");
+                    return;
+                };
+                let mut dns_servers = Vec::new();
+                for s in ipv4.dns_servers.iter().flatten() {
+                    let _ = dns_servers.push(*s);
+                }
+                let config = ConfigV4::Static(embassy_net::StaticConfigV4 {
+                    address: Ipv4Cidr::new(addr, 0),
+                    gateway: None,
+                    dns_servers,
+                });
+                stack.set_config_v4(config);
+            },
+            |ipv6| {
+                let config = ConfigV6::Static(embassy_net::StaticConfigV6 {
+                    address: Ipv6Cidr::new(ipv6.address, 64),
+                    gateway: ipv6.peer_address,
+                    dns_servers: Vec::new(),
+                });
+                stack.set_config_v6(config);
+            },
+        )
         .await
         .unwrap();
     unreachable!()
@@ -0,0 +1,123 @@
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::tls::{ClientConfig, Psk, TlsConnection};
+use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources};
+use embassy_net_tuntap::TunTapDevice;
+use embassy_time::Duration;
+use embedded_io_async::{Read, Write};
+use heapless::Vec;
+use log::*;
+use rand_core::{OsRng, RngCore};
+use static_cell::StaticCell;
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+    /// Pre-shared key identity
+    #[clap(long, default_value = "embassy")]
+    identity: String,
+    /// Pre-shared key, as a string
+    #[clap(long, default_value = "embassy-secret-key")]
+    key: String,
+}
+
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, TunTapDevice>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap).unwrap();
+
+    let config = Config::ipv4_static(embassy_net::StaticConfigV4 {
+        address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+        dns_servers: Vec::new(),
+        gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
+    });
+
+    // Generate random seed
+    let mut seed = [0; 8];
+    OsRng.fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    // Init network stack
+    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+    let (stack, runner) = embassy_net::new(device, config, RESOURCES.init(StackResources::new()), seed);
+
+    // Launch network task
+    spawner.spawn(net_task(runner)).unwrap();
+
+    let mut rx_buffer = [0; 4096];
+    let mut tx_buffer = [0; 4096];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    socket.set_timeout(Some(Duration::from_secs(10)));
+
+    let remote_endpoint = (Ipv4Address::new(192, 168, 69, 100), 4433);
+    info!("connecting to {:?}...", remote_endpoint);
+    if let Err(e) = socket.connect(remote_endpoint).await {
+        warn!("connect error: {:?}", e);
+        return;
+    }
+
+    let mut read_record_buffer = [0; 8192];
+    let mut write_record_buffer = [0; 2048];
+    let mut tls = TlsConnection::new(socket, &mut read_record_buffer, &mut write_record_buffer);
+
+    let mut config = ClientConfig::default();
+    config.psk = Some(Psk {
+        identity: opts.identity.as_bytes(),
+        key: opts.key.as_bytes(),
+    });
+    if let Err(e) = tls.connect(&config, &mut OsRng).await {
+        warn!("handshake error: {:?}", e);
+        return;
+    }
+    info!("connected!");
+
+    if let Err(e) = tls.write_all(b"Hello over TLS!\n").await {
+        warn!("write error: {:?}", e);
+        return;
+    }
+    if let Err(e) = tls.flush().await {
+        warn!("flush error: {:?}", e);
+        return;
+    }
+
+    let mut buf = [0; 1024];
+    loop {
+        match tls.read(&mut buf).await {
+            Ok(0) => {
+                info!("closed");
+                break;
+            }
+            Ok(n) => info!("received {:?}", core::str::from_utf8(&buf[..n])),
+            Err(e) => {
+                warn!("read error: {:?}", e);
+                break;
+            }
+        }
+    }
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
@@ -166,7 +166,12 @@ async fn main(spawner: Spawner) {
     ];
 
     static RUNNER: StaticCell<Runner> = StaticCell::new();
-    let runner = RUNNER.init(Runner::new(mbox.mac_subsystem, tx_queue));
+    let runner = RUNNER.init(Runner::new(
+        mbox.mac_subsystem,
+        tx_queue,
+        PanId([0x1A, 0xAA]),
+        extended_address,
+    ));
 
     spawner.spawn(run_mac(runner)).unwrap();
 